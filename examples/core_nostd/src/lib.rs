@@ -0,0 +1,58 @@
+//! The TPU built without std, as a microcontroller would run it. There's no assembler without std, so the program
+//! is built from `Instruction`s, as one loaded from flash would be.
+//!
+//! Building this crate on its own checks the core of `tls` still builds with only core and alloc:
+//!
+//! ```sh
+//! cargo build -p core_nostd
+//! ```
+//!
+//! In a `cargo build --workspace` the features of `tls` are unified with the other members, so there it's built with
+//! std, but this crate still only uses what `tls` has without it.
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use tls::shared::{HaltReason, Instruction, OperandValueType, Register};
+use tls::tpu::{RunResult, TpuBuilder};
+
+/// Count `A` up to `limit` and halt, returning `A` and why the TPU stopped
+pub fn count_to(limit: u16) -> (u16, RunResult) {
+    let program: Vec<Rc<Instruction>> = [
+        Instruction::LDR(Register::A, OperandValueType::Immediate(0)),
+        Instruction::LDR(Register::X, OperandValueType::Immediate(limit)),
+        Instruction::INC(Register::A),
+        Instruction::CMP(Register::A, OperandValueType::Register(Register::X)),
+        Instruction::BZC(OperandValueType::Immediate(2)),
+        Instruction::HLT,
+    ]
+    .into_iter()
+    .map(Rc::new)
+    .collect();
+
+    let mut tpu = TpuBuilder::new()
+        .program(program)
+        .build()
+        .expect("the program isn't empty");
+    let result = tpu.run(u64::from(limit) * 16 + 64);
+    (tpu.read_register(Register::A), result)
+}
+
+/// Whether `result` is the TPU reaching its `HLT`
+pub fn finished(result: RunResult) -> bool {
+    result == RunResult::Halted(HaltReason::HLTOpcode)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_count_to() {
+        let (count, result) = count_to(10);
+        assert_eq!(count, 10);
+        assert!(finished(result));
+    }
+}
@@ -0,0 +1,291 @@
+/*
+ * A C interface to the TPU. A handle isn't thread-safe: it may be used from any thread but only one at a time, so a
+ * host sharing one between threads must serialize the calls itself.
+ */
+
+#ifndef TLS_H
+#define TLS_H
+
+/* Generated by cbindgen from src/ffi.rs, don't edit by hand */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Most words a packet can carry, as `NetPacket::MAX_PAYLOAD`, spelt out so cbindgen can put it in the header
+ */
+#define TLS_MAX_PAYLOAD 4
+
+/**
+ * Most operands an instruction takes
+ */
+#define TLS_MAX_OPERANDS 4
+
+/**
+ * Why a TPU halted, as `HaltReason`
+ */
+typedef enum TlsHaltReason {
+  TLS_HALT_REASON_DIV0 = 0,
+  TLS_HALT_REASON_HLT_OPCODE = 1,
+  TLS_HALT_REASON_INVALID_PC = 2,
+  TLS_HALT_REASON_INVALID_VALUE = 3,
+  TLS_HALT_REASON_STACK_OVERFLOW = 4,
+  TLS_HALT_REASON_STACK_UNDERFLOW = 5,
+  TLS_HALT_REASON_INDEX_OUT_OF_RANGE = 6,
+  TLS_HALT_REASON_WRITE_PROTECTED = 7,
+  TLS_HALT_REASON_NESTED_LOOP = 8,
+  TLS_HALT_REASON_UNHANDLED_TRAP = 9,
+  TLS_HALT_REASON_END_OF_PROGRAM = 10,
+  TLS_HALT_REASON_NO_SAVED_CONTEXT = 11,
+} TlsHaltReason;
+
+/**
+ * What a call did
+ */
+typedef enum TlsStatus {
+  TLS_STATUS_OK = 0,
+  /**
+   * A pointer that can't be null was
+   */
+  TLS_STATUS_NULL_POINTER = 1,
+  /**
+   * The source isn't UTF-8
+   */
+  TLS_STATUS_INVALID_UTF8 = 2,
+  /**
+   * The source isn't a valid program, or is empty, `tls_last_error` says why
+   */
+  TLS_STATUS_PARSE_ERROR = 3,
+  /**
+   * A register, pin, RAM address or packet length that doesn't exist
+   */
+  TLS_STATUS_OUT_OF_RANGE = 4,
+  /**
+   * The pin is an output, driven by the program
+   */
+  TLS_STATUS_NOT_AN_INPUT = 5,
+  /**
+   * The TPU's packet queue has no room
+   */
+  TLS_STATUS_QUEUE_FULL = 6,
+  /**
+   * There was nothing to read, no packet waiting or no halt reason
+   */
+  TLS_STATUS_EMPTY = 7,
+  /**
+   * Something went wrong inside the VM, the handle should be destroyed
+   */
+  TLS_STATUS_PANIC = 8,
+  /**
+   * The TPU can't be made as asked, e.g. no instructions were given, `tls_last_error` says why
+   */
+  TLS_STATUS_CONFIG_ERROR = 9,
+  /**
+   * An instruction has an unknown opcode or the wrong operands, `tls_last_error` says why
+   */
+  TLS_STATUS_INVALID_INSTRUCTION = 10,
+} TlsStatus;
+
+/**
+ * A TPU owned by the host, only ever seen through a pointer
+ */
+typedef struct TpuHandle TpuHandle;
+
+/**
+ * A network packet, `length` words of `payload` are in use
+ */
+typedef struct TlsPacket {
+  uint16_t sender;
+  uint16_t target;
+  uint8_t port;
+  uint16_t length;
+  uint16_t payload[TLS_MAX_PAYLOAD];
+} TlsPacket;
+
+/**
+ * An operand of a `TlsInstruction`, a register numbered as `tls_tpu_read_register` or an immediate value
+ */
+typedef struct TlsOperand {
+  bool is_register;
+  uint16_t value;
+} TlsOperand;
+
+/**
+ * An instruction by its opcode, see `Instruction::OPCODES`, `operand_count` of `operands` are in use
+ */
+typedef struct TlsInstruction {
+  uint16_t opcode;
+  uint8_t operand_count;
+  TlsOperand operands[TLS_MAX_OPERANDS];
+} TlsInstruction;
+
+/**
+ * Make a TPU running the RGAL program in `source`, at network address `address`. Bit n of `analog_inputs` and
+ * `digital_inputs` makes pin n an input driven by the host, the rest are outputs. The handle is written to `out`
+ * and must be freed with `tls_tpu_destroy`.
+ *
+ * # Safety
+ *
+ * `source` is a NUL-terminated string and `out` is valid for a write of a pointer.
+ */
+TlsStatus tls_tpu_create(const char *source,
+                         uint16_t address,
+                         uint8_t analog_inputs,
+                         uint8_t digital_inputs,
+                         TpuHandle **out);
+
+/**
+ * Make a TPU as `tls_tpu_create` does, running the `length` instructions at `program`
+ *
+ * # Safety
+ *
+ * `program` is valid for reads of `length` instructions and `out` is valid for a write of a pointer.
+ */
+TlsStatus tls_tpu_create_from_opcodes(const TlsInstruction *program,
+                                      size_t length,
+                                      uint16_t address,
+                                      uint8_t analog_inputs,
+                                      uint8_t digital_inputs,
+                                      TpuHandle **out);
+
+/**
+ * Free a TPU, null is ignored
+ *
+ * # Safety
+ *
+ * `handle` is null or from `tls_tpu_create` and not yet destroyed. It can't be used after this.
+ */
+void tls_tpu_destroy(TpuHandle *handle);
+
+/**
+ * Run one clock cycle
+ *
+ * # Safety
+ *
+ * `handle` is null or from `tls_tpu_create` and not yet destroyed.
+ */
+TlsStatus tls_tpu_tick(TpuHandle *handle);
+
+/**
+ * Run up to `ticks` clock cycles, stopping early if the TPU halts
+ *
+ * # Safety
+ *
+ * `handle` is null or from `tls_tpu_create` and not yet destroyed.
+ */
+TlsStatus tls_tpu_run(TpuHandle *handle, uint64_t ticks);
+
+/**
+ * Read a register, numbered A, X, Y then R0 to R6 from 0
+ *
+ * # Safety
+ *
+ * `handle` is null or from `tls_tpu_create` and not yet destroyed, `out` is null or valid for a write.
+ */
+TlsStatus tls_tpu_read_register(TpuHandle *handle, uint8_t register_, uint16_t *out);
+
+/**
+ * Write a register, numbered as `tls_tpu_read_register`
+ *
+ * # Safety
+ *
+ * `handle` is null or from `tls_tpu_create` and not yet destroyed.
+ */
+TlsStatus tls_tpu_write_register(TpuHandle *handle, uint8_t register_, uint16_t value);
+
+/**
+ * Read a word of RAM as it's stored, memory mapped I/O isn't seen
+ *
+ * # Safety
+ *
+ * `handle` is null or from `tls_tpu_create` and not yet destroyed, `out` is null or valid for a write.
+ */
+TlsStatus tls_tpu_read_ram(TpuHandle *handle, uint16_t address, uint16_t *out);
+
+/**
+ * Write a word of RAM, bypassing write protection and memory mapped I/O as `TPU::write_ram_slice` does
+ *
+ * # Safety
+ *
+ * `handle` is null or from `tls_tpu_create` and not yet destroyed.
+ */
+TlsStatus tls_tpu_write_ram(TpuHandle *handle, uint16_t address, uint16_t value);
+
+/**
+ * Drive an analog input, clamped to the pin's resolution
+ *
+ * # Safety
+ *
+ * `handle` is null or from `tls_tpu_create` and not yet destroyed.
+ */
+TlsStatus tls_tpu_drive_analog(TpuHandle *handle, uint8_t pin, uint16_t value);
+
+/**
+ * Drive a digital input high if `value` isn't 0
+ *
+ * # Safety
+ *
+ * `handle` is null or from `tls_tpu_create` and not yet destroyed.
+ */
+TlsStatus tls_tpu_drive_digital(TpuHandle *handle, uint8_t pin, bool value);
+
+/**
+ * Read an analog pin, input or output
+ *
+ * # Safety
+ *
+ * `handle` is null or from `tls_tpu_create` and not yet destroyed, `out` is null or valid for a write.
+ */
+TlsStatus tls_tpu_read_analog(TpuHandle *handle, uint8_t pin, uint16_t *out);
+
+/**
+ * Read every digital pin as a word, bit n is pin n
+ *
+ * # Safety
+ *
+ * `handle` is null or from `tls_tpu_create` and not yet destroyed, `out` is null or valid for a write.
+ */
+TlsStatus tls_tpu_read_digital(TpuHandle *handle, uint16_t *out);
+
+/**
+ * Hand the TPU a packet as if the network had delivered it, `TLS_STATUS_QUEUE_FULL` if it has no room
+ *
+ * # Safety
+ *
+ * `handle` is null or from `tls_tpu_create` and not yet destroyed, `packet` is null or valid for a read.
+ */
+TlsStatus tls_tpu_inject_packet(TpuHandle *handle, const TlsPacket *packet);
+
+/**
+ * Take the oldest packet the TPU has sent, `TLS_STATUS_EMPTY` if there are none
+ *
+ * # Safety
+ *
+ * `handle` is null or from `tls_tpu_create` and not yet destroyed, `out` is null or valid for a write.
+ */
+TlsStatus tls_tpu_drain_packet(TpuHandle *handle, TlsPacket *out);
+
+/**
+ * Why the TPU halted, `TLS_STATUS_EMPTY` if it's still running
+ *
+ * # Safety
+ *
+ * `handle` is null or from `tls_tpu_create` and not yet destroyed, `out` is null or valid for a write.
+ */
+TlsStatus tls_tpu_halt_reason(TpuHandle *handle, TlsHaltReason *out);
+
+/**
+ * Copy the message of the last create that failed on this thread into `buffer`, cut short to fit and always
+ * NUL-terminated. Returns the length of the whole message, so a host can tell it was cut short and ask again with
+ * more room, 0 if there hasn't been one.
+ *
+ * # Safety
+ *
+ * `buffer` is null or valid for `length` bytes of writes.
+ */
+size_t tls_last_error(char *buffer, size_t length);
+
+#endif  /* TLS_H */
@@ -0,0 +1,6 @@
+#!/bin/sh
+# Check the assembler, the TPU and tls-wasm build for the browser, without the terminal debugger. Needs the target:
+#   rustup target add wasm32-unknown-unknown
+set -e
+cd "$(dirname "$0")/.."
+exec cargo check -p tls-wasm --target wasm32-unknown-unknown "$@"
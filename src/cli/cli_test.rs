@@ -0,0 +1,512 @@
+use crate::cli::headless::{EXIT_FAULT, EXIT_OUT_OF_CYCLES, RamDump, Stop, run_headless};
+use crate::cli::repl::{self, CommandError, Reply, dispatch, parse_command, run_repl};
+use crate::cli::{CliError, Command, Options, TpuSpec, parse_args};
+use crate::shared::{AnalogPin, DigitalPin, HaltReason, Instruction, OperandValueType, Register};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use strum::EnumCount;
+
+    const SAMPLE_PROGRAM: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/res/scenarios/button_and_reading.rgal"
+    );
+
+    fn args(args: &[&str]) -> Result<Options, CliError> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_parse_args() {
+        // Nothing runs the demo on the basic TPU
+        let options = args(&[]).unwrap();
+        assert_eq!(options, Options::default());
+        let demo = options.load_program("HLT").unwrap();
+        assert_eq!(demo.len(), 1);
+
+        let options = args(&[
+            "--address",
+            "0x2",
+            "--digital-inputs=1,2",
+            SAMPLE_PROGRAM,
+            "--analog-inputs",
+            "3",
+            "--highlight-ticks=0",
+            "--history",
+            "50",
+            "--trace-buffer=4096",
+            "--sample-ticks",
+        ])
+        .unwrap();
+        assert_eq!(options.program.as_deref(), Some(SAMPLE_PROGRAM.as_ref()));
+        assert_eq!(options.address, 0x2);
+        let mut digital_inputs = [false; DigitalPin::COUNT];
+        digital_inputs[1..=2].fill(true);
+        assert_eq!(options.digital_inputs, digital_inputs);
+        let mut analog_inputs = [false; AnalogPin::COUNT];
+        analog_inputs[3] = true;
+        assert_eq!(options.analog_inputs, analog_inputs);
+        assert_eq!(options.highlight_ticks, Some(0));
+        assert_eq!(options.history, 50);
+        assert_eq!(options.trace_buffer, Some(4096));
+        assert!(options.sample_ticks);
+
+        let program = options.load_program("HLT").unwrap();
+        let tpu = options.create_tpu(program);
+        assert_eq!(tpu.state().network_address, 0x2);
+        assert_eq!(tpu.state().digital_pin_config, digital_inputs);
+        assert!(tpu.read_rom().len() > 1);
+
+        assert!(args(&["--help"]).unwrap().help);
+    }
+
+    #[test]
+    fn test_parse_args_errors() {
+        assert!(
+            matches!(args(&["--speed", "2"]), Err(CliError::UnknownOption(option)) if option == "--speed")
+        );
+        assert!(matches!(
+            args(&["--address"]),
+            Err(CliError::MissingValue(_))
+        ));
+        assert!(matches!(
+            args(&["--address", "0x10000"]),
+            Err(CliError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            args(&["--history", "-1"]),
+            Err(CliError::InvalidValue { .. })
+        ));
+        // There are only 8 digital pins and 4 analog pins
+        assert!(matches!(
+            args(&["--digital-inputs", "0,8"]),
+            Err(CliError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            args(&["--analog-inputs", "a"]),
+            Err(CliError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            args(&["one.rgal", "two.rgal"]),
+            Err(CliError::UnexpectedArgument(argument)) if argument == "two.rgal"
+        ));
+
+        let missing = args(&["no/such/program.rgal"]).unwrap();
+        assert!(matches!(
+            missing.load_program("HLT"),
+            Err(CliError::Program(crate::Error::Io(_)))
+        ));
+    }
+
+    #[test]
+    fn test_program_parse_error() {
+        let path = std::env::temp_dir().join(format!("tls_cli_test_{}.rgal", std::process::id()));
+        std::fs::write(&path, "LDR A, 1\nLDR Q, 2\nHLT\n").unwrap();
+        let options = args(&[path.to_str().unwrap()]).unwrap();
+        let result = options.load_program("HLT");
+        std::fs::remove_file(&path).unwrap();
+
+        let error = result.unwrap_err();
+        assert!(matches!(
+            error,
+            CliError::Program(crate::Error::Parse { line: 2, .. })
+        ));
+        // Rendered with the file and the line it's on
+        let message = error.to_string();
+        assert!(message.contains(&format!("{}:2:", path.display())));
+        assert!(message.contains("LDR Q, 2"));
+    }
+
+    #[test]
+    fn test_parse_run_args() {
+        let options = args(&[
+            "run",
+            "program.rgal",
+            "--max-cycles",
+            "500",
+            "--json",
+            "--dump-ram",
+            "0x00..0x10",
+            "--dump-ram=0x7F",
+        ])
+        .unwrap();
+        assert_eq!(options.command, Command::Run);
+        assert_eq!(options.max_cycles, 500);
+        assert!(options.json);
+        assert_eq!(options.dump_ram, vec![0x00..0x10, 0x7F..0x80]);
+
+        // Only the first argument is the command
+        assert_eq!(args(&["run.rgal"]).unwrap().command, Command::Debug);
+        assert!(matches!(
+            args(&["x.rgal", "run"]),
+            Err(CliError::UnexpectedArgument(_))
+        ));
+
+        assert!(matches!(args(&["run"]), Err(CliError::MissingProgram)));
+        for range in ["0x10..0x10", "0x70..0x81", "ten"] {
+            assert!(matches!(
+                args(&["run", "x.rgal", "--dump-ram", range]),
+                Err(CliError::InvalidValue { .. })
+            ));
+        }
+        assert!(matches!(
+            args(&["run", "x.rgal", "--max-cycles", "-1"]),
+            Err(CliError::InvalidValue { .. })
+        ));
+    }
+
+    #[test]
+    fn test_parse_tpu_args() {
+        let options = args(&[
+            "--tpu",
+            "addr=0x1,program=a.rgal",
+            "--tpu=program=b.rgal,addr=2",
+            "--digital-inputs",
+            "0",
+        ])
+        .unwrap();
+        assert_eq!(
+            options.tpus,
+            vec![
+                TpuSpec {
+                    address: 0x1,
+                    program: "a.rgal".into()
+                },
+                TpuSpec {
+                    address: 0x2,
+                    program: "b.rgal".into()
+                },
+            ]
+        );
+        assert!(options.digital_inputs[0]);
+
+        for spec in [
+            "addr=1",
+            "program=a.rgal",
+            "addr=x,program=a.rgal",
+            "addr=1,program=",
+        ] {
+            assert!(matches!(
+                args(&["--tpu", spec]),
+                Err(CliError::InvalidValue { .. })
+            ));
+        }
+        // The network is only for the debugger, and replaces PROGRAM
+        assert!(matches!(
+            args(&["a.rgal", "--tpu", "addr=1,program=b.rgal"]),
+            Err(CliError::UnexpectedArgument(_))
+        ));
+        assert!(matches!(
+            args(&["repl", "--tpu", "addr=1,program=b.rgal"]),
+            Err(CliError::UnexpectedArgument(_))
+        ));
+
+        let spec = format!("addr=0x7,program={SAMPLE_PROGRAM}");
+        let options = args(&["--tpu", &spec, "--tpu", &spec.replace("0x7", "0x8")]).unwrap();
+        let network = options.create_network().unwrap();
+        assert_eq!(network.len(), 2);
+        assert_eq!(network.tpu(1).state().network_address, 0x8);
+        assert_eq!(
+            options.reload_program(1, "HLT").unwrap(),
+            *network.tpu(1).read_rom()
+        );
+
+        let missing = args(&["--tpu", "addr=1,program=missing.rgal"]).unwrap();
+        assert!(matches!(
+            missing.create_network(),
+            Err(CliError::Program(crate::Error::Io(_)))
+        ));
+
+        // Labels come from the TPU's own program, or the demo without one
+        assert!(missing.load_symbols(0, "HLT").is_empty());
+        assert_eq!(
+            Options::default().load_symbols(0, "NOP\nEND: HLT")["END"],
+            1
+        );
+    }
+
+    #[test]
+    fn test_run_headless() {
+        let tpu = |program: &str| {
+            Options::default()
+                .create_tpu(crate::rgal::parse_program(program).expect("parse failure"))
+        };
+
+        let mut passing = tpu("LDR A, 3\nSTM 0x10, A\nDPW 2, 1\nHLT");
+        let summary = run_headless(&mut passing, 1000, &[0x10..0x12, 0x7F..0x80]);
+        assert_eq!(summary.stop, Stop::Halted);
+        assert_eq!(summary.halt_reason, Some(HaltReason::HLTOpcode));
+        assert_eq!(summary.exit_code(), 0);
+        assert_eq!(summary.program_counter, 3);
+        assert_eq!(summary.cycles, passing.state().cycle_count);
+        assert_eq!(summary.registers["A"], 3);
+        assert_eq!(
+            summary.ram,
+            vec![
+                RamDump {
+                    start: 0x10,
+                    words: vec![3, 0]
+                },
+                RamDump {
+                    start: 0x7F,
+                    words: vec![0]
+                }
+            ]
+        );
+        assert!(summary.digital_pins[2]);
+        #[cfg(feature = "serde")]
+        {
+            let json: serde_json::Value =
+                serde_json::from_str(&summary.to_json().unwrap()).unwrap();
+            assert_eq!(json["stop"], "halted");
+            assert_eq!(json["halt_reason"], "HLTOpcode");
+            assert_eq!(json["registers"]["A"], 3);
+            assert_eq!(json["ram"][0]["words"][0], 3);
+        }
+
+        let mut div0 = tpu("LDR A, 1\nLDR X, 0\nDIV A, X\nHLT");
+        let summary = run_headless(&mut div0, 1000, &[]);
+        assert_eq!(summary.halt_reason, Some(HaltReason::Div0));
+        assert_eq!(summary.program_counter, 2);
+        assert_eq!(summary.exit_code(), EXIT_FAULT);
+        assert!(summary.to_string().starts_with("Halted: Div0\n"));
+
+        let mut endless = tpu("LDR A, 1\nJMP 0");
+        let summary = run_headless(&mut endless, 100, &[]);
+        assert_eq!(summary.stop, Stop::OutOfCycles);
+        assert_eq!(summary.halt_reason, None);
+        assert_eq!(summary.cycles, 100);
+        assert_eq!(summary.exit_code(), EXIT_OUT_OF_CYCLES);
+    }
+
+    #[test]
+    fn test_parse_repl_command() {
+        assert_eq!(args(&["repl"]).unwrap().command, Command::Repl);
+        assert_eq!(parse_command("step"), Ok(repl::Command::Step));
+        assert_eq!(parse_command("  RUN 100 "), Ok(repl::Command::Run(100)));
+        assert_eq!(parse_command("reg a"), Ok(repl::Command::Reg(Register::A)));
+        assert_eq!(
+            parse_command("reg r3 = 0x10"),
+            Ok(repl::Command::SetReg(Register::R3, 0x10))
+        );
+        assert_eq!(
+            parse_command("reg A=5"),
+            Ok(repl::Command::SetReg(Register::A, 5))
+        );
+        assert_eq!(
+            parse_command("ram 0x10"),
+            Ok(repl::Command::Ram(0x10..0x11))
+        );
+        assert_eq!(parse_command("ram 0..4"), Ok(repl::Command::Ram(0..4)));
+        assert_eq!(
+            parse_command("exec LDR A, 42"),
+            Ok(repl::Command::Exec(Instruction::LDR(
+                Register::A,
+                OperandValueType::Immediate(42)
+            )))
+        );
+        assert_eq!(parse_command("break 7"), Ok(repl::Command::Break(7)));
+        assert_eq!(
+            parse_command("until 0x12"),
+            Ok(repl::Command::Until(0x12, repl::UNTIL_TICKS))
+        );
+        assert_eq!(
+            parse_command("until 3  50"),
+            Ok(repl::Command::Until(3, 50))
+        );
+        assert_eq!(parse_command("packets"), Ok(repl::Command::Packets));
+        assert_eq!(parse_command("quit"), Ok(repl::Command::Quit));
+
+        assert!(matches!(
+            parse_command("jump 4"),
+            Err(CommandError::UnknownCommand(command)) if command == "jump"
+        ));
+        assert!(
+            parse_command("jump")
+                .unwrap_err()
+                .to_string()
+                .contains(repl::HELP)
+        );
+        assert_eq!(
+            parse_command("run"),
+            Err(CommandError::Usage("run <TICKS>"))
+        );
+        assert_eq!(
+            parse_command("until"),
+            Err(CommandError::Usage("until <ADDRESS> [<TICKS>]"))
+        );
+        assert!(matches!(
+            parse_command("until 3 x"),
+            Err(CommandError::Usage(_))
+        ));
+        assert!(matches!(
+            parse_command("step 2"),
+            Err(CommandError::Usage(_))
+        ));
+        assert!(matches!(
+            parse_command("reg A = x"),
+            Err(CommandError::Usage(_))
+        ));
+        assert!(matches!(
+            parse_command("ram 0x80"),
+            Err(CommandError::Usage(_))
+        ));
+        assert_eq!(
+            parse_command("reg Q"),
+            Err(CommandError::UnknownRegister("Q".to_string()))
+        );
+        assert!(matches!(
+            parse_command("exec LDR Q, 1"),
+            Err(CommandError::Instruction(_))
+        ));
+    }
+
+    #[test]
+    fn test_repl_dispatch() {
+        let program =
+            crate::rgal::parse_program("LDR A, 1\nSTM 0x10, A\nHLT").expect("parse failure");
+        let mut tpu = Options::default().create_tpu(program);
+        let mut run = |line: &str| dispatch(&mut tpu, parse_command(line).unwrap());
+
+        assert_eq!(
+            run("break 2"),
+            Reply::Breakpoint {
+                address: 2,
+                set: true
+            }
+        );
+        assert_eq!(run("break 3"), Reply::NoInstruction(3));
+        assert!(matches!(
+            run("step"),
+            Reply::Stopped {
+                program_counter: 1,
+                halt_reason: None,
+                ..
+            }
+        ));
+        assert!(matches!(
+            run("run 100"),
+            Reply::Stopped {
+                program_counter: 2,
+                breakpoint: Some(2),
+                ..
+            }
+        ));
+        assert_eq!(
+            run("ram 0x10..0x12"),
+            Reply::Ram {
+                start: 0x10,
+                words: vec![1, 0]
+            }
+        );
+
+        assert_eq!(run("reg X = 7"), Reply::Register(Register::X, 7));
+        assert_eq!(run("exec ADD A, X"), Reply::Executed(Ok(true)));
+        assert_eq!(run("reg A"), Reply::Register(Register::A, 8));
+        assert_eq!(run("exec WRX"), Reply::Executed(Ok(false)));
+        assert!(matches!(
+            run("packets"),
+            Reply::Packets { incoming, outgoing } if incoming.is_empty() && outgoing.is_empty()
+        ));
+
+        assert!(matches!(
+            run("run 100"),
+            Reply::Stopped {
+                halt_reason: Some(HaltReason::HLTOpcode),
+                breakpoint: None,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_repl_until() {
+        let program =
+            crate::rgal::parse_program("LDR A, 3\nDEC A\nBNZ 1, A\nHLT").expect("parse failure");
+        let mut tpu = Options::default().create_tpu(program);
+        let mut run = |line: &str| dispatch(&mut tpu, parse_command(line).unwrap());
+
+        assert!(matches!(
+            run("until 2"),
+            Reply::Stopped {
+                program_counter: 2,
+                breakpoint: None,
+                ..
+            }
+        ));
+        assert_eq!(run("reg A"), Reply::Register(Register::A, 2));
+        // From the address itself it goes round once more, and no breakpoint is left behind
+        run("until 2");
+        assert_eq!(run("reg A"), Reply::Register(Register::A, 1));
+        assert!(matches!(
+            run("break 1"),
+            Reply::Breakpoint { set: true, .. }
+        ));
+        assert!(matches!(
+            run("until 3"),
+            Reply::Stopped {
+                program_counter: 1,
+                breakpoint: Some(1),
+                ..
+            }
+        ));
+        // Giving up before getting there
+        assert!(matches!(
+            run("until 3 1"),
+            Reply::Stopped {
+                program_counter: 1,
+                breakpoint: None,
+                ..
+            }
+        ));
+        assert_eq!(run("until 4"), Reply::NoInstruction(4));
+        assert!(matches!(
+            run("until 0"),
+            Reply::Stopped {
+                halt_reason: Some(HaltReason::HLTOpcode),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_repl_empty_program() {
+        // The REPL starts like this without a program
+        let mut tpu = Options::default().create_tpu(Vec::new());
+        assert!(matches!(
+            dispatch(&mut tpu, repl::Command::Step),
+            Reply::Stopped {
+                program_counter: 0,
+                halt_reason: Some(HaltReason::EndOfProgram),
+                ..
+            }
+        ));
+        assert!(matches!(
+            dispatch(&mut tpu, parse_command("run 100").unwrap()),
+            Reply::Stopped {
+                halt_reason: Some(HaltReason::EndOfProgram),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_run_repl() {
+        let mut tpu = Options::default().create_tpu(vec![]);
+        let mut output = Vec::new();
+        run_repl(
+            &mut tpu,
+            "reg A = 5\n\nbogus\nreg A\nquit\nreg A = 6\n".as_bytes(),
+            &mut output,
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        // Unknown commands print the help and carry on, quit stops reading
+        assert!(output.contains("unknown command bogus"));
+        assert!(output.contains(repl::HELP));
+        assert_eq!(output.matches("A = 0x0005 (5)").count(), 2);
+        assert_eq!(tpu.read_register(Register::A), 5);
+    }
+}
@@ -0,0 +1,126 @@
+use crate::shared::{AnalogPin, DigitalPin, HaltReason, Register};
+use crate::tpu::{RunResult, TPU};
+#[cfg(feature = "serde")]
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Range;
+use strum::{EnumCount, IntoEnumIterator};
+
+/// Exit code when the TPU halted on an error
+pub const EXIT_FAULT: i32 = 3;
+/// Exit code when the cycle budget ran out before the TPU halted
+pub const EXIT_OUT_OF_CYCLES: i32 = 4;
+
+/// Why a headless run stopped
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Stop {
+    Halted,
+    OutOfCycles,
+}
+
+/// Words of RAM from `start`, see `--dump-ram`
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct RamDump {
+    pub start: usize,
+    pub words: Vec<u16>,
+}
+
+/// How a headless run ended, printed as text or JSON
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct RunSummary {
+    pub stop: Stop,
+    /// `None` when the budget ran out first
+    pub halt_reason: Option<HaltReason>,
+    pub program_counter: usize,
+    pub cycles: u64,
+    pub registers: BTreeMap<String, u16>,
+    pub ram: Vec<RamDump>,
+    pub digital_pins: [bool; DigitalPin::COUNT],
+    pub analog_pins: [u16; AnalogPin::COUNT],
+}
+
+/// Run the TPU until it halts or has run `max_cycles` ticks, then sum up where it ended, with the words of RAM in
+/// each of `dump_ram`
+pub fn run_headless(tpu: &mut TPU, max_cycles: u64, dump_ram: &[Range<usize>]) -> RunSummary {
+    // The TPU has no breakpoints here, so it only stops for one of the other two
+    let stop = match tpu.run(max_cycles) {
+        RunResult::OutOfTicks if !tpu.halted() => Stop::OutOfCycles,
+        _ => Stop::Halted,
+    };
+    let state = tpu.state();
+
+    RunSummary {
+        stop,
+        halt_reason: state.halt_reason,
+        program_counter: state.program_counter,
+        cycles: state.cycle_count,
+        registers: Register::iter()
+            .map(|register| (register.to_string(), tpu.read_register(register)))
+            .collect(),
+        ram: dump_ram
+            .iter()
+            .map(|addresses| RamDump {
+                start: addresses.start,
+                words: addresses
+                    .clone()
+                    .map(|address| tpu.read_ram(address))
+                    .collect(),
+            })
+            .collect(),
+        digital_pins: state.digital_pins,
+        analog_pins: state.analog_pins,
+    }
+}
+
+impl RunSummary {
+    /// 0 when the program finished, by HLT or by running off the end, `EXIT_FAULT` when it halted on an error and
+    /// `EXIT_OUT_OF_CYCLES` when it didn't finish in time
+    pub fn exit_code(&self) -> i32 {
+        match (self.stop, self.halt_reason) {
+            (Stop::OutOfCycles, _) => EXIT_OUT_OF_CYCLES,
+            (Stop::Halted, Some(HaltReason::HLTOpcode | HaltReason::EndOfProgram)) => 0,
+            (Stop::Halted, _) => EXIT_FAULT,
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+impl fmt::Display for RunSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.stop, self.halt_reason) {
+            (Stop::Halted, Some(reason)) => writeln!(f, "Halted: {reason:?}")?,
+            _ => writeln!(f, "Out of cycles")?,
+        }
+        writeln!(f, "PC: {:04X}", self.program_counter)?;
+        writeln!(f, "Cycles: {}", self.cycles)?;
+        // In the TPU's order, not the map's
+        for register in Register::iter().map(|register| register.to_string()) {
+            writeln!(f, "{register}: {:04X}", self.registers[&register])?;
+        }
+        for dump in &self.ram {
+            for (row, words) in dump.words.chunks(8).enumerate() {
+                write!(f, "{:04X}:", dump.start + row * 8)?;
+                for word in words {
+                    write!(f, " {word:04X}")?;
+                }
+                writeln!(f)?;
+            }
+        }
+        let digital: String = self
+            .digital_pins
+            .iter()
+            .map(|&level| if level { '1' } else { '0' })
+            .collect();
+        writeln!(f, "Digital pins: {digital}")?;
+        write!(f, "Analog pins: {:?}", self.analog_pins)
+    }
+}
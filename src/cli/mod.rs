@@ -0,0 +1,363 @@
+#[cfg(test)]
+mod cli_test;
+pub mod headless;
+pub mod repl;
+
+use crate::network::Network;
+use crate::rgal::{
+    SymbolTable, parse_program, parse_program_from_file, parse_program_from_file_with_symbols,
+    parse_program_with_symbols,
+};
+use crate::shared::{AnalogPin, DigitalPin, Instruction};
+use crate::tpu::TPU;
+use std::fmt;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::rc::Rc;
+use strum::EnumCount;
+
+pub const USAGE: &str = "\
+Usage: tls [OPTIONS] [PROGRAM]
+       tls run [OPTIONS] PROGRAM
+       tls repl [OPTIONS] [PROGRAM]
+
+Runs PROGRAM, an RGAL file, in the debugger, or a built in demo if there isn't one.
+`run` runs it without the debugger until it halts, then prints where it ended. It exits with 0 if the program finished,
+3 if it halted on an error and 4 if it ran out of cycles.
+`repl` gives a prompt to step, run and poke the TPU at, with an empty program if there isn't one.
+
+Options:
+  --address <ADDRESS>          Network address of the TPU [default: 0x1]
+  --digital-inputs <PINS>      Digital pins to configure as inputs, e.g. 0,3,7, the rest are outputs
+  --analog-inputs <PINS>       Analog pins to configure as inputs, e.g. 1,2, the rest are outputs
+  --tpu addr=<ADDRESS>,program=<PROGRAM>
+                               Debug a TPU on a network with the others given, instead of PROGRAM, can be given more
+                               than once. --digital-inputs and --analog-inputs apply to all of them
+  --highlight-ticks <TICKS>    Ticks a changed value stays highlighted for, 0 turns it off [default: 8]
+  --history <STEPS>            Instructions the debugger can step back through, 0 turns it off [default: 1000]
+  --sample-ticks               Sample the analog pins for their sparklines every tick rather than every frame
+  --trace-buffer <BYTES>       Bytes of a trace the debugger holds before writing them to the file [default: 65536]
+  --max-cycles <CYCLES>        With run, ticks to give up after [default: 1000000]
+  --json                       With run, print the summary as JSON
+  --dump-ram <ADDRESSES>       With run, add the words at an address or range like 0x00..0x10 to the summary, can be
+                               given more than once
+  -h, --help                   Print this help";
+
+/// One of the TPUs on a network, see `--tpu`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TpuSpec {
+    pub address: u16,
+    pub program: PathBuf,
+}
+
+/// Whether to debug the program or just run it
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Command {
+    #[default]
+    Debug,
+    /// Run without the debugger, see `headless::run_headless`
+    Run,
+    /// Poke the TPU at a prompt, see `repl::run_repl`
+    Repl,
+}
+
+/// What the debugger was asked to run, see `parse_args`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Options {
+    pub command: Command,
+    /// The program to load, `None` runs the demo
+    pub program: Option<PathBuf>,
+    /// TPUs to debug on a network instead of `program`
+    pub tpus: Vec<TpuSpec>,
+    pub address: u16,
+    pub analog_inputs: [bool; AnalogPin::COUNT],
+    pub digital_inputs: [bool; DigitalPin::COUNT],
+    /// `None` leaves the debugger's default
+    pub highlight_ticks: Option<u16>,
+    /// Instructions the debugger keeps to step back through
+    pub history: usize,
+    /// The debugger samples the analog pins every tick
+    pub sample_ticks: bool,
+    /// `None` leaves the debugger's default
+    pub trace_buffer: Option<usize>,
+    /// Ticks `run` gives the program to halt in
+    pub max_cycles: u64,
+    /// `run` prints JSON instead of text
+    pub json: bool,
+    /// RAM `run` includes in its summary
+    pub dump_ram: Vec<Range<usize>>,
+    pub help: bool,
+}
+
+impl Default for Options {
+    /// The same TPU as `create_basic_tpu_config`
+    fn default() -> Self {
+        Self {
+            command: Command::Debug,
+            program: None,
+            tpus: Vec::new(),
+            address: 0x1,
+            analog_inputs: [false; AnalogPin::COUNT],
+            digital_inputs: [false; DigitalPin::COUNT],
+            highlight_ticks: None,
+            history: 1000,
+            sample_ticks: false,
+            trace_buffer: None,
+            max_cycles: 1_000_000,
+            json: false,
+            dump_ram: Vec::new(),
+            help: false,
+        }
+    }
+}
+
+/// Why the debugger couldn't start
+#[derive(Debug)]
+pub enum CliError {
+    UnknownOption(String),
+    MissingValue(String),
+    InvalidValue {
+        option: String,
+        value: String,
+    },
+    /// More than one program was given
+    UnexpectedArgument(String),
+    /// `run` needs a program, there's no demo to fall back on
+    MissingProgram,
+    Program(crate::Error),
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::UnknownOption(option) => write!(f, "unknown option {option}\n\n{USAGE}"),
+            CliError::MissingValue(option) => write!(f, "{option} needs a value\n\n{USAGE}"),
+            CliError::InvalidValue { option, value } => {
+                write!(f, "invalid value {value:?} for {option}")
+            }
+            CliError::UnexpectedArgument(argument) => {
+                write!(
+                    f,
+                    "unexpected argument {argument}, only one program can be run\n\n{USAGE}"
+                )
+            }
+            CliError::MissingProgram => write!(f, "run needs a program\n\n{USAGE}"),
+            CliError::Program(crate::Error::Io(error)) => {
+                write!(f, "couldn't read the program: {error}")
+            }
+            CliError::Program(error) => write!(f, "couldn't load the program:\n{error}"),
+        }
+    }
+}
+
+impl std::error::Error for CliError {}
+
+/// Parse the command line, without the name of the binary
+pub fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Options, CliError> {
+    let mut options = Options::default();
+    let mut args = args.into_iter().peekable();
+    if args.next_if(|arg| arg == "run").is_some() {
+        options.command = Command::Run;
+    } else if args.next_if(|arg| arg == "repl").is_some() {
+        options.command = Command::Repl;
+    }
+
+    while let Some(arg) = args.next() {
+        // Both `--option value` and `--option=value` are accepted
+        let (option, inline_value) = match arg.split_once('=') {
+            Some((option, value)) if arg.starts_with("--") => {
+                (option.to_string(), Some(value.to_string()))
+            }
+            _ => (arg.clone(), None),
+        };
+        let mut value = || {
+            inline_value
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| CliError::MissingValue(option.clone()))
+        };
+
+        match option.as_str() {
+            "-h" | "--help" => options.help = true,
+            "--address" => {
+                let value = value()?;
+                options.address = parse_number(&value).ok_or(CliError::InvalidValue {
+                    option: option.clone(),
+                    value,
+                })?;
+            }
+            "--highlight-ticks" => {
+                let value = value()?;
+                options.highlight_ticks =
+                    Some(parse_number(&value).ok_or(CliError::InvalidValue {
+                        option: option.clone(),
+                        value,
+                    })?);
+            }
+            "--history" => {
+                let value = value()?;
+                options.history = value.parse().map_err(|_| CliError::InvalidValue {
+                    option: option.clone(),
+                    value,
+                })?;
+            }
+            "--trace-buffer" => {
+                let value = value()?;
+                options.trace_buffer = Some(value.parse().map_err(|_| CliError::InvalidValue {
+                    option: option.clone(),
+                    value,
+                })?);
+            }
+            "--max-cycles" => {
+                let value = value()?;
+                options.max_cycles = value.parse().map_err(|_| CliError::InvalidValue {
+                    option: option.clone(),
+                    value,
+                })?;
+            }
+            "--sample-ticks" => options.sample_ticks = true,
+            "--json" => options.json = true,
+            "--dump-ram" => {
+                let value = value()?;
+                let addresses = parse_addresses(&value).ok_or(CliError::InvalidValue {
+                    option: option.clone(),
+                    value,
+                })?;
+                options.dump_ram.push(addresses);
+            }
+            "--tpu" => {
+                let value = value()?;
+                let spec = parse_tpu_spec(&value).ok_or(CliError::InvalidValue {
+                    option: option.clone(),
+                    value,
+                })?;
+                options.tpus.push(spec);
+            }
+            "--digital-inputs" => options.digital_inputs = parse_pins(&option, &value()?)?,
+            "--analog-inputs" => options.analog_inputs = parse_pins(&option, &value()?)?,
+            _ if option.starts_with('-') && option != "-" => {
+                return Err(CliError::UnknownOption(option));
+            }
+            _ if options.program.is_some() => return Err(CliError::UnexpectedArgument(arg)),
+            _ => options.program = Some(PathBuf::from(arg)),
+        }
+    }
+
+    // A network of TPUs is only for the debugger
+    if let (Some(program), false) = (&options.program, options.tpus.is_empty()) {
+        return Err(CliError::UnexpectedArgument(program.display().to_string()));
+    }
+    if options.command != Command::Debug && !options.tpus.is_empty() {
+        return Err(CliError::UnexpectedArgument("--tpu".to_string()));
+    }
+    if options.command == Command::Run && options.program.is_none() && !options.help {
+        return Err(CliError::MissingProgram);
+    }
+    Ok(options)
+}
+
+impl Options {
+    /// Load the program file, or parse `demo` if no file was given
+    pub fn load_program(&self, demo: &str) -> Result<Vec<Rc<Instruction>>, CliError> {
+        match &self.program {
+            Some(path) => parse_program_from_file(path).map_err(CliError::Program),
+            None => parse_program(demo).map_err(CliError::Program),
+        }
+    }
+
+    /// A TPU with the address and pins asked for
+    pub fn create_tpu(&self, program: Vec<Rc<Instruction>>) -> TPU {
+        self.create_tpu_at(self.address, program)
+    }
+
+    fn create_tpu_at(&self, address: u16, program: Vec<Rc<Instruction>>) -> TPU {
+        TPU::new(address, self.analog_inputs, self.digital_inputs, program)
+    }
+
+    /// The `--tpu`s on a network, in the order they were given
+    pub fn create_network(&self) -> Result<Network, CliError> {
+        let mut network = Network::new();
+        for spec in &self.tpus {
+            let program = parse_program_from_file(&spec.program).map_err(CliError::Program)?;
+            network.add_tpu(self.create_tpu_at(spec.address, program));
+        }
+        Ok(network)
+    }
+
+    /// Load the program of the TPU at `index` again, from `--tpu` or PROGRAM
+    pub fn reload_program(
+        &self,
+        index: usize,
+        demo: &str,
+    ) -> Result<Vec<Rc<Instruction>>, CliError> {
+        match self.tpus.get(index) {
+            Some(spec) => parse_program_from_file(&spec.program).map_err(CliError::Program),
+            None => self.load_program(demo),
+        }
+    }
+
+    /// The labels in the program of the TPU at `index`, none if it can't be loaded, which loading it reports
+    pub fn load_symbols(&self, index: usize, demo: &str) -> SymbolTable {
+        let loaded = match (self.tpus.get(index), &self.program) {
+            (Some(spec), _) => parse_program_from_file_with_symbols(&spec.program).ok(),
+            (None, Some(path)) => parse_program_from_file_with_symbols(path).ok(),
+            (None, None) => parse_program_with_symbols(demo).ok(),
+        };
+        loaded.map(|(_, symbols)| symbols).unwrap_or_default()
+    }
+}
+
+/// A decimal, `0x` hex or `0b` binary word, as in RGAL
+pub fn parse_number(value: &str) -> Option<u16> {
+    if let Some(hex) = value.strip_prefix("0x") {
+        u16::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = value.strip_prefix("0b") {
+        u16::from_str_radix(binary, 2).ok()
+    } else {
+        value.parse().ok()
+    }
+}
+
+/// `addr=<ADDRESS>,program=<PROGRAM>`, in either order
+fn parse_tpu_spec(value: &str) -> Option<TpuSpec> {
+    let mut address = None;
+    let mut program = None;
+    for field in value.split(',') {
+        match field.split_once('=')? {
+            ("addr", value) => address = Some(parse_number(value)?),
+            ("program", value) if !value.is_empty() => program = Some(PathBuf::from(value)),
+            _ => return None,
+        }
+    }
+    Some(TpuSpec {
+        address: address?,
+        program: program?,
+    })
+}
+
+/// An address or a `..` range of them in RAM, like a Rust range the end isn't included
+pub fn parse_addresses(value: &str) -> Option<Range<usize>> {
+    let addresses = match value.split_once("..") {
+        Some((start, end)) => parse_number(start)?.into()..parse_number(end)?.into(),
+        None => {
+            let address = parse_number(value)?.into();
+            address..address + 1
+        }
+    };
+    (!addresses.is_empty() && addresses.end <= TPU::RAM_SIZE).then_some(addresses)
+}
+
+/// A comma separated list of pin numbers below `N`
+fn parse_pins<const N: usize>(option: &str, value: &str) -> Result<[bool; N], CliError> {
+    let invalid = || CliError::InvalidValue {
+        option: option.into(),
+        value: value.into(),
+    };
+
+    let mut pins = [false; N];
+    for pin in value.split(',').filter(|pin| !pin.trim().is_empty()) {
+        let pin = parse_number(pin.trim()).ok_or_else(invalid)? as usize;
+        *pins.get_mut(pin).ok_or_else(invalid)? = true;
+    }
+    Ok(pins)
+}
@@ -0,0 +1,314 @@
+use crate::cli::{parse_addresses, parse_number};
+use crate::rgal::parse_instruction;
+use crate::shared::{HaltReason, Instruction, NetPacket, Register};
+use crate::tpu::{RunResult, TPU};
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::ops::Range;
+use std::str::FromStr;
+
+/// Ticks `until` gives up after if it isn't told
+pub const UNTIL_TICKS: u64 = 1_000_000;
+
+pub const HELP: &str = "\
+Commands:
+  step                 Run until the next instruction is done
+  run <TICKS>          Tick up to TICKS times, stopping at a halt or a breakpoint
+  until <ADDRESS> [<TICKS>]
+                       Run until the PC reaches ADDRESS, like run, giving up after TICKS [default: 1000000]
+  reg <REGISTER>       Print a register
+  reg <REGISTER> = <V> Set a register
+  ram <ADDRESSES>      Print the words at an address or range like 0x10..0x18
+  exec <INSTRUCTION>   Execute an instruction now, outside the program
+  break <ADDRESS>      Set or clear a breakpoint
+  packets              List the packets waiting to be received and sent
+  help                 Print this help
+  quit                 Leave";
+
+/// A line typed at the REPL, see `parse_command`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Command {
+    Step,
+    Run(u64),
+    /// Run to an address, giving up after the ticks given, see `TPU::run_until_pc`
+    Until(usize, u64),
+    Reg(Register),
+    SetReg(Register, u16),
+    Ram(Range<usize>),
+    Exec(Instruction),
+    Break(usize),
+    Packets,
+    Help,
+    Quit,
+}
+
+/// Why a line couldn't be understood
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum CommandError {
+    UnknownCommand(String),
+    /// The command was right but what came after it wasn't, with how it's used
+    Usage(&'static str),
+    UnknownRegister(String),
+    /// Not RGAL, with the parser's error
+    Instruction(String),
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::UnknownCommand(command) => {
+                write!(f, "unknown command {command}\n\n{HELP}")
+            }
+            CommandError::Usage(usage) => write!(f, "usage: {usage}"),
+            CommandError::UnknownRegister(register) => write!(f, "no register called {register}"),
+            CommandError::Instruction(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+/// What a command did, for `run_repl` to print
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Reply {
+    /// Where the TPU stopped after `step` or `run`
+    Stopped {
+        program_counter: usize,
+        cycles: u64,
+        halt_reason: Option<HaltReason>,
+        /// `run` stopped at a breakpoint
+        breakpoint: Option<usize>,
+    },
+    Register(Register, u16),
+    Ram {
+        start: usize,
+        words: Vec<u16>,
+    },
+    /// `exec`'s result, see `TPU::execute_now`
+    Executed(Result<bool, HaltReason>),
+    Breakpoint {
+        address: usize,
+        set: bool,
+    },
+    /// A breakpoint or `until` past the end of the program would never be hit
+    NoInstruction(usize),
+    Packets {
+        incoming: Vec<NetPacket>,
+        outgoing: Vec<NetPacket>,
+    },
+    Help,
+    Quit,
+}
+
+impl fmt::Display for Reply {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reply::Stopped {
+                program_counter,
+                cycles,
+                halt_reason,
+                breakpoint,
+            } => {
+                write!(f, "PC {program_counter:04X}, cycle {cycles}")?;
+                if let Some(reason) = halt_reason {
+                    write!(f, ", halted: {reason:?}")?;
+                }
+                if let Some(address) = breakpoint {
+                    write!(f, ", breakpoint hit at {address:#06X}")?;
+                }
+                Ok(())
+            }
+            Reply::Register(register, value) => write!(f, "{register} = {value:#06X} ({value})"),
+            Reply::Ram { start, words } => {
+                for (row, words) in words.chunks(8).enumerate() {
+                    if row > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{:04X}:", start + row * 8)?;
+                    for word in words {
+                        write!(f, " {word:04X}")?;
+                    }
+                }
+                Ok(())
+            }
+            Reply::Executed(Ok(true)) => write!(f, "ok"),
+            Reply::Executed(Ok(false)) => write!(f, "it would have waited, nothing was done"),
+            Reply::Executed(Err(reason)) => write!(f, "halted: {reason:?}"),
+            Reply::Breakpoint { address, set } => write!(
+                f,
+                "breakpoint {} at {address:#06X}",
+                if *set { "set" } else { "cleared" }
+            ),
+            Reply::NoInstruction(address) => write!(f, "no instruction at {address:#06X}"),
+            Reply::Packets { incoming, outgoing } => {
+                for (heading, packets) in [("incoming", incoming), ("outgoing", outgoing)] {
+                    write!(f, "{heading}: {}", packets.len())?;
+                    for packet in packets {
+                        write!(
+                            f,
+                            "\n  {:04X}->{:04X} :{} {:04X?}",
+                            packet.sender,
+                            packet.target,
+                            packet.port,
+                            packet.words()
+                        )?;
+                    }
+                    if heading == "incoming" {
+                        writeln!(f)?;
+                    }
+                }
+                Ok(())
+            }
+            Reply::Help => write!(f, "{HELP}"),
+            Reply::Quit => Ok(()),
+        }
+    }
+}
+
+/// A line typed at the REPL. Commands aren't case sensitive, nor are register names.
+pub fn parse_command(line: &str) -> Result<Command, CommandError> {
+    let line = line.trim();
+    let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let rest = rest.trim();
+
+    match command.to_lowercase().as_str() {
+        "step" | "s" => no_arguments(rest, Command::Step, "step"),
+        "run" | "r" => rest
+            .parse()
+            .map(Command::Run)
+            .map_err(|_| CommandError::Usage("run <TICKS>")),
+        "until" | "u" => {
+            let usage = CommandError::Usage("until <ADDRESS> [<TICKS>]");
+            let (address, ticks) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            let address = parse_number(address).ok_or(usage.clone())?;
+            let ticks = match ticks.trim() {
+                "" => UNTIL_TICKS,
+                ticks => ticks.parse().map_err(|_| usage)?,
+            };
+            Ok(Command::Until(address.into(), ticks))
+        }
+        "reg" => {
+            let usage = CommandError::Usage("reg <REGISTER> [= <VALUE>]");
+            let (register, value) = match rest.split_once('=') {
+                Some((register, value)) => (register.trim(), Some(value.trim())),
+                None => (rest, None),
+            };
+            if register.is_empty() {
+                return Err(usage);
+            }
+            let register = Register::from_str(&register.to_uppercase())
+                .map_err(|_| CommandError::UnknownRegister(register.to_string()))?;
+            match value {
+                None => Ok(Command::Reg(register)),
+                Some(value) => parse_number(value)
+                    .map(|value| Command::SetReg(register, value))
+                    .ok_or(usage),
+            }
+        }
+        "ram" => parse_addresses(rest)
+            .map(Command::Ram)
+            .ok_or(CommandError::Usage("ram <ADDRESS>|<START>..<END>")),
+        "exec" | "x" => parse_instruction(rest)
+            .map(Command::Exec)
+            .map_err(|error| CommandError::Instruction(error.to_string())),
+        "break" | "b" => parse_number(rest)
+            .map(|address| Command::Break(address.into()))
+            .ok_or(CommandError::Usage("break <ADDRESS>")),
+        "packets" => no_arguments(rest, Command::Packets, "packets"),
+        "help" | "?" => Ok(Command::Help),
+        "quit" | "q" | "exit" => Ok(Command::Quit),
+        _ => Err(CommandError::UnknownCommand(command.to_string())),
+    }
+}
+
+fn no_arguments(
+    rest: &str,
+    command: Command,
+    usage: &'static str,
+) -> Result<Command, CommandError> {
+    if rest.is_empty() {
+        Ok(command)
+    } else {
+        Err(CommandError::Usage(usage))
+    }
+}
+
+/// Carry out a command on the TPU
+pub fn dispatch(tpu: &mut TPU, command: Command) -> Reply {
+    let stopped = |tpu: &TPU, breakpoint| Reply::Stopped {
+        program_counter: tpu.state().program_counter,
+        cycles: tpu.state().cycle_count,
+        halt_reason: tpu.state().halt_reason,
+        breakpoint,
+    };
+
+    match command {
+        Command::Step => {
+            tpu.step();
+            stopped(tpu, None)
+        }
+        Command::Run(ticks) => {
+            let breakpoint = match tpu.run(ticks) {
+                RunResult::Breakpoint(address) => Some(address),
+                RunResult::Halted(_) | RunResult::OutOfTicks => None,
+            };
+            stopped(tpu, breakpoint)
+        }
+        Command::Until(address, _) if address >= tpu.read_rom().len() => {
+            Reply::NoInstruction(address)
+        }
+        Command::Until(address, ticks) => {
+            // Reaching the address is reported like a breakpoint, only one on the way is worth saying
+            let breakpoint = match tpu.run_until_pc(address, ticks) {
+                RunResult::Breakpoint(stop) if stop != address => Some(stop),
+                _ => None,
+            };
+            stopped(tpu, breakpoint)
+        }
+        Command::Reg(register) => Reply::Register(register, tpu.read_register(register)),
+        Command::SetReg(register, value) => {
+            tpu.write_register(register, value);
+            Reply::Register(register, value)
+        }
+        Command::Ram(addresses) => Reply::Ram {
+            start: addresses.start,
+            words: addresses.map(|address| tpu.read_ram(address)).collect(),
+        },
+        Command::Exec(instruction) => Reply::Executed(tpu.execute_now(&instruction)),
+        Command::Break(address) if address >= tpu.read_rom().len() => Reply::NoInstruction(address),
+        Command::Break(address) => Reply::Breakpoint {
+            address,
+            set: tpu.toggle_breakpoint(address),
+        },
+        Command::Packets => Reply::Packets {
+            incoming: tpu.state().incoming_packets.iter().copied().collect(),
+            outgoing: tpu.state().outgoing_packets.iter().copied().collect(),
+        },
+        Command::Help => Reply::Help,
+        Command::Quit => Reply::Quit,
+    }
+}
+
+/// Read commands from `input` until `quit` or the end of it, printing what each did to `output`
+pub fn run_repl(tpu: &mut TPU, input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+    write!(output, "> ")?;
+    output.flush()?;
+    for line in input.lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            match parse_command(&line) {
+                Ok(command) => {
+                    let reply = dispatch(tpu, command);
+                    if reply == Reply::Quit {
+                        return Ok(());
+                    }
+                    writeln!(output, "{reply}")?;
+                }
+                Err(error) => writeln!(output, "{error}")?,
+            }
+        }
+        write!(output, "> ")?;
+        output.flush()?;
+    }
+    writeln!(output)
+}
@@ -0,0 +1,75 @@
+#[cfg(feature = "serde")]
+use crate::tpu::SnapshotError;
+use pest::RuleType;
+use pest::error::LineColLocation;
+use std::fmt;
+use std::io;
+
+/// Why a program, scenario or snapshot couldn't be loaded
+#[derive(Debug)]
+pub enum Error {
+    /// Text that isn't valid RGAL, or a scenario that isn't valid. `line` and `col` count from 1, `message` is the
+    /// whole error as it's shown, pointing at the mistake in the line it's on.
+    Parse {
+        line: usize,
+        col: usize,
+        message: String,
+    },
+    /// A program that parses but can't be run
+    InvalidProgram(String),
+    Io(io::Error),
+    #[cfg(feature = "serde")]
+    Snapshot(SnapshotError),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Parse { message, .. } => write!(f, "{message}"),
+            Error::InvalidProgram(message) => write!(f, "invalid program: {message}"),
+            Error::Io(error) => write!(f, "{error}"),
+            #[cfg(feature = "serde")]
+            Error::Snapshot(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(error) => Some(error),
+            #[cfg(feature = "serde")]
+            Error::Snapshot(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// Keeps where pest's error is and how it renders, so the grammar's rules needn't be public
+impl<R: RuleType> From<pest::error::Error<R>> for Error {
+    fn from(error: pest::error::Error<R>) -> Self {
+        let (line, col) = match error.line_col {
+            LineColLocation::Pos(position) | LineColLocation::Span(position, _) => position,
+        };
+        Error::Parse {
+            line,
+            col,
+            message: error.to_string(),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<SnapshotError> for Error {
+    fn from(error: SnapshotError) -> Self {
+        Error::Snapshot(error)
+    }
+}
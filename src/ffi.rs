@@ -0,0 +1,604 @@
+//! A C interface for hosts that aren't written in Rust, the declarations are in `include/tls.h`.
+//!
+//! A TPU is made with `tls_tpu_create` from RGAL source, or `tls_tpu_create_from_opcodes` from instructions already
+//! assembled, and freed with `tls_tpu_destroy`. Everything else takes the handle it gave.
+//! Each function returns a `TlsStatus`, with anything it reads written through an out pointer, and a panic inside is
+//! caught and returned as `TLS_STATUS_PANIC` rather than unwinding into the host.
+//!
+//! A handle isn't thread-safe. It may be used from any thread, but only one at a time, so a host sharing one between
+//! threads must serialize the calls itself. The message behind a failed create is kept per thread, see
+//! `tls_last_error`.
+
+use crate::rgal::parse_program;
+use crate::shared::{
+    AnalogPin, DigitalPin, HaltReason, Instruction, NetPacket, OperandValueType, Register,
+};
+use crate::tpu::{PinMode, TPU, TpuBuilder};
+use std::cell::RefCell;
+use std::ffi::{CStr, c_char};
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::ptr;
+use std::rc::Rc;
+use strum::EnumCount;
+
+/// Most words a packet can carry, as `NetPacket::MAX_PAYLOAD`, spelt out so cbindgen can put it in the header
+pub const TLS_MAX_PAYLOAD: usize = 4;
+const _: () = assert!(TLS_MAX_PAYLOAD == NetPacket::MAX_PAYLOAD);
+
+/// Most operands an instruction takes
+pub const TLS_MAX_OPERANDS: usize = 4;
+const _: () = {
+    let mut index = 0;
+    while index < Instruction::OPCODES.len() {
+        assert!(Instruction::OPCODES[index].operands.len() <= TLS_MAX_OPERANDS);
+        index += 1;
+    }
+};
+
+/// A TPU owned by the host, only ever seen through a pointer
+pub struct TpuHandle {
+    tpu: TPU,
+}
+
+/// What a call did
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TlsStatus {
+    Ok = 0,
+    /// A pointer that can't be null was
+    NullPointer = 1,
+    /// The source isn't UTF-8
+    InvalidUtf8 = 2,
+    /// The source isn't a valid program, or is empty, `tls_last_error` says why
+    ParseError = 3,
+    /// A register, pin, RAM address or packet length that doesn't exist
+    OutOfRange = 4,
+    /// The pin is an output, driven by the program
+    NotAnInput = 5,
+    /// The TPU's packet queue has no room
+    QueueFull = 6,
+    /// There was nothing to read, no packet waiting or no halt reason
+    Empty = 7,
+    /// Something went wrong inside the VM, the handle should be destroyed
+    Panic = 8,
+    /// The TPU can't be made as asked, e.g. no instructions were given, `tls_last_error` says why
+    ConfigError = 9,
+    /// An instruction has an unknown opcode or the wrong operands, `tls_last_error` says why
+    InvalidInstruction = 10,
+}
+
+/// Why a TPU halted, as `HaltReason`
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TlsHaltReason {
+    Div0 = 0,
+    HltOpcode = 1,
+    InvalidPc = 2,
+    InvalidValue = 3,
+    StackOverflow = 4,
+    StackUnderflow = 5,
+    IndexOutOfRange = 6,
+    WriteProtected = 7,
+    NestedLoop = 8,
+    UnhandledTrap = 9,
+    EndOfProgram = 10,
+    NoSavedContext = 11,
+}
+
+impl From<HaltReason> for TlsHaltReason {
+    fn from(reason: HaltReason) -> Self {
+        match reason {
+            HaltReason::Div0 => TlsHaltReason::Div0,
+            HaltReason::HLTOpcode => TlsHaltReason::HltOpcode,
+            HaltReason::InvalidPC => TlsHaltReason::InvalidPc,
+            HaltReason::InvalidValue => TlsHaltReason::InvalidValue,
+            HaltReason::StackOverflow => TlsHaltReason::StackOverflow,
+            HaltReason::StackUnderflow => TlsHaltReason::StackUnderflow,
+            HaltReason::IndexOutOfRange => TlsHaltReason::IndexOutOfRange,
+            HaltReason::WriteProtected => TlsHaltReason::WriteProtected,
+            HaltReason::NestedLoop => TlsHaltReason::NestedLoop,
+            HaltReason::UnhandledTrap => TlsHaltReason::UnhandledTrap,
+            HaltReason::EndOfProgram => TlsHaltReason::EndOfProgram,
+            HaltReason::NoSavedContext => TlsHaltReason::NoSavedContext,
+        }
+    }
+}
+
+/// A network packet, `length` words of `payload` are in use
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct TlsPacket {
+    pub sender: u16,
+    pub target: u16,
+    pub port: u8,
+    pub length: u16,
+    pub payload: [u16; TLS_MAX_PAYLOAD],
+}
+
+impl From<&NetPacket> for TlsPacket {
+    fn from(packet: &NetPacket) -> Self {
+        let words = packet.words();
+        let mut payload = [0; TLS_MAX_PAYLOAD];
+        payload[..words.len()].copy_from_slice(words);
+        Self {
+            sender: packet.sender,
+            target: packet.target,
+            port: packet.port,
+            length: words.len() as u16,
+            payload,
+        }
+    }
+}
+
+/// An operand of a `TlsInstruction`, a register numbered as `tls_tpu_read_register` or an immediate value
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct TlsOperand {
+    pub is_register: bool,
+    pub value: u16,
+}
+
+/// An instruction by its opcode, see `Instruction::OPCODES`, `operand_count` of `operands` are in use
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct TlsInstruction {
+    pub opcode: u16,
+    pub operand_count: u8,
+    pub operands: [TlsOperand; TLS_MAX_OPERANDS],
+}
+
+impl TryFrom<&TlsInstruction> for Instruction {
+    type Error = String;
+
+    fn try_from(instruction: &TlsInstruction) -> Result<Self, String> {
+        let Some(operands) = instruction
+            .operands
+            .get(..instruction.operand_count as usize)
+        else {
+            return Err(format!(
+                "an instruction can't have {} operands",
+                instruction.operand_count
+            ));
+        };
+        let operands = operands
+            .iter()
+            .map(|operand| match operand.is_register {
+                true => u8::try_from(operand.value)
+                    .ok()
+                    .and_then(Register::from_repr)
+                    .map(OperandValueType::Register)
+                    .ok_or_else(|| format!("there's no register {}", operand.value)),
+                false => Ok(OperandValueType::Immediate(operand.value)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Instruction::from_opcode(instruction.opcode, &operands).map_err(|error| error.to_string())
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+/// Keep `message` for `tls_last_error`
+fn fail(status: TlsStatus, message: String) -> TlsStatus {
+    LAST_ERROR.with(|last| *last.borrow_mut() = message);
+    status
+}
+
+/// Run `call`, turning a panic into `TlsStatus::Panic` so it doesn't unwind into the host
+fn guard(call: impl FnOnce() -> TlsStatus) -> TlsStatus {
+    catch_unwind(AssertUnwindSafe(call)).unwrap_or(TlsStatus::Panic)
+}
+
+/// Run `call` on the TPU behind `handle`
+///
+/// # Safety
+///
+/// `handle` is null or from `tls_tpu_create` and not yet destroyed, and no other call is using it.
+unsafe fn with_tpu(handle: *mut TpuHandle, call: impl FnOnce(&mut TPU) -> TlsStatus) -> TlsStatus {
+    guard(|| match unsafe { handle.as_mut() } {
+        Some(handle) => call(&mut handle.tpu),
+        None => TlsStatus::NullPointer,
+    })
+}
+
+/// Write `value` through `out`, unless it's null
+///
+/// # Safety
+///
+/// `out` is null or valid for a write of `T`.
+unsafe fn write_out<T>(out: *mut T, value: T) -> TlsStatus {
+    if out.is_null() {
+        return TlsStatus::NullPointer;
+    }
+    unsafe { out.write(value) };
+    TlsStatus::Ok
+}
+
+/// Make a TPU running the RGAL program in `source`, at network address `address`. Bit n of `analog_inputs` and
+/// `digital_inputs` makes pin n an input driven by the host, the rest are outputs. The handle is written to `out`
+/// and must be freed with `tls_tpu_destroy`.
+///
+/// # Safety
+///
+/// `source` is a NUL-terminated string and `out` is valid for a write of a pointer.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tls_tpu_create(
+    source: *const c_char,
+    address: u16,
+    analog_inputs: u8,
+    digital_inputs: u8,
+    out: *mut *mut TpuHandle,
+) -> TlsStatus {
+    guard(|| {
+        if source.is_null() || out.is_null() {
+            return TlsStatus::NullPointer;
+        }
+        let Ok(source) = unsafe { CStr::from_ptr(source) }.to_str() else {
+            return TlsStatus::InvalidUtf8;
+        };
+        match parse_program(source) {
+            Ok(program) => unsafe { create(program, address, analog_inputs, digital_inputs, out) },
+            Err(error) => fail(TlsStatus::ParseError, error.to_string()),
+        }
+    })
+}
+
+/// Make a TPU as `tls_tpu_create` does, running the `length` instructions at `program`
+///
+/// # Safety
+///
+/// `program` is valid for reads of `length` instructions and `out` is valid for a write of a pointer.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tls_tpu_create_from_opcodes(
+    program: *const TlsInstruction,
+    length: usize,
+    address: u16,
+    analog_inputs: u8,
+    digital_inputs: u8,
+    out: *mut *mut TpuHandle,
+) -> TlsStatus {
+    guard(|| {
+        if program.is_null() || out.is_null() {
+            return TlsStatus::NullPointer;
+        }
+        let instructions = unsafe { std::slice::from_raw_parts(program, length) };
+        let mut program = Vec::with_capacity(length);
+        for (line, instruction) in instructions.iter().enumerate() {
+            match Instruction::try_from(instruction) {
+                Ok(instruction) => program.push(Rc::new(instruction)),
+                Err(error) => {
+                    return fail(
+                        TlsStatus::InvalidInstruction,
+                        format!("instruction {line}: {error}"),
+                    );
+                }
+            }
+        }
+        unsafe { create(program, address, analog_inputs, digital_inputs, out) }
+    })
+}
+
+/// Build the TPU for the create functions and hand it to the host
+///
+/// # Safety
+///
+/// `out` is valid for a write of a pointer.
+unsafe fn create(
+    program: Vec<Rc<Instruction>>,
+    address: u16,
+    analog_inputs: u8,
+    digital_inputs: u8,
+    out: *mut *mut TpuHandle,
+) -> TlsStatus {
+    let mode = |mask: u8, index: usize| match mask & (1 << index) {
+        0 => PinMode::Output,
+        _ => PinMode::Input,
+    };
+    let mut builder = TpuBuilder::new().network_address(address).program(program);
+    for index in 0..AnalogPin::COUNT {
+        let pin = AnalogPin::from_repr(index as u16).unwrap();
+        builder = builder.analog_pin(pin, mode(analog_inputs, index));
+    }
+    for index in 0..DigitalPin::COUNT {
+        let pin = DigitalPin::from_repr(index as u16).unwrap();
+        builder = builder.digital_pin(pin, mode(digital_inputs, index));
+    }
+    match builder.build() {
+        Ok(tpu) => unsafe { write_out(out, Box::into_raw(Box::new(TpuHandle { tpu }))) },
+        Err(error) => fail(TlsStatus::ConfigError, error.to_string()),
+    }
+}
+
+/// Free a TPU, null is ignored
+///
+/// # Safety
+///
+/// `handle` is null or from `tls_tpu_create` and not yet destroyed. It can't be used after this.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tls_tpu_destroy(handle: *mut TpuHandle) {
+    if handle.is_null() {
+        return;
+    }
+    // Nothing can be done about a panic while dropping, but it mustn't unwind into the host
+    let _ = catch_unwind(AssertUnwindSafe(|| drop(unsafe { Box::from_raw(handle) })));
+}
+
+/// Run one clock cycle
+///
+/// # Safety
+///
+/// `handle` is null or from `tls_tpu_create` and not yet destroyed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tls_tpu_tick(handle: *mut TpuHandle) -> TlsStatus {
+    unsafe {
+        with_tpu(handle, |tpu| {
+            tpu.tick();
+            TlsStatus::Ok
+        })
+    }
+}
+
+/// Run up to `ticks` clock cycles, stopping early if the TPU halts
+///
+/// # Safety
+///
+/// `handle` is null or from `tls_tpu_create` and not yet destroyed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tls_tpu_run(handle: *mut TpuHandle, ticks: u64) -> TlsStatus {
+    unsafe {
+        with_tpu(handle, |tpu| {
+            tpu.run(ticks);
+            TlsStatus::Ok
+        })
+    }
+}
+
+/// Read a register, numbered A, X, Y then R0 to R6 from 0
+///
+/// # Safety
+///
+/// `handle` is null or from `tls_tpu_create` and not yet destroyed, `out` is null or valid for a write.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tls_tpu_read_register(
+    handle: *mut TpuHandle,
+    register: u8,
+    out: *mut u16,
+) -> TlsStatus {
+    unsafe {
+        with_tpu(handle, |tpu| match Register::from_repr(register) {
+            Some(register) => write_out(out, tpu.read_register(register)),
+            None => TlsStatus::OutOfRange,
+        })
+    }
+}
+
+/// Write a register, numbered as `tls_tpu_read_register`
+///
+/// # Safety
+///
+/// `handle` is null or from `tls_tpu_create` and not yet destroyed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tls_tpu_write_register(
+    handle: *mut TpuHandle,
+    register: u8,
+    value: u16,
+) -> TlsStatus {
+    unsafe {
+        with_tpu(handle, |tpu| match Register::from_repr(register) {
+            Some(register) => {
+                tpu.write_register(register, value);
+                TlsStatus::Ok
+            }
+            None => TlsStatus::OutOfRange,
+        })
+    }
+}
+
+/// Read a word of RAM as it's stored, memory mapped I/O isn't seen
+///
+/// # Safety
+///
+/// `handle` is null or from `tls_tpu_create` and not yet destroyed, `out` is null or valid for a write.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tls_tpu_read_ram(
+    handle: *mut TpuHandle,
+    address: u16,
+    out: *mut u16,
+) -> TlsStatus {
+    unsafe {
+        with_tpu(handle, |tpu| match tpu.state().ram.get(address as usize) {
+            Some(&value) => write_out(out, value),
+            None => TlsStatus::OutOfRange,
+        })
+    }
+}
+
+/// Write a word of RAM, bypassing write protection and memory mapped I/O as `TPU::write_ram_slice` does
+///
+/// # Safety
+///
+/// `handle` is null or from `tls_tpu_create` and not yet destroyed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tls_tpu_write_ram(
+    handle: *mut TpuHandle,
+    address: u16,
+    value: u16,
+) -> TlsStatus {
+    unsafe {
+        with_tpu(handle, |tpu| {
+            if address as usize >= tpu.ram_size() {
+                return TlsStatus::OutOfRange;
+            }
+            tpu.write_ram_slice(address as usize, &[value]);
+            TlsStatus::Ok
+        })
+    }
+}
+
+/// Drive an analog input, clamped to the pin's resolution
+///
+/// # Safety
+///
+/// `handle` is null or from `tls_tpu_create` and not yet destroyed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tls_tpu_drive_analog(
+    handle: *mut TpuHandle,
+    pin: u8,
+    value: u16,
+) -> TlsStatus {
+    unsafe {
+        with_tpu(handle, |tpu| match AnalogPin::from_repr(pin as u16) {
+            Some(pin) => match tpu.drive_analog_input(pin, value) {
+                Ok(()) => TlsStatus::Ok,
+                Err(_) => TlsStatus::NotAnInput,
+            },
+            None => TlsStatus::OutOfRange,
+        })
+    }
+}
+
+/// Drive a digital input high if `value` isn't 0
+///
+/// # Safety
+///
+/// `handle` is null or from `tls_tpu_create` and not yet destroyed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tls_tpu_drive_digital(
+    handle: *mut TpuHandle,
+    pin: u8,
+    value: bool,
+) -> TlsStatus {
+    unsafe {
+        with_tpu(handle, |tpu| match DigitalPin::from_repr(pin as u16) {
+            Some(pin) => match tpu.drive_digital_input(pin, value) {
+                Ok(()) => TlsStatus::Ok,
+                Err(_) => TlsStatus::NotAnInput,
+            },
+            None => TlsStatus::OutOfRange,
+        })
+    }
+}
+
+/// Read an analog pin, input or output
+///
+/// # Safety
+///
+/// `handle` is null or from `tls_tpu_create` and not yet destroyed, `out` is null or valid for a write.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tls_tpu_read_analog(
+    handle: *mut TpuHandle,
+    pin: u8,
+    out: *mut u16,
+) -> TlsStatus {
+    unsafe {
+        with_tpu(handle, |tpu| match AnalogPin::from_repr(pin as u16) {
+            Some(pin) => write_out(out, tpu.get_analog_pin(pin)),
+            None => TlsStatus::OutOfRange,
+        })
+    }
+}
+
+/// Read every digital pin as a word, bit n is pin n
+///
+/// # Safety
+///
+/// `handle` is null or from `tls_tpu_create` and not yet destroyed, `out` is null or valid for a write.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tls_tpu_read_digital(handle: *mut TpuHandle, out: *mut u16) -> TlsStatus {
+    unsafe { with_tpu(handle, |tpu| write_out(out, tpu.get_digital_pins())) }
+}
+
+/// Hand the TPU a packet as if the network had delivered it, `TLS_STATUS_QUEUE_FULL` if it has no room
+///
+/// # Safety
+///
+/// `handle` is null or from `tls_tpu_create` and not yet destroyed, `packet` is null or valid for a read.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tls_tpu_inject_packet(
+    handle: *mut TpuHandle,
+    packet: *const TlsPacket,
+) -> TlsStatus {
+    unsafe {
+        with_tpu(handle, |tpu| {
+            let Some(packet) = packet.as_ref() else {
+                return TlsStatus::NullPointer;
+            };
+            let Some(words) = packet.payload.get(..packet.length as usize) else {
+                return TlsStatus::OutOfRange;
+            };
+            let packet =
+                NetPacket::with_payload(packet.sender, packet.target, words).on_port(packet.port);
+            match tpu.deliver_packet(packet) {
+                true => TlsStatus::Ok,
+                false => TlsStatus::QueueFull,
+            }
+        })
+    }
+}
+
+/// Take the oldest packet the TPU has sent, `TLS_STATUS_EMPTY` if there are none
+///
+/// # Safety
+///
+/// `handle` is null or from `tls_tpu_create` and not yet destroyed, `out` is null or valid for a write.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tls_tpu_drain_packet(
+    handle: *mut TpuHandle,
+    out: *mut TlsPacket,
+) -> TlsStatus {
+    unsafe {
+        with_tpu(handle, |tpu| {
+            if out.is_null() {
+                return TlsStatus::NullPointer;
+            }
+            match tpu.take_outgoing_packet() {
+                Some(packet) => write_out(out, TlsPacket::from(&packet)),
+                None => TlsStatus::Empty,
+            }
+        })
+    }
+}
+
+/// Why the TPU halted, `TLS_STATUS_EMPTY` if it's still running
+///
+/// # Safety
+///
+/// `handle` is null or from `tls_tpu_create` and not yet destroyed, `out` is null or valid for a write.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tls_tpu_halt_reason(
+    handle: *mut TpuHandle,
+    out: *mut TlsHaltReason,
+) -> TlsStatus {
+    unsafe {
+        with_tpu(handle, |tpu| match tpu.state().halt_reason {
+            Some(reason) => write_out(out, reason.into()),
+            None => TlsStatus::Empty,
+        })
+    }
+}
+
+/// Copy the message of the last create that failed on this thread into `buffer`, cut short to fit and always
+/// NUL-terminated. Returns the length of the whole message, so a host can tell it was cut short and ask again with
+/// more room, 0 if there hasn't been one.
+///
+/// # Safety
+///
+/// `buffer` is null or valid for `length` bytes of writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn tls_last_error(buffer: *mut c_char, length: usize) -> usize {
+    catch_unwind(|| {
+        LAST_ERROR.with(|last| {
+            let message = last.borrow();
+            if !buffer.is_null() && length > 0 {
+                let copied = message.len().min(length - 1);
+                unsafe {
+                    ptr::copy_nonoverlapping(message.as_ptr().cast(), buffer, copied);
+                    buffer.add(copied).write(0);
+                }
+            }
+            message.len()
+        })
+    })
+    .unwrap_or(0)
+}
@@ -1,3 +1,42 @@
+//! The TPU, the RGAL assembler and the tools around them, for embedding the TPU in something else. The `tls` binary's
+//! debugger is built on this crate.
+//!
+//! Most programs only need the `prelude`:
+//!
+//! ```
+//! use tls::prelude::*;
+//!
+//! let program = parse_program("LDR A, 2\nLDR X, 3\nADD A, X\nHLT").unwrap();
+//! let mut tpu = create_basic_tpu_config(program);
+//! assert_eq!(tpu.run(100), RunResult::Halted(HaltReason::HLTOpcode));
+//! assert_eq!(tpu.read_register(Register::A), 5);
+//! ```
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod cli;
+#[cfg(feature = "std")]
+mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod log;
+pub mod network;
+pub mod observer;
+pub mod opcode;
+pub mod prelude;
+#[cfg(feature = "std")]
 pub mod rgal;
+#[cfg(feature = "std")]
+pub mod scenario;
 pub mod shared;
 pub mod tpu;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "std")]
+pub mod wave;
+
+#[cfg(feature = "std")]
+pub use error::{Error, Result};
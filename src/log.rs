@@ -0,0 +1,15 @@
+//! `tracing`'s macros with the `std` feature. Without it there's nowhere to log to, so they only check their
+//! arguments.
+
+#[cfg(feature = "std")]
+pub(crate) use tracing::{error, info, trace};
+
+#[cfg(not(feature = "std"))]
+macro_rules! discard {
+    ($($arg:tt)*) => {{
+        let _ = format_args!($($arg)*);
+    }};
+}
+
+#[cfg(not(feature = "std"))]
+pub(crate) use {discard as error, discard as info, discard as trace};
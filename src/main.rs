@@ -1,50 +1,90 @@
-mod rgal;
-mod shared;
-mod tpu;
+mod tui;
 
-use crate::shared::{AnalogPin, DigitalPin, Register};
-use crate::tpu::create_basic_tpu_config;
+use crate::tui::analog::Sampling;
+use crate::tui::app::{Action, App};
+use crate::tui::log::{LogBuffer, LogLayer};
+use crate::tui::machines::Machines;
+use crate::tui::ui::ui;
 use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode},
+    event::{self, DisableMouseCapture, EnableMouseCapture, Event},
     execute,
     terminal::{EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode, enable_raw_mode},
 };
-use ratatui::{
-    Frame, Terminal,
-    layout::{Constraint, Direction, Layout},
-    style::{Color, Style},
-    widgets::{Block, Borders, Paragraph},
-};
+use ratatui::Terminal;
 use std::{
     error::Error,
     io,
     time::{Duration, Instant},
 };
-use strum::IntoEnumIterator;
-use tracing::Level;
-use tracing_subscriber;
-use tracing_subscriber::fmt::format;
-
-fn main() -> Result<(), Box<dyn Error>> {
-    // tracing_subscriber::fmt()
-    //     .with_max_level(Level::TRACE)
-    //     .init();
-
-    // Create app state
-    let program = rgal::parse_program(
-        r#"
+use tls::rgal::SymbolResolver;
+use tls::shared::{AnalogPin, DigitalPin};
+use tls::tpu::peripheral::seven_segment::{SevenSegment, SevenSegmentDisplay};
+use tls::{cli, tpu};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+
+/// Run when no program is given
+const DEMO_PROGRAM: &str = r#"
         LDR A, 0
         LDR X, 0b100000001
-        SMOI 0, X, Y
-        BLE 5, Y, 129
+LOOP:   SMOI 0, X, Y
+        BLE SHOW, Y, 129
         LDR Y, 0
-        DPWW X
+SHOW:   DPWW X
         ROL X, X, 1
-        JMP 2"#,
-    )
-    .unwrap();
+        JMP LOOP"#;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let options = cli::parse_args(std::env::args().skip(1)).unwrap_or_else(|error| {
+        eprintln!("{error}");
+        std::process::exit(2);
+    });
+    if options.help {
+        println!("{}", cli::USAGE);
+        return Ok(());
+    }
+
+    // Create app state, several TPUs go on a network
+    let mut machines = if options.tpus.is_empty() {
+        Machines::single(create_tpu(&options)?)
+    } else {
+        Machines::network(options.create_network().unwrap_or_else(|error| {
+            eprintln!("{error}");
+            std::process::exit(1);
+        }))
+    };
 
-    let mut tpu = create_basic_tpu_config(program);
+    for index in 0..machines.len() {
+        machines.tpu_mut(index).enable_history(options.history);
+        load_symbols(&mut machines, &options, index);
+    }
+
+    // A one digit display on the first seven digital pins of each TPU
+    let segments = [
+        DigitalPin::Digital0,
+        DigitalPin::Digital1,
+        DigitalPin::Digital2,
+        DigitalPin::Digital3,
+        DigitalPin::Digital4,
+        DigitalPin::Digital5,
+        DigitalPin::Digital6,
+    ];
+    let displays: Vec<_> = (0..machines.len())
+        .map(|index| {
+            let seven_segment = SevenSegment::new(segments, AnalogPin::Analog0, 1);
+            let display = seven_segment.display();
+            machines
+                .tpu_mut(index)
+                .attach_peripheral(Box::new(seven_segment));
+            display
+        })
+        .collect();
+
+    // Events are kept for the log panel, written out they'd spoil the screen
+    let log = LogBuffer::default();
+    tracing_subscriber::registry()
+        .with(LogLayer::new(log.clone()))
+        .init();
 
     // Setup terminal
     enable_raw_mode()?;
@@ -54,7 +94,7 @@ fn main() -> Result<(), Box<dyn Error>> {
     let mut terminal = Terminal::new(backend)?;
 
     // Run the app
-    let res = run_app(&mut terminal, &mut tpu);
+    let res = run_app(&mut terminal, &mut machines, &displays, &options, log);
 
     // Restore terminal
     disable_raw_mode()?;
@@ -72,296 +112,103 @@ fn main() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-fn run_app<B: ratatui::backend::Backend>(
-    terminal: &mut Terminal<B>,
-    tpu: &mut tpu::TPU,
-) -> io::Result<()> {
-    let tick_rate = Duration::from_millis(50);
-    let mut last_tick = Instant::now();
-    let step_rate = Duration::from_millis(50); // 2 Hz for continuous running
-    let mut last_step = Instant::now();
-    let mut continuous_running = false;
-
-    loop {
-        terminal.draw(|f| ui(f, tpu.state(), continuous_running))?;
-
-        let timeout = tick_rate
-            .checked_sub(last_tick.elapsed())
-            .unwrap_or_else(|| Duration::from_secs(0));
-
-        if event::poll(timeout)? {
-            if let Event::Key(key) = event::read()? {
-                match key.code {
-                    KeyCode::Char('q') => return Ok(()),
-                    KeyCode::Char('s') => {
-                        tpu.step();
-                    }
-                    KeyCode::Char(' ') => {
-                        tpu.tick();
-                    }
-                    KeyCode::Char('r') | KeyCode::Char('R') => {
-                        continuous_running = true;
-                    }
-                    KeyCode::Char('b') | KeyCode::Char('B') => {
-                        continuous_running = false;
-                    }
-                    _ => {}
-                }
-            }
-        }
-
-        // Handle continuous running mode
-        if continuous_running && last_step.elapsed() >= step_rate {
-            tpu.step();
-            last_step = Instant::now();
-        }
-
-        if last_tick.elapsed() >= tick_rate {
-            last_tick = Instant::now();
-        }
-    }
+/// Name the lines of the program of the TPU at `index` by its labels
+fn load_symbols(machines: &mut Machines, options: &cli::Options, index: usize) {
+    let symbols = options.load_symbols(index, DEMO_PROGRAM);
+    let len = machines.tpu(index).read_rom().len();
+    machines.set_symbols(index, SymbolResolver::new(&symbols, len));
 }
 
-fn ui(f: &mut Frame, tpu: &tpu::TpuState, continuous_running: bool) {
-    // Create main layout with title and content areas
-    let main_chunks = Layout::default()
-        .direction(Direction::Vertical)
-        .margin(1)
-        .constraints(
-            [
-                Constraint::Length(3), // Title
-                Constraint::Min(0),    // Content
-            ]
-            .as_ref(),
-        )
-        .split(f.size());
-
-    // Title with mode indicator
-    let mode_text = if continuous_running {
-        "TPU Simulator - RUNNING (Press B to stop) - Space to tick, S to Step, R to run, Q to quit"
+/// The TPU for PROGRAM, or the demo. `run` and `repl` are done with it here, they don't need the debugger.
+fn create_tpu(options: &cli::Options) -> Result<tpu::TPU, Box<dyn Error>> {
+    // The REPL starts with an empty program rather than the demo
+    let program = if options.command == cli::Command::Repl && options.program.is_none() {
+        Vec::new()
     } else {
-        "TPU Simulator - Press Space to tick, S to Step, R to run, Q to quit"
+        options.load_program(DEMO_PROGRAM).unwrap_or_else(|error| {
+            eprintln!("{error}");
+            std::process::exit(1);
+        })
     };
+    let mut tpu = options.create_tpu(program);
 
-    let title = Paragraph::new(mode_text)
-        .style(Style::default().fg(Color::Cyan))
-        .block(Block::default().borders(Borders::ALL));
-    f.render_widget(title, main_chunks[0]);
-
-    // Split content area into left and right columns
-    let content_chunks = Layout::default()
-        .direction(Direction::Horizontal)
-        .constraints(
-            [
-                Constraint::Percentage(50), // Left column
-                Constraint::Percentage(50), // Right column
-            ]
-            .as_ref(),
-        )
-        .split(main_chunks[1]);
-
-    // Split left column into sections
-    let left_chunks = Layout::default()
-        .direction(Direction::Vertical)
-        .constraints(
-            [
-                Constraint::Percentage(25), // CPU Status
-                Constraint::Percentage(25), // Registers
-                Constraint::Percentage(25), // Network
-                Constraint::Percentage(25), // Stack
-            ]
-            .as_ref(),
-        )
-        .split(content_chunks[0]);
-
-    // Split right column into sections
-    let right_chunks = Layout::default()
-        .direction(Direction::Vertical)
-        .constraints(
-            [
-                Constraint::Percentage(33), // RAM
-                Constraint::Percentage(33), // ROM
-                Constraint::Percentage(34), // I/O Pins
-            ]
-            .as_ref(),
-        )
-        .split(content_chunks[1]);
-
-    // Render each component
-    render_cpu_status(f, tpu, left_chunks[0]);
-    render_registers(f, tpu, left_chunks[1]);
-    render_network(f, tpu, left_chunks[2]);
-    render_stack(f, tpu, left_chunks[3]);
-    render_ram(f, tpu, right_chunks[0]);
-    render_rom(f, tpu, right_chunks[1]);
-    render_io_pins(f, tpu, right_chunks[2]);
-}
-
-fn render_cpu_status(f: &mut Frame, tpu: &tpu::TpuState, area: ratatui::layout::Rect) {
-    let halted = tpu.halted;
-    let program_counter = tpu.program_counter;
-    let wait_cycles = tpu.execution_state.wait_cycles;
-    let text = format!(
-        "Program Counter: {:04X}\nWait Cycles: {:04X}\nHalted: {}",
-        program_counter, wait_cycles, halted
-    );
-    let widget =
-        Paragraph::new(text).block(Block::default().borders(Borders::ALL).title("TPU Status"));
-    f.render_widget(widget, area);
-}
-
-fn render_registers(f: &mut Frame, tpu: &tpu::TpuState, area: ratatui::layout::Rect) {
-    let mut text = String::new();
-    for register in Register::iter() {
-        let value = tpu.registers[register as usize];
-        text.push_str(&format!("{:2}: {:04X}\n", format!("{:?}", register), value));
+    if options.command == cli::Command::Repl {
+        cli::repl::run_repl(&mut tpu, io::stdin().lock(), io::stdout())?;
+        std::process::exit(0);
     }
-    let widget =
-        Paragraph::new(text).block(Block::default().borders(Borders::ALL).title("Registers"));
-    f.render_widget(widget, area);
-}
-
-fn render_network(f: &mut Frame, tpu: &tpu::TpuState, area: ratatui::layout::Rect) {
-    let network_address = tpu.network_address;
-    let incoming_packets = tpu.incoming_packets.len();
-    let outgoing_packets = tpu.outgoing_packets.len();
-
-    let text = format!(
-        "Network Address: {:04X}\nIncoming Packets: {}\nOutgoing Packets: {}",
-        network_address, incoming_packets, outgoing_packets
-    );
-
-    let widget =
-        Paragraph::new(text).block(Block::default().borders(Borders::ALL).title("Network"));
-    f.render_widget(widget, area);
-}
-
-fn render_stack(f: &mut Frame, tpu: &tpu::TpuState, area: ratatui::layout::Rect) {
-    let stack_size = tpu.stack.len();
-    let stack_contents = &tpu.stack;
-
-    let mut text = format!("Stack Size: {}\n", stack_size);
-
-    if stack_contents.is_empty() {
-        text.push_str("<empty>");
-    } else {
-        for (i, &value) in stack_contents.iter().enumerate() {
-            text.push_str(&format!("{}: {:04X}\n", i, value));
+    if options.command == cli::Command::Run {
+        let summary = cli::headless::run_headless(&mut tpu, options.max_cycles, &options.dump_ram);
+        if options.json {
+            println!("{}", summary.to_json()?);
+        } else {
+            println!("{summary}");
         }
+        std::process::exit(summary.exit_code());
     }
-
-    let widget = Paragraph::new(text).block(Block::default().borders(Borders::ALL).title("Stack"));
-    f.render_widget(widget, area);
+    Ok(tpu)
 }
 
-fn render_ram(f: &mut Frame, tpu: &tpu::TpuState, area: ratatui::layout::Rect) {
-    let ram_size = tpu.ram.len();
-
-    let mut text = String::new();
-
-    // Display a portion of RAM (first 32 bytes)
-    for i in 0..ram_size {
-        if i % 4 == 0 && i > 0 {
-            text.push('\n');
-        }
-        let value = tpu.ram[i];
-        text.push_str(&format!("{:04X}: {:04X} ", i, value));
+fn run_app<B: ratatui::backend::Backend>(
+    terminal: &mut Terminal<B>,
+    machines: &mut Machines,
+    displays: &[SevenSegmentDisplay],
+    options: &cli::Options,
+    log: LogBuffer,
+) -> io::Result<()> {
+    let frame_rate = Duration::from_millis(50);
+    let mut last_frame = Instant::now();
+    let mut app = App::new();
+    app.log = log;
+    if let Some(ticks) = options.highlight_ticks {
+        app.highlights.fade_ticks = ticks.into();
     }
-
-    let widget = Paragraph::new(text).block(
-        Block::default()
-            .borders(Borders::ALL)
-            .title(format!("RAM, {} words", ram_size)),
-    );
-    f.render_widget(widget, area);
-}
-
-fn render_rom(f: &mut Frame, tpu: &tpu::TpuState, area: ratatui::layout::Rect) {
-    let rom = &tpu.rom;
-    let rom_size = rom.len();
-    let program_counter = tpu.program_counter;
-
-    let mut text = format!(
-        "ROM Size: {}\nProgram Counter: {:04X}\n \n  ADDR  INSTRUCTION\n  ----  ------------\n",
-        rom_size, program_counter
-    );
-
-    // Display a portion of ROM (first 8 instructions)
-    //let display_size = std::cmp::min(8, rom_size);
-    for i in 0..rom_size {
-        if let Some(instruction) = tpu.rom.get(i) {
-            let marker = if i == program_counter { ">" } else { " " };
-            text.push_str(&format!("{} {:04X}: {}\n", marker, i, instruction));
-        }
+    if let Some(bytes) = options.trace_buffer {
+        app.trace.buffer = bytes;
     }
-
-    let widget = Paragraph::new(text).block(Block::default().borders(Borders::ALL).title("ROM"));
-    f.render_widget(widget, area);
-}
-
-fn render_io_pins(f: &mut Frame, tpu: &tpu::TpuState, area: ratatui::layout::Rect) {
-    let chunks = Layout::default()
-        .direction(Direction::Vertical)
-        .constraints(
-            [
-                Constraint::Percentage(50), // Analog
-                Constraint::Percentage(50), // Digital
-            ]
-            .as_ref(),
-        )
-        .split(area);
-
-    render_digital_io_block(f, tpu, chunks[0]);
-    render_analog_io_block(f, tpu, chunks[1]);
-    // // For now, just display a placeholder
-    // let widget = Paragraph::new("I/O Pin states will be displayed here")
-    //     .block(Block::default().borders(Borders::ALL).title("I/O Pins"));
-    //    f.render_widget(widget, area);
-}
-
-fn render_digital_io_block(f: &mut Frame, tpu: &tpu::TpuState, area: ratatui::layout::Rect) {
-    let constraints = DigitalPin::iter().map(|_| Constraint::Fill(1));
-
-    let chunks = Layout::default()
-        .direction(Direction::Horizontal)
-        .constraints(constraints)
-        .split(area);
-
-    for pin in DigitalPin::iter() {
-        let state = tpu.digital_pins[pin as usize];
-        let widget = Paragraph::new("")
-            .style(Style::default().fg(Color::White).bg(if state {
-                Color::Green
-            } else {
-                Color::Black
-            }))
-            .block(
-                Block::default()
-                    .borders(Borders::ALL)
-                    .title(format!("{pin:?}")),
-            );
-        f.render_widget(widget, chunks[pin as usize]);
+    if options.sample_ticks {
+        app.analog_history.sampling = Sampling::Tick;
     }
-}
 
-fn render_analog_io_block(f: &mut Frame, tpu: &tpu::TpuState, area: ratatui::layout::Rect) {
-    let constraints = AnalogPin::iter().map(|_| Constraint::Fill(1));
+    loop {
+        app.highlights.update(machines.focused().state());
+        app.watches.update(machines.focused());
+        app.analog_history.sample(machines.focused().state());
+        terminal.draw(|f| ui(f, machines, displays, &mut app))?;
+
+        // Wait for a key or the mouse until the next frame is due
+        let timeout = frame_rate
+            .checked_sub(last_frame.elapsed())
+            .unwrap_or_else(|| Duration::from_secs(0));
 
-    let chunks = Layout::default()
-        .direction(Direction::Horizontal)
-        .constraints(constraints)
-        .split(area);
+        if event::poll(timeout)? {
+            match event::read()? {
+                Event::Key(key) => match app.handle_key(key.code, machines) {
+                    Action::Continue => {}
+                    Action::Quit => return Ok(()),
+                    Action::Reload => {
+                        match options.reload_program(machines.focus(), DEMO_PROGRAM) {
+                            Ok(program) => {
+                                machines.focused_mut().load_program(program);
+                                load_symbols(machines, options, machines.focus());
+                            }
+                            // Keep the program that's loaded, the error is usually a line or two
+                            Err(error) => {
+                                app.banner =
+                                    Some(format!("reload failed: {error}").replace('\n', " "))
+                            }
+                        }
+                    }
+                },
+                Event::Mouse(mouse) => app.handle_mouse(mouse, machines),
+                _ => {}
+            }
+        }
 
-    for pin in AnalogPin::iter() {
-        let state = tpu.analog_pins[pin as usize];
-        let widget = Paragraph::new(format!("{}", state))
-            .style(Style::default().fg(Color::White).bg(Color::Black))
-            .centered()
-            .block(
-                Block::default()
-                    .borders(Borders::ALL)
-                    .title(format!("{pin:?}")),
-            );
-        f.render_widget(widget, chunks[pin as usize]);
+        // Run the ticks due since the last frame in one go, then draw
+        let elapsed = last_frame.elapsed();
+        if elapsed >= frame_rate {
+            last_frame = Instant::now();
+            app.run_frame(machines, elapsed);
+        }
     }
 }
@@ -0,0 +1,349 @@
+#[cfg(test)]
+mod network_test;
+pub mod sniffer;
+
+use crate::network::sniffer::{PacketEvent, PacketEventKind};
+use crate::observer::{Observer, ObserverId, Observers, Sniffer};
+use crate::shared::NetPacket;
+use crate::tpu::TPU;
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Several TPUs sharing a network, ticked in lockstep.
+///
+/// Each tick every TPU is ticked in the order it was added, then the packets they sent are delivered in the same
+/// order, so a packet sent on one tick can be received on the next. A packet to `TPU::BROADCAST_ADDRESS` is copied to
+/// every TPU except its sender, unless the sender has `TpuConfig::broadcast` turned off, any other packet goes to the
+/// TPUs with that address. Packets nobody can take are dropped.
+///
+/// A packet can be held in flight for a number of ticks before it reaches its receiver, set for the whole network or
+/// for a single link between two TPUs. Packets due on the same tick arrive in the order they were sent.
+///
+/// To test programs against an unreliable link, the network can be told to flip a bit in some of the packets it
+/// delivers, chosen by a seeded generator so a run can be repeated. The damaged packets fail their checksum.
+///
+/// A sniffer can watch every packet the network sends, delivers and drops, in the order the network handles them.
+/// Packets a TPU sends to itself never reach the network, so the sniffer doesn't see them.
+///
+/// A TPU can change its address with NETA. The network picks the change up after the tick, before delivering, unless
+/// another TPU already has that address, then the TPU gets its old address back and the collision is recorded.
+#[derive(Default)]
+pub struct Network {
+    tpus: Vec<TPU>,
+    /// Indexes of the TPUs with each address
+    addresses: BTreeMap<u16, Vec<usize>>,
+    /// The address each TPU was last indexed under
+    indexed: Vec<u16>,
+    collisions: Vec<AddressCollision>,
+    /// Packets each TPU missed because its incoming buffer was full
+    drops: Vec<u64>,
+    /// One in this many delivered packets is damaged, 0 never damages any
+    corrupt_one_in: u32,
+    corruption_rng: u32,
+    corrupted: u64,
+    /// Ticks a packet spends in flight, unless its link has its own latency
+    latency: u64,
+    /// Latency of single links, keyed by the indexes of the sender and receiver
+    link_latency: BTreeMap<(usize, usize), u64>,
+    /// Packets on their way, keyed by the tick they arrive on, with the index of their receiver
+    in_flight: BTreeMap<u64, Vec<(usize, NetPacket)>>,
+    /// Ticks since the network was created
+    cycle: u64,
+    /// Told about the network's ticks and packets
+    observers: Observers,
+    /// The observer `Network::set_sniffer` added
+    sniffer: Option<ObserverId>,
+}
+
+/// A TPU asking for an address another TPU already has, see `Network::address_collisions`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct AddressCollision {
+    /// The network's cycle when it was refused
+    pub cycle: u64,
+    /// Index of the TPU that asked
+    pub tpu: usize,
+    pub address: u16,
+}
+
+/// Why `Network::run` stopped
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum NetworkRunResult {
+    /// A TPU's next instruction to be fetched is at one of its breakpoints
+    Breakpoint { tpu: usize, address: usize },
+    /// Every TPU has halted, there's nothing left to run
+    AllHalted,
+    /// All the ticks asked for were run
+    OutOfTicks,
+}
+
+impl Network {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Connect a TPU, returning its index
+    pub fn add_tpu(&mut self, tpu: TPU) -> usize {
+        let index = self.tpus.len();
+        let address = tpu.state().network_address;
+        self.addresses.entry(address).or_default().push(index);
+        self.indexed.push(address);
+        self.tpus.push(tpu);
+        self.drops.push(0);
+        index
+    }
+
+    /// Whether a TPU on the network has `address`
+    pub fn has_address(&self, address: u16) -> bool {
+        self.addresses
+            .get(&address)
+            .is_some_and(|indexes| !indexes.is_empty())
+    }
+
+    pub fn tpu(&self, index: usize) -> &TPU {
+        &self.tpus[index]
+    }
+
+    pub fn tpu_mut(&mut self, index: usize) -> &mut TPU {
+        &mut self.tpus[index]
+    }
+
+    pub fn len(&self) -> usize {
+        self.tpus.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tpus.is_empty()
+    }
+
+    /// Packets the TPU missed because its incoming buffer was full
+    pub fn drops(&self, index: usize) -> u64 {
+        self.drops[index]
+    }
+
+    /// Address changes that were refused because another TPU had the address, oldest first
+    pub fn address_collisions(&self) -> &[AddressCollision] {
+        &self.collisions
+    }
+
+    /// Damage one in `one_in` delivered packets, starting the generator from `seed`, 0 turns it off
+    pub fn set_corruption(&mut self, one_in: u32, seed: u32) {
+        self.corrupt_one_in = one_in;
+        // Xorshift never leaves 0
+        self.corruption_rng = seed.max(1);
+    }
+
+    /// Packets damaged in transit
+    pub fn corrupted(&self) -> u64 {
+        self.corrupted
+    }
+
+    /// Ticks every packet spends in flight, 0 delivers at the end of the tick it was sent on
+    pub fn set_latency(&mut self, cycles: u64) {
+        self.latency = cycles;
+    }
+
+    /// Ticks packets from the `sender` TPU to the `receiver` TPU spend in flight, overriding the network's latency
+    pub fn set_link_latency(&mut self, sender: usize, receiver: usize, cycles: u64) {
+        self.link_latency.insert((sender, receiver), cycles);
+    }
+
+    /// Call `sniffer` for every packet sent, delivered or dropped, see `PacketLog` for one that records them
+    pub fn set_sniffer(&mut self, sniffer: impl FnMut(&PacketEvent) + 'static) {
+        if let Some(id) = self.sniffer.take() {
+            self.observers.remove(id);
+        }
+        self.sniffer = Some(self.observers.add(Sniffer(sniffer)));
+    }
+
+    /// Tell `observer` about the network's ticks and packets from now on, until it's removed. The TPUs' own events go
+    /// to their observers, see `TPU::add_observer`.
+    pub fn add_observer(&mut self, observer: impl Observer + 'static) -> ObserverId {
+        self.observers.add(observer)
+    }
+
+    /// Returns whether there was an observer with `id`
+    pub fn remove_observer(&mut self, id: ObserverId) -> bool {
+        self.observers.remove(id)
+    }
+
+    /// Ticks since the network was created
+    pub fn cycle(&self) -> u64 {
+        self.cycle
+    }
+
+    /// Tick every TPU once, then deliver what they sent
+    pub fn tick(&mut self) {
+        for tpu in &mut self.tpus {
+            tpu.tick();
+        }
+        self.cycle += 1;
+        self.reindex();
+        self.send();
+        self.arrive();
+        if !self.observers.is_empty() {
+            let cycle = self.cycle;
+            self.observers.notify(|observer| observer.on_tick(cycle));
+        }
+    }
+
+    /// Tick up to `ticks` times like `TPU::run`, stopping early at the first TPU to reach a breakpoint, or once every
+    /// TPU has halted. A TPU that halts on its own doesn't stop the others.
+    pub fn run(&mut self, ticks: u64) -> NetworkRunResult {
+        for _ in 0..ticks {
+            if self.all_halted() {
+                return NetworkRunResult::AllHalted;
+            }
+            self.tick();
+            if let Some(tpu) = self
+                .tpus
+                .iter()
+                .position(|tpu| !tpu.halted() && tpu.at_breakpoint())
+            {
+                return NetworkRunResult::Breakpoint {
+                    tpu,
+                    address: self.tpus[tpu].state().program_counter,
+                };
+            }
+        }
+        if self.all_halted() {
+            NetworkRunResult::AllHalted
+        } else {
+            NetworkRunResult::OutOfTicks
+        }
+    }
+
+    pub fn all_halted(&self) -> bool {
+        self.tpus.iter().all(TPU::halted)
+    }
+
+    /// Pick up TPUs that changed their address, giving the old address back to any that asked for one in use
+    fn reindex(&mut self) {
+        for index in 0..self.tpus.len() {
+            let old = self.indexed[index];
+            let new = self.tpus[index].state().network_address;
+            if new == old {
+                continue;
+            }
+            if self.addresses.contains_key(&new) {
+                self.tpus[index].set_network_address(old);
+                self.collisions.push(AddressCollision {
+                    cycle: self.cycle,
+                    tpu: index,
+                    address: new,
+                });
+                continue;
+            }
+
+            if let Some(indexes) = self.addresses.get_mut(&old) {
+                indexes.retain(|&other| other != index);
+                if indexes.is_empty() {
+                    self.addresses.remove(&old);
+                }
+            }
+            self.addresses.insert(new, vec![index]);
+            self.indexed[index] = new;
+        }
+    }
+
+    /// Put the packets the TPUs sent in flight
+    fn send(&mut self) {
+        for sender in 0..self.tpus.len() {
+            let broadcast = self.tpus[sender].state().config.broadcast;
+            while let Some(packet) = self.tpus[sender].take_outgoing_packet() {
+                self.sniff(PacketEventKind::Sent, &packet, packet.target);
+                if broadcast && packet.target == TPU::BROADCAST_ADDRESS {
+                    for receiver in (0..self.tpus.len()).filter(|&receiver| receiver != sender) {
+                        self.dispatch(sender, receiver, packet);
+                    }
+                    continue;
+                }
+
+                match self.addresses.get(&packet.target).cloned() {
+                    Some(receivers) => {
+                        for receiver in receivers {
+                            self.dispatch(sender, receiver, packet);
+                        }
+                    }
+                    None => self.sniff(PacketEventKind::Dropped, &packet, packet.target),
+                }
+            }
+        }
+    }
+
+    fn dispatch(&mut self, sender: usize, receiver: usize, packet: NetPacket) {
+        let latency = self
+            .link_latency
+            .get(&(sender, receiver))
+            .copied()
+            .unwrap_or(self.latency);
+        self.in_flight
+            .entry(self.cycle + latency)
+            .or_default()
+            .push((receiver, packet));
+    }
+
+    /// Hand over the packets due this tick
+    fn arrive(&mut self) {
+        while let Some(entry) = self.in_flight.first_entry() {
+            if *entry.key() > self.cycle {
+                break;
+            }
+            for (receiver, packet) in entry.remove() {
+                self.deliver_to(receiver, packet);
+            }
+        }
+    }
+
+    fn deliver_to(&mut self, receiver: usize, mut packet: NetPacket) {
+        if self.corrupt_one_in != 0 && self.next_random().is_multiple_of(self.corrupt_one_in) {
+            self.corrupt(&mut packet);
+        }
+
+        let address = self.tpus[receiver].state().network_address;
+        if self.tpus[receiver].deliver_packet(packet) {
+            self.sniff(PacketEventKind::Delivered, &packet, address);
+        } else {
+            self.drops[receiver] += 1;
+            self.sniff(PacketEventKind::Dropped, &packet, address);
+        }
+    }
+
+    fn sniff(&mut self, kind: PacketEventKind, packet: &NetPacket, destination: u16) {
+        if self.observers.is_empty() {
+            return;
+        }
+        let event = PacketEvent {
+            cycle: self.cycle,
+            kind,
+            source: packet.sender,
+            destination,
+            port: packet.port,
+            payload: packet.words().to_vec(),
+        };
+        self.observers.notify(|observer| observer.on_packet(&event));
+    }
+
+    /// Flip one bit of the payload in use or of the checksum
+    fn corrupt(&mut self, packet: &mut NetPacket) {
+        let length = packet.words().len();
+        let word = self.next_random() as usize % (length + 1);
+        let bit = 1 << (self.next_random() % 16);
+        if word < length {
+            packet.payload[word] ^= bit;
+        } else {
+            packet.checksum ^= bit;
+        }
+        self.corrupted += 1;
+    }
+
+    /// Xorshift32
+    fn next_random(&mut self) -> u32 {
+        let mut x = self.corruption_rng;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.corruption_rng = x;
+        x
+    }
+}
@@ -0,0 +1,614 @@
+use crate::network::sniffer::{PacketEvent, PacketEventKind, PacketLog};
+use crate::network::{Network, NetworkRunResult};
+use crate::observer::{ObservedEvent, RecordingObserver};
+use crate::rgal::parse_program;
+use crate::shared::{AnalogPin, DigitalPin, NetPacket, Register};
+use crate::tpu::peripheral::detector::{Arrivals, DetectorSim};
+use crate::tpu::{TPU, TpuConfig};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use strum::EnumCount;
+
+    fn create_tpu(network_address: u16, program: &str, config: TpuConfig) -> TPU {
+        TPU::new_with_config(
+            network_address,
+            [false; AnalogPin::COUNT],
+            [false; DigitalPin::COUNT],
+            parse_program(program).expect("parse failure"),
+            config,
+        )
+    }
+
+    /// Sends 42 to address 2 until it's acknowledged, a damaged reply counts as a refusal
+    const RETRYING_SENDER: &str = r#"LDR A, 0x2
+    XMIT A, 42
+    RXAV R0
+    BEZ 2, R0
+    RECVV R1
+    BEZ 1, R1
+    BEQ 1, Y, 0
+    HLT"#;
+
+    /// Replies to every packet with whether it passed its checksum, storing the data of good ones at 0x30
+    const VERIFYING_RECEIVER: &str = r#"RXAV R0
+    BEZ 0, R0
+    RECVV R1
+    XMIT X, R1
+    BEZ 0, R1
+    STM 0x30, Y
+    JMP 0"#;
+
+    /// Holds green on pin 1 for 20 cycles, then extends it 5 cycles at a time while the detector on pin 0 calls, up to 10
+    /// extensions
+    const ACTUATED_GREEN: &str = r#"DPW 1, 1
+    LDR R0, 20
+    DJNZ 2, R0
+    LDR R1, 10
+    BPLW 8, 0
+    LDR R0, 5
+    DJNZ 6, R0
+    DJNZ 4, R1
+    DPW 1, 0
+    HLT"#;
+
+    /// Forwards everything it receives using the routing table at 0x40
+    const RELAY: &str = r#"RXAV R0
+    BEZ 0, R0
+    FWD 0x40
+    JMP 0"#;
+
+    /// Takes its address from the switches on pins 0 to 3, then waits for a packet
+    const SELF_ADDRESSING: &str = r#"DPRW R0
+    NETA R0
+    WRX
+    HLT"#;
+
+    fn run(network: &mut Network, ticks: usize) {
+        for _ in 0..ticks {
+            network.tick();
+        }
+    }
+
+    #[test]
+    fn test_unicast_delivery() {
+        let mut network = Network::new();
+        let sender = network.add_tpu(create_tpu(
+            0x1,
+            "LDR A, 0x2\nXMIT A, 42\nHLT",
+            TpuConfig::default(),
+        ));
+        let receiver = network.add_tpu(create_tpu(0x2, "WRX\nHLT", TpuConfig::default()));
+        let bystander = network.add_tpu(create_tpu(0x3, "HLT", TpuConfig::default()));
+
+        run(&mut network, 20);
+        assert!(network.tpu(receiver).halted());
+        assert_eq!(network.tpu(receiver).read_register(Register::X), 0x1);
+        assert_eq!(network.tpu(receiver).read_register(Register::Y), 42);
+        assert!(network.tpu(sender).state().outgoing_packets.is_empty());
+        assert!(network.tpu(bystander).state().incoming_packets.is_empty());
+    }
+
+    #[test]
+    fn test_broadcast() {
+        let mut network = Network::new();
+        let sender = network.add_tpu(create_tpu(
+            0x1,
+            "LDR A, 0xFFFF\nXMIT A, 7\nHLT",
+            TpuConfig::default(),
+        ));
+        let receivers: Vec<usize> = (0x2..0x5)
+            .map(|address| network.add_tpu(create_tpu(address, "WRX\nHLT", TpuConfig::default())))
+            .collect();
+        let full = network.add_tpu(create_tpu(0x5, "HLT", TpuConfig::default()));
+        for data in 0..TPU::NET_BUFFER_SIZE as u16 {
+            assert!(
+                network
+                    .tpu_mut(full)
+                    .deliver_packet(NetPacket::new(0x9, 0x5, data))
+            );
+        }
+
+        run(&mut network, 20);
+
+        // Every receiver got the same payload from one XMIT
+        for &receiver in &receivers {
+            let tpu = network.tpu(receiver);
+            assert!(tpu.halted());
+            assert_eq!(tpu.read_register(Register::X), 0x1);
+            assert_eq!(tpu.read_register(Register::Y), 7);
+            assert_eq!(network.drops(receiver), 0);
+        }
+
+        // The full receiver dropped it and the sender didn't hear itself
+        assert_eq!(network.drops(full), 1);
+        assert_eq!(
+            network.tpu(full).state().incoming_packets.len(),
+            TPU::NET_BUFFER_SIZE
+        );
+        assert!(network.tpu(sender).state().incoming_packets.is_empty());
+    }
+
+    #[test]
+    fn test_broadcast_disabled() {
+        // 0xFFFF is a normal address when the sender turns broadcast off
+        let config = TpuConfig {
+            broadcast: false,
+            ..TpuConfig::default()
+        };
+        let mut network = Network::new();
+        network.add_tpu(create_tpu(0x1, "LDR A, 0xFFFF\nXMIT A, 7\nHLT", config));
+        let other = network.add_tpu(create_tpu(0x2, "HLT", TpuConfig::default()));
+        let target = network.add_tpu(create_tpu(0xFFFF, "WRX\nHLT", TpuConfig::default()));
+
+        run(&mut network, 20);
+        assert!(network.tpu(other).state().incoming_packets.is_empty());
+        assert_eq!(network.tpu(target).read_register(Register::Y), 7);
+    }
+
+    #[test]
+    fn test_multi_word_round_trip() {
+        let payload = [0x1111, 0x2222, 0x3333, 0x4444];
+
+        for length in [0, 1, NetPacket::MAX_PAYLOAD] {
+            let mut network = Network::new();
+            let mut sender = create_tpu(
+                0x1,
+                &format!("LDR A, 0x2\nXMITM A, 0x10, {length}\nHLT"),
+                TpuConfig::default(),
+            );
+            sender.write_ram_slice(0x10, &payload);
+            let sender = network.add_tpu(sender);
+
+            // Waits for the packet without taking it, then stores it past a marker
+            let mut receiver = create_tpu(
+                0x2,
+                "RXAV R0\nBEZ 0, R0\nRECVM 0x20\nHLT",
+                TpuConfig::default(),
+            );
+            receiver.write_ram_slice(0x20, &[0xAAAA; NetPacket::MAX_PAYLOAD]);
+            let receiver = network.add_tpu(receiver);
+
+            run(&mut network, 60);
+            assert!(network.tpu(sender).halted());
+            assert!(network.tpu(receiver).halted());
+
+            let receiver = network.tpu(receiver);
+            assert_eq!(receiver.read_register(Register::X), 0x1);
+            assert_eq!(receiver.read_register(Register::Y), length as u16);
+            for (offset, word) in payload.iter().enumerate() {
+                let expected = if offset < length { *word } else { 0xAAAA };
+                assert_eq!(receiver.read_ram(0x20 + offset), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_checksum_verification() {
+        let run_receiver = |corrupt: bool| {
+            let mut network = Network::new();
+            if corrupt {
+                network.set_corruption(1, 7);
+            }
+            network.add_tpu(create_tpu(
+                0x1,
+                "LDR A, 0x2\nXMIT A, 42\nHLT",
+                TpuConfig::default(),
+            ));
+            let receiver = network.add_tpu(create_tpu(
+                0x2,
+                "RXAV R0\nBEZ 0, R0\nRECVV R1\nHLT",
+                TpuConfig::default(),
+            ));
+
+            run(&mut network, 40);
+            assert!(network.tpu(receiver).halted());
+            assert_eq!(network.corrupted(), corrupt as u64);
+            network.tpu(receiver).read_register(Register::R1)
+        };
+
+        assert_eq!(run_receiver(false), 1);
+        assert_eq!(run_receiver(true), 0);
+    }
+
+    #[test]
+    fn test_retry_over_corrupting_link() {
+        let mut network = Network::new();
+        network.set_corruption(2, 12345);
+        let sender = network.add_tpu(create_tpu(0x1, RETRYING_SENDER, TpuConfig::default()));
+        let receiver = network.add_tpu(create_tpu(0x2, VERIFYING_RECEIVER, TpuConfig::default()));
+
+        for _ in 0..2000 {
+            if network.tpu(sender).halted() {
+                break;
+            }
+            network.tick();
+        }
+
+        assert!(network.tpu(sender).halted());
+        assert!(network.corrupted() > 0);
+        assert_eq!(network.tpu(receiver).read_ram(0x30), 42);
+    }
+
+    #[test]
+    fn test_latency_round_trip() {
+        // Ticks until the pinging TPU has its reply back
+        let round_trip = |latency: u64, link: Option<u64>| {
+            let mut network = Network::new();
+            network.set_latency(latency);
+            let ping = network.add_tpu(create_tpu(
+                0x1,
+                "LDR A, 0x2\nXMIT A, 7\nWRX\nHLT",
+                TpuConfig::default(),
+            ));
+            let pong =
+                network.add_tpu(create_tpu(0x2, "WRX\nXMIT X, Y\nHLT", TpuConfig::default()));
+            if let Some(cycles) = link {
+                network.set_link_latency(pong, ping, cycles);
+            }
+
+            while !network.tpu(ping).halted() {
+                network.tick();
+                assert!(network.cycle() < 1000);
+            }
+            assert_eq!(network.tpu(ping).read_register(Register::Y), 7);
+            network.cycle()
+        };
+
+        let overhead = round_trip(0, None);
+        assert_eq!(round_trip(5, None), overhead + 2 * 5);
+        assert_eq!(round_trip(3, Some(10)), overhead + 3 + 10);
+
+        // Identical runs are identical
+        assert_eq!(round_trip(5, None), round_trip(5, None));
+    }
+
+    #[test]
+    fn test_relay_chain() {
+        // 0x1 <-> 0x2 <-> 0x3, the first word of the payload is the final target
+        let mut network = Network::new();
+        let mut origin = create_tpu(
+            0x1,
+            "LDR A, 0x2\nXMITM A, 0x10, 2\nHLT",
+            TpuConfig::default(),
+        );
+        origin.write_ram_slice(0x10, &[0x3, 42]);
+        network.add_tpu(origin);
+        let mut relay = create_tpu(0x2, RELAY, TpuConfig::default());
+        relay.write_ram_slice(0x40 + 0x3, &[0x3]);
+        let relay = network.add_tpu(relay);
+        let destination = network.add_tpu(create_tpu(
+            0x3,
+            "RXAV R0\nBEZ 0, R0\nRECVM 0x20\nHLT",
+            TpuConfig::default(),
+        ));
+
+        run(&mut network, 100);
+        let destination = network.tpu(destination);
+        assert!(destination.halted());
+        assert_eq!(destination.read_register(Register::X), 0x1);
+        assert_eq!(destination.read_ram(0x20), 0x3);
+        assert_eq!(destination.read_ram(0x21), 42);
+        assert_eq!(network.tpu(relay).ttl_drops(), 0);
+    }
+
+    #[test]
+    fn test_relay_loop_runs_out_of_hops() {
+        // The relays route 0x9 to each other, so the packet bounces until its hop limit runs out
+        let mut network = Network::new();
+        let mut origin = create_tpu(
+            0x1,
+            "LDR A, 0x2\nXMITM A, 0x10, 1\nHLT",
+            TpuConfig::default(),
+        );
+        origin.write_ram_slice(0x10, &[0x9]);
+        network.add_tpu(origin);
+        let relays: Vec<usize> = [(0x2, 0x3), (0x3, 0x2)]
+            .into_iter()
+            .map(|(address, next_hop)| {
+                let mut relay = create_tpu(address, RELAY, TpuConfig::default());
+                relay.write_ram_slice(0x40 + 0x9, &[next_hop]);
+                network.add_tpu(relay)
+            })
+            .collect();
+
+        run(&mut network, 1000);
+        let drops: u64 = relays
+            .iter()
+            .map(|&relay| network.tpu(relay).ttl_drops())
+            .sum();
+        assert_eq!(drops, 1);
+        for &relay in &relays {
+            assert!(network.tpu(relay).state().incoming_packets.is_empty());
+            assert!(network.tpu(relay).state().outgoing_packets.is_empty());
+        }
+    }
+
+    /// Cycles the actuated fixture holds green for, running on a network with the detector feeding pin 0
+    fn actuated_green(arrivals: Arrivals) -> u64 {
+        let mut digital_config = [false; DigitalPin::COUNT];
+        digital_config[DigitalPin::Digital0 as usize] = true;
+        let mut tpu = TPU::new(
+            0x1,
+            [false; AnalogPin::COUNT],
+            digital_config,
+            parse_program(ACTUATED_GREEN).expect("parse failure"),
+        );
+        tpu.attach_peripheral(Box::new(DetectorSim::new(
+            vec![DigitalPin::Digital0],
+            arrivals,
+            10..=12,
+            99,
+        )));
+        let mut network = Network::new();
+        let index = network.add_tpu(tpu);
+
+        let (mut green_from, mut green_until) = (None, None);
+        while !network.tpu(index).halted() {
+            network.tick();
+            let green = network.tpu(index).state().digital_pins[DigitalPin::Digital1 as usize];
+            if green && green_from.is_none() {
+                green_from = Some(network.cycle());
+            }
+            if !green && green_from.is_some() && green_until.is_none() {
+                green_until = Some(network.cycle());
+            }
+        }
+        green_until.expect("green never ended") - green_from.expect("green never started")
+    }
+
+    #[test]
+    fn test_detector_sim_extends_green() {
+        let no_demand = actuated_green(Arrivals::Schedule(Vec::new()));
+        // Arrivals at most 9 cycles apart, each present for at least 10, never leave a gap
+        let continuous = actuated_green(Arrivals::Periodic {
+            period: 5,
+            jitter: 2,
+        });
+
+        // Every extension is used, each 5 cycles or so of DJNZ plus the demand check
+        assert!(
+            continuous >= no_demand + 10 * 5,
+            "{continuous} vs {no_demand}"
+        );
+        assert_eq!(
+            continuous,
+            actuated_green(Arrivals::Periodic {
+                period: 5,
+                jitter: 2,
+            })
+        );
+    }
+
+    #[test]
+    fn test_sniffer_ping_pong() {
+        let mut network = Network::new();
+        network.set_latency(3);
+        let log = PacketLog::new();
+        network.set_sniffer(log.sniffer());
+        let recorder = RecordingObserver::new();
+        network.add_observer(recorder.clone());
+        let ping = network.add_tpu(create_tpu(
+            0x1,
+            "LDR A, 0x2\nXMIT A, 7\nWRX\nHLT",
+            TpuConfig::default(),
+        ));
+        network.add_tpu(create_tpu(0x2, "WRX\nXMIT X, Y\nHLT", TpuConfig::default()));
+        while !network.tpu(ping).halted() {
+            network.tick();
+        }
+
+        let event = |cycle, kind, source, destination| PacketEvent {
+            cycle,
+            kind,
+            source,
+            destination,
+            port: 0,
+            payload: vec![7],
+        };
+        assert_eq!(
+            log.events(),
+            [
+                event(11, PacketEventKind::Sent, 0x1, 0x2),
+                event(14, PacketEventKind::Delivered, 0x1, 0x2),
+                event(25, PacketEventKind::Sent, 0x2, 0x1),
+                event(28, PacketEventKind::Delivered, 0x2, 0x1),
+            ]
+        );
+        // Observers see the same packets, and a tick for each of the network's
+        let events = recorder.events();
+        let packets: Vec<_> = events
+            .iter()
+            .filter_map(|event| match event {
+                ObservedEvent::Packet(packet) => Some(packet.clone()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(packets, log.events());
+        assert_eq!(events.len() - packets.len(), network.cycle() as usize);
+        assert_eq!(events.last(), Some(&ObservedEvent::Tick(network.cycle())));
+        assert_eq!(
+            log.to_text().lines().next(),
+            Some("      11 Sent      0x0001 -> 0x0002:0 [7]")
+        );
+        #[cfg(feature = "serde")]
+        assert!(log.to_json().unwrap().starts_with(
+            r#"[{"cycle":11,"kind":"Sent","source":1,"destination":2,"port":0,"payload":[7]},"#
+        ));
+    }
+
+    #[test]
+    fn test_sniffer_drops() {
+        // Floods a TPU that never reads, then sends to an address nobody has
+        let flood = format!(
+            "LDR A, 0x2\n{}LDR A, 0x9\nXMIT A, 99\nHLT",
+            "XMIT A, 1\n".repeat(TPU::NET_BUFFER_SIZE + 2)
+        );
+        let mut network = Network::new();
+        let log = PacketLog::new();
+        network.set_sniffer(log.sniffer());
+        let sender = network.add_tpu(create_tpu(0x1, &flood, TpuConfig::default()));
+        let receiver = network.add_tpu(create_tpu(0x2, "HLT", TpuConfig::default()));
+        while !network.tpu(sender).halted() {
+            network.tick();
+        }
+        network.tick();
+
+        let events = log.events();
+        let kinds = |kind| events.iter().filter(|event| event.kind == kind).count();
+        assert_eq!(kinds(PacketEventKind::Sent), TPU::NET_BUFFER_SIZE + 3);
+        assert_eq!(kinds(PacketEventKind::Delivered), TPU::NET_BUFFER_SIZE);
+        assert_eq!(kinds(PacketEventKind::Dropped), 3);
+        assert_eq!(network.drops(receiver), 2);
+
+        // Every drop comes straight after the send it belongs to, in the order the network handled them
+        let dropped: Vec<(u16, Vec<u16>)> = events
+            .windows(2)
+            .filter(|pair| pair[1].kind == PacketEventKind::Dropped)
+            .map(|pair| {
+                assert_eq!(pair[0].kind, PacketEventKind::Sent);
+                assert_eq!(pair[0].cycle, pair[1].cycle);
+                (pair[1].destination, pair[1].payload.clone())
+            })
+            .collect();
+        assert_eq!(dropped, [(0x2, vec![1]), (0x2, vec![1]), (0x9, vec![99])]);
+        assert!(events.windows(2).all(|pair| pair[0].cycle <= pair[1].cycle));
+    }
+
+    #[test]
+    fn test_neta_self_addressing() {
+        // The same image on two TPUs, both set to 0x5, and a third set to 0x6
+        let switched = |switches: &[DigitalPin]| {
+            let mut digital_config = [false; DigitalPin::COUNT];
+            digital_config[..4].fill(true);
+            let mut tpu = TPU::new(
+                0x0,
+                [false; AnalogPin::COUNT],
+                digital_config,
+                parse_program(SELF_ADDRESSING).expect("parse failure"),
+            );
+            for &pin in switches {
+                tpu.drive_digital_input(pin, true).unwrap();
+            }
+            tpu
+        };
+        let five = [DigitalPin::Digital0, DigitalPin::Digital2];
+        let six = [DigitalPin::Digital1, DigitalPin::Digital2];
+
+        let mut network = Network::new();
+        let sender = network.add_tpu(create_tpu(
+            0x1,
+            "LDR R0, 30\nDJNZ 1, R0\nLDR A, 0x5\nXMIT A, 42\nLDR A, 0x6\nXMIT A, 43\nHLT",
+            TpuConfig::default(),
+        ));
+        let first = network.add_tpu(switched(&five));
+        let second = network.add_tpu(switched(&five));
+        let other = network.add_tpu(switched(&six));
+        run(&mut network, 500);
+
+        assert!(network.tpu(sender).halted());
+        assert_eq!(network.tpu(first).state().network_address, 0x5);
+        assert!(network.tpu(first).halted());
+        assert_eq!(network.tpu(first).read_register(Register::X), 0x1);
+        assert_eq!(network.tpu(first).read_register(Register::Y), 42);
+        assert_eq!(network.tpu(other).state().network_address, 0x6);
+        assert!(network.tpu(other).halted());
+        assert_eq!(network.tpu(other).read_register(Register::Y), 43);
+
+        // 0x5 was taken, so the second TPU keeps its old address and never hears anything
+        assert_eq!(network.tpu(second).state().network_address, 0x0);
+        assert!(!network.tpu(second).halted());
+        assert!(network.tpu(second).state().incoming_packets.is_empty());
+        let collisions = network.address_collisions();
+        assert_eq!(collisions.len(), 1);
+        assert_eq!((collisions[0].tpu, collisions[0].address), (second, 0x5));
+        // Refused while the sender was still waiting
+        assert!(collisions[0].cycle < 30);
+    }
+
+    #[test]
+    fn test_ports_interleaved() {
+        let mut network = Network::new();
+        let log = PacketLog::new();
+        network.set_sniffer(log.sniffer());
+        // Telemetry on port 2 and control on port 1, sent interleaved
+        network.add_tpu(create_tpu(
+            0x1,
+            "LDR A, 0x2\nXMITP A, 2, 100\nXMITP A, 1, 7\nXMITP A, 2, 101\nXMITP A, 1, 8\nHLT",
+            TpuConfig::default(),
+        ));
+        // Waits for everything, then handles all the control messages before any telemetry
+        let receiver = network.add_tpu(create_tpu(
+            0x2,
+            r#"LDR R0, 100
+            DJNZ 1, R0
+            RECVP 1
+            STM 0x20, Y
+            RECVP 1
+            STM 0x21, Y
+            RECVP 1
+            STM 0x22, X
+            RECVP 2
+            STM 0x30, Y
+            RECVP 2
+            STM 0x31, Y
+            RXAV R1
+            HLT"#,
+            TpuConfig::default(),
+        ));
+        while !network.tpu(receiver).halted() {
+            network.tick();
+        }
+
+        let receiver = network.tpu(receiver);
+        assert_eq!(receiver.read_ram(0x20), 7);
+        assert_eq!(receiver.read_ram(0x21), 8);
+        assert_eq!(receiver.read_ram(0x22), TPU::BROADCAST_ADDRESS);
+        assert_eq!(receiver.read_ram(0x30), 100);
+        assert_eq!(receiver.read_ram(0x31), 101);
+        assert_eq!(receiver.read_register(Register::R1), 0);
+
+        let delivered: Vec<(u8, Vec<u16>)> = log
+            .events()
+            .into_iter()
+            .filter(|event| event.kind == PacketEventKind::Delivered)
+            .map(|event| (event.port, event.payload))
+            .collect();
+        assert_eq!(
+            delivered,
+            [(2, vec![100]), (1, vec![7]), (2, vec![101]), (1, vec![8])]
+        );
+    }
+
+    #[test]
+    fn test_run_to_breakpoint() {
+        let mut network = Network::new();
+        let sender = network.add_tpu(create_tpu(
+            0x1,
+            "LDR A, 0x2\nXMIT A, 42\nHLT",
+            TpuConfig::default(),
+        ));
+        let receiver = network.add_tpu(create_tpu(0x2, "WRX\nNOP\nHLT", TpuConfig::default()));
+        network.tpu_mut(receiver).toggle_breakpoint(1);
+
+        assert_eq!(network.run(1), NetworkRunResult::OutOfTicks);
+        // The sender halting doesn't stop the receiver, which stops once the packet's arrived
+        assert_eq!(
+            network.run(100),
+            NetworkRunResult::Breakpoint {
+                tpu: receiver,
+                address: 1
+            }
+        );
+        assert!(network.tpu(sender).halted());
+        assert_eq!(network.tpu(receiver).read_register(Register::Y), 42);
+
+        // Running again carries on past it
+        assert_eq!(network.run(100), NetworkRunResult::AllHalted);
+        assert!(network.all_halted());
+        let cycle = network.cycle();
+        assert_eq!(network.run(100), NetworkRunResult::AllHalted);
+        assert_eq!(network.cycle(), cycle);
+    }
+}
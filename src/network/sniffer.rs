@@ -0,0 +1,92 @@
+use alloc::format;
+use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::fmt;
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+/// What happened to a packet on the network
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub enum PacketEventKind {
+    /// A TPU sent it, before it's copied to its receivers
+    Sent,
+    /// A receiver took it into its incoming buffer
+    Delivered,
+    /// The receiver's buffer was full, or no TPU had the address it was sent to
+    Dropped,
+}
+
+/// A packet seen by the network, see `Network::set_sniffer`
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct PacketEvent {
+    /// The network's cycle when it happened
+    pub cycle: u64,
+    pub kind: PacketEventKind,
+    /// Address of the sender
+    pub source: u16,
+    /// Address the packet was sent to, or of the TPU it was delivered to or dropped by
+    pub destination: u16,
+    pub port: u8,
+    /// The payload in use, as it was when it happened, so a damaged packet shows the damage on delivery
+    pub payload: Vec<u16>,
+}
+
+impl fmt::Display for PacketEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:>8} {:<9} {:#06X} -> {:#06X}:{} {:?}",
+            self.cycle,
+            format!("{:?}", self.kind),
+            self.source,
+            self.destination,
+            self.port,
+            self.payload
+        )
+    }
+}
+
+/// Collects every packet event on a network, oldest first.
+///
+/// A clone shares the same log, so the host keeps one and hands `PacketLog::sniffer` to the network.
+#[derive(Clone, Debug, Default)]
+pub struct PacketLog(Rc<RefCell<Vec<PacketEvent>>>);
+
+impl PacketLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A hook for `Network::set_sniffer` that records into this log
+    pub fn sniffer(&self) -> impl FnMut(&PacketEvent) + 'static {
+        let log = self.clone();
+        move |event| log.0.borrow_mut().push(event.clone())
+    }
+
+    pub fn events(&self) -> Vec<PacketEvent> {
+        self.0.borrow().clone()
+    }
+
+    pub fn clear(&self) {
+        self.0.borrow_mut().clear();
+    }
+
+    /// One event per line
+    pub fn to_text(&self) -> String {
+        self.0
+            .borrow()
+            .iter()
+            .map(|event| format!("{event}\n"))
+            .collect()
+    }
+
+    /// The events as a JSON array
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(&*self.0.borrow())
+    }
+}
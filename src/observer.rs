@@ -0,0 +1,166 @@
+use crate::network::sniffer::PacketEvent;
+use crate::shared::HaltReason;
+use crate::tpu::{PinChange, TraceEvent};
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+
+/// Told about what happens as a TPU or a network runs, see `TPU::add_observer` and `Network::add_observer`.
+///
+/// Every method does nothing unless it's overridden, so an observer only implements the events it wants. A TPU
+/// reports its ticks, instructions, halts and pin changes, a network reports its ticks and packets. In a tick the
+/// events come in the order they happened, with `on_tick` last.
+pub trait Observer {
+    /// A tick has finished, `cycle` is the cycle count after it
+    fn on_tick(&mut self, _cycle: u64) {}
+
+    /// An instruction has finished, including one that halted the TPU. One taking several cycles is reported once, on
+    /// its last.
+    fn on_instruction_retired(&mut self, _event: &TraceEvent) {}
+
+    /// The TPU has halted, on cycle `cycle`
+    fn on_halt(&mut self, _cycle: u64, _reason: HaltReason) {}
+
+    /// An output pin has changed value, see `TPU::set_pin_callback`
+    fn on_pin_change(&mut self, _change: PinChange) {}
+
+    /// A packet was sent, delivered or dropped by the network
+    fn on_packet(&mut self, _event: &PacketEvent) {}
+}
+
+/// An observer that ignores everything, a placeholder where one is needed
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NullObserver;
+
+impl Observer for NullObserver {}
+
+/// An event seen by a `RecordingObserver`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ObservedEvent {
+    Tick(u64),
+    InstructionRetired(TraceEvent),
+    Halt { cycle: u64, reason: HaltReason },
+    PinChange(PinChange),
+    Packet(PacketEvent),
+}
+
+/// Keeps every event it's told about, oldest first.
+///
+/// A clone shares the same events, so the host keeps one and hands a clone to `TPU::add_observer`.
+#[derive(Clone, Debug, Default)]
+pub struct RecordingObserver(Rc<RefCell<Vec<ObservedEvent>>>);
+
+impl RecordingObserver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn events(&self) -> Vec<ObservedEvent> {
+        self.0.borrow().clone()
+    }
+
+    /// Take the events recorded so far, leaving none
+    pub fn take_events(&self) -> Vec<ObservedEvent> {
+        core::mem::take(&mut self.0.borrow_mut())
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.borrow().is_empty()
+    }
+
+    fn push(&self, event: ObservedEvent) {
+        self.0.borrow_mut().push(event);
+    }
+}
+
+impl Observer for RecordingObserver {
+    fn on_tick(&mut self, cycle: u64) {
+        self.push(ObservedEvent::Tick(cycle));
+    }
+
+    fn on_instruction_retired(&mut self, event: &TraceEvent) {
+        self.push(ObservedEvent::InstructionRetired(event.clone()));
+    }
+
+    fn on_halt(&mut self, cycle: u64, reason: HaltReason) {
+        self.push(ObservedEvent::Halt { cycle, reason });
+    }
+
+    fn on_pin_change(&mut self, change: PinChange) {
+        self.push(ObservedEvent::PinChange(change));
+    }
+
+    fn on_packet(&mut self, event: &PacketEvent) {
+        self.push(ObservedEvent::Packet(event.clone()));
+    }
+}
+
+/// Names an observer so it can be removed, see `TPU::remove_observer`
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct ObserverId(u64);
+
+/// The observers of a TPU or a network. A clone shares them, like a cloned TPU shares its pin callback.
+#[derive(Clone, Default)]
+pub(crate) struct Observers {
+    next_id: u64,
+    observers: Vec<(ObserverId, Rc<RefCell<dyn Observer>>)>,
+}
+
+impl Observers {
+    pub(crate) fn add(&mut self, observer: impl Observer + 'static) -> ObserverId {
+        let id = ObserverId(self.next_id);
+        self.next_id += 1;
+        self.observers.push((id, Rc::new(RefCell::new(observer))));
+        id
+    }
+
+    /// Returns whether there was an observer with `id`
+    pub(crate) fn remove(&mut self, id: ObserverId) -> bool {
+        let len = self.observers.len();
+        self.observers.retain(|(observer, _)| *observer != id);
+        self.observers.len() != len
+    }
+
+    /// Checked before building an event, so nothing is done for one nobody is watching
+    pub(crate) fn is_empty(&self) -> bool {
+        self.observers.is_empty()
+    }
+
+    /// Tell each observer, in the order they were added
+    pub(crate) fn notify(&self, mut event: impl FnMut(&mut dyn Observer)) {
+        for (_, observer) in &self.observers {
+            event(&mut *observer.borrow_mut());
+        }
+    }
+}
+
+/// `TPU::set_pin_callback` as an observer
+pub(crate) struct PinCallback<F>(pub(crate) F);
+
+impl<F: FnMut(PinChange)> Observer for PinCallback<F> {
+    fn on_pin_change(&mut self, change: PinChange) {
+        (self.0)(change);
+    }
+}
+
+/// `TPU::set_trace_callback` as an observer
+pub(crate) struct TraceCallback<F>(pub(crate) F);
+
+impl<F: FnMut(&TraceEvent)> Observer for TraceCallback<F> {
+    fn on_instruction_retired(&mut self, event: &TraceEvent) {
+        (self.0)(event);
+    }
+}
+
+/// `Network::set_sniffer` as an observer
+pub(crate) struct Sniffer<F>(pub(crate) F);
+
+impl<F: FnMut(&PacketEvent)> Observer for Sniffer<F> {
+    fn on_packet(&mut self, event: &PacketEvent) {
+        (self.0)(event);
+    }
+}
@@ -0,0 +1,88 @@
+//! The number of each instruction, for anything storing or sending a program as numbers rather than RGAL.
+//!
+//! Every `Instruction` variant has its opcode written next to it as `#[opcode(..)]`, so reordering the enum changes
+//! nothing. Opcodes are append-only: a new instruction takes the next unused opcode, and an opcode is never changed
+//! or given to another instruction, even after the one it named is removed. 0x0000 is never used, so zeroed memory
+//! isn't mistaken for an instruction.
+//!
+//! An instruction is its opcode and its operands, which round-trip:
+//!
+//! ```
+//! use tls::shared::{Instruction, OperandValueType, Register};
+//!
+//! let instruction = Instruction::LDR(Register::A, OperandValueType::Immediate(7));
+//! let decoded = Instruction::from_opcode(instruction.opcode(), &instruction.operands());
+//! assert_eq!(decoded, Ok(instruction));
+//! ```
+
+#[cfg(test)]
+mod opcode_test;
+
+use core::fmt;
+
+/// What an operand of an instruction holds
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OperandKind {
+    /// Only a register, given as `OperandValueType::Register`
+    Register,
+    /// A register or an immediate value
+    Value,
+}
+
+/// An entry of `Instruction::OPCODES`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct OpcodeInfo {
+    pub opcode: u16,
+    pub mnemonic: &'static str,
+    pub operands: &'static [OperandKind],
+}
+
+impl OpcodeInfo {
+    /// The entry for `opcode`, if an instruction has it
+    pub fn find(opcode: u16) -> Option<&'static OpcodeInfo> {
+        crate::shared::Instruction::OPCODES
+            .iter()
+            .find(|info| info.opcode == opcode)
+    }
+}
+
+/// Why `Instruction::from_opcode` couldn't make an instruction
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OpcodeError {
+    /// No instruction has the opcode
+    UnknownOpcode(u16),
+    /// The instruction takes a different number of operands
+    OperandCount {
+        opcode: u16,
+        expected: usize,
+        found: usize,
+    },
+    /// The operand at `index` must be a register but is an immediate value
+    ExpectedRegister { opcode: u16, index: usize },
+}
+
+impl fmt::Display for OpcodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OpcodeError::UnknownOpcode(opcode) => {
+                write!(f, "no instruction has opcode {opcode:#06X}")
+            }
+            OpcodeError::OperandCount {
+                opcode,
+                expected,
+                found,
+            } => write!(
+                f,
+                "opcode {opcode:#06X} takes {expected} operands, not {found}"
+            ),
+            OpcodeError::ExpectedRegister { opcode, index } => {
+                write!(
+                    f,
+                    "operand {index} of opcode {opcode:#06X} must be a register"
+                )
+            }
+        }
+    }
+}
+
+impl core::error::Error for OpcodeError {}
@@ -0,0 +1,255 @@
+use super::*;
+use crate::rgal::parse_instruction;
+use crate::shared::{Instruction, OperandValueType, Register};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every opcode given out so far. Opcodes are append-only, so this list only ever grows at the end, an entry is
+    /// never changed or removed.
+    const GOLDEN: &[(&str, u16)] = &[
+        ("PUSH", 0x0001),
+        ("POP", 0x0002),
+        ("PEEK", 0x0003),
+        ("SCR", 0x0004),
+        ("RSP", 0x0005),
+        ("ENTER", 0x0006),
+        ("LEAVE", 0x0007),
+        ("PEEKF", 0x0008),
+        ("XMIT", 0x0009),
+        ("RECV", 0x000A),
+        ("XMITM", 0x000B),
+        ("XMITP", 0x000C),
+        ("RECVP", 0x000D),
+        ("UTX", 0x000E),
+        ("URX", 0x000F),
+        ("FWD", 0x0010),
+        ("RECVV", 0x0011),
+        ("RECVM", 0x0012),
+        ("TXBS", 0x0013),
+        ("RXBS", 0x0014),
+        ("TXOK", 0x0015),
+        ("RXAV", 0x0016),
+        ("NETR", 0x0017),
+        ("NETA", 0x0018),
+        ("ADD", 0x0019),
+        ("SUB", 0x001A),
+        ("MUL", 0x001B),
+        ("DIV", 0x001C),
+        ("MOD", 0x001D),
+        ("AND", 0x001E),
+        ("OR", 0x001F),
+        ("XOR", 0x0020),
+        ("NOT", 0x0021),
+        ("INC", 0x0022),
+        ("DEC", 0x0023),
+        ("CMP", 0x0024),
+        ("SLL", 0x0025),
+        ("SLC", 0x0026),
+        ("SLR", 0x0027),
+        ("SRC", 0x0028),
+        ("ROL", 0x0029),
+        ("ROR", 0x002A),
+        ("RCY", 0x002B),
+        ("RMV", 0x002C),
+        ("CMOVZ", 0x002D),
+        ("CMOVN", 0x002E),
+        ("LDR", 0x002F),
+        ("LDM", 0x0030),
+        ("LDO", 0x0031),
+        ("LDOI", 0x0032),
+        ("STM", 0x0033),
+        ("LDB", 0x0034),
+        ("STB", 0x0035),
+        ("SDB", 0x0036),
+        ("LDRP", 0x0037),
+        ("STRP", 0x0038),
+        ("CRC", 0x0039),
+        ("STMO", 0x003A),
+        ("SMOI", 0x003B),
+        ("LDOS", 0x003C),
+        ("SMOS", 0x003D),
+        ("NVL", 0x003E),
+        ("NVS", 0x003F),
+        ("DPW", 0x0040),
+        ("DPR", 0x0041),
+        ("DPWW", 0x0042),
+        ("DPRW", 0x0043),
+        ("PEVC", 0x0044),
+        ("PEVR", 0x0045),
+        ("PCNTR", 0x0046),
+        ("PCNTC", 0x0047),
+        ("DCFG", 0x0048),
+        ("DCFR", 0x0049),
+        ("PWMC", 0x004A),
+        ("PWMD", 0x004B),
+        ("PWMS", 0x004C),
+        ("APW", 0x004D),
+        ("CMPT", 0x004E),
+        ("ACFG", 0x004F),
+        ("ACFR", 0x0050),
+        ("APRW", 0x0051),
+        ("APWA", 0x0052),
+        ("APRA", 0x0053),
+        ("APR", 0x0054),
+        ("NOP", 0x0055),
+        ("SLP", 0x0056),
+        ("WRX", 0x0057),
+        ("WRXT", 0x0058),
+        ("HLT", 0x0059),
+        ("RST", 0x005A),
+        ("JMP", 0x005B),
+        ("BEZ", 0x005C),
+        ("BNZ", 0x005D),
+        ("BEQ", 0x005E),
+        ("BNE", 0x005F),
+        ("BGE", 0x0060),
+        ("BLE", 0x0061),
+        ("BGT", 0x0062),
+        ("BLT", 0x0063),
+        ("DJNZ", 0x0064),
+        ("JTB", 0x0065),
+        ("JTBN", 0x0066),
+        ("JSRT", 0x0067),
+        ("BPH", 0x0068),
+        ("BPLW", 0x0069),
+        ("LOOPS", 0x006A),
+        ("SEZ", 0x006B),
+        ("SNZ", 0x006C),
+        ("BCS", 0x006D),
+        ("BCC", 0x006E),
+        ("BMI", 0x006F),
+        ("BPL", 0x0070),
+        ("BZS", 0x0071),
+        ("BZC", 0x0072),
+        ("JPR", 0x0073),
+        ("BREZ", 0x0074),
+        ("BRNZ", 0x0075),
+        ("BREQ", 0x0076),
+        ("BRNE", 0x0077),
+        ("BRGE", 0x0078),
+        ("BRLE", 0x0079),
+        ("BRGT", 0x007A),
+        ("BRLT", 0x007B),
+        ("JSR", 0x007C),
+        ("RTS", 0x007D),
+        ("SWI", 0x007E),
+        ("SJMP", 0x007F),
+        ("SRET", 0x0080),
+    ];
+
+    /// Operands of the right kinds for `info`, different at each position
+    fn sample_operands(info: &OpcodeInfo) -> Vec<OperandValueType> {
+        info.operands
+            .iter()
+            .enumerate()
+            .map(|(index, kind)| match kind {
+                OperandKind::Register => OperandValueType::Register(Register::R3),
+                OperandKind::Value if index % 2 == 0 => {
+                    OperandValueType::Immediate(0x12 + index as u16)
+                }
+                OperandKind::Value => OperandValueType::Register(Register::Y),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_opcodes_unique() {
+        let mut opcodes: Vec<u16> = Instruction::OPCODES
+            .iter()
+            .map(|info| info.opcode)
+            .collect();
+        let mut mnemonics: Vec<&str> = Instruction::OPCODES
+            .iter()
+            .map(|info| info.mnemonic)
+            .collect();
+        opcodes.sort();
+        opcodes.dedup();
+        mnemonics.sort();
+        mnemonics.dedup();
+        assert_eq!(opcodes.len(), Instruction::OPCODES.len());
+        assert_eq!(mnemonics.len(), Instruction::OPCODES.len());
+        assert!(!opcodes.contains(&0));
+    }
+
+    #[test]
+    fn test_opcodes_golden() {
+        // Nothing given out has changed
+        for &(mnemonic, opcode) in GOLDEN {
+            let info = OpcodeInfo::find(opcode)
+                .unwrap_or_else(|| panic!("{mnemonic} lost opcode {opcode:#06X}"));
+            assert_eq!(
+                info.mnemonic, mnemonic,
+                "opcode {opcode:#06X} changed instruction"
+            );
+        }
+
+        // And anything new was added past the end
+        let last = GOLDEN.iter().map(|&(_, opcode)| opcode).max().unwrap();
+        for info in Instruction::OPCODES {
+            if !GOLDEN.contains(&(info.mnemonic, info.opcode)) {
+                assert!(
+                    info.opcode > last,
+                    "{} reuses opcode {:#06X}",
+                    info.mnemonic,
+                    info.opcode
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_opcode_round_trip() {
+        for info in Instruction::OPCODES {
+            let operands = sample_operands(info);
+            let instruction = Instruction::from_opcode(info.opcode, &operands).unwrap();
+            assert_eq!(instruction.opcode(), info.opcode);
+            assert_eq!(instruction.operands(), operands);
+
+            // The mnemonic and operands are the ones RGAL uses. Immediates are shown in hex without a prefix, so the
+            // text parses back to the same instruction but not always the same values.
+            let text = instruction.to_string();
+            assert_eq!(text.split(' ').next(), Some(info.mnemonic));
+            assert_eq!(
+                parse_instruction(&text).unwrap().opcode(),
+                info.opcode,
+                "{text}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_from_opcode_errors() {
+        assert_eq!(
+            Instruction::from_opcode(0, &[]),
+            Err(OpcodeError::UnknownOpcode(0))
+        );
+        let ldr = Instruction::LDR(Register::A, OperandValueType::Immediate(1)).opcode();
+        assert_eq!(
+            Instruction::from_opcode(ldr, &[OperandValueType::Immediate(1)]),
+            Err(OpcodeError::OperandCount {
+                opcode: ldr,
+                expected: 2,
+                found: 1
+            })
+        );
+        assert_eq!(
+            Instruction::from_opcode(
+                ldr,
+                &[
+                    OperandValueType::Immediate(1),
+                    OperandValueType::Immediate(1)
+                ]
+            ),
+            Err(OpcodeError::ExpectedRegister {
+                opcode: ldr,
+                index: 0
+            })
+        );
+        assert_eq!(
+            Instruction::from_opcode(Instruction::HLT.opcode(), &[]),
+            Ok(Instruction::HLT)
+        );
+    }
+}
@@ -0,0 +1,13 @@
+//! The types and functions needed to assemble a program and run it on a TPU, or a network of them
+
+pub use crate::network::{Network, NetworkRunResult};
+pub use crate::observer::Observer;
+#[cfg(feature = "std")]
+pub use crate::rgal::{
+    SymbolResolver, parse_program, parse_program_from_file, parse_program_with_symbols,
+};
+pub use crate::shared::{AnalogPin, DigitalPin, HaltReason, Instruction, NetPacket, Register};
+pub use crate::tpu::{
+    ConfigError, Expr, PinMode, RunResult, TPU, TpuBuilder, TpuConfig, TpuSnapshot, TpuState,
+    TraceEvent, create_basic_tpu_config,
+};
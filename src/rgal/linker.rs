@@ -0,0 +1,115 @@
+use crate::Error;
+use crate::rgal::parse_program;
+use crate::shared::{Instruction, OperandValueType};
+use std::rc::Rc;
+
+/// Link several RGAL modules into a single program.
+///
+/// Each module is given its own RAM window of `data_window` words, module 0 owns the first window,
+/// module 1 the next and so on. An `SDB` prologue setting the module's Data Base is placed in front of
+/// each module, so `LDRP` and `STRP` access that module's window once execution enters it from the top.
+///
+/// Line numbers in each module are relative to its own first line. Absolute branch targets given as
+/// immediate values are moved to where the module ends up, targets held in registers are left alone.
+pub fn link_modules(modules: &[&str], data_window: u16) -> crate::Result<Vec<Rc<Instruction>>> {
+    let mut program = Vec::new();
+
+    for (index, module) in modules.iter().enumerate() {
+        let data_base = (index as u16).saturating_mul(data_window);
+        program.push(Rc::new(Instruction::SDB(OperandValueType::Immediate(
+            data_base,
+        ))));
+
+        // The module starts after its prologue, which has to be somewhere a branch can reach
+        let start = u16::try_from(program.len()).map_err(|_| {
+            Error::InvalidProgram(format!("module {index} starts past line {}", u16::MAX))
+        })?;
+        for instruction in parse_program(module)? {
+            program.push(Rc::new(relocate(*instruction, start)));
+        }
+    }
+
+    Ok(program)
+}
+
+/// Move an absolute branch target by the line the module starts on
+fn relocate(instruction: Instruction, start: u16) -> Instruction {
+    let shift = |target: OperandValueType| match target {
+        OperandValueType::Immediate(line) => OperandValueType::Immediate(line.wrapping_add(start)),
+        register => register,
+    };
+
+    match instruction {
+        Instruction::JMP(target) => Instruction::JMP(shift(target)),
+        Instruction::JSR(target) => Instruction::JSR(shift(target)),
+        Instruction::SJMP(target) => Instruction::SJMP(shift(target)),
+        Instruction::BEZ(target, source) => Instruction::BEZ(shift(target), source),
+        Instruction::BNZ(target, source) => Instruction::BNZ(shift(target), source),
+        Instruction::BEQ(target, source, value) => Instruction::BEQ(shift(target), source, value),
+        Instruction::BNE(target, source, value) => Instruction::BNE(shift(target), source, value),
+        Instruction::BGE(target, source, value) => Instruction::BGE(shift(target), source, value),
+        Instruction::BLE(target, source, value) => Instruction::BLE(shift(target), source, value),
+        Instruction::BGT(target, source, value) => Instruction::BGT(shift(target), source, value),
+        Instruction::BLT(target, source, value) => Instruction::BLT(shift(target), source, value),
+        Instruction::DJNZ(target, counter) => Instruction::DJNZ(shift(target), counter),
+        Instruction::JTB(base, index) => Instruction::JTB(shift(base), index),
+        Instruction::JTBN(base, index, count) => Instruction::JTBN(shift(base), index, count),
+        Instruction::JSRT(base, index) => Instruction::JSRT(shift(base), index),
+        Instruction::LOOPS(count, end) => Instruction::LOOPS(count, shift(end)),
+        Instruction::BPH(target, pin) => Instruction::BPH(shift(target), pin),
+        Instruction::BPLW(target, pin) => Instruction::BPLW(shift(target), pin),
+        Instruction::BCS(target) => Instruction::BCS(shift(target)),
+        Instruction::BCC(target) => Instruction::BCC(shift(target)),
+        Instruction::BMI(target) => Instruction::BMI(shift(target)),
+        Instruction::BPL(target) => Instruction::BPL(shift(target)),
+        Instruction::BZS(target) => Instruction::BZS(shift(target)),
+        Instruction::BZC(target) => Instruction::BZC(shift(target)),
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::Register;
+    use crate::tpu::create_basic_tpu_config;
+
+    #[test]
+    fn test_link_modules() {
+        // Both modules use offset 0 of their scratch area, the second one branches within itself
+        const FIRST: &str = r#"LDR A, 5
+        STRP 0, A"#;
+        const SECOND: &str = r#"LDR A, 7
+        JMP 3
+        HLT
+        STRP 0, A
+        LDRP X, 0
+        HLT"#;
+
+        let program = link_modules(&[FIRST, SECOND], 0x20).unwrap();
+        assert_eq!(program.len(), 10);
+        assert_eq!(
+            *program[0],
+            Instruction::SDB(OperandValueType::Immediate(0))
+        );
+        assert_eq!(
+            *program[3],
+            Instruction::SDB(OperandValueType::Immediate(0x20))
+        );
+        assert_eq!(
+            *program[5],
+            Instruction::JMP(OperandValueType::Immediate(7))
+        );
+
+        let mut tpu = create_basic_tpu_config(program);
+        while !tpu.halted() {
+            tpu.tick();
+        }
+
+        // The scratch areas don't collide
+        assert_eq!(tpu.read_ram(0), 5);
+        assert_eq!(tpu.read_ram(0x20), 7);
+        assert_eq!(tpu.read_register(Register::X), 7);
+        assert_eq!(tpu.state().program_counter, 9);
+    }
+}
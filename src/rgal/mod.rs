@@ -1,15 +1,19 @@
+pub mod linker;
 mod no_operands;
 mod reg_opcode;
 mod reg_reg_opcodes;
 mod reg_reg_value_opcodes;
 mod reg_value_opcodes;
 mod reg_value_reg_opcodes;
+mod reg_value_reg_value_opcodes;
 mod reg_value_value_opcodes;
+mod symbols;
 mod value_opcodes;
 mod value_reg_opcodes;
 mod value_reg_value_opcodes;
 mod value_value_opcodes;
 mod value_value_reg;
+mod value_value_reg_value_opcodes;
 
 use crate::rgal::no_operands::parse_no_operand_opcodes;
 use crate::rgal::reg_opcode::parse_single_register_operand_opcodes;
@@ -17,51 +21,115 @@ use crate::rgal::reg_reg_opcodes::parse_two_register_operand_opcodes;
 use crate::rgal::reg_reg_value_opcodes::parse_two_register_value_operand_opcodes;
 use crate::rgal::reg_value_opcodes::parse_register_value_operand_opcodes;
 use crate::rgal::reg_value_reg_opcodes::parse_register_value_register_operand_opcodes;
+use crate::rgal::reg_value_reg_value_opcodes::parse_register_value_register_value_operand_opcodes;
+use crate::rgal::reg_value_value_opcodes::parse_register_value_value_operand_opcodes;
 use crate::rgal::value_opcodes::parse_single_value_operand_opcodes;
 use crate::rgal::value_reg_opcodes::parse_value_register_operand_opcodes;
 use crate::rgal::value_reg_value_opcodes::parse_value_register_value_operand_opcodes;
 use crate::rgal::value_value_opcodes::parse_two_value_operand_opcodes;
 use crate::rgal::value_value_reg::parse_value_value_register_operand_opcodes;
+use crate::rgal::value_value_reg_value_opcodes::parse_value_value_register_value_operand_opcodes;
 use crate::shared::{Instruction, OperandValueType, Register};
 use pest::error::ErrorVariant;
 use pest::iterators::Pair;
 use pest::{Parser, Position};
 use pest_derive::Parser;
+use std::path::Path;
 use std::rc::Rc;
 use std::str::FromStr;
 
+pub use symbols::{SymbolResolver, SymbolTable};
+
 #[derive(Parser)]
 #[grammar = "rgal/rgal.pest"]
 pub struct RgalParser;
 
 // Parse a TPU program from a string
-pub fn parse_program(input: &str) -> Result<Vec<Rc<Instruction>>, pest::error::Error<Rule>> {
+pub fn parse_program(input: &str) -> crate::Result<Vec<Rc<Instruction>>> {
+    parse_program_with_symbols(input).map(|(program, _)| program)
+}
+
+/// Parse a TPU program from a string, with the line each of its labels names
+pub fn parse_program_with_symbols(
+    input: &str,
+) -> crate::Result<(Vec<Rc<Instruction>>, SymbolTable)> {
+    Ok(parse_with_symbols(input)?)
+}
+
+/// `parse_program_with_symbols`, with pest's error so a file's path can be added to it
+fn parse_with_symbols(
+    input: &str,
+) -> Result<(Vec<Rc<Instruction>>, SymbolTable), pest::error::Error<Rule>> {
     let pairs = RgalParser::parse(Rule::program, input.trim())?;
-    let mut instructions = Vec::new();
+    let items: Vec<_> = pairs
+        .filter(|pair| pair.as_rule() == Rule::program)
+        .flat_map(|pair| pair.into_inner())
+        .collect();
 
-    for pair in pairs {
-        if pair.as_rule() == Rule::program {
-            for inner_pair in pair.into_inner() {
-                if inner_pair.as_rule() == Rule::instruction {
-                    for inner_pair in inner_pair.into_inner() {
-                        instructions.push(Rc::new(parse_instruction_from_pair(inner_pair)?));
-                    }
+    // Labels can be used before they're defined, so they're all found first
+    let mut symbols = SymbolTable::new();
+    let mut line = 0u16;
+    for pair in &items {
+        match pair.as_rule() {
+            Rule::label => {
+                let span = pair.as_span();
+                let name = pair.as_str().trim_end_matches(':');
+                let error = |message: String| {
+                    pest::error::Error::new_from_span(ErrorVariant::CustomError { message }, span)
+                };
+                if Register::from_str(name).is_ok() {
+                    return Err(error(format!(
+                        "Label can't be named after a register: {name}"
+                    )));
+                }
+                if symbols.insert(name.to_string(), line).is_some() {
+                    return Err(error(format!("Label defined twice: {name}")));
                 }
             }
+            Rule::instruction => line = line.wrapping_add(1),
+            _ => {}
         }
     }
 
-    Ok(instructions)
+    let mut instructions = Vec::new();
+    for pair in items {
+        if pair.as_rule() == Rule::instruction {
+            for inner_pair in pair.into_inner() {
+                instructions.push(Rc::new(parse_instruction_from_pair(inner_pair, &symbols)?));
+            }
+        }
+    }
+
+    Ok((instructions, symbols))
+}
+
+// Parse a TPU program from a file, errors point at the line in the file
+pub fn parse_program_from_file(path: impl AsRef<Path>) -> crate::Result<Vec<Rc<Instruction>>> {
+    parse_program_from_file_with_symbols(path).map(|(program, _)| program)
+}
+
+/// Parse a TPU program from a file, with the line each of its labels names
+pub fn parse_program_from_file_with_symbols(
+    path: impl AsRef<Path>,
+) -> crate::Result<(Vec<Rc<Instruction>>, SymbolTable)> {
+    let path = path.as_ref();
+    let input = std::fs::read_to_string(path)?;
+    parse_with_symbols(&input).map_err(|error| error.with_path(&path.to_string_lossy()).into())
 }
 
 // Parse a single instruction from a string
-pub fn parse_instruction(input: &str) -> Result<Instruction, pest::error::Error<Rule>> {
+pub fn parse_instruction(input: &str) -> crate::Result<Instruction> {
+    Ok(parse_single_instruction(input)?)
+}
+
+fn parse_single_instruction(input: &str) -> Result<Instruction, pest::error::Error<Rule>> {
     let pairs = RgalParser::parse(Rule::instruction, input)?;
 
     for pair in pairs {
         if pair.as_rule() == Rule::instruction {
             for inner_pair in pair.into_inner() {
-                return parse_instruction_from_pair(inner_pair);
+                // A line on its own has no labels to refer to
+                return parse_instruction_from_pair(inner_pair, &SymbolTable::new());
             }
         }
     }
@@ -74,7 +142,10 @@ pub fn parse_instruction(input: &str) -> Result<Instruction, pest::error::Error<
     ))
 }
 
-fn parse_instruction_from_pair(pair: Pair<Rule>) -> Result<Instruction, pest::error::Error<Rule>> {
+fn parse_instruction_from_pair(
+    pair: Pair<Rule>,
+    symbols: &SymbolTable,
+) -> Result<Instruction, pest::error::Error<Rule>> {
     let rule = pair.as_rule();
     let span = pair.as_span();
     let opcode_str;
@@ -98,7 +169,7 @@ fn parse_instruction_from_pair(pair: Pair<Rule>) -> Result<Instruction, pest::er
                 parse_single_register_operand_opcodes(
                     span,
                     opcode_str,
-                    parse_any_operand_from_pair(operand_pair)?,
+                    parse_any_operand_from_pair(operand_pair, symbols)?,
                 )
             } else {
                 Err(pest::error::Error::new_from_span(
@@ -126,7 +197,7 @@ fn parse_instruction_from_pair(pair: Pair<Rule>) -> Result<Instruction, pest::er
                 parse_single_value_operand_opcodes(
                     span,
                     opcode_str,
-                    parse_any_operand_from_pair(operand_pair)?,
+                    parse_any_operand_from_pair(operand_pair, symbols)?,
                 )
             } else {
                 Err(pest::error::Error::new_from_span(
@@ -155,8 +226,8 @@ fn parse_instruction_from_pair(pair: Pair<Rule>) -> Result<Instruction, pest::er
                 parse_two_register_operand_opcodes(
                     span,
                     opcode_str,
-                    parse_any_operand_from_pair(operand1_pair)?,
-                    parse_any_operand_from_pair(operand2_pair)?,
+                    parse_any_operand_from_pair(operand1_pair, symbols)?,
+                    parse_any_operand_from_pair(operand2_pair, symbols)?,
                 )
             } else {
                 Err(pest::error::Error::new_from_span(
@@ -185,8 +256,8 @@ fn parse_instruction_from_pair(pair: Pair<Rule>) -> Result<Instruction, pest::er
                 parse_register_value_operand_opcodes(
                     span,
                     opcode_str,
-                    parse_any_operand_from_pair(operand1_pair)?,
-                    parse_any_operand_from_pair(operand2_pair)?,
+                    parse_any_operand_from_pair(operand1_pair, symbols)?,
+                    parse_any_operand_from_pair(operand2_pair, symbols)?,
                 )
             } else {
                 Err(pest::error::Error::new_from_span(
@@ -215,8 +286,8 @@ fn parse_instruction_from_pair(pair: Pair<Rule>) -> Result<Instruction, pest::er
                 parse_value_register_operand_opcodes(
                     span,
                     opcode_str,
-                    parse_any_operand_from_pair(operand1_pair)?,
-                    parse_any_operand_from_pair(operand2_pair)?,
+                    parse_any_operand_from_pair(operand1_pair, symbols)?,
+                    parse_any_operand_from_pair(operand2_pair, symbols)?,
                 )
             } else {
                 Err(pest::error::Error::new_from_span(
@@ -246,8 +317,8 @@ fn parse_instruction_from_pair(pair: Pair<Rule>) -> Result<Instruction, pest::er
                 parse_two_value_operand_opcodes(
                     span,
                     opcode_str,
-                    parse_any_operand_from_pair(operand1_pair)?,
-                    parse_any_operand_from_pair(operand2_pair)?,
+                    parse_any_operand_from_pair(operand1_pair, symbols)?,
+                    parse_any_operand_from_pair(operand2_pair, symbols)?,
                 )
             } else {
                 Err(pest::error::Error::new_from_span(
@@ -273,11 +344,12 @@ fn parse_instruction_from_pair(pair: Pair<Rule>) -> Result<Instruction, pest::er
             if let (Some(operand1_pair), Some(operand2_pair), Some(operand3_pair)) =
                 (inner_pairs.next(), inner_pairs.next(), inner_pairs.next())
             {
-                parse_two_value_operand_opcodes(
+                parse_register_value_value_operand_opcodes(
                     span,
                     opcode_str,
-                    parse_any_operand_from_pair(operand1_pair)?,
-                    parse_any_operand_from_pair(operand2_pair)?,
+                    parse_any_operand_from_pair(operand1_pair, symbols)?,
+                    parse_any_operand_from_pair(operand2_pair, symbols)?,
+                    parse_any_operand_from_pair(operand3_pair, symbols)?,
                 )
             } else {
                 Err(pest::error::Error::new_from_span(
@@ -306,9 +378,9 @@ fn parse_instruction_from_pair(pair: Pair<Rule>) -> Result<Instruction, pest::er
                 parse_value_register_value_operand_opcodes(
                     span,
                     opcode_str,
-                    parse_any_operand_from_pair(operand1_pair)?,
-                    parse_any_operand_from_pair(operand2_pair)?,
-                    parse_any_operand_from_pair(operand3_pair)?,
+                    parse_any_operand_from_pair(operand1_pair, symbols)?,
+                    parse_any_operand_from_pair(operand2_pair, symbols)?,
+                    parse_any_operand_from_pair(operand3_pair, symbols)?,
                 )
             } else {
                 Err(pest::error::Error::new_from_span(
@@ -337,9 +409,9 @@ fn parse_instruction_from_pair(pair: Pair<Rule>) -> Result<Instruction, pest::er
                 parse_two_register_value_operand_opcodes(
                     span,
                     opcode_str,
-                    parse_any_operand_from_pair(operand1_pair)?,
-                    parse_any_operand_from_pair(operand2_pair)?,
-                    parse_any_operand_from_pair(operand3_pair)?,
+                    parse_any_operand_from_pair(operand1_pair, symbols)?,
+                    parse_any_operand_from_pair(operand2_pair, symbols)?,
+                    parse_any_operand_from_pair(operand3_pair, symbols)?,
                 )
             } else {
                 Err(pest::error::Error::new_from_span(
@@ -368,9 +440,9 @@ fn parse_instruction_from_pair(pair: Pair<Rule>) -> Result<Instruction, pest::er
                 parse_value_value_register_operand_opcodes(
                     span,
                     opcode_str,
-                    parse_any_operand_from_pair(operand1_pair)?,
-                    parse_any_operand_from_pair(operand2_pair)?,
-                    parse_any_operand_from_pair(operand3_pair)?,
+                    parse_any_operand_from_pair(operand1_pair, symbols)?,
+                    parse_any_operand_from_pair(operand2_pair, symbols)?,
+                    parse_any_operand_from_pair(operand3_pair, symbols)?,
                 )
             } else {
                 Err(pest::error::Error::new_from_span(
@@ -399,9 +471,89 @@ fn parse_instruction_from_pair(pair: Pair<Rule>) -> Result<Instruction, pest::er
                 parse_register_value_register_operand_opcodes(
                     span,
                     opcode_str,
-                    parse_any_operand_from_pair(operand1_pair)?,
-                    parse_any_operand_from_pair(operand2_pair)?,
-                    parse_any_operand_from_pair(operand3_pair)?,
+                    parse_any_operand_from_pair(operand1_pair, symbols)?,
+                    parse_any_operand_from_pair(operand2_pair, symbols)?,
+                    parse_any_operand_from_pair(operand3_pair, symbols)?,
+                )
+            } else {
+                Err(pest::error::Error::new_from_span(
+                    ErrorVariant::CustomError {
+                        message: "Failed to parse instruction".into(),
+                    },
+                    span,
+                ))
+            }
+        }
+        Rule::four_reg_any_reg_any_operand_instruction => {
+            let mut inner_pairs = pair.into_inner();
+            opcode_str = inner_pairs
+                .next()
+                .ok_or(pest::error::Error::new_from_span(
+                    ErrorVariant::CustomError {
+                        message: "Failed to parse instruction".into(),
+                    },
+                    span,
+                ))?
+                .as_str();
+
+            if let (
+                Some(operand1_pair),
+                Some(operand2_pair),
+                Some(operand3_pair),
+                Some(operand4_pair),
+            ) = (
+                inner_pairs.next(),
+                inner_pairs.next(),
+                inner_pairs.next(),
+                inner_pairs.next(),
+            ) {
+                parse_register_value_register_value_operand_opcodes(
+                    span,
+                    opcode_str,
+                    parse_any_operand_from_pair(operand1_pair, symbols)?,
+                    parse_any_operand_from_pair(operand2_pair, symbols)?,
+                    parse_any_operand_from_pair(operand3_pair, symbols)?,
+                    parse_any_operand_from_pair(operand4_pair, symbols)?,
+                )
+            } else {
+                Err(pest::error::Error::new_from_span(
+                    ErrorVariant::CustomError {
+                        message: "Failed to parse instruction".into(),
+                    },
+                    span,
+                ))
+            }
+        }
+        Rule::four_any_any_reg_any_operand_instruction => {
+            let mut inner_pairs = pair.into_inner();
+            opcode_str = inner_pairs
+                .next()
+                .ok_or(pest::error::Error::new_from_span(
+                    ErrorVariant::CustomError {
+                        message: "Failed to parse instruction".into(),
+                    },
+                    span,
+                ))?
+                .as_str();
+
+            if let (
+                Some(operand1_pair),
+                Some(operand2_pair),
+                Some(operand3_pair),
+                Some(operand4_pair),
+            ) = (
+                inner_pairs.next(),
+                inner_pairs.next(),
+                inner_pairs.next(),
+                inner_pairs.next(),
+            ) {
+                parse_value_value_register_value_operand_opcodes(
+                    span,
+                    opcode_str,
+                    parse_any_operand_from_pair(operand1_pair, symbols)?,
+                    parse_any_operand_from_pair(operand2_pair, symbols)?,
+                    parse_any_operand_from_pair(operand3_pair, symbols)?,
+                    parse_any_operand_from_pair(operand4_pair, symbols)?,
                 )
             } else {
                 Err(pest::error::Error::new_from_span(
@@ -418,6 +570,7 @@ fn parse_instruction_from_pair(pair: Pair<Rule>) -> Result<Instruction, pest::er
 
 fn parse_any_operand_from_pair(
     pair: Pair<Rule>,
+    symbols: &SymbolTable,
 ) -> Result<OperandValueType, pest::error::Error<Rule>> {
     let span = pair.as_span();
 
@@ -443,6 +596,17 @@ fn parse_any_operand_from_pair(
                 )),
             }
         }
+        Rule::label_ref => symbols
+            .get(pair.as_str())
+            .map(|&line| OperandValueType::Immediate(line))
+            .ok_or_else(|| {
+                pest::error::Error::new_from_span(
+                    ErrorVariant::CustomError {
+                        message: format!("Unknown label: {}", pair.as_str()),
+                    },
+                    span,
+                )
+            }),
         Rule::hex_number => {
             let hex_str = pair.as_str().trim_start_matches("0x");
             u16::from_str_radix(hex_str, 16)
@@ -492,8 +656,7 @@ fn parse_any_operand_from_pair(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use pest::error::ErrorVariant;
-    use pest::error::LineColLocation::Pos;
+    use crate::Error;
 
     #[test]
     fn test_parse_instruction() {
@@ -525,6 +688,256 @@ mod tests {
         let instruction = parse_instruction("HLT").unwrap();
         assert_eq!(instruction, Instruction::HLT);
 
+        // LDOI must not be shadowed by LDO
+        assert_eq!(
+            parse_instruction("LDOI A, 0, X").unwrap(),
+            Instruction::LDOI(Register::A, OperandValueType::Immediate(0), Register::X)
+        );
+
+        assert_eq!(
+            parse_instruction("LDOS A, 0x10, X, 4").unwrap(),
+            Instruction::LDOS(
+                Register::A,
+                OperandValueType::Immediate(0x10),
+                Register::X,
+                OperandValueType::Immediate(4)
+            )
+        );
+        assert_eq!(
+            parse_instruction("SMOS 0x10, A, X, Y").unwrap(),
+            Instruction::SMOS(
+                OperandValueType::Immediate(0x10),
+                OperandValueType::Register(Register::A),
+                Register::X,
+                OperandValueType::Register(Register::Y)
+            )
+        );
+
+        assert_eq!(
+            parse_instruction("CMOVZ A, 5, X").unwrap(),
+            Instruction::CMOVZ(Register::A, OperandValueType::Immediate(5), Register::X)
+        );
+        assert_eq!(
+            parse_instruction("CMOVN A, Y, X").unwrap(),
+            Instruction::CMOVN(
+                Register::A,
+                OperandValueType::Register(Register::Y),
+                Register::X
+            )
+        );
+
+        // LDRP must not be shadowed by LDR
+        assert_eq!(
+            parse_instruction("LDRP A, 2").unwrap(),
+            Instruction::LDRP(Register::A, OperandValueType::Immediate(2))
+        );
+        assert_eq!(
+            parse_instruction("STRP 2, A").unwrap(),
+            Instruction::STRP(
+                OperandValueType::Immediate(2),
+                OperandValueType::Register(Register::A)
+            )
+        );
+        assert_eq!(
+            parse_instruction("SDB 0x40").unwrap(),
+            Instruction::SDB(OperandValueType::Immediate(0x40))
+        );
+
+        assert_eq!(
+            parse_instruction("LOOPS 4, X").unwrap(),
+            Instruction::LOOPS(
+                OperandValueType::Immediate(4),
+                OperandValueType::Register(Register::X)
+            )
+        );
+
+        assert_eq!(
+            parse_instruction("DCFG 2, 1").unwrap(),
+            Instruction::DCFG(
+                OperandValueType::Immediate(2),
+                OperandValueType::Immediate(1)
+            )
+        );
+        assert_eq!(
+            parse_instruction("ACFG X, 0").unwrap(),
+            Instruction::ACFG(
+                OperandValueType::Register(Register::X),
+                OperandValueType::Immediate(0)
+            )
+        );
+
+        assert_eq!(
+            parse_instruction("DCFR A, 2").unwrap(),
+            Instruction::DCFR(Register::A, OperandValueType::Immediate(2))
+        );
+        assert_eq!(
+            parse_instruction("ACFR A, X").unwrap(),
+            Instruction::ACFR(Register::A, OperandValueType::Register(Register::X))
+        );
+
+        assert_eq!(
+            parse_instruction("PWMC 1, 8").unwrap(),
+            Instruction::PWMC(
+                OperandValueType::Immediate(1),
+                OperandValueType::Immediate(8)
+            )
+        );
+        assert_eq!(
+            parse_instruction("PWMD 1, A").unwrap(),
+            Instruction::PWMD(
+                OperandValueType::Immediate(1),
+                OperandValueType::Register(Register::A)
+            )
+        );
+        assert_eq!(
+            parse_instruction("PWMS 1, 0").unwrap(),
+            Instruction::PWMS(
+                OperandValueType::Immediate(1),
+                OperandValueType::Immediate(0)
+            )
+        );
+
+        assert_eq!(
+            parse_instruction("APRW A").unwrap(),
+            Instruction::APRW(Register::A)
+        );
+        assert_eq!(
+            parse_instruction("APWA 16").unwrap(),
+            Instruction::APWA(OperandValueType::Immediate(16))
+        );
+        assert_eq!(
+            parse_instruction("APRA X").unwrap(),
+            Instruction::APRA(OperandValueType::Register(Register::X))
+        );
+
+        assert_eq!(
+            parse_instruction("PEVC R1").unwrap(),
+            Instruction::PEVC(Register::R1)
+        );
+        assert_eq!(parse_instruction("PEVR").unwrap(), Instruction::PEVR);
+
+        assert_eq!(
+            parse_instruction("PCNTR A, 3").unwrap(),
+            Instruction::PCNTR(Register::A, OperandValueType::Immediate(3))
+        );
+        assert_eq!(
+            parse_instruction("PCNTC X").unwrap(),
+            Instruction::PCNTC(OperandValueType::Register(Register::X))
+        );
+
+        assert_eq!(
+            parse_instruction("TXOK R2").unwrap(),
+            Instruction::TXOK(Register::R2)
+        );
+
+        assert_eq!(
+            parse_instruction("RXAV A").unwrap(),
+            Instruction::RXAV(Register::A)
+        );
+
+        assert_eq!(
+            parse_instruction("CMPT 1, R2").unwrap(),
+            Instruction::CMPT(
+                OperandValueType::Immediate(1),
+                OperandValueType::Register(Register::R2)
+            )
+        );
+
+        assert_eq!(
+            parse_instruction("NETR R3").unwrap(),
+            Instruction::NETR(Register::R3)
+        );
+        assert_eq!(
+            parse_instruction("NETA 0x12").unwrap(),
+            Instruction::NETA(OperandValueType::Immediate(0x12))
+        );
+
+        // WRXT must not be shadowed by WRX
+        assert_eq!(
+            parse_instruction("WRXT 100").unwrap(),
+            Instruction::WRXT(OperandValueType::Immediate(100))
+        );
+        assert_eq!(parse_instruction("WRX").unwrap(), Instruction::WRX);
+
+        assert_eq!(
+            parse_instruction("UTX 0x41").unwrap(),
+            Instruction::UTX(OperandValueType::Immediate(0x41))
+        );
+        assert_eq!(
+            parse_instruction("URX R0").unwrap(),
+            Instruction::URX(Register::R0)
+        );
+
+        // XMITM, RECVM and RECVV must not be shadowed by XMIT and RECV
+        assert_eq!(
+            parse_instruction("XMITM A, 0x10, 4").unwrap(),
+            Instruction::XMITM(
+                Register::A,
+                OperandValueType::Immediate(0x10),
+                OperandValueType::Immediate(4)
+            )
+        );
+        assert_eq!(
+            parse_instruction("RECVM X").unwrap(),
+            Instruction::RECVM(OperandValueType::Register(Register::X))
+        );
+        assert_eq!(parse_instruction("RECV").unwrap(), Instruction::RECV);
+        assert_eq!(
+            parse_instruction("XMITP A, 2, R0").unwrap(),
+            Instruction::XMITP(
+                Register::A,
+                OperandValueType::Immediate(2),
+                OperandValueType::Register(Register::R0)
+            )
+        );
+        assert_eq!(
+            parse_instruction("RECVP 2").unwrap(),
+            Instruction::RECVP(OperandValueType::Immediate(2))
+        );
+        assert_eq!(
+            parse_instruction("FWD 0x40").unwrap(),
+            Instruction::FWD(OperandValueType::Immediate(0x40))
+        );
+        assert_eq!(
+            parse_instruction("RECVV R0").unwrap(),
+            Instruction::RECVV(Register::R0)
+        );
+
+        // BPLW must not be shadowed by BPL
+        assert_eq!(
+            parse_instruction("BPLW 4, X").unwrap(),
+            Instruction::BPLW(
+                OperandValueType::Immediate(4),
+                OperandValueType::Register(Register::X)
+            )
+        );
+        assert_eq!(
+            parse_instruction("BPH 4, 2").unwrap(),
+            Instruction::BPH(
+                OperandValueType::Immediate(4),
+                OperandValueType::Immediate(2)
+            )
+        );
+
+        // JTBN must not be shadowed by JTB
+        assert_eq!(
+            parse_instruction("JTB 4, X").unwrap(),
+            Instruction::JTB(OperandValueType::Immediate(4), Register::X)
+        );
+        assert_eq!(
+            parse_instruction("JTBN 4, X, 3").unwrap(),
+            Instruction::JTBN(
+                OperandValueType::Immediate(4),
+                Register::X,
+                OperandValueType::Immediate(3)
+            )
+        );
+
+        assert_eq!(
+            parse_instruction("JSRT 4, X").unwrap(),
+            Instruction::JSRT(OperandValueType::Immediate(4), Register::X)
+        );
+
         // Test analog pin operands
         match parse_instruction("APR A, 0") {
             Ok(instruction) => match instruction {
@@ -558,23 +971,7 @@ mod tests {
     fn test_parse_program() {
         let program = parse_program("PUSH 42\nPOP A\nADD A, X\nNOP\nSUB R0, R1\nHLT");
 
-        let program = match program {
-            Ok(program) => program,
-            Err(e) => match e.variant {
-                ErrorVariant::ParsingError { ref positives, .. } => {
-                    let (line, col) = match e.line_col {
-                        Pos((line, col)) => (line, col),
-                        _ => (1, 1),
-                    };
-                    panic!(
-                        "Error in program at line {line}, {col}, Expected {:?}, found {} instead",
-                        positives,
-                        e.line()
-                    );
-                }
-                _ => panic!("Failed to parse program: {:?}", e),
-            },
-        };
+        let program = program.unwrap_or_else(|error| panic!("Failed to parse program:\n{error}"));
 
         assert_eq!(program.len(), 6);
 
@@ -661,4 +1058,75 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_parse_labels() {
+        // Labels can be used before they're defined, and can share a line with an instruction
+        let (program, symbols) = parse_program_with_symbols(
+            "JMP_START: JMP START\nSTART:\n// comment\nLOOP: DEC A\nBNZ LOOP, A\nLDR X, END\nEND: HLT",
+        )
+        .unwrap();
+        assert_eq!(program.len(), 5);
+        assert_eq!(symbols.len(), 4);
+        assert_eq!(symbols["JMP_START"], 0);
+        assert_eq!(symbols["START"], 1);
+        assert_eq!(symbols["LOOP"], 1);
+        assert_eq!(symbols["END"], 4);
+        assert_eq!(
+            *program[0],
+            Instruction::JMP(OperandValueType::Immediate(1))
+        );
+        assert_eq!(
+            *program[2],
+            Instruction::BNZ(OperandValueType::Immediate(1), Register::A)
+        );
+        assert_eq!(
+            *program[3],
+            Instruction::LDR(Register::X, OperandValueType::Immediate(4))
+        );
+
+        let message = |input: &str| match parse_program(input).unwrap_err() {
+            Error::Parse { message, .. } => message,
+            error => panic!("Unexpected error: {error:?}"),
+        };
+        assert!(message("JMP NOWHERE").ends_with("= Unknown label: NOWHERE"));
+        assert!(message("A: HLT").ends_with("= Label can't be named after a register: A"));
+        assert!(message("TOP: NOP\nTOP: HLT").ends_with("= Label defined twice: TOP"));
+
+        // A line on its own has no labels
+        assert!(parse_instruction("JMP START").is_err());
+        // A register is still a register, a word starting with one's name is a label
+        assert_eq!(
+            parse_program("AGAIN: JMP AGAIN\nJMP A").unwrap()[1].as_ref(),
+            &Instruction::JMP(OperandValueType::Register(Register::A))
+        );
+    }
+
+    #[test]
+    fn test_parse_error() {
+        let error = parse_program("LDR A, 1\nADD A, 3\nHLT").unwrap_err();
+        let Error::Parse { line, col, .. } = error else {
+            panic!("Unexpected error: {error:?}");
+        };
+        assert_eq!((line, col), (2, 8));
+
+        // Shown with the line it's on, pointing at the mistake
+        let message = error.to_string();
+        assert!(message.contains("--> 2:8"), "{message}");
+        assert!(message.contains("2 | ADD A, 3"), "{message}");
+        assert!(message.contains("= expected register"), "{message}");
+
+        assert!(matches!(
+            parse_instruction("FOO A"),
+            Err(Error::Parse {
+                line: 1,
+                col: 1,
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse_program_from_file("no/such/program.rgal"),
+            Err(Error::Io(_))
+        ));
+    }
 }
@@ -9,13 +9,17 @@ pub fn parse_no_operand_opcodes(
 ) -> Result<Instruction, pest::error::Error<Rule>> {
     match opcode {
         "SCR" => Ok(Instruction::SCR),
+        "LEAVE" => Ok(Instruction::LEAVE),
         "RECV" => Ok(Instruction::RECV),
         "TXBS" => Ok(Instruction::TXBS),
         "RXBS" => Ok(Instruction::RXBS),
         "NOP" => Ok(Instruction::NOP),
         "WRX" => Ok(Instruction::WRX),
         "HLT" => Ok(Instruction::HLT),
+        "RST" => Ok(Instruction::RST),
         "RTS" => Ok(Instruction::RTS),
+        "SRET" => Ok(Instruction::SRET),
+        "PEVR" => Ok(Instruction::PEVR),
         _ => Err(pest::error::Error::new_from_span(
             ErrorVariant::CustomError {
                 message: "Failed to parse instruction".into(),
@@ -24,6 +24,15 @@ pub fn parse_single_register_operand_opcodes(
         "INC" => Ok(Instruction::INC(register_operand)),
         "DEC" => Ok(Instruction::DEC(register_operand)),
         "DPRW" => Ok(Instruction::DPRW(register_operand)),
+        "APRW" => Ok(Instruction::APRW(register_operand)),
+        "PEVC" => Ok(Instruction::PEVC(register_operand)),
+        "TXOK" => Ok(Instruction::TXOK(register_operand)),
+        "RXAV" => Ok(Instruction::RXAV(register_operand)),
+        "NETR" => Ok(Instruction::NETR(register_operand)),
+        "RECVV" => Ok(Instruction::RECVV(register_operand)),
+        "URX" => Ok(Instruction::URX(register_operand)),
+        "SEZ" => Ok(Instruction::SEZ(register_operand)),
+        "SNZ" => Ok(Instruction::SNZ(register_operand)),
 
         _ => Err(pest::error::Error::new_from_span(
             ErrorVariant::CustomError {
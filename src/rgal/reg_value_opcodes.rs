@@ -20,11 +20,19 @@ pub fn parse_register_value_operand_opcodes(
 
     match opcode {
         "PEEK" => Ok(Instruction::PEEK(register, value)),
+        "PEEKF" => Ok(Instruction::PEEKF(register, value)),
         "XMIT" => Ok(Instruction::XMIT(register, value)),
         "LDR" => Ok(Instruction::LDR(register, value)),
         "LDM" => Ok(Instruction::LDM(register, value)),
         "DPR" => Ok(Instruction::DPR(register, value)),
         "APR" => Ok(Instruction::APR(register, value)),
+        "DCFR" => Ok(Instruction::DCFR(register, value)),
+        "ACFR" => Ok(Instruction::ACFR(register, value)),
+        "PCNTR" => Ok(Instruction::PCNTR(register, value)),
+        "NVL" => Ok(Instruction::NVL(register, value)),
+        "LDB" => Ok(Instruction::LDB(register, value)),
+        "LDRP" => Ok(Instruction::LDRP(register, value)),
+        "CMP" => Ok(Instruction::CMP(register, value)),
 
         _ => Err(pest::error::Error::new_from_span(
             ErrorVariant::CustomError {
@@ -24,6 +24,8 @@ pub fn parse_register_value_register_operand_opcodes(
     match opcode {
         "LDO" => Ok(Instruction::LDO(register_a, value, register_b)),
         "LDOI" => Ok(Instruction::LDOI(register_a, value, register_b)),
+        "CMOVZ" => Ok(Instruction::CMOVZ(register_a, value, register_b)),
+        "CMOVN" => Ok(Instruction::CMOVN(register_a, value, register_b)),
         _ => Err(pest::error::Error::new_from_span(
             ErrorVariant::CustomError {
                 message: "Failed to parse instruction".into(),
@@ -0,0 +1,34 @@
+use crate::rgal::Rule;
+use crate::shared::{Instruction, OperandValueType};
+use pest::Span;
+use pest::error::ErrorVariant;
+
+pub fn parse_register_value_register_value_operand_opcodes(
+    span: Span,
+    opcode: &str,
+    register_a: OperandValueType,
+    value_a: OperandValueType,
+    register_b: OperandValueType,
+    value_b: OperandValueType,
+) -> Result<Instruction, pest::error::Error<Rule>> {
+    let (OperandValueType::Register(register_a), OperandValueType::Register(register_b)) =
+        (register_a, register_b)
+    else {
+        return Err(pest::error::Error::new_from_span(
+            ErrorVariant::CustomError {
+                message: "Expected register, value, register, value operands".into(),
+            },
+            span,
+        ));
+    };
+
+    match opcode {
+        "LDOS" => Ok(Instruction::LDOS(register_a, value_a, register_b, value_b)),
+        _ => Err(pest::error::Error::new_from_span(
+            ErrorVariant::CustomError {
+                message: "Failed to parse instruction".into(),
+            },
+            span,
+        )),
+    }
+}
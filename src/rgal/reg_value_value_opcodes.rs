@@ -3,7 +3,7 @@ use crate::shared::{Instruction, OperandValueType};
 use pest::Span;
 use pest::error::ErrorVariant;
 
-pub fn parse_register_value__value_operand_opcodes(
+pub fn parse_register_value_value_operand_opcodes(
     span: Span,
     opcode: &str,
     register: OperandValueType,
@@ -13,13 +13,15 @@ pub fn parse_register_value__value_operand_opcodes(
     let OperandValueType::Register(register_a) = register else {
         return Err(pest::error::Error::new_from_span(
             ErrorVariant::CustomError {
-                message: "Expected register, value, register operands".into(),
+                message: "Expected register, value, value operands".into(),
             },
             span,
         ));
     };
 
     match opcode {
+        "XMITM" => Ok(Instruction::XMITM(register_a, value_a, value_b)),
+        "XMITP" => Ok(Instruction::XMITP(register_a, value_a, value_b)),
         _ => Err(pest::error::Error::new_from_span(
             ErrorVariant::CustomError {
                 message: "Failed to parse instruction".into(),
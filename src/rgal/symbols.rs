@@ -0,0 +1,147 @@
+use crate::shared::{Instruction, OperandValueType};
+use std::collections::BTreeMap;
+
+/// The line each label in a program names, see `parse_program_with_symbols`
+pub type SymbolTable = BTreeMap<String, u16>;
+
+/// Names the lines of a program by their labels, so its instructions can be shown the way they were written.
+///
+/// Only lines in the program are named, a branch to a label past the end is an error when it's taken, so it's shown
+/// as the number it is.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SymbolResolver {
+    /// The labels on each line, in name order
+    labels: BTreeMap<u16, Vec<String>>,
+    len: usize,
+}
+
+impl SymbolResolver {
+    /// Resolve the labels in `symbols` for a program of `len` instructions
+    pub fn new(symbols: &SymbolTable, len: usize) -> Self {
+        let mut labels: BTreeMap<u16, Vec<String>> = BTreeMap::new();
+        for (name, &line) in symbols {
+            labels.entry(line).or_default().push(name.clone());
+        }
+        Self { labels, len }
+    }
+
+    /// The labels naming `line`, none if it's past the end of the program
+    pub fn labels_at(&self, line: usize) -> &[String] {
+        if line >= self.len {
+            return &[];
+        }
+        u16::try_from(line)
+            .ok()
+            .and_then(|line| self.labels.get(&line))
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// The first label naming `line`, if it's in the program
+    pub fn resolve(&self, line: u16) -> Option<&str> {
+        self.labels_at(usize::from(line))
+            .first()
+            .map(String::as_str)
+    }
+
+    /// The instruction as `Display` shows it, with its branch target named, e.g. `BEZ DONE, A` for `BEZ 7, A`
+    pub fn format_instruction(&self, instruction: &Instruction) -> String {
+        let text = instruction.to_string();
+        let Some((target, label)) = branch_target(instruction)
+            .and_then(|(operand, line)| Some((operand, self.resolve(line)?)))
+        else {
+            return text;
+        };
+        let Some((mnemonic, operands)) = text.split_once(' ') else {
+            return text;
+        };
+
+        let operands: Vec<_> = operands
+            .split(", ")
+            .enumerate()
+            .map(|(index, operand)| if index == target { label } else { operand })
+            .collect();
+        format!("{mnemonic} {}", operands.join(", "))
+    }
+}
+
+/// Which operand of `instruction` is a line it branches to or reads a table from, and the line, if it's given as a
+/// number. The same instructions `linker::relocate` moves.
+fn branch_target(instruction: &Instruction) -> Option<(usize, u16)> {
+    let (operand, target) = match *instruction {
+        Instruction::JMP(target)
+        | Instruction::JSR(target)
+        | Instruction::SJMP(target)
+        | Instruction::BEZ(target, _)
+        | Instruction::BNZ(target, _)
+        | Instruction::BEQ(target, _, _)
+        | Instruction::BNE(target, _, _)
+        | Instruction::BGE(target, _, _)
+        | Instruction::BLE(target, _, _)
+        | Instruction::BGT(target, _, _)
+        | Instruction::BLT(target, _, _)
+        | Instruction::DJNZ(target, _)
+        | Instruction::JTB(target, _)
+        | Instruction::JTBN(target, _, _)
+        | Instruction::JSRT(target, _)
+        | Instruction::BPH(target, _)
+        | Instruction::BPLW(target, _)
+        | Instruction::BCS(target)
+        | Instruction::BCC(target)
+        | Instruction::BMI(target)
+        | Instruction::BPL(target)
+        | Instruction::BZS(target)
+        | Instruction::BZC(target) => (0, target),
+        Instruction::LOOPS(_, end) => (1, end),
+        _ => return None,
+    };
+    let OperandValueType::Immediate(line) = target else {
+        return None;
+    };
+    Some((operand, line))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rgal::parse_program_with_symbols;
+
+    #[test]
+    fn test_symbol_resolver() {
+        const PROGRAM: &str = r#"START:
+        LDR A, 3
+        LOOP: DEC A
+        BNZ LOOP, A
+        JMP 0
+        JMP 9
+        JMP X
+        LOOPS 2, END
+        END:"#;
+
+        let (program, symbols) = parse_program_with_symbols(PROGRAM).unwrap();
+        assert_eq!(symbols["START"], 0);
+        assert_eq!(symbols["LOOP"], 1);
+        assert_eq!(symbols["END"], 7);
+        let resolver = SymbolResolver::new(&symbols, program.len());
+
+        // Labelled targets are named, whether the program used the label or the number
+        assert_eq!(resolver.format_instruction(&program[2]), "BNZ LOOP, A");
+        assert_eq!(resolver.format_instruction(&program[3]), "JMP START");
+        assert_eq!(resolver.labels_at(1), ["LOOP"]);
+        assert_eq!(resolver.resolve(0), Some("START"));
+
+        // Unlabelled lines, targets in registers and other instructions are left as they are
+        assert_eq!(resolver.resolve(2), None);
+        assert!(resolver.labels_at(2).is_empty());
+        assert_eq!(resolver.format_instruction(&program[5]), "JMP X");
+        assert_eq!(resolver.format_instruction(&program[0]), "LDR A, 0003");
+
+        // Past the end of the program, even with a label there
+        assert_eq!(resolver.format_instruction(&program[4]), "JMP 0009");
+        assert_eq!(resolver.format_instruction(&program[6]), "LOOPS 0002, 0007");
+        assert_eq!(resolver.resolve(7), None);
+        assert!(resolver.labels_at(7).is_empty());
+
+        let none = SymbolResolver::default();
+        assert_eq!(none.format_instruction(&program[2]), "BNZ 0001, A");
+    }
+}
@@ -10,11 +10,30 @@ pub fn parse_single_value_operand_opcodes(
 ) -> Result<Instruction, pest::error::Error<Rule>> {
     match opcode {
         "PUSH" => Ok(Instruction::PUSH(operand_value_type)),
+        "ENTER" => Ok(Instruction::ENTER(operand_value_type)),
         "DPWW" => Ok(Instruction::DPWW(operand_value_type)),
+        "APWA" => Ok(Instruction::APWA(operand_value_type)),
+        "APRA" => Ok(Instruction::APRA(operand_value_type)),
+        "PCNTC" => Ok(Instruction::PCNTC(operand_value_type)),
         "JMP" => Ok(Instruction::JMP(operand_value_type)),
         "JPR" => Ok(Instruction::JPR(operand_value_type)),
         "JSR" => Ok(Instruction::JSR(operand_value_type)),
+        "SWI" => Ok(Instruction::SWI(operand_value_type)),
+        "SJMP" => Ok(Instruction::SJMP(operand_value_type)),
         "SLP" => Ok(Instruction::SLP(operand_value_type)),
+        "WRXT" => Ok(Instruction::WRXT(operand_value_type)),
+        "RECVM" => Ok(Instruction::RECVM(operand_value_type)),
+        "RECVP" => Ok(Instruction::RECVP(operand_value_type)),
+        "FWD" => Ok(Instruction::FWD(operand_value_type)),
+        "UTX" => Ok(Instruction::UTX(operand_value_type)),
+        "NETA" => Ok(Instruction::NETA(operand_value_type)),
+        "SDB" => Ok(Instruction::SDB(operand_value_type)),
+        "BCS" => Ok(Instruction::BCS(operand_value_type)),
+        "BCC" => Ok(Instruction::BCC(operand_value_type)),
+        "BMI" => Ok(Instruction::BMI(operand_value_type)),
+        "BPL" => Ok(Instruction::BPL(operand_value_type)),
+        "BZS" => Ok(Instruction::BZS(operand_value_type)),
+        "BZC" => Ok(Instruction::BZC(operand_value_type)),
         _ => Err(pest::error::Error::new_from_span(
             ErrorVariant::CustomError {
                 message: "Failed to parse instruction".into(),
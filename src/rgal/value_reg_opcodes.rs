@@ -23,6 +23,9 @@ pub fn parse_value_register_operand_opcodes(
         "BNZ" => Ok(Instruction::BNZ(value, register)),
         "BREZ" => Ok(Instruction::BREZ(value, register)),
         "BRNZ" => Ok(Instruction::BRNZ(value, register)),
+        "DJNZ" => Ok(Instruction::DJNZ(value, register)),
+        "JTB" => Ok(Instruction::JTB(value, register)),
+        "JSRT" => Ok(Instruction::JSRT(value, register)),
 
         _ => Err(pest::error::Error::new_from_span(
             ErrorVariant::CustomError {
@@ -32,6 +32,7 @@ pub fn parse_value_register_value_operand_opcodes(
         "BRLE" => Ok(Instruction::BRLE(value_a, register, value_b)),
         "BRGT" => Ok(Instruction::BRGT(value_a, register, value_b)),
         "BRLT" => Ok(Instruction::BRLT(value_a, register, value_b)),
+        "JTBN" => Ok(Instruction::JTBN(value_a, register, value_b)),
 
         _ => Err(pest::error::Error::new_from_span(
             ErrorVariant::CustomError {
@@ -13,6 +13,19 @@ pub fn parse_two_value_operand_opcodes(
         "STM" => Ok(Instruction::STM(operand_a, operand_b)),
         "DPW" => Ok(Instruction::DPW(operand_a, operand_b)),
         "APW" => Ok(Instruction::APW(operand_a, operand_b)),
+        "CMPT" => Ok(Instruction::CMPT(operand_a, operand_b)),
+        "DCFG" => Ok(Instruction::DCFG(operand_a, operand_b)),
+        "ACFG" => Ok(Instruction::ACFG(operand_a, operand_b)),
+        "PWMC" => Ok(Instruction::PWMC(operand_a, operand_b)),
+        "PWMD" => Ok(Instruction::PWMD(operand_a, operand_b)),
+        "PWMS" => Ok(Instruction::PWMS(operand_a, operand_b)),
+        "NVS" => Ok(Instruction::NVS(operand_a, operand_b)),
+        "STB" => Ok(Instruction::STB(operand_a, operand_b)),
+        "STRP" => Ok(Instruction::STRP(operand_a, operand_b)),
+        "BPH" => Ok(Instruction::BPH(operand_a, operand_b)),
+        "BPLW" => Ok(Instruction::BPLW(operand_a, operand_b)),
+        "LOOPS" => Ok(Instruction::LOOPS(operand_a, operand_b)),
+        "CRC" => Ok(Instruction::CRC(operand_a, operand_b)),
 
         _ => Err(pest::error::Error::new_from_span(
             ErrorVariant::CustomError {
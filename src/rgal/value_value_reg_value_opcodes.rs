@@ -0,0 +1,32 @@
+use crate::rgal::Rule;
+use crate::shared::{Instruction, OperandValueType};
+use pest::Span;
+use pest::error::ErrorVariant;
+
+pub fn parse_value_value_register_value_operand_opcodes(
+    span: Span,
+    opcode: &str,
+    value_a: OperandValueType,
+    value_b: OperandValueType,
+    register: OperandValueType,
+    value_c: OperandValueType,
+) -> Result<Instruction, pest::error::Error<Rule>> {
+    let OperandValueType::Register(register) = register else {
+        return Err(pest::error::Error::new_from_span(
+            ErrorVariant::CustomError {
+                message: "Expected value, value, register, value operands".into(),
+            },
+            span,
+        ));
+    };
+
+    match opcode {
+        "SMOS" => Ok(Instruction::SMOS(value_a, value_b, register, value_c)),
+        _ => Err(pest::error::Error::new_from_span(
+            ErrorVariant::CustomError {
+                message: "Failed to parse instruction".into(),
+            },
+            span,
+        )),
+    }
+}
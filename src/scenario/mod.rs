@@ -0,0 +1,410 @@
+#[cfg(test)]
+mod scenario_test;
+
+use crate::network::Network;
+use crate::shared::{AnalogPin, DigitalPin, NetPacket, Register};
+use crate::tpu::TPU;
+use pest::Parser;
+use pest::error::ErrorVariant;
+use pest::iterators::Pair;
+use pest_derive::Parser;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Parser)]
+#[grammar = "scenario/scenario.pest"]
+pub struct ScenarioParser;
+
+/// A script of timed inputs and checks for end to end tests, replayed against a TPU or a network by a `ScenarioRunner`.
+///
+/// A scenario is a text file with one event per line, each starting with the cycle it happens on. Events on the
+/// same cycle happen in the order they're written, after that many ticks and before the next one. They happen to
+/// TPU 0 unless they name another with `tpu`, its index in the `Network`.
+///
+/// ```text
+/// // Press the button, then send the reading
+/// @100 drive D2 high
+/// @150 drive A1 300
+/// @250 tpu 1 packet 0x3 7       // from 0x3, with the words that follow as the payload
+/// @400 expect A0 >= 500
+/// @400 expect R0 == 0x7
+/// @400 expect RAM[0x20] != 0
+/// @400 expect D1 == 1           // digital pins read as 0 or 1
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Scenario {
+    /// Events in the order they happen
+    events: Vec<Event>,
+}
+
+#[derive(Clone, Debug)]
+struct Event {
+    cycle: u64,
+    /// Line of the script the event is on
+    line: usize,
+    tpu: usize,
+    action: Action,
+}
+
+#[derive(Clone, Debug)]
+enum Action {
+    DriveDigital(DigitalPin, bool),
+    DriveAnalog(AnalogPin, u16),
+    Packet { sender: u16, words: Vec<u16> },
+    Expect(Probe, Comparison, u16),
+}
+
+/// A value a scenario can check
+#[derive(Clone, Copy, Debug)]
+enum Probe {
+    Register(Register),
+    Ram(usize),
+    Digital(DigitalPin),
+    Analog(AnalogPin),
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    GreaterOrEqual,
+    LessOrEqual,
+    Greater,
+    Less,
+}
+
+impl Comparison {
+    fn holds(self, actual: u16, expected: u16) -> bool {
+        match self {
+            Comparison::Equal => actual == expected,
+            Comparison::NotEqual => actual != expected,
+            Comparison::GreaterOrEqual => actual >= expected,
+            Comparison::LessOrEqual => actual <= expected,
+            Comparison::Greater => actual > expected,
+            Comparison::Less => actual < expected,
+        }
+    }
+
+    fn symbol(self) -> &'static str {
+        match self {
+            Comparison::Equal => "==",
+            Comparison::NotEqual => "!=",
+            Comparison::GreaterOrEqual => ">=",
+            Comparison::LessOrEqual => "<=",
+            Comparison::Greater => ">",
+            Comparison::Less => "<",
+        }
+    }
+}
+
+impl fmt::Display for Probe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Probe::Register(register) => write!(f, "{register}"),
+            Probe::Ram(address) => write!(f, "RAM[{address:#06X}]"),
+            Probe::Digital(pin) => write!(f, "D{}", *pin as u16),
+            Probe::Analog(pin) => write!(f, "A{}", *pin as u16),
+        }
+    }
+}
+
+// Parse a scenario from a string
+pub fn parse_scenario(input: &str) -> crate::Result<Scenario> {
+    Ok(parse_events(input)?)
+}
+
+fn parse_events(input: &str) -> Result<Scenario, pest::error::Error<Rule>> {
+    let pairs = ScenarioParser::parse(Rule::scenario, input)?;
+    let mut events = Vec::new();
+
+    for pair in pairs.flatten() {
+        if pair.as_rule() == Rule::event {
+            events.push(parse_event(pair)?);
+        }
+    }
+
+    // Stable, so events on the same cycle keep the order they were written in
+    events.sort_by_key(|event| event.cycle);
+    Ok(Scenario { events })
+}
+
+fn parse_event(pair: Pair<Rule>) -> Result<Event, pest::error::Error<Rule>> {
+    let line = pair.as_span().start_pos().line_col().0;
+    let mut inner = pair.into_inner();
+    let cycle = parse_number(inner.next().unwrap())?;
+
+    let mut action = inner.next().unwrap();
+    let mut tpu = 0;
+    if action.as_rule() == Rule::tpu {
+        tpu = parse_number(action.into_inner().next().unwrap())? as usize;
+        action = inner.next().unwrap();
+    }
+
+    let action = match action.as_rule() {
+        Rule::drive_digital => {
+            let mut inner = action.into_inner();
+            let pin = parse_digital_pin(inner.next().unwrap())?;
+            Action::DriveDigital(pin, inner.next().unwrap().as_str() == "high")
+        }
+        Rule::drive_analog => {
+            let mut inner = action.into_inner();
+            let pin = parse_analog_pin(inner.next().unwrap())?;
+            Action::DriveAnalog(pin, parse_word(inner.next().unwrap())?)
+        }
+        Rule::packet => {
+            let span = action.as_span();
+            let mut inner = action.into_inner();
+            let sender = parse_word(inner.next().unwrap())?;
+            let words = inner.map(parse_word).collect::<Result<Vec<_>, _>>()?;
+            if words.len() > NetPacket::MAX_PAYLOAD {
+                return Err(custom_error(
+                    format!("A packet carries at most {} words", NetPacket::MAX_PAYLOAD),
+                    span,
+                ));
+            }
+            Action::Packet { sender, words }
+        }
+        Rule::expect => {
+            let mut inner = action.into_inner();
+            let probe = parse_probe(inner.next().unwrap())?;
+            let comparison = match inner.next().unwrap().as_str() {
+                "==" => Comparison::Equal,
+                "!=" => Comparison::NotEqual,
+                ">=" => Comparison::GreaterOrEqual,
+                "<=" => Comparison::LessOrEqual,
+                ">" => Comparison::Greater,
+                _ => Comparison::Less,
+            };
+            Action::Expect(probe, comparison, parse_word(inner.next().unwrap())?)
+        }
+        _ => unreachable!("not an action: {:?}", action.as_rule()),
+    };
+
+    Ok(Event {
+        cycle,
+        line,
+        tpu,
+        action,
+    })
+}
+
+fn parse_probe(pair: Pair<Rule>) -> Result<Probe, pest::error::Error<Rule>> {
+    let span = pair.as_span();
+    match pair.as_rule() {
+        Rule::ram => Ok(Probe::Ram(
+            parse_number(pair.into_inner().next().unwrap())? as usize
+        )),
+        Rule::digital_pin => parse_digital_pin(pair).map(Probe::Digital),
+        Rule::analog_pin => parse_analog_pin(pair).map(Probe::Analog),
+        _ => Register::from_str(pair.as_str())
+            .map(Probe::Register)
+            .map_err(|_| custom_error(format!("Invalid register: {}", pair.as_str()), span)),
+    }
+}
+
+fn parse_digital_pin(pair: Pair<Rule>) -> Result<DigitalPin, pest::error::Error<Rule>> {
+    pair.as_str()[1..]
+        .parse()
+        .ok()
+        .and_then(DigitalPin::from_repr)
+        .ok_or_else(|| {
+            custom_error(
+                format!("Invalid digital pin: {}", pair.as_str()),
+                pair.as_span(),
+            )
+        })
+}
+
+fn parse_analog_pin(pair: Pair<Rule>) -> Result<AnalogPin, pest::error::Error<Rule>> {
+    pair.as_str()[1..]
+        .parse()
+        .ok()
+        .and_then(AnalogPin::from_repr)
+        .ok_or_else(|| {
+            custom_error(
+                format!("Invalid analog pin: {}", pair.as_str()),
+                pair.as_span(),
+            )
+        })
+}
+
+fn parse_word(pair: Pair<Rule>) -> Result<u16, pest::error::Error<Rule>> {
+    let span = pair.as_span();
+    let number = parse_number(pair)?;
+    u16::try_from(number).map_err(|_| custom_error(format!("{number} doesn't fit in a word"), span))
+}
+
+fn parse_number(pair: Pair<Rule>) -> Result<u64, pest::error::Error<Rule>> {
+    let result = match pair.as_rule() {
+        Rule::hex_number => u64::from_str_radix(&pair.as_str()[2..], 16),
+        Rule::binary_number => u64::from_str_radix(&pair.as_str()[2..], 2),
+        _ => pair.as_str().parse(),
+    };
+    result.map_err(|_| custom_error(format!("Invalid number: {}", pair.as_str()), pair.as_span()))
+}
+
+fn custom_error(message: String, span: pest::Span) -> pest::error::Error<Rule> {
+    pest::error::Error::new_from_span(ErrorVariant::CustomError { message }, span)
+}
+
+/// What a scenario runs against
+pub trait Testbed {
+    /// Advance one cycle
+    fn tick(&mut self);
+
+    /// Ticks run so far
+    fn cycle(&self) -> u64;
+
+    fn tpu_mut(&mut self, index: usize) -> Option<&mut TPU>;
+}
+
+/// A single TPU is TPU 0
+impl Testbed for TPU {
+    fn tick(&mut self) {
+        TPU::tick(self);
+    }
+
+    fn cycle(&self) -> u64 {
+        self.state().cycle_count
+    }
+
+    fn tpu_mut(&mut self, index: usize) -> Option<&mut TPU> {
+        (index == 0).then_some(self)
+    }
+}
+
+impl Testbed for Network {
+    fn tick(&mut self) {
+        Network::tick(self);
+    }
+
+    fn cycle(&self) -> u64 {
+        Network::cycle(self)
+    }
+
+    fn tpu_mut(&mut self, index: usize) -> Option<&mut TPU> {
+        (index < self.len()).then(|| Network::tpu_mut(self, index))
+    }
+}
+
+/// An event that didn't go as the scenario said
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScenarioFailure {
+    pub cycle: u64,
+    /// Line of the script the event is on
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ScenarioFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "cycle {} (line {}): {}",
+            self.cycle, self.line, self.message
+        )
+    }
+}
+
+/// Ticks a testbed through a scenario, applying each event on its cycle and collecting the ones that fail.
+///
+/// Failing checks don't stop the run, so one run reports everything that went wrong.
+pub struct ScenarioRunner<T: Testbed> {
+    testbed: T,
+    scenario: Scenario,
+    failures: Vec<ScenarioFailure>,
+}
+
+impl<T: Testbed> ScenarioRunner<T> {
+    pub fn new(testbed: T, scenario: Scenario) -> Self {
+        Self {
+            testbed,
+            scenario,
+            failures: Vec::new(),
+        }
+    }
+
+    /// Tick until every event has happened, returning the failures
+    pub fn run(&mut self) -> &[ScenarioFailure] {
+        let events = std::mem::take(&mut self.scenario.events);
+        for event in &events {
+            while self.testbed.cycle() < event.cycle {
+                self.testbed.tick();
+            }
+            if self.testbed.cycle() > event.cycle {
+                self.fail(event, "the testbed had already run past this cycle".into());
+                continue;
+            }
+            self.apply(event);
+        }
+        self.scenario.events = events;
+        &self.failures
+    }
+
+    pub fn failures(&self) -> &[ScenarioFailure] {
+        &self.failures
+    }
+
+    pub fn testbed(&self) -> &T {
+        &self.testbed
+    }
+
+    pub fn testbed_mut(&mut self) -> &mut T {
+        &mut self.testbed
+    }
+
+    pub fn into_testbed(self) -> T {
+        self.testbed
+    }
+
+    fn apply(&mut self, event: &Event) {
+        let Some(tpu) = self.testbed.tpu_mut(event.tpu) else {
+            self.fail(event, format!("there is no TPU {}", event.tpu));
+            return;
+        };
+
+        let failure = match &event.action {
+            &Action::DriveDigital(pin, level) => tpu
+                .drive_digital_input(pin, level)
+                .err()
+                .map(|error| format!("couldn't drive D{}: {error:?}", pin as u16)),
+            &Action::DriveAnalog(pin, value) => tpu
+                .drive_analog_input(pin, value)
+                .err()
+                .map(|error| format!("couldn't drive A{}: {error:?}", pin as u16)),
+            Action::Packet { sender, words } => {
+                let address = tpu.state().network_address;
+                let packet = NetPacket::with_payload(*sender, address, words);
+                (!tpu.deliver_packet(packet)).then(|| {
+                    format!("packet from {sender:#06X} dropped, the incoming buffer is full")
+                })
+            }
+            &Action::Expect(probe, comparison, expected) => {
+                let actual = match probe {
+                    Probe::Register(register) => tpu.read_register(register),
+                    Probe::Ram(address) => tpu.read_ram(address),
+                    Probe::Digital(pin) => tpu.state().digital_pins[pin as usize] as u16,
+                    Probe::Analog(pin) => tpu.get_analog_pin(pin),
+                };
+                (!comparison.holds(actual, expected)).then(|| {
+                    format!(
+                        "expected {probe} {} {expected}, it was {actual}",
+                        comparison.symbol()
+                    )
+                })
+            }
+        };
+
+        if let Some(message) = failure {
+            self.fail(event, message);
+        }
+    }
+
+    fn fail(&mut self, event: &Event, message: String) {
+        self.failures.push(ScenarioFailure {
+            cycle: event.cycle,
+            line: event.line,
+            message,
+        });
+    }
+}
@@ -0,0 +1,138 @@
+use crate::network::Network;
+use crate::rgal::parse_program;
+use crate::scenario::{ScenarioFailure, ScenarioRunner, parse_scenario};
+use crate::shared::{AnalogPin, DigitalPin};
+use crate::tpu::TPU;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use strum::EnumCount;
+
+    const SAMPLE_PROGRAM: &str = include_str!("../../res/scenarios/button_and_reading.rgal");
+    const SAMPLE_SCENARIO: &str = include_str!("../../res/scenarios/button_and_reading.scenario");
+
+    /// The sample program with D2 and A1 as inputs
+    fn create_sample_tpu(network_address: u16) -> TPU {
+        let mut analog_config = [false; AnalogPin::COUNT];
+        analog_config[AnalogPin::Analog1 as usize] = true;
+        let mut digital_config = [false; DigitalPin::COUNT];
+        digital_config[DigitalPin::Digital2 as usize] = true;
+        TPU::new(
+            network_address,
+            analog_config,
+            digital_config,
+            parse_program(SAMPLE_PROGRAM).expect("parse failure"),
+        )
+    }
+
+    #[test]
+    fn test_sample_scenario() {
+        let scenario = parse_scenario(SAMPLE_SCENARIO).expect("parse failure");
+        let mut runner = ScenarioRunner::new(create_sample_tpu(0x1), scenario);
+
+        assert_eq!(runner.run(), []);
+        assert!(runner.testbed().halted());
+        assert_eq!(runner.testbed().state().cycle_count, 400);
+    }
+
+    #[test]
+    fn test_failing_expectations() {
+        // The button is never pressed, so nothing after it happens
+        let scenario = parse_scenario(
+            r#"@50 expect D1 == 0
+            // Events can be written out of order
+            @400 expect A0 >= 500
+            @250 packet 0x3 7 8
+            @300 drive D1 high
+            @400 expect RAM[0x20] == 0x7
+            @400 tpu 1 expect A == 0"#,
+        )
+        .expect("parse failure");
+        let mut runner = ScenarioRunner::new(create_sample_tpu(0x1), scenario);
+        runner.run();
+
+        let failure = |cycle, line, message: &str| ScenarioFailure {
+            cycle,
+            line,
+            message: message.into(),
+        };
+        assert_eq!(
+            runner.failures(),
+            [
+                failure(300, 5, "couldn't drive D1: NotAnInput"),
+                failure(400, 3, "expected A0 >= 500, it was 0"),
+                failure(400, 6, "expected RAM[0x0020] == 7, it was 0"),
+                failure(400, 7, "there is no TPU 1"),
+            ]
+        );
+        assert_eq!(
+            runner.failures()[1].to_string(),
+            "cycle 400 (line 3): expected A0 >= 500, it was 0"
+        );
+    }
+
+    #[test]
+    fn test_network_scenario() {
+        // TPU 0 waits for a value at 0x20 to send to TPU 1, which runs the sample program
+        let sender = TPU::new(
+            0x3,
+            [false; AnalogPin::COUNT],
+            [false; DigitalPin::COUNT],
+            parse_program("LDM R0, 0x20\nBEZ 0, R0\nLDR A, 0x2\nXMIT A, R0\nHLT")
+                .expect("parse failure"),
+        );
+        let mut network = Network::new();
+        network.add_tpu(sender);
+        network.add_tpu(create_sample_tpu(0x2));
+
+        let scenario = parse_scenario(
+            r#"@10 tpu 1 drive D2 high
+            @20 tpu 1 drive A1 42
+            @30 tpu 0 expect R0 == 0
+            @100 expect R0 == 0
+            @101 tpu 1 expect A0 == 0"#,
+        )
+        .expect("parse failure");
+        let mut runner = ScenarioRunner::new(network, scenario);
+        assert_eq!(runner.run(), []);
+
+        // Nothing was sent yet, hand TPU 0 a value and carry on
+        runner.testbed_mut().tpu_mut(0).write_ram_slice(0x20, &[5]);
+        let scenario = parse_scenario(
+            r#"@200 tpu 1 expect A0 == 500
+            @200 tpu 1 expect X == 0x3
+            @200 tpu 1 expect R2 == 42
+            @200 tpu 2 expect A == 0"#,
+        )
+        .expect("parse failure");
+        let mut runner = ScenarioRunner::new(runner.into_testbed(), scenario);
+        assert_eq!(
+            runner.run(),
+            [ScenarioFailure {
+                cycle: 200,
+                line: 4,
+                message: "there is no TPU 2".into(),
+            }]
+        );
+        assert!(runner.testbed().tpu(1).halted());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse_scenario("@10 drive D8 high").is_err());
+        assert!(parse_scenario("@10 drive A4 1").is_err());
+        assert!(parse_scenario("@10 drive D1 1").is_err());
+        assert!(parse_scenario("@10 packet 0x3 1 2 3 4 5").is_err());
+        assert!(parse_scenario("@10 expect R7 == 1").is_err());
+        assert!(parse_scenario("@10 expect A == 0x10000").is_err());
+        assert!(parse_scenario("10 expect A == 1").is_err());
+        assert!(parse_scenario("@10 expect A == 1 @20 expect A == 1").is_err());
+
+        // Blank lines and comments are fine anywhere
+        let scenario = parse_scenario("\n// Nothing yet\n\n@10 expect A == 1 // trailing\n\n")
+            .expect("parse failure");
+        assert_eq!(scenario.events.len(), 1);
+        assert_eq!(scenario.events[0].line, 4);
+    }
+}
@@ -1,8 +1,12 @@
+use alloc::format;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 use strum_macros::{EnumCount as EnumCountMacro, EnumIter, EnumString, FromRepr};
-use tls_derive::DisplayInstruction;
+use tls_derive::{DisplayInstruction, InstructionOpcode};
 
 /// Enum representing the available registers
 #[derive(Debug, Clone, Copy, FromRepr, EnumIter, EnumString, EnumCountMacro, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[repr(u8)]
 pub enum Register {
     A = 0,
@@ -17,13 +21,14 @@ pub enum Register {
     R6 = 9,
 }
 
-impl std::fmt::Display for Register {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl core::fmt::Display for Register {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(f, "{:?}", self)
     }
 }
 
 #[derive(Debug, Clone, Copy, FromRepr, EnumIter, EnumCountMacro, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[repr(u16)]
 pub enum AnalogPin {
     Analog0 = 0,
@@ -33,6 +38,7 @@ pub enum AnalogPin {
 }
 
 #[derive(Debug, Clone, Copy, FromRepr, EnumIter, EnumCountMacro, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[repr(u16)]
 pub enum DigitalPin {
     Digital0 = 0,
@@ -46,129 +52,481 @@ pub enum DigitalPin {
 }
 
 #[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct NetPacket {
     pub sender: u16,
     pub target: u16,
-    pub data: u16,
+    /// Number of words of the payload in use
+    pub length: u16,
+    pub payload: [u16; NetPacket::MAX_PAYLOAD],
+    /// Set when the packet is made, a packet damaged in transit no longer matches it
+    pub checksum: u16,
+    /// Hops left before FWD drops the packet
+    pub ttl: u8,
+    /// Lets a receiver keep different kinds of message apart, see RECVP, XMIT and the other instructions use 0
+    pub port: u8,
+}
+
+impl NetPacket {
+    /// Most words a single packet can carry
+    pub const MAX_PAYLOAD: usize = 4;
+    /// Hops a new packet may be forwarded
+    pub const DEFAULT_TTL: u8 = 8;
+
+    /// A packet carrying a single word, as sent by XMIT
+    pub fn new(sender: u16, target: u16, data: u16) -> Self {
+        Self::with_payload(sender, target, &[data])
+    }
+
+    /// A packet carrying the given words, anything past MAX_PAYLOAD is cut off
+    pub fn with_payload(sender: u16, target: u16, words: &[u16]) -> Self {
+        let length = words.len().min(Self::MAX_PAYLOAD);
+        let mut payload = [0; Self::MAX_PAYLOAD];
+        payload[..length].copy_from_slice(&words[..length]);
+        let mut packet = Self {
+            sender,
+            target,
+            length: length as u16,
+            payload,
+            checksum: 0,
+            ttl: Self::DEFAULT_TTL,
+            port: 0,
+        };
+        packet.checksum = packet.compute_checksum();
+        packet
+    }
+
+    /// The same packet sent to a port
+    pub fn on_port(mut self, port: u8) -> Self {
+        self.port = port;
+        self.checksum = self.compute_checksum();
+        self
+    }
+
+    /// CRC-16/CCITT of the sender, port, length and the words of the payload in use, the target and hop limit change
+    /// when the packet is forwarded so they aren't covered. The port is the high byte of the length word, so a packet
+    /// on port 0 has the same checksum it had before there were ports.
+    pub fn compute_checksum(&self) -> u16 {
+        [self.sender, (self.port as u16) << 8 | self.length]
+            .iter()
+            .chain(self.words())
+            .fold(0xFFFF, |crc, word| crc16_ccitt(crc, *word))
+    }
+
+    /// Whether the packet still matches its checksum
+    pub fn verify(&self) -> bool {
+        self.checksum == self.compute_checksum()
+    }
+
+    /// The words of the payload in use
+    pub fn words(&self) -> &[u16] {
+        &self.payload[..(self.length as usize).min(Self::MAX_PAYLOAD)]
+    }
+
+    /// The first word of the payload, 0 if the packet is empty
+    pub fn data(&self) -> u16 {
+        self.words().first().copied().unwrap_or(0)
+    }
+}
+
+/// Feed one word into a CRC-16/CCITT calculation, high byte first
+pub fn crc16_ccitt(crc: u16, word: u16) -> u16 {
+    let mut crc = crc;
+    for byte in word.to_be_bytes() {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum OperandValueType {
     Immediate(u16),
     Register(Register),
 }
 
-/// An instruction, comprising an opcode and operands
-#[derive(Debug, Clone, Copy, PartialEq, Eq, DisplayInstruction)]
+/// An instruction, comprising an opcode and operands.
+///
+/// Each variant's `#[opcode(..)]` is its number in `crate::opcode`. Opcodes are append-only, a new instruction takes
+/// the next unused one and an existing one is never renumbered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, DisplayInstruction, InstructionOpcode)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Instruction {
     // Stack operations
     /// Push operand to Stack
+    #[opcode(0x0001)]
     PUSH(OperandValueType),
     /// Pop a value from the stack into Register
+    #[opcode(0x0002)]
     POP(Register),
     /// Copy the value from Stack without removing it into Register
+    #[opcode(0x0003)]
     PEEK(Register, OperandValueType),
     /// Stack Clear
+    #[opcode(0x0004)]
     SCR,
     /// Read Stack Pointer into Register
+    #[opcode(0x0005)]
     RSP(Register),
+    /// Open a stack frame with operand locals, the frame pointer is kept in R6
+    #[opcode(0x0006)]
+    ENTER(OperandValueType),
+    /// Close the current stack frame
+    #[opcode(0x0007)]
+    LEAVE,
+    /// Copy a local of the current stack frame into Register
+    #[opcode(0x0008)]
+    PEEKF(Register, OperandValueType),
 
     // Network operations
+    #[opcode(0x0009)]
     XMIT(Register, OperandValueType),
+    #[opcode(0x000A)]
     RECV,
+    /// Send count words starting at address to the address in Register
+    #[opcode(0x000B)]
+    XMITM(Register, OperandValueType, OperandValueType),
+    /// Send operand 3 to port operand 2 of the address in Register
+    #[opcode(0x000C)]
+    XMITP(Register, OperandValueType, OperandValueType),
+    /// Receive the oldest packet on port operand like RECV, leaving packets on other ports waiting
+    #[opcode(0x000D)]
+    RECVP(OperandValueType),
+    /// Queue the low byte of operand for the UART to send, setting the carry flag if the FIFO is full
+    #[opcode(0x000E)]
+    UTX(OperandValueType),
+    /// Take a byte the UART received into Register, 0xFFFF if there is none
+    #[opcode(0x000F)]
+    URX(Register),
+    /// Send the oldest waiting packet on to the next hop for the final target in its first word, looked up in a
+    /// table in RAM starting at address
+    #[opcode(0x0010)]
+    FWD(OperandValueType),
+    /// Receive a packet like RECV, putting 1 into Register if it passes its checksum, otherwise 0
+    #[opcode(0x0011)]
+    RECVV(Register),
+    /// Receive a packet, storing its payload from address, the sender in X and the length in Y
+    #[opcode(0x0012)]
+    RECVM(OperandValueType),
+    #[opcode(0x0013)]
     TXBS,
+    #[opcode(0x0014)]
     RXBS,
+    /// Put 1 into Register if no packet has been dropped since the last TXOK, otherwise 0
+    #[opcode(0x0015)]
+    TXOK(Register),
+    /// Put 1 into Register if a packet is waiting to be received, otherwise 0
+    #[opcode(0x0016)]
+    RXAV(Register),
+    /// Put the TPU's network address into Register
+    #[opcode(0x0017)]
+    NETR(Register),
+    /// Set the TPU's network address to operand
+    #[opcode(0x0018)]
+    NETA(OperandValueType),
 
     // Math operators
+    #[opcode(0x0019)]
     ADD(Register, Register),
+    #[opcode(0x001A)]
     SUB(Register, Register),
+    #[opcode(0x001B)]
     MUL(Register, Register),
+    #[opcode(0x001C)]
     DIV(Register, Register),
+    #[opcode(0x001D)]
     MOD(Register, Register),
+    #[opcode(0x001E)]
     AND(Register, Register),
+    #[opcode(0x001F)]
     OR(Register, Register),
+    #[opcode(0x0020)]
     XOR(Register, Register),
+    #[opcode(0x0021)]
     NOT(Register),
+    #[opcode(0x0022)]
     INC(Register),
+    #[opcode(0x0023)]
     DEC(Register),
+    /// Compare Register with operand 2 and set the flags, no register is changed
+    #[opcode(0x0024)]
+    CMP(Register, OperandValueType),
 
     // Bitshifting operations
+    #[opcode(0x0025)]
     SLL(Register, Register, OperandValueType),
+    #[opcode(0x0026)]
     SLC(Register, Register, OperandValueType),
+    #[opcode(0x0027)]
     SLR(Register, Register, OperandValueType),
+    #[opcode(0x0028)]
     SRC(Register, Register, OperandValueType),
 
     // Rotate operations
+    #[opcode(0x0029)]
     ROL(Register, Register, OperandValueType),
+    #[opcode(0x002A)]
     ROR(Register, Register, OperandValueType),
 
     // Memory operations
     /// Register Copy
+    #[opcode(0x002B)]
     RCY(Register, Register),
     /// Register Move
+    #[opcode(0x002C)]
     RMV(Register, Register),
+    /// Copy operand 2 into Register 1 if Register 3 is zero
+    #[opcode(0x002D)]
+    CMOVZ(Register, OperandValueType, Register),
+    /// Copy operand 2 into Register 1 if Register 3 is not zero
+    #[opcode(0x002E)]
+    CMOVN(Register, OperandValueType, Register),
     /// Load Register
+    #[opcode(0x002F)]
     LDR(Register, OperandValueType),
     /// Load Register from Memory
+    #[opcode(0x0030)]
     LDM(Register, OperandValueType),
     /// Load Register from Memory w/Offset
+    #[opcode(0x0031)]
     LDO(Register, OperandValueType, Register),
     /// Load Register from Memory w/Offset+Inc
+    #[opcode(0x0032)]
     LDOI(Register, OperandValueType, Register),
     /// Store Memory
+    #[opcode(0x0033)]
     STM(OperandValueType, OperandValueType),
+    /// Load Register from Byte Address
+    #[opcode(0x0034)]
+    LDB(Register, OperandValueType),
+    /// Store Byte To Memory
+    #[opcode(0x0035)]
+    STB(OperandValueType, OperandValueType),
+    /// Set the Data Base used by LDRP and STRP
+    #[opcode(0x0036)]
+    SDB(OperandValueType),
+    /// Load Register from Memory relative to the Data Base
+    #[opcode(0x0037)]
+    LDRP(Register, OperandValueType),
+    /// Store Memory relative to the Data Base
+    #[opcode(0x0038)]
+    STRP(OperandValueType, OperandValueType),
+    /// CRC-16 of a RAM range
+    #[opcode(0x0039)]
+    CRC(OperandValueType, OperandValueType),
     /// Store Memory w/Offset
+    #[opcode(0x003A)]
     STMO(OperandValueType, OperandValueType, Register),
     /// Store Memory w/Offset+Inc
+    #[opcode(0x003B)]
     SMOI(OperandValueType, OperandValueType, Register),
+    /// Load Register from Memory w/Offset+Stride
+    #[opcode(0x003C)]
+    LDOS(Register, OperandValueType, Register, OperandValueType),
+    /// Store Memory w/Offset+Stride
+    #[opcode(0x003D)]
+    SMOS(
+        OperandValueType,
+        OperandValueType,
+        Register,
+        OperandValueType,
+    ),
+    /// Load Register from Non-Volatile Memory
+    #[opcode(0x003E)]
+    NVL(Register, OperandValueType),
+    /// Store Non-Volatile Memory
+    #[opcode(0x003F)]
+    NVS(OperandValueType, OperandValueType),
 
     // Digital Pin operations
+    #[opcode(0x0040)]
     DPW(OperandValueType, OperandValueType),
     //DPWH(OperandValueType),
+    #[opcode(0x0041)]
     DPR(Register, OperandValueType),
+    #[opcode(0x0042)]
     DPWW(OperandValueType),
+    #[opcode(0x0043)]
     DPRW(Register),
+    /// Put the number of queued pin events into Register, bit 15 is set if any were dropped
+    #[opcode(0x0044)]
+    PEVC(Register),
+    /// Pop the oldest pin event, X = pin, Y = level, A = low word of the cycle it happened on
+    #[opcode(0x0045)]
+    PEVR,
+    /// Read the rising edge count of digital pin operand 2 into Register
+    #[opcode(0x0046)]
+    PCNTR(Register, OperandValueType),
+    /// Clear the rising edge count of digital pin operand 1
+    #[opcode(0x0047)]
+    PCNTC(OperandValueType),
+    /// Set the direction of digital pin operand 1, 0 = output, anything else = input
+    #[opcode(0x0048)]
+    DCFG(OperandValueType, OperandValueType),
+    /// Read the direction of digital pin operand 2 into Register, 0 = output, 1 = input
+    #[opcode(0x0049)]
+    DCFR(Register, OperandValueType),
+    /// Set the PWM period in ticks of digital pin operand 1
+    #[opcode(0x004A)]
+    PWMC(OperandValueType, OperandValueType),
+    /// Set the PWM duty, the ticks per period the pin is high, of digital pin operand 1
+    #[opcode(0x004B)]
+    PWMD(OperandValueType, OperandValueType),
+    /// Enable PWM on digital pin operand 1, 0 disables it and drives the pin low
+    #[opcode(0x004C)]
+    PWMS(OperandValueType, OperandValueType),
 
     // Analog Pin operations
+    #[opcode(0x004D)]
     APW(OperandValueType, OperandValueType),
+    /// Set the threshold of the comparator on analog pin operand 1 to operand 2
+    #[opcode(0x004E)]
+    CMPT(OperandValueType, OperandValueType),
+    /// Set the direction of analog pin operand 1, 0 = output, anything else = input
+    #[opcode(0x004F)]
+    ACFG(OperandValueType, OperandValueType),
+    /// Read the direction of analog pin operand 2 into Register, 0 = output, 1 = input
+    #[opcode(0x0050)]
+    ACFR(Register, OperandValueType),
+    /// Read the top 4 bits of every analog pin into Register, pin 0 in the lowest nibble
+    #[opcode(0x0051)]
+    APRW(Register),
+    /// Write every analog pin from consecutive RAM words starting at the operand
+    #[opcode(0x0052)]
+    APWA(OperandValueType),
+    /// Read every analog pin into consecutive RAM words starting at the operand
+    #[opcode(0x0053)]
+    APRA(OperandValueType),
     //APWH(OperandValueType, OperandValueType),
+    #[opcode(0x0054)]
     APR(Register, OperandValueType),
 
     // Misc operations
+    #[opcode(0x0055)]
     NOP,
+    #[opcode(0x0056)]
     SLP(OperandValueType),
+    #[opcode(0x0057)]
     WRX,
+    /// Wait up to operand cycles for a packet, X is 0xFFFF if none arrived
+    #[opcode(0x0058)]
+    WRXT(OperandValueType),
+    #[opcode(0x0059)]
     HLT,
+    /// Reset the TPU
+    #[opcode(0x005A)]
+    RST,
 
     // Branching
+    #[opcode(0x005B)]
     JMP(OperandValueType),
+    #[opcode(0x005C)]
     BEZ(OperandValueType, Register),
+    #[opcode(0x005D)]
     BNZ(OperandValueType, Register),
+    #[opcode(0x005E)]
     BEQ(OperandValueType, Register, OperandValueType),
+    #[opcode(0x005F)]
     BNE(OperandValueType, Register, OperandValueType),
+    #[opcode(0x0060)]
     BGE(OperandValueType, Register, OperandValueType),
+    #[opcode(0x0061)]
     BLE(OperandValueType, Register, OperandValueType),
+    #[opcode(0x0062)]
     BGT(OperandValueType, Register, OperandValueType),
+    #[opcode(0x0063)]
     BLT(OperandValueType, Register, OperandValueType),
+    /// Decrement Register and branch to operand 1 if it's not zero
+    #[opcode(0x0064)]
+    DJNZ(OperandValueType, Register),
+    /// Jump to operand 1 plus the index in Register
+    #[opcode(0x0065)]
+    JTB(OperandValueType, Register),
+    /// Jump to operand 1 plus the index in Register, or fall through if the index is not below operand 3
+    #[opcode(0x0066)]
+    JTBN(OperandValueType, Register, OperandValueType),
+    /// Call the subroutine at operand 1 plus the index in Register
+    #[opcode(0x0067)]
+    JSRT(OperandValueType, Register),
+    /// Branch to operand 1 if the digital pin in operand 2 is high
+    #[opcode(0x0068)]
+    BPH(OperandValueType, OperandValueType),
+    /// Branch to operand 1 if the digital pin in operand 2 is low
+    #[opcode(0x0069)]
+    BPLW(OperandValueType, OperandValueType),
+    /// Run the lines up to and including operand 2, operand 1 times
+    #[opcode(0x006A)]
+    LOOPS(OperandValueType, OperandValueType),
+    /// Skip the next instruction if Register is zero
+    #[opcode(0x006B)]
+    SEZ(Register),
+    /// Skip the next instruction if Register is not zero
+    #[opcode(0x006C)]
+    SNZ(Register),
+    /// Branch if the carry flag is set
+    #[opcode(0x006D)]
+    BCS(OperandValueType),
+    /// Branch if the carry flag is clear
+    #[opcode(0x006E)]
+    BCC(OperandValueType),
+    /// Branch if the negative flag is set
+    #[opcode(0x006F)]
+    BMI(OperandValueType),
+    /// Branch if the negative flag is clear
+    #[opcode(0x0070)]
+    BPL(OperandValueType),
+    /// Branch if the zero flag is set
+    #[opcode(0x0071)]
+    BZS(OperandValueType),
+    /// Branch if the zero flag is clear
+    #[opcode(0x0072)]
+    BZC(OperandValueType),
 
     // Relative Branches
+    #[opcode(0x0073)]
     JPR(OperandValueType),
+    #[opcode(0x0074)]
     BREZ(OperandValueType, Register),
+    #[opcode(0x0075)]
     BRNZ(OperandValueType, Register),
+    #[opcode(0x0076)]
     BREQ(OperandValueType, Register, OperandValueType),
+    #[opcode(0x0077)]
     BRNE(OperandValueType, Register, OperandValueType),
+    #[opcode(0x0078)]
     BRGE(OperandValueType, Register, OperandValueType),
+    #[opcode(0x0079)]
     BRLE(OperandValueType, Register, OperandValueType),
+    #[opcode(0x007A)]
     BRGT(OperandValueType, Register, OperandValueType),
+    #[opcode(0x007B)]
     BRLT(OperandValueType, Register, OperandValueType),
 
     // Subroutines
+    #[opcode(0x007C)]
     JSR(OperandValueType),
+    #[opcode(0x007D)]
     RTS,
+    /// Software interrupt, call the trap vector with the code in operand 1
+    #[opcode(0x007E)]
+    SWI(OperandValueType),
+    /// Save the registers and jump absolute
+    #[opcode(0x007F)]
+    SJMP(OperandValueType),
+    /// Restore the registers saved by SJMP and return to the line after it
+    #[opcode(0x0080)]
+    SRET,
 }
 
-impl std::fmt::Display for OperandValueType {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl core::fmt::Display for OperandValueType {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             OperandValueType::Register(reg) => write!(f, "{}", format!("{:?}", reg)),
             OperandValueType::Immediate(val) => write!(f, "{:04X}", val),
@@ -196,11 +554,18 @@ pub(crate) enum ExecuteResult {
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
-pub(crate) enum HaltReason {
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum HaltReason {
     Div0,
     HLTOpcode,
     InvalidPC,
     InvalidValue,
     StackOverflow,
+    StackUnderflow,
     IndexOutOfRange,
+    WriteProtected,
+    NestedLoop,
+    UnhandledTrap,
+    EndOfProgram,
+    NoSavedContext,
 }
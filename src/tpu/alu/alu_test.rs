@@ -1,6 +1,8 @@
 use crate::shared::{ExecuteResult, OperandValueType, Register};
 use crate::tpu::alu::*;
-use crate::tpu::{ExecutionState, TPU, TpuState, create_basic_tpu_config};
+use crate::tpu::{
+    ExecutionState, Flags, PwmChannel, TPU, TpuConfig, TpuState, create_basic_tpu_config,
+};
 
 #[cfg(test)]
 mod tests {
@@ -12,23 +14,50 @@ mod tests {
     fn create_tpu_with_registers(a: u16, x: u16, y: u16) -> TPU {
         let mut tpu_state = TpuState {
             stack: Vec::new(),
+            stack_high_water: 0,
+            return_addresses: Vec::new(),
             analog_pins: [0; AnalogPin::COUNT],
             digital_pins: [false; DigitalPin::COUNT],
             analog_pin_config: [false; AnalogPin::COUNT],
             digital_pin_config: [true; DigitalPin::COUNT],
             ram: [0; TPU::RAM_SIZE],
+            nvram: Vec::new(),
             rom: Vec::new(),
             network_address: 0x1,
             incoming_packets: std::collections::VecDeque::new(),
             outgoing_packets: std::collections::VecDeque::new(),
             registers: [0; Register::COUNT],
+            flags: Flags::default(),
+            data_base: 0,
+            hardware_loop: None,
+            saved_context: None,
+            pwm: [PwmChannel::default(); DigitalPin::COUNT],
+            cycle_count: 0,
+            instructions_retired: 0,
+            pin_events: std::collections::VecDeque::new(),
+            pin_events_overflowed: false,
+            pulse_counts: [0; DigitalPin::COUNT],
+            bounce: [None; DigitalPin::COUNT],
+            bounce_rng: 1,
+            analog_inputs: [0; AnalogPin::COUNT],
+            noise_offsets: [0; AnalogPin::COUNT],
+            noise_rng: 1,
+            comparator_thresholds: [0; AnalogPin::COUNT],
+            tx_overflow: false,
+            tx_cooldown: 0,
+            ttl_drops: 0,
+            uart_tx: std::collections::VecDeque::new(),
+            uart_rx: std::collections::VecDeque::new(),
             program_counter: 0,
             halted: false,
+            halt_reason: None,
             execution_state: ExecutionState {
                 instruction: None,
                 wait_cycles: 0,
                 execute_each_cycle: false,
+                stall_cycles: 0,
             },
+            config: TpuConfig::default(),
         };
 
         // Set register values
@@ -116,6 +145,46 @@ mod tests {
         assert_eq!(tpu.read_register(Register::A), 65535); // Wrapping subtraction
     }
 
+    #[test]
+    fn test_op_cmp() {
+        // Test case 1: Equal
+        let mut tpu = create_tpu_with_registers(5, 0, 0);
+        let result = op_cmp(&mut tpu, &Register::A, &OperandValueType::Immediate(5));
+        assert_eq!(result, ExecuteResult::PCAdvance);
+        assert!(tpu.tpu_state.flags.zero);
+        assert!(!tpu.tpu_state.flags.carry);
+        assert!(!tpu.tpu_state.flags.negative);
+        assert_eq!(tpu.read_register(Register::A), 5); // Not written
+
+        // Test case 2: Lower sets the carry (borrow) and the negative flag
+        let mut tpu = create_tpu_with_registers(3, 5, 0);
+        let result = op_cmp(
+            &mut tpu,
+            &Register::A,
+            &OperandValueType::Register(Register::X),
+        );
+        assert_eq!(result, ExecuteResult::PCAdvance);
+        assert!(!tpu.tpu_state.flags.zero);
+        assert!(tpu.tpu_state.flags.carry);
+        assert!(tpu.tpu_state.flags.negative);
+
+        // Test case 3: Higher clears all of them
+        let mut tpu = create_tpu_with_registers(5, 3, 0);
+        let result = op_cmp(
+            &mut tpu,
+            &Register::A,
+            &OperandValueType::Register(Register::X),
+        );
+        assert_eq!(result, ExecuteResult::PCAdvance);
+        assert_eq!(tpu.tpu_state.flags, Flags::default());
+
+        // Test case 4: Negative is bit 15 of the result, even without a borrow
+        let mut tpu = create_tpu_with_registers(0x8000, 0, 0);
+        op_cmp(&mut tpu, &Register::A, &OperandValueType::Immediate(0));
+        assert!(tpu.tpu_state.flags.negative);
+        assert!(!tpu.tpu_state.flags.carry);
+    }
+
     #[test]
     fn test_op_mul() {
         // Test case 1: Basic multiplication
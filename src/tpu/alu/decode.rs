@@ -30,6 +30,15 @@ pub fn decode_op_sub() -> DecodeResult {
     }
 }
 
+pub fn decode_op_cmp(right: &OperandValueType) -> DecodeResult {
+    // Same as SUB when comparing two registers
+    let cycles = TPU::check_operand_cost(&[right]) + 1;
+    DecodeResult {
+        cycles,
+        call_every_cycle: false,
+    }
+}
+
 pub fn decode_op_mul() -> DecodeResult {
     DecodeResult {
         cycles: 4,
@@ -3,7 +3,7 @@ mod alu_test;
 pub mod decode;
 
 use crate::shared::{ExecuteResult, HaltReason, OperandValueType, Register};
-use crate::tpu::TPU;
+use crate::tpu::{Flags, TPU};
 
 // Math operators
 
@@ -32,6 +32,20 @@ pub fn op_add(tpu: &mut TPU, left: &Register, right: &Register) -> ExecuteResult
     ExecuteResult::PCAdvance
 }
 
+/// Subtract operand 2 from the register and set the flags from the result, which is discarded
+pub fn op_cmp(tpu: &mut TPU, left: &Register, right: &OperandValueType) -> ExecuteResult {
+    let a = tpu.read_register(*left);
+    let b = tpu.get_operand_value(right);
+    let (result, borrow) = a.overflowing_sub(b);
+
+    tpu.tpu_state.flags = Flags {
+        zero: result == 0,
+        carry: borrow,
+        negative: result & 0x8000 != 0,
+    };
+    ExecuteResult::PCAdvance
+}
+
 pub fn op_sub(tpu: &mut TPU, left: &Register, right: &Register) -> ExecuteResult {
     let a = tpu.read_register(*left);
     let b = tpu.read_register(*right);
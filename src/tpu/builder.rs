@@ -0,0 +1,167 @@
+use crate::network::Network;
+use crate::shared::{AnalogPin, DigitalPin, Instruction};
+use crate::tpu::{TPU, TpuConfig};
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::fmt;
+use strum::EnumCount;
+
+/// Which way a pin is driven, by the host or by the TPU
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum PinMode {
+    /// The host drives it and the program reads it
+    Input,
+    /// The program drives it
+    #[default]
+    Output,
+}
+
+/// Why `TpuBuilder` couldn't build a TPU
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ConfigError {
+    /// No program was given, or it has no instructions
+    EmptyProgram,
+    /// `TpuConfig::analog_resolution_bits` isn't from 1 to 16
+    AnalogResolution(u8),
+    /// `TpuConfig::trap_vector` is past the end of the program
+    TrapVector(u16),
+    /// Another TPU on the network already has the address
+    AddressInUse(u16),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::EmptyProgram => write!(f, "the program is empty"),
+            ConfigError::AnalogResolution(bits) => {
+                write!(f, "analog pins can't have {bits} bits, they have 1 to 16")
+            }
+            ConfigError::TrapVector(line) => {
+                write!(
+                    f,
+                    "the trap vector {line:#06X} is past the end of the program"
+                )
+            }
+            ConfigError::AddressInUse(address) => {
+                write!(f, "a TPU on the network already has address {address:#06X}")
+            }
+        }
+    }
+}
+
+impl core::error::Error for ConfigError {}
+
+/// Builds a TPU a setting at a time, anything not set is as `TPU::new` leaves it: address 0x1, every pin an output
+/// and the default `TpuConfig`.
+///
+/// ```
+/// use tls::prelude::*;
+///
+/// let tpu = TpuBuilder::new()
+///     .network_address(0x2)
+///     .digital_pin(DigitalPin::Digital0, PinMode::Input)
+///     .program(parse_program("DPR A, 0\nHLT").unwrap())
+///     .build()
+///     .unwrap();
+/// assert_eq!(tpu.state().network_address, 0x2);
+/// ```
+#[derive(Clone, Debug)]
+pub struct TpuBuilder {
+    network_address: u16,
+    analog_pin_config: [bool; AnalogPin::COUNT],
+    digital_pin_config: [bool; DigitalPin::COUNT],
+    program: Vec<Rc<Instruction>>,
+    config: TpuConfig,
+}
+
+impl Default for TpuBuilder {
+    fn default() -> Self {
+        Self {
+            network_address: 0x1,
+            analog_pin_config: [false; AnalogPin::COUNT],
+            digital_pin_config: [false; DigitalPin::COUNT],
+            program: Vec::new(),
+            config: TpuConfig::default(),
+        }
+    }
+}
+
+impl TpuBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn network_address(mut self, address: u16) -> Self {
+        self.network_address = address;
+        self
+    }
+
+    pub fn analog_pin(mut self, pin: AnalogPin, mode: PinMode) -> Self {
+        self.analog_pin_config[pin as usize] = mode == PinMode::Input;
+        self
+    }
+
+    pub fn digital_pin(mut self, pin: DigitalPin, mode: PinMode) -> Self {
+        self.digital_pin_config[pin as usize] = mode == PinMode::Input;
+        self
+    }
+
+    pub fn program(mut self, program: Vec<Rc<Instruction>>) -> Self {
+        self.program = program;
+        self
+    }
+
+    /// Words of non-volatile memory, see `TpuConfig::nvram_size`
+    pub fn nvram_size(mut self, words: usize) -> Self {
+        self.config.nvram_size = words;
+        self
+    }
+
+    /// Seed both the bounce pattern and the analog noise, so a run can be repeated exactly
+    pub fn seed(mut self, seed: u32) -> Self {
+        self.config.bounce_seed = seed;
+        self.config.noise_seed = seed;
+        self
+    }
+
+    /// Replace the whole config, settings made before this by `nvram_size` and `seed` are replaced with it
+    pub fn config(mut self, config: TpuConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn build(self) -> Result<TPU, ConfigError> {
+        if self.program.is_empty() {
+            return Err(ConfigError::EmptyProgram);
+        }
+        let bits = self.config.analog_resolution_bits;
+        if !(1..=16).contains(&bits) {
+            return Err(ConfigError::AnalogResolution(bits));
+        }
+        if let Some(line) = self.config.trap_vector
+            && usize::from(line) >= self.program.len()
+        {
+            return Err(ConfigError::TrapVector(line));
+        }
+        Ok(self.build_unchecked())
+    }
+
+    /// Build the TPU and connect it to `network`, returning its index, unless another TPU there has its address
+    pub fn build_on(self, network: &mut Network) -> Result<usize, ConfigError> {
+        if network.has_address(self.network_address) {
+            return Err(ConfigError::AddressInUse(self.network_address));
+        }
+        Ok(network.add_tpu(self.build()?))
+    }
+
+    /// Build without checking the settings, an empty program is fine
+    pub(crate) fn build_unchecked(self) -> TPU {
+        TPU::new_with_config(
+            self.network_address,
+            self.analog_pin_config,
+            self.digital_pin_config,
+            self.program,
+            self.config,
+        )
+    }
+}
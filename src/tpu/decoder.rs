@@ -1,9 +1,9 @@
+use crate::log::trace;
 use crate::shared::{DecodeResult, Instruction};
 use crate::tpu::flow::decode;
 use crate::tpu::{TPU, mmu};
 use crate::tpu::{alu, io_matrix};
-use std::rc::Rc;
-use tracing::trace;
+use alloc::rc::Rc;
 
 pub fn decode(instruction: &Rc<Instruction>) -> DecodeResult {
     trace!("DECODE: {instruction:?}");
@@ -15,12 +15,27 @@ pub fn decode(instruction: &Rc<Instruction>) -> DecodeResult {
         Instruction::PEEK(_, index) => mmu::decode::decode_op_peek(index),
         Instruction::SCR => mmu::decode::decode_op_scr(),
         Instruction::RSP(_) => mmu::decode::decode_op_rsp(),
+        Instruction::ENTER(locals) => mmu::decode::decode_op_enter(locals),
+        Instruction::LEAVE => mmu::decode::decode_op_leave(),
+        Instruction::PEEKF(_, index) => mmu::decode::decode_op_peekf(index),
 
         // Networking
         Instruction::XMIT(_, _) => io_matrix::decode::decode_op_xmit(),
         Instruction::RECV => io_matrix::decode::decode_op_recv(),
+        Instruction::RECVV(_) => io_matrix::decode::decode_op_recv(),
+        Instruction::XMITM(_, address, count) => io_matrix::decode::decode_op_xmitm(address, count),
+        Instruction::RECVM(address) => io_matrix::decode::decode_op_recvm(address),
+        Instruction::XMITP(_, port, data) => io_matrix::decode::decode_op_xmitp(port, data),
+        Instruction::RECVP(port) => io_matrix::decode::decode_op_recvp(port),
+        Instruction::FWD(table) => io_matrix::decode::decode_op_fwd(table),
+        Instruction::UTX(value) => io_matrix::decode::decode_op_utx(value),
+        Instruction::URX(_) => io_matrix::decode::decode_op_urx(),
         Instruction::TXBS => io_matrix::decode::decode_op_txbs(),
+        Instruction::TXOK(_) => io_matrix::decode::decode_op_txok(),
+        Instruction::RXAV(_) => io_matrix::decode::decode_op_rxav(),
         Instruction::RXBS => io_matrix::decode::decode_op_rxbs(),
+        Instruction::NETR(_) => io_matrix::decode::decode_op_netr(),
+        Instruction::NETA(address) => io_matrix::decode::decode_op_neta(address),
 
         // Arithmetic
         Instruction::ADD(_, _) => alu::decode::decode_op_add(),
@@ -34,6 +49,7 @@ pub fn decode(instruction: &Rc<Instruction>) -> DecodeResult {
         Instruction::NOT(_) => alu::decode::decode_op_not(),
         Instruction::INC(_) => alu::decode::decode_op_inc(),
         Instruction::DEC(_) => alu::decode::decode_op_dec(),
+        Instruction::CMP(_, right) => alu::decode::decode_op_cmp(right),
 
         // Bitwise
         Instruction::SLL(_, _, shift) => alu::decode::decode_op_sll(shift),
@@ -46,13 +62,24 @@ pub fn decode(instruction: &Rc<Instruction>) -> DecodeResult {
         // Memory/Register Data movement
         Instruction::RCY(_, _) => mmu::decode::decode_op_rcy(),
         Instruction::RMV(_, _) => mmu::decode::decode_op_rmv(),
+        Instruction::CMOVZ(_, _, _) | Instruction::CMOVN(_, _, _) => mmu::decode::decode_op_cmov(),
         Instruction::LDR(target, source) => mmu::decode::decode_op_ldr(target, source),
         Instruction::LDM(target, source) => mmu::decode::decode_op_ldm(target, source),
         Instruction::LDO(_, source, _) => mmu::decode::decode_op_ldo(source),
         Instruction::LDOI(_, source, _) => mmu::decode::decode_op_ldoi(source),
         Instruction::STM(_, source) => mmu::decode::decode_op_stm(source),
+        Instruction::LDB(_, source) => mmu::decode::decode_op_ldb(source),
+        Instruction::STB(target, source) => mmu::decode::decode_op_stb(target, source),
+        Instruction::SDB(value) => mmu::decode::decode_op_sdb(value),
+        Instruction::LDRP(_, offset) => mmu::decode::decode_op_ldrp(offset),
+        Instruction::STRP(_, source) => mmu::decode::decode_op_strp(source),
+        Instruction::CRC(_, _) => mmu::decode::decode_op_crc(),
         Instruction::STMO(_, source, _) => mmu::decode::decode_op_stmo(source),
         Instruction::SMOI(_, source, _) => mmu::decode::decode_op_smoi(source),
+        Instruction::LDOS(_, source, _, stride) => mmu::decode::decode_op_ldos(source, stride),
+        Instruction::SMOS(_, source, _, stride) => mmu::decode::decode_op_smos(source, stride),
+        Instruction::NVL(_, source) => mmu::decode::decode_op_nvl(source),
+        Instruction::NVS(target, source) => mmu::decode::decode_op_nvs(target, source),
 
         // Digital I/O
         Instruction::DPW(target, value) => io_matrix::decode::decode_op_dpw(target, value),
@@ -60,9 +87,28 @@ pub fn decode(instruction: &Rc<Instruction>) -> DecodeResult {
         Instruction::DPR(_, source) => io_matrix::decode::decode_op_dpr(source),
         Instruction::DPWW(value) => io_matrix::decode::decode_op_dpww(value),
         Instruction::DPRW(_) => io_matrix::decode::decode_op_dprw(),
+        Instruction::APRW(_) => io_matrix::decode::decode_op_aprw(),
+        Instruction::PEVC(_) => io_matrix::decode::decode_op_pevc(),
+        Instruction::PEVR => io_matrix::decode::decode_op_pevr(),
+        Instruction::PCNTR(_, pin) | Instruction::PCNTC(pin) => {
+            io_matrix::decode::decode_op_pulse_counter(pin)
+        }
+        Instruction::APWA(base) | Instruction::APRA(base) => {
+            io_matrix::decode::decode_op_analog_block(base)
+        }
 
         // Analog I/O
         Instruction::APW(target, source) => io_matrix::decode::decode_op_apw(target, source),
+        Instruction::CMPT(pin, threshold) => io_matrix::decode::decode_op_cmpt(pin, threshold),
+        Instruction::PWMC(pin, value)
+        | Instruction::PWMD(pin, value)
+        | Instruction::PWMS(pin, value) => io_matrix::decode::decode_op_pwm(pin, value),
+        Instruction::DCFR(_, _) | Instruction::ACFR(_, _) => {
+            io_matrix::decode::decode_op_pin_config_read()
+        }
+        Instruction::DCFG(pin, direction) | Instruction::ACFG(pin, direction) => {
+            io_matrix::decode::decode_op_pin_config(pin, direction)
+        }
         // Instruction::APWH => io_matrix::decode::decode_op_apwh(operands),
         Instruction::APR(_, source) => io_matrix::decode::decode_op_apr(source),
 
@@ -70,12 +116,27 @@ pub fn decode(instruction: &Rc<Instruction>) -> DecodeResult {
         Instruction::NOP => TPU::decode_op_nop(),
         Instruction::SLP(_) => TPU::decode_op_slp(),
         Instruction::WRX => TPU::decode_op_wrx(),
+        Instruction::WRXT(_) => TPU::decode_op_wrxt(),
         Instruction::HLT => TPU::decode_op_hlt(),
+        Instruction::RST => TPU::decode_op_rst(),
 
         // Branching - Absolute
         Instruction::JMP(target) => decode::decode_op_jmp(target),
         Instruction::BEZ(_, _) => decode::decode_op_bez(),
         Instruction::BNZ(_, _) => decode::decode_op_bnz(),
+        Instruction::DJNZ(target, _) => decode::decode_op_djnz(target),
+        Instruction::JTB(_, _) => decode::decode_op_jtb(),
+        Instruction::JSRT(_, _) => decode::decode_op_jsrt(),
+        Instruction::SEZ(_) | Instruction::SNZ(_) => decode::decode_op_skip(),
+        Instruction::LOOPS(count, end) => decode::decode_op_loops(count, end),
+        Instruction::BPH(_, _) | Instruction::BPLW(_, _) => decode::decode_op_pin_branch(),
+        Instruction::JTBN(_, _, count) => decode::decode_op_jtbn(count),
+        Instruction::BCS(target)
+        | Instruction::BCC(target)
+        | Instruction::BMI(target)
+        | Instruction::BPL(target)
+        | Instruction::BZS(target)
+        | Instruction::BZC(target) => decode::decode_op_flag_branch(target),
         Instruction::BEQ(_, _, _) => decode::decode_op_beq(),
         Instruction::BNE(_, _, _) => decode::decode_op_bne(),
         Instruction::BGE(_, _, _) => decode::decode_op_bge(),
@@ -97,5 +158,8 @@ pub fn decode(instruction: &Rc<Instruction>) -> DecodeResult {
         // Subroutines
         Instruction::JSR(target) => decode::decode_op_jsr(target),
         Instruction::RTS => decode::decode_op_rts(),
+        Instruction::SWI(code) => decode::decode_op_swi(code),
+        Instruction::SJMP(target) => decode::decode_op_sjmp(target),
+        Instruction::SRET => decode::decode_op_sret(),
     }
 }
@@ -0,0 +1,54 @@
+use crate::shared::{AnalogPin, DigitalPin, Register};
+use crate::tpu::TpuState;
+use alloc::vec::Vec;
+use strum::IntoEnumIterator;
+
+/// What changed between two states of the same TPU, see `TpuState::diff`
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct StateDiff {
+    pub registers: Vec<Register>,
+    /// RAM addresses, NVRAM isn't included
+    pub ram: Vec<usize>,
+    /// Stack slots, counted from the bottom, that were pushed or now hold something else. Pops aren't included.
+    pub stack: Vec<usize>,
+    pub digital_pins: Vec<DigitalPin>,
+    pub analog_pins: Vec<AnalogPin>,
+    pub flags: bool,
+}
+
+impl StateDiff {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl TpuState {
+    /// What's different in this state from `before`, e.g. a copy taken before a tick
+    pub fn diff(&self, before: &TpuState) -> StateDiff {
+        StateDiff {
+            registers: Register::iter()
+                .filter(|&register| {
+                    self.registers[register as usize] != before.registers[register as usize]
+                })
+                .collect(),
+            ram: changed(&self.ram, &before.ram),
+            stack: changed(&self.stack, &before.stack),
+            digital_pins: DigitalPin::iter()
+                .filter(|&pin| self.digital_pins[pin as usize] != before.digital_pins[pin as usize])
+                .collect(),
+            analog_pins: AnalogPin::iter()
+                .filter(|&pin| self.analog_pins[pin as usize] != before.analog_pins[pin as usize])
+                .collect(),
+            flags: self.flags != before.flags,
+        }
+    }
+}
+
+/// Indexes of `now` that are new or hold something else than in `before`
+fn changed<T: PartialEq>(now: &[T], before: &[T]) -> Vec<usize> {
+    now.iter()
+        .enumerate()
+        .filter(|&(index, value)| before.get(index) != Some(value))
+        .map(|(index, _)| index)
+        .collect()
+}
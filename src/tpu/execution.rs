@@ -1,7 +1,7 @@
 use crate::shared::{ExecuteResult, Instruction};
 use crate::tpu::{TPU, alu, flow, io_matrix, mmu};
 
-pub fn execute(tpu: &mut TPU, instruction: &Instruction, _: u16) -> ExecuteResult {
+pub fn execute(tpu: &mut TPU, instruction: &Instruction, wait_cycles: u16) -> ExecuteResult {
     let result = match instruction {
         // Stack operations
         Instruction::PUSH(source) => mmu::op_push(tpu, source),
@@ -9,13 +9,31 @@ pub fn execute(tpu: &mut TPU, instruction: &Instruction, _: u16) -> ExecuteResul
         Instruction::PEEK(target, source) => mmu::op_peek(tpu, target, source),
         Instruction::SCR => mmu::op_scr(tpu),
         Instruction::RSP(target) => mmu::op_rsp(tpu, target),
+        Instruction::ENTER(locals) => mmu::op_enter(tpu, locals),
+        Instruction::LEAVE => mmu::op_leave(tpu),
+        Instruction::PEEKF(target, index) => mmu::op_peekf(tpu, target, index),
 
         // Networking
         Instruction::XMIT(target, data) => io_matrix::op_xmit(tpu, target, data),
         Instruction::RECV => io_matrix::op_recv(tpu),
+        Instruction::RECVV(status) => io_matrix::op_recvv(tpu, status),
+        Instruction::XMITM(target, address, count) => {
+            io_matrix::op_xmitm(tpu, target, address, count)
+        }
+        Instruction::RECVM(address) => io_matrix::op_recvm(tpu, address),
+        Instruction::XMITP(target, port, data) => io_matrix::op_xmitp(tpu, target, port, data),
+        Instruction::RECVP(port) => io_matrix::op_recvp(tpu, port),
+        Instruction::FWD(table) => io_matrix::op_fwd(tpu, table),
+        Instruction::UTX(value) => io_matrix::op_utx(tpu, value),
+        Instruction::URX(target) => io_matrix::op_urx(tpu, target),
         Instruction::TXBS => io_matrix::op_txbs(tpu),
+        Instruction::TXOK(target) => io_matrix::op_txok(tpu, target),
+        Instruction::RXAV(target) => io_matrix::op_rxav(tpu, target),
         Instruction::RXBS => io_matrix::op_rxbs(tpu),
+        Instruction::NETR(target) => io_matrix::op_netr(tpu, target),
+        Instruction::NETA(address) => io_matrix::op_neta(tpu, address),
         Instruction::WRX => TPU::op_wrx(tpu),
+        Instruction::WRXT(timeout) => TPU::op_wrxt(tpu, timeout, wait_cycles),
 
         // Arithmetic
         Instruction::ADD(left, right) => alu::op_add(tpu, left, right),
@@ -29,6 +47,7 @@ pub fn execute(tpu: &mut TPU, instruction: &Instruction, _: u16) -> ExecuteResul
         Instruction::NOT(value) => alu::op_not(tpu, value),
         Instruction::INC(target) => alu::op_inc(tpu, target),
         Instruction::DEC(target) => alu::op_dec(tpu, target),
+        Instruction::CMP(left, right) => alu::op_cmp(tpu, left, right),
 
         // Bitwise
         Instruction::SLL(target, source, shift) => alu::op_sll(tpu, target, source, shift),
@@ -41,13 +60,33 @@ pub fn execute(tpu: &mut TPU, instruction: &Instruction, _: u16) -> ExecuteResul
         // Memory/Register Data movement
         Instruction::RCY(target, source) => mmu::op_rcy(tpu, target, source),
         Instruction::RMV(target, source) => mmu::op_rmv(tpu, target, source),
+        Instruction::CMOVZ(target, source, condition) => {
+            mmu::op_cmovz(tpu, target, source, condition)
+        }
+        Instruction::CMOVN(target, source, condition) => {
+            mmu::op_cmovn(tpu, target, source, condition)
+        }
         Instruction::LDR(target, source) => mmu::op_ldr(tpu, target, source),
         Instruction::LDM(target, source) => mmu::op_ldm(tpu, target, source),
         Instruction::LDO(target, source, offset) => mmu::op_ldo(tpu, target, source, offset),
         Instruction::LDOI(target, source, offset) => mmu::op_ldoi(tpu, target, source, offset),
         Instruction::STM(target, source) => mmu::op_stm(tpu, target, source),
+        Instruction::LDB(target, source) => mmu::op_ldb(tpu, target, source),
+        Instruction::STB(target, source) => mmu::op_stb(tpu, target, source),
+        Instruction::SDB(value) => mmu::op_sdb(tpu, value),
+        Instruction::LDRP(target, offset) => mmu::op_ldrp(tpu, target, offset),
+        Instruction::STRP(offset, source) => mmu::op_strp(tpu, offset, source),
+        Instruction::CRC(address, count) => mmu::op_crc(tpu, address, count, wait_cycles),
         Instruction::STMO(target, source, offset) => mmu::op_stmo(tpu, target, source, offset),
         Instruction::SMOI(target, source, offset) => mmu::op_smoi(tpu, target, source, offset),
+        Instruction::LDOS(target, source, offset, stride) => {
+            mmu::op_ldos(tpu, target, source, offset, stride)
+        }
+        Instruction::SMOS(target, source, offset, stride) => {
+            mmu::op_smos(tpu, target, source, offset, stride)
+        }
+        Instruction::NVL(target, source) => mmu::op_nvl(tpu, target, source),
+        Instruction::NVS(target, source) => mmu::op_nvs(tpu, target, source),
 
         // Digital I/O
         Instruction::DPW(target, source) => io_matrix::op_dpw(tpu, target, source),
@@ -55,16 +94,32 @@ pub fn execute(tpu: &mut TPU, instruction: &Instruction, _: u16) -> ExecuteResul
         Instruction::DPR(target, source) => io_matrix::op_dpr(tpu, target, source),
         Instruction::DPWW(value) => io_matrix::op_dpww(tpu, value),
         Instruction::DPRW(target) => io_matrix::op_dprw(tpu, target),
+        Instruction::APRW(target) => io_matrix::op_aprw(tpu, target),
+        Instruction::PEVC(target) => io_matrix::op_pevc(tpu, target),
+        Instruction::PEVR => io_matrix::op_pevr(tpu),
+        Instruction::PCNTR(target, pin) => io_matrix::op_pcntr(tpu, target, pin),
+        Instruction::PCNTC(pin) => io_matrix::op_pcntc(tpu, pin),
+        Instruction::APWA(base) => io_matrix::op_apwa(tpu, base),
+        Instruction::APRA(base) => io_matrix::op_apra(tpu, base),
 
         // Analog I/O
         Instruction::APW(target, source) => io_matrix::op_apw(tpu, target, source),
+        Instruction::CMPT(pin, threshold) => io_matrix::op_cmpt(tpu, pin, threshold),
+        Instruction::DCFG(pin, direction) => io_matrix::op_dcfg(tpu, pin, direction),
+        Instruction::PWMC(pin, period) => io_matrix::op_pwmc(tpu, pin, period),
+        Instruction::PWMD(pin, duty) => io_matrix::op_pwmd(tpu, pin, duty),
+        Instruction::PWMS(pin, enable) => io_matrix::op_pwms(tpu, pin, enable),
+        Instruction::DCFR(target, pin) => io_matrix::op_dcfr(tpu, target, pin),
+        Instruction::ACFR(target, pin) => io_matrix::op_acfr(tpu, target, pin),
+        Instruction::ACFG(pin, direction) => io_matrix::op_acfg(tpu, pin, direction),
         // Instruction::APWH => io_matrix::op_apwh(tpu, operands),
         Instruction::APR(target, source) => io_matrix::op_apr(tpu, target, source),
 
         // Misc
-        Instruction::SLP(value) => tpu.op_slp(value),
-        Instruction::NOP => TPU::op_nop(),
-        Instruction::HLT => TPU::op_hlt(),
+        Instruction::SLP(value) => TPU::op_slp(tpu, value),
+        Instruction::NOP => TPU::op_nop(tpu),
+        Instruction::HLT => TPU::op_hlt(tpu),
+        Instruction::RST => TPU::op_rst(tpu),
 
         // Branching - Absolute
         Instruction::JMP(target) => flow::op_jmp(tpu, target),
@@ -76,6 +131,21 @@ pub fn execute(tpu: &mut TPU, instruction: &Instruction, _: u16) -> ExecuteResul
         Instruction::BLE(target, source, value) => flow::op_ble(tpu, target, source, value),
         Instruction::BGT(target, source, value) => flow::op_bgt(tpu, target, source, value),
         Instruction::BLT(target, source, value) => flow::op_blt(tpu, target, source, value),
+        Instruction::DJNZ(target, counter) => flow::op_djnz(tpu, target, counter),
+        Instruction::JTB(base, index) => flow::op_jtb(tpu, base, index),
+        Instruction::JSRT(base, index) => flow::op_jsrt(tpu, base, index),
+        Instruction::SEZ(source) => flow::op_sez(tpu, source),
+        Instruction::LOOPS(count, end) => flow::op_loops(tpu, count, end),
+        Instruction::BPH(target, pin) => flow::op_bph(tpu, target, pin),
+        Instruction::BPLW(target, pin) => flow::op_bplw(tpu, target, pin),
+        Instruction::SNZ(source) => flow::op_snz(tpu, source),
+        Instruction::JTBN(base, index, count) => flow::op_jtbn(tpu, base, index, count),
+        Instruction::BCS(target) => flow::op_bcs(tpu, target),
+        Instruction::BCC(target) => flow::op_bcc(tpu, target),
+        Instruction::BMI(target) => flow::op_bmi(tpu, target),
+        Instruction::BPL(target) => flow::op_bpl(tpu, target),
+        Instruction::BZS(target) => flow::op_bzs(tpu, target),
+        Instruction::BZC(target) => flow::op_bzc(tpu, target),
 
         // Branching - Relative
         Instruction::JPR(target) => flow::op_jpr(tpu, target),
@@ -90,6 +160,9 @@ pub fn execute(tpu: &mut TPU, instruction: &Instruction, _: u16) -> ExecuteResul
 
         // Subroutines
         Instruction::JSR(target) => flow::op_jsr(tpu, target),
+        Instruction::SWI(code) => flow::op_swi(tpu, code),
+        Instruction::SJMP(target) => flow::op_sjmp(tpu, target),
+        Instruction::SRET => flow::op_sret(tpu),
         Instruction::RTS => flow::op_rts(tpu),
     };
     result
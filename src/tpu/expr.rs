@@ -0,0 +1,455 @@
+use crate::shared::{AnalogPin, DigitalPin, Register};
+use crate::tpu::TPU;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
+
+/// An expression over a TPU's state, like `A`, `ram[0x20] + 1` or `dpins & 0b11 == 3`, for watches.
+///
+/// Values are 64 bit signed so cycle counts fit and `A - 1` can go below zero. Numbers can be decimal, hex or
+/// binary. What can be read:
+///
+/// - registers by name, `A`, `X`, `Y` and `R0` to `R6`
+/// - `ram[ADDRESS]`, the word stored at an address, memory mapped pins aren't read through it
+/// - `pin(N)`, 1 if digital pin N is high, and `apin(N)`, the value of analog pin N
+/// - `pc`, `cycles`, `depth` (values on the stack), `dpins` (the digital pins as a word), `rxbs` and `txbs`
+///   (packets waiting to be received and sent)
+///
+/// The operators are Rust's, with the same precedence, comparisons and `&&`, `||` and `!` give 1 or 0.
+/// Names aren't case sensitive, and two expressions are equal if they read the same whatever their spacing or case.
+#[derive(Clone, Debug)]
+pub struct Expr {
+    text: String,
+    node: Node,
+}
+
+/// Why an expression couldn't be parsed, positions are in characters from the start
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ExprError {
+    Empty,
+    /// The expression stopped before something it needed, like a closing bracket
+    UnexpectedEnd,
+    Unexpected {
+        position: usize,
+        found: String,
+    },
+    UnknownName {
+        position: usize,
+        name: String,
+    },
+    InvalidNumber {
+        position: usize,
+        number: String,
+    },
+}
+
+/// Why an expression couldn't be evaluated on a TPU
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum EvalError {
+    AddressOutOfRange(i64),
+    NoDigitalPin(i64),
+    NoAnalogPin(i64),
+    DivideByZero,
+}
+
+impl fmt::Display for ExprError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExprError::Empty => write!(f, "type an expression"),
+            ExprError::UnexpectedEnd => write!(f, "the expression ends too soon"),
+            ExprError::Unexpected { position, found } => {
+                write!(f, "didn't expect {found} at {position}")
+            }
+            ExprError::UnknownName { position, name } => {
+                write!(f, "nothing called {name} at {position}")
+            }
+            ExprError::InvalidNumber { position, number } => {
+                write!(f, "{number} at {position} isn't a number")
+            }
+        }
+    }
+}
+
+impl core::error::Error for ExprError {}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalError::AddressOutOfRange(address) if *address < 0 => {
+                write!(f, "{address} isn't in RAM")
+            }
+            EvalError::AddressOutOfRange(address) => write!(f, "{address:#X} isn't in RAM"),
+            EvalError::NoDigitalPin(pin) => write!(f, "no digital pin {pin}"),
+            EvalError::NoAnalogPin(pin) => write!(f, "no analog pin {pin}"),
+            EvalError::DivideByZero => write!(f, "divide by zero"),
+        }
+    }
+}
+
+impl core::error::Error for EvalError {}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Value {
+    Register(Register),
+    ProgramCounter,
+    Cycles,
+    StackDepth,
+    DigitalPins,
+    RxBuffer,
+    TxBuffer,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Lookup {
+    Ram,
+    DigitalPin,
+    AnalogPin,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum UnaryOp {
+    Negate,
+    Not,
+    BitNot,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Shl,
+    Shr,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Node {
+    Number(i64),
+    Value(Value),
+    Lookup(Lookup, Box<Node>),
+    Unary(UnaryOp, Box<Node>),
+    Binary(BinaryOp, Box<Node>, Box<Node>),
+}
+
+/// Binary operators from the loosest binding to the tightest
+const PRECEDENCE: [&[(&str, BinaryOp)]; 9] = [
+    &[("||", BinaryOp::Or)],
+    &[("&&", BinaryOp::And)],
+    &[
+        ("==", BinaryOp::Eq),
+        ("!=", BinaryOp::Ne),
+        ("<=", BinaryOp::Le),
+        (">=", BinaryOp::Ge),
+        ("<", BinaryOp::Lt),
+        (">", BinaryOp::Gt),
+    ],
+    &[("|", BinaryOp::BitOr)],
+    &[("^", BinaryOp::BitXor)],
+    &[("&", BinaryOp::BitAnd)],
+    &[("<<", BinaryOp::Shl), (">>", BinaryOp::Shr)],
+    &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+    &[
+        ("*", BinaryOp::Mul),
+        ("/", BinaryOp::Div),
+        ("%", BinaryOp::Rem),
+    ],
+];
+
+impl Expr {
+    pub fn parse(text: &str) -> Result<Self, ExprError> {
+        let mut parser = Parser {
+            chars: text.chars().collect(),
+            position: 0,
+        };
+        parser.skip_whitespace();
+        if parser.peek().is_none() {
+            return Err(ExprError::Empty);
+        }
+        let node = parser.binary(0)?;
+        parser.skip_whitespace();
+        if let Some(c) = parser.peek() {
+            return Err(parser.unexpected(c));
+        }
+
+        Ok(Self {
+            text: text.trim().to_string(),
+            node,
+        })
+    }
+
+    pub fn eval(&self, tpu: &TPU) -> Result<i64, EvalError> {
+        eval(&self.node, tpu)
+    }
+}
+
+impl PartialEq for Expr {
+    fn eq(&self, other: &Self) -> bool {
+        self.node == other.node
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+impl FromStr for Expr {
+    type Err = ExprError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        Self::parse(text)
+    }
+}
+
+fn eval(node: &Node, tpu: &TPU) -> Result<i64, EvalError> {
+    let state = tpu.state();
+    Ok(match node {
+        Node::Number(number) => *number,
+        Node::Value(value) => match value {
+            Value::Register(register) => tpu.read_register(*register).into(),
+            Value::ProgramCounter => state.program_counter as i64,
+            Value::Cycles => state.cycle_count as i64,
+            Value::StackDepth => state.stack.len() as i64,
+            Value::DigitalPins => tpu.get_digital_pins().into(),
+            Value::RxBuffer => state.incoming_packets.len() as i64,
+            Value::TxBuffer => state.outgoing_packets.len() as i64,
+        },
+        Node::Lookup(lookup, index) => {
+            let index = eval(index, tpu)?;
+            match lookup {
+                Lookup::Ram => usize::try_from(index)
+                    .ok()
+                    .and_then(|address| state.ram.get(address))
+                    .map(|&word| word.into())
+                    .ok_or(EvalError::AddressOutOfRange(index))?,
+                Lookup::DigitalPin => u16::try_from(index)
+                    .ok()
+                    .and_then(DigitalPin::from_repr)
+                    .map(|pin| state.digital_pins[pin as usize].into())
+                    .ok_or(EvalError::NoDigitalPin(index))?,
+                Lookup::AnalogPin => u16::try_from(index)
+                    .ok()
+                    .and_then(AnalogPin::from_repr)
+                    .map(|pin| state.analog_pins[pin as usize].into())
+                    .ok_or(EvalError::NoAnalogPin(index))?,
+            }
+        }
+        Node::Unary(op, operand) => {
+            let operand = eval(operand, tpu)?;
+            match op {
+                UnaryOp::Negate => operand.wrapping_neg(),
+                UnaryOp::Not => (operand == 0).into(),
+                UnaryOp::BitNot => !operand,
+            }
+        }
+        // Short circuit so `depth > 0 && ram[..]` style guards work
+        Node::Binary(BinaryOp::And, left, right) => {
+            (eval(left, tpu)? != 0 && eval(right, tpu)? != 0).into()
+        }
+        Node::Binary(BinaryOp::Or, left, right) => {
+            (eval(left, tpu)? != 0 || eval(right, tpu)? != 0).into()
+        }
+        Node::Binary(op, left, right) => {
+            let (left, right) = (eval(left, tpu)?, eval(right, tpu)?);
+            // Shifting by 64 or more, or by a negative amount, shifts everything out
+            let shift = u32::try_from(right).ok().filter(|&shift| shift < i64::BITS);
+            match op {
+                BinaryOp::Eq => (left == right).into(),
+                BinaryOp::Ne => (left != right).into(),
+                BinaryOp::Lt => (left < right).into(),
+                BinaryOp::Le => (left <= right).into(),
+                BinaryOp::Gt => (left > right).into(),
+                BinaryOp::Ge => (left >= right).into(),
+                BinaryOp::BitOr => left | right,
+                BinaryOp::BitXor => left ^ right,
+                BinaryOp::BitAnd => left & right,
+                BinaryOp::Shl => shift.map_or(0, |shift| left << shift),
+                BinaryOp::Shr => shift.map_or(if left < 0 { -1 } else { 0 }, |shift| left >> shift),
+                BinaryOp::Add => left.wrapping_add(right),
+                BinaryOp::Sub => left.wrapping_sub(right),
+                BinaryOp::Mul => left.wrapping_mul(right),
+                BinaryOp::Div if right == 0 => return Err(EvalError::DivideByZero),
+                BinaryOp::Div => left.wrapping_div(right),
+                BinaryOp::Rem if right == 0 => return Err(EvalError::DivideByZero),
+                BinaryOp::Rem => left.wrapping_rem(right),
+                BinaryOp::And | BinaryOp::Or => unreachable!("short circuited above"),
+            }
+        }
+    })
+}
+
+struct Parser {
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.position += 1;
+        }
+    }
+
+    /// Move past `token` if it's next, `=` and `==` aren't confused, nor `&` and `&&`
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        let len = token.chars().count();
+        let matches = self
+            .chars
+            .get(self.position..self.position + len)
+            .is_some_and(|next| next.iter().copied().eq(token.chars()));
+        let doubled = matches!(token, "&" | "|" | "<" | ">")
+            && self.chars.get(self.position + len) == token.chars().next().as_ref();
+        let comparison =
+            matches!(token, "<" | ">" | "!") && self.chars.get(self.position + len) == Some(&'=');
+        if matches && !doubled && !comparison {
+            self.position += len;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: char) -> Result<(), ExprError> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(c) if c == token => {
+                self.position += 1;
+                Ok(())
+            }
+            Some(c) => Err(self.unexpected(c)),
+            None => Err(ExprError::UnexpectedEnd),
+        }
+    }
+
+    fn unexpected(&self, c: char) -> ExprError {
+        ExprError::Unexpected {
+            position: self.position,
+            found: c.to_string(),
+        }
+    }
+
+    /// Operators binding at least as tightly as `PRECEDENCE[level]`, left to right
+    fn binary(&mut self, level: usize) -> Result<Node, ExprError> {
+        let Some(operators) = PRECEDENCE.get(level) else {
+            return self.unary();
+        };
+        let mut left = self.binary(level + 1)?;
+        'outer: loop {
+            for &(token, op) in *operators {
+                if self.eat(token) {
+                    let right = self.binary(level + 1)?;
+                    left = Node::Binary(op, Box::new(left), Box::new(right));
+                    continue 'outer;
+                }
+            }
+            return Ok(left);
+        }
+    }
+
+    fn unary(&mut self) -> Result<Node, ExprError> {
+        for (token, op) in [
+            ("-", UnaryOp::Negate),
+            ("!", UnaryOp::Not),
+            ("~", UnaryOp::BitNot),
+        ] {
+            if self.eat(token) {
+                return Ok(Node::Unary(op, Box::new(self.unary()?)));
+            }
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Node, ExprError> {
+        self.skip_whitespace();
+        let start = self.position;
+        match self.peek() {
+            None => Err(ExprError::UnexpectedEnd),
+            Some('(') => {
+                self.position += 1;
+                let node = self.binary(0)?;
+                self.expect(')')?;
+                Ok(node)
+            }
+            Some(c) if c.is_ascii_digit() => {
+                let number = self.word();
+                parse_number(&number)
+                    .map(Node::Number)
+                    .ok_or(ExprError::InvalidNumber {
+                        position: start,
+                        number,
+                    })
+            }
+            Some(c) if c.is_alphabetic() || c == '_' => {
+                let name = self.word();
+                let lookup = |parser: &mut Self, lookup, open, close| {
+                    parser.expect(open)?;
+                    let index = parser.binary(0)?;
+                    parser.expect(close)?;
+                    Ok(Node::Lookup(lookup, Box::new(index)))
+                };
+                match name.to_lowercase().as_str() {
+                    "ram" => lookup(self, Lookup::Ram, '[', ']'),
+                    "pin" => lookup(self, Lookup::DigitalPin, '(', ')'),
+                    "apin" => lookup(self, Lookup::AnalogPin, '(', ')'),
+                    "pc" => Ok(Node::Value(Value::ProgramCounter)),
+                    "cycles" => Ok(Node::Value(Value::Cycles)),
+                    "depth" => Ok(Node::Value(Value::StackDepth)),
+                    "dpins" => Ok(Node::Value(Value::DigitalPins)),
+                    "rxbs" => Ok(Node::Value(Value::RxBuffer)),
+                    "txbs" => Ok(Node::Value(Value::TxBuffer)),
+                    register => Register::from_str(&register.to_uppercase())
+                        .map(|register| Node::Value(Value::Register(register)))
+                        .map_err(|_| ExprError::UnknownName {
+                            position: start,
+                            name,
+                        }),
+                }
+            }
+            Some(c) => Err(self.unexpected(c)),
+        }
+    }
+
+    /// A name or number, letters, digits and underscores
+    fn word(&mut self) -> String {
+        let start = self.position;
+        while self.peek().is_some_and(|c| c.is_alphanumeric() || c == '_') {
+            self.position += 1;
+        }
+        self.chars[start..self.position].iter().collect()
+    }
+}
+
+/// Decimal, or hex or binary with a `0x` or `0b` prefix, underscores are ignored
+fn parse_number(number: &str) -> Option<i64> {
+    let number = number.replace('_', "");
+    let lower = number.to_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = lower.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()
+    } else {
+        lower.parse().ok()
+    }
+}
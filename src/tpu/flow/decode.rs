@@ -25,6 +25,77 @@ pub fn decode_op_bnz() -> DecodeResult {
     }
 }
 
+pub fn decode_op_djnz(target: &OperandValueType) -> DecodeResult {
+    // One cycle to decrement, one to branch
+    let cycles = TPU::check_operand_cost(&[target]) + 2;
+
+    DecodeResult {
+        cycles,
+        call_every_cycle: false,
+    }
+}
+
+pub fn decode_op_jtb() -> DecodeResult {
+    // The cost is fixed so a dispatch takes the same time whatever the index is
+    DecodeResult {
+        cycles: 2,
+        call_every_cycle: false,
+    }
+}
+
+pub fn decode_op_jtbn(count: &OperandValueType) -> DecodeResult {
+    let cycles = TPU::check_operand_cost(&[count]) + 2;
+
+    DecodeResult {
+        cycles,
+        call_every_cycle: false,
+    }
+}
+
+pub fn decode_op_jsrt() -> DecodeResult {
+    // Fixed like JTB, plus a cycle for the push
+    DecodeResult {
+        cycles: 3,
+        call_every_cycle: false,
+    }
+}
+
+pub fn decode_op_loops(count: &OperandValueType, end: &OperandValueType) -> DecodeResult {
+    // Only the setup costs anything, going back to the start is free
+    let cycles = TPU::check_operand_cost(&[count, end]) + 1;
+
+    DecodeResult {
+        cycles,
+        call_every_cycle: false,
+    }
+}
+
+pub fn decode_op_pin_branch() -> DecodeResult {
+    // One cycle to read the pin, one to branch
+    DecodeResult {
+        cycles: 2,
+        call_every_cycle: false,
+    }
+}
+
+pub fn decode_op_skip() -> DecodeResult {
+    // The skipped instruction is never fetched, so it costs nothing
+    DecodeResult {
+        cycles: 1,
+        call_every_cycle: false,
+    }
+}
+
+pub fn decode_op_flag_branch(target: &OperandValueType) -> DecodeResult {
+    // Same as JMP, the flags are already known
+    let cycles = TPU::check_operand_cost(&[target]) + 1;
+
+    DecodeResult {
+        cycles,
+        call_every_cycle: true,
+    }
+}
+
 pub fn decode_op_beq() -> DecodeResult {
     DecodeResult {
         cycles: 3,
@@ -140,6 +211,31 @@ pub fn decode_op_jsr(target: &OperandValueType) -> DecodeResult {
     }
 }
 
+pub fn decode_op_swi(code: &OperandValueType) -> DecodeResult {
+    // Same as JSR
+    let cycles = TPU::check_operand_cost(&[code]) + 4;
+    DecodeResult {
+        cycles,
+        call_every_cycle: true,
+    }
+}
+
+pub fn decode_op_sjmp(target: &OperandValueType) -> DecodeResult {
+    // The register file is copied to the shadow bank in one go
+    let cycles = TPU::check_operand_cost(&[target]) + 3;
+    DecodeResult {
+        cycles,
+        call_every_cycle: true,
+    }
+}
+
+pub fn decode_op_sret() -> DecodeResult {
+    DecodeResult {
+        cycles: 3,
+        call_every_cycle: true,
+    }
+}
+
 pub fn decode_op_rts() -> DecodeResult {
     DecodeResult {
         cycles: 2,
@@ -1,7 +1,10 @@
+use crate::observer::{NullObserver, ObservedEvent, RecordingObserver};
 use crate::rgal::parse_program;
 use crate::shared::Register;
 use crate::tpu::flow::*;
-use crate::tpu::{TPU, TpuState};
+use crate::tpu::{
+    Expr, Flags, HardwareLoop, PwmChannel, RunResult, TPU, TpuConfig, TpuState, TraceEvent,
+};
 
 #[cfg(test)]
 mod tests {
@@ -26,19 +29,45 @@ mod tests {
 
         let mut tpu_state = TpuState {
             stack: Vec::new(),
+            stack_high_water: 0,
+            return_addresses: Vec::new(),
             analog_pins: [0; AnalogPin::COUNT],
             digital_pins: [false; DigitalPin::COUNT],
             analog_pin_config: [false; AnalogPin::COUNT],
             digital_pin_config: [true; DigitalPin::COUNT],
             ram: [0; TPU::RAM_SIZE],
+            nvram: Vec::new(),
             rom: program,
             network_address: 0x1,
             incoming_packets: std::collections::VecDeque::new(),
             outgoing_packets: std::collections::VecDeque::new(),
             registers: [0; Register::COUNT],
+            flags: Flags::default(),
+            data_base: 0,
+            hardware_loop: None,
+            saved_context: None,
+            pwm: [PwmChannel::default(); DigitalPin::COUNT],
+            cycle_count: 0,
+            instructions_retired: 0,
+            pin_events: std::collections::VecDeque::new(),
+            pin_events_overflowed: false,
+            pulse_counts: [0; DigitalPin::COUNT],
+            bounce: [None; DigitalPin::COUNT],
+            bounce_rng: 1,
+            analog_inputs: [0; AnalogPin::COUNT],
+            noise_offsets: [0; AnalogPin::COUNT],
+            noise_rng: 1,
+            comparator_thresholds: [0; AnalogPin::COUNT],
+            tx_overflow: false,
+            tx_cooldown: 0,
+            ttl_drops: 0,
+            uart_tx: std::collections::VecDeque::new(),
+            uart_rx: std::collections::VecDeque::new(),
             program_counter: 0,
             halted: false,
+            halt_reason: None,
             execution_state: ExecutionState::default(),
+            config: TpuConfig::default(),
         };
 
         // Set register values
@@ -82,6 +111,645 @@ mod tests {
         assert_eq!(tpu.tpu_state.program_counter, 0);
     }
 
+    #[test]
+    fn test_op_djnz() {
+        // Taken while the counter is not zero
+        let mut tpu = create_tpu_with_pc(LOOP_PROGRAM, 3);
+        tpu.write_register(Register::X, 2);
+        let result = op_djnz(&mut tpu, &OperandValueType::Immediate(1), &Register::X);
+        assert_eq!(result, ExecuteResult::PCModified);
+        assert_eq!(tpu.tpu_state.program_counter, 1);
+        assert_eq!(tpu.read_register(Register::X), 1);
+
+        // Falls through once it reaches zero
+        let mut tpu = create_tpu_with_pc(LOOP_PROGRAM, 3);
+        tpu.write_register(Register::X, 1);
+        let result = op_djnz(&mut tpu, &OperandValueType::Immediate(1), &Register::X);
+        assert_eq!(result, ExecuteResult::PCModified);
+        assert_eq!(tpu.tpu_state.program_counter, 4);
+        assert_eq!(tpu.read_register(Register::X), 0);
+
+        // Zero wraps around, so it's taken
+        let mut tpu = create_tpu_with_pc(LOOP_PROGRAM, 3);
+        let result = op_djnz(&mut tpu, &OperandValueType::Immediate(1), &Register::X);
+        assert_eq!(result, ExecuteResult::PCModified);
+        assert_eq!(tpu.tpu_state.program_counter, 1);
+        assert_eq!(tpu.read_register(Register::X), 0xFFFF);
+
+        // Error case - branch to an invalid line
+        let mut tpu = create_tpu_with_pc(LOOP_PROGRAM, 3);
+        tpu.write_register(Register::X, 2);
+        let result = op_djnz(&mut tpu, &OperandValueType::Immediate(10), &Register::X);
+        assert_eq!(result, ExecuteResult::Halt(HaltReason::InvalidPC));
+    }
+
+    #[test]
+    fn test_djnz_loop_is_faster() {
+        const DEC_BEZ_JMP_LOOP: &str = r#"LDR R0, 10
+        LDR A, 0
+        INC A
+        DEC R0
+        BEZ 6, R0
+        JMP 2
+        STM 0x10, A
+        HLT"#;
+
+        const DJNZ_LOOP: &str = r#"LDR R0, 10
+        LDR A, 0
+        INC A
+        DJNZ 2, R0
+        STM 0x10, A
+        HLT"#;
+
+        // Returns the end state and the number of cycles taken
+        let run = |program: &str| {
+            let mut tpu = create_tpu_with_program(program, 0, 0, 0);
+            let mut cycles = 0;
+            while !tpu.halted() {
+                tpu.tick();
+                cycles += 1;
+            }
+            (
+                tpu.read_register(Register::A),
+                tpu.read_register(Register::R0),
+                tpu.read_ram(0x10),
+                cycles,
+            )
+        };
+
+        let (a, r0, ram, cycles) = run(DEC_BEZ_JMP_LOOP);
+        let (djnz_a, djnz_r0, djnz_ram, djnz_cycles) = run(DJNZ_LOOP);
+        assert_eq!((a, r0, ram), (10, 0, 10));
+        assert_eq!((djnz_a, djnz_r0, djnz_ram), (a, r0, ram));
+        assert!(djnz_cycles < cycles, "{djnz_cycles} >= {cycles}");
+    }
+
+    #[test]
+    fn test_op_jtb() {
+        // Jumps to the base plus the index
+        let mut tpu = create_tpu_with_pc(LOOP_PROGRAM, 0);
+        tpu.write_register(Register::X, 3);
+        let result = op_jtb(&mut tpu, &OperandValueType::Immediate(1), &Register::X);
+        assert_eq!(result, ExecuteResult::PCModified);
+        assert_eq!(tpu.tpu_state.program_counter, 4);
+
+        // Error case - index past the end of the program
+        let mut tpu = create_tpu_with_pc(LOOP_PROGRAM, 0);
+        tpu.write_register(Register::X, 5);
+        let result = op_jtb(&mut tpu, &OperandValueType::Immediate(1), &Register::X);
+        assert_eq!(result, ExecuteResult::Halt(HaltReason::InvalidPC));
+
+        // Error case - the index can't wrap back into the program
+        let mut tpu = create_tpu_with_pc(LOOP_PROGRAM, 0);
+        tpu.write_register(Register::X, 0xFFFF);
+        let result = op_jtb(&mut tpu, &OperandValueType::Immediate(1), &Register::X);
+        assert_eq!(result, ExecuteResult::Halt(HaltReason::InvalidPC));
+    }
+
+    #[test]
+    fn test_op_jtbn() {
+        let count = OperandValueType::Immediate(3);
+
+        // In range jumps
+        let mut tpu = create_tpu_with_pc(LOOP_PROGRAM, 0);
+        tpu.write_register(Register::X, 2);
+        let result = op_jtbn(
+            &mut tpu,
+            &OperandValueType::Immediate(1),
+            &Register::X,
+            &count,
+        );
+        assert_eq!(result, ExecuteResult::PCModified);
+        assert_eq!(tpu.tpu_state.program_counter, 3);
+
+        // Out of range falls through, even if the target is a valid line
+        let mut tpu = create_tpu_with_pc(LOOP_PROGRAM, 0);
+        tpu.write_register(Register::X, 3);
+        let result = op_jtbn(
+            &mut tpu,
+            &OperandValueType::Immediate(1),
+            &Register::X,
+            &count,
+        );
+        assert_eq!(result, ExecuteResult::PCModified);
+        assert_eq!(tpu.tpu_state.program_counter, 1);
+    }
+
+    #[test]
+    fn test_jump_table_state_machine() {
+        // A 4 state machine, state 3 halts so the program can be run to completion
+        const STATE_MACHINE: &str = r#"JTB 1, X
+        JMP 5
+        JMP 7
+        JMP 9
+        JMP 11
+        LDR X, 1
+        JMP 0
+        LDR X, 2
+        JMP 0
+        LDR X, 3
+        JMP 0
+        HLT"#;
+
+        // Returns the number of cycles taken to reach the state's handler
+        let dispatch_cycles = |state: u16| {
+            let mut tpu = create_tpu_with_program(STATE_MACHINE, 0, state, 0);
+            let handler = 5 + 2 * state as usize;
+            let mut cycles = 0;
+            while tpu.tpu_state.program_counter != handler {
+                tpu.tick();
+                cycles += 1;
+            }
+            cycles
+        };
+
+        let cycles = dispatch_cycles(0);
+        for state in 1..4 {
+            assert_eq!(dispatch_cycles(state), cycles, "state {state}");
+        }
+
+        // Every state is visited in turn before halting
+        let mut tpu = create_tpu_with_program(STATE_MACHINE, 0, 0, 0);
+        let mut ticks = 0;
+        while !tpu.halted() && ticks < 100 {
+            tpu.tick();
+            ticks += 1;
+        }
+        assert!(tpu.halted());
+        assert_eq!(tpu.read_register(Register::X), 3);
+        assert_eq!(tpu.tpu_state.program_counter, 11);
+    }
+
+    #[test]
+    fn test_op_jsrt() {
+        // Calls the slot and pushes the line after the call
+        let mut tpu = create_tpu_with_pc(LOOP_PROGRAM, 1);
+        tpu.write_register(Register::X, 2);
+        let result = op_jsrt(&mut tpu, &OperandValueType::Immediate(2), &Register::X);
+        assert_eq!(result, ExecuteResult::PCModified);
+        assert_eq!(tpu.tpu_state.program_counter, 4);
+        assert_eq!(tpu.tpu_state.stack, vec![2]);
+
+        // Error case - a bad slot halts without touching the stack
+        let mut tpu = create_tpu_with_pc(LOOP_PROGRAM, 1);
+        tpu.write_register(Register::X, 4);
+        let result = op_jsrt(&mut tpu, &OperandValueType::Immediate(2), &Register::X);
+        assert_eq!(result, ExecuteResult::Halt(HaltReason::InvalidPC));
+        assert!(tpu.tpu_state.stack.is_empty());
+        assert_eq!(tpu.tpu_state.program_counter, 1);
+
+        // Error case - stack overflow
+        let mut tpu = create_tpu_with_pc(LOOP_PROGRAM, 1);
+        tpu.tpu_state.stack = vec![0; TPU::STACK_SIZE];
+        let result = op_jsrt(&mut tpu, &OperandValueType::Immediate(2), &Register::X);
+        assert_eq!(result, ExecuteResult::Halt(HaltReason::StackOverflow));
+    }
+
+    #[test]
+    fn test_call_table() {
+        // Calls each entry of a 3 entry table, each handler adds its own value to A
+        const PROGRAM: &str = r#"LDR X, 0
+        JSRT 7, X
+        INC X
+        LDR Y, 3
+        BNE 1, X, Y
+        STM 0x10, A
+        HLT
+        JMP 10
+        JMP 13
+        JMP 16
+        LDR R0, 1
+        ADD A, R0
+        RTS
+        LDR R0, 10
+        ADD A, R0
+        RTS
+        LDR R0, 100
+        ADD A, R0
+        RTS"#;
+
+        let mut tpu = create_tpu_with_program(PROGRAM, 0, 0, 0);
+        let mut ticks = 0;
+        while !tpu.halted() && ticks < 1000 {
+            tpu.tick();
+            ticks += 1;
+        }
+        assert_eq!(tpu.tpu_state.program_counter, 6);
+        assert_eq!(tpu.read_ram(0x10), 111);
+        assert!(tpu.tpu_state.stack.is_empty());
+
+        // A slot past the end of the program halts instead of jumping
+        let mut tpu = create_tpu_with_program(PROGRAM, 0, 0, 0);
+        tpu.tpu_state.program_counter = 1;
+        tpu.write_register(Register::X, 12);
+        while !tpu.halted() {
+            tpu.tick();
+        }
+        assert_eq!(tpu.tpu_state.program_counter, 1);
+    }
+
+    #[test]
+    fn test_op_loops() {
+        let mut tpu = create_tpu_with_pc(LOOP_PROGRAM, 1);
+        let result = op_loops(
+            &mut tpu,
+            &OperandValueType::Immediate(3),
+            &OperandValueType::Immediate(3),
+        );
+        assert_eq!(result, ExecuteResult::PCAdvance);
+        assert_eq!(
+            tpu.tpu_state.hardware_loop,
+            Some(HardwareLoop {
+                remaining: 3,
+                start: 2,
+                end: 3
+            })
+        );
+
+        // Only one loop can be active
+        let result = op_loops(
+            &mut tpu,
+            &OperandValueType::Immediate(3),
+            &OperandValueType::Immediate(3),
+        );
+        assert_eq!(result, ExecuteResult::Halt(HaltReason::NestedLoop));
+
+        // A count of zero skips the body
+        let mut tpu = create_tpu_with_pc(LOOP_PROGRAM, 1);
+        let result = op_loops(
+            &mut tpu,
+            &OperandValueType::Immediate(0),
+            &OperandValueType::Immediate(3),
+        );
+        assert_eq!(result, ExecuteResult::PCModified);
+        assert_eq!(tpu.tpu_state.program_counter, 4);
+        assert_eq!(tpu.tpu_state.hardware_loop, None);
+
+        // Error case - the end is before the body or past the end of the program
+        for end in [1, 6] {
+            let mut tpu = create_tpu_with_pc(LOOP_PROGRAM, 1);
+            let result = op_loops(
+                &mut tpu,
+                &OperandValueType::Immediate(3),
+                &OperandValueType::Immediate(end),
+            );
+            assert_eq!(result, ExecuteResult::Halt(HaltReason::InvalidPC));
+        }
+    }
+
+    #[test]
+    fn test_hardware_loop_program() {
+        // The body has multi-cycle instructions, A = R0 * X on the last iteration
+        const LOOP: &str = r#"LDR X, 3
+        LOOPS 4, 3
+        INC R0
+        MUL R0, X
+        STM 0x10, A
+        HLT"#;
+
+        const UNROLLED: &str = r#"LDR X, 3
+        INC R0
+        MUL R0, X
+        INC R0
+        MUL R0, X
+        INC R0
+        MUL R0, X
+        INC R0
+        MUL R0, X
+        STM 0x10, A
+        HLT"#;
+
+        // Returns the end state and the number of cycles taken
+        let run = |program: &str| {
+            let mut tpu = create_tpu_with_program(program, 0, 0, 0);
+            let mut cycles = 0;
+            while !tpu.halted() {
+                tpu.tick();
+                cycles += 1;
+            }
+            (
+                tpu.read_register(Register::R0),
+                tpu.read_ram(0x10),
+                tpu.tpu_state.hardware_loop,
+                cycles,
+            )
+        };
+
+        let (r0, ram, hardware_loop, cycles) = run(LOOP);
+        assert_eq!((r0, ram), (4, 12));
+        assert_eq!(hardware_loop, None);
+
+        // Going back to the start costs nothing, only LOOPS itself does
+        let (_, _, _, unrolled_cycles) = run(UNROLLED);
+        assert_eq!(cycles, unrolled_cycles + 1);
+
+        // Reset clears an active loop
+        let mut tpu = create_tpu_with_program(LOOP, 0, 0, 0);
+        while tpu.tpu_state.hardware_loop.is_none() {
+            tpu.tick();
+        }
+        tpu.reset();
+        assert_eq!(tpu.tpu_state.hardware_loop, None);
+    }
+
+    #[test]
+    fn test_op_bph_and_bplw() {
+        let target = OperandValueType::Immediate(4);
+
+        // Pin 2 high, BPH is taken and BPLW falls through
+        let mut tpu = create_tpu_with_pc(LOOP_PROGRAM, 1);
+        tpu.tpu_state.digital_pins[2] = true;
+        let result = op_bph(&mut tpu, &target, &OperandValueType::Immediate(2));
+        assert_eq!(result, ExecuteResult::PCModified);
+        assert_eq!(tpu.tpu_state.program_counter, 4);
+
+        let mut tpu = create_tpu_with_pc(LOOP_PROGRAM, 1);
+        tpu.tpu_state.digital_pins[2] = true;
+        let result = op_bplw(&mut tpu, &target, &OperandValueType::Immediate(2));
+        assert_eq!(result, ExecuteResult::PCModified);
+        assert_eq!(tpu.tpu_state.program_counter, 2);
+
+        // Pin given by a register, low
+        let mut tpu = create_tpu_with_pc(LOOP_PROGRAM, 1);
+        tpu.write_register(Register::X, 3);
+        let pin = OperandValueType::Register(Register::X);
+        let result = op_bph(&mut tpu, &target, &pin);
+        assert_eq!(result, ExecuteResult::PCModified);
+        assert_eq!(tpu.tpu_state.program_counter, 2);
+        let result = op_bplw(&mut tpu, &target, &pin);
+        assert_eq!(result, ExecuteResult::PCModified);
+        assert_eq!(tpu.tpu_state.program_counter, 4);
+
+        // Error case - invalid pin
+        let mut tpu = create_tpu_with_pc(LOOP_PROGRAM, 1);
+        let pin = OperandValueType::Immediate(DigitalPin::COUNT as u16);
+        let result = op_bph(&mut tpu, &target, &pin);
+        assert_eq!(result, ExecuteResult::Halt(HaltReason::IndexOutOfRange));
+        let result = op_bplw(&mut tpu, &target, &pin);
+        assert_eq!(result, ExecuteResult::Halt(HaltReason::IndexOutOfRange));
+        assert_eq!(tpu.tpu_state.program_counter, 1);
+    }
+
+    #[test]
+    fn test_op_sez_and_snz() {
+        // SEZ skips when zero
+        let mut tpu = create_tpu_with_pc(LOOP_PROGRAM, 1);
+        let result = op_sez(&mut tpu, &Register::X);
+        assert_eq!(result, ExecuteResult::PCModified);
+        assert_eq!(tpu.tpu_state.program_counter, 3);
+
+        // And doesn't when it's not
+        tpu.write_register(Register::X, 1);
+        let result = op_sez(&mut tpu, &Register::X);
+        assert_eq!(result, ExecuteResult::PCAdvance);
+        assert_eq!(tpu.tpu_state.program_counter, 3);
+
+        // SNZ is the opposite
+        let result = op_snz(&mut tpu, &Register::X);
+        assert_eq!(result, ExecuteResult::PCModified);
+        assert_eq!(tpu.tpu_state.program_counter, 5);
+        assert!(!tpu.halted());
+
+        // Skipping past the end of the program ends it, the PC stays in range
+        let result = op_snz(&mut tpu, &Register::X);
+        assert_eq!(result, ExecuteResult::Halt(HaltReason::EndOfProgram));
+        assert_eq!(tpu.tpu_state.program_counter, 5);
+    }
+
+    #[test]
+    fn test_skip_cycles() {
+        // Runs the program to the end and returns A and the number of cycles taken
+        let run = |program: &str, x: u16| {
+            let mut tpu = create_tpu_with_program(program, 0, x, 0);
+            let mut cycles = 0;
+            while !tpu.halted() {
+                tpu.tick();
+                cycles += 1;
+            }
+            (tpu.read_register(Register::A), cycles)
+        };
+
+        // The skipped instruction is a single cycle, multi cycle and a branch
+        for skipped in ["INC A", "MUL X, X", "JMP 0"] {
+            let program = format!("LDR A, 7\nSEZ X\n{skipped}\nHLT");
+            let (taken_a, taken_cycles) = run(&program, 0);
+            assert_eq!(taken_a, 7, "{skipped}");
+
+            // The skip costs the same as not running the instruction at all
+            let (_, baseline_cycles) = run("LDR A, 7\nSEZ X\nHLT", 1);
+            assert_eq!(taken_cycles, baseline_cycles, "{skipped}");
+        }
+
+        // Not skipped, the instruction is charged as normal
+        let (a, cycles) = run("LDR A, 7\nSEZ X\nMUL X, X\nHLT", 2);
+        let (_, skipped_cycles) = run("LDR A, 7\nSEZ X\nMUL X, X\nHLT", 0);
+        assert_eq!(a, 4);
+        assert!(cycles > skipped_cycles);
+    }
+
+    #[test]
+    fn test_op_flag_branches() {
+        type Branch = fn(&mut TPU, &OperandValueType) -> ExecuteResult;
+        // The register value and the value it's compared against
+        type Comparison = (u16, u16);
+
+        // Each branch with the comparison that takes it and the one that doesn't
+        let cases: [(Branch, Comparison, Comparison); 6] = [
+            (op_bcs, (1, 2), (2, 1)),
+            (op_bcc, (2, 1), (1, 2)),
+            (op_bmi, (0, 1), (1, 0)),
+            (op_bpl, (1, 0), (0, 1)),
+            (op_bzs, (5, 5), (5, 4)),
+            (op_bzc, (5, 4), (5, 5)),
+        ];
+
+        for (branch, taken, not_taken) in cases {
+            let mut tpu = create_tpu_with_pc(LOOP_PROGRAM, 2);
+            tpu.write_register(Register::X, taken.0);
+            crate::tpu::alu::op_cmp(
+                &mut tpu,
+                &Register::X,
+                &OperandValueType::Immediate(taken.1),
+            );
+            let result = branch(&mut tpu, &OperandValueType::Immediate(4));
+            assert_eq!(result, ExecuteResult::PCModified);
+            assert_eq!(tpu.tpu_state.program_counter, 4);
+
+            let mut tpu = create_tpu_with_pc(LOOP_PROGRAM, 2);
+            tpu.write_register(Register::X, not_taken.0);
+            crate::tpu::alu::op_cmp(
+                &mut tpu,
+                &Register::X,
+                &OperandValueType::Immediate(not_taken.1),
+            );
+            let result = branch(&mut tpu, &OperandValueType::Immediate(4));
+            assert_eq!(result, ExecuteResult::PCModified);
+            assert_eq!(tpu.tpu_state.program_counter, 3);
+        }
+
+        // Error case - branch to an invalid line
+        let mut tpu = create_tpu_with_pc(LOOP_PROGRAM, 2);
+        let result = op_bcc(&mut tpu, &OperandValueType::Immediate(10));
+        assert_eq!(result, ExecuteResult::Halt(HaltReason::InvalidPC));
+    }
+
+    #[test]
+    fn test_cmp_and_branch_program() {
+        // Count up to 5 using the flags instead of a zero test
+        const PROGRAM: &str = r#"LDR A, 0
+        INC A
+        CMP A, 5
+        BCS 1
+        STM 0x10, A
+        HLT"#;
+
+        let mut tpu = create_tpu_with_program(PROGRAM, 0, 0, 0);
+        while !tpu.halted() {
+            tpu.tick();
+        }
+        assert_eq!(tpu.read_ram(0x10), 5);
+        assert_eq!(
+            tpu.tpu_state.flags,
+            Flags {
+                zero: true,
+                carry: false,
+                negative: false
+            }
+        );
+    }
+
+    #[test]
+    fn test_relative_branch_extreme_offsets() {
+        // The largest forward and backward offsets from the first and last lines
+        for (pc, offset) in [(0, 0x7FFF), (5, 0x8000), (0, 0xFFFF), (5, 0x7FFF)] {
+            let mut tpu = create_tpu_with_pc(LOOP_PROGRAM, pc);
+            tpu.write_register(Register::X, offset);
+            tpu.write_register(Register::A, 1);
+
+            let result = op_jpr(&mut tpu, &OperandValueType::Register(Register::X));
+            assert_eq!(result, ExecuteResult::Halt(HaltReason::InvalidPC));
+            let result = op_brnz(
+                &mut tpu,
+                &OperandValueType::Register(Register::X),
+                &Register::A,
+            );
+            assert_eq!(result, ExecuteResult::Halt(HaltReason::InvalidPC));
+            assert_eq!(tpu.tpu_state.program_counter, pc);
+        }
+
+        // A program counter at the very top of the address space can't overflow either
+        let mut tpu = create_tpu_with_pc(LOOP_PROGRAM, usize::MAX);
+        tpu.write_register(Register::X, 0x7FFF);
+        let result = op_jpr(&mut tpu, &OperandValueType::Register(Register::X));
+        assert_eq!(result, ExecuteResult::Halt(HaltReason::InvalidPC));
+
+        // Not taken, the next line is past the end
+        tpu.write_register(Register::A, 0);
+        let result = op_brnz(
+            &mut tpu,
+            &OperandValueType::Register(Register::X),
+            &Register::A,
+        );
+        assert_eq!(result, ExecuteResult::Halt(HaltReason::EndOfProgram));
+
+        // An empty program has no valid lines at all
+        let mut tpu = create_tpu_with_pc(LOOP_PROGRAM, 0);
+        tpu.tpu_state.rom.clear();
+        let result = op_jpr(&mut tpu, &OperandValueType::Immediate(0));
+        assert_eq!(result, ExecuteResult::Halt(HaltReason::InvalidPC));
+    }
+
+    #[test]
+    fn test_branch_at_end_of_program() {
+        // Not taken on the last line ends the program like any other instruction
+        let mut tpu = create_tpu_with_program("LDR X, 1\nBEZ 0, X", 0, 0, 0);
+        while !tpu.halted() {
+            tpu.tick();
+        }
+        assert_eq!(tpu.tpu_state.program_counter, 1);
+
+        let mut tpu = create_tpu_with_pc(LOOP_PROGRAM, 5);
+        tpu.write_register(Register::X, 1);
+        let result = op_bez(&mut tpu, &OperandValueType::Immediate(0), &Register::X);
+        assert_eq!(result, ExecuteResult::Halt(HaltReason::EndOfProgram));
+        assert_eq!(tpu.tpu_state.program_counter, 5);
+
+        // Taken to exactly the end of the program is an invalid target
+        let rom_len = tpu.tpu_state.rom.len() as u16;
+        let result = op_bnz(
+            &mut tpu,
+            &OperandValueType::Immediate(rom_len),
+            &Register::X,
+        );
+        assert_eq!(result, ExecuteResult::Halt(HaltReason::InvalidPC));
+        assert_eq!(tpu.tpu_state.program_counter, 5);
+
+        // A plain instruction on the last line leaves the PC on it too
+        let mut tpu = create_tpu_with_program("LDR X, 1\nINC X", 0, 0, 0);
+        while !tpu.halted() {
+            tpu.tick();
+        }
+        assert_eq!(tpu.tpu_state.program_counter, 1);
+        assert_eq!(tpu.read_register(Register::X), 2);
+    }
+
+    #[test]
+    fn test_relative_branch_signed_offsets() {
+        // Backward, 0xFFFD is -3
+        let mut tpu = create_tpu_with_pc(LOOP_PROGRAM, 4);
+        let result = op_jpr(&mut tpu, &OperandValueType::Immediate(0xFFFD));
+        assert_eq!(result, ExecuteResult::PCModified);
+        assert_eq!(tpu.tpu_state.program_counter, 1);
+
+        // Backward through a register
+        let mut tpu = create_tpu_with_pc(LOOP_PROGRAM, 3);
+        tpu.write_register(Register::X, 0xFFFE);
+        tpu.write_register(Register::A, 0);
+        let result = op_brez(
+            &mut tpu,
+            &OperandValueType::Register(Register::X),
+            &Register::A,
+        );
+        assert_eq!(result, ExecuteResult::PCModified);
+        assert_eq!(tpu.tpu_state.program_counter, 1);
+
+        // Forward
+        let mut tpu = create_tpu_with_pc(LOOP_PROGRAM, 1);
+        tpu.write_register(Register::A, 5);
+        let result = op_brgt(
+            &mut tpu,
+            &OperandValueType::Immediate(2),
+            &Register::A,
+            &OperandValueType::Immediate(4),
+        );
+        assert_eq!(result, ExecuteResult::PCModified);
+        assert_eq!(tpu.tpu_state.program_counter, 3);
+
+        // Zero offset branches to itself
+        let mut tpu = create_tpu_with_pc(LOOP_PROGRAM, 2);
+        let result = op_jpr(&mut tpu, &OperandValueType::Immediate(0));
+        assert_eq!(result, ExecuteResult::PCModified);
+        assert_eq!(tpu.tpu_state.program_counter, 2);
+
+        // Going back past line 0 halts
+        let mut tpu = create_tpu_with_pc(LOOP_PROGRAM, 2);
+        let result = op_jpr(&mut tpu, &OperandValueType::Immediate(0xFFFD));
+        assert_eq!(result, ExecuteResult::Halt(HaltReason::InvalidPC));
+        assert_eq!(tpu.tpu_state.program_counter, 2);
+
+        // ...but only if the branch is taken
+        let mut tpu = create_tpu_with_pc(LOOP_PROGRAM, 2);
+        tpu.write_register(Register::A, 1);
+        let result = op_brlt(
+            &mut tpu,
+            &OperandValueType::Immediate(0xFFFD),
+            &Register::A,
+            &OperandValueType::Immediate(1),
+        );
+        assert_eq!(result, ExecuteResult::PCModified);
+        assert_eq!(tpu.tpu_state.program_counter, 3);
+    }
+
     #[test]
     fn test_op_bez() {
         // Test case 1: Branch when value is zero
@@ -721,7 +1389,7 @@ mod tests {
         assert_eq!(result, ExecuteResult::PCModified); // No error
         assert_eq!(tpu.tpu_state.program_counter, 4); // PC is now at line 4
         assert_eq!(tpu.tpu_state.stack.len(), 1); // Stack has one item
-        assert_eq!(tpu.tpu_state.stack[0], 0); // Return address is 0
+        assert_eq!(tpu.tpu_state.stack[0], 1); // Return address is the next line
 
         // Test case 2: Call subroutine with register operand
         let mut tpu = create_tpu_with_pc(LOOP_PROGRAM, 1);
@@ -731,7 +1399,7 @@ mod tests {
         assert_eq!(result, ExecuteResult::PCModified); // No error
         assert_eq!(tpu.tpu_state.program_counter, 4); // PC is now at line 4
         assert_eq!(tpu.tpu_state.stack.len(), 1); // Stack has one item
-        assert_eq!(tpu.tpu_state.stack[0], 1); // Return address is 1
+        assert_eq!(tpu.tpu_state.stack[0], 2); // Return address is the next line
 
         // Test case 3: Error case - call to an invalid line
         let mut tpu = create_tpu_with_pc(LOOP_PROGRAM, 0);
@@ -765,14 +1433,157 @@ mod tests {
         // Test case 3: Error case - return with empty stack
         let mut tpu = create_tpu_with_pc(LOOP_PROGRAM, 4);
         let result = op_rts(&mut tpu);
-        assert_eq!(result, ExecuteResult::PCModified); // No error (pop from empty stack returns 0)
-        assert_eq!(tpu.tpu_state.program_counter, 0); // PC is set to 0
+        assert_eq!(result, ExecuteResult::Halt(HaltReason::StackUnderflow));
+        assert_eq!(tpu.tpu_state.program_counter, 4); // PC is unchanged
         assert_eq!(tpu.tpu_state.stack.len(), 0); // Stack is empty
+
+        // Test case 4: Error case - return address past the end of the program
+        let mut tpu = create_tpu_with_pc(LOOP_PROGRAM, 4);
+        tpu.push(6);
+        let result = op_rts(&mut tpu);
+        assert_eq!(result, ExecuteResult::Halt(HaltReason::InvalidPC));
+    }
+
+    #[test]
+    fn test_taken_branch_penalty() {
+        // The branch on line 1 is taken when X is zero, both paths run the same instructions
+        const PROGRAM: &str = r#"LDR A, 1
+        BEZ 2, X
+        INC A
+        HLT"#;
+
+        // Returns the number of cycles taken to halt
+        let run = |x: u16, penalty: bool| {
+            let mut tpu = create_tpu_with_program(PROGRAM, 0, x, 0);
+            tpu.tpu_state.config.taken_branch_penalty = penalty;
+            let mut cycles = 0;
+            while !tpu.halted() {
+                tpu.tick();
+                cycles += 1;
+            }
+            assert_eq!(tpu.read_register(Register::A), 2);
+            cycles
+        };
+
+        let taken = run(0, true);
+        let not_taken = run(1, true);
+        assert_eq!(taken - not_taken, TPU::TAKEN_BRANCH_PENALTY as usize);
+
+        // Disabled, the original timing is kept
+        assert_eq!(run(0, false), run(1, false));
+    }
+
+    #[test]
+    fn test_op_swi() {
+        // Test case 1: Calls the vector with the code in R5
+        let mut tpu = create_tpu_with_pc(LOOP_PROGRAM, 1);
+        tpu.tpu_state.config.trap_vector = Some(4);
+        let result = op_swi(&mut tpu, &OperandValueType::Immediate(0x42));
+        assert_eq!(result, ExecuteResult::PCModified);
+        assert_eq!(tpu.tpu_state.program_counter, 4);
+        assert_eq!(tpu.tpu_state.stack, vec![2]);
+        assert_eq!(tpu.read_register(TPU::TRAP_CODE), 0x42);
+
+        // RTS returns to the line after the trap
+        let result = op_rts(&mut tpu);
+        assert_eq!(result, ExecuteResult::PCModified);
+        assert_eq!(tpu.tpu_state.program_counter, 2);
+
+        // Test case 2: Error case - no vector
+        let mut tpu = create_tpu_with_pc(LOOP_PROGRAM, 1);
+        let result = op_swi(&mut tpu, &OperandValueType::Immediate(0x42));
+        assert_eq!(result, ExecuteResult::Halt(HaltReason::UnhandledTrap));
+        assert!(tpu.tpu_state.stack.is_empty());
+
+        // Test case 3: Error case - no room on the stack for the return address
+        let mut tpu = create_tpu_with_pc(LOOP_PROGRAM, 1);
+        tpu.tpu_state.config.trap_vector = Some(4);
+        tpu.tpu_state.stack = vec![0; TPU::STACK_SIZE];
+        let result = op_swi(&mut tpu, &OperandValueType::Immediate(0x42));
+        assert_eq!(result, ExecuteResult::Halt(HaltReason::StackOverflow));
+        assert_eq!(tpu.read_register(TPU::TRAP_CODE), 0);
+
+        // Test case 4: Error case - vector past the end of the program
+        let mut tpu = create_tpu_with_pc(LOOP_PROGRAM, 1);
+        tpu.tpu_state.config.trap_vector = Some(6);
+        let result = op_swi(&mut tpu, &OperandValueType::Immediate(0x42));
+        assert_eq!(result, ExecuteResult::Halt(HaltReason::InvalidPC));
+        assert!(tpu.tpu_state.stack.is_empty());
+    }
+
+    #[test]
+    fn test_op_sjmp_and_sret() {
+        // Test case 1: Saves the registers and the line after the jump
+        let mut tpu = create_tpu_with_program(LOOP_PROGRAM, 1, 2, 3);
+        tpu.tpu_state.program_counter = 1;
+        let result = op_sjmp(&mut tpu, &OperandValueType::Immediate(4));
+        assert_eq!(result, ExecuteResult::PCModified);
+        assert_eq!(tpu.tpu_state.program_counter, 4);
+        let context = tpu.tpu_state.saved_context.unwrap();
+        assert_eq!(context.return_address, 2);
+        assert_eq!(context.registers, tpu.tpu_state.registers);
+
+        // Test case 2: A second jump replaces the saved context
+        tpu.write_register(Register::A, 10);
+        let result = op_sjmp(&mut tpu, &OperandValueType::Immediate(0));
+        assert_eq!(result, ExecuteResult::PCModified);
+        let context = tpu.tpu_state.saved_context.unwrap();
+        assert_eq!(context.return_address, 5);
+        assert_eq!(context.registers[Register::A as usize], 10);
+
+        // Test case 3: Restores and clears the context
+        tpu.write_register(Register::A, 20);
+        let result = op_sret(&mut tpu);
+        assert_eq!(result, ExecuteResult::PCModified);
+        assert_eq!(tpu.tpu_state.program_counter, 5);
+        assert_eq!(tpu.read_register(Register::A), 10);
+        assert_eq!(tpu.tpu_state.saved_context, None);
+
+        // Test case 4: Error case - nothing to return to
+        let result = op_sret(&mut tpu);
+        assert_eq!(result, ExecuteResult::Halt(HaltReason::NoSavedContext));
+
+        // Test case 5: Error case - invalid target leaves the context alone
+        let result = op_sjmp(&mut tpu, &OperandValueType::Immediate(10));
+        assert_eq!(result, ExecuteResult::Halt(HaltReason::InvalidPC));
+        assert_eq!(tpu.tpu_state.saved_context, None);
+    }
+
+    #[test]
+    fn test_sjmp_restores_clobbered_registers() {
+        // The recovery routine at line 5 uses every register it touches as scratch
+        const PROGRAM: &str = r#"LDR A, 1
+        LDR R0, 2
+        SJMP 5
+        STM 0x10, A
+        HLT
+        LDR A, 100
+        LDR R0, 200
+        LDR X, 300
+        STM 0x11, X
+        SRET"#;
+
+        let mut tpu = create_tpu_with_program(PROGRAM, 0, 7, 0);
+        let mut ticks = 0;
+        while !tpu.halted() && ticks < 1000 {
+            tpu.tick();
+            ticks += 1;
+        }
+
+        // The routine ran, then the interrupted registers carried on
+        assert_eq!(tpu.tpu_state.program_counter, 4);
+        assert_eq!(tpu.read_ram(0x11), 300);
+        assert_eq!(tpu.read_ram(0x10), 1);
+        assert_eq!(tpu.read_register(Register::A), 1);
+        assert_eq!(tpu.read_register(Register::R0), 2);
+        assert_eq!(tpu.read_register(Register::X), 7);
     }
 
     #[test]
     fn test_full_program_execution() {
         let mut tpu = create_tpu_with_program(LOOP_PROGRAM, 0, 0, 0);
+        // This steps through the original timing, taken branches cost the same as not taken
+        tpu.tpu_state.config.taken_branch_penalty = false;
 
         // Execute the program step by step
         // LDA 10 - Load 10 into A
@@ -810,4 +1621,170 @@ mod tests {
         tpu.tick();
         assert_eq!(tpu.tpu_state.halted, true);
     }
+
+    #[test]
+    fn test_conditional_breakpoint() {
+        let mut tpu = create_tpu_with_program(LOOP_PROGRAM, 0, 0, 0);
+        // The BEZ runs once for each value of A from 9 down, it only stops on the pass where A is 4
+        tpu.set_breakpoint(2, Some(Expr::parse("A == 4").unwrap()));
+        assert_eq!(tpu.run(1000), RunResult::Breakpoint(2));
+        assert_eq!(tpu.read_register(Register::A), 4);
+        assert_eq!(tpu.run(1000), RunResult::Halted(HaltReason::HLTOpcode));
+        assert_eq!(tpu.read_register(Register::A), 255);
+
+        // A condition that can't be evaluated stops, rather than being ignored
+        let mut tpu = create_tpu_with_program(LOOP_PROGRAM, 0, 0, 0);
+        tpu.set_breakpoint(2, Some(Expr::parse("ram[0xFFFF] == 1").unwrap()));
+        assert_eq!(tpu.run(1000), RunResult::Breakpoint(2));
+        assert_eq!(tpu.read_register(Register::A), 9);
+
+        // Setting it again without a condition stops every time, toggling it clears it
+        tpu.set_breakpoint(2, None);
+        assert_eq!(tpu.breakpoint_condition(2), None);
+        assert_eq!(tpu.run(1000), RunResult::Breakpoint(2));
+        assert_eq!(tpu.read_register(Register::A), 8);
+        tpu.set_breakpoint(2, Some(Expr::parse("A == 0").unwrap()));
+        assert_eq!(tpu.breakpoint_condition(2).unwrap().to_string(), "A == 0");
+        assert!(!tpu.toggle_breakpoint(2));
+        assert!(tpu.toggle_breakpoint(2));
+        assert_eq!(tpu.breakpoint_condition(2), None);
+
+        // Conditions go with their breakpoints when a shorter program is loaded
+        tpu.set_breakpoint(4, Some(Expr::parse("A").unwrap()));
+        tpu.load_program(parse_program("NOP\nNOP\nHLT").unwrap());
+        assert_eq!(tpu.breakpoint_condition(4), None);
+        assert_eq!(tpu.breakpoints().iter().copied().collect::<Vec<_>>(), [2]);
+    }
+
+    #[test]
+    fn test_observers() {
+        let mut tpu = create_tpu_with_program(LOOP_PROGRAM, 0, 0, 0);
+        let recorder = RecordingObserver::new();
+        let late = RecordingObserver::new();
+        tpu.add_observer(NullObserver);
+        tpu.add_observer(recorder.clone());
+        let late_id = tpu.add_observer(late.clone());
+        assert!(tpu.remove_observer(late_id));
+        assert!(!tpu.remove_observer(late_id));
+        assert_eq!(tpu.run(1000), RunResult::Halted(HaltReason::HLTOpcode));
+
+        let events = recorder.events();
+        let ticks: Vec<u64> = events
+            .iter()
+            .filter_map(|event| match event {
+                ObservedEvent::Tick(cycle) => Some(*cycle),
+                _ => None,
+            })
+            .collect();
+        let retired: Vec<usize> = events
+            .iter()
+            .filter_map(|event| match event {
+                ObservedEvent::InstructionRetired(event) => Some(event.pc),
+                _ => None,
+            })
+            .collect();
+        let cycles = tpu.state().cycle_count;
+        assert_eq!(ticks, (1..=cycles).collect::<Vec<_>>());
+        // LDR, nine passes of DEC, BEZ and JMP, the last DEC and the BEZ taken, then LDR and HLT
+        let mut expected = vec![0];
+        for _ in 0..9 {
+            expected.extend([1, 2, 3]);
+        }
+        expected.extend([1, 2, 4, 5]);
+        assert_eq!(retired, expected);
+        assert_eq!(events.len(), ticks.len() + retired.len() + 1, "{events:?}");
+
+        // The HLT finishes, halts the TPU and then its tick ends
+        let [.., last_retired, halt, last_tick] = events.as_slice() else {
+            panic!("too few events");
+        };
+        assert!(matches!(
+            last_retired,
+            ObservedEvent::InstructionRetired(TraceEvent { pc: 5, .. })
+        ));
+        assert_eq!(
+            *halt,
+            ObservedEvent::Halt {
+                cycle: cycles,
+                reason: HaltReason::HLTOpcode
+            }
+        );
+        assert_eq!(*last_tick, ObservedEvent::Tick(cycles));
+        assert!(late.is_empty());
+
+        // The trace callback is an observer like any other
+        tpu.reset();
+        recorder.take_events();
+        let count = std::rc::Rc::new(std::cell::Cell::new(0));
+        let seen = count.clone();
+        tpu.set_trace_callback(Box::new(move |_| seen.set(seen.get() + 1)));
+        tpu.run(1000);
+        assert_eq!(count.get(), retired.len());
+        assert_eq!(recorder.len(), events.len());
+    }
+
+    #[test]
+    fn test_instructions_iterator() {
+        let mut tpu = create_tpu_with_program(LOOP_PROGRAM, 0, 0, 0);
+        let rom = tpu.read_rom().clone();
+        let retired: Vec<_> = tpu.instructions().collect();
+
+        let mut expected = vec![0];
+        for _ in 0..9 {
+            expected.extend([1, 2, 3]);
+        }
+        expected.extend([1, 2, 4, 5]);
+        assert_eq!(
+            retired.iter().map(|(pc, _)| *pc).collect::<Vec<_>>(),
+            expected
+        );
+        assert!(
+            retired
+                .iter()
+                .all(|(pc, instruction)| *instruction == rom[*pc])
+        );
+        assert!(tpu.halted());
+        assert_eq!(tpu.instructions().next(), None);
+
+        // A breakpoint ends it, a new one carries on past it
+        let mut tpu = create_tpu_with_program(LOOP_PROGRAM, 0, 0, 0);
+        tpu.toggle_breakpoint(3);
+        assert_eq!(tpu.instructions().count(), 3);
+        assert_eq!(tpu.state().program_counter, 3);
+        assert_eq!(tpu.instructions().count(), 3);
+        let last = tpu.ticks().last().unwrap();
+        assert!(last.at_breakpoint);
+        assert!(!last.halted);
+        assert_eq!(last.pc, 3);
+    }
+
+    #[test]
+    fn test_ticks_iterator() {
+        let mut iterated = create_tpu_with_program(LOOP_PROGRAM, 0, 0, 0);
+        let mut ticked = create_tpu_with_program(LOOP_PROGRAM, 0, 0, 0);
+        let summaries: Vec<_> = iterated.ticks().take(10).collect();
+        for _ in 0..10 {
+            ticked.tick();
+        }
+        assert_eq!(summaries.len(), 10);
+        assert!(iterated.state().diff(ticked.state()).is_empty());
+        assert_eq!(iterated.state().cycle_count, ticked.state().cycle_count);
+        assert_eq!(
+            iterated.state().execution_state.wait_cycles,
+            ticked.state().execution_state.wait_cycles
+        );
+        assert_eq!(
+            summaries.iter().map(|tick| tick.cycle).collect::<Vec<_>>(),
+            (1..=10).collect::<Vec<_>>()
+        );
+        assert_eq!(summaries.last().unwrap().pc, ticked.state().program_counter);
+
+        // The tick that halts is the last
+        let rest: Vec<_> = iterated.ticks().collect();
+        let last = rest.last().unwrap();
+        assert!(last.halted);
+        assert_eq!(last.retired.as_ref().map(|(pc, _)| *pc), Some(5));
+        assert_eq!(rest.iter().filter(|tick| tick.halted).count(), 1);
+        assert_eq!(last.cycle, iterated.state().cycle_count);
+    }
 }
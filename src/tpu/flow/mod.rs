@@ -3,8 +3,8 @@ pub mod decode;
 mod flow_test;
 
 use crate::shared::Register;
-use crate::shared::{ExecuteResult, HaltReason, OperandValueType};
-use crate::tpu::TPU;
+use crate::shared::{DigitalPin, ExecuteResult, HaltReason, OperandValueType};
+use crate::tpu::{HardwareLoop, SavedContext, TPU};
 
 pub fn op_jmp(tpu: &mut TPU, target: &OperandValueType) -> ExecuteResult {
     let address = tpu.get_operand_value(target) as usize;
@@ -17,19 +17,23 @@ fn set_program_counter_conditionally(
     condition: bool,
     address: usize,
 ) -> ExecuteResult {
-    // Check if the value is zero
-    let target = if condition {
-        address
-    } else {
-        tpu.tpu_state.program_counter + 1
-    };
+    // Not taken carries on to the next line like any other instruction
+    if !condition {
+        let next = tpu.tpu_state.program_counter.saturating_add(1);
+        return tpu.fall_through(next);
+    }
 
     // Check if the address is valid
-    if target > (tpu.tpu_state.rom.len() - 1) {
+    if address >= tpu.tpu_state.rom.len() {
         return ExecuteResult::Halt(HaltReason::InvalidPC);
     }
 
-    tpu.tpu_state.program_counter = target;
+    // A taken branch flushes the pipeline
+    if tpu.tpu_state.config.taken_branch_penalty {
+        tpu.tpu_state.execution_state.stall_cycles = TPU::TAKEN_BRANCH_PENALTY;
+    }
+
+    tpu.tpu_state.program_counter = address;
     ExecuteResult::PCModified
 }
 
@@ -126,25 +130,162 @@ pub fn op_blt(
     set_program_counter_conditionally(tpu, a < b, address)
 }
 
+/// Decrement the counter register (wrapping) and branch if the result is not zero
+pub fn op_djnz(tpu: &mut TPU, target: &OperandValueType, counter: &Register) -> ExecuteResult {
+    let address = tpu.get_operand_value(target) as usize;
+    let value = tpu.read_register(*counter).wrapping_sub(1);
+    tpu.write_register(*counter, value);
+    set_program_counter_conditionally(tpu, value != 0, address)
+}
+
+/// Jump to the base address plus the index, typically into a block of JMPs
+pub fn op_jtb(tpu: &mut TPU, base: &OperandValueType, index: &Register) -> ExecuteResult {
+    let address = (tpu.get_operand_value(base) as usize) + tpu.read_register(*index) as usize;
+    set_program_counter_conditionally(tpu, true, address)
+}
+
+/// Same as JTB but falls through to the next line if the index is not below the count
+pub fn op_jtbn(
+    tpu: &mut TPU,
+    base: &OperandValueType,
+    index: &Register,
+    count: &OperandValueType,
+) -> ExecuteResult {
+    let offset = tpu.read_register(*index);
+    let address = (tpu.get_operand_value(base) as usize) + offset as usize;
+    let in_range = offset < tpu.get_operand_value(count);
+    set_program_counter_conditionally(tpu, in_range, address)
+}
+
+/// Start a hardware loop over the following lines up to and including the end line.
+/// The loop back happens in `TPU::execute_instruction`, a count of zero skips the body.
+pub fn op_loops(tpu: &mut TPU, count: &OperandValueType, end: &OperandValueType) -> ExecuteResult {
+    if tpu.tpu_state.hardware_loop.is_some() {
+        return ExecuteResult::Halt(HaltReason::NestedLoop);
+    }
+
+    let count = tpu.get_operand_value(count);
+    let start = tpu.tpu_state.program_counter + 1;
+    let end = tpu.get_operand_value(end) as usize;
+    if end < start || end >= tpu.tpu_state.rom.len() {
+        return ExecuteResult::Halt(HaltReason::InvalidPC);
+    }
+
+    if count == 0 {
+        return tpu.fall_through(end + 1);
+    }
+
+    tpu.tpu_state.hardware_loop = Some(HardwareLoop {
+        remaining: count,
+        start,
+        end,
+    });
+    ExecuteResult::PCAdvance
+}
+
+/// Branch if the digital pin is at the given level
+fn branch_on_pin(
+    tpu: &mut TPU,
+    target: &OperandValueType,
+    pin: &OperandValueType,
+    level: bool,
+) -> ExecuteResult {
+    let Some(pin) = DigitalPin::from_repr(tpu.get_operand_value(pin)) else {
+        return ExecuteResult::Halt(HaltReason::IndexOutOfRange);
+    };
+
+    let address = tpu.get_operand_value(target) as usize;
+    let value = tpu.get_digital_pin(pin);
+    set_program_counter_conditionally(tpu, value == level, address)
+}
+
+pub fn op_bph(tpu: &mut TPU, target: &OperandValueType, pin: &OperandValueType) -> ExecuteResult {
+    branch_on_pin(tpu, target, pin, true)
+}
+
+pub fn op_bplw(tpu: &mut TPU, target: &OperandValueType, pin: &OperandValueType) -> ExecuteResult {
+    branch_on_pin(tpu, target, pin, false)
+}
+
+/// Skip over the next instruction if the condition holds.
+/// Skipping past the last line ends the program, the same as running off the end of it.
+fn skip_conditionally(tpu: &mut TPU, condition: bool) -> ExecuteResult {
+    if !condition {
+        return ExecuteResult::PCAdvance;
+    }
+
+    let target = tpu.tpu_state.program_counter.saturating_add(2);
+    tpu.fall_through(target)
+}
+
+pub fn op_sez(tpu: &mut TPU, source: &Register) -> ExecuteResult {
+    let value = tpu.read_register(*source);
+    skip_conditionally(tpu, value == 0)
+}
+
+pub fn op_snz(tpu: &mut TPU, source: &Register) -> ExecuteResult {
+    let value = tpu.read_register(*source);
+    skip_conditionally(tpu, value != 0)
+}
+
+// Flag branches, the flags are set by CMP
+pub fn op_bcs(tpu: &mut TPU, target: &OperandValueType) -> ExecuteResult {
+    let address = tpu.get_operand_value(target) as usize;
+    set_program_counter_conditionally(tpu, tpu.tpu_state.flags.carry, address)
+}
+
+pub fn op_bcc(tpu: &mut TPU, target: &OperandValueType) -> ExecuteResult {
+    let address = tpu.get_operand_value(target) as usize;
+    set_program_counter_conditionally(tpu, !tpu.tpu_state.flags.carry, address)
+}
+
+pub fn op_bmi(tpu: &mut TPU, target: &OperandValueType) -> ExecuteResult {
+    let address = tpu.get_operand_value(target) as usize;
+    set_program_counter_conditionally(tpu, tpu.tpu_state.flags.negative, address)
+}
+
+pub fn op_bpl(tpu: &mut TPU, target: &OperandValueType) -> ExecuteResult {
+    let address = tpu.get_operand_value(target) as usize;
+    set_program_counter_conditionally(tpu, !tpu.tpu_state.flags.negative, address)
+}
+
+pub fn op_bzs(tpu: &mut TPU, target: &OperandValueType) -> ExecuteResult {
+    let address = tpu.get_operand_value(target) as usize;
+    set_program_counter_conditionally(tpu, tpu.tpu_state.flags.zero, address)
+}
+
+pub fn op_bzc(tpu: &mut TPU, target: &OperandValueType) -> ExecuteResult {
+    let address = tpu.get_operand_value(target) as usize;
+    set_program_counter_conditionally(tpu, !tpu.tpu_state.flags.zero, address)
+}
+
 // Relative Branches
+/// Branch relative to the current line, the offset is treated as signed (two's complement)
+/// so `0xFFFD` jumps back three lines.
+fn branch_relative(tpu: &mut TPU, condition: bool, target: &OperandValueType) -> ExecuteResult {
+    let offset = tpu.get_operand_value(target) as i16 as isize;
+
+    // Going back past line 0 becomes an out of range line, which halts with InvalidPC
+    let new_pc = tpu
+        .tpu_state
+        .program_counter
+        .checked_add_signed(offset)
+        .unwrap_or(usize::MAX);
+    set_program_counter_conditionally(tpu, condition, new_pc)
+}
+
 pub fn op_jpr(tpu: &mut TPU, target: &OperandValueType) -> ExecuteResult {
-    let offset = tpu.get_operand_value(target) as usize;
-    let new_pc = tpu.tpu_state.program_counter + offset;
-    set_program_counter_conditionally(tpu, true, new_pc)
+    branch_relative(tpu, true, target)
 }
 
 pub fn op_brez(tpu: &mut TPU, target: &OperandValueType, source: &Register) -> ExecuteResult {
-    let offset = tpu.get_operand_value(target) as usize;
     let value = tpu.read_register(*source);
-    let new_pc = tpu.tpu_state.program_counter + offset;
-    set_program_counter_conditionally(tpu, value == 0, new_pc)
+    branch_relative(tpu, value == 0, target)
 }
 
 pub fn op_brnz(tpu: &mut TPU, target: &OperandValueType, source: &Register) -> ExecuteResult {
-    let offset = tpu.get_operand_value(target) as usize;
     let value = tpu.read_register(*source);
-    let new_pc = tpu.tpu_state.program_counter + offset;
-    set_program_counter_conditionally(tpu, value != 0, new_pc)
+    branch_relative(tpu, value != 0, target)
 }
 
 pub fn op_breq(
@@ -153,12 +294,10 @@ pub fn op_breq(
     source: &Register,
     value: &OperandValueType,
 ) -> ExecuteResult {
-    let offset = tpu.get_operand_value(target) as usize;
     let a = tpu.read_register(*source);
     let b = tpu.get_operand_value(value);
 
-    let new_pc = tpu.tpu_state.program_counter + offset;
-    set_program_counter_conditionally(tpu, a == b, new_pc)
+    branch_relative(tpu, a == b, target)
 }
 
 pub fn op_brne(
@@ -167,12 +306,10 @@ pub fn op_brne(
     source: &Register,
     value: &OperandValueType,
 ) -> ExecuteResult {
-    let offset = tpu.get_operand_value(target) as usize;
     let a = tpu.read_register(*source);
     let b = tpu.get_operand_value(value);
 
-    let new_pc = tpu.tpu_state.program_counter + offset;
-    set_program_counter_conditionally(tpu, a != b, new_pc)
+    branch_relative(tpu, a != b, target)
 }
 
 pub fn op_brge(
@@ -181,12 +318,10 @@ pub fn op_brge(
     source: &Register,
     value: &OperandValueType,
 ) -> ExecuteResult {
-    let offset = tpu.get_operand_value(target) as usize;
     let a = tpu.read_register(*source);
     let b = tpu.get_operand_value(value);
 
-    let new_pc = tpu.tpu_state.program_counter + offset;
-    set_program_counter_conditionally(tpu, a >= b, new_pc)
+    branch_relative(tpu, a >= b, target)
 }
 
 pub fn op_brle(
@@ -195,12 +330,10 @@ pub fn op_brle(
     source: &Register,
     value: &OperandValueType,
 ) -> ExecuteResult {
-    let offset = tpu.get_operand_value(target) as usize;
     let a = tpu.read_register(*source);
     let b = tpu.get_operand_value(value);
 
-    let new_pc = tpu.tpu_state.program_counter + offset;
-    set_program_counter_conditionally(tpu, a <= b, new_pc)
+    branch_relative(tpu, a <= b, target)
 }
 
 pub fn op_brgt(
@@ -209,12 +342,10 @@ pub fn op_brgt(
     source: &Register,
     value: &OperandValueType,
 ) -> ExecuteResult {
-    let offset = tpu.get_operand_value(target) as usize;
     let a = tpu.read_register(*source);
     let b = tpu.get_operand_value(value);
 
-    let new_pc = tpu.tpu_state.program_counter + offset;
-    set_program_counter_conditionally(tpu, a > b, new_pc)
+    branch_relative(tpu, a > b, target)
 }
 
 pub fn op_brlt(
@@ -223,12 +354,10 @@ pub fn op_brlt(
     source: &Register,
     value: &OperandValueType,
 ) -> ExecuteResult {
-    let offset = tpu.get_operand_value(target) as usize;
     let a = tpu.read_register(*source);
     let b = tpu.get_operand_value(value);
 
-    let new_pc = tpu.tpu_state.program_counter + offset;
-    set_program_counter_conditionally(tpu, a < b, new_pc)
+    branch_relative(tpu, a < b, target)
 }
 
 // Subroutines
@@ -241,20 +370,95 @@ pub fn op_jsr(tpu: &mut TPU, target: &OperandValueType) -> ExecuteResult {
         return ExecuteResult::Halt(HaltReason::StackOverflow);
     }
 
-    let old_pc = tpu.tpu_state.program_counter;
+    // Return to the line after the call
+    let return_address = tpu.tpu_state.program_counter + 1;
 
     let result = set_program_counter_conditionally(tpu, true, address as usize);
 
     if matches!(result, ExecuteResult::PCModified) {
         // Only push the return address if we've validated the landing address
         // And modified the program counter
-        tpu.push(old_pc as u16);
+        tpu.push_return_address(return_address as u16);
+    }
+    result
+}
+
+/// Call through a table, the slot is validated the same way as JTB
+pub fn op_jsrt(tpu: &mut TPU, base: &OperandValueType, index: &Register) -> ExecuteResult {
+    if tpu.tpu_state.stack.len() == TPU::STACK_SIZE {
+        return ExecuteResult::Halt(HaltReason::StackOverflow);
+    }
+
+    let address = (tpu.get_operand_value(base) as usize) + tpu.read_register(*index) as usize;
+    let return_address = tpu.tpu_state.program_counter + 1;
+
+    let result = set_program_counter_conditionally(tpu, true, address);
+
+    if matches!(result, ExecuteResult::PCModified) {
+        tpu.push_return_address(return_address as u16);
+    }
+    result
+}
+
+/// Software interrupt, calls the trap vector like JSR with the code in `TPU::TRAP_CODE`
+pub fn op_swi(tpu: &mut TPU, code: &OperandValueType) -> ExecuteResult {
+    let Some(vector) = tpu.tpu_state.config.trap_vector else {
+        return ExecuteResult::Halt(HaltReason::UnhandledTrap);
+    };
+
+    if tpu.tpu_state.stack.len() == TPU::STACK_SIZE {
+        return ExecuteResult::Halt(HaltReason::StackOverflow);
+    }
+
+    let code = tpu.get_operand_value(code);
+    let return_address = tpu.tpu_state.program_counter + 1;
+
+    let result = set_program_counter_conditionally(tpu, true, vector as usize);
+
+    if matches!(result, ExecuteResult::PCModified) {
+        tpu.push_return_address(return_address as u16);
+        tpu.write_register(TPU::TRAP_CODE, code);
+    }
+    result
+}
+
+/// Save the registers and jump, replacing any context that's already saved
+pub fn op_sjmp(tpu: &mut TPU, target: &OperandValueType) -> ExecuteResult {
+    let address = tpu.get_operand_value(target) as usize;
+    let context = SavedContext {
+        registers: tpu.tpu_state.registers,
+        return_address: tpu.tpu_state.program_counter + 1,
+    };
+
+    let result = set_program_counter_conditionally(tpu, true, address);
+
+    if matches!(result, ExecuteResult::PCModified) {
+        tpu.tpu_state.saved_context = Some(context);
+    }
+    result
+}
+
+/// Restore the registers saved by SJMP and return to the line after it
+pub fn op_sret(tpu: &mut TPU) -> ExecuteResult {
+    let Some(context) = tpu.tpu_state.saved_context else {
+        return ExecuteResult::Halt(HaltReason::NoSavedContext);
+    };
+
+    let result = set_program_counter_conditionally(tpu, true, context.return_address);
+
+    if matches!(result, ExecuteResult::PCModified) {
+        tpu.tpu_state.registers = context.registers;
+        tpu.tpu_state.saved_context = None;
     }
     result
 }
 
 pub fn op_rts(tpu: &mut TPU) -> ExecuteResult {
-    // Pop the return address from the stack
+    if tpu.tpu_state.stack.is_empty() {
+        return ExecuteResult::Halt(HaltReason::StackUnderflow);
+    }
+
+    // Pop the return address from the stack, it's validated like any other jump
     let address = tpu.pop() as usize;
     set_program_counter_conditionally(tpu, true, address)
 }
@@ -0,0 +1,94 @@
+use crate::tpu::{TPU, TpuState};
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+
+/// The states at the start of the last few instructions, see `TPU::enable_history`
+#[derive(Clone)]
+pub(crate) struct History {
+    depth: usize,
+    /// Oldest first, without their ROM, which doesn't change while the program runs
+    states: VecDeque<TpuState>,
+}
+
+impl History {
+    fn new(depth: usize) -> Self {
+        Self {
+            depth,
+            states: VecDeque::with_capacity(depth.min(1024)),
+        }
+    }
+
+    fn push(&mut self, state: TpuState) {
+        if self.states.len() == self.depth {
+            self.states.pop_front();
+        }
+        self.states.push_back(state);
+    }
+}
+
+impl TPU {
+    /// Start keeping the state at the start of up to `depth` instructions, so `TPU::step_back` can undo them. Any
+    /// history kept already is discarded.
+    pub fn enable_history(&mut self, depth: usize) {
+        self.history = (depth > 0).then(|| Box::new(History::new(depth)));
+    }
+
+    pub fn disable_history(&mut self) {
+        self.history = None;
+    }
+
+    /// How many instructions `TPU::step_back` can undo, `None` if history isn't enabled
+    pub fn history_len(&self) -> Option<usize> {
+        self.history.as_ref().map(|history| history.states.len())
+    }
+
+    /// Go back to the start of the last instruction, or of the one in progress, undoing what `TPU::step` did.
+    /// Returns false if there's no history to go back through. Peripherals aren't wound back, nor are changes to
+    /// the TPU between ticks, like a poked register, made after the instruction started.
+    pub fn step_back(&mut self) -> bool {
+        let Some(history) = &mut self.history else {
+            return false;
+        };
+        let Some(mut state) = history.states.pop_back() else {
+            return false;
+        };
+        // An instruction that waits, like WRX, starts again every tick until it's done, step back over all of it.
+        // A loop back onto the same instruction retires it each time round, so those are undone one at a time.
+        while history
+            .states
+            .back()
+            .is_some_and(|earlier| earlier.instructions_retired == state.instructions_retired)
+        {
+            state = history.states.pop_back().expect("checked above");
+        }
+
+        state.rom = core::mem::take(&mut self.tpu_state.rom);
+        self.tpu_state = state;
+        true
+    }
+
+    /// Keep the state if the next tick starts an instruction
+    pub(crate) fn record_history(&mut self) {
+        let starting = !self.tpu_state.halted
+            && self.tpu_state.execution_state.instruction.is_none()
+            && self.tpu_state.execution_state.wait_cycles <= 1;
+        if !starting || self.history.is_none() {
+            return;
+        }
+
+        // The ROM is put back from the TPU when stepping back, so it isn't copied every instruction
+        let rom = core::mem::take(&mut self.tpu_state.rom);
+        let state = self.tpu_state.clone();
+        self.tpu_state.rom = rom;
+        if let Some(history) = &mut self.history {
+            history.push(state);
+        }
+    }
+
+    /// Forget the history kept, the states in it can't be gone back to
+    pub(crate) fn clear_history(&mut self) {
+        if let Some(history) = &mut self.history {
+            history.states.clear();
+        }
+    }
+}
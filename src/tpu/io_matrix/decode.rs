@@ -1,6 +1,32 @@
 use crate::shared::DecodeResult;
-use crate::shared::OperandValueType;
+use crate::shared::{AnalogPin, OperandValueType};
 use crate::tpu::TPU;
+use strum::EnumCount;
+
+pub fn decode_op_pin_config_read() -> DecodeResult {
+    DecodeResult {
+        cycles: 1,
+        call_every_cycle: false,
+    }
+}
+
+pub fn decode_op_pin_config(pin: &OperandValueType, direction: &OperandValueType) -> DecodeResult {
+    let cycles = TPU::check_operand_cost(&[pin, direction]) + 2;
+
+    DecodeResult {
+        cycles,
+        call_every_cycle: false,
+    }
+}
+
+pub fn decode_op_pwm(pin: &OperandValueType, value: &OperandValueType) -> DecodeResult {
+    let cycles = TPU::check_operand_cost(&[pin, value]) + 2;
+
+    DecodeResult {
+        cycles,
+        call_every_cycle: false,
+    }
+}
 
 pub fn decode_op_dpw(target: &OperandValueType, value: &OperandValueType) -> DecodeResult {
     let cycles = TPU::check_operand_cost(&[target, value]) + 4;
@@ -39,6 +65,13 @@ pub fn decode_op_apw(target: &OperandValueType, value: &OperandValueType) -> Dec
     }
 }
 
+pub fn decode_op_cmpt(pin: &OperandValueType, threshold: &OperandValueType) -> DecodeResult {
+    DecodeResult {
+        cycles: TPU::check_operand_cost(&[pin, threshold]) + 2,
+        call_every_cycle: false,
+    }
+}
+
 // pub fn decode_op_apwh() -> DecodeResult {
 //     let mut cycles = 6;
 //
@@ -63,6 +96,20 @@ pub fn decode_op_xmit() -> DecodeResult {
     }
 }
 
+pub fn decode_op_xmitp(port: &OperandValueType, data: &OperandValueType) -> DecodeResult {
+    DecodeResult {
+        cycles: 10 + TPU::check_operand_cost(&[port, data]),
+        call_every_cycle: false,
+    }
+}
+
+pub fn decode_op_recvp(port: &OperandValueType) -> DecodeResult {
+    DecodeResult {
+        cycles: 10 + TPU::check_operand_cost(&[port]),
+        call_every_cycle: false,
+    }
+}
+
 pub fn decode_op_recv() -> DecodeResult {
     DecodeResult {
         cycles: 10,
@@ -70,6 +117,55 @@ pub fn decode_op_recv() -> DecodeResult {
     }
 }
 
+pub fn decode_op_xmitm(address: &OperandValueType, count: &OperandValueType) -> DecodeResult {
+    DecodeResult {
+        cycles: 10 + TPU::check_operand_cost(&[address, count]),
+        call_every_cycle: false,
+    }
+}
+
+pub fn decode_op_fwd(table: &OperandValueType) -> DecodeResult {
+    DecodeResult {
+        cycles: 10 + TPU::check_operand_cost(&[table]),
+        call_every_cycle: false,
+    }
+}
+
+pub fn decode_op_utx(value: &OperandValueType) -> DecodeResult {
+    DecodeResult {
+        cycles: TPU::check_operand_cost(&[value]) + 1,
+        call_every_cycle: false,
+    }
+}
+
+pub fn decode_op_urx() -> DecodeResult {
+    DecodeResult {
+        cycles: 1,
+        call_every_cycle: false,
+    }
+}
+
+pub fn decode_op_recvm(address: &OperandValueType) -> DecodeResult {
+    DecodeResult {
+        cycles: 10 + TPU::check_operand_cost(&[address]),
+        call_every_cycle: false,
+    }
+}
+
+pub fn decode_op_txok() -> DecodeResult {
+    DecodeResult {
+        cycles: 1,
+        call_every_cycle: false,
+    }
+}
+
+pub fn decode_op_rxav() -> DecodeResult {
+    DecodeResult {
+        cycles: 1,
+        call_every_cycle: false,
+    }
+}
+
 pub fn decode_op_txbs() -> DecodeResult {
     DecodeResult {
         cycles: 2,
@@ -84,6 +180,20 @@ pub fn decode_op_rxbs() -> DecodeResult {
     }
 }
 
+pub fn decode_op_netr() -> DecodeResult {
+    DecodeResult {
+        cycles: 1,
+        call_every_cycle: false,
+    }
+}
+
+pub fn decode_op_neta(address: &OperandValueType) -> DecodeResult {
+    DecodeResult {
+        cycles: TPU::check_operand_cost(&[address]) + 2,
+        call_every_cycle: false,
+    }
+}
+
 pub fn decode_op_dpww(value: &OperandValueType) -> DecodeResult {
     let cycles = TPU::check_operand_cost(&[value]) + 4;
     DecodeResult {
@@ -98,3 +208,41 @@ pub fn decode_op_dprw() -> DecodeResult {
         call_every_cycle: false,
     }
 }
+
+pub fn decode_op_aprw() -> DecodeResult {
+    DecodeResult {
+        cycles: 2,
+        call_every_cycle: false,
+    }
+}
+
+/// One cycle per analog pin on top of the instruction itself
+pub fn decode_op_analog_block(base: &OperandValueType) -> DecodeResult {
+    let cycles = TPU::check_operand_cost(&[base]) + 1 + AnalogPin::COUNT as u16;
+    DecodeResult {
+        cycles,
+        call_every_cycle: false,
+    }
+}
+
+pub fn decode_op_pevc() -> DecodeResult {
+    DecodeResult {
+        cycles: 1,
+        call_every_cycle: false,
+    }
+}
+
+pub fn decode_op_pevr() -> DecodeResult {
+    DecodeResult {
+        cycles: 2,
+        call_every_cycle: false,
+    }
+}
+
+pub fn decode_op_pulse_counter(pin: &OperandValueType) -> DecodeResult {
+    let cycles = TPU::check_operand_cost(&[pin]) + 1;
+    DecodeResult {
+        cycles,
+        call_every_cycle: false,
+    }
+}
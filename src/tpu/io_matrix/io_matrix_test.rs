@@ -2,7 +2,10 @@ use crate::shared::{
     AnalogPin, DigitalPin, ExecuteResult, HaltReason, NetPacket, OperandValueType, Register,
 };
 use crate::tpu::io_matrix::*;
-use crate::tpu::{TPU, TpuState, create_basic_tpu_config};
+use crate::tpu::{
+    AnalogNoise, Comparator, Flags, PinError, PwmChannel, TPU, TpuConfig, TpuState,
+    create_basic_tpu_config,
+};
 
 #[cfg(test)]
 mod tests {
@@ -15,21 +18,47 @@ mod tests {
     fn create_tpu_with_registers(a: u16, x: u16, y: u16) -> TPU {
         let mut tpu_state = TpuState {
             stack: Vec::new(),
+            stack_high_water: 0,
+            return_addresses: Vec::new(),
             analog_pins: [0; AnalogPin::COUNT],
             digital_pins: [false; DigitalPin::COUNT],
             analog_pin_config: [false; AnalogPin::COUNT],
             digital_pin_config: [false; DigitalPin::COUNT],
 
             ram: [0; TPU::RAM_SIZE],
+            nvram: Vec::new(),
             rom: vec![],
             network_address: 0x1,
             incoming_packets: VecDeque::new(),
             outgoing_packets: VecDeque::new(),
             registers: [0; Register::COUNT],
+            flags: Flags::default(),
+            data_base: 0,
+            hardware_loop: None,
+            saved_context: None,
+            pwm: [PwmChannel::default(); DigitalPin::COUNT],
+            cycle_count: 0,
+            instructions_retired: 0,
+            pin_events: VecDeque::new(),
+            pin_events_overflowed: false,
+            pulse_counts: [0; DigitalPin::COUNT],
+            bounce: [None; DigitalPin::COUNT],
+            bounce_rng: 1,
+            analog_inputs: [0; AnalogPin::COUNT],
+            noise_offsets: [0; AnalogPin::COUNT],
+            noise_rng: 1,
+            comparator_thresholds: [0; AnalogPin::COUNT],
+            tx_overflow: false,
+            tx_cooldown: 0,
+            ttl_drops: 0,
+            uart_tx: VecDeque::new(),
+            uart_rx: VecDeque::new(),
 
             program_counter: 0,
             halted: false,
+            halt_reason: None,
             execution_state: ExecutionState::default(),
+            config: TpuConfig::default(),
         };
 
         // Set register values
@@ -40,6 +69,11 @@ mod tests {
         TPU::new_from_state(tpu_state)
     }
 
+    // Helper function to send a single word from the TPU, skipping the buffer and cooldown checks XMIT makes
+    fn send_word(tpu: &mut TPU, target: u16, data: u16) {
+        tpu.send_packet(NetPacket::new(tpu.tpu_state.network_address, target, data));
+    }
+
     // Helper function to create a TPU with specific digital pin values
     fn create_tpu_with_digital_pins(pin_values: &[(DigitalPin, bool)]) -> TPU {
         let mut tpu = create_tpu_with_registers(0, 0, 0);
@@ -207,6 +241,1013 @@ mod tests {
         assert_eq!(result, ExecuteResult::Halt(HaltReason::IndexOutOfRange)); // Error
     }
 
+    #[test]
+    fn test_drive_inputs() {
+        let mut tpu = TPU::new(
+            0x1,
+            [true; AnalogPin::COUNT],
+            [true; DigitalPin::COUNT],
+            vec![],
+        );
+
+        // The program reads back what the host drives
+        assert_eq!(tpu.drive_digital_input(DigitalPin::Digital3, true), Ok(()));
+        op_dpr(&mut tpu, &Register::A, &OperandValueType::Immediate(3));
+        assert_eq!(tpu.read_register(Register::A), 1);
+
+        assert_eq!(tpu.drive_analog_input(AnalogPin::Analog2, 512), Ok(()));
+        op_apr(&mut tpu, &Register::A, &OperandValueType::Immediate(2));
+        assert_eq!(tpu.read_register(Register::A), 512);
+
+        // The program still can't write to them
+        op_dpw(
+            &mut tpu,
+            &OperandValueType::Immediate(3),
+            &OperandValueType::Immediate(0),
+        );
+        assert_eq!(tpu.get_digital_pins(), 1 << 3);
+
+        // Error case - outputs are driven by the program
+        let mut tpu = create_tpu_with_registers(0, 0, 0);
+        assert_eq!(
+            tpu.drive_digital_input(DigitalPin::Digital0, true),
+            Err(PinError::NotAnInput)
+        );
+        assert_eq!(
+            tpu.drive_analog_input(AnalogPin::Analog0, 1),
+            Err(PinError::NotAnInput)
+        );
+        assert_eq!(tpu.get_digital_pins(), 0);
+        assert_eq!(tpu.get_analog_pin(AnalogPin::Analog0), 0);
+    }
+
+    #[test]
+    fn test_op_dcfg_and_acfg() {
+        // All outputs to begin with
+        let mut tpu = create_tpu_with_registers(0, 0, 0);
+
+        let result = op_dcfg(
+            &mut tpu,
+            &OperandValueType::Immediate(1),
+            &OperandValueType::Immediate(1),
+        );
+        assert_eq!(result, ExecuteResult::PCAdvance);
+        assert!(tpu.digital_pin_is_input(DigitalPin::Digital1));
+        assert!(!tpu.digital_pin_is_input(DigitalPin::Digital0));
+
+        let result = op_acfg(
+            &mut tpu,
+            &OperandValueType::Immediate(2),
+            &OperandValueType::Immediate(5),
+        );
+        assert_eq!(result, ExecuteResult::PCAdvance);
+        assert!(tpu.analog_pin_is_input(AnalogPin::Analog2));
+
+        // Back to an output
+        op_acfg(
+            &mut tpu,
+            &OperandValueType::Immediate(2),
+            &OperandValueType::Immediate(0),
+        );
+        assert!(!tpu.analog_pin_is_input(AnalogPin::Analog2));
+
+        // Error case - invalid pin numbers
+        let result = op_dcfg(
+            &mut tpu,
+            &OperandValueType::Immediate(100),
+            &OperandValueType::Immediate(1),
+        );
+        assert_eq!(result, ExecuteResult::Halt(HaltReason::IndexOutOfRange));
+        let result = op_acfg(
+            &mut tpu,
+            &OperandValueType::Immediate(100),
+            &OperandValueType::Immediate(1),
+        );
+        assert_eq!(result, ExecuteResult::Halt(HaltReason::IndexOutOfRange));
+    }
+
+    #[test]
+    fn test_op_dcfr_and_acfr() {
+        // All inputs
+        let mut tpu = TPU::new(
+            0x1,
+            [true; AnalogPin::COUNT],
+            [true; DigitalPin::COUNT],
+            vec![],
+        );
+        let result = op_dcfr(&mut tpu, &Register::A, &OperandValueType::Immediate(3));
+        assert_eq!(result, ExecuteResult::PCAdvance);
+        assert_eq!(tpu.read_register(Register::A), 1);
+        tpu.write_register(Register::X, 1);
+        let result = op_acfr(
+            &mut tpu,
+            &Register::Y,
+            &OperandValueType::Register(Register::X),
+        );
+        assert_eq!(result, ExecuteResult::PCAdvance);
+        assert_eq!(tpu.read_register(Register::Y), 1);
+
+        // All outputs
+        let mut tpu = create_tpu_with_registers(5, 0, 5);
+        op_dcfr(&mut tpu, &Register::A, &OperandValueType::Immediate(3));
+        assert_eq!(tpu.read_register(Register::A), 0);
+        op_acfr(&mut tpu, &Register::Y, &OperandValueType::Immediate(1));
+        assert_eq!(tpu.read_register(Register::Y), 0);
+
+        // Follows a runtime change
+        op_dcfg(
+            &mut tpu,
+            &OperandValueType::Immediate(3),
+            &OperandValueType::Immediate(1),
+        );
+        op_dcfr(&mut tpu, &Register::A, &OperandValueType::Immediate(3));
+        assert_eq!(tpu.read_register(Register::A), 1);
+
+        // Error case - invalid pin numbers
+        let result = op_dcfr(&mut tpu, &Register::A, &OperandValueType::Immediate(100));
+        assert_eq!(result, ExecuteResult::Halt(HaltReason::IndexOutOfRange));
+        let result = op_acfr(&mut tpu, &Register::A, &OperandValueType::Immediate(100));
+        assert_eq!(result, ExecuteResult::Halt(HaltReason::IndexOutOfRange));
+    }
+
+    #[test]
+    fn test_analog_resolution() {
+        // Pin 0 is an output, pin 1 an input
+        let mut pin_config = [true; AnalogPin::COUNT];
+        pin_config[0] = false;
+        let create = |bits| {
+            TPU::new_with_config(
+                0x1,
+                pin_config,
+                [true; DigitalPin::COUNT],
+                vec![],
+                TpuConfig {
+                    analog_resolution_bits: bits,
+                    ..TpuConfig::default()
+                },
+            )
+        };
+
+        assert_eq!(create_basic_tpu_config(vec![]).analog_max(), u16::MAX);
+
+        for (bits, max) in [(10, 1023), (12, 4095)] {
+            let mut tpu = create(bits);
+            assert_eq!(tpu.analog_max(), max);
+
+            // Exact maximum is kept
+            op_apw(
+                &mut tpu,
+                &OperandValueType::Immediate(0),
+                &OperandValueType::Immediate(max),
+            );
+            op_apr(&mut tpu, &Register::A, &OperandValueType::Immediate(0));
+            assert_eq!(tpu.read_register(Register::A), max);
+
+            // One over and far over clamp
+            op_apw(
+                &mut tpu,
+                &OperandValueType::Immediate(0),
+                &OperandValueType::Immediate(max + 1),
+            );
+            op_apr(&mut tpu, &Register::A, &OperandValueType::Immediate(0));
+            assert_eq!(tpu.read_register(Register::A), max);
+            op_apw(
+                &mut tpu,
+                &OperandValueType::Immediate(0),
+                &OperandValueType::Immediate(0x5000),
+            );
+            assert_eq!(tpu.get_analog_pin(AnalogPin::Analog0), max);
+
+            // The host is clamped too
+            assert_eq!(tpu.drive_analog_input(AnalogPin::Analog1, max), Ok(()));
+            assert_eq!(tpu.get_analog_pin(AnalogPin::Analog1), max);
+            assert_eq!(tpu.drive_analog_input(AnalogPin::Analog1, max + 1), Ok(()));
+            op_apr(&mut tpu, &Register::X, &OperandValueType::Immediate(1));
+            assert_eq!(tpu.read_register(Register::X), max);
+        }
+    }
+
+    #[test]
+    fn test_pin_event_queue() {
+        let program = crate::rgal::parse_program(
+            r#"NOP
+            NOP
+            NOP
+            PEVC R0
+            PEVR
+            STM 0x10, X
+            STM 0x11, Y
+            STM 0x12, A
+            PEVR
+            STM 0x13, X
+            STM 0x14, Y
+            STM 0x15, A
+            PEVR
+            STM 0x16, X
+            STM 0x17, Y
+            STM 0x18, A
+            PEVR
+            PEVC R1
+            HLT"#,
+        )
+        .expect("parse failure");
+        let mut tpu = TPU::new_with_config(
+            0x1,
+            [true; AnalogPin::COUNT],
+            [true; DigitalPin::COUNT],
+            program,
+            TpuConfig {
+                pin_event_queue_size: 4,
+                ..TpuConfig::default()
+            },
+        );
+
+        // Three edges between ticks while the program idles, driving the same level again isn't an edge
+        tpu.drive_digital_input(DigitalPin::Digital2, true).unwrap();
+        tpu.tick();
+        tpu.tick();
+        tpu.drive_digital_input(DigitalPin::Digital5, true).unwrap();
+        tpu.drive_digital_input(DigitalPin::Digital5, true).unwrap();
+        tpu.tick();
+        tpu.drive_digital_input(DigitalPin::Digital2, false)
+            .unwrap();
+        assert_eq!(tpu.tpu_state.pin_events.len(), 3);
+
+        while !tpu.halted() {
+            tpu.tick();
+        }
+
+        assert_eq!(tpu.read_register(Register::R0), 3);
+        let drained: Vec<u16> = (0x10..0x19).map(|address| tpu.read_ram(address)).collect();
+        assert_eq!(drained, vec![2, 1, 0, 5, 1, 2, 2, 0, 3]);
+        // The queue is empty
+        assert_eq!(tpu.read_register(Register::X), u16::MAX);
+        assert_eq!(tpu.read_register(Register::R1), 0);
+    }
+
+    #[test]
+    fn test_pulse_counter() {
+        // Busy work while the host pulses pin 4, then read and clear the count
+        let program = crate::rgal::parse_program(
+            r#"LDR R0, 0
+            INC R0
+            STM R0, R0
+            BNE 1, R0, 40
+            PCNTR A, 4
+            PCNTC 4
+            PCNTR X, 4
+            HLT"#,
+        )
+        .expect("parse failure");
+        let mut tpu = TPU::new(
+            0x1,
+            [true; AnalogPin::COUNT],
+            [true; DigitalPin::COUNT],
+            program,
+        );
+
+        for tick in 0..42 {
+            // A pulse every 6 ticks, high for 2 of them
+            let level = tick % 6 < 2;
+            tpu.drive_digital_input(DigitalPin::Digital4, level)
+                .unwrap();
+            // Holding a level isn't an edge, neither is another pin
+            tpu.drive_digital_input(DigitalPin::Digital4, level)
+                .unwrap();
+            tpu.drive_digital_input(DigitalPin::Digital5, tick == 10)
+                .unwrap();
+            tpu.tick();
+        }
+
+        // Still busy in the loop
+        assert!(tpu.state().program_counter < 4);
+        while !tpu.halted() {
+            tpu.tick();
+        }
+
+        assert_eq!(tpu.read_register(Register::A), 7);
+        assert_eq!(tpu.read_register(Register::X), 0);
+        assert_eq!(tpu.pulse_count(DigitalPin::Digital4), 0);
+        assert_eq!(tpu.pulse_count(DigitalPin::Digital5), 1);
+
+        // Counts are part of the state and survive a snapshot
+        let restored = TPU::new_from_state(tpu.state().clone());
+        assert_eq!(restored.pulse_count(DigitalPin::Digital5), 1);
+
+        // The counter wraps, a reset clears it
+        tpu.tpu_state.pulse_counts[0] = u16::MAX;
+        tpu.drive_digital_input(DigitalPin::Digital0, true).unwrap();
+        assert_eq!(tpu.pulse_count(DigitalPin::Digital0), 0);
+        tpu.drive_digital_input(DigitalPin::Digital0, false)
+            .unwrap();
+        tpu.drive_digital_input(DigitalPin::Digital0, true).unwrap();
+        assert_eq!(tpu.pulse_count(DigitalPin::Digital0), 1);
+        tpu.reset();
+        assert_eq!(tpu.pulse_count(DigitalPin::Digital0), 0);
+
+        // Error case - invalid pin numbers
+        let pin = OperandValueType::Immediate(100);
+        assert_eq!(
+            op_pcntr(&mut tpu, &Register::A, &pin),
+            ExecuteResult::Halt(HaltReason::IndexOutOfRange)
+        );
+        assert_eq!(
+            op_pcntc(&mut tpu, &pin),
+            ExecuteResult::Halt(HaltReason::IndexOutOfRange)
+        );
+    }
+
+    /// Counts presses on pin 0 into R2 by polling, taking every high reading as a press
+    const NAIVE_PRESS_COUNTER: &str = r#"DPR R0, 0
+    BEQ 0, R0, 0
+    INC R2
+    DPR R0, 0
+    BNE 3, R0, 0
+    JMP 0"#;
+
+    /// Counts presses on pin 0 into R2, only trusting a level that is still there after a settling delay
+    const DEBOUNCED_PRESS_COUNTER: &str = r#"DPR R0, 0
+    BEQ 0, R0, 0
+    LDR R1, 20
+    DJNZ 3, R1
+    DPR R0, 0
+    BEQ 0, R0, 0
+    INC R2
+    DPR R0, 0
+    BNE 7, R0, 0
+    LDR R1, 20
+    DJNZ 10, R1
+    DPR R0, 0
+    BNE 7, R0, 0
+    JMP 0"#;
+
+    /// Press and release pin 0 a few times, returning the presses the program counted
+    fn count_presses(program: &str, presses: usize) -> u16 {
+        let program = crate::rgal::parse_program(program).expect("parse failure");
+        let mut tpu = TPU::new_with_config(
+            0x1,
+            [true; AnalogPin::COUNT],
+            [true; DigitalPin::COUNT],
+            program,
+            TpuConfig {
+                bounce_cycles: 20,
+                bounce_seed: 0x1234,
+                taken_branch_penalty: false,
+                ..TpuConfig::default()
+            },
+        );
+
+        for _ in 0..presses {
+            for level in [true, false] {
+                tpu.drive_digital_input(DigitalPin::Digital0, level)
+                    .unwrap();
+                for _ in 0..200 {
+                    tpu.tick();
+                }
+            }
+        }
+        tpu.read_register(Register::R2)
+    }
+
+    #[test]
+    fn test_input_bounce() {
+        assert!(count_presses(NAIVE_PRESS_COUNTER, 5) > 5);
+        assert_eq!(count_presses(DEBOUNCED_PRESS_COUNTER, 5), 5);
+
+        // The same seed always chatters the same way
+        assert_eq!(
+            count_presses(NAIVE_PRESS_COUNTER, 5),
+            count_presses(NAIVE_PRESS_COUNTER, 5)
+        );
+    }
+
+    /// Reads analog pin 1 into RAM from 0x20, 16 times
+    const ANALOG_SAMPLER: &str = r#"LDR X, 0
+    APR R0, 1
+    SMOI 0x20, R0, X
+    BLT 1, X, 16
+    HLT"#;
+
+    /// The values `ANALOG_SAMPLER` read with 500 driven onto pin 1, then 20 from the 8th tick, and the TPU
+    fn sample_analog(noise: Option<AnalogNoise>, seed: u32) -> (Vec<u16>, TPU) {
+        let mut config = TpuConfig {
+            noise_seed: seed,
+            ..TpuConfig::default()
+        };
+        config.analog_noise[AnalogPin::Analog1 as usize] = noise;
+        let mut tpu = TPU::new_with_config(
+            0x1,
+            [true; AnalogPin::COUNT],
+            [false; DigitalPin::COUNT],
+            crate::rgal::parse_program(ANALOG_SAMPLER).unwrap(),
+            config,
+        );
+
+        tpu.drive_analog_input(AnalogPin::Analog1, 500).unwrap();
+        while !tpu.halted() {
+            if tpu.state().cycle_count == 8 {
+                tpu.drive_analog_input(AnalogPin::Analog1, 20).unwrap();
+            }
+            tpu.tick();
+        }
+        (
+            (0x20..0x30).map(|address| tpu.read_ram(address)).collect(),
+            tpu,
+        )
+    }
+
+    #[test]
+    fn test_analog_noise() {
+        // No amplitude is the same as no noise
+        let (quiet, quiet_tpu) = sample_analog(None, 0);
+        for noise in [
+            AnalogNoise::Uniform { amplitude: 0 },
+            AnalogNoise::RandomWalk {
+                amplitude: 0,
+                step: 0,
+            },
+            AnalogNoise::RandomWalk {
+                amplitude: 0,
+                step: 5,
+            },
+        ] {
+            let (samples, tpu) = sample_analog(Some(noise), 99);
+            assert_eq!(samples, quiet);
+            assert_eq!(tpu.state().cycle_count, quiet_tpu.state().cycle_count);
+            assert_eq!(tpu.state().registers, quiet_tpu.state().registers);
+        }
+
+        // A fixed seed always reads the same, the true value is still there for the host
+        let uniform = AnalogNoise::Uniform { amplitude: 8 };
+        let (samples, tpu) = sample_analog(Some(uniform), 1234);
+        assert_eq!(
+            samples,
+            [
+                507, 12, 18, 25, 21, 24, 24, 15, 12, 19, 21, 20, 28, 18, 17, 21
+            ]
+        );
+        assert_eq!(tpu.true_analog_input(AnalogPin::Analog1), 20);
+        assert_ne!(samples, sample_analog(Some(uniform), 1235).0);
+
+        let walk = AnalogNoise::RandomWalk {
+            amplitude: 6,
+            step: 3,
+        };
+        let (samples, _) = sample_analog(Some(walk), 1234);
+        assert_eq!(
+            samples,
+            [
+                501, 14, 20, 14, 18, 26, 26, 14, 16, 20, 17, 18, 24, 20, 26, 22
+            ]
+        );
+    }
+
+    #[test]
+    fn test_analog_noise_snapshot() {
+        let mut config = TpuConfig {
+            noise_seed: 77,
+            ..TpuConfig::default()
+        };
+        config.analog_noise[AnalogPin::Analog0 as usize] = Some(AnalogNoise::RandomWalk {
+            amplitude: 100,
+            step: 10,
+        });
+        let mut tpu = TPU::new_with_config(
+            0x1,
+            [true; AnalogPin::COUNT],
+            [false; DigitalPin::COUNT],
+            crate::rgal::parse_program("HLT").unwrap(),
+            config,
+        );
+        tpu.drive_analog_input(AnalogPin::Analog0, 1000).unwrap();
+        for _ in 0..10 {
+            tpu.tick();
+        }
+
+        // The noise carries on from a snapshot exactly as it would have, and stays on the inputs it's configured for
+        let mut restored = TPU::new_from_state(tpu.state().clone());
+        for _ in 0..20 {
+            tpu.tick();
+            restored.tick();
+            let value = tpu.get_analog_pin(AnalogPin::Analog0);
+            assert_eq!(restored.get_analog_pin(AnalogPin::Analog0), value);
+            assert!((900..=1100).contains(&value));
+            assert_eq!(tpu.get_analog_pin(AnalogPin::Analog1), 0);
+        }
+
+        // A reset restarts the generator, the driven value survives it
+        tpu.reset();
+        restored.reset();
+        tpu.tick();
+        restored.tick();
+        assert_eq!(tpu.true_analog_input(AnalogPin::Analog0), 1000);
+        assert_eq!(
+            tpu.get_analog_pin(AnalogPin::Analog0),
+            restored.get_analog_pin(AnalogPin::Analog0)
+        );
+    }
+
+    /// A TPU with a comparator from analog pin 0 to digital pin 3, both inputs
+    fn create_tpu_with_comparator(program: &str, hysteresis: u16) -> TPU {
+        let mut config = TpuConfig {
+            pin_event_queue_size: 8,
+            ..TpuConfig::default()
+        };
+        config.comparators[AnalogPin::Analog0 as usize] = Some(Comparator {
+            digital: DigitalPin::Digital3,
+            threshold: 500,
+            hysteresis,
+        });
+        let mut digital_config = [false; DigitalPin::COUNT];
+        digital_config[DigitalPin::Digital3 as usize] = true;
+        TPU::new_with_config(
+            0x1,
+            [true; AnalogPin::COUNT],
+            digital_config,
+            crate::rgal::parse_program(program).unwrap(),
+            config,
+        )
+    }
+
+    /// Ramp analog pin 0 through `levels`, returning the levels the comparator output changed on
+    fn sweep(tpu: &mut TPU, levels: impl Iterator<Item = u16>) -> Vec<(u16, bool)> {
+        let mut transitions = Vec::new();
+        for level in levels {
+            let before = tpu.tpu_state.digital_pins[DigitalPin::Digital3 as usize];
+            tpu.drive_analog_input(AnalogPin::Analog0, level).unwrap();
+            tpu.tick();
+            let after = tpu.tpu_state.digital_pins[DigitalPin::Digital3 as usize];
+            if after != before {
+                transitions.push((level, after));
+            }
+        }
+        transitions
+    }
+
+    #[test]
+    fn test_analog_comparator() {
+        // Without hysteresis, high while above the threshold
+        let mut tpu = create_tpu_with_comparator("HLT", 0);
+        assert_eq!(sweep(&mut tpu, 0..=1000), [(501, true)]);
+        assert_eq!(sweep(&mut tpu, (0..=1000).rev()), [(500, false)]);
+
+        // Hysteresis holds the output until the level is clear of the threshold either way
+        let mut tpu = create_tpu_with_comparator("HLT", 20);
+        assert_eq!(sweep(&mut tpu, 0..=1000), [(521, true)]);
+        assert_eq!(sweep(&mut tpu, (0..=1000).rev()), [(480, false)]);
+        // Wandering around the threshold doesn't chatter
+        let wander = (0..100).map(|step| 490 + (step % 7) * 5);
+        assert_eq!(sweep(&mut tpu, wander), []);
+
+        // The pin event queue and pulse counter see the edges like any other input
+        assert_eq!(tpu.tpu_state.pin_events.len(), 2);
+        assert_eq!(tpu.pulse_count(DigitalPin::Digital3), 1);
+    }
+
+    #[test]
+    fn test_op_cmpt() {
+        let mut tpu = create_tpu_with_comparator(
+            r#"DPR R0, 3
+            CMPT 0, 100
+            NOP
+            DPR R1, 3
+            HLT"#,
+            20,
+        );
+        tpu.drive_analog_input(AnalogPin::Analog0, 150).unwrap();
+        while !tpu.halted() {
+            tpu.tick();
+        }
+        // Below 500 but above the new threshold of 100
+        assert_eq!(tpu.read_register(Register::R0), 0);
+        assert_eq!(tpu.read_register(Register::R1), 1);
+
+        // A reset goes back to the configured threshold
+        tpu.reset();
+        tpu.tick();
+        assert!(!tpu.tpu_state.digital_pins[DigitalPin::Digital3 as usize]);
+        assert_eq!(
+            tpu.tpu_state.comparator_thresholds[AnalogPin::Analog0 as usize],
+            500
+        );
+
+        // Only analog pins have comparators
+        let mut tpu = create_tpu_with_registers(0, 0, 0);
+        let result = op_cmpt(
+            &mut tpu,
+            &OperandValueType::Immediate(AnalogPin::COUNT as u16),
+            &OperandValueType::Immediate(1),
+        );
+        assert_eq!(result, ExecuteResult::Halt(HaltReason::IndexOutOfRange));
+    }
+
+    #[test]
+    fn test_input_bounce_settles() {
+        let config = TpuConfig {
+            bounce_cycles: 8,
+            bounce_seed: 7,
+            ..TpuConfig::default()
+        };
+        let mut tpu = TPU::new_with_config(
+            0x1,
+            [true; AnalogPin::COUNT],
+            [true; DigitalPin::COUNT],
+            crate::rgal::parse_program("HLT").unwrap(),
+            config,
+        );
+
+        // Bouncing carries on while halted, chatter shows up as extra edges, then the pin settles where it was driven
+        tpu.drive_digital_input(DigitalPin::Digital1, true).unwrap();
+        let mut levels = vec![];
+        for _ in 0..8 {
+            tpu.tick();
+            levels.push(tpu.get_digital_pin(DigitalPin::Digital1));
+        }
+        assert!(tpu.pulse_count(DigitalPin::Digital1) > 1);
+        assert_eq!(levels.last(), Some(&true));
+        assert_eq!(tpu.tpu_state.bounce[1], None);
+
+        // Off by default
+        let mut tpu = TPU::new(
+            0x1,
+            [true; AnalogPin::COUNT],
+            [true; DigitalPin::COUNT],
+            crate::rgal::parse_program("HLT").unwrap(),
+        );
+        tpu.drive_digital_input(DigitalPin::Digital1, true).unwrap();
+        for _ in 0..8 {
+            tpu.tick();
+            assert!(tpu.get_digital_pin(DigitalPin::Digital1));
+        }
+        assert_eq!(tpu.pulse_count(DigitalPin::Digital1), 1);
+    }
+
+    #[test]
+    fn test_pin_event_queue_overflow() {
+        let mut tpu = TPU::new_with_config(
+            0x1,
+            [true; AnalogPin::COUNT],
+            [true; DigitalPin::COUNT],
+            vec![],
+            TpuConfig {
+                pin_event_queue_size: 2,
+                ..TpuConfig::default()
+            },
+        );
+        for level in [true, false, true] {
+            tpu.drive_digital_input(DigitalPin::Digital0, level)
+                .unwrap();
+        }
+
+        // The newest edge is dropped and bit 15 reports it
+        op_pevc(&mut tpu, &Register::A);
+        assert_eq!(tpu.read_register(Register::A), 0x8002);
+        op_pevr(&mut tpu);
+        assert_eq!(tpu.read_register(Register::Y), 1);
+        op_pevc(&mut tpu, &Register::A);
+        assert_eq!(tpu.read_register(Register::A), 0x8001);
+
+        // Emptying the queue clears the indicator
+        op_pevr(&mut tpu);
+        assert_eq!(tpu.read_register(Register::Y), 0);
+        op_pevc(&mut tpu, &Register::A);
+        assert_eq!(tpu.read_register(Register::A), 0);
+
+        // Disabled by default
+        let mut tpu = TPU::new(
+            0x1,
+            [true; AnalogPin::COUNT],
+            [true; DigitalPin::COUNT],
+            vec![],
+        );
+        tpu.drive_digital_input(DigitalPin::Digital0, true).unwrap();
+        assert!(tpu.tpu_state.pin_events.is_empty());
+    }
+
+    #[test]
+    fn test_op_pwm() {
+        let mut tpu = create_tpu_with_registers(4, 0, 0);
+
+        let pin = OperandValueType::Immediate(2);
+        let result = op_pwmc(&mut tpu, &pin, &OperandValueType::Immediate(8));
+        assert_eq!(result, ExecuteResult::PCAdvance);
+        let result = op_pwmd(&mut tpu, &pin, &OperandValueType::Register(Register::A));
+        assert_eq!(result, ExecuteResult::PCAdvance);
+        let result = op_pwms(&mut tpu, &pin, &OperandValueType::Immediate(1));
+        assert_eq!(result, ExecuteResult::PCAdvance);
+        assert_eq!(
+            tpu.tpu_state.pwm[2],
+            PwmChannel {
+                period: 8,
+                duty: 4,
+                enabled: true
+            }
+        );
+
+        // DPW takes the pin out of PWM mode
+        op_dpw(&mut tpu, &pin, &OperandValueType::Immediate(1));
+        assert!(!tpu.tpu_state.pwm[2].enabled);
+        assert!(tpu.get_digital_pin(DigitalPin::Digital2));
+
+        // Disabling drives the pin low
+        op_pwms(&mut tpu, &pin, &OperandValueType::Immediate(1));
+        op_pwms(&mut tpu, &pin, &OperandValueType::Immediate(0));
+        assert!(!tpu.tpu_state.pwm[2].enabled);
+        assert!(!tpu.get_digital_pin(DigitalPin::Digital2));
+
+        // Error case - invalid pin numbers
+        let pin = OperandValueType::Immediate(100);
+        let value = OperandValueType::Immediate(1);
+        assert_eq!(
+            op_pwmc(&mut tpu, &pin, &value),
+            ExecuteResult::Halt(HaltReason::IndexOutOfRange)
+        );
+        assert_eq!(
+            op_pwmd(&mut tpu, &pin, &value),
+            ExecuteResult::Halt(HaltReason::IndexOutOfRange)
+        );
+        assert_eq!(
+            op_pwms(&mut tpu, &pin, &value),
+            ExecuteResult::Halt(HaltReason::IndexOutOfRange)
+        );
+    }
+
+    #[test]
+    fn test_pwm_duty_cycle() {
+        // 25% duty on pin 1, the program then halts and the PWM keeps running
+        let program = crate::rgal::parse_program(
+            r#"PWMC 1, 8
+            PWMD 1, 2
+            PWMS 1, 1
+            HLT"#,
+        )
+        .expect("parse failure");
+        let mut tpu = create_basic_tpu_config(program);
+        while !tpu.halted() {
+            tpu.tick();
+        }
+
+        let periods = 5;
+        let mut high = 0;
+        for _ in 0..periods * 8 {
+            tpu.tick();
+            if tpu.get_digital_pins() & 0b10 != 0 {
+                high += 1;
+            }
+        }
+        assert_eq!(high, periods * 2);
+
+        // The level follows the cycle counter
+        let cycle = tpu.tpu_state.cycle_count;
+        tpu.tick();
+        assert_eq!(tpu.get_digital_pin(DigitalPin::Digital1), cycle % 8 < 2);
+    }
+
+    #[test]
+    fn test_pin_direction_changes_mid_program() {
+        // Drive pin 0, turn it into an input, try to drive it again, then turn it back
+        let program = crate::rgal::parse_program(
+            r#"DPW 0, 1
+            DCFG 0, 1
+            DPW 0, 0
+            DPR R0, 0
+            DCFG 0, 0
+            DPW 0, 0
+            DPR R1, 0
+            HLT"#,
+        )
+        .expect("parse failure");
+        let mut tpu = create_basic_tpu_config(program);
+
+        let run_line = |tpu: &mut TPU| {
+            let pc = tpu.tpu_state.program_counter;
+            while tpu.tpu_state.program_counter == pc && !tpu.halted() {
+                tpu.tick();
+            }
+        };
+
+        run_line(&mut tpu);
+        assert_eq!(tpu.get_digital_pins(), 1);
+        run_line(&mut tpu);
+        assert!(tpu.digital_pin_is_input(DigitalPin::Digital0));
+
+        // As an input the write is ignored and the last driven value is kept
+        run_line(&mut tpu);
+        run_line(&mut tpu);
+        assert_eq!(tpu.read_register(Register::R0), 1);
+
+        // The host can now drive it
+        assert_eq!(tpu.drive_digital_input(DigitalPin::Digital0, false), Ok(()));
+        assert_eq!(tpu.get_digital_pins(), 0);
+        assert_eq!(tpu.drive_digital_input(DigitalPin::Digital0, true), Ok(()));
+
+        // Back to an output, writes take effect again
+        run_line(&mut tpu);
+        run_line(&mut tpu);
+        run_line(&mut tpu);
+        assert_eq!(tpu.read_register(Register::R1), 0);
+        assert_eq!(tpu.get_digital_pins(), 0);
+    }
+
+    #[test]
+    fn test_xmit_full_buffer() {
+        let program = crate::rgal::parse_program(
+            r#"XMIT A, 1
+            TXOK R0
+            XMIT A, 2
+            TXOK R1
+            TXOK R2
+            HLT"#,
+        )
+        .expect("parse failure");
+        let create = |xmit_blocks| {
+            let mut tpu = TPU::new_with_config(
+                0x1,
+                [true; AnalogPin::COUNT],
+                [true; DigitalPin::COUNT],
+                program.clone(),
+                TpuConfig {
+                    xmit_blocks,
+                    ..TpuConfig::default()
+                },
+            );
+            tpu.write_register(Register::A, 0x2);
+            tpu
+        };
+
+        // Dropping, room for the first packet but not the second
+        let mut tpu = create(false);
+        for data in 0..TPU::NET_BUFFER_SIZE as u16 - 1 {
+            send_word(&mut tpu, 0x2, data);
+        }
+        while !tpu.halted() {
+            tpu.tick();
+        }
+        assert_eq!(tpu.tpu_state.outgoing_packets.len(), TPU::NET_BUFFER_SIZE);
+        assert_eq!(tpu.tpu_state.outgoing_packets.back().unwrap().data(), 1);
+        assert_eq!(tpu.read_register(Register::R0), 1);
+        assert_eq!(tpu.read_register(Register::R1), 0);
+        // Reading it cleared the indicator
+        assert_eq!(tpu.read_register(Register::R2), 1);
+
+        // Blocking, the second XMIT waits until the host takes a packet
+        let mut tpu = create(true);
+        for data in 0..TPU::NET_BUFFER_SIZE as u16 - 1 {
+            send_word(&mut tpu, 0x2, data);
+        }
+        for _ in 0..50 {
+            tpu.tick();
+        }
+        assert_eq!(tpu.state().program_counter, 2);
+        tpu.tpu_state.outgoing_packets.pop_front();
+        while !tpu.halted() {
+            tpu.tick();
+        }
+        assert_eq!(tpu.tpu_state.outgoing_packets.len(), TPU::NET_BUFFER_SIZE);
+        assert_eq!(tpu.tpu_state.outgoing_packets.back().unwrap().data(), 2);
+        assert_eq!(tpu.read_register(Register::R1), 1);
+    }
+
+    #[test]
+    fn test_xmit_rate_limit() {
+        let program =
+            crate::rgal::parse_program(&format!("{}TXOK R0\nHLT", "XMIT A, 1\n".repeat(5)))
+                .expect("parse failure");
+        // The cycles each packet was sent on, and the cycle the TPU halted on
+        let run = |min_cycles_between_tx, xmit_blocks| {
+            let mut tpu = TPU::new_with_config(
+                0x1,
+                [true; AnalogPin::COUNT],
+                [true; DigitalPin::COUNT],
+                program.clone(),
+                TpuConfig {
+                    xmit_blocks,
+                    min_cycles_between_tx,
+                    ..TpuConfig::default()
+                },
+            );
+            tpu.write_register(Register::A, 0x2);
+            let mut sent = Vec::new();
+            while !tpu.halted() {
+                tpu.tick();
+                if tpu.tpu_state.outgoing_packets.len() > sent.len() {
+                    sent.push(tpu.tpu_state.cycle_count);
+                }
+            }
+            (sent, tpu)
+        };
+
+        let (unlimited, tpu) = run(0, false);
+        assert_eq!(unlimited.len(), 5);
+        let gap = unlimited[1] - unlimited[0];
+        let unlimited_halt = tpu.tpu_state.cycle_count;
+
+        // Stalling, the burst is stretched to one packet every 30 cycles
+        let (stalled, tpu) = run(30, true);
+        assert_eq!(stalled[0], unlimited[0]);
+        assert!(stalled.windows(2).all(|pair| pair[1] - pair[0] == 30));
+        assert_eq!(tpu.tpu_state.cycle_count, unlimited_halt + 4 * (30 - gap));
+        assert_eq!(tpu.read_register(Register::R0), 1);
+
+        // Flagging, nothing waits and the packets sent during the cooldown are dropped
+        let (flagged, tpu) = run(30, false);
+        assert_eq!(gap, 10);
+        // One XMIT every 10 cycles, so the fourth is the first after the cooldown
+        assert_eq!(flagged, [unlimited[0], unlimited[3]]);
+        assert_eq!(tpu.tpu_state.cycle_count, unlimited_halt);
+        assert_eq!(tpu.read_register(Register::R0), 0);
+
+        // A reset frees the link
+        let mut tpu = tpu;
+        tpu.tpu_state.tx_cooldown = 10;
+        tpu.reset();
+        assert_eq!(tpu.tpu_state.tx_cooldown, 0);
+    }
+
+    #[test]
+    fn test_op_fwd_table_bounds() {
+        // The entry for 0x10 would be past the end of RAM
+        let mut tpu = create_tpu_with_registers(0, 0, 0);
+        let address = tpu.tpu_state.network_address;
+        tpu.deliver_packet(NetPacket::with_payload(0x9, address, &[0x10, 1]));
+        let result = op_fwd(&mut tpu, &OperandValueType::Immediate(0x78));
+        assert_eq!(result, ExecuteResult::Halt(HaltReason::IndexOutOfRange));
+        assert_eq!(tpu.tpu_state.incoming_packets.len(), 1);
+        assert!(tpu.tpu_state.outgoing_packets.is_empty());
+
+        // A table under the I/O aliases is still read from RAM
+        let mut tpu = create_tpu_with_registers(0, 0, 0);
+        tpu.tpu_state.config.memory_mapped_io = true;
+        tpu.tpu_state.ram[TPU::MMIO_ANALOG_BASE + 2] = 0x3;
+        tpu.deliver_packet(NetPacket::with_payload(0x9, address, &[0x2, 1]));
+        let result = op_fwd(
+            &mut tpu,
+            &OperandValueType::Immediate(TPU::MMIO_ANALOG_BASE as u16),
+        );
+        assert_eq!(result, ExecuteResult::PCAdvance);
+        assert_eq!(tpu.tpu_state.outgoing_packets.front().unwrap().target, 0x3);
+    }
+
+    #[test]
+    fn test_fwd_rate_limit() {
+        let program =
+            crate::rgal::parse_program(&format!("{}TXOK R0\nHLT", "FWD 0x40\n".repeat(5)))
+                .expect("parse failure");
+        // The cycles each packet was forwarded on, and the TPU once it halted
+        let run = |min_cycles_between_tx, xmit_blocks| {
+            let mut tpu = TPU::new_with_config(
+                0x1,
+                [true; AnalogPin::COUNT],
+                [true; DigitalPin::COUNT],
+                program.clone(),
+                TpuConfig {
+                    xmit_blocks,
+                    min_cycles_between_tx,
+                    ..TpuConfig::default()
+                },
+            );
+            // Everything for 0x2 goes via 0x3
+            tpu.write_ram(0x42, 0x3);
+            for sequence in 0..5 {
+                tpu.deliver_packet(NetPacket::with_payload(0x9, 0x1, &[0x2, sequence]));
+            }
+            let mut sent = Vec::new();
+            while !tpu.halted() {
+                tpu.tick();
+                if tpu.tpu_state.outgoing_packets.len() > sent.len() {
+                    sent.push(tpu.tpu_state.cycle_count);
+                }
+            }
+            (sent, tpu)
+        };
+
+        let (unlimited, tpu) = run(0, false);
+        assert_eq!(unlimited.len(), 5);
+        let gap = unlimited[1] - unlimited[0];
+        assert!(gap < 30);
+        assert_eq!(tpu.read_register(Register::R0), 1);
+
+        // Stalling, the burst is stretched to one packet every 30 cycles and none are lost
+        let (stalled, tpu) = run(30, true);
+        assert_eq!(stalled[0], unlimited[0]);
+        assert!(stalled.windows(2).all(|pair| pair[1] - pair[0] == 30));
+        assert_eq!(tpu.read_register(Register::R0), 1);
+        let forwarded: Vec<u16> = tpu
+            .tpu_state
+            .outgoing_packets
+            .iter()
+            .map(|packet| packet.payload[1])
+            .collect();
+        assert_eq!(forwarded, [0, 1, 2, 3, 4]);
+        assert!(tpu.tpu_state.incoming_packets.is_empty());
+
+        // Flagging, the packets forwarded during the cooldown are dropped
+        let (flagged, tpu) = run(30, false);
+        assert_eq!(flagged.len(), 1 + 4 * gap as usize / 30);
+        assert_eq!(tpu.read_register(Register::R0), 0);
+        assert!(tpu.tpu_state.incoming_packets.is_empty());
+    }
+
     #[test]
     fn test_op_xmit() {
         // Test case 1: Send a packet
@@ -220,7 +1261,7 @@ mod tests {
         let packet = &tpu.tpu_state.outgoing_packets[0];
         assert_eq!(packet.sender, 0x1); // From our network address
         assert_eq!(packet.target, 0x2); // To the target address
-        assert_eq!(packet.data, 42); // With the data
+        assert_eq!(packet.data(), 42); // With the data
 
         // Test case 2: Send a packet with register values
         let mut tpu = create_tpu_with_registers(0, 0x3, 24);
@@ -232,17 +1273,148 @@ mod tests {
         let packet = &tpu.tpu_state.outgoing_packets[0];
         assert_eq!(packet.sender, 0x1); // From our network address
         assert_eq!(packet.target, 0x3); // To the target address
-        assert_eq!(packet.data, 24); // With the data
+        assert_eq!(packet.data(), 24); // With the data
+    }
+
+    #[test]
+    fn test_xmit_loopback() {
+        // Sent to our own address 0x1
+        let mut tpu = create_tpu_with_registers(0x1, 0, 0);
+        let result = op_xmit(&mut tpu, &Register::A, &OperandValueType::Immediate(42));
+        assert_eq!(result, ExecuteResult::PCAdvance);
+        assert!(tpu.tpu_state.outgoing_packets.is_empty());
+
+        op_rxbs(&mut tpu);
+        assert_eq!(tpu.read_register(Register::X), 1);
+        op_recv(&mut tpu);
+        assert_eq!(tpu.read_register(Register::X), 0x1);
+        assert_eq!(tpu.read_register(Register::Y), 42);
+
+        // A full incoming buffer drops it like a full outgoing one
+        let incoming = [NetPacket::new(0x2, 0x1, 0); TPU::NET_BUFFER_SIZE];
+        let mut tpu = create_tpu_with_network_packets(&incoming);
+        tpu.write_register(Register::A, 0x1);
+        op_xmit(&mut tpu, &Register::A, &OperandValueType::Immediate(42));
+        assert!(tpu.tpu_state.tx_overflow);
+        assert!(tpu.tpu_state.outgoing_packets.is_empty());
+        assert_eq!(tpu.tpu_state.incoming_packets.len(), TPU::NET_BUFFER_SIZE);
+    }
+
+    #[test]
+    fn test_op_xmitm() {
+        let mut tpu = create_tpu_with_registers(0x2, 0, 0);
+        tpu.write_ram_slice(0x10, &[1, 2, 3, 4, 5]);
+
+        let result = op_xmitm(
+            &mut tpu,
+            &Register::A,
+            &OperandValueType::Immediate(0x10),
+            &OperandValueType::Immediate(3),
+        );
+        assert_eq!(result, ExecuteResult::PCAdvance);
+        let packet = tpu.tpu_state.outgoing_packets.back().unwrap();
+        assert_eq!(packet.target, 0x2);
+        assert_eq!(packet.words(), &[1, 2, 3]);
+
+        // More words than a packet can carry
+        let result = op_xmitm(
+            &mut tpu,
+            &Register::A,
+            &OperandValueType::Immediate(0x10),
+            &OperandValueType::Immediate(5),
+        );
+        assert_eq!(result, ExecuteResult::Halt(HaltReason::IndexOutOfRange));
+        assert_eq!(tpu.tpu_state.outgoing_packets.len(), 1);
+    }
+
+    #[test]
+    fn test_op_xmitp() {
+        let mut tpu = create_tpu_with_registers(0x2, 0, 0);
+        let result = op_xmitp(
+            &mut tpu,
+            &Register::A,
+            &OperandValueType::Immediate(5),
+            &OperandValueType::Immediate(42),
+        );
+        assert_eq!(result, ExecuteResult::PCAdvance);
+        let packet = tpu.tpu_state.outgoing_packets.back().unwrap();
+        assert_eq!((packet.target, packet.port, packet.data()), (0x2, 5, 42));
+        assert!(packet.verify());
+
+        // The port is part of the checksum, port 0 checks the same as a packet from XMIT
+        let mut damaged = *packet;
+        damaged.port = 4;
+        assert!(!damaged.verify());
+        assert_eq!(
+            NetPacket::new(0x1, 0x2, 42).on_port(0),
+            NetPacket::new(0x1, 0x2, 42)
+        );
+
+        // Ports are a byte
+        let result = op_xmitp(
+            &mut tpu,
+            &Register::A,
+            &OperandValueType::Immediate(0x100),
+            &OperandValueType::Immediate(42),
+        );
+        assert_eq!(result, ExecuteResult::Halt(HaltReason::InvalidValue));
+        assert_eq!(tpu.tpu_state.outgoing_packets.len(), 1);
+    }
+
+    #[test]
+    fn test_op_recvp() {
+        let incoming = [
+            NetPacket::new(0x2, 0x1, 100).on_port(2),
+            NetPacket::new(0x3, 0x1, 7).on_port(1),
+            NetPacket::new(0x2, 0x1, 101).on_port(2),
+        ];
+        let mut tpu = create_tpu_with_network_packets(&incoming);
+
+        let result = op_recvp(&mut tpu, &OperandValueType::Immediate(1));
+        assert_eq!(result, ExecuteResult::PCAdvance);
+        assert_eq!(tpu.read_register(Register::X), 0x3);
+        assert_eq!(tpu.read_register(Register::Y), 7);
+
+        // Nothing left on port 1, the other packets keep their order
+        op_recvp(&mut tpu, &OperandValueType::Immediate(1));
+        assert_eq!(tpu.read_register(Register::X), TPU::BROADCAST_ADDRESS);
+        assert_eq!(tpu.read_register(Register::Y), 0);
+        assert_eq!(tpu.tpu_state.incoming_packets.len(), 2);
+
+        // RECV takes from any port
+        op_recv(&mut tpu);
+        assert_eq!(tpu.read_register(Register::Y), 100);
+        op_recvp(&mut tpu, &OperandValueType::Immediate(2));
+        assert_eq!(tpu.read_register(Register::Y), 101);
+        assert!(tpu.tpu_state.incoming_packets.is_empty());
+
+        let result = op_recvp(&mut tpu, &OperandValueType::Immediate(0x100));
+        assert_eq!(result, ExecuteResult::Halt(HaltReason::InvalidValue));
+    }
+
+    #[test]
+    fn test_op_recvm() {
+        let incoming = [NetPacket::with_payload(0x2, 0x1, &[7, 8])];
+        let mut tpu = create_tpu_with_network_packets(&incoming);
+        let result = op_recvm(&mut tpu, &OperandValueType::Immediate(0x10));
+        assert_eq!(result, ExecuteResult::PCAdvance);
+        assert_eq!(tpu.read_register(Register::X), 0x2);
+        assert_eq!(tpu.read_register(Register::Y), 2);
+        assert_eq!(tpu.read_ram(0x10), 7);
+        assert_eq!(tpu.read_ram(0x11), 8);
+        assert!(tpu.tpu_state.incoming_packets.is_empty());
+
+        // Empty queue, like RECV
+        let result = op_recvm(&mut tpu, &OperandValueType::Immediate(0x10));
+        assert_eq!(result, ExecuteResult::PCAdvance);
+        assert_eq!(tpu.read_register(Register::X), TPU::BROADCAST_ADDRESS);
+        assert_eq!(tpu.read_register(Register::Y), 0);
     }
 
     #[test]
     fn test_op_recv() {
         // Test case 1: Receive a packet
-        let incoming = [NetPacket {
-            sender: 0x2,
-            target: 0x1,
-            data: 42,
-        }];
+        let incoming = [NetPacket::new(0x2, 0x1, 42)];
         let mut tpu = create_tpu_with_network_packets(&incoming);
         let result = op_recv(&mut tpu);
         assert_eq!(result, ExecuteResult::PCAdvance); // No error
@@ -254,8 +1426,73 @@ mod tests {
         let mut tpu = create_tpu_with_registers(0, 0, 0);
         let result = op_recv(&mut tpu);
         assert_eq!(result, ExecuteResult::PCAdvance); // No error
-        assert_eq!(tpu.read_register(Register::X), 0); // Default sender
-        assert_eq!(tpu.read_register(Register::Y), 0); // Default data
+        assert_eq!(tpu.read_register(Register::X), TPU::BROADCAST_ADDRESS); // No sender
+        assert_eq!(tpu.read_register(Register::Y), 0); // No data
+
+        // Test case 3: A real packet from address 0 carrying 0 is told apart from an empty queue
+        let incoming = [NetPacket::new(0, 0x1, 0)];
+        let mut tpu = create_tpu_with_network_packets(&incoming);
+        tpu.write_register(Register::X, 0x55);
+        op_recv(&mut tpu);
+        assert_eq!(tpu.read_register(Register::X), 0);
+    }
+
+    #[test]
+    fn test_op_rxav() {
+        let mut tpu = create_tpu_with_registers(0, 0, 0);
+        let result = op_rxav(&mut tpu, &Register::A);
+        assert_eq!(result, ExecuteResult::PCAdvance);
+        assert_eq!(tpu.read_register(Register::A), 0);
+
+        let incoming = [NetPacket::new(0x2, 0x1, 1), NetPacket::new(0x3, 0x1, 2)];
+        let mut tpu = create_tpu_with_network_packets(&incoming);
+        op_rxav(&mut tpu, &Register::A);
+        assert_eq!(tpu.read_register(Register::A), 1);
+
+        // Doesn't take the packet
+        assert_eq!(tpu.tpu_state.incoming_packets.len(), 2);
+    }
+
+    #[test]
+    fn test_op_netr_neta() {
+        let mut tpu = create_tpu_with_registers(0, 0, 0x7);
+        let result = op_netr(&mut tpu, &Register::A);
+        assert_eq!(result, ExecuteResult::PCAdvance);
+        assert_eq!(
+            tpu.read_register(Register::A),
+            tpu.tpu_state.network_address
+        );
+
+        let result = op_neta(&mut tpu, &OperandValueType::Register(Register::Y));
+        assert_eq!(result, ExecuteResult::PCAdvance);
+        op_netr(&mut tpu, &Register::A);
+        assert_eq!(tpu.read_register(Register::A), 0x7);
+
+        // Packets to the new address loop back
+        send_word(&mut tpu, 0x7, 42);
+        assert_eq!(tpu.tpu_state.incoming_packets.len(), 1);
+        assert!(tpu.tpu_state.outgoing_packets.is_empty());
+    }
+
+    #[test]
+    fn test_op_neta_broadcast_address() {
+        let mut tpu = create_tpu_with_registers(0, 0, 0);
+        let address = tpu.tpu_state.network_address;
+        let result = op_neta(
+            &mut tpu,
+            &OperandValueType::Immediate(TPU::BROADCAST_ADDRESS),
+        );
+        assert_eq!(result, ExecuteResult::Halt(HaltReason::InvalidValue));
+        assert_eq!(tpu.tpu_state.network_address, address);
+
+        // With broadcast disabled it's a normal address
+        tpu.tpu_state.config.broadcast = false;
+        let result = op_neta(
+            &mut tpu,
+            &OperandValueType::Immediate(TPU::BROADCAST_ADDRESS),
+        );
+        assert_eq!(result, ExecuteResult::PCAdvance);
+        assert_eq!(tpu.tpu_state.network_address, TPU::BROADCAST_ADDRESS);
     }
 
     #[test]
@@ -269,8 +1506,8 @@ mod tests {
         // Test case 2: Get transmit buffer size (with packets)
         let mut tpu = create_tpu_with_registers(0, 0, 0);
         // Add some outgoing packets
-        tpu.send_packet(0x2, 42);
-        tpu.send_packet(0x3, 24);
+        send_word(&mut tpu, 0x2, 42);
+        send_word(&mut tpu, 0x3, 24);
         let result = op_txbs(&mut tpu);
         assert_eq!(result, ExecuteResult::PCAdvance); // No error
         assert_eq!(tpu.read_register(Register::X), 2); // Two packets in buffer
@@ -285,18 +1522,7 @@ mod tests {
         assert_eq!(tpu.read_register(Register::X), 0); // Empty buffer
 
         // Test case 2: Get receive buffer size (with packets)
-        let incoming = [
-            NetPacket {
-                sender: 0x2,
-                target: 0x1,
-                data: 42,
-            },
-            NetPacket {
-                sender: 0x3,
-                target: 0x1,
-                data: 24,
-            },
-        ];
+        let incoming = [NetPacket::new(0x2, 0x1, 42), NetPacket::new(0x3, 0x1, 24)];
         let mut tpu = create_tpu_with_network_packets(&incoming);
         let result = op_rxbs(&mut tpu);
         assert_eq!(result, ExecuteResult::PCAdvance); // No error
@@ -347,7 +1573,7 @@ mod tests {
         }
 
         // Test case 2: Set all pins to 1
-        let all_pins_mask = (1 << DigitalPin::COUNT) - 1;
+        let all_pins_mask = TPU::digital_pin_mask();
         tpu.set_digital_pins(all_pins_mask);
         for pin in DigitalPin::iter() {
             assert_eq!(tpu.get_digital_pin(pin), true);
@@ -383,7 +1609,7 @@ mod tests {
         for pin in DigitalPin::iter() {
             tpu.set_digital_pin(pin, true);
         }
-        let all_pins_mask = (1 << DigitalPin::COUNT) - 1;
+        let all_pins_mask = TPU::digital_pin_mask();
         assert_eq!(tpu.get_digital_pins(), all_pins_mask);
 
         // Test case 3: Alternating pins
@@ -407,7 +1633,7 @@ mod tests {
         assert_eq!(tpu.get_digital_pins(), 0);
 
         // Test case 2: Set all pins to 1
-        let all_pins_mask = (1 << DigitalPin::COUNT) - 1;
+        let all_pins_mask = TPU::digital_pin_mask();
         let value = OperandValueType::Immediate(all_pins_mask);
         let result = op_dpww(&mut tpu, &value);
         assert_eq!(result, ExecuteResult::PCAdvance); // No error
@@ -428,6 +1654,84 @@ mod tests {
         assert_eq!(tpu.get_digital_pins(), alternating_mask);
     }
 
+    #[test]
+    fn test_op_aprw() {
+        let mut tpu = create_tpu_with_registers(0, 0, 0);
+        for (pin, value) in AnalogPin::iter().zip([0x0000, 0x1FFF, 0xA000, 0xFFFF]) {
+            tpu.set_analog_pin(pin, value);
+        }
+        let result = op_aprw(&mut tpu, &Register::A);
+        assert_eq!(result, ExecuteResult::PCAdvance);
+        assert_eq!(tpu.read_register(Register::A), 0xFA10);
+
+        // The top 4 bits of a 10 bit pin
+        tpu.tpu_state.config.analog_resolution_bits = 10;
+        tpu.set_analog_pin(AnalogPin::Analog0, 0x3C0);
+        op_aprw(&mut tpu, &Register::A);
+        assert_eq!(tpu.read_register(Register::A) & 0xF, 0xF);
+    }
+
+    #[test]
+    fn test_analog_block_round_trip() {
+        // Analog3 is an input, the others outputs
+        let mut pin_config = [false; AnalogPin::COUNT];
+        pin_config[3] = true;
+        let program = crate::rgal::parse_program(
+            r#"APWA 0x10
+            APRA X
+            HLT"#,
+        )
+        .expect("parse failure");
+        let mut tpu = TPU::new(0x1, pin_config, [false; DigitalPin::COUNT], program);
+
+        let pattern = [0x1234, 0x00FF, 0xBEEF, 0x5555];
+        tpu.write_ram_slice(0x10, &pattern);
+        tpu.drive_analog_input(AnalogPin::Analog3, 7).unwrap();
+        tpu.write_register(Register::X, 0x20);
+
+        // Immediate base, one cycle per pin on top of the instruction
+        let mut cycles = 0;
+        while tpu.state().program_counter == 0 {
+            tpu.tick();
+            cycles += 1;
+        }
+        assert_eq!(cycles, 1 + AnalogPin::COUNT);
+        assert_eq!(tpu.get_analog_pin(AnalogPin::Analog0), 0x1234);
+        assert_eq!(tpu.get_analog_pin(AnalogPin::Analog1), 0x00FF);
+        assert_eq!(tpu.get_analog_pin(AnalogPin::Analog2), 0xBEEF);
+        // The input wasn't written
+        assert_eq!(tpu.get_analog_pin(AnalogPin::Analog3), 7);
+
+        // A register base costs one more
+        let mut cycles = 0;
+        while tpu.state().program_counter == 1 {
+            tpu.tick();
+            cycles += 1;
+        }
+        assert_eq!(cycles, 2 + AnalogPin::COUNT);
+        for address in 0x20..0x20 + AnalogPin::COUNT {
+            let pin = AnalogPin::from_repr((address - 0x20) as u16).unwrap();
+            assert_eq!(tpu.read_ram(address), tpu.get_analog_pin(pin));
+        }
+        assert_eq!(tpu.read_ram(0x23), 7);
+
+        // A write protected block is left untouched
+        let mut tpu = TPU::new_with_config(
+            0x1,
+            pin_config,
+            [false; DigitalPin::COUNT],
+            vec![],
+            TpuConfig {
+                read_only_ram: Some(0x22..0x23),
+                ..TpuConfig::default()
+            },
+        );
+        tpu.set_analog_pin(AnalogPin::Analog0, 9);
+        let result = op_apra(&mut tpu, &OperandValueType::Immediate(0x20));
+        assert_eq!(result, ExecuteResult::Halt(HaltReason::WriteProtected));
+        assert_eq!(tpu.read_ram(0x20), 0);
+    }
+
     #[test]
     fn test_op_dprw() {
         // Test case 1: Read all pins (all LOW)
@@ -445,7 +1749,7 @@ mod tests {
         for pin in DigitalPin::iter() {
             tpu.set_digital_pin(pin, true);
         }
-        let all_pins_mask = (1 << DigitalPin::COUNT) - 1;
+        let all_pins_mask = TPU::digital_pin_mask();
         let target = Register::A;
         let result = op_dprw(&mut tpu, &target);
         assert_eq!(result, ExecuteResult::PCAdvance); // No error
@@ -453,7 +1757,7 @@ mod tests {
 
         // Test case 3: Read all pins (alternating)
         let mut tpu = create_tpu_with_registers(0, 0, 0);
-        let all_pins_mask = (1 << DigitalPin::COUNT) - 1;
+        let all_pins_mask = TPU::digital_pin_mask();
         let alternating_mask = 0b01010101 & all_pins_mask; // Only use valid pins
         for pin in DigitalPin::iter() {
             let value = (alternating_mask & (1 << (pin as u16))) != 0;
@@ -463,5 +1767,53 @@ mod tests {
         let result = op_dprw(&mut tpu, &target);
         assert_eq!(result, ExecuteResult::PCAdvance); // No error
         assert_eq!(tpu.read_register(Register::A), alternating_mask);
+
+        // Test case 4: Bits above the pins read as zero
+        let mut tpu = create_tpu_with_registers(0, 0, 0);
+        op_dpww(&mut tpu, &OperandValueType::Immediate(0xFFFF));
+        op_dprw(&mut tpu, &Register::A);
+        assert_eq!(tpu.read_register(Register::A), TPU::digital_pin_mask());
+    }
+
+    #[test]
+    fn test_op_dpww_stray_bits() {
+        let stray = !TPU::digital_pin_mask() & 0xA500;
+        assert_ne!(stray, 0);
+
+        // Bits for missing pins are ignored and set the carry flag
+        let mut tpu = create_basic_tpu_config(vec![]);
+        let result = op_dpww(&mut tpu, &OperandValueType::Immediate(stray | 0b101));
+        assert_eq!(result, ExecuteResult::PCAdvance);
+        assert_eq!(tpu.get_digital_pins(), 0b101);
+        assert!(tpu.tpu_state.flags.carry);
+
+        // A clean word clears it again
+        let result = op_dpww(&mut tpu, &OperandValueType::Immediate(0b11));
+        assert_eq!(result, ExecuteResult::PCAdvance);
+        assert_eq!(tpu.get_digital_pins(), 0b11);
+        assert!(!tpu.tpu_state.flags.carry);
+
+        // The strict config halts and leaves the pins alone
+        let mut tpu = TPU::new_with_config(
+            0x1,
+            [false; AnalogPin::COUNT],
+            [false; DigitalPin::COUNT],
+            vec![],
+            TpuConfig {
+                strict_pin_words: true,
+                ..TpuConfig::default()
+            },
+        );
+        let result = op_dpww(&mut tpu, &OperandValueType::Immediate(0b11));
+        assert_eq!(result, ExecuteResult::PCAdvance);
+        let result = op_dpww(&mut tpu, &OperandValueType::Immediate(stray | 0b101));
+        assert_eq!(result, ExecuteResult::Halt(HaltReason::InvalidValue));
+        assert_eq!(tpu.get_digital_pins(), 0b11);
+        let result = op_dpww(
+            &mut tpu,
+            &OperandValueType::Immediate(TPU::digital_pin_mask()),
+        );
+        assert_eq!(result, ExecuteResult::PCAdvance);
+        assert_eq!(tpu.get_digital_pins(), TPU::digital_pin_mask());
     }
 }
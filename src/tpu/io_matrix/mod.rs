@@ -2,8 +2,12 @@ pub mod decode;
 #[cfg(test)]
 mod io_matrix_test;
 
-use crate::shared::{AnalogPin, DigitalPin, ExecuteResult, HaltReason, OperandValueType, Register};
-use crate::tpu::TPU;
+use crate::shared::{
+    AnalogPin, DigitalPin, ExecuteResult, HaltReason, NetPacket, OperandValueType, Register,
+};
+use crate::tpu::{PwmChannel, TPU};
+use alloc::vec::Vec;
+use strum::{EnumCount, IntoEnumIterator};
 
 // Digital Pin operations
 pub fn op_dpw(
@@ -78,6 +82,20 @@ pub fn op_apw(
     ExecuteResult::PCAdvance
 }
 
+/// Comparator Threshold, moves the threshold of the comparator on an analog pin, from the next tick
+pub fn op_cmpt(
+    tpu: &mut TPU,
+    pin: &OperandValueType,
+    threshold: &OperandValueType,
+) -> ExecuteResult {
+    let Some(pin) = AnalogPin::from_repr(tpu.get_operand_value(pin)) else {
+        return ExecuteResult::Halt(HaltReason::IndexOutOfRange);
+    };
+    tpu.tpu_state.comparator_thresholds[pin as usize] = tpu.get_operand_value(threshold);
+
+    ExecuteResult::PCAdvance
+}
+
 // pub fn op_apwh(tpu: &mut TPU, operands: &[Operand]) -> ExecuteResult {
 //     // Get the pin number and value
 //     let pin_num = tpu.get_operand_value(&operands[0]);
@@ -96,6 +114,92 @@ pub fn op_apw(
 //     ExecuteResult::Continue
 // }
 
+// Pin direction, a pin that becomes an input keeps the value it was last driven to until the host drives it
+pub fn op_dcfg(
+    tpu: &mut TPU,
+    pin: &OperandValueType,
+    direction: &OperandValueType,
+) -> ExecuteResult {
+    let Some(pin) = DigitalPin::from_repr(tpu.get_operand_value(pin)) else {
+        return ExecuteResult::Halt(HaltReason::IndexOutOfRange);
+    };
+
+    tpu.tpu_state.digital_pin_config[pin as usize] = tpu.get_operand_value(direction) != 0;
+    ExecuteResult::PCAdvance
+}
+
+pub fn op_acfg(
+    tpu: &mut TPU,
+    pin: &OperandValueType,
+    direction: &OperandValueType,
+) -> ExecuteResult {
+    let Some(pin) = AnalogPin::from_repr(tpu.get_operand_value(pin)) else {
+        return ExecuteResult::Halt(HaltReason::IndexOutOfRange);
+    };
+
+    tpu.tpu_state.analog_pin_config[pin as usize] = tpu.get_operand_value(direction) != 0;
+    ExecuteResult::PCAdvance
+}
+
+pub fn op_dcfr(tpu: &mut TPU, target: &Register, pin: &OperandValueType) -> ExecuteResult {
+    let Some(pin) = DigitalPin::from_repr(tpu.get_operand_value(pin)) else {
+        return ExecuteResult::Halt(HaltReason::IndexOutOfRange);
+    };
+
+    let input = tpu.digital_pin_is_input(pin);
+    tpu.write_register(*target, input as u16);
+    ExecuteResult::PCAdvance
+}
+
+pub fn op_acfr(tpu: &mut TPU, target: &Register, pin: &OperandValueType) -> ExecuteResult {
+    let Some(pin) = AnalogPin::from_repr(tpu.get_operand_value(pin)) else {
+        return ExecuteResult::Halt(HaltReason::IndexOutOfRange);
+    };
+
+    let input = tpu.analog_pin_is_input(pin);
+    tpu.write_register(*target, input as u16);
+    ExecuteResult::PCAdvance
+}
+
+/// The PWM settings of a digital pin operand, None if the pin doesn't exist
+fn pwm_channel<'a>(tpu: &'a mut TPU, pin: &OperandValueType) -> Option<&'a mut PwmChannel> {
+    let pin = DigitalPin::from_repr(tpu.get_operand_value(pin))?;
+    Some(&mut tpu.tpu_state.pwm[pin as usize])
+}
+
+pub fn op_pwmc(tpu: &mut TPU, pin: &OperandValueType, period: &OperandValueType) -> ExecuteResult {
+    let period = tpu.get_operand_value(period);
+    let Some(channel) = pwm_channel(tpu, pin) else {
+        return ExecuteResult::Halt(HaltReason::IndexOutOfRange);
+    };
+
+    channel.period = period;
+    ExecuteResult::PCAdvance
+}
+
+pub fn op_pwmd(tpu: &mut TPU, pin: &OperandValueType, duty: &OperandValueType) -> ExecuteResult {
+    let duty = tpu.get_operand_value(duty);
+    let Some(channel) = pwm_channel(tpu, pin) else {
+        return ExecuteResult::Halt(HaltReason::IndexOutOfRange);
+    };
+
+    channel.duty = duty;
+    ExecuteResult::PCAdvance
+}
+
+pub fn op_pwms(tpu: &mut TPU, pin: &OperandValueType, enable: &OperandValueType) -> ExecuteResult {
+    let Some(pin) = DigitalPin::from_repr(tpu.get_operand_value(pin)) else {
+        return ExecuteResult::Halt(HaltReason::IndexOutOfRange);
+    };
+
+    let enabled = tpu.get_operand_value(enable) != 0;
+    tpu.tpu_state.pwm[pin as usize].enabled = enabled;
+    if !enabled {
+        tpu.set_digital_pin(pin, false);
+    }
+    ExecuteResult::PCAdvance
+}
+
 pub fn op_apr(tpu: &mut TPU, target: &Register, source: &OperandValueType) -> ExecuteResult {
     let pin_num = tpu.get_operand_value(source);
 
@@ -115,24 +219,226 @@ pub fn op_xmit(tpu: &mut TPU, target: &Register, data: &OperandValueType) -> Exe
     let target = tpu.read_register(*target);
     let data = tpu.get_operand_value(data);
 
-    // Send the packet if there's room in the buffer
-    if tpu.tpu_state.outgoing_packets.len() < TPU::NET_BUFFER_SIZE {
-        tpu.send_packet(target, data);
+    transmit(tpu, target, 0, &[data])
+}
+
+/// Transmit to Port, like XMIT but on the given port
+pub fn op_xmitp(
+    tpu: &mut TPU,
+    target: &Register,
+    port: &OperandValueType,
+    data: &OperandValueType,
+) -> ExecuteResult {
+    let target = tpu.read_register(*target);
+    let Ok(port) = u8::try_from(tpu.get_operand_value(port)) else {
+        return ExecuteResult::Halt(HaltReason::InvalidValue);
+    };
+    let data = tpu.get_operand_value(data);
+
+    transmit(tpu, target, port, &[data])
+}
+
+/// Transmit Multiple, sends count words of RAM starting at address as one packet
+pub fn op_xmitm(
+    tpu: &mut TPU,
+    target: &Register,
+    address: &OperandValueType,
+    count: &OperandValueType,
+) -> ExecuteResult {
+    let target = tpu.read_register(*target);
+    let address = tpu.get_operand_value(address) as usize;
+    let count = tpu.get_operand_value(count) as usize;
+
+    if count > NetPacket::MAX_PAYLOAD {
+        return ExecuteResult::Halt(HaltReason::IndexOutOfRange);
+    }
+
+    let words: Vec<u16> = (address..address + count)
+        .map(|address| tpu.read_ram(address))
+        .collect();
+    transmit(tpu, target, 0, &words)
+}
+
+/// Forward, sends the oldest waiting packet on toward the final target in its first word.
+///
+/// The next hop is read from the routing table at `table` plus the final target. A packet for this TPU is left
+/// waiting for RECV or RECVM, one with no hops left is dropped and counted. Sending is limited like XMIT, a packet
+/// that can't be sent is dropped or waited on as configured. The table is always read from RAM, even where memory
+/// mapped I/O aliases it, and a slot past the end of RAM halts with the packet left waiting.
+pub fn op_fwd(tpu: &mut TPU, table: &OperandValueType) -> ExecuteResult {
+    let table = tpu.get_operand_value(table) as usize;
+
+    let Some(packet) = tpu.tpu_state.incoming_packets.front().copied() else {
+        return ExecuteResult::PCAdvance;
+    };
+    let final_target = packet.data();
+    if final_target == tpu.tpu_state.network_address {
+        return ExecuteResult::PCAdvance;
+    }
+    let Some(&next_hop) = tpu.tpu_state.ram.get(table + final_target as usize) else {
+        return ExecuteResult::Halt(HaltReason::IndexOutOfRange);
+    };
+    tpu.receive_packet();
+
+    if packet.ttl == 0 {
+        tpu.tpu_state.ttl_drops += 1;
+        return ExecuteResult::PCAdvance;
+    }
+
+    let forwarded = NetPacket {
+        target: next_hop,
+        ttl: packet.ttl - 1,
+        ..packet
+    };
+    let result = transmit_packet(tpu, forwarded);
+    if result == ExecuteResult::NoPCAdvance {
+        // Put it back so it isn't lost while FWD waits
+        tpu.tpu_state.incoming_packets.push_front(packet);
+    }
+    result
+}
+
+/// UART Transmit, queues the low byte of the operand, the carry flag is set if the FIFO was full and it was dropped
+pub fn op_utx(tpu: &mut TPU, value: &OperandValueType) -> ExecuteResult {
+    let byte = tpu.get_operand_value(value) as u8;
+
+    let full = tpu.tpu_state.uart_tx.len() >= TPU::UART_FIFO_SIZE;
+    if !full {
+        tpu.tpu_state.uart_tx.push_back(byte);
+    }
+    tpu.tpu_state.flags.carry = full;
+
+    ExecuteResult::PCAdvance
+}
+
+/// UART Receive, takes the oldest received byte into the register, 0xFFFF if there is none
+pub fn op_urx(tpu: &mut TPU, target: &Register) -> ExecuteResult {
+    let word = tpu.tpu_state.uart_rx.pop_front().unwrap_or(u16::MAX);
+    tpu.write_register(*target, word);
+    ExecuteResult::PCAdvance
+}
+
+/// Send the words from this TPU, see `transmit_packet`
+fn transmit(tpu: &mut TPU, target: u16, port: u8, words: &[u16]) -> ExecuteResult {
+    let packet =
+        NetPacket::with_payload(tpu.tpu_state.network_address, target, words).on_port(port);
+    transmit_packet(tpu, packet)
+}
+
+/// Queue a packet if there's room in the buffer and the link isn't cooling down, otherwise block or drop it as
+/// configured
+fn transmit_packet(tpu: &mut TPU, packet: NetPacket) -> ExecuteResult {
+    let target = packet.target;
+    // A packet to ourselves skips the network, so it's our incoming buffer that has to have room
+    let loopback = target == tpu.tpu_state.network_address;
+    let queued = if loopback {
+        tpu.tpu_state.incoming_packets.len()
+    } else {
+        tpu.tpu_state.outgoing_packets.len()
+    };
+
+    if !loopback && tpu.tpu_state.tx_cooldown > 0 {
+        if tpu.tpu_state.config.xmit_blocks {
+            // Try again once the link is free
+            tpu.tpu_state.execution_state.wait_cycles = tpu.tpu_state.tx_cooldown;
+            return ExecuteResult::NoPCAdvance;
+        }
+        // Busy, TXOK reports it like a full buffer
+        tpu.tpu_state.tx_overflow = true;
+    } else if queued < TPU::NET_BUFFER_SIZE {
+        tpu.send_packet(packet);
+        if !loopback {
+            tpu.tpu_state.tx_cooldown = tpu.tpu_state.config.min_cycles_between_tx;
+        }
+    } else if tpu.tpu_state.config.xmit_blocks {
+        // Try again next cycle, like WRX
+        tpu.tpu_state.execution_state.wait_cycles = 1;
+        return ExecuteResult::NoPCAdvance;
+    } else {
+        // Dropped, TXOK reports it
+        tpu.tpu_state.tx_overflow = true;
     }
-    // else
-    // {
-    // Set overflow CPU flag?
-    // }
 
     ExecuteResult::PCAdvance
 }
 
+/// Transmit OK, 1 if no packet has been dropped since the last TXOK, otherwise 0
+pub fn op_txok(tpu: &mut TPU, target: &Register) -> ExecuteResult {
+    let overflowed = core::mem::take(&mut tpu.tpu_state.tx_overflow);
+    tpu.write_register(*target, !overflowed as u16);
+    ExecuteResult::PCAdvance
+}
+
 pub fn op_recv(tpu: &mut TPU) -> ExecuteResult {
-    let packet = tpu.receive_packet();
+    // Nothing can send from the broadcast address, so it marks an empty queue
+    let packet = tpu.receive_packet().unwrap_or(NetPacket::new(
+        TPU::BROADCAST_ADDRESS,
+        tpu.tpu_state.network_address,
+        0,
+    ));
 
     // Store the sender in X and the data in Y
     tpu.write_register(Register::X, packet.sender);
-    tpu.write_register(Register::Y, packet.data);
+    tpu.write_register(Register::Y, packet.data());
+
+    ExecuteResult::PCAdvance
+}
+
+/// Receive from Port, like RECV but takes the oldest packet on the port, packets on other ports keep waiting
+pub fn op_recvp(tpu: &mut TPU, port: &OperandValueType) -> ExecuteResult {
+    let Ok(port) = u8::try_from(tpu.get_operand_value(port)) else {
+        return ExecuteResult::Halt(HaltReason::InvalidValue);
+    };
+    let packet = tpu.receive_packet_on_port(port).unwrap_or(NetPacket::new(
+        TPU::BROADCAST_ADDRESS,
+        tpu.tpu_state.network_address,
+        0,
+    ));
+
+    tpu.write_register(Register::X, packet.sender);
+    tpu.write_register(Register::Y, packet.data());
+
+    ExecuteResult::PCAdvance
+}
+
+/// Receive and Verify, like RECV but puts 1 into Register if the packet passes its checksum, otherwise 0
+pub fn op_recvv(tpu: &mut TPU, status: &Register) -> ExecuteResult {
+    // An empty queue has nothing damaged in it
+    let verified = tpu
+        .tpu_state
+        .incoming_packets
+        .front()
+        .is_none_or(NetPacket::verify);
+
+    op_recv(tpu);
+    tpu.write_register(*status, verified as u16);
+
+    ExecuteResult::PCAdvance
+}
+
+/// Receive Multiple, stores the payload from address, the sender in X and the length in Y
+pub fn op_recvm(tpu: &mut TPU, address: &OperandValueType) -> ExecuteResult {
+    let address = tpu.get_operand_value(address) as usize;
+
+    let Some(packet) = tpu.tpu_state.incoming_packets.front().copied() else {
+        // Same as RECV on an empty queue
+        tpu.write_register(Register::X, TPU::BROADCAST_ADDRESS);
+        tpu.write_register(Register::Y, 0);
+        return ExecuteResult::PCAdvance;
+    };
+
+    // The packet stays queued unless the whole payload can be stored
+    let length = packet.length as usize;
+    if (address..address + length).any(|address| tpu.ram_write_protected(address)) {
+        return ExecuteResult::Halt(HaltReason::WriteProtected);
+    }
+
+    tpu.receive_packet();
+    for (offset, word) in packet.words().iter().enumerate() {
+        tpu.write_ram(address + offset, *word);
+    }
+    tpu.write_register(Register::X, packet.sender);
+    tpu.write_register(Register::Y, packet.length);
 
     ExecuteResult::PCAdvance
 }
@@ -146,6 +452,13 @@ pub fn op_txbs(tpu: &mut TPU) -> ExecuteResult {
     ExecuteResult::PCAdvance
 }
 
+/// Receive Available, 1 if a packet is waiting, otherwise 0
+pub fn op_rxav(tpu: &mut TPU, target: &Register) -> ExecuteResult {
+    let available = !tpu.tpu_state.incoming_packets.is_empty();
+    tpu.write_register(*target, available as u16);
+    ExecuteResult::PCAdvance
+}
+
 /// Get the number of packets waiting to be received
 pub fn op_rxbs(tpu: &mut TPU) -> ExecuteResult {
     let rx_buffer_size = tpu.tpu_state.incoming_packets.len() as u16;
@@ -155,11 +468,38 @@ pub fn op_rxbs(tpu: &mut TPU) -> ExecuteResult {
     ExecuteResult::PCAdvance
 }
 
+/// Network Read, puts the TPU's network address into the register
+pub fn op_netr(tpu: &mut TPU, target: &Register) -> ExecuteResult {
+    tpu.write_register(*target, tpu.tpu_state.network_address);
+    ExecuteResult::PCAdvance
+}
+
+/// Network Assign, sets the TPU's network address.
+///
+/// A network checks the new address after the tick and puts the old one back if another TPU already has it, see
+/// `Network::address_collisions`. Taking `TPU::BROADCAST_ADDRESS` halts while broadcast is enabled.
+pub fn op_neta(tpu: &mut TPU, address: &OperandValueType) -> ExecuteResult {
+    let address = tpu.get_operand_value(address);
+    // A TPU at the broadcast address would look like every packet was meant for it
+    if tpu.tpu_state.config.broadcast && address == TPU::BROADCAST_ADDRESS {
+        return ExecuteResult::Halt(HaltReason::InvalidValue);
+    }
+    tpu.tpu_state.network_address = address;
+    ExecuteResult::PCAdvance
+}
+
 /// Digital Pin Write Word operation
 pub fn op_dpww(tpu: &mut TPU, value: &OperandValueType) -> ExecuteResult {
     // Get the bitmask value
     let bitmask = tpu.get_operand_value(value);
 
+    // Bits for pins this TPU doesn't have are flagged, or halt under the strict config
+    let stray_bits = bitmask & !TPU::digital_pin_mask() != 0;
+    if stray_bits && tpu.tpu_state.config.strict_pin_words {
+        return ExecuteResult::Halt(HaltReason::InvalidValue);
+    }
+    tpu.tpu_state.flags.carry = stray_bits;
+
     // Set the digital pins based on the bitmask
     tpu.set_digital_pins(bitmask);
 
@@ -177,3 +517,94 @@ pub fn op_dprw(tpu: &mut TPU, target: &Register) -> ExecuteResult {
     // Return ExecuteResult::Continue to indicate no error
     ExecuteResult::PCAdvance
 }
+
+/// Analog Pin Read Word operation, a 4 bit snapshot of every pin
+pub fn op_aprw(tpu: &mut TPU, target: &Register) -> ExecuteResult {
+    // Keep the most significant 4 bits at the configured resolution
+    let shift = tpu.tpu_state.config.analog_resolution_bits.clamp(4, 16) - 4;
+
+    let mut word = 0;
+    for pin in AnalogPin::iter() {
+        let nibble = (tpu.get_analog_pin(pin) >> shift) & 0xF;
+        word |= nibble << (pin as u16 * 4);
+    }
+
+    tpu.write_register(*target, word);
+    ExecuteResult::PCAdvance
+}
+
+/// Analog Pin Write All operation, pin N is written from RAM address base + N
+pub fn op_apwa(tpu: &mut TPU, base: &OperandValueType) -> ExecuteResult {
+    let base = tpu.get_operand_value(base) as usize;
+
+    // Inputs are left alone by set_analog_pin
+    for pin in AnalogPin::iter() {
+        let value = tpu.read_ram(base + pin as usize);
+        tpu.set_analog_pin(pin, value);
+    }
+
+    ExecuteResult::PCAdvance
+}
+
+/// Analog Pin Read All operation, pin N is stored at RAM address base + N
+pub fn op_apra(tpu: &mut TPU, base: &OperandValueType) -> ExecuteResult {
+    let base = tpu.get_operand_value(base) as usize;
+
+    // Nothing is stored unless the whole block is writable
+    if (base..base + AnalogPin::COUNT).any(|address| tpu.ram_write_protected(address)) {
+        return ExecuteResult::Halt(HaltReason::WriteProtected);
+    }
+
+    for pin in AnalogPin::iter() {
+        let value = tpu.get_analog_pin(pin);
+        tpu.write_ram(base + pin as usize, value);
+    }
+
+    ExecuteResult::PCAdvance
+}
+
+/// Pin Event Count operation
+pub fn op_pevc(tpu: &mut TPU, target: &Register) -> ExecuteResult {
+    let count = tpu.tpu_state.pin_events.len() as u16;
+    let overflowed = (tpu.tpu_state.pin_events_overflowed as u16) << 15;
+    tpu.write_register(*target, count | overflowed);
+    ExecuteResult::PCAdvance
+}
+
+/// Pin Event Read operation, pops the oldest event into X, Y and A
+pub fn op_pevr(tpu: &mut TPU) -> ExecuteResult {
+    let Some(event) = tpu.tpu_state.pin_events.pop_front() else {
+        // No pin has this number, so the program can tell the queue was empty
+        tpu.write_register(Register::X, u16::MAX);
+        return ExecuteResult::PCAdvance;
+    };
+
+    if tpu.tpu_state.pin_events.is_empty() {
+        tpu.tpu_state.pin_events_overflowed = false;
+    }
+
+    tpu.write_register(Register::X, event.pin as u16);
+    tpu.write_register(Register::Y, event.level as u16);
+    tpu.write_register(Register::A, event.cycle as u16);
+    ExecuteResult::PCAdvance
+}
+
+/// Pulse Counter Read operation
+pub fn op_pcntr(tpu: &mut TPU, target: &Register, pin: &OperandValueType) -> ExecuteResult {
+    let Some(pin) = DigitalPin::from_repr(tpu.get_operand_value(pin)) else {
+        return ExecuteResult::Halt(HaltReason::IndexOutOfRange);
+    };
+
+    tpu.write_register(*target, tpu.pulse_count(pin));
+    ExecuteResult::PCAdvance
+}
+
+/// Pulse Counter Clear operation
+pub fn op_pcntc(tpu: &mut TPU, pin: &OperandValueType) -> ExecuteResult {
+    let Some(pin) = DigitalPin::from_repr(tpu.get_operand_value(pin)) else {
+        return ExecuteResult::Halt(HaltReason::IndexOutOfRange);
+    };
+
+    tpu.tpu_state.pulse_counts[pin as usize] = 0;
+    ExecuteResult::PCAdvance
+}
@@ -42,6 +42,36 @@ pub fn decode_op_rsp() -> DecodeResult {
     }
 }
 
+pub fn decode_op_enter(locals: &OperandValueType) -> DecodeResult {
+    // Three cycles needed minimum
+    // * One to push the old frame pointer
+    // * One to copy the stack pointer into the frame pointer
+    // * One to reserve the locals
+    let cycles = TPU::check_operand_cost(&[locals]) + 3;
+
+    DecodeResult {
+        cycles,
+        call_every_cycle: false,
+    }
+}
+
+pub fn decode_op_leave() -> DecodeResult {
+    DecodeResult {
+        cycles: 2,
+        call_every_cycle: false,
+    }
+}
+
+pub fn decode_op_peekf(index: &OperandValueType) -> DecodeResult {
+    // One more than PEEK, to add the frame pointer
+    let cycles = TPU::check_operand_cost(&[index]) + 2;
+
+    DecodeResult {
+        cycles,
+        call_every_cycle: false,
+    }
+}
+
 pub fn decode_op_rcy() -> DecodeResult {
     DecodeResult {
         cycles: 2,
@@ -56,6 +86,14 @@ pub fn decode_op_rmv() -> DecodeResult {
     }
 }
 
+pub fn decode_op_cmov() -> DecodeResult {
+    // Fixed, so the cost is the same whether or not the value is moved
+    DecodeResult {
+        cycles: 2,
+        call_every_cycle: false,
+    }
+}
+
 pub fn decode_op_str(_: &Register, source: &OperandValueType) -> DecodeResult {
     // Calculate the number of clock cycles
     let cycles = TPU::check_operand_cost(&[source]) + 1;
@@ -116,6 +154,62 @@ pub fn decode_op_stm(source: &OperandValueType) -> DecodeResult {
     }
 }
 
+pub fn decode_op_sdb(value: &OperandValueType) -> DecodeResult {
+    let cycles = TPU::check_operand_cost(&[value]) + 1;
+    DecodeResult {
+        cycles,
+        call_every_cycle: false,
+    }
+}
+
+pub fn decode_op_ldrp(offset: &OperandValueType) -> DecodeResult {
+    // Same as LDO, one cycle to add the Data Base
+    let cycles = TPU::check_operand_cost(&[offset]) + 2;
+    DecodeResult {
+        cycles,
+        call_every_cycle: false,
+    }
+}
+
+pub fn decode_op_strp(source: &OperandValueType) -> DecodeResult {
+    // Same as STM, plus a cycle to add the Data Base
+    let cycles = TPU::check_operand_cost(&[source]) + 2;
+    DecodeResult {
+        cycles,
+        call_every_cycle: false,
+    }
+}
+
+pub fn decode_op_ldb(source: &OperandValueType) -> DecodeResult {
+    // Two cycles needed minimum
+    // * One to read the word
+    // * One to extract the byte into the target register
+    let cycles = TPU::check_operand_cost(&[source]) + 2;
+    DecodeResult {
+        cycles,
+        call_every_cycle: false,
+    }
+}
+
+pub fn decode_op_stb(target: &OperandValueType, source: &OperandValueType) -> DecodeResult {
+    // Two cycles needed minimum, to read-modify-write the word holding the byte
+    let cycles = TPU::check_operand_cost(&[target, source]) + 2;
+    DecodeResult {
+        cycles,
+        call_every_cycle: false,
+    }
+}
+
+/// CRC is decoded with the longest possible wait, `op_crc` finishes early once its real cost has elapsed
+pub const CRC_DECODE_CYCLES: u16 = u16::MAX;
+
+pub fn decode_op_crc() -> DecodeResult {
+    DecodeResult {
+        cycles: CRC_DECODE_CYCLES, // The cost depends on the length, see op_crc
+        call_every_cycle: true,
+    }
+}
+
 pub fn decode_op_stmo(source: &OperandValueType) -> DecodeResult {
     // Calculate the number of clock cycles
     let cycles = TPU::check_operand_cost(&[source]) + 4;
@@ -133,3 +227,41 @@ pub fn decode_op_smoi(source: &OperandValueType) -> DecodeResult {
         call_every_cycle: false,
     }
 }
+
+pub fn decode_op_ldos(source: &OperandValueType, stride: &OperandValueType) -> DecodeResult {
+    // Same as LDOI, the stride is added instead of incrementing by one
+    // 1 cycle penalty for each operand that is a memory address
+    let cycles = TPU::check_operand_cost(&[source, stride]) + 3;
+
+    DecodeResult {
+        cycles,
+        call_every_cycle: false,
+    }
+}
+
+pub fn decode_op_smos(source: &OperandValueType, stride: &OperandValueType) -> DecodeResult {
+    // Same as SMOI, plus any penalty for the stride operand
+    let cycles = TPU::check_operand_cost(&[source, stride]) + 5;
+    DecodeResult {
+        cycles,
+        call_every_cycle: false,
+    }
+}
+
+pub fn decode_op_nvl(source: &OperandValueType) -> DecodeResult {
+    // Non-volatile memory is slower to read than RAM
+    let cycles = TPU::check_operand_cost(&[source]) + 6;
+    DecodeResult {
+        cycles,
+        call_every_cycle: false,
+    }
+}
+
+pub fn decode_op_nvs(target: &OperandValueType, source: &OperandValueType) -> DecodeResult {
+    // Non-volatile memory is much slower to write than RAM
+    let cycles = TPU::check_operand_cost(&[target, source]) + 10;
+    DecodeResult {
+        cycles,
+        call_every_cycle: false,
+    }
+}
@@ -1,32 +1,62 @@
 use crate::shared::{ExecuteResult, OperandValueType, Register};
 use crate::tpu::mmu::*;
-use crate::tpu::{ExecutionState, TPU, TpuState, create_basic_tpu_config};
+use crate::tpu::{
+    ExecutionState, Flags, PwmChannel, TPU, TpuConfig, TpuState, create_basic_tpu_config,
+};
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::shared::{AnalogPin, DigitalPin, HaltReason};
+    use crate::shared::{AnalogPin, DigitalPin, HaltReason, Instruction};
+    use crate::tpu::io_matrix::{op_apr, op_dpww};
+    use std::rc::Rc;
     use strum::EnumCount;
 
     // Helper function to create a TPU with specific register values
     fn create_tpu_with_registers(a: u16, x: u16, y: u16) -> TPU {
         let mut tpu_state = TpuState {
             stack: Vec::new(),
+            stack_high_water: 0,
+            return_addresses: Vec::new(),
             analog_pins: [0; AnalogPin::COUNT],
             digital_pins: [false; DigitalPin::COUNT],
             analog_pin_config: [false; AnalogPin::COUNT],
             digital_pin_config: [true; DigitalPin::COUNT],
 
             ram: [0; TPU::RAM_SIZE],
+            nvram: Vec::new(),
             rom: vec![],
             network_address: 0x1,
             incoming_packets: std::collections::VecDeque::new(),
             outgoing_packets: std::collections::VecDeque::new(),
             registers: [0; Register::COUNT],
+            flags: Flags::default(),
+            data_base: 0,
+            hardware_loop: None,
+            saved_context: None,
+            pwm: [PwmChannel::default(); DigitalPin::COUNT],
+            cycle_count: 0,
+            instructions_retired: 0,
+            pin_events: std::collections::VecDeque::new(),
+            pin_events_overflowed: false,
+            pulse_counts: [0; DigitalPin::COUNT],
+            bounce: [None; DigitalPin::COUNT],
+            bounce_rng: 1,
+            analog_inputs: [0; AnalogPin::COUNT],
+            noise_offsets: [0; AnalogPin::COUNT],
+            noise_rng: 1,
+            comparator_thresholds: [0; AnalogPin::COUNT],
+            tx_overflow: false,
+            tx_cooldown: 0,
+            ttl_drops: 0,
+            uart_tx: std::collections::VecDeque::new(),
+            uart_rx: std::collections::VecDeque::new(),
 
             program_counter: 0,
             halted: false,
+            halt_reason: None,
             execution_state: ExecutionState::default(),
+            config: TpuConfig::default(),
         };
 
         // Set register values
@@ -84,7 +114,70 @@ mod tests {
         assert_eq!(tpu.read_register(Register::R1), 42); // R1 now has R0's value
         assert_eq!(tpu.read_register(Register::R0), 0); // R0 is now zero
     }
-    
+
+    #[test]
+    fn test_op_cmovz_and_cmovn() {
+        // Test case 1: CMOVZ moves when the condition is zero
+        let mut tpu = create_tpu_with_registers(10, 0, 30);
+        let result = op_cmovz(
+            &mut tpu,
+            &Register::A,
+            &OperandValueType::Register(Register::Y),
+            &Register::X,
+        );
+        assert_eq!(result, ExecuteResult::PCAdvance);
+        assert_eq!(tpu.read_register(Register::A), 30);
+
+        // Test case 2: And leaves the target alone when it's not
+        let mut tpu = create_tpu_with_registers(10, 1, 30);
+        let result = op_cmovz(
+            &mut tpu,
+            &Register::A,
+            &OperandValueType::Immediate(99),
+            &Register::X,
+        );
+        assert_eq!(result, ExecuteResult::PCAdvance);
+        assert_eq!(tpu.read_register(Register::A), 10);
+
+        // Test case 3: CMOVN is the opposite
+        let mut tpu = create_tpu_with_registers(10, 1, 30);
+        let result = op_cmovn(
+            &mut tpu,
+            &Register::A,
+            &OperandValueType::Immediate(99),
+            &Register::X,
+        );
+        assert_eq!(result, ExecuteResult::PCAdvance);
+        assert_eq!(tpu.read_register(Register::A), 99);
+
+        let mut tpu = create_tpu_with_registers(10, 0, 30);
+        let result = op_cmovn(
+            &mut tpu,
+            &Register::A,
+            &OperandValueType::Immediate(99),
+            &Register::X,
+        );
+        assert_eq!(result, ExecuteResult::PCAdvance);
+        assert_eq!(tpu.read_register(Register::A), 10);
+
+        // Test case 4: The condition can be the target, e.g. replacing zero with a default
+        let mut tpu = create_tpu_with_registers(0, 0, 0);
+        op_cmovz(
+            &mut tpu,
+            &Register::A,
+            &OperandValueType::Immediate(7),
+            &Register::A,
+        );
+        assert_eq!(tpu.read_register(Register::A), 7);
+        op_cmovz(
+            &mut tpu,
+            &Register::A,
+            &OperandValueType::Immediate(8),
+            &Register::A,
+        );
+        assert_eq!(tpu.read_register(Register::A), 7);
+    }
+
     #[test]
     fn test_op_ldr() {
         // Test case 1: Load constant into register
@@ -130,7 +223,7 @@ mod tests {
         assert_eq!(tpu.read_register(Register::Y), 99); // Y now has the value from memory
         assert_eq!(tpu.read_register(Register::X), 9); // X remains unchanged
     }
-    
+
     #[test]
     fn test_op_stm() {
         // Test case 1: Store constant into memory
@@ -153,7 +246,7 @@ mod tests {
         assert_eq!(result, ExecuteResult::PCAdvance); // No error
         assert_eq!(tpu.read_ram(9), 10); // Memory at address 9 now has A's value
     }
-    
+
     #[test]
     fn test_op_stmo() {
         // Test case 1: Store register into memory with offset
@@ -343,6 +436,338 @@ mod tests {
         assert_eq!(tpu.read_register(Register::A), 99); // A now has the value from memory
         assert_eq!(tpu.read_register(Register::X), 6); // X is incremented
     }
+
+    #[test]
+    fn test_op_ldos_and_smos() {
+        let ram_values = [(20, 99), (24, 100)];
+        let mut tpu = create_tpu_with_ram(&ram_values);
+        tpu.write_register(Register::X, 0); // Offset
+        tpu.write_register(Register::Y, 4); // Stride
+
+        // Test LDOS - Load with offset and stride
+        let result = op_ldos(
+            &mut tpu,
+            &Register::A,
+            &OperandValueType::Immediate(20),
+            &Register::X,
+            &OperandValueType::Register(Register::Y),
+        );
+        assert_eq!(result, ExecuteResult::PCAdvance);
+        assert_eq!(tpu.read_register(Register::A), 99);
+        assert_eq!(tpu.read_register(Register::X), 4);
+
+        let result = op_ldos(
+            &mut tpu,
+            &Register::A,
+            &OperandValueType::Immediate(20),
+            &Register::X,
+            &OperandValueType::Register(Register::Y),
+        );
+        assert_eq!(result, ExecuteResult::PCAdvance);
+        assert_eq!(tpu.read_register(Register::A), 100);
+        assert_eq!(tpu.read_register(Register::X), 8);
+
+        // Test SMOS - Store with offset and stride, a stride of 0xFFFF wraps the offset backwards
+        let result = op_smos(
+            &mut tpu,
+            &OperandValueType::Immediate(22),
+            &OperandValueType::Immediate(7),
+            &Register::X,
+            &OperandValueType::Immediate(0xFFFF),
+        );
+        assert_eq!(result, ExecuteResult::PCAdvance);
+        assert_eq!(tpu.read_ram(30), 7);
+        assert_eq!(tpu.read_register(Register::X), 7);
+    }
+
+    #[test]
+    fn test_op_ldrp_and_strp() {
+        let ram_values = [(2, 0x1111), (0x42, 0x2222)];
+        let mut tpu = create_tpu_with_ram(&ram_values);
+
+        // The Data Base starts at zero
+        op_ldrp(&mut tpu, &Register::A, &OperandValueType::Immediate(2));
+        assert_eq!(tpu.read_register(Register::A), 0x1111);
+
+        let result = op_sdb(&mut tpu, &OperandValueType::Immediate(0x40));
+        assert_eq!(result, ExecuteResult::PCAdvance);
+        op_ldrp(&mut tpu, &Register::A, &OperandValueType::Immediate(2));
+        assert_eq!(tpu.read_register(Register::A), 0x2222);
+
+        let result = op_strp(
+            &mut tpu,
+            &OperandValueType::Immediate(3),
+            &OperandValueType::Register(Register::A),
+        );
+        assert_eq!(result, ExecuteResult::PCAdvance);
+        assert_eq!(tpu.read_ram(0x43), 0x2222);
+        assert_eq!(tpu.read_ram(3), 0);
+
+        // Out of range addresses read as zero and writes are dropped
+        op_sdb(&mut tpu, &OperandValueType::Immediate(0xFFFF));
+        op_strp(
+            &mut tpu,
+            &OperandValueType::Immediate(0xFFFF),
+            &OperandValueType::Immediate(1),
+        );
+        op_ldrp(&mut tpu, &Register::A, &OperandValueType::Immediate(0xFFFF));
+        assert_eq!(tpu.read_register(Register::A), 0);
+
+        // Reset clears the Data Base
+        tpu.reset();
+        assert_eq!(tpu.tpu_state.data_base, 0);
+    }
+
+    #[test]
+    fn test_op_ldb_and_stb() {
+        let ram_values = [(10, 0x1234)];
+        let mut tpu = create_tpu_with_ram(&ram_values);
+
+        // Both byte lanes of word 10
+        op_ldb(&mut tpu, &Register::A, &OperandValueType::Immediate(20));
+        assert_eq!(tpu.read_register(Register::A), 0x34);
+        op_ldb(&mut tpu, &Register::A, &OperandValueType::Immediate(21));
+        assert_eq!(tpu.read_register(Register::A), 0x12);
+
+        // Storing a byte leaves the neighbouring byte intact, only the low byte of the source is used
+        let result = op_stb(
+            &mut tpu,
+            &OperandValueType::Immediate(20),
+            &OperandValueType::Immediate(0xAB),
+        );
+        assert_eq!(result, ExecuteResult::PCAdvance);
+        assert_eq!(tpu.read_ram(10), 0x12AB);
+
+        op_stb(
+            &mut tpu,
+            &OperandValueType::Immediate(21),
+            &OperandValueType::Immediate(0xFFCD),
+        );
+        assert_eq!(tpu.read_ram(10), 0xCDAB);
+
+        // A word store replaces both bytes
+        op_stm(
+            &mut tpu,
+            &OperandValueType::Immediate(10),
+            &OperandValueType::Immediate(0x5678),
+        );
+        op_ldb(&mut tpu, &Register::A, &OperandValueType::Immediate(20));
+        assert_eq!(tpu.read_register(Register::A), 0x78);
+        op_ldb(&mut tpu, &Register::A, &OperandValueType::Immediate(21));
+        assert_eq!(tpu.read_register(Register::A), 0x56);
+
+        // Out of range byte addresses read as zero and writes are dropped
+        let past_end = (TPU::RAM_SIZE * 2) as u16;
+        op_stb(
+            &mut tpu,
+            &OperandValueType::Immediate(past_end),
+            &OperandValueType::Immediate(0x11),
+        );
+        tpu.write_register(Register::A, 0xFFFF);
+        op_ldb(
+            &mut tpu,
+            &Register::A,
+            &OperandValueType::Immediate(past_end),
+        );
+        assert_eq!(tpu.read_register(Register::A), 0);
+    }
+
+    #[test]
+    fn test_read_only_ram() {
+        let mut tpu = create_tpu_with_registers(0, 2, 0);
+        tpu.tpu_state.config.read_only_ram = Some(0x10..0x20);
+
+        // The host can still load the protected table
+        tpu.write_ram_slice(0x10, &[0x1234, 0x5678]);
+        assert_eq!(tpu.read_ram(0x10), 0x1234);
+        assert_eq!(tpu.read_ram(0x11), 0x5678);
+
+        // Stores into the range halt and leave memory alone
+        let result = op_stm(
+            &mut tpu,
+            &OperandValueType::Immediate(0x10),
+            &OperandValueType::Immediate(1),
+        );
+        assert_eq!(result, ExecuteResult::Halt(HaltReason::WriteProtected));
+        assert_eq!(tpu.read_ram(0x10), 0x1234);
+
+        let result = op_smoi(
+            &mut tpu,
+            &OperandValueType::Immediate(0x0E),
+            &OperandValueType::Immediate(1),
+            &Register::X,
+        );
+        assert_eq!(result, ExecuteResult::Halt(HaltReason::WriteProtected));
+        assert_eq!(tpu.read_register(Register::X), 2); // Not incremented
+
+        let result = op_stb(
+            &mut tpu,
+            &OperandValueType::Immediate(0x23),
+            &OperandValueType::Immediate(1),
+        );
+        assert_eq!(result, ExecuteResult::Halt(HaltReason::WriteProtected));
+        assert_eq!(tpu.read_ram(0x11), 0x5678);
+
+        // The addresses either side of the range are writable
+        let result = op_stm(
+            &mut tpu,
+            &OperandValueType::Immediate(0x0F),
+            &OperandValueType::Immediate(1),
+        );
+        assert_eq!(result, ExecuteResult::PCAdvance);
+        assert_eq!(tpu.read_ram(0x0F), 1);
+
+        let result = op_stmo(
+            &mut tpu,
+            &OperandValueType::Immediate(0x1E),
+            &OperandValueType::Immediate(2),
+            &Register::X,
+        );
+        assert_eq!(result, ExecuteResult::PCAdvance);
+        assert_eq!(tpu.read_ram(0x20), 2);
+
+        // Without a protected range the same store succeeds
+        let mut tpu = create_tpu_with_registers(0, 0, 0);
+        let result = op_stm(
+            &mut tpu,
+            &OperandValueType::Immediate(0x10),
+            &OperandValueType::Immediate(1),
+        );
+        assert_eq!(result, ExecuteResult::PCAdvance);
+        assert_eq!(tpu.read_ram(0x10), 1);
+    }
+
+    // Host side CRC-16/CCITT-FALSE over bytes, to check op_crc against
+    fn reference_crc(bytes: &[u8]) -> u16 {
+        let mut crc: u16 = 0xFFFF;
+        for &byte in bytes {
+            for bit in (0..8).rev() {
+                let input = (byte >> bit) & 1 == 1;
+                let top = crc & 0x8000 != 0;
+                crc <<= 1;
+                if input != top {
+                    crc ^= 0x1021;
+                }
+            }
+        }
+        crc
+    }
+
+    #[test]
+    fn test_op_crc() {
+        // Standard check value
+        assert_eq!(reference_crc(b"123456789"), 0x29B1);
+
+        let buffers: [&[u16]; 4] = [&[], &[0x3132], &[0x1234, 0x5678, 0x9ABC], &[0xFFFF; 5]];
+        for buffer in buffers {
+            let mut tpu = create_tpu_with_registers(0, 0, 0);
+            tpu.write_ram_slice(0x10, buffer);
+
+            let result = op_crc(
+                &mut tpu,
+                &OperandValueType::Immediate(0x10),
+                &OperandValueType::Immediate(buffer.len() as u16),
+                0,
+            );
+            assert_eq!(result, ExecuteResult::PCAdvance);
+
+            let bytes: Vec<u8> = buffer.iter().flat_map(|word| word.to_be_bytes()).collect();
+            assert_eq!(tpu.read_register(Register::A), reference_crc(&bytes));
+        }
+
+        // A buffer ending at the last word of RAM
+        let mut tpu = create_tpu_with_registers(0, 0, 4);
+        tpu.write_ram_slice(TPU::RAM_SIZE - 4, &[1, 2, 3, 4]);
+        let result = op_crc(
+            &mut tpu,
+            &OperandValueType::Immediate((TPU::RAM_SIZE - 4) as u16),
+            &OperandValueType::Register(Register::Y),
+            0,
+        );
+        assert_eq!(result, ExecuteResult::PCAdvance);
+        assert_eq!(
+            tpu.read_register(Register::A),
+            reference_crc(&[0, 1, 0, 2, 0, 3, 0, 4])
+        );
+
+        // One word further runs off the end
+        let result = op_crc(
+            &mut tpu,
+            &OperandValueType::Immediate((TPU::RAM_SIZE - 3) as u16),
+            &OperandValueType::Register(Register::Y),
+            0,
+        );
+        assert_eq!(result, ExecuteResult::Halt(HaltReason::IndexOutOfRange));
+    }
+
+    #[test]
+    fn test_op_crc_cycles() {
+        for length in [0, 1, 10] {
+            let mut tpu = create_basic_tpu_config(vec![
+                Rc::new(Instruction::CRC(
+                    OperandValueType::Immediate(0),
+                    OperandValueType::Immediate(length),
+                )),
+                Rc::new(Instruction::HLT),
+            ]);
+
+            let mut cycles = 0;
+            while tpu.tpu_state.program_counter == 0 {
+                tpu.tick();
+                cycles += 1;
+            }
+            assert_eq!(cycles, 2 + length);
+            assert_eq!(
+                tpu.read_register(Register::A),
+                reference_crc(&vec![0; length as usize * 2])
+            );
+        }
+    }
+
+    #[test]
+    fn test_op_enter_leave_peekf() {
+        let mut tpu = create_tpu_with_registers(0, 0, 0);
+        op_push(&mut tpu, &OperandValueType::Immediate(42));
+        tpu.write_register(Register::R6, 7);
+
+        // Saved frame pointer at 1, locals at 2 and 3
+        let result = op_enter(&mut tpu, &OperandValueType::Immediate(2));
+        assert_eq!(result, ExecuteResult::PCAdvance);
+        assert_eq!(tpu.read_register(Register::R6), 2);
+        assert_eq!(tpu.tpu_state.stack, vec![42, 7, 0, 0]);
+
+        op_push(&mut tpu, &OperandValueType::Immediate(99));
+        op_peekf(&mut tpu, &Register::A, &OperandValueType::Immediate(2));
+        assert_eq!(tpu.read_register(Register::A), 99);
+
+        // Past the top of the stack
+        let result = op_peekf(&mut tpu, &Register::A, &OperandValueType::Immediate(3));
+        assert_eq!(result, ExecuteResult::Halt(HaltReason::IndexOutOfRange));
+
+        let result = op_leave(&mut tpu);
+        assert_eq!(result, ExecuteResult::PCAdvance);
+        assert_eq!(tpu.read_register(Register::R6), 7);
+        assert_eq!(tpu.tpu_state.stack, vec![42]);
+
+        // No frame open
+        tpu.write_register(Register::R6, 0);
+        let result = op_leave(&mut tpu);
+        assert_eq!(result, ExecuteResult::Halt(HaltReason::StackUnderflow));
+
+        // The frame pointer is above the top of the stack
+        tpu.write_register(Register::R6, 5);
+        let result = op_leave(&mut tpu);
+        assert_eq!(result, ExecuteResult::Halt(HaltReason::StackUnderflow));
+
+        // Not enough room for the saved frame pointer and the locals
+        let result = op_enter(
+            &mut tpu,
+            &OperandValueType::Immediate(TPU::STACK_SIZE as u16 - 1),
+        );
+        assert_eq!(result, ExecuteResult::Halt(HaltReason::StackOverflow));
+        assert_eq!(tpu.tpu_state.stack, vec![42]);
+    }
+
     #[test]
     fn test_op_scr() {
         let mut tpu = create_tpu_with_registers(10, 20, 30);
@@ -373,4 +798,142 @@ mod tests {
         assert_eq!(result, ExecuteResult::PCAdvance);
         assert_eq!(tpu.read_register(Register::A), 3); // Stack pointer is 3
     }
+
+    // Helper function to create a TPU with memory mapped I/O and all pins as outputs
+    fn create_tpu_with_mmio(enabled: bool) -> TPU {
+        let mut tpu = create_tpu_with_registers(0, 0, 0);
+        tpu.tpu_state.config.memory_mapped_io = enabled;
+        tpu.tpu_state.digital_pin_config = [false; DigitalPin::COUNT];
+        tpu
+    }
+
+    #[test]
+    fn test_mmio_digital_word() {
+        // STM to the digital word address behaves exactly like DPWW
+        let mut mapped = create_tpu_with_mmio(true);
+        let mut direct = create_tpu_with_mmio(true);
+        let result = op_stm(
+            &mut mapped,
+            &OperandValueType::Immediate(TPU::MMIO_DIGITAL_WORD as u16),
+            &OperandValueType::Immediate(0xFFA5),
+        );
+        assert_eq!(result, ExecuteResult::PCAdvance);
+        op_dpww(&mut direct, &OperandValueType::Immediate(0xFFA5));
+        assert_eq!(mapped.tpu_state.digital_pins, direct.tpu_state.digital_pins);
+        assert_eq!(mapped.get_digital_pins(), 0xA5);
+
+        // The backing RAM word is untouched and reads come from the pins
+        assert_eq!(mapped.tpu_state.ram[TPU::MMIO_DIGITAL_WORD], 0);
+        op_ldm(
+            &mut mapped,
+            &Register::A,
+            &OperandValueType::Immediate(TPU::MMIO_DIGITAL_WORD as u16),
+        );
+        assert_eq!(mapped.read_register(Register::A), 0xA5);
+
+        // Input pins are not driven by the alias
+        let mut tpu = create_tpu_with_mmio(true);
+        tpu.tpu_state.digital_pin_config[DigitalPin::Digital0 as usize] = true;
+        tpu.write_ram(TPU::MMIO_DIGITAL_WORD, 0b11);
+        assert_eq!(tpu.get_digital_pins(), 0b10);
+    }
+
+    #[test]
+    fn test_mmio_analog_pins() {
+        let mut tpu = create_tpu_with_mmio(true);
+
+        // STMO through the alias behaves like APW
+        tpu.write_register(Register::X, 2);
+        let result = op_stmo(
+            &mut tpu,
+            &OperandValueType::Immediate(TPU::MMIO_ANALOG_BASE as u16),
+            &OperandValueType::Immediate(0x1234),
+            &Register::X,
+        );
+        assert_eq!(result, ExecuteResult::PCAdvance);
+        assert_eq!(tpu.get_analog_pin(AnalogPin::Analog2), 0x1234);
+
+        // LDM from the alias equals APR
+        op_ldm(
+            &mut tpu,
+            &Register::A,
+            &OperandValueType::Immediate((TPU::MMIO_ANALOG_BASE + 2) as u16),
+        );
+        op_apr(&mut tpu, &Register::Y, &OperandValueType::Immediate(2));
+        assert_eq!(tpu.read_register(Register::A), 0x1234);
+        assert_eq!(tpu.read_register(Register::Y), 0x1234);
+
+        // Input pins ignore writes through the alias
+        tpu.tpu_state.analog_pin_config[AnalogPin::Analog1 as usize] = true;
+        tpu.write_ram(TPU::MMIO_ANALOG_BASE + 1, 99);
+        assert_eq!(tpu.get_analog_pin(AnalogPin::Analog1), 0);
+
+        // Addresses past the last pin are still plain RAM
+        let address = TPU::MMIO_ANALOG_BASE + AnalogPin::COUNT;
+        tpu.write_ram(address, 77);
+        assert_eq!(tpu.read_ram(address), 77);
+    }
+
+    #[test]
+    fn test_mmio_disabled_is_plain_ram() {
+        let mut tpu = create_tpu_with_mmio(false);
+        op_stm(
+            &mut tpu,
+            &OperandValueType::Immediate(TPU::MMIO_DIGITAL_WORD as u16),
+            &OperandValueType::Immediate(0xFF),
+        );
+        op_stm(
+            &mut tpu,
+            &OperandValueType::Immediate(TPU::MMIO_ANALOG_BASE as u16),
+            &OperandValueType::Immediate(42),
+        );
+        assert_eq!(tpu.get_digital_pins(), 0);
+        assert_eq!(tpu.get_analog_pin(AnalogPin::Analog0), 0);
+        assert_eq!(tpu.read_ram(TPU::MMIO_DIGITAL_WORD), 0xFF);
+        assert_eq!(tpu.read_ram(TPU::MMIO_ANALOG_BASE), 42);
+    }
+
+    #[test]
+    fn test_op_nvl_and_nvs() {
+        let mut tpu = create_tpu_with_registers(10, 3, 0x1234);
+        tpu.tpu_state.nvram = vec![0; 4];
+
+        // Store with a register address and value
+        let result = op_nvs(
+            &mut tpu,
+            &OperandValueType::Register(Register::X),
+            &OperandValueType::Register(Register::Y),
+        );
+        assert_eq!(result, ExecuteResult::PCAdvance);
+        assert_eq!(tpu.nvram(), &[0, 0, 0, 0x1234]);
+
+        // Load it back
+        let result = op_nvl(&mut tpu, &Register::A, &OperandValueType::Immediate(3));
+        assert_eq!(result, ExecuteResult::PCAdvance);
+        assert_eq!(tpu.read_register(Register::A), 0x1234);
+
+        // Out of bounds halts
+        let result = op_nvl(&mut tpu, &Register::A, &OperandValueType::Immediate(4));
+        assert_eq!(result, ExecuteResult::Halt(HaltReason::IndexOutOfRange));
+        let result = op_nvs(
+            &mut tpu,
+            &OperandValueType::Immediate(4),
+            &OperandValueType::Immediate(1),
+        );
+        assert_eq!(result, ExecuteResult::Halt(HaltReason::IndexOutOfRange));
+    }
+
+    #[test]
+    fn test_set_nvram() {
+        let mut tpu = create_tpu_with_registers(0, 0, 0);
+        tpu.tpu_state.nvram = vec![9; 4];
+
+        // A short image only replaces the start
+        tpu.set_nvram(&[1, 2]);
+        assert_eq!(tpu.nvram(), &[1, 2, 9, 9]);
+
+        // A long image is truncated
+        tpu.set_nvram(&[5, 6, 7, 8, 10, 11]);
+        assert_eq!(tpu.nvram(), &[5, 6, 7, 8]);
+    }
 }
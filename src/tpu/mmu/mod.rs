@@ -2,8 +2,8 @@ pub mod decode;
 #[cfg(test)]
 mod mmu_test;
 
-use crate::shared::Register;
-use crate::shared::{ExecuteResult, HaltReason, OperandValueType};
+use crate::shared::{ExecuteResult, HaltReason, OperandValueType, crc16_ccitt};
+use crate::shared::{Instruction, Register};
 use crate::tpu::TPU;
 
 // Stack operations
@@ -58,6 +58,7 @@ pub fn op_peek(tpu: &mut TPU, operand_1: &Register, operand_2: &OperandValueType
 pub fn op_scr(tpu: &mut TPU) -> ExecuteResult {
     // Clear the stack
     tpu.tpu_state.stack.clear();
+    tpu.tpu_state.return_addresses.clear();
 
     // Return ExecuteResult::Continue to indicate no error
     ExecuteResult::PCAdvance
@@ -69,6 +70,56 @@ pub fn op_rsp(tpu: &mut TPU, operand: &Register) -> ExecuteResult {
     ExecuteResult::PCAdvance
 }
 
+/// Open a stack frame
+///
+/// Pushes the old frame pointer, points the frame pointer at the first local and reserves the locals, set to zero.
+pub fn op_enter(tpu: &mut TPU, locals: &OperandValueType) -> ExecuteResult {
+    let locals = tpu.get_operand_value(locals) as usize;
+
+    // The saved frame pointer plus the locals must fit
+    if tpu.tpu_state.stack.len() + 1 + locals > TPU::STACK_SIZE {
+        return ExecuteResult::Halt(HaltReason::StackOverflow);
+    }
+
+    tpu.push(tpu.read_register(TPU::FRAME_POINTER));
+    tpu.write_register(TPU::FRAME_POINTER, tpu.stack_pointer());
+    for _ in 0..locals {
+        tpu.push(0);
+    }
+    ExecuteResult::PCAdvance
+}
+
+/// Close the current stack frame
+///
+/// Drops everything above the frame pointer and restores the previous frame pointer.
+pub fn op_leave(tpu: &mut TPU) -> ExecuteResult {
+    let frame_pointer = tpu.read_register(TPU::FRAME_POINTER) as usize;
+
+    // There must be a saved frame pointer below the frame
+    if frame_pointer == 0 || frame_pointer > tpu.tpu_state.stack.len() {
+        return ExecuteResult::Halt(HaltReason::StackUnderflow);
+    }
+
+    tpu.tpu_state.stack.truncate(frame_pointer);
+    tpu.tpu_state.return_addresses.truncate(frame_pointer);
+    let saved_frame_pointer = tpu.pop();
+    tpu.write_register(TPU::FRAME_POINTER, saved_frame_pointer);
+    ExecuteResult::PCAdvance
+}
+
+/// Peek at a local of the current stack frame
+pub fn op_peekf(tpu: &mut TPU, target: &Register, index: &OperandValueType) -> ExecuteResult {
+    let frame_pointer = tpu.read_register(TPU::FRAME_POINTER) as usize;
+    let index = tpu.get_operand_value(index) as usize;
+
+    let Some(&value) = tpu.tpu_state.stack.get(frame_pointer + index) else {
+        return ExecuteResult::Halt(HaltReason::IndexOutOfRange);
+    };
+
+    tpu.write_register(*target, value);
+    ExecuteResult::PCAdvance
+}
+
 // Memory operations
 /// Copy the value from the source register to the destination register
 pub fn op_rcy(tpu: &mut TPU, operand_1: &Register, operand_2: &Register) -> ExecuteResult {
@@ -87,6 +138,34 @@ pub fn op_rmv(tpu: &mut TPU, operand_1: &Register, operand_2: &Register) -> Exec
     ExecuteResult::PCAdvance
 }
 
+/// Copy the source into the target register if the condition register is zero
+pub fn op_cmovz(
+    tpu: &mut TPU,
+    target: &Register,
+    source: &OperandValueType,
+    condition: &Register,
+) -> ExecuteResult {
+    if tpu.read_register(*condition) == 0 {
+        let value = tpu.get_operand_value(source);
+        tpu.write_register(*target, value);
+    }
+    ExecuteResult::PCAdvance
+}
+
+/// Copy the source into the target register if the condition register is not zero
+pub fn op_cmovn(
+    tpu: &mut TPU,
+    target: &Register,
+    source: &OperandValueType,
+    condition: &Register,
+) -> ExecuteResult {
+    if tpu.read_register(*condition) != 0 {
+        let value = tpu.get_operand_value(source);
+        tpu.write_register(*target, value);
+    }
+    ExecuteResult::PCAdvance
+}
+
 /// Load a value into a register
 pub fn op_ldr(tpu: &mut TPU, target: &Register, source: &OperandValueType) -> ExecuteResult {
     // Get the value
@@ -143,6 +222,71 @@ pub fn op_ldoi(
     ExecuteResult::PCAdvance
 }
 
+/// The RAM word a memory instruction will access with the current register values, if it accesses a single word
+pub fn effective_address(tpu: &TPU, instruction: &Instruction) -> Option<usize> {
+    let offset_address = |address: &OperandValueType, offset: &Register| {
+        tpu.get_operand_value(address) as usize + tpu.read_register(*offset) as usize
+    };
+
+    match instruction {
+        Instruction::LDM(_, address) | Instruction::STM(address, _) => {
+            Some(tpu.get_operand_value(address) as usize)
+        }
+        Instruction::LDO(_, address, offset)
+        | Instruction::LDOI(_, address, offset)
+        | Instruction::LDOS(_, address, offset, _)
+        | Instruction::STMO(address, _, offset)
+        | Instruction::SMOI(address, _, offset)
+        | Instruction::SMOS(address, _, offset, _) => Some(offset_address(address, offset)),
+        Instruction::LDRP(_, offset) | Instruction::STRP(offset, _) => {
+            Some(data_address(tpu, offset))
+        }
+        Instruction::LDB(_, byte_address) | Instruction::STB(byte_address, _) => {
+            Some(tpu.get_operand_value(byte_address) as usize / 2)
+        }
+        _ => None,
+    }
+}
+
+/// The RAM address of an offset from the Data Base
+fn data_address(tpu: &TPU, offset: &OperandValueType) -> usize {
+    tpu.tpu_state.data_base as usize + tpu.get_operand_value(offset) as usize
+}
+
+/// Set the Data Base
+pub fn op_sdb(tpu: &mut TPU, value: &OperandValueType) -> ExecuteResult {
+    tpu.tpu_state.data_base = tpu.get_operand_value(value);
+    ExecuteResult::PCAdvance
+}
+
+/// Load Register relative to the Data Base
+pub fn op_ldrp(tpu: &mut TPU, target: &Register, offset: &OperandValueType) -> ExecuteResult {
+    let value = tpu.read_ram(data_address(tpu, offset));
+    tpu.write_register(*target, value);
+    ExecuteResult::PCAdvance
+}
+
+/// Store To Memory relative to the Data Base
+pub fn op_strp(
+    tpu: &mut TPU,
+    offset: &OperandValueType,
+    source: &OperandValueType,
+) -> ExecuteResult {
+    let address = data_address(tpu, offset);
+    let value = tpu.get_operand_value(source);
+    store_ram(tpu, address, value)
+}
+
+/// Store a value in RAM, halting if the address is write protected
+fn store_ram(tpu: &mut TPU, address: usize, value: u16) -> ExecuteResult {
+    if tpu.ram_write_protected(address) {
+        return ExecuteResult::Halt(HaltReason::WriteProtected);
+    }
+
+    tpu.write_ram(address, value);
+    ExecuteResult::PCAdvance
+}
+
 /// Store To Memory
 pub fn op_stm(
     tpu: &mut TPU,
@@ -154,10 +298,7 @@ pub fn op_stm(
     let value = tpu.get_operand_value(source);
 
     // Store the value in memory
-    tpu.write_ram(address, value);
-
-    // Return ExecuteResult::Continue to indicate no error
-    ExecuteResult::PCAdvance
+    store_ram(tpu, address, value)
 }
 
 /// Store To Memory With Offset
@@ -173,9 +314,75 @@ pub fn op_stmo(
     let offset_amount = tpu.read_register(*offset) as usize;
 
     // Store the value in memory
-    tpu.write_ram(address + offset_amount, value);
+    store_ram(tpu, address + offset_amount, value)
+}
 
-    // Return ExecuteResult::Continue to indicate no error
+/// Load a byte into a register from Memory
+///
+/// The byte address is the word address * 2, plus 0 for the low byte or 1 for the high byte.
+pub fn op_ldb(tpu: &mut TPU, target: &Register, source: &OperandValueType) -> ExecuteResult {
+    let byte_address = tpu.get_operand_value(source) as usize;
+    let word = tpu.read_ram(byte_address / 2);
+    let value = if byte_address.is_multiple_of(2) {
+        word & 0x00FF
+    } else {
+        word >> 8
+    };
+
+    tpu.write_register(*target, value);
+    ExecuteResult::PCAdvance
+}
+
+/// Store a byte To Memory, leaving the other byte of the word intact
+pub fn op_stb(
+    tpu: &mut TPU,
+    target: &OperandValueType,
+    source: &OperandValueType,
+) -> ExecuteResult {
+    let byte_address = tpu.get_operand_value(target) as usize;
+    let value = tpu.get_operand_value(source) & 0x00FF;
+
+    // Read-modify-write the word holding the byte
+    let word = tpu.read_ram(byte_address / 2);
+    let word = if byte_address.is_multiple_of(2) {
+        (word & 0xFF00) | value
+    } else {
+        (word & 0x00FF) | (value << 8)
+    };
+
+    store_ram(tpu, byte_address / 2, word)
+}
+
+/// Compute the CRC-16/CCITT-FALSE of `count` words of RAM starting at `address` into A
+///
+/// Each word is fed high byte first. Takes 2 cycles plus 1 per word, halts if the range runs past the end of RAM.
+pub fn op_crc(
+    tpu: &mut TPU,
+    address: &OperandValueType,
+    count: &OperandValueType,
+    wait_cycles: u16,
+) -> ExecuteResult {
+    let start = tpu.get_operand_value(address) as usize;
+    let length = tpu.get_operand_value(count);
+
+    let Some(end) = start
+        .checked_add(length as usize)
+        .filter(|&end| end <= tpu.ram_size())
+    else {
+        return ExecuteResult::Halt(HaltReason::IndexOutOfRange);
+    };
+
+    // Keep going until the length dependent cost has elapsed
+    let elapsed = decode::CRC_DECODE_CYCLES - wait_cycles;
+    let cost = (TPU::check_operand_cost(&[address, count]) + 2).saturating_add(length);
+    if elapsed < cost {
+        return ExecuteResult::NoPCAdvance;
+    }
+
+    let crc = (start..end)
+        .map(|address| tpu.read_ram(address))
+        .fold(0xFFFF, crc16_ccitt);
+    tpu.write_register(Register::A, crc);
     ExecuteResult::PCAdvance
 }
 
@@ -186,7 +393,73 @@ pub fn op_smoi(
     source: &OperandValueType,
     offset: &Register,
 ) -> ExecuteResult {
-    op_stmo(tpu, target, source, offset);
+    let result = op_stmo(tpu, target, source, offset);
+    if !matches!(result, ExecuteResult::PCAdvance) {
+        return result;
+    }
     tpu.write_register(*offset, tpu.read_register(*offset).wrapping_add(1));
     ExecuteResult::PCAdvance
 }
+
+/// Load Register From Memory with Offset and Stride
+pub fn op_ldos(
+    tpu: &mut TPU,
+    target: &Register,
+    source: &OperandValueType,
+    offset: &Register,
+    stride: &OperandValueType,
+) -> ExecuteResult {
+    // Read the stride before the load, in case the target register is also the stride
+    let stride = tpu.get_operand_value(stride);
+    op_ldo(tpu, target, source, offset);
+    tpu.write_register(*offset, tpu.read_register(*offset).wrapping_add(stride));
+    ExecuteResult::PCAdvance
+}
+
+/// Store To Memory with Offset and Stride
+pub fn op_smos(
+    tpu: &mut TPU,
+    target: &OperandValueType,
+    source: &OperandValueType,
+    offset: &Register,
+    stride: &OperandValueType,
+) -> ExecuteResult {
+    let stride = tpu.get_operand_value(stride);
+    let result = op_stmo(tpu, target, source, offset);
+    if !matches!(result, ExecuteResult::PCAdvance) {
+        return result;
+    }
+    tpu.write_register(*offset, tpu.read_register(*offset).wrapping_add(stride));
+    ExecuteResult::PCAdvance
+}
+
+/// Load a value into a register from Non-Volatile Memory
+pub fn op_nvl(tpu: &mut TPU, target: &Register, source: &OperandValueType) -> ExecuteResult {
+    let address = tpu.get_operand_value(source) as usize;
+
+    // Out of bounds
+    let Some(&value) = tpu.tpu_state.nvram.get(address) else {
+        return ExecuteResult::Halt(HaltReason::IndexOutOfRange);
+    };
+
+    tpu.write_register(*target, value);
+    ExecuteResult::PCAdvance
+}
+
+/// Store a value into Non-Volatile Memory
+pub fn op_nvs(
+    tpu: &mut TPU,
+    target: &OperandValueType,
+    source: &OperandValueType,
+) -> ExecuteResult {
+    let address = tpu.get_operand_value(target) as usize;
+    let value = tpu.get_operand_value(source);
+
+    // Out of bounds
+    let Some(cell) = tpu.tpu_state.nvram.get_mut(address) else {
+        return ExecuteResult::Halt(HaltReason::IndexOutOfRange);
+    };
+
+    *cell = value;
+    ExecuteResult::PCAdvance
+}
@@ -1,26 +1,58 @@
 mod alu;
+mod builder;
 mod decoder;
+mod diff;
 mod execution;
+mod expr;
 mod flow;
+mod history;
 mod io_matrix;
 mod mmu;
+pub mod peripheral;
+mod ram_stats;
+mod snapshot;
+mod stack;
 #[cfg(test)]
 mod tpu_test;
 
+pub use builder::{ConfigError, PinMode, TpuBuilder};
+pub use diff::StateDiff;
+pub use expr::{EvalError, Expr, ExprError};
+pub use peripheral::{Peripheral, PinBus};
+pub use ram_stats::RamStats;
+#[cfg(feature = "serde")]
+pub use snapshot::SnapshotError;
+pub use snapshot::TpuSnapshot;
+pub use stack::StackEntry;
+
+use crate::log::{error, info, trace};
+use crate::observer::{Observer, ObserverId, Observers, PinCallback, TraceCallback};
 use crate::shared::{
     AnalogPin, DecodeResult, DigitalPin, HaltReason, Instruction, NetPacket, Register,
 };
 use crate::shared::{ExecuteResult, OperandValueType};
-use std::collections::VecDeque;
-use std::fmt;
-use std::rc::Rc;
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, BTreeSet, VecDeque};
+use alloc::format;
+use alloc::rc::Rc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::Range;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 use strum::{EnumCount, IntoEnumIterator};
-use tracing::{error, trace};
 
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct TpuState {
     /// Stack for operations
     pub stack: Vec<u16>,
+    /// The deepest the stack has been since the last reset
+    pub stack_high_water: usize,
+    /// Whether each value on the stack is a return address pushed by a call, see `TPU::stack_entries`
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub return_addresses: Vec<bool>,
     /// Analog I/O
     pub analog_pins: [u16; AnalogPin::COUNT],
     /// Digital I/O
@@ -30,8 +62,12 @@ pub struct TpuState {
     /// Digital Pin configurations (true = input, false = output)
     pub digital_pin_config: [bool; DigitalPin::COUNT],
     /// Memory
+    #[cfg_attr(feature = "serde", serde(with = "snapshot::ram_words"))]
     pub ram: [u16; TPU::RAM_SIZE],
-    /// The program ROM
+    /// Non-volatile memory, survives a reset
+    pub nvram: Vec<u16>,
+    /// The program ROM. Serialized as the instructions themselves, so deserializing gives each line an `Rc` of its
+    /// own rather than sharing them with the TPU it came from.
     pub rom: Vec<Rc<Instruction>>,
     /// My network address
     pub network_address: u16,
@@ -41,15 +77,295 @@ pub struct TpuState {
     pub outgoing_packets: VecDeque<NetPacket>,
     /// Registers (A, X, Y, R1-R6)
     pub registers: [u16; Register::COUNT],
+    /// Condition flags
+    pub flags: Flags,
+    /// Data Base, the RAM address LDRP and STRP are relative to
+    pub data_base: u16,
+    /// The active hardware loop, set by LOOPS
+    pub hardware_loop: Option<HardwareLoop>,
+    /// Registers saved by SJMP for SRET to restore
+    pub saved_context: Option<SavedContext>,
+    /// PWM settings for each digital pin, see PWMC, PWMD and PWMS
+    pub pwm: [PwmChannel; DigitalPin::COUNT],
+    /// Ticks since the last reset, the time base for PWM
+    pub cycle_count: u64,
+    /// Instructions finished since the last reset, those that halted the TPU aren't counted
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub instructions_retired: u64,
+    /// Digital input edges waiting for PEVR, oldest first, see `TpuConfig::pin_event_queue_size`
+    pub pin_events: VecDeque<PinEvent>,
+    /// An edge was dropped because the queue was full, cleared when PEVR empties the queue
+    pub pin_events_overflowed: bool,
+    /// Rising edges seen on each digital input, wrapping at u16, see PCNTR and PCNTC
+    pub pulse_counts: [u16; DigitalPin::COUNT],
+    /// Digital inputs that are still bouncing after the host drove them
+    pub bounce: [Option<Bounce>; DigitalPin::COUNT],
+    /// Generator state for the bounce pattern, restarted from `TpuConfig::bounce_seed` on reset
+    pub bounce_rng: u32,
+    /// The values the host drove onto the analog inputs, before any noise
+    pub analog_inputs: [u16; AnalogPin::COUNT],
+    /// Where each random walk is, see `AnalogNoise::RandomWalk`
+    pub noise_offsets: [i32; AnalogPin::COUNT],
+    /// Generator state for the analog noise, restarted from `TpuConfig::noise_seed` on reset
+    pub noise_rng: u32,
+    /// Threshold of the comparator on each analog pin, from `TpuConfig::comparators` until CMPT changes it
+    pub comparator_thresholds: [u16; AnalogPin::COUNT],
+    /// XMIT dropped a packet since the last TXOK
+    pub tx_overflow: bool,
+    /// Cycles until the link is free to send again, see `TpuConfig::min_cycles_between_tx`
+    pub tx_cooldown: u16,
+    /// Packets FWD dropped because their hop limit ran out
+    pub ttl_drops: u64,
+    /// Bytes UTX queued for the UART to send
+    pub uart_tx: VecDeque<u8>,
+    /// Bytes the UART received for URX, with `Uart::FRAMING_ERROR` set on a bad frame
+    pub uart_rx: VecDeque<u16>,
     /// Tracks the current line of program
     pub program_counter: usize,
     /// Are we in an error state?
     pub halted: bool,
+    /// Why the TPU last halted, cleared on reset
+    pub halt_reason: Option<HaltReason>,
     /// The state of the current execution (if any)
     pub execution_state: ExecutionState,
+    /// Construction-time options, kept with the state so a restored TPU resets the same way
+    pub config: TpuConfig,
+}
+
+/// Optional TPU behaviour that is fixed when the TPU is built
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TpuConfig {
+    /// Sparse RAM image of (address, value) pairs, written into RAM at construction and on every reset.
+    /// Addresses outside of RAM are ignored.
+    pub initial_ram: Vec<(usize, u16)>,
+    /// Alias the I/O matrix into the top of RAM, see `TPU::MMIO_ANALOG_BASE` and `TPU::MMIO_DIGITAL_WORD`
+    pub memory_mapped_io: bool,
+    /// Number of words of non-volatile memory
+    pub nvram_size: usize,
+    /// RAM addresses that instructions may not write to, a store into the range halts the TPU.
+    /// The initial RAM image and `TPU::write_ram_slice` are exempt.
+    pub read_only_ram: Option<Range<usize>>,
+    /// Memory instructions whose effective address is in the zero page cost one cycle less
+    pub zero_page_discount: bool,
+    /// Taken branches cost `TPU::TAKEN_BRANCH_PENALTY` extra cycles, disable for the original timing
+    pub taken_branch_penalty: bool,
+    /// The line SWI jumps to, a trap without one halts the TPU
+    pub trap_vector: Option<u16>,
+    /// Resolution of the analog pins, values written to them are clamped to `TPU::analog_max`
+    pub analog_resolution_bits: u8,
+    /// How many digital input edges are queued for PEVR, 0 doesn't record them
+    pub pin_event_queue_size: usize,
+    /// Ticks a digital input chatters for after the host changes it, 0 gives clean edges
+    pub bounce_cycles: u16,
+    /// Seed for the bounce pattern, the same seed always chatters the same way
+    pub bounce_seed: u32,
+    /// XMIT and FWD wait for room in a full transmit buffer like WRX, instead of dropping the packet. The same goes
+    /// for sending during the cooldown set by `min_cycles_between_tx`.
+    pub xmit_blocks: bool,
+    /// Fewest cycles between two packets sent onto the network, to model the bandwidth of the link, 0 has no limit
+    pub min_cycles_between_tx: u16,
+    /// Packets sent to `TPU::BROADCAST_ADDRESS` go to every other TPU, disable to use it as a normal address
+    pub broadcast: bool,
+    /// DPWW halts when the word has bits set above `TPU::digital_pin_mask`, instead of setting the carry flag
+    pub strict_pin_words: bool,
+    /// Noise added to each analog input every tick, `None` leaves the input as the host drove it
+    pub analog_noise: [Option<AnalogNoise>; AnalogPin::COUNT],
+    /// Seed for the analog noise, the same seed always gives the same noise
+    pub noise_seed: u32,
+    /// A comparator watching each analog pin, `None` leaves the pin without one
+    pub comparators: [Option<Comparator>; AnalogPin::COUNT],
+}
+
+impl Default for TpuConfig {
+    fn default() -> Self {
+        Self {
+            initial_ram: Vec::new(),
+            memory_mapped_io: false,
+            nvram_size: TPU::NVRAM_SIZE,
+            read_only_ram: None,
+            zero_page_discount: false,
+            taken_branch_penalty: true,
+            trap_vector: None,
+            analog_resolution_bits: 16,
+            pin_event_queue_size: 0,
+            bounce_cycles: 0,
+            bounce_seed: 0,
+            xmit_blocks: false,
+            min_cycles_between_tx: 0,
+            broadcast: true,
+            strict_pin_words: false,
+            analog_noise: [None; AnalogPin::COUNT],
+            noise_seed: 0,
+            comparators: [None; AnalogPin::COUNT],
+        }
+    }
+}
+
+/// Returned when the host tries to drive a pin that the TPU is driving itself
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PinError {
+    /// The pin is configured as an output
+    NotAnInput,
+}
+
+impl fmt::Display for PinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PinError::NotAnInput => write!(f, "pin is not configured as an input"),
+        }
+    }
+}
+
+impl core::error::Error for PinError {}
+
+/// Condition flags, set by CMP and consumed by the flag branches
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Flags {
+    /// The result was zero
+    pub zero: bool,
+    /// The subtraction borrowed, i.e. the first operand was lower (unsigned)
+    pub carry: bool,
+    /// Bit 15 of the result was set
+    pub negative: bool,
+}
+
+/// A fixed count loop run without a branch instruction, see LOOPS
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct HardwareLoop {
+    /// Iterations left, including the current one
+    pub remaining: u16,
+    /// First line of the loop body
+    pub start: usize,
+    /// Last line of the loop body
+    pub end: usize,
+}
+
+/// Pulse width modulation of a digital output
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PwmChannel {
+    /// Length of one PWM cycle in ticks, a period of 0 keeps the pin low
+    pub period: u16,
+    /// Ticks at the start of each period that the pin is high
+    pub duty: u16,
+    /// Is the pin being modulated
+    pub enabled: bool,
+}
+
+impl PwmChannel {
+    /// The level of the pin on the given tick
+    pub fn level(&self, cycle: u64) -> bool {
+        cycle
+            .checked_rem(self.period as u64)
+            .is_some_and(|phase| phase < self.duty as u64)
+    }
+}
+
+/// A digital input changing level, recorded by `TPU::drive_digital_input`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct PinEvent {
+    /// The value of `TpuState::cycle_count` when the pin changed
+    pub cycle: u64,
+    pub pin: DigitalPin,
+    /// The new level of the pin
+    pub level: bool,
+}
+
+/// A digital input settling after the host drove it, see `TpuConfig::bounce_cycles`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Bounce {
+    /// The level the pin settles on
+    pub target: bool,
+    /// Ticks of chatter left
+    pub remaining: u16,
+}
+
+/// A pin on the I/O matrix
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Pin {
+    Digital(DigitalPin),
+    Analog(AnalogPin),
+}
+
+/// An output pin changing value, see `TPU::set_pin_callback`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PinChange {
+    /// The value of `TpuState::cycle_count` when the pin changed
+    pub cycle: u64,
+    pub pin: Pin,
+    /// Digital pins are 0 or 1
+    pub old: u16,
+    pub new: u16,
+}
+
+/// An instruction having run, see `TPU::set_trace_callback`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TraceEvent {
+    /// The value of `TpuState::cycle_count` when it finished
+    pub cycle: u64,
+    /// Where it is in ROM
+    pub pc: usize,
+    pub instruction: Rc<Instruction>,
+    /// The registers once it's run
+    pub registers: [u16; Register::COUNT],
+    /// The RAM address it wrote and what it wrote there, the last one for an instruction writing a block
+    pub ram_write: Option<(usize, u16)>,
+    /// The digital pins once it's run, as DPRW reads them
+    pub digital_pins: u16,
+}
+
+/// Why `TPU::run` stopped
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RunResult {
+    /// The next instruction to be fetched is at a breakpoint, at this address
+    Breakpoint(usize),
+    Halted(HaltReason),
+    /// All the ticks asked for were run
+    OutOfTicks,
+}
+
+/// Noise on an analog input, like a real sensor, see `TpuConfig::analog_noise`.
+/// The noisy value is what APR and the rest of the program see, it's clamped to the pin's range.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum AnalogNoise {
+    /// A fresh offset every tick, anywhere from -amplitude to +amplitude
+    Uniform { amplitude: u16 },
+    /// An offset that moves by up to `step` each tick, drifting back towards the true value and never more than
+    /// `amplitude` away from it
+    RandomWalk { amplitude: u16, step: u16 },
+}
+
+/// Drives a digital input high while an analog pin is above a threshold, see `TpuConfig::comparators`.
+///
+/// The output goes high once the level is above `threshold + hysteresis` and low again once it's at or below
+/// `threshold - hysteresis`, so a level wandering around the threshold doesn't chatter. The digital pin has to be an
+/// input, an output is left to the program.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Comparator {
+    pub digital: DigitalPin,
+    /// Where it starts, CMPT can move it
+    pub threshold: u16,
+    pub hysteresis: u16,
+}
+
+/// The register file and return address saved by SJMP
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SavedContext {
+    pub registers: [u16; Register::COUNT],
+    /// The line after the SJMP
+    pub return_address: usize,
 }
 
 #[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ExecutionState {
     /// This is the function that we execute when `wait_cycles` reaches zero.
     /// It actually executes the instruction that we previously decoded.
@@ -58,6 +374,8 @@ pub struct ExecutionState {
     pub wait_cycles: u16,
     /// Should the current instruction be called every cycle until finished?
     pub execute_each_cycle: bool,
+    /// Cycles to stall for after the current instruction modifies the PC, e.g. a taken branch
+    pub stall_cycles: u16,
 }
 
 impl fmt::Display for TpuState {
@@ -177,9 +495,10 @@ impl fmt::Display for TpuState {
         // Stack
         writeln!(
             f,
-            "{} Stack (Size: {:04x})                                        {}",
+            "{} Stack (Size: {:04x}, Max: {:04x})                             {}",
             v_line,
             self.stack.len(),
+            self.stack_high_water,
             v_line
         )?;
         if self.stack.is_empty() {
@@ -275,6 +594,26 @@ impl fmt::Display for TpuState {
 #[derive(Clone)]
 pub struct TPU {
     tpu_state: TpuState,
+    /// Opt-in RAM access counters, kept out of `TpuState` so they don't end up in snapshots
+    ram_stats: Option<Box<RamStats>>,
+    /// Devices wired to the pins, also kept out of `TpuState`
+    peripherals: Vec<Box<dyn Peripheral>>,
+    /// Told about what the TPU does, shared with clones of the TPU
+    observers: Observers,
+    /// The observer `TPU::set_pin_callback` added
+    pin_callback: Option<ObserverId>,
+    /// The observer `TPU::set_trace_callback` added
+    trace_callback: Option<ObserverId>,
+    /// The last RAM write by the instruction running, for the trace callback
+    ram_write: Option<(usize, u16)>,
+    /// Where the instruction that finished on the last tick was, for `TPU::ticks`
+    retired: Option<usize>,
+    /// ROM addresses `TPU::run` stops at, a debugging aid so they survive a reset
+    breakpoints: BTreeSet<usize>,
+    /// Breakpoints that only stop when their condition is true, by address
+    breakpoint_conditions: BTreeMap<usize, Expr>,
+    /// Opt-in record of earlier states for `TPU::step_back`
+    history: Option<Box<history::History>>,
 }
 
 impl fmt::Display for TPU {
@@ -287,6 +626,23 @@ impl TPU {
     pub const STACK_SIZE: usize = 16;
     pub const NET_BUFFER_SIZE: usize = 8;
     pub const RAM_SIZE: usize = 128;
+    pub const NVRAM_SIZE: usize = 16;
+    /// With memory mapped I/O enabled, address `MMIO_ANALOG_BASE + n` aliases analog pin `n`
+    pub const MMIO_ANALOG_BASE: usize = 0x70;
+    /// Packets to this address go to every other TPU on the network, it's never a sender
+    pub const BROADCAST_ADDRESS: u16 = 0xFFFF;
+    /// Bytes each of the UART FIFOs can hold
+    pub const UART_FIFO_SIZE: usize = 4;
+    /// With memory mapped I/O enabled, this address aliases the digital pin word
+    pub const MMIO_DIGITAL_WORD: usize = 0x78;
+    /// Addresses below this are in the zero page, see `TpuConfig::zero_page_discount`
+    pub const ZERO_PAGE_SIZE: usize = 16;
+    /// Extra cycles charged for a taken branch, see `TpuConfig::taken_branch_penalty`
+    pub const TAKEN_BRANCH_PENALTY: u16 = 1;
+    /// ENTER, LEAVE and PEEKF keep the frame pointer in this register
+    pub const FRAME_POINTER: Register = Register::R6;
+    /// SWI puts its code operand in this register for the trap handler
+    pub const TRAP_CODE: Register = Register::R5;
 
     // Helper function to get a value from an operand
     // Returns a tuple (delay, value) where delay is 1 for register access, 0 for constant
@@ -313,27 +669,81 @@ impl TPU {
         analog_pin_config: [bool; AnalogPin::COUNT],
         digital_pin_config: [bool; DigitalPin::COUNT],
         program: Vec<Rc<Instruction>>,
+    ) -> Self {
+        TPU::new_with_config(
+            network_address,
+            analog_pin_config,
+            digital_pin_config,
+            program,
+            TpuConfig::default(),
+        )
+    }
+
+    /// Create a new TPU VM with a specified network address, pin configurations and options
+    pub fn new_with_config(
+        network_address: u16,
+        analog_pin_config: [bool; AnalogPin::COUNT],
+        digital_pin_config: [bool; DigitalPin::COUNT],
+        program: Vec<Rc<Instruction>>,
+        config: TpuConfig,
     ) -> Self {
         let mut tpu = Self {
+            ram_stats: None,
+            peripherals: Vec::new(),
+            observers: Observers::default(),
+            pin_callback: None,
+            trace_callback: None,
+            ram_write: None,
+            retired: None,
+            breakpoints: BTreeSet::new(),
+            breakpoint_conditions: BTreeMap::new(),
+            history: None,
             tpu_state: TpuState {
                 stack: Vec::new(),
+                stack_high_water: 0,
+                return_addresses: Vec::new(),
                 analog_pins: [0; AnalogPin::COUNT],
                 digital_pins: [false; DigitalPin::COUNT],
                 analog_pin_config,
                 digital_pin_config,
                 ram: [0; TPU::RAM_SIZE],
+                nvram: vec![0; config.nvram_size],
                 rom: program,
                 network_address,
                 incoming_packets: VecDeque::new(),
                 outgoing_packets: VecDeque::new(),
                 registers: [0; Register::COUNT],
+                flags: Flags::default(),
+                data_base: 0,
+                hardware_loop: None,
+                saved_context: None,
+                pwm: [PwmChannel::default(); DigitalPin::COUNT],
+                cycle_count: 0,
+                instructions_retired: 0,
+                pin_events: VecDeque::new(),
+                pin_events_overflowed: false,
+                pulse_counts: [0; DigitalPin::COUNT],
+                bounce: [None; DigitalPin::COUNT],
+                bounce_rng: 0,
+                analog_inputs: [0; AnalogPin::COUNT],
+                noise_offsets: [0; AnalogPin::COUNT],
+                noise_rng: 0,
+                comparator_thresholds: [0; AnalogPin::COUNT],
+                tx_overflow: false,
+                tx_cooldown: 0,
+                ttl_drops: 0,
+                uart_tx: VecDeque::new(),
+                uart_rx: VecDeque::new(),
                 program_counter: 0,
                 halted: false,
+                halt_reason: None,
                 execution_state: ExecutionState {
                     instruction: None,
                     wait_cycles: 0,
                     execute_each_cycle: false,
+                    stall_cycles: 0,
                 },
+                config,
             },
         };
 
@@ -342,20 +752,37 @@ impl TPU {
     }
 
     pub fn new_from_state(tpu_state: TpuState) -> TPU {
-        TPU { tpu_state }
+        TPU {
+            tpu_state,
+            ram_stats: None,
+            peripherals: Vec::new(),
+            observers: Observers::default(),
+            pin_callback: None,
+            trace_callback: None,
+            ram_write: None,
+            retired: None,
+            breakpoints: BTreeSet::new(),
+            breakpoint_conditions: BTreeMap::new(),
+            history: None,
+        }
     }
 
-    fn reset(&mut self) {
+    /// Start the program again from the top with cleared registers, stacks and RAM, re-applying the initial RAM image; NVRAM is kept
+    pub fn reset(&mut self) {
         trace!("RESET");
+        self.clear_history();
 
         // Clear stack
         self.tpu_state.stack.clear();
+        self.tpu_state.return_addresses.clear();
+        self.tpu_state.stack_high_water = 0;
 
         // Clear program counter
         self.tpu_state.program_counter = 0;
 
         // Clear halt
         self.tpu_state.halted = false;
+        self.tpu_state.halt_reason = None;
 
         // Clear execution state
         self.tpu_state.execution_state = ExecutionState::default();
@@ -364,15 +791,47 @@ impl TPU {
         for register in Register::iter() {
             self.write_register(register, 0);
         }
-
-        // Clear RAM
+        self.tpu_state.flags = Flags::default();
+        self.tpu_state.data_base = 0;
+        self.tpu_state.hardware_loop = None;
+        self.tpu_state.saved_context = None;
+        self.tpu_state.pwm = [PwmChannel::default(); DigitalPin::COUNT];
+        self.tpu_state.cycle_count = 0;
+        self.tpu_state.instructions_retired = 0;
+        self.tpu_state.pin_events.clear();
+        self.tpu_state.pin_events_overflowed = false;
+        self.tpu_state.pulse_counts = [0; DigitalPin::COUNT];
+        self.tpu_state.bounce = [None; DigitalPin::COUNT];
+        // Xorshift never leaves zero
+        self.tpu_state.bounce_rng = self.tpu_state.config.bounce_seed.max(1);
+        self.tpu_state.noise_offsets = [0; AnalogPin::COUNT];
+        self.tpu_state.noise_rng = self.tpu_state.config.noise_seed.max(1);
+        self.tpu_state.comparator_thresholds = self
+            .tpu_state
+            .config
+            .comparators
+            .map(|comparator| comparator.map_or(0, |comparator| comparator.threshold));
+
+        // Clear RAM, NVRAM is deliberately left alone
         for index in 0..TPU::RAM_SIZE {
             self.tpu_state.ram[index] = 0;
         }
 
+        // Re-apply the initial RAM image
+        for &(address, value) in &self.tpu_state.config.initial_ram {
+            if address < self.tpu_state.ram.len() {
+                self.tpu_state.ram[address] = value;
+            }
+        }
+
         // Clear network buffers
         self.tpu_state.incoming_packets.clear();
         self.tpu_state.outgoing_packets.clear();
+        self.tpu_state.tx_overflow = false;
+        self.tpu_state.tx_cooldown = 0;
+        self.tpu_state.ttl_drops = 0;
+        self.tpu_state.uart_tx.clear();
+        self.tpu_state.uart_rx.clear();
 
         // Reset I/O pins
         for pin in DigitalPin::iter() {
@@ -386,8 +845,24 @@ impl TPU {
     /// Allow the CPU to execute for a single clock cycle
     pub fn tick(&mut self) {
         trace!("TICK");
+        self.retired = None;
+        self.record_history();
         self.decrement_wait_cycles();
+        self.update_pwm();
+        self.update_bounce();
+        self.update_noise();
+        self.update_comparators();
+        self.tpu_state.cycle_count = self.tpu_state.cycle_count.wrapping_add(1);
+        self.run_cycle();
+        self.update_peripherals();
+        if !self.observers.is_empty() {
+            let cycle = self.tpu_state.cycle_count;
+            self.observers.notify(|observer| observer.on_tick(cycle));
+        }
+    }
 
+    /// Execute or fetch for this tick, if the TPU isn't halted or waiting
+    fn run_cycle(&mut self) {
         if self.tpu_state.halted {
             return;
         }
@@ -408,9 +883,204 @@ impl TPU {
         self.fetch_instruction()
     }
 
+    /// Call `callback` whenever an output pin changes value, whether the program, PWM, a reset or a peripheral changed
+    /// it. Writes that leave a pin as it was and inputs driven by the host aren't reported.
+    pub fn set_pin_callback(&mut self, callback: Box<dyn FnMut(PinChange)>) {
+        if let Some(id) = self.pin_callback.take() {
+            self.observers.remove(id);
+        }
+        self.pin_callback = Some(self.observers.add(PinCallback(callback)));
+    }
+
+    /// Tell the observers about a change
+    fn notify_pin_change(&self, pin: Pin, old: u16, new: u16) {
+        if old == new || self.observers.is_empty() {
+            return;
+        }
+        let change = PinChange {
+            cycle: self.tpu_state.cycle_count,
+            pin,
+            old,
+            new,
+        };
+        self.observers
+            .notify(|observer| observer.on_pin_change(change));
+    }
+
+    /// Call `callback` as each instruction finishes, including one that halts the TPU. An instruction taking several
+    /// cycles is reported once, on its last.
+    pub fn set_trace_callback(&mut self, callback: Box<dyn FnMut(&TraceEvent)>) {
+        self.clear_trace_callback();
+        self.trace_callback = Some(self.observers.add(TraceCallback(callback)));
+    }
+
+    /// Stop calling the trace callback
+    pub fn clear_trace_callback(&mut self) {
+        if let Some(id) = self.trace_callback.take() {
+            self.observers.remove(id);
+        }
+    }
+
+    /// Tell `observer` about everything the TPU does from now on, until it's removed. Clones of the TPU share their
+    /// observers. With none the TPU doesn't build the events at all.
+    pub fn add_observer(&mut self, observer: impl Observer + 'static) -> ObserverId {
+        self.observers.add(observer)
+    }
+
+    /// Returns whether there was an observer with `id`
+    pub fn remove_observer(&mut self, id: ObserverId) -> bool {
+        self.observers.remove(id)
+    }
+
+    /// Tell the observers about an instruction that's finished
+    fn notify_trace(&mut self, pc: usize, instruction: &Rc<Instruction>) {
+        let ram_write = self.ram_write.take();
+        if self.observers.is_empty() {
+            return;
+        }
+        let event = TraceEvent {
+            cycle: self.tpu_state.cycle_count,
+            pc,
+            instruction: instruction.clone(),
+            registers: self.tpu_state.registers,
+            ram_write,
+            digital_pins: self.get_digital_pins(),
+        };
+        self.observers
+            .notify(|observer| observer.on_instruction_retired(&event));
+    }
+
+    /// Wire a simulated device to the pins, it's ticked after every instruction phase
+    pub fn attach_peripheral(&mut self, peripheral: Box<dyn Peripheral>) {
+        self.peripherals.push(peripheral);
+    }
+
+    /// Let the peripherals see the pins as the instruction left them.
+    /// Like the rest of the I/O matrix they keep running while the TPU is halted.
+    fn update_peripherals(&mut self) {
+        let mut peripherals = core::mem::take(&mut self.peripherals);
+        let mut bus = PinBus::new(self);
+        for peripheral in &mut peripherals {
+            peripheral.tick(&mut bus);
+        }
+        self.peripherals = peripherals;
+    }
+
+    /// Drive the PWM outputs for this tick.
+    /// Like the rest of the I/O matrix this keeps running while the TPU is halted.
+    fn update_pwm(&mut self) {
+        let cycle = self.tpu_state.cycle_count;
+        for pin in DigitalPin::iter() {
+            let channel = self.tpu_state.pwm[pin as usize];
+            if channel.enabled && !self.tpu_state.digital_pin_config[pin as usize] {
+                let level = channel.level(cycle);
+                let old = core::mem::replace(&mut self.tpu_state.digital_pins[pin as usize], level);
+                self.notify_pin_change(Pin::Digital(pin), old as u16, level as u16);
+            }
+        }
+    }
+
+    /// Chatter the inputs that are still bouncing, settling them on their last tick
+    fn update_bounce(&mut self) {
+        for pin in DigitalPin::iter() {
+            let Some(mut bounce) = self.tpu_state.bounce[pin as usize] else {
+                continue;
+            };
+
+            // A pin turned into an output is driven by the program instead
+            if !self.tpu_state.digital_pin_config[pin as usize] {
+                self.tpu_state.bounce[pin as usize] = None;
+                continue;
+            }
+
+            bounce.remaining -= 1;
+            let level = if bounce.remaining == 0 {
+                self.tpu_state.bounce[pin as usize] = None;
+                bounce.target
+            } else {
+                self.tpu_state.bounce[pin as usize] = Some(bounce);
+                self.next_bounce_bit()
+            };
+            self.set_input_level(pin, level);
+        }
+    }
+
+    /// Add this tick's noise to the analog inputs that have it
+    fn update_noise(&mut self) {
+        for pin in AnalogPin::iter() {
+            let Some(noise) = self.tpu_state.config.analog_noise[pin as usize] else {
+                continue;
+            };
+            // An output is driven by the program instead
+            if !self.tpu_state.analog_pin_config[pin as usize] {
+                continue;
+            }
+
+            let offset = match noise {
+                AnalogNoise::Uniform { amplitude } => self.next_noise(amplitude),
+                AnalogNoise::RandomWalk { amplitude, step } => {
+                    let offset = self.tpu_state.noise_offsets[pin as usize];
+                    let offset = offset - offset / 8 + self.next_noise(step);
+                    offset.clamp(-(amplitude as i32), amplitude as i32)
+                }
+            };
+            self.tpu_state.noise_offsets[pin as usize] = offset;
+
+            let value = self.tpu_state.analog_inputs[pin as usize] as i32 + offset;
+            self.tpu_state.analog_pins[pin as usize] =
+                value.clamp(0, self.analog_max() as i32) as u16;
+        }
+    }
+
+    /// Drive the comparator outputs from the analog pins, before the instruction phase so the program sees them
+    fn update_comparators(&mut self) {
+        for pin in AnalogPin::iter() {
+            let Some(comparator) = self.tpu_state.config.comparators[pin as usize] else {
+                continue;
+            };
+            let digital = comparator.digital;
+            if !self.tpu_state.digital_pin_config[digital as usize] {
+                continue;
+            }
+
+            let level = self.tpu_state.analog_pins[pin as usize];
+            let threshold = self.tpu_state.comparator_thresholds[pin as usize];
+            let high = self.tpu_state.digital_pins[digital as usize];
+            if !high && level > threshold.saturating_add(comparator.hysteresis) {
+                self.set_input_level(digital, true);
+            } else if high && level <= threshold.saturating_sub(comparator.hysteresis) {
+                self.set_input_level(digital, false);
+            }
+        }
+    }
+
+    /// A random offset from -range to +range, from the xorshift generator behind the analog noise
+    fn next_noise(&mut self, range: u16) -> i32 {
+        if range == 0 {
+            return 0;
+        }
+        let mut state = self.tpu_state.noise_rng;
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        self.tpu_state.noise_rng = state;
+        (state % (range as u32 * 2 + 1)) as i32 - range as i32
+    }
+
+    /// The next bit from the xorshift generator behind the bounce pattern
+    fn next_bounce_bit(&mut self) -> bool {
+        let mut state = self.tpu_state.bounce_rng;
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        self.tpu_state.bounce_rng = state;
+        state & 1 != 0
+    }
+
     fn decrement_wait_cycles(&mut self) {
         self.tpu_state.execution_state.wait_cycles =
             self.tpu_state.execution_state.wait_cycles.saturating_sub(1);
+        self.tpu_state.tx_cooldown = self.tpu_state.tx_cooldown.saturating_sub(1);
     }
 
     /// Executes until the next instruction is complete
@@ -422,9 +1092,169 @@ impl TPU {
         }
     }
 
+    /// Execute an instruction that isn't in the program straight away, e.g. one typed at a debugger's prompt.
+    /// It takes no cycles and only moves the program counter if it branches. Returns `Ok(false)` if it would have had
+    /// to wait, like WRX with nothing to receive, in which case it did nothing. A halt halts the TPU as usual.
+    pub fn execute_now(&mut self, instruction: &Instruction) -> Result<bool, HaltReason> {
+        // Waits and stalls set here belong to the program's instruction in flight, not this one
+        let execution_state = self.tpu_state.execution_state.clone();
+        let result = execution::execute(self, instruction, 1);
+        self.tpu_state.execution_state = execution_state;
+
+        match result {
+            ExecuteResult::Halt(reason) => {
+                self.halt(reason);
+                Err(reason)
+            }
+            ExecuteResult::NoPCAdvance => Ok(false),
+            ExecuteResult::PCAdvance | ExecuteResult::PCModified => Ok(true),
+        }
+    }
+
+    /// Tick up to `ticks` times, stopping early if the TPU halts or reaches a breakpoint, i.e. the next tick would fetch
+    /// the instruction there. A breakpoint is only noticed after a tick, so calling this again carries on past it, and
+    /// one reached on the last tick is still reported.
+    pub fn run(&mut self, ticks: u64) -> RunResult {
+        self.run_to(ticks, None)
+    }
+
+    /// Like `run`, but stopping at `pc` as well, as if there were a breakpoint there for just this run, which is how
+    /// reaching it is reported. It's only noticed after a tick, like a breakpoint, so with the PC already there at least
+    /// one instruction runs. If `pc` isn't reached in `max_cycles` ticks it's `OutOfTicks`.
+    pub fn run_until_pc(&mut self, pc: usize, max_cycles: u64) -> RunResult {
+        self.run_to(max_cycles, Some(pc))
+    }
+
+    /// See `run` and `run_until_pc`
+    fn run_to(&mut self, ticks: u64, target: Option<usize>) -> RunResult {
+        for _ in 0..ticks {
+            if let Some(reason) = self.tpu_state.halt_reason {
+                return RunResult::Halted(reason);
+            }
+            self.tick();
+            if !self.tpu_state.halted
+                && (self.at_breakpoint() || target.is_some_and(|pc| self.about_to_fetch(pc)))
+            {
+                return RunResult::Breakpoint(self.tpu_state.program_counter);
+            }
+        }
+        match self.tpu_state.halt_reason {
+            Some(reason) => RunResult::Halted(reason),
+            None => RunResult::OutOfTicks,
+        }
+    }
+
+    /// Tick lazily, one tick for each item taken. It ends once the TPU halts, yielding the tick that halted it, or
+    /// after the tick that reaches a breakpoint, like `run`. A new iterator carries on past the breakpoint.
+    ///
+    /// ```
+    /// use tls::prelude::*;
+    ///
+    /// let mut tpu = create_basic_tpu_config(parse_program("LDR A, 1\nHLT").unwrap());
+    /// let halted = tpu.ticks().take(100).filter(|tick| tick.halted).count();
+    /// assert_eq!(halted, 1);
+    /// ```
+    pub fn ticks(&mut self) -> Ticks<'_> {
+        Ticks {
+            tpu: self,
+            done: false,
+        }
+    }
+
+    /// The instructions that finish from now on, as their address and instruction, ticking as far as each one needs.
+    /// It ends the same way as `ticks`. Nothing is yielded while the TPU waits, so a program waiting forever, e.g. on
+    /// WRX with nothing coming, never yields; `ticks` can be limited instead.
+    pub fn instructions(&mut self) -> Retired<'_> {
+        Retired {
+            ticks: self.ticks(),
+        }
+    }
+
+    /// Whether the next tick fetches the instruction at `address`, rather than carrying on with one it's running
+    pub fn about_to_fetch(&self, address: usize) -> bool {
+        let execution_state = &self.tpu_state.execution_state;
+        execution_state.instruction.is_none()
+            && execution_state.wait_cycles <= 1
+            && self.tpu_state.program_counter == address
+    }
+
+    /// Whether the next tick fetches the instruction at a breakpoint, and its condition, if it has one, is true. A
+    /// condition that can't be evaluated stops as well, so a mistake in it isn't missed.
+    pub(crate) fn at_breakpoint(&self) -> bool {
+        let program_counter = self.tpu_state.program_counter;
+        self.about_to_fetch(program_counter)
+            && self.breakpoints.contains(&program_counter)
+            && self
+                .breakpoint_conditions
+                .get(&program_counter)
+                .is_none_or(|condition| condition.eval(self) != Ok(0))
+    }
+
+    /// Set or clear the breakpoint at a ROM address, returns whether it's now set. A breakpoint set this way always
+    /// stops.
+    pub fn toggle_breakpoint(&mut self, address: usize) -> bool {
+        self.breakpoint_conditions.remove(&address);
+        if self.breakpoints.remove(&address) {
+            false
+        } else {
+            self.breakpoints.insert(address)
+        }
+    }
+
+    /// Set a breakpoint at a ROM address that only stops when `condition` is true, or always if there isn't one. A
+    /// breakpoint already there has its condition replaced.
+    pub fn set_breakpoint(&mut self, address: usize, condition: Option<Expr>) {
+        self.breakpoints.insert(address);
+        match condition {
+            Some(condition) => self.breakpoint_conditions.insert(address, condition),
+            None => self.breakpoint_conditions.remove(&address),
+        };
+    }
+
+    /// The condition on the breakpoint at a ROM address, `None` if it always stops or there isn't one
+    pub fn breakpoint_condition(&self, address: usize) -> Option<&Expr> {
+        self.breakpoint_conditions.get(&address)
+    }
+
+    /// Drop the breakpoints past the end of a program of `len` instructions
+    fn retain_breakpoints(&mut self, len: usize) {
+        self.breakpoints.retain(|&address| address < len);
+        self.breakpoint_conditions
+            .retain(|&address, _| address < len);
+    }
+
+    pub fn breakpoints(&self) -> &BTreeSet<usize> {
+        &self.breakpoints
+    }
+
+    /// Replace the program and reset. Breakpoints past the end of the new program are dropped, the rest are kept.
+    pub fn load_program(&mut self, program: Vec<Rc<Instruction>>) {
+        self.retain_breakpoints(program.len());
+        self.tpu_state.rom = program;
+        self.reset();
+    }
+
     fn fetch_instruction(&mut self) {
-        let instruction = self.tpu_state.rom[self.tpu_state.program_counter].clone();
-        let result = decoder::decode(&instruction);
+        self.ram_write = None;
+        // Only an empty program leaves the PC out of range, see `fall_through`
+        let Some(instruction) = self
+            .tpu_state
+            .rom
+            .get(self.tpu_state.program_counter)
+            .cloned()
+        else {
+            self.halt(HaltReason::EndOfProgram);
+            return;
+        };
+        let mut result = decoder::decode(&instruction);
+
+        // The registers can't change before this instruction executes, so the address it will access is known now
+        if self.tpu_state.config.zero_page_discount
+            && mmu::effective_address(self, &instruction)
+                .is_some_and(|address| address < TPU::ZERO_PAGE_SIZE)
+        {
+            result.cycles = result.cycles.saturating_sub(1).max(1);
+        }
 
         // This instruction executes in a single clock cycle, so do it now.
         if result.cycles == 1 {
@@ -439,7 +1269,15 @@ impl TPU {
     }
 
     fn execute_instruction(&mut self, instruction: Rc<Instruction>, wait_cycles: u16) {
+        let pc = self.tpu_state.program_counter;
         let result = execution::execute(self, &instruction, wait_cycles);
+        if matches!(result, ExecuteResult::PCAdvance | ExecuteResult::PCModified) {
+            self.tpu_state.instructions_retired += 1;
+        }
+        if result != ExecuteResult::NoPCAdvance {
+            self.retired = Some(pc);
+            self.notify_trace(pc, &instruction);
+        }
 
         match result {
             ExecuteResult::PCAdvance => {
@@ -448,15 +1286,28 @@ impl TPU {
                 self.tpu_state.execution_state.instruction = None;
                 self.tpu_state.execution_state.execute_each_cycle = false;
 
+                // The end of a hardware loop goes back to its start until the count runs out
+                if let Some(hardware_loop) = &mut self.tpu_state.hardware_loop
+                    && hardware_loop.end == self.tpu_state.program_counter
+                {
+                    if hardware_loop.remaining > 1 {
+                        hardware_loop.remaining -= 1;
+                        self.tpu_state.program_counter = hardware_loop.start;
+                        return;
+                    }
+                    self.tpu_state.hardware_loop = None;
+                }
+
                 // Advance the program counter
-                // Check that the program counter is not going out of bounds
-                if self.tpu_state.program_counter + 1 > (self.tpu_state.rom.len() - 1) {
-                    self.tpu_state.halted = true;
+                let next = self.tpu_state.program_counter + 1;
+                if let ExecuteResult::Halt(reason) = self.fall_through(next) {
+                    self.halt(reason);
                 }
-                self.tpu_state.program_counter += 1;
             }
             ExecuteResult::PCModified => {
-                self.tpu_state.execution_state.wait_cycles = 0;
+                // Any stall still counts the cycle we're in, so it needs one more to be waited out
+                let stall = core::mem::take(&mut self.tpu_state.execution_state.stall_cycles);
+                self.tpu_state.execution_state.wait_cycles = if stall > 0 { stall + 1 } else { 0 };
                 self.tpu_state.execution_state.instruction = None;
                 self.tpu_state.execution_state.execute_each_cycle = false;
                 return;
@@ -464,13 +1315,36 @@ impl TPU {
             ExecuteResult::NoPCAdvance => {
                 self.tpu_state.execution_state.instruction = Some(instruction)
             }
-            ExecuteResult::Halt(reason) => {
-                error!("TPU Halted: {reason:?}");
-                self.tpu_state.halted = true
-            }
+            ExecuteResult::Halt(reason) => self.halt(reason),
         }
     }
 
+    fn halt(&mut self, reason: HaltReason) {
+        // Running off the end is how most programs finish, it's not an error
+        if reason == HaltReason::EndOfProgram {
+            info!("TPU Halted: {reason:?}");
+        } else {
+            error!("TPU Halted: {reason:?}");
+        }
+        self.tpu_state.halted = true;
+        self.tpu_state.halt_reason = Some(reason);
+        let cycle = self.tpu_state.cycle_count;
+        self.observers
+            .notify(|observer| observer.on_halt(cycle, reason));
+    }
+
+    /// Carry on at a later line without branching, e.g. the next line or past a skipped one.
+    /// Running past the last instruction ends the program and leaves the PC where it was, so it's never out of range.
+    /// Taken branches are validated separately, a target past the end of the program is an `InvalidPC`.
+    pub(crate) fn fall_through(&mut self, target: usize) -> ExecuteResult {
+        if target >= self.tpu_state.rom.len() {
+            return ExecuteResult::Halt(HaltReason::EndOfProgram);
+        }
+
+        self.tpu_state.program_counter = target;
+        ExecuteResult::PCModified
+    }
+
     pub fn busy(&self) -> bool {
         self.tpu_state.execution_state.wait_cycles > 0
     }
@@ -488,21 +1362,125 @@ impl TPU {
         self.tpu_state.registers[register as usize]
     }
 
-    /// Write a value to a register
-    fn write_register(&mut self, register: Register, value: u16) {
+    /// Write a value to a register, hosts can use it to poke one between ticks
+    pub fn write_register(&mut self, register: Register, value: u16) {
         self.tpu_state.registers[register as usize] = value;
     }
 
     /// Push a value onto the stack
     fn push(&mut self, value: u16) {
+        self.push_tagged(value, false);
+    }
+
+    /// Push the address a call returns to onto the stack
+    fn push_return_address(&mut self, address: u16) {
+        self.push_tagged(address, true);
+    }
+
+    fn push_tagged(&mut self, value: u16, return_address: bool) {
         self.tpu_state.stack.push(value);
+        self.tpu_state.return_addresses.push(return_address);
+        self.tpu_state.stack_high_water = self
+            .tpu_state
+            .stack_high_water
+            .max(self.tpu_state.stack.len());
     }
 
     /// Pop a value from the stack
     fn pop(&mut self) -> u16 {
+        self.tpu_state.return_addresses.pop();
         self.tpu_state.stack.pop().unwrap_or(0)
     }
 
+    /// Is the analog pin configured as an input, see ACFG
+    pub fn analog_pin_is_input(&self, pin: AnalogPin) -> bool {
+        self.tpu_state.analog_pin_config[pin as usize]
+    }
+
+    /// Is the digital pin configured as an input, see DCFG
+    pub fn digital_pin_is_input(&self, pin: DigitalPin) -> bool {
+        self.tpu_state.digital_pin_config[pin as usize]
+    }
+
+    /// Drive an analog input from outside the TPU, e.g. a sensor reading, clamped to `TPU::analog_max`.
+    /// Pins configured as outputs are driven by the program, so they return an error.
+    /// With `TpuConfig::analog_noise` set for the pin, the noise is added on top from the next tick.
+    pub fn drive_analog_input(&mut self, pin: AnalogPin, value: u16) -> Result<(), PinError> {
+        if !self.tpu_state.analog_pin_config[pin as usize] {
+            return Err(PinError::NotAnInput);
+        }
+        let value = value.min(self.analog_max());
+        self.tpu_state.analog_inputs[pin as usize] = value;
+        self.tpu_state.analog_pins[pin as usize] = value;
+        Ok(())
+    }
+
+    /// The value last driven onto an analog input, without the noise the program sees
+    pub fn true_analog_input(&self, pin: AnalogPin) -> u16 {
+        self.tpu_state.analog_inputs[pin as usize]
+    }
+
+    /// Drive a digital input from outside the TPU, e.g. a button press.
+    /// Pins configured as outputs are driven by the program, so they return an error.
+    /// With `TpuConfig::bounce_cycles` set, a change of level chatters for that many ticks before it settles.
+    pub fn drive_digital_input(&mut self, pin: DigitalPin, value: bool) -> Result<(), PinError> {
+        if !self.tpu_state.digital_pin_config[pin as usize] {
+            return Err(PinError::NotAnInput);
+        }
+
+        // Compare against where a bouncing pin will settle, not where it happens to be
+        let settled = match self.tpu_state.bounce[pin as usize] {
+            Some(bounce) => bounce.target,
+            None => self.tpu_state.digital_pins[pin as usize],
+        };
+        if settled == value {
+            return Ok(());
+        }
+
+        let bounce_cycles = self.tpu_state.config.bounce_cycles;
+        self.tpu_state.bounce[pin as usize] = (bounce_cycles > 0).then_some(Bounce {
+            target: value,
+            remaining: bounce_cycles,
+        });
+        self.set_input_level(pin, value);
+        Ok(())
+    }
+
+    /// Change the level of a digital input.
+    /// A change of level is queued for PEVR if the pin event queue is enabled, a rising edge is also counted.
+    fn set_input_level(&mut self, pin: DigitalPin, level: bool) {
+        if self.tpu_state.digital_pins[pin as usize] != level {
+            self.queue_pin_event(pin, level);
+            if level {
+                let count = &mut self.tpu_state.pulse_counts[pin as usize];
+                *count = count.wrapping_add(1);
+            }
+        }
+        self.tpu_state.digital_pins[pin as usize] = level;
+    }
+
+    /// Rising edges driven onto a digital input since the last reset or PCNTC
+    pub fn pulse_count(&self, pin: DigitalPin) -> u16 {
+        self.tpu_state.pulse_counts[pin as usize]
+    }
+
+    /// Record a digital input edge, flagging an overflow if there's no room
+    fn queue_pin_event(&mut self, pin: DigitalPin, level: bool) {
+        let capacity = self.tpu_state.config.pin_event_queue_size;
+        if capacity == 0 {
+            return;
+        }
+        if self.tpu_state.pin_events.len() >= capacity {
+            self.tpu_state.pin_events_overflowed = true;
+            return;
+        }
+        self.tpu_state.pin_events.push_back(PinEvent {
+            cycle: self.tpu_state.cycle_count,
+            pin,
+            level,
+        });
+    }
+
     /// Set an analog pin value
     /// If the pin is configured as an input, this function does nothing
     fn set_analog_pin(&mut self, pin: AnalogPin, value: u16) {
@@ -512,7 +1490,22 @@ impl TPU {
             return;
         }
         // Pin is an output, set the value
-        self.tpu_state.analog_pins[pin as usize] = value;
+        let value = value.min(self.analog_max());
+        let old = core::mem::replace(&mut self.tpu_state.analog_pins[pin as usize], value);
+        self.notify_pin_change(Pin::Analog(pin), old, value);
+    }
+
+    /// The bits of a digital pin word that are pins, pin 0 is bit 0
+    pub const fn digital_pin_mask() -> u16 {
+        u16::MAX >> (16 - DigitalPin::COUNT)
+    }
+
+    /// The highest value an analog pin can hold at the configured resolution
+    pub fn analog_max(&self) -> u16 {
+        let bits = self.tpu_state.config.analog_resolution_bits as u32;
+        u16::MAX
+            .checked_shr(16u32.saturating_sub(bits))
+            .unwrap_or(0)
     }
 
     /// Get an analog input value
@@ -521,7 +1514,8 @@ impl TPU {
     }
 
     /// Set a digital pin value
-    /// If the pin is configured as an input, this function does nothing
+    /// If the pin is configured as an input, this function does nothing.
+    /// Writing an output takes it out of PWM mode.
     fn set_digital_pin(&mut self, pin: DigitalPin, value: bool) {
         // Check if the pin is configured as an input (true)
         if self.tpu_state.digital_pin_config[pin as usize] {
@@ -529,9 +1523,12 @@ impl TPU {
             return;
         }
         // Pin is an output, set the value
-        self.tpu_state.digital_pins[pin as usize] = value;
+        self.tpu_state.pwm[pin as usize].enabled = false;
+        let old = core::mem::replace(&mut self.tpu_state.digital_pins[pin as usize], value);
+        self.notify_pin_change(Pin::Digital(pin), old as u16, value as u16);
     }
 
+    /// Write every output pin from a word, bits above `TPU::digital_pin_mask` are ignored
     pub fn set_digital_pins(&mut self, word: u16) {
         // Apply the word to the digital pins
         for pin in DigitalPin::iter() {
@@ -540,13 +1537,14 @@ impl TPU {
         }
     }
 
+    /// Every pin as a word, bits above `TPU::digital_pin_mask` are always 0
     pub fn get_digital_pins(&self) -> u16 {
         // Get the current digital pin values
         let mut word = 0;
         for pin in DigitalPin::iter() {
             word |= (self.get_digital_pin(pin) as u16) << pin as u16;
         }
-        word
+        word & Self::digital_pin_mask()
     }
 
     /// Get a digital input value
@@ -556,7 +1554,19 @@ impl TPU {
 
     /// Read a byte from RAM
     pub fn read_ram(&self, address: usize) -> u16 {
+        if self.tpu_state.config.memory_mapped_io {
+            if let Some(pin) = TPU::mmio_analog_pin(address) {
+                return self.get_analog_pin(pin);
+            }
+            if address == TPU::MMIO_DIGITAL_WORD {
+                return self.get_digital_pins();
+            }
+        }
+
         if address < self.tpu_state.ram.len() {
+            if let Some(stats) = &self.ram_stats {
+                stats.record_read(address);
+            }
             self.tpu_state.ram[address]
         } else {
             0
@@ -570,31 +1580,119 @@ impl TPU {
 
     /// Write a byte to RAM
     fn write_ram(&mut self, address: usize, value: u16) {
+        if self.tpu_state.config.memory_mapped_io {
+            // Writes behave like APW/DPWW, so input pins are left alone
+            if let Some(pin) = TPU::mmio_analog_pin(address) {
+                self.set_analog_pin(pin, value);
+                return;
+            }
+            if address == TPU::MMIO_DIGITAL_WORD {
+                self.set_digital_pins(value);
+                return;
+            }
+        }
+
         if address < self.tpu_state.ram.len() {
+            if let Some(stats) = &mut self.ram_stats {
+                stats.record_write(address);
+            }
             self.tpu_state.ram[address] = value;
+            self.ram_write = Some((address, value));
         }
     }
 
+    /// Start counting reads and writes of each RAM address, any previous counts are discarded
+    pub fn enable_ram_stats(&mut self) {
+        self.ram_stats = Some(Box::new(RamStats::new(TPU::RAM_SIZE)));
+    }
+
+    /// The RAM access counters, if enabled with `enable_ram_stats`
+    pub fn ram_stats(&self) -> Option<&RamStats> {
+        self.ram_stats.as_deref()
+    }
+
+    /// Is this RAM address inside the configured read-only range?
+    fn ram_write_protected(&self, address: usize) -> bool {
+        self.tpu_state
+            .config
+            .read_only_ram
+            .as_ref()
+            .is_some_and(|range| range.contains(&address))
+    }
+
+    /// Load a block of words into RAM starting at `address`, e.g. a table prepared by the host.
+    /// This bypasses write protection and memory mapped I/O, words beyond the end of RAM are ignored.
+    pub fn write_ram_slice(&mut self, address: usize, data: &[u16]) {
+        let Some(ram) = self.tpu_state.ram.get_mut(address..) else {
+            return;
+        };
+        let length = data.len().min(ram.len());
+        ram[..length].copy_from_slice(&data[..length]);
+    }
+
+    /// Translate a memory mapped I/O address into the analog pin it aliases
+    fn mmio_analog_pin(address: usize) -> Option<AnalogPin> {
+        let index = address.checked_sub(TPU::MMIO_ANALOG_BASE)?;
+        AnalogPin::from_repr(u16::try_from(index).ok()?)
+    }
+
+    /// Get the contents of the non-volatile memory, e.g. to persist it between runs
+    pub fn nvram(&self) -> &[u16] {
+        &self.tpu_state.nvram
+    }
+
+    /// Load the non-volatile memory from a previously saved image.
+    /// Words beyond the NVRAM size are ignored, a shorter image leaves the remaining words untouched.
+    pub fn set_nvram(&mut self, data: &[u16]) {
+        let length = data.len().min(self.tpu_state.nvram.len());
+        self.tpu_state.nvram[..length].copy_from_slice(&data[..length]);
+    }
+
     pub fn read_rom(&self) -> &Vec<Rc<Instruction>> {
         &self.tpu_state.rom
     }
 
-    /// Send a packet
-    fn send_packet(&mut self, address: u16, data: u16) {
-        self.tpu_state.outgoing_packets.push_back(NetPacket {
-            sender: self.tpu_state.network_address,
-            target: address,
-            data,
-        });
+    /// Send a packet as it is, one to our own address goes straight to the incoming queue
+    fn send_packet(&mut self, packet: NetPacket) {
+        if packet.target == self.tpu_state.network_address {
+            self.tpu_state.incoming_packets.push_back(packet);
+        } else {
+            self.tpu_state.outgoing_packets.push_back(packet);
+        }
+    }
+
+    /// Change the network address, as NETA does
+    pub fn set_network_address(&mut self, address: u16) {
+        self.tpu_state.network_address = address;
+    }
+
+    /// Take the oldest packet waiting to be sent, for the network to deliver
+    pub fn take_outgoing_packet(&mut self) -> Option<NetPacket> {
+        self.tpu_state.outgoing_packets.pop_front()
+    }
+
+    /// Put a packet from the network into the incoming buffer, false if the buffer is full and it was dropped
+    pub fn deliver_packet(&mut self, packet: NetPacket) -> bool {
+        if self.tpu_state.incoming_packets.len() >= TPU::NET_BUFFER_SIZE {
+            return false;
+        }
+        self.tpu_state.incoming_packets.push_back(packet);
+        true
     }
 
     /// Receive a packet, if one is available
-    /// Returns 0 if no packet is available
-    fn receive_packet(&mut self) -> NetPacket {
-        self.tpu_state
+    fn receive_packet(&mut self) -> Option<NetPacket> {
+        self.tpu_state.incoming_packets.pop_front()
+    }
+
+    /// Receive the oldest packet on a port, if one is available
+    fn receive_packet_on_port(&mut self, port: u8) -> Option<NetPacket> {
+        let index = self
+            .tpu_state
             .incoming_packets
-            .pop_front()
-            .unwrap_or_default()
+            .iter()
+            .position(|packet| packet.port == port)?;
+        self.tpu_state.incoming_packets.remove(index)
     }
 
     /// Get the current stack pointer (size of the stack)
@@ -602,17 +1700,37 @@ impl TPU {
         self.tpu_state.stack.len() as u16
     }
 
+    /// Packets FWD dropped because their hop limit ran out, since the last reset
+    pub fn ttl_drops(&self) -> u64 {
+        self.tpu_state.ttl_drops
+    }
+
+    /// Bytes waiting for the UART to send them
+    pub fn uart_tx_fifo(&self) -> &VecDeque<u8> {
+        &self.tpu_state.uart_tx
+    }
+
+    /// Bytes the UART received that URX hasn't taken yet
+    pub fn uart_rx_fifo(&self) -> &VecDeque<u16> {
+        &self.tpu_state.uart_rx
+    }
+
+    /// Get the deepest the stack has been since the last reset, SCR does not lower it
+    pub fn stack_high_water(&self) -> usize {
+        self.tpu_state.stack_high_water
+    }
+
     // Misc operations
-    fn op_nop() -> ExecuteResult {
+    fn op_nop(_: &mut TPU) -> ExecuteResult {
         // Sleep is handled by the wait_cycles mechanism
         // No additional action needed here
         ExecuteResult::PCAdvance
     }
 
-    fn op_slp(&mut self, value: &OperandValueType) -> ExecuteResult {
+    fn op_slp(tpu: &mut TPU, value: &OperandValueType) -> ExecuteResult {
         // Get the sleep duration
-        let delay = TPU::check_operand_cost(&[value]).saturating_add(self.get_operand_value(value));
-        self.tpu_state.execution_state.wait_cycles = delay;
+        let delay = TPU::check_operand_cost(&[value]).saturating_add(tpu.get_operand_value(value));
+        tpu.tpu_state.execution_state.wait_cycles = delay;
         ExecuteResult::PCAdvance
     }
 
@@ -651,7 +1769,49 @@ impl TPU {
         }
     }
 
-    fn op_hlt() -> ExecuteResult {
+    /// WRXT is decoded with the longest possible wait, `op_wrxt` finishes early on a packet or the timeout
+    const WRXT_DECODE_CYCLES: u16 = u16::MAX;
+
+    fn op_wrxt(tpu: &mut TPU, timeout: &OperandValueType, wait_cycles: u16) -> ExecuteResult {
+        if !tpu.tpu_state.incoming_packets.is_empty() {
+            io_matrix::op_recv(tpu);
+            return ExecuteResult::PCAdvance;
+        }
+
+        // Keep waiting until the timeout has elapsed
+        let elapsed = TPU::WRXT_DECODE_CYCLES - wait_cycles;
+        let cost =
+            TPU::check_operand_cost(&[timeout]).saturating_add(tpu.get_operand_value(timeout));
+        if elapsed < cost {
+            return ExecuteResult::NoPCAdvance;
+        }
+
+        // Timed out, RECV on the empty queue marks X with the broadcast address
+        io_matrix::op_recv(tpu);
+        ExecuteResult::PCAdvance
+    }
+
+    fn decode_op_wrxt() -> DecodeResult {
+        DecodeResult {
+            cycles: TPU::WRXT_DECODE_CYCLES, // The cost depends on the timeout, see op_wrxt
+            call_every_cycle: true,
+        }
+    }
+
+    fn op_rst(tpu: &mut TPU) -> ExecuteResult {
+        // Reset puts the program counter back to the first line
+        tpu.reset();
+        ExecuteResult::PCModified
+    }
+
+    fn decode_op_rst() -> DecodeResult {
+        DecodeResult {
+            cycles: 4,
+            call_every_cycle: false,
+        }
+    }
+
+    fn op_hlt(_: &mut TPU) -> ExecuteResult {
         ExecuteResult::Halt(HaltReason::HLTOpcode)
     }
 
@@ -663,11 +1823,66 @@ impl TPU {
     }
 }
 
-pub fn create_basic_tpu_config<'t>(program: Vec<Rc<Instruction>>) -> TPU {
-    TPU::new(
-        0x1,
-        [false; AnalogPin::COUNT],
-        [false; DigitalPin::COUNT],
-        program,
-    )
+/// What happened on a tick, see `TPU::ticks`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TickSummary {
+    /// The value of `TpuState::cycle_count` after the tick
+    pub cycle: u64,
+    /// The program counter after the tick
+    pub pc: usize,
+    /// The address of the instruction that finished on the tick and the instruction, if one did
+    pub retired: Option<(usize, Rc<Instruction>)>,
+    pub halted: bool,
+    /// The next tick fetches the instruction at a breakpoint, the iterator ends here
+    pub at_breakpoint: bool,
+}
+
+/// Ticks a TPU as it's iterated, see `TPU::ticks`
+pub struct Ticks<'a> {
+    tpu: &'a mut TPU,
+    done: bool,
+}
+
+impl Iterator for Ticks<'_> {
+    type Item = TickSummary;
+
+    fn next(&mut self) -> Option<TickSummary> {
+        if self.done || self.tpu.tpu_state.halted {
+            return None;
+        }
+        self.tpu.tick();
+        let state = &self.tpu.tpu_state;
+        let at_breakpoint = !state.halted && self.tpu.at_breakpoint();
+        self.done = state.halted || at_breakpoint;
+        Some(TickSummary {
+            cycle: state.cycle_count,
+            pc: state.program_counter,
+            retired: self.tpu.retired.and_then(|pc| {
+                state
+                    .rom
+                    .get(pc)
+                    .map(|instruction| (pc, instruction.clone()))
+            }),
+            halted: state.halted,
+            at_breakpoint,
+        })
+    }
+}
+
+/// Runs a TPU an instruction at a time as it's iterated, see `TPU::instructions`
+pub struct Retired<'a> {
+    ticks: Ticks<'a>,
+}
+
+impl Iterator for Retired<'_> {
+    type Item = (usize, Rc<Instruction>);
+
+    fn next(&mut self) -> Option<(usize, Rc<Instruction>)> {
+        self.ticks.find_map(|tick| tick.retired)
+    }
+}
+
+/// A TPU at address 0x1 with every pin an output, see `TpuBuilder`. The program may be empty, to load one later.
+pub fn create_basic_tpu_config(program: Vec<Rc<Instruction>>) -> TPU {
+    TpuBuilder::new().program(program).build_unchecked()
 }
@@ -0,0 +1,180 @@
+use crate::shared::DigitalPin;
+use crate::tpu::PinEvent;
+use crate::tpu::peripheral::{Peripheral, PinBus};
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::ops::RangeInclusive;
+
+/// When vehicles arrive at a `DetectorSim`
+#[derive(Clone, Debug)]
+pub enum Arrivals {
+    /// A vehicle on each of these cycles
+    Schedule(Vec<u64>),
+    /// A vehicle every `period` cycles, each one up to `jitter` cycles early or late
+    Periodic { period: u64, jitter: u64 },
+    /// A vehicle on any cycle with a chance of one in `one_in`, which is close to a Poisson process
+    Random { one_in: u32 },
+}
+
+/// Vehicles passing over loop detectors, for testing actuated control against realistic calls.
+///
+/// Each pin is a lane of its own, fed by the same arrival model. A vehicle holds its lane's digital input high for
+/// a number of cycles picked from the presence range, a vehicle arriving while the lane is occupied extends it.
+/// Random choices come from a seeded generator so a run can be repeated. Every edge driven onto the pins is logged,
+/// the host reads the log through the handle from `DetectorSim::edges`. A clone of the peripheral shares its handle.
+#[derive(Clone, Debug)]
+pub struct DetectorSim {
+    lanes: Vec<Lane>,
+    arrivals: Arrivals,
+    presence: RangeInclusive<u64>,
+    rng: u32,
+    edges: DetectorEdges,
+}
+
+/// A lane of a `DetectorSim`
+#[derive(Clone, Copy, Debug)]
+struct Lane {
+    pin: DigitalPin,
+    /// The cycle the next vehicle arrives on, for the schedule and periodic models
+    next_arrival: Option<u64>,
+    /// Index of the next arrival in the schedule, or the cycle a periodic arrival is due before jitter
+    next_nominal: u64,
+    /// The first cycle the lane is clear again
+    occupied_until: u64,
+    level: bool,
+}
+
+/// The edges a `DetectorSim` has driven, oldest first
+#[derive(Clone, Debug, Default)]
+pub struct DetectorEdges(Rc<RefCell<Vec<PinEvent>>>);
+
+impl DetectorEdges {
+    pub fn events(&self) -> Vec<PinEvent> {
+        self.0.borrow().clone()
+    }
+
+    /// Vehicles seen so far across all lanes
+    pub fn vehicles(&self) -> usize {
+        self.0.borrow().iter().filter(|event| event.level).count()
+    }
+}
+
+impl DetectorSim {
+    /// Detectors on `pins`, each vehicle present for between the ends of `presence` cycles, at least 1.
+    /// The generator starts from `seed`.
+    pub fn new(
+        pins: Vec<DigitalPin>,
+        arrivals: Arrivals,
+        presence: RangeInclusive<u64>,
+        seed: u32,
+    ) -> Self {
+        let mut sim = Self {
+            lanes: Vec::new(),
+            arrivals,
+            presence,
+            // Xorshift never leaves 0
+            rng: seed.max(1),
+            edges: DetectorEdges::default(),
+        };
+        sim.lanes = pins
+            .into_iter()
+            .map(|pin| {
+                let mut lane = Lane {
+                    pin,
+                    next_arrival: None,
+                    next_nominal: 0,
+                    occupied_until: 0,
+                    level: false,
+                };
+                sim.schedule_next(&mut lane);
+                lane
+            })
+            .collect();
+        sim
+    }
+
+    /// A handle to read the edge log once the simulator is attached to a TPU
+    pub fn edges(&self) -> DetectorEdges {
+        self.edges.clone()
+    }
+
+    /// Work out when the lane's next vehicle arrives, the random model decides each cycle instead
+    fn schedule_next(&mut self, lane: &mut Lane) {
+        match &self.arrivals {
+            Arrivals::Schedule(cycles) => {
+                lane.next_arrival = cycles.get(lane.next_nominal as usize).copied();
+                lane.next_nominal += 1;
+            }
+            &Arrivals::Periodic { period, jitter } => {
+                lane.next_nominal += period.max(1);
+                let offset = self.next_random() as u64 % (jitter * 2 + 1);
+                lane.next_arrival = Some((lane.next_nominal + offset).saturating_sub(jitter));
+            }
+            Arrivals::Random { .. } => {}
+        }
+    }
+
+    /// Whether a vehicle arrives in the lane this cycle
+    fn arrives(&mut self, lane: &mut Lane, cycle: u64) -> bool {
+        if let Arrivals::Random { one_in } = self.arrivals {
+            return one_in != 0 && self.next_random().is_multiple_of(one_in);
+        }
+
+        let mut arrived = false;
+        // A jittered arrival can land before the cycle the lane was last checked on, it arrives straight away
+        while lane.next_arrival.is_some_and(|arrival| arrival <= cycle) {
+            arrived = true;
+            self.schedule_next(lane);
+        }
+        arrived
+    }
+
+    /// Cycles the next vehicle is present for
+    fn presence(&mut self) -> u64 {
+        let (shortest, longest) = (*self.presence.start(), *self.presence.end());
+        let span = longest.saturating_sub(shortest).saturating_add(1);
+        (shortest + self.next_random() as u64 % span).max(1)
+    }
+
+    /// Xorshift32
+    fn next_random(&mut self) -> u32 {
+        let mut x = self.rng;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.rng = x;
+        x
+    }
+}
+
+impl Peripheral for DetectorSim {
+    fn tick(&mut self, io: &mut PinBus) {
+        let cycle = io.cycle();
+        let mut lanes = core::mem::take(&mut self.lanes);
+
+        for lane in &mut lanes {
+            if self.arrives(lane, cycle) {
+                let until = cycle + self.presence();
+                lane.occupied_until = lane.occupied_until.max(until);
+            }
+
+            let level = lane.occupied_until > cycle;
+            if level != lane.level && io.drive_digital(lane.pin, level).is_ok() {
+                lane.level = level;
+                self.edges.0.borrow_mut().push(PinEvent {
+                    cycle,
+                    pin: lane.pin,
+                    level,
+                });
+            }
+        }
+
+        self.lanes = lanes;
+    }
+
+    fn clone_box(&self) -> Box<dyn Peripheral> {
+        Box::new(self.clone())
+    }
+}
@@ -0,0 +1,60 @@
+use crate::shared::DigitalPin;
+use crate::tpu::peripheral::{Peripheral, PinBus};
+use alloc::boxed::Box;
+
+/// A lamp with failure detection.
+///
+/// The lamp is driven from a digital output. Switching it again sooner than `min_period` cycles after the last switch
+/// damages it, and from then on the fault input is held high.
+#[derive(Clone, Debug)]
+pub struct FaultLamp {
+    drive: DigitalPin,
+    fault: DigitalPin,
+    min_period: u64,
+    level: bool,
+    last_switch: Option<u64>,
+    faulted: bool,
+}
+
+impl FaultLamp {
+    pub fn new(drive: DigitalPin, fault: DigitalPin, min_period: u64) -> Self {
+        Self {
+            drive,
+            fault,
+            min_period,
+            level: false,
+            last_switch: None,
+            faulted: false,
+        }
+    }
+
+    /// Whether the lamp has been damaged
+    pub fn faulted(&self) -> bool {
+        self.faulted
+    }
+}
+
+impl Peripheral for FaultLamp {
+    fn tick(&mut self, io: &mut PinBus) {
+        let level = io.digital(self.drive);
+        if level != self.level {
+            let cycle = io.cycle();
+            if let Some(last) = self.last_switch
+                && cycle.wrapping_sub(last) < self.min_period
+            {
+                self.faulted = true;
+            }
+            self.level = level;
+            self.last_switch = Some(cycle);
+        }
+
+        // The fault line is only wired up if the TPU has it as an input
+        if self.faulted {
+            let _ = io.drive_digital(self.fault, true);
+        }
+    }
+
+    fn clone_box(&self) -> Box<dyn Peripheral> {
+        Box::new(self.clone())
+    }
+}
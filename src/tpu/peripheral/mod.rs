@@ -0,0 +1,83 @@
+pub mod detector;
+pub mod fault_lamp;
+#[cfg(test)]
+mod peripheral_test;
+pub mod seven_segment;
+pub mod shift_register;
+pub mod signal;
+pub mod uart;
+
+use crate::shared::{AnalogPin, DigitalPin};
+use crate::tpu::{PinError, TPU};
+use alloc::boxed::Box;
+
+/// A simulated device wired to a TPU's pins, see `TPU::attach_peripheral`.
+///
+/// Peripherals are kept out of `TpuState`, so like the RAM access counters they are not part of a snapshot, a TPU
+/// made with `TPU::new_from_state` has none attached. Cloning a TPU clones its peripherals along with it.
+pub trait Peripheral {
+    /// Called once per TPU tick, after the instruction for that tick has run
+    fn tick(&mut self, io: &mut PinBus);
+
+    /// A copy of the peripheral, so a TPU with peripherals attached can be cloned
+    fn clone_box(&self) -> Box<dyn Peripheral>;
+}
+
+impl Clone for Box<dyn Peripheral> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+/// A peripheral's view of the TPU's pins, it can read any pin but only drive inputs
+pub struct PinBus<'a> {
+    tpu: &'a mut TPU,
+}
+
+impl<'a> PinBus<'a> {
+    pub(crate) fn new(tpu: &'a mut TPU) -> Self {
+        Self { tpu }
+    }
+
+    /// Cycles the TPU has run
+    pub fn cycle(&self) -> u64 {
+        self.tpu.tpu_state.cycle_count
+    }
+
+    /// Current level of a digital pin
+    pub fn digital(&self, pin: DigitalPin) -> bool {
+        self.tpu.get_digital_pin(pin)
+    }
+
+    /// Current value of an analog pin
+    pub fn analog(&self, pin: AnalogPin) -> u16 {
+        self.tpu.get_analog_pin(pin)
+    }
+
+    /// Drive a digital input, the same as `TPU::drive_digital_input`
+    pub fn drive_digital(&mut self, pin: DigitalPin, level: bool) -> Result<(), PinError> {
+        self.tpu.drive_digital_input(pin, level)
+    }
+
+    /// Drive an analog input, the same as `TPU::drive_analog_input`
+    pub fn drive_analog(&mut self, pin: AnalogPin, value: u16) -> Result<(), PinError> {
+        self.tpu.drive_analog_input(pin, value)
+    }
+
+    /// Set a digital output, for the TPU's own peripherals that own a pin such as `Uart`
+    pub(crate) fn drive_output(&mut self, pin: DigitalPin, level: bool) {
+        self.tpu.set_digital_pin(pin, level);
+    }
+
+    /// Take the next byte UTX queued for the UART to send
+    pub(crate) fn take_uart_tx(&mut self) -> Option<u8> {
+        self.tpu.tpu_state.uart_tx.pop_front()
+    }
+
+    /// Hand a byte the UART received to URX, it's lost if the FIFO is full
+    pub(crate) fn push_uart_rx(&mut self, word: u16) {
+        if self.tpu.tpu_state.uart_rx.len() < TPU::UART_FIFO_SIZE {
+            self.tpu.tpu_state.uart_rx.push_back(word);
+        }
+    }
+}
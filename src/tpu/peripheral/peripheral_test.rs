@@ -0,0 +1,483 @@
+use crate::rgal::parse_program;
+use crate::shared::{AnalogPin, DigitalPin, OperandValueType, Register};
+use crate::tpu::io_matrix::op_utx;
+use crate::tpu::peripheral::detector::{Arrivals, DetectorSim};
+use crate::tpu::peripheral::seven_segment::SevenSegment;
+use crate::tpu::peripheral::shift_register::ShiftRegister;
+use crate::tpu::peripheral::signal::{
+    Aspect, ConflictMonitor, SignalFault, SignalFaults, SignalHead, SignalHistory,
+};
+use crate::tpu::peripheral::uart::Uart;
+use crate::tpu::{PinEvent, TPU};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tpu::peripheral::seven_segment::SevenSegmentDisplay;
+    #[cfg(feature = "tui")]
+    use ratatui::{buffer::Buffer, layout::Rect, widgets::Widget};
+    use strum::{EnumCount, IntoEnumIterator};
+
+    const CYCLES_PER_BIT: u16 = 8;
+
+    /// Sends the bytes 0x48 0x69 0x00 0xFF 0xA5 0x5A, retrying while the FIFO is full
+    const UART_SENDER: &str = r#"UTX 0x48
+    BCS 0
+    UTX 0x69
+    BCS 2
+    UTX 0x00
+    BCS 4
+    UTX 0xFF
+    BCS 6
+    UTX 0xA5
+    BCS 8
+    UTX 0x5A
+    BCS 10
+    HLT"#;
+
+    /// Stores 6 received bytes from 0x20
+    const UART_RECEIVER: &str = r#"LDR X, 0
+    URX R0
+    BEQ 1, R0, 0xFFFF
+    SMOI 0x20, R0, X
+    BNE 1, X, 6
+    HLT"#;
+
+    /// Clocks the 16 bits of A out to a shift register, highest bit first, with data on pin 0 and the clock on
+    /// pin 1. Line 0 jumps over it, call it with JSR 1 and pulse the latch on pin 2 to update the outputs.
+    const SHIFT_OUT: &str = r#"JMP 9
+    LDR R1, 16
+    SLR R0, A, 15
+    DPW 0, R0
+    DPW 1, 1
+    DPW 1, 0
+    SLL A, A, 1
+    DJNZ 2, R1
+    RTS"#;
+
+    /// Two phases, each green, amber, then all red, twice. Head 0 is on pins 0 to 2 and head 1 on pins 3 to 5
+    const TWO_PHASE: &str = r#"LDR R1, 2
+    DPWW 0x0C
+    LDR R0, 20
+    DJNZ 3, R0
+    DPWW 0x0A
+    LDR R0, 5
+    DJNZ 6, R0
+    DPWW 0x09
+    DPWW 0x21
+    LDR R0, 20
+    DJNZ 10, R0
+    DPWW 0x11
+    LDR R0, 5
+    DJNZ 13, R0
+    DPWW 0x09
+    DJNZ 1, R1
+    HLT"#;
+
+    /// Gives head 1 green while head 0 still has it, then reads the fault input into R2
+    const CONFLICTING_GREENS: &str = r#"DPWW 0x0C
+    LDR R0, 20
+    DJNZ 2, R0
+    DPWW 0x24
+    LDR R0, 5
+    DJNZ 5, R0
+    DPR R2, 6
+    HLT"#;
+
+    /// A TPU with a UART sending on pin 0 and receiving on pin 1
+    fn create_tpu_with_uart(program: &str) -> TPU {
+        let mut digital_config = [false; DigitalPin::COUNT];
+        digital_config[DigitalPin::Digital1 as usize] = true;
+        let mut tpu = TPU::new(
+            0x1,
+            [false; AnalogPin::COUNT],
+            digital_config,
+            parse_program(program).expect("parse failure"),
+        );
+        tpu.attach_peripheral(Box::new(Uart::new(
+            DigitalPin::Digital0,
+            DigitalPin::Digital1,
+            CYCLES_PER_BIT,
+        )));
+        tpu
+    }
+
+    /// Drive a frame onto the receive pin one bit at a time, the start bit first
+    fn drive_frame(tpu: &mut TPU, bits: &[bool]) {
+        for &bit in bits {
+            for _ in 0..CYCLES_PER_BIT {
+                tpu.drive_digital_input(DigitalPin::Digital1, bit).unwrap();
+                tpu.tick();
+            }
+        }
+    }
+
+    #[test]
+    fn test_uart_pin_to_pin() {
+        let mut sender = create_tpu_with_uart(UART_SENDER);
+        let mut receiver = create_tpu_with_uart(UART_RECEIVER);
+
+        for _ in 0..2000 {
+            if receiver.halted() {
+                break;
+            }
+            sender.tick();
+            receiver.tick();
+            // The sender's transmit pin is wired to the receiver's receive pin
+            let level = sender.tpu_state.digital_pins[DigitalPin::Digital0 as usize];
+            receiver
+                .drive_digital_input(DigitalPin::Digital1, level)
+                .unwrap();
+        }
+
+        assert!(sender.halted());
+        assert!(receiver.halted());
+        assert!(sender.uart_tx_fifo().is_empty());
+        assert!(receiver.uart_rx_fifo().is_empty());
+        let received: Vec<u16> = (0x20..0x26)
+            .map(|address| receiver.read_ram(address))
+            .collect();
+        assert_eq!(received, vec![0x48, 0x69, 0x00, 0xFF, 0xA5, 0x5A]);
+    }
+
+    #[test]
+    fn test_uart_fifo_full() {
+        let mut tpu = create_tpu_with_uart("HLT");
+        for byte in 0..TPU::UART_FIFO_SIZE as u16 + 1 {
+            tpu.write_register(Register::A, byte);
+            op_utx(&mut tpu, &OperandValueType::Register(Register::A));
+        }
+        assert!(tpu.tpu_state.flags.carry);
+        assert_eq!(tpu.uart_tx_fifo().len(), TPU::UART_FIFO_SIZE);
+        assert_eq!(tpu.uart_tx_fifo().back(), Some(&3));
+    }
+
+    #[test]
+    fn test_uart_framing_error() {
+        let mut tpu = create_tpu_with_uart("HLT");
+        tpu.tick();
+
+        // Idle, then 0x41 with a good stop bit, then 0x41 with a low stop bit
+        let data = [true, false, false, false, false, false, true, false];
+        for stop in [true, false] {
+            let mut frame = vec![true, false];
+            frame.extend(data);
+            frame.push(stop);
+            drive_frame(&mut tpu, &frame);
+        }
+        drive_frame(&mut tpu, &[true]);
+
+        let received: Vec<u16> = tpu.uart_rx_fifo().iter().copied().collect();
+        assert_eq!(received, vec![0x41, 0x41 | Uart::FRAMING_ERROR]);
+    }
+
+    #[test]
+    fn test_shift_register() {
+        let run = |latch: bool| {
+            let latch = if latch { "DPW 2, 1\n DPW 2, 0" } else { "NOP" };
+            let program = format!("{SHIFT_OUT}\nLDR A, 0xA5C3\nJSR 1\n{latch}\nHLT");
+            let mut tpu = TPU::new(
+                0x1,
+                [false; AnalogPin::COUNT],
+                [false; DigitalPin::COUNT],
+                parse_program(&program).expect("parse failure"),
+            );
+            let expander = ShiftRegister::new(
+                DigitalPin::Digital0,
+                DigitalPin::Digital1,
+                DigitalPin::Digital2,
+                16,
+            );
+            let outputs = expander.outputs();
+            tpu.attach_peripheral(Box::new(expander));
+
+            while !tpu.halted() {
+                tpu.tick();
+            }
+            outputs
+        };
+
+        let outputs = run(true);
+        assert_eq!(outputs.word(), 0xA5C3);
+        assert!(outputs.output(0));
+        assert!(!outputs.output(2));
+        assert!(outputs.output(15));
+
+        // Shifted but never latched
+        assert_eq!(run(false).word(), 0);
+    }
+
+    /// A TPU showing segments on pins 0 to 6 on the digit selected by analog pin 0
+    fn create_tpu_with_display(program: &str, digits: usize) -> (TPU, SevenSegmentDisplay) {
+        let mut tpu = TPU::new(
+            0x1,
+            [false; AnalogPin::COUNT],
+            [false; DigitalPin::COUNT],
+            parse_program(program).expect("parse failure"),
+        );
+        let segments: Vec<DigitalPin> = DigitalPin::iter().take(7).collect();
+        let display = SevenSegment::new(segments.try_into().unwrap(), AnalogPin::Analog0, digits);
+        let handle = display.display();
+        tpu.attach_peripheral(Box::new(display));
+        (tpu, handle)
+    }
+
+    #[test]
+    fn test_seven_segment_counting() {
+        // Shows 0 to 9 from a table of segment patterns
+        let (mut tpu, display) = create_tpu_with_display(
+            r#"LDR X, 0
+            LDO A, 0x40, X
+            DPWW A
+            INC X
+            BNE 1, X, 10
+            HLT"#,
+            1,
+        );
+        tpu.write_ram_slice(
+            0x40,
+            &[0x3F, 0x06, 0x5B, 0x4F, 0x66, 0x6D, 0x7D, 0x07, 0x7F, 0x6F],
+        );
+
+        let mut shown = vec![display.text()];
+        while !tpu.halted() {
+            tpu.tick();
+            if shown.last() != Some(&display.text()) {
+                shown.push(display.text());
+            }
+        }
+        assert_eq!(
+            shown,
+            [" ", "0", "1", "2", "3", "4", "5", "6", "7", "8", "9"]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "tui")]
+    fn test_seven_segment_widget() {
+        // 1 and 2 on the first two digits, segments a and d together aren't a digit
+        let (mut tpu, display) = create_tpu_with_display(
+            r#"DPWW 0x06
+            APW 0, 1
+            DPWW 0x5B
+            APW 0, 2
+            DPWW 0x09
+            HLT"#,
+            4,
+        );
+        while !tpu.halted() {
+            tpu.tick();
+        }
+        assert_eq!(display.text(), "12? ");
+
+        let area = Rect::new(0, 0, 16, 3);
+        let mut buffer = Buffer::empty(area);
+        (&display).render(area, &mut buffer);
+        assert_eq!(
+            buffer,
+            Buffer::with_lines(vec![
+                "     _          ",
+                "  |  _|  ?      ",
+                "  | |_          ",
+            ])
+        );
+    }
+
+    /// A TPU with two signal heads that conflict, the monitor holds pin 6 high on a fault
+    fn create_tpu_with_signals(program: &str) -> (TPU, SignalHistory, SignalFaults) {
+        let mut digital_config = [false; DigitalPin::COUNT];
+        digital_config[DigitalPin::Digital6 as usize] = true;
+        let mut tpu = TPU::new(
+            0x1,
+            [false; AnalogPin::COUNT],
+            digital_config,
+            parse_program(program).expect("parse failure"),
+        );
+
+        let heads = vec![
+            SignalHead::new(
+                DigitalPin::Digital0,
+                DigitalPin::Digital1,
+                DigitalPin::Digital2,
+            ),
+            SignalHead::new(
+                DigitalPin::Digital3,
+                DigitalPin::Digital4,
+                DigitalPin::Digital5,
+            ),
+        ];
+        let history = heads[0].history();
+        tpu.attach_peripheral(Box::new(heads[0].clone()));
+        let monitor =
+            ConflictMonitor::new(heads, vec![(0, 1)], 10).with_fault_pin(DigitalPin::Digital6);
+        let faults = monitor.faults();
+        tpu.attach_peripheral(Box::new(monitor));
+        (tpu, history, faults)
+    }
+
+    #[test]
+    fn test_conflict_monitor_clean() {
+        let (mut tpu, history, faults) = create_tpu_with_signals(TWO_PHASE);
+        while !tpu.halted() {
+            tpu.tick();
+        }
+
+        assert!(faults.is_empty());
+        assert!(!tpu.tpu_state.digital_pins[DigitalPin::Digital6 as usize]);
+        let aspects: Vec<Aspect> = history
+            .changes()
+            .iter()
+            .map(|&(_, aspect)| aspect)
+            .collect();
+        assert_eq!(
+            aspects,
+            [
+                Aspect::Dark,
+                Aspect::Green,
+                Aspect::Amber,
+                Aspect::Red,
+                Aspect::Green,
+                Aspect::Amber,
+                Aspect::Red,
+            ]
+        );
+        assert_eq!(history.current(), Aspect::Red);
+    }
+
+    #[test]
+    fn test_conflict_monitor_conflicting_greens() {
+        let (mut tpu, _, faults) = create_tpu_with_signals(CONFLICTING_GREENS);
+
+        // The cycle both greens first appear on the pins
+        let mut violation = None;
+        while !tpu.halted() {
+            tpu.tick();
+            let pins = tpu.tpu_state.digital_pins;
+            if violation.is_none()
+                && pins[DigitalPin::Digital2 as usize]
+                && pins[DigitalPin::Digital5 as usize]
+            {
+                violation = Some(tpu.tpu_state.cycle_count);
+            }
+        }
+
+        let cycle = violation.expect("no conflicting greens");
+        assert_eq!(
+            faults.log(),
+            [SignalFault::ConflictingGreens {
+                cycle,
+                heads: (0, 1)
+            }]
+        );
+        // The program saw the fault input go high
+        assert_eq!(tpu.read_register(Register::R2), 1);
+    }
+
+    #[test]
+    fn test_conflict_monitor_green_with_red() {
+        // Head 0 lights red and green together, which isn't a valid aspect but still conflicts with head 1's green
+        let (mut tpu, history, faults) = create_tpu_with_signals("DPWW 0x25\nNOP\nHLT");
+        while !tpu.halted() {
+            tpu.tick();
+        }
+        assert_eq!(history.current(), Aspect::Invalid);
+        assert!(matches!(
+            faults.log()[..],
+            [SignalFault::ConflictingGreens { heads: (0, 1), .. }]
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "names a head")]
+    fn test_conflict_monitor_unknown_head() {
+        let head = SignalHead::new(
+            DigitalPin::Digital0,
+            DigitalPin::Digital1,
+            DigitalPin::Digital2,
+        );
+        ConflictMonitor::new(vec![head], vec![(0, 1)], 10);
+    }
+
+    #[test]
+    fn test_conflict_monitor_dark() {
+        // Both heads go dark for longer than the monitor allows
+        let (mut tpu, _, faults) =
+            create_tpu_with_signals("DPWW 0x09\nDPWW 0\nLDR R0, 15\nDJNZ 3, R0\nHLT");
+        while !tpu.halted() {
+            tpu.tick();
+        }
+        assert!(matches!(
+            faults.log()[..],
+            [
+                SignalFault::Dark { head: 0, .. },
+                SignalFault::Dark { head: 1, .. }
+            ]
+        ));
+    }
+
+    /// The edges a detector drives onto an idle TPU over 500 ticks
+    fn detector_edges(arrivals: Arrivals, seed: u32) -> Vec<PinEvent> {
+        let mut digital_config = [false; DigitalPin::COUNT];
+        digital_config[DigitalPin::Digital0 as usize] = true;
+        digital_config[DigitalPin::Digital1 as usize] = true;
+        let mut tpu = TPU::new(
+            0x1,
+            [false; AnalogPin::COUNT],
+            digital_config,
+            parse_program("HLT").expect("parse failure"),
+        );
+        let sim = DetectorSim::new(
+            vec![DigitalPin::Digital0, DigitalPin::Digital1],
+            arrivals,
+            3..=12,
+            seed,
+        );
+        let edges = sim.edges();
+        tpu.attach_peripheral(Box::new(sim));
+        for _ in 0..500 {
+            tpu.tick();
+        }
+        edges.events()
+    }
+
+    #[test]
+    fn test_detector_sim_reproducible() {
+        let random = Arrivals::Random { one_in: 20 };
+        let edges = detector_edges(random.clone(), 42);
+        assert!(edges.len() > 10);
+        assert_eq!(edges, detector_edges(random.clone(), 42));
+        assert_ne!(edges, detector_edges(random, 43));
+
+        let periodic = Arrivals::Periodic {
+            period: 40,
+            jitter: 5,
+        };
+        let edges = detector_edges(periodic.clone(), 7);
+        assert_eq!(edges, detector_edges(periodic.clone(), 7));
+        assert_ne!(edges, detector_edges(periodic, 8));
+        // Both lanes see a vehicle about every 40 cycles
+        let vehicles = edges.iter().filter(|event| event.level).count();
+        assert!((22..=26).contains(&vehicles), "{vehicles} vehicles");
+
+        // A schedule puts each vehicle down on its cycle, present for 3 to 12 cycles
+        let edges = detector_edges(Arrivals::Schedule(vec![10, 100]), 1);
+        let rising: Vec<u64> = edges
+            .iter()
+            .filter(|event| event.level && event.pin == DigitalPin::Digital0)
+            .map(|event| event.cycle)
+            .collect();
+        assert_eq!(rising, [10, 100]);
+        for pin in [DigitalPin::Digital0, DigitalPin::Digital1] {
+            let lane: Vec<PinEvent> = edges
+                .iter()
+                .filter(|event| event.pin == pin)
+                .copied()
+                .collect();
+            assert_eq!(lane.len(), 4);
+            for pair in lane.chunks(2) {
+                assert!(pair[0].level && !pair[1].level);
+                assert!((3..=12).contains(&(pair[1].cycle - pair[0].cycle)));
+            }
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use crate::shared::{AnalogPin, DigitalPin};
+use crate::tpu::peripheral::{Peripheral, PinBus};
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+#[cfg(feature = "tui")]
+use ratatui::{buffer::Buffer, layout::Rect, style::Style, widgets::Widget};
+
+/// Segments lit for each hex digit, segment `a` in bit 0 through `g` in bit 6
+const DIGITS: [(u8, char); 16] = [
+    (0x3F, '0'),
+    (0x06, '1'),
+    (0x5B, '2'),
+    (0x4F, '3'),
+    (0x66, '4'),
+    (0x6D, '5'),
+    (0x7D, '6'),
+    (0x07, '7'),
+    (0x7F, '8'),
+    (0x6F, '9'),
+    (0x77, 'A'),
+    (0x7C, 'b'),
+    (0x39, 'C'),
+    (0x5E, 'd'),
+    (0x79, 'E'),
+    (0x71, 'F'),
+];
+
+/// A multiplexed seven segment display.
+///
+/// Seven digital outputs drive segments `a` to `g`, an analog output selects which digit they're shown on. The digit
+/// keeps its segments until it's selected again. The host reads the display through the handle from
+/// `SevenSegment::display`, which with the `tui` feature can also be rendered as a widget. A clone of the peripheral
+/// shares its handle.
+#[derive(Clone, Debug)]
+pub struct SevenSegment {
+    segments: [DigitalPin; 7],
+    select: AnalogPin,
+    display: SevenSegmentDisplay,
+}
+
+/// The host's view of a `SevenSegment`
+#[derive(Clone, Debug)]
+pub struct SevenSegmentDisplay(Rc<RefCell<Vec<u8>>>);
+
+impl SevenSegmentDisplay {
+    /// Lit segments of each digit, segment `a` in bit 0
+    pub fn segments(&self) -> Vec<u8> {
+        self.0.borrow().clone()
+    }
+
+    /// The digits as text, a blank digit is a space and segments that aren't a digit are a `?`
+    pub fn text(&self) -> String {
+        self.0
+            .borrow()
+            .iter()
+            .map(|&segments| decode(segments))
+            .collect()
+    }
+}
+
+/// The character a digit shows
+fn decode(segments: u8) -> char {
+    if segments == 0 {
+        return ' ';
+    }
+    DIGITS
+        .iter()
+        .find(|&&(pattern, _)| pattern == segments)
+        .map_or('?', |&(_, digit)| digit)
+}
+
+impl SevenSegment {
+    pub fn new(segments: [DigitalPin; 7], select: AnalogPin, digits: usize) -> Self {
+        Self {
+            segments,
+            select,
+            display: SevenSegmentDisplay(Rc::new(RefCell::new(vec![0; digits]))),
+        }
+    }
+
+    /// A handle to read the display once it's attached to a TPU
+    pub fn display(&self) -> SevenSegmentDisplay {
+        self.display.clone()
+    }
+}
+
+impl Peripheral for SevenSegment {
+    fn tick(&mut self, io: &mut PinBus) {
+        let lit = self
+            .segments
+            .iter()
+            .enumerate()
+            .fold(0, |lit, (segment, &pin)| {
+                lit | ((io.digital(pin) as u8) << segment)
+            });
+
+        // Selecting a digit the display doesn't have shows nothing
+        let digit = io.analog(self.select) as usize;
+        if let Some(segments) = self.display.0.borrow_mut().get_mut(digit) {
+            *segments = lit;
+        }
+    }
+
+    fn clone_box(&self) -> Box<dyn Peripheral> {
+        Box::new(self.clone())
+    }
+}
+
+/// Draws each digit 3 cells wide and 3 high with a gap between them, anything that isn't a digit as a `?`
+#[cfg(feature = "tui")]
+impl Widget for &SevenSegmentDisplay {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let lit = |segments: u8, segment: u8, symbol: &'static str| {
+            if segments & (1 << segment) != 0 {
+                symbol
+            } else {
+                " "
+            }
+        };
+
+        for (index, &segments) in self.0.borrow().iter().enumerate() {
+            let x = area.x + index as u16 * 4;
+            if x + 3 > area.right() {
+                break;
+            }
+
+            let rows = if decode(segments) == '?' {
+                ["   ".to_string(), " ? ".to_string(), "   ".to_string()]
+            } else {
+                [
+                    format!(" {} ", lit(segments, 0, "_")),
+                    [
+                        lit(segments, 5, "|"),
+                        lit(segments, 6, "_"),
+                        lit(segments, 1, "|"),
+                    ]
+                    .concat(),
+                    [
+                        lit(segments, 4, "|"),
+                        lit(segments, 3, "_"),
+                        lit(segments, 2, "|"),
+                    ]
+                    .concat(),
+                ]
+            };
+            for (row, text) in rows.iter().enumerate().take(area.height as usize) {
+                buf.set_string(x, area.y + row as u16, text, Style::default());
+            }
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use crate::shared::DigitalPin;
+use crate::tpu::peripheral::{Peripheral, PinBus};
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use core::cell::Cell;
+
+/// A serial in, parallel out shift register expanding a TPU's outputs, like a 74HC595 chain.
+///
+/// The level of the data pin is shifted in on each rising edge of the clock pin, the newest bit in bit 0. A rising
+/// edge of the latch pin copies the shifted bits to the outputs, which the host reads through the handle from
+/// `ShiftRegister::outputs`. A clone of the peripheral shares its handle.
+#[derive(Clone, Debug)]
+pub struct ShiftRegister {
+    data: DigitalPin,
+    clock: DigitalPin,
+    latch: DigitalPin,
+    mask: u64,
+    shifted: u64,
+    last_clock: bool,
+    last_latch: bool,
+    outputs: ShiftRegisterOutputs,
+}
+
+/// The host's view of a `ShiftRegister`'s latched outputs
+#[derive(Clone, Debug, Default)]
+pub struct ShiftRegisterOutputs(Rc<Cell<u64>>);
+
+impl ShiftRegisterOutputs {
+    /// All outputs, output 0 in bit 0
+    pub fn word(&self) -> u64 {
+        self.0.get()
+    }
+
+    pub fn output(&self, index: u32) -> bool {
+        self.word().checked_shr(index).unwrap_or(0) & 1 != 0
+    }
+}
+
+impl ShiftRegister {
+    /// A register `bits` long, at most 64
+    pub fn new(data: DigitalPin, clock: DigitalPin, latch: DigitalPin, bits: u32) -> Self {
+        Self {
+            data,
+            clock,
+            latch,
+            mask: u64::MAX
+                .checked_shr(64u32.saturating_sub(bits))
+                .unwrap_or(0),
+            shifted: 0,
+            last_clock: false,
+            last_latch: false,
+            outputs: ShiftRegisterOutputs::default(),
+        }
+    }
+
+    /// A handle to read the outputs once the register is attached to a TPU
+    pub fn outputs(&self) -> ShiftRegisterOutputs {
+        self.outputs.clone()
+    }
+}
+
+impl Peripheral for ShiftRegister {
+    fn tick(&mut self, io: &mut PinBus) {
+        let clock = io.digital(self.clock);
+        if clock && !self.last_clock {
+            self.shifted = ((self.shifted << 1) | io.digital(self.data) as u64) & self.mask;
+        }
+        self.last_clock = clock;
+
+        let latch = io.digital(self.latch);
+        if latch && !self.last_latch {
+            self.outputs.0.set(self.shifted);
+        }
+        self.last_latch = latch;
+    }
+
+    fn clone_box(&self) -> Box<dyn Peripheral> {
+        Box::new(self.clone())
+    }
+}
@@ -0,0 +1,216 @@
+use crate::shared::DigitalPin;
+use crate::tpu::peripheral::{Peripheral, PinBus};
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+
+/// What a signal head is showing
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Aspect {
+    Dark,
+    Red,
+    RedAmber,
+    Amber,
+    Green,
+    /// Any other combination of lamps, e.g. red and green together
+    Invalid,
+}
+
+/// A signal head for one movement, with its red, amber and green lamps driven by three digital outputs.
+///
+/// Attached as a peripheral it records every change of aspect, which the host reads through the handle from
+/// `SignalHead::history`. A clone of the head shares its handle.
+#[derive(Clone, Debug)]
+pub struct SignalHead {
+    red: DigitalPin,
+    amber: DigitalPin,
+    green: DigitalPin,
+    history: SignalHistory,
+}
+
+/// The aspects a `SignalHead` has shown, as (cycle, aspect) from when each started
+#[derive(Clone, Debug, Default)]
+pub struct SignalHistory(Rc<RefCell<Vec<(u64, Aspect)>>>);
+
+impl SignalHistory {
+    pub fn changes(&self) -> Vec<(u64, Aspect)> {
+        self.0.borrow().clone()
+    }
+
+    /// The aspect shown now, dark until the head has been ticked
+    pub fn current(&self) -> Aspect {
+        self.0
+            .borrow()
+            .last()
+            .map_or(Aspect::Dark, |&(_, aspect)| aspect)
+    }
+}
+
+impl SignalHead {
+    pub fn new(red: DigitalPin, amber: DigitalPin, green: DigitalPin) -> Self {
+        Self {
+            red,
+            amber,
+            green,
+            history: SignalHistory::default(),
+        }
+    }
+
+    /// A handle to read the aspects shown once the head is attached to a TPU
+    pub fn history(&self) -> SignalHistory {
+        self.history.clone()
+    }
+
+    /// The aspect the pins are showing
+    pub fn aspect(&self, io: &PinBus) -> Aspect {
+        match (
+            io.digital(self.red),
+            io.digital(self.amber),
+            io.digital(self.green),
+        ) {
+            (false, false, false) => Aspect::Dark,
+            (true, false, false) => Aspect::Red,
+            (true, true, false) => Aspect::RedAmber,
+            (false, true, false) => Aspect::Amber,
+            (false, false, true) => Aspect::Green,
+            _ => Aspect::Invalid,
+        }
+    }
+}
+
+impl Peripheral for SignalHead {
+    fn tick(&mut self, io: &mut PinBus) {
+        let aspect = self.aspect(io);
+        let mut history = self.history.0.borrow_mut();
+        if history.last().is_none_or(|&(_, last)| last != aspect) {
+            history.push((io.cycle(), aspect));
+        }
+    }
+
+    fn clone_box(&self) -> Box<dyn Peripheral> {
+        Box::new(self.clone())
+    }
+}
+
+/// A fault found by a `ConflictMonitor`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SignalFault {
+    /// Two heads that must never be green together were, from this cycle
+    ConflictingGreens { cycle: u64, heads: (usize, usize) },
+    /// A head had been dark for longer than allowed at this cycle
+    Dark { cycle: u64, head: usize },
+}
+
+/// Watches a set of signal heads for greens shown to conflicting movements and for heads left dark.
+///
+/// Each fault is logged once when it starts, the host reads the log through the handle from
+/// `ConflictMonitor::faults`. The monitor can also hold a digital input high from the first fault on, so the program
+/// can react to it. Heads are numbered in the order they're given.
+#[derive(Clone, Debug)]
+pub struct ConflictMonitor {
+    heads: Vec<SignalHead>,
+    /// Pairs of heads that must never be green together
+    conflicts: Vec<(usize, usize)>,
+    /// Cycles a head may stay dark before it's a fault
+    dark_limit: u64,
+    fault_pin: Option<DigitalPin>,
+    /// Cycles each head has been dark for
+    dark_for: Vec<u64>,
+    /// Conflicting pairs that are green at the moment
+    green_conflicts: Vec<bool>,
+    faults: SignalFaults,
+}
+
+/// The faults a `ConflictMonitor` has logged, oldest first
+#[derive(Clone, Debug, Default)]
+pub struct SignalFaults(Rc<RefCell<Vec<SignalFault>>>);
+
+impl SignalFaults {
+    pub fn log(&self) -> Vec<SignalFault> {
+        self.0.borrow().clone()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.borrow().is_empty()
+    }
+}
+
+impl ConflictMonitor {
+    /// Watch `heads`, every pair in `conflicts` has to name two of them
+    pub fn new(heads: Vec<SignalHead>, conflicts: Vec<(usize, usize)>, dark_limit: u64) -> Self {
+        assert!(
+            conflicts
+                .iter()
+                .all(|&(a, b)| a < heads.len() && b < heads.len()),
+            "a conflicting pair names a head that isn't being watched"
+        );
+        Self {
+            dark_for: vec![0; heads.len()],
+            green_conflicts: vec![false; conflicts.len()],
+            heads,
+            conflicts,
+            dark_limit,
+            fault_pin: None,
+            faults: SignalFaults::default(),
+        }
+    }
+
+    /// Hold this digital input high once there has been a fault
+    pub fn with_fault_pin(mut self, pin: DigitalPin) -> Self {
+        self.fault_pin = Some(pin);
+        self
+    }
+
+    /// A handle to read the fault log once the monitor is attached to a TPU
+    pub fn faults(&self) -> SignalFaults {
+        self.faults.clone()
+    }
+}
+
+impl Peripheral for ConflictMonitor {
+    fn tick(&mut self, io: &mut PinBus) {
+        let cycle = io.cycle();
+        let aspects: Vec<Aspect> = self.heads.iter().map(|head| head.aspect(io)).collect();
+        let mut faults = self.faults.0.borrow_mut();
+
+        // Checked on the lamps, a green lit along with red or amber is still a green
+        let greens: Vec<bool> = self
+            .heads
+            .iter()
+            .map(|head| io.digital(head.green))
+            .collect();
+        for (index, &(a, b)) in self.conflicts.iter().enumerate() {
+            let both_green = greens[a] && greens[b];
+            if both_green && !self.green_conflicts[index] {
+                faults.push(SignalFault::ConflictingGreens {
+                    cycle,
+                    heads: (a, b),
+                });
+            }
+            self.green_conflicts[index] = both_green;
+        }
+
+        for (head, aspect) in aspects.iter().enumerate() {
+            if *aspect != Aspect::Dark {
+                self.dark_for[head] = 0;
+                continue;
+            }
+            self.dark_for[head] += 1;
+            if self.dark_for[head] == self.dark_limit + 1 {
+                faults.push(SignalFault::Dark { cycle, head });
+            }
+        }
+
+        if let Some(pin) = self.fault_pin
+            && !faults.is_empty()
+        {
+            let _ = io.drive_digital(pin, true);
+        }
+    }
+
+    fn clone_box(&self) -> Box<dyn Peripheral> {
+        Box::new(self.clone())
+    }
+}
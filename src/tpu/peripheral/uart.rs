@@ -0,0 +1,129 @@
+use crate::shared::DigitalPin;
+use crate::tpu::peripheral::{Peripheral, PinBus};
+use alloc::boxed::Box;
+
+/// Bits in a frame, a low start bit, 8 data bits with the lowest first and a high stop bit
+const FRAME_BITS: u8 = 10;
+
+/// A serial port shifting the bytes queued by UTX out of one pin, and the bytes it sees on another into URX.
+///
+/// The line idles high, and is held high for a bit before the first frame. Every bit is held for `cycles_per_bit`
+/// ticks, received bits are sampled in their middle.
+/// A byte whose stop bit is low is still handed to URX, marked with `Uart::FRAMING_ERROR`.
+#[derive(Clone, Debug)]
+pub struct Uart {
+    tx_pin: DigitalPin,
+    rx_pin: DigitalPin,
+    cycles_per_bit: u16,
+    tx: Option<Shift>,
+    /// Ticks the transmit pin has been idle, a frame only starts once it has been high for a whole bit
+    tx_idle: u16,
+    rx: Option<Shift>,
+    /// Level of the receive pin last tick, a start bit is a fall from high
+    rx_last: bool,
+}
+
+/// A frame being shifted in or out
+#[derive(Clone, Copy, Debug)]
+struct Shift {
+    /// Frame bits, the start bit in bit 0
+    frame: u16,
+    /// Bit of the frame on the line now
+    bit: u8,
+    /// Ticks until the next bit
+    countdown: u16,
+}
+
+impl Uart {
+    /// Set on a received byte whose stop bit was low
+    pub const FRAMING_ERROR: u16 = 0x8000;
+
+    pub fn new(tx_pin: DigitalPin, rx_pin: DigitalPin, cycles_per_bit: u16) -> Self {
+        Self {
+            tx_pin,
+            rx_pin,
+            cycles_per_bit: cycles_per_bit.max(1),
+            tx: None,
+            tx_idle: 0,
+            rx: None,
+            rx_last: false,
+        }
+    }
+
+    fn transmit(&mut self, io: &mut PinBus) {
+        if self.tx.is_none()
+            && self.tx_idle >= self.cycles_per_bit
+            && let Some(byte) = io.take_uart_tx()
+        {
+            self.tx = Some(Shift {
+                frame: (1 << 9) | ((byte as u16) << 1),
+                bit: 0,
+                countdown: self.cycles_per_bit,
+            });
+        }
+
+        let Some(shift) = &mut self.tx else {
+            io.drive_output(self.tx_pin, true);
+            self.tx_idle = self.tx_idle.saturating_add(1);
+            return;
+        };
+        io.drive_output(self.tx_pin, shift.frame & (1 << shift.bit) != 0);
+
+        shift.countdown -= 1;
+        if shift.countdown == 0 {
+            shift.bit += 1;
+            shift.countdown = self.cycles_per_bit;
+            if shift.bit == FRAME_BITS {
+                // The stop bit counts as idle, so the next frame can follow straight on
+                self.tx = None;
+                self.tx_idle = self.cycles_per_bit;
+            }
+        }
+    }
+
+    fn receive(&mut self, io: &mut PinBus) {
+        let level = io.digital(self.rx_pin);
+        let last = core::mem::replace(&mut self.rx_last, level);
+
+        let Some(shift) = &mut self.rx else {
+            if last && !level {
+                // Sample the start bit again in its middle
+                self.rx = Some(Shift {
+                    frame: 0,
+                    bit: 0,
+                    countdown: self.cycles_per_bit.div_ceil(2),
+                });
+            }
+            return;
+        };
+
+        shift.countdown -= 1;
+        if shift.countdown > 0 {
+            return;
+        }
+        shift.countdown = self.cycles_per_bit;
+        shift.frame |= (level as u16) << shift.bit;
+        shift.bit += 1;
+
+        if shift.bit == 1 && level {
+            // The line went back high, it was a glitch rather than a start bit
+            self.rx = None;
+        } else if shift.bit == FRAME_BITS {
+            let byte = (shift.frame >> 1) & 0xFF;
+            let framing_error = if level { 0 } else { Self::FRAMING_ERROR };
+            io.push_uart_rx(byte | framing_error);
+            self.rx = None;
+        }
+    }
+}
+
+impl Peripheral for Uart {
+    fn tick(&mut self, io: &mut PinBus) {
+        self.transmit(io);
+        self.receive(io);
+    }
+
+    fn clone_box(&self) -> Box<dyn Peripheral> {
+        Box::new(self.clone())
+    }
+}
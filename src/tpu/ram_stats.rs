@@ -0,0 +1,62 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::Cell;
+
+/// Per-address RAM access counters, see `TPU::enable_ram_stats`
+#[derive(Clone, Debug)]
+pub struct RamStats {
+    /// Reads happen through `&TPU`, so these need interior mutability
+    reads: Vec<Cell<u32>>,
+    writes: Vec<u32>,
+}
+
+impl RamStats {
+    pub(crate) fn new(size: usize) -> Self {
+        Self {
+            reads: vec![Cell::new(0); size],
+            writes: vec![0; size],
+        }
+    }
+
+    pub(crate) fn record_read(&self, address: usize) {
+        if let Some(count) = self.reads.get(address) {
+            count.set(count.get().saturating_add(1));
+        }
+    }
+
+    pub(crate) fn record_write(&mut self, address: usize) {
+        if let Some(count) = self.writes.get_mut(address) {
+            *count = count.saturating_add(1);
+        }
+    }
+
+    /// Number of reads of each address
+    pub fn reads(&self) -> Vec<u32> {
+        self.reads.iter().map(Cell::get).collect()
+    }
+
+    /// Number of writes to each address
+    pub fn writes(&self) -> &[u32] {
+        &self.writes
+    }
+
+    /// The `n` busiest addresses as (address, reads, writes), busiest first.
+    /// Addresses that were never accessed are left out.
+    pub fn top_n(&self, n: usize) -> Vec<(usize, u32, u32)> {
+        let mut busiest: Vec<(usize, u32, u32)> = self
+            .reads()
+            .into_iter()
+            .zip(&self.writes)
+            .enumerate()
+            .map(|(address, (reads, &writes))| (address, reads, writes))
+            .filter(|&(_, reads, writes)| reads > 0 || writes > 0)
+            .collect();
+
+        // Ties are broken by address so the report is stable
+        busiest.sort_by_key(|&(address, reads, writes)| {
+            (core::cmp::Reverse(reads as u64 + writes as u64), address)
+        });
+        busiest.truncate(n);
+        busiest
+    }
+}
@@ -0,0 +1,123 @@
+use crate::tpu::{TPU, TpuState};
+use core::fmt;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// A copy of a TPU's state, versioned so that one saved by an older build is refused rather than misread.
+///
+/// The ROM is saved with the rest of the state, one copy of each instruction, so a snapshot can be restored on its
+/// own. Peripherals, breakpoints and RAM stats live on the `TPU` and aren't saved.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TpuSnapshot {
+    pub version: u32,
+    pub state: TpuState,
+}
+
+impl fmt::Debug for TpuSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TpuSnapshot")
+            .field("version", &self.version)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Why a snapshot couldn't be read
+#[cfg(feature = "serde")]
+#[derive(Debug)]
+pub enum SnapshotError {
+    /// Not a snapshot, or a damaged one
+    Json(serde_json::Error),
+    /// Saved by a build with a different `TpuSnapshot::VERSION`
+    Version(u32),
+}
+
+#[cfg(feature = "serde")]
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Json(error) => write!(f, "not a TPU snapshot: {error}"),
+            SnapshotError::Version(version) => write!(
+                f,
+                "snapshot version {version} can't be read, this build reads version {}",
+                TpuSnapshot::VERSION
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl core::error::Error for SnapshotError {}
+
+impl TpuSnapshot {
+    /// Bumped whenever `TpuState` changes in a way older snapshots can't be read into
+    pub const VERSION: u32 = 1;
+
+    pub fn new(state: TpuState) -> Self {
+        Self {
+            version: Self::VERSION,
+            state,
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+
+    #[cfg(feature = "serde")]
+    pub fn from_json(json: &str) -> crate::Result<Self> {
+        // The version is read first, a snapshot from another version may not have the same fields
+        #[derive(Deserialize)]
+        struct Version {
+            version: u32,
+        }
+        let Version { version } = serde_json::from_str(json).map_err(SnapshotError::Json)?;
+        if version != Self::VERSION {
+            return Err(SnapshotError::Version(version).into());
+        }
+        Ok(serde_json::from_str(json).map_err(SnapshotError::Json)?)
+    }
+}
+
+impl TPU {
+    pub fn snapshot(&self) -> TpuSnapshot {
+        TpuSnapshot::new(self.tpu_state.clone())
+    }
+
+    /// Put the TPU back to a snapshot, ROM and all. Peripherals stay attached, and breakpoints past the end of the
+    /// snapshot's program are dropped like `TPU::load_program` does. The history kept for `TPU::step_back` is
+    /// discarded.
+    pub fn restore(&mut self, snapshot: TpuSnapshot) {
+        self.retain_breakpoints(snapshot.state.rom.len());
+        self.tpu_state = snapshot.state;
+        // A snapshot from before return addresses were tagged has none, keep one tag per stack entry regardless
+        let depth = self.tpu_state.stack.len();
+        self.tpu_state.return_addresses.resize(depth, false);
+        self.clear_history();
+    }
+}
+
+/// Serde only handles arrays of up to 32 elements, RAM goes through a slice and back
+#[cfg(feature = "serde")]
+pub(crate) mod ram_words {
+    use crate::tpu::TPU;
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    pub fn serialize<S: Serializer>(
+        ram: &[u16; TPU::RAM_SIZE],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(ram)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<[u16; TPU::RAM_SIZE], D::Error> {
+        let words = Vec::<u16>::deserialize(deserializer)?;
+        let len = words.len();
+        words
+            .try_into()
+            .map_err(|_| D::Error::invalid_length(len, &"a word for every address in RAM"))
+    }
+}
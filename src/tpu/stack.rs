@@ -0,0 +1,57 @@
+use crate::shared::Instruction;
+use crate::tpu::TPU;
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// A value on the stack, told apart by what pushed it
+#[derive(Clone, Debug, PartialEq)]
+pub enum StackEntry {
+    /// Pushed by PUSH, ENTER and the like
+    Value(u16),
+    /// Pushed by JSR, JSRT or SWI, with the instruction it returns to if it's in the program
+    ReturnAddress {
+        address: u16,
+        instruction: Option<Rc<Instruction>>,
+    },
+}
+
+impl fmt::Display for StackEntry {
+    /// `0x002A` for a value, `ret → 0x0005 (BEZ 7, A)` for a return address
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StackEntry::Value(value) => write!(f, "{value:#06X}"),
+            StackEntry::ReturnAddress {
+                address,
+                instruction: Some(instruction),
+            } => write!(f, "ret → {address:#06X} ({instruction})"),
+            StackEntry::ReturnAddress {
+                address,
+                instruction: None,
+            } => write!(f, "ret → {address:#06X}"),
+        }
+    }
+}
+
+impl TPU {
+    /// What's on the stack, top first. Values without a tag, say from an older snapshot, are taken as plain values.
+    pub fn stack_entries(&self) -> Vec<StackEntry> {
+        let state = &self.tpu_state;
+        state
+            .stack
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(index, &value)| {
+                if state.return_addresses.get(index) == Some(&true) {
+                    StackEntry::ReturnAddress {
+                        address: value,
+                        instruction: state.rom.get(usize::from(value)).cloned(),
+                    }
+                } else {
+                    StackEntry::Value(value)
+                }
+            })
+            .collect()
+    }
+}
@@ -1,13 +1,20 @@
-use crate::shared::{OperandValueType, Register};
-use crate::tpu::{TPU, create_basic_tpu_config};
+use crate::network::Network;
+use crate::shared::{HaltReason, OperandValueType, Register};
+use crate::tpu::peripheral::fault_lamp::FaultLamp;
+use crate::tpu::{
+    ConfigError, EvalError, Expr, ExprError, Pin, PinChange, PinMode, RunResult, StackEntry,
+    StateDiff, TPU, TpuBuilder, TpuConfig, TraceEvent, create_basic_tpu_config,
+};
+#[cfg(feature = "serde")]
+use crate::tpu::{SnapshotError, TpuSnapshot, TpuState};
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::rgal;
-    use crate::shared::{AnalogPin, DigitalPin, Instruction};
+    use crate::shared::{AnalogPin, DigitalPin, Instruction, NetPacket};
     use std::rc::Rc;
-    use strum::IntoEnumIterator;
+    use strum::{EnumCount, IntoEnumIterator};
 
     #[test]
     fn test_tpu_init() {
@@ -39,6 +46,705 @@ mod tests {
         assert_eq!(tpu.tpu_state.stack.is_empty(), false)
     }
 
+    #[test]
+    fn test_halt_reason() {
+        let program =
+            rgal::parse_program("LDR A, 1\nLDR X, 0\nDIV A, X\nHLT").expect("parse failure");
+        let mut tpu = create_basic_tpu_config(program);
+        assert_eq!(tpu.state().halt_reason, None);
+        while !tpu.halted() {
+            tpu.tick();
+        }
+        assert_eq!(tpu.state().halt_reason, Some(HaltReason::Div0));
+
+        tpu.reset();
+        assert_eq!(tpu.state().halt_reason, None);
+    }
+
+    #[test]
+    fn test_initial_ram_survives_reset() {
+        let program = rgal::parse_program(
+            r#"LDM A, 0x10
+            LDM X, 0x11
+            STM 0x10, 0
+            RST"#,
+        )
+        .expect("parse failure");
+
+        let config = TpuConfig {
+            initial_ram: vec![(0x10, 0x1234), (0x11, 0x5678), (TPU::RAM_SIZE, 0xFFFF)],
+            ..TpuConfig::default()
+        };
+        let mut tpu = TPU::new_with_config(
+            0x1,
+            [false; AnalogPin::COUNT],
+            [false; DigitalPin::COUNT],
+            program,
+            config,
+        );
+
+        // The table is present straight after construction
+        assert_eq!(tpu.read_ram(0x10), 0x1234);
+        assert_eq!(tpu.read_ram(0x11), 0x5678);
+
+        // Read the table, then clobber part of it
+        tpu.step();
+        tpu.step();
+        tpu.step();
+        assert_eq!(tpu.read_register(Register::A), 0x1234);
+        assert_eq!(tpu.read_register(Register::X), 0x5678);
+        assert_eq!(tpu.read_ram(0x10), 0);
+
+        // A snapshot taken now must reset to the same image
+        let mut restored = TPU::new_from_state(tpu.state().clone());
+
+        // RST restores the table and clears everything else
+        tpu.step();
+        assert_eq!(tpu.tpu_state.program_counter, 0);
+        assert_eq!(tpu.read_register(Register::A), 0);
+        assert_eq!(tpu.read_ram(0x10), 0x1234);
+
+        // The program reads the restored table again
+        tpu.step();
+        tpu.step();
+        assert_eq!(tpu.read_register(Register::A), 0x1234);
+        assert_eq!(tpu.read_register(Register::X), 0x5678);
+
+        restored.step();
+        assert_eq!(restored.tpu_state.program_counter, 0);
+        assert_eq!(restored.read_ram(0x10), 0x1234);
+        assert_eq!(restored.read_ram(0x11), 0x5678);
+    }
+
+    #[test]
+    fn test_nvram_survives_reset() {
+        let program = rgal::parse_program(
+            r#"NVL A, 2
+            INC A
+            NVS 2, A
+            STM 0x20, A
+            RST"#,
+        )
+        .expect("parse failure");
+        let mut tpu = create_basic_tpu_config(program);
+        assert_eq!(tpu.nvram().len(), TPU::NVRAM_SIZE);
+
+        // The host restores a previously saved image
+        tpu.set_nvram(&[0, 0, 41]);
+
+        for _ in 0..4 {
+            tpu.step();
+        }
+        assert_eq!(tpu.nvram()[2], 42);
+        assert_eq!(tpu.read_ram(0x20), 42);
+
+        // RST clears RAM and registers but not NVRAM
+        tpu.step();
+        assert_eq!(tpu.tpu_state.program_counter, 0);
+        assert_eq!(tpu.read_register(Register::A), 0);
+        assert_eq!(tpu.read_ram(0x20), 0);
+        assert_eq!(tpu.nvram()[2], 42);
+
+        // The program picks up where it left off
+        tpu.step();
+        assert_eq!(tpu.read_register(Register::A), 42);
+    }
+
+    #[test]
+    fn test_strided_table_walk() {
+        // Four records of three words each, sum the middle field of every record
+        let program = rgal::parse_program(
+            r#"LDR X, 0
+            LDR R0, 4
+            LDOS Y, 0x11, X, 3
+            ADD A, Y
+            DEC R0
+            BNZ 2, R0
+            HLT"#,
+        )
+        .expect("parse failure");
+
+        let mut initial_ram = Vec::new();
+        for (record, field) in [5, 7, 11, 13].into_iter().enumerate() {
+            let base = 0x10 + record * 3;
+            initial_ram.push((base, 0xFFFF));
+            initial_ram.push((base + 1, field));
+            initial_ram.push((base + 2, 0xFFFF));
+        }
+        let config = TpuConfig {
+            initial_ram,
+            ..TpuConfig::default()
+        };
+        let mut tpu = TPU::new_with_config(
+            0x1,
+            [false; AnalogPin::COUNT],
+            [false; DigitalPin::COUNT],
+            program,
+            config,
+        );
+
+        for _ in 0..1000 {
+            if tpu.halted() {
+                break;
+            }
+            tpu.tick();
+        }
+        assert!(tpu.halted());
+        assert_eq!(tpu.read_register(Register::A), 36);
+        assert_eq!(tpu.read_register(Register::X), 12);
+    }
+
+    #[test]
+    fn test_stack_high_water() {
+        let program = rgal::parse_program(
+            r#"PUSH 1
+            JSR 3
+            HLT
+            PUSH 2
+            JSR 6
+            HLT
+            PUSH 3
+            PUSH 4
+            POP Y
+            POP Y
+            POP Y
+            POP Y
+            POP Y
+            POP A
+            PUSH 5
+            SCR
+            HLT"#,
+        )
+        .expect("parse failure");
+        let mut tpu = create_basic_tpu_config(program);
+
+        for _ in 0..1000 {
+            if tpu.halted() {
+                break;
+            }
+            tpu.tick();
+        }
+        assert!(tpu.halted());
+        assert_eq!(tpu.read_register(Register::A), 1);
+
+        // Four pushes and two return addresses, popped and cleared back down again
+        assert_eq!(tpu.stack_pointer(), 0);
+        assert_eq!(tpu.stack_high_water(), 6);
+        assert!(tpu.state().to_string().contains("Max: 0006"));
+
+        // Only a reset clears it
+        tpu.reset();
+        assert_eq!(tpu.stack_high_water(), 0);
+    }
+
+    #[test]
+    fn test_zero_page_discount() {
+        // Returns the number of cycles to reach the HLT, and the loaded value
+        let run = |address: u16, zero_page_discount: bool| {
+            let program = rgal::parse_program(&format!(
+                r#"LDR X, 1
+                LDR R0, 7
+                STM {address}, R0
+                LDM A, {address}
+                STMO {address}, A, X
+                LDO Y, {address}, X
+                HLT"#
+            ))
+            .expect("parse failure");
+            let config = TpuConfig {
+                zero_page_discount,
+                ..TpuConfig::default()
+            };
+            let mut tpu = TPU::new_with_config(
+                0x1,
+                [false; AnalogPin::COUNT],
+                [false; DigitalPin::COUNT],
+                program,
+                config,
+            );
+
+            let mut cycles = 0;
+            while tpu.tpu_state.program_counter != 6 {
+                tpu.tick();
+                cycles += 1;
+            }
+            (cycles, tpu.read_register(Register::Y))
+        };
+
+        // Disabled by default, so the address doesn't matter
+        assert_eq!(run(0x05, false), run(0x50, false));
+
+        // Each of the four memory instructions is a cycle faster in the zero page
+        let (zero_page_cycles, zero_page_value) = run(0x05, true);
+        let (cycles, value) = run(0x50, true);
+        assert_eq!(zero_page_cycles + 4, cycles);
+        assert_eq!(cycles, run(0x50, false).0);
+        assert_eq!(zero_page_value, 7);
+        assert_eq!(value, 7);
+
+        // The offset counts towards the effective address, 0x0F + 1 is outside the zero page
+        let (boundary_cycles, _) = run(0x0F, true);
+        assert_eq!(boundary_cycles, zero_page_cycles + 2);
+    }
+
+    #[test]
+    fn test_subroutine_called_from_two_sites() {
+        let program = rgal::parse_program(
+            r#"LDR A, 1
+            JSR 7
+            RCY R0, A
+            JSR 7
+            RCY R1, A
+            HLT
+            NOP
+            INC A
+            INC A
+            RTS"#,
+        )
+        .expect("parse failure");
+        let mut tpu = create_basic_tpu_config(program);
+
+        for _ in 0..1000 {
+            if tpu.halted() {
+                break;
+            }
+            tpu.tick();
+        }
+
+        // Each call returned to the line after it, so both copies ran once and we stopped at the HLT
+        assert!(tpu.halted());
+        assert_eq!(tpu.tpu_state.program_counter, 5);
+        assert_eq!(tpu.read_register(Register::R0), 3);
+        assert_eq!(tpu.read_register(Register::R1), 5);
+        assert_eq!(tpu.stack_pointer(), 0);
+    }
+
+    #[test]
+    fn test_software_interrupt() {
+        // The handler at line 6 adds the trap code to R0 and returns
+        let program = rgal::parse_program(
+            r#"SWI 3
+            SWI 4
+            STM 0x10, R0
+            HLT
+            NOP
+            NOP
+            ADD R0, R5
+            RCY R0, A
+            RTS"#,
+        )
+        .expect("parse failure");
+        let config = TpuConfig {
+            trap_vector: Some(6),
+            ..TpuConfig::default()
+        };
+        let mut tpu = TPU::new_with_config(
+            0x1,
+            [false; AnalogPin::COUNT],
+            [false; DigitalPin::COUNT],
+            program.clone(),
+            config,
+        );
+
+        for _ in 0..1000 {
+            if tpu.halted() {
+                break;
+            }
+            tpu.tick();
+        }
+
+        // Both traps returned to the line after them
+        assert_eq!(tpu.tpu_state.program_counter, 3);
+        assert_eq!(tpu.read_ram(0x10), 7);
+        assert_eq!(tpu.read_register(TPU::TRAP_CODE), 4);
+        assert_eq!(tpu.stack_pointer(), 0);
+
+        // Without a vector the first trap halts
+        let mut tpu = create_basic_tpu_config(program);
+        for _ in 0..1000 {
+            if tpu.halted() {
+                break;
+            }
+            tpu.tick();
+        }
+        assert!(tpu.halted());
+        assert_eq!(tpu.tpu_state.program_counter, 0);
+        assert_eq!(tpu.stack_pointer(), 0);
+    }
+
+    #[test]
+    fn test_wait_receive_with_timeout() {
+        let program = rgal::parse_program("WRXT 10\nHLT").expect("parse failure");
+        let packet = NetPacket::new(0x2, 0x1, 42);
+
+        // Ticks until WRXT finishes, delivering the packet just before the given tick
+        let run = |arrival: Option<u16>| {
+            let mut tpu = create_basic_tpu_config(program.clone());
+            let mut cycles = 0;
+            while tpu.state().program_counter == 0 {
+                cycles += 1;
+                if arrival == Some(cycles) {
+                    tpu.tpu_state.incoming_packets.push_back(packet);
+                }
+                tpu.tick();
+            }
+            (tpu, cycles)
+        };
+
+        // Arrives before the deadline
+        let (tpu, cycles) = run(Some(4));
+        assert_eq!(cycles, 4);
+        assert_eq!(tpu.read_register(Register::X), 0x2);
+        assert_eq!(tpu.read_register(Register::Y), 42);
+
+        // Nothing arrives
+        let (tpu, cycles) = run(None);
+        assert_eq!(cycles, 10);
+        assert_eq!(tpu.read_register(Register::X), TPU::BROADCAST_ADDRESS);
+
+        // Arrives on the final cycle
+        let (tpu, cycles) = run(Some(10));
+        assert_eq!(cycles, 10);
+        assert_eq!(tpu.read_register(Register::X), 0x2);
+        assert_eq!(tpu.read_register(Register::Y), 42);
+    }
+
+    #[test]
+    fn test_nested_stack_frames() {
+        // Each subroutine opens a frame and pushes a local of its own
+        let program = rgal::parse_program(
+            r#"JSR 3
+            HLT
+            NOP
+            ENTER 1
+            PUSH 11
+            JSR 11
+            PEEKF R0, 1
+            PEEKF R1, 0
+            LEAVE
+            RTS
+            NOP
+            ENTER 2
+            PUSH 22
+            PEEKF R2, 2
+            PEEKF R3, 0
+            LEAVE
+            RTS"#,
+        )
+        .expect("parse failure");
+        let mut tpu = create_basic_tpu_config(program);
+
+        for _ in 0..1000 {
+            if tpu.halted() {
+                break;
+            }
+            tpu.tick();
+        }
+        assert!(tpu.halted());
+        assert_eq!(tpu.tpu_state.program_counter, 1);
+
+        // The inner frame saw its own locals, and didn't disturb the outer frame's
+        assert_eq!(tpu.read_register(Register::R2), 22);
+        assert_eq!(tpu.read_register(Register::R3), 0);
+        assert_eq!(tpu.read_register(Register::R0), 11);
+        assert_eq!(tpu.read_register(Register::R1), 0);
+
+        // Both frames are gone and the frame pointer is back where it started
+        assert_eq!(tpu.stack_pointer(), 0);
+        assert_eq!(tpu.read_register(TPU::FRAME_POINTER), 0);
+    }
+
+    #[test]
+    fn test_ram_stats() {
+        let program = rgal::parse_program(
+            r#"LDR X, 0
+            LDR R0, 3
+            STM 0x10, R0
+            LDM A, 0x10
+            SMOI 0x20, A, X
+            SMOI 0x20, A, X
+            LDM Y, 0x20
+            LDM Y, 0x20
+            HLT"#,
+        )
+        .expect("parse failure");
+
+        // Disabled by default, the counters aren't even allocated
+        let mut tpu = create_basic_tpu_config(program.clone());
+        while !tpu.halted() {
+            tpu.tick();
+        }
+        assert!(tpu.ram_stats().is_none());
+
+        let mut tpu = create_basic_tpu_config(program);
+        tpu.enable_ram_stats();
+        while !tpu.halted() {
+            tpu.tick();
+        }
+
+        let stats = tpu.ram_stats().expect("stats enabled");
+        let reads = stats.reads();
+        assert_eq!((reads[0x10], stats.writes()[0x10]), (1, 1));
+        assert_eq!((reads[0x20], stats.writes()[0x20]), (2, 1));
+        assert_eq!((reads[0x21], stats.writes()[0x21]), (0, 1));
+        assert_eq!(reads.iter().sum::<u32>(), 3);
+        assert_eq!(stats.writes().iter().sum::<u32>(), 3);
+
+        assert_eq!(stats.top_n(2), vec![(0x20, 2, 1), (0x10, 1, 1)]);
+        assert_eq!(stats.top_n(10).len(), 3);
+
+        // Snapshots of the state don't carry the counters
+        let restored = TPU::new_from_state(tpu.state().clone());
+        assert!(restored.ram_stats().is_none());
+    }
+
+    #[test]
+    fn test_fault_lamp_peripheral() {
+        // Switches the lamp on pin 0 every `delay` loops, pin 1 is the lamp's fault line
+        let run = |delay: u16| {
+            let program = rgal::parse_program(&format!(
+                r#"LDR R1, 4
+                DPW 0, 1
+                LDR R0, {delay}
+                DJNZ 3, R0
+                DPW 0, 0
+                LDR R0, {delay}
+                DJNZ 6, R0
+                DJNZ 1, R1
+                HLT"#
+            ))
+            .expect("parse failure");
+            let mut digital_config = [false; DigitalPin::COUNT];
+            digital_config[DigitalPin::Digital1 as usize] = true;
+            let mut tpu = TPU::new(0x1, [false; AnalogPin::COUNT], digital_config, program);
+            tpu.attach_peripheral(Box::new(FaultLamp::new(
+                DigitalPin::Digital0,
+                DigitalPin::Digital1,
+                50,
+            )));
+
+            while !tpu.halted() {
+                tpu.tick();
+            }
+            tpu
+        };
+
+        let tpu = run(40);
+        assert!(!tpu.tpu_state.digital_pins[DigitalPin::Digital1 as usize]);
+
+        // Switching too fast damages it, and the fault stays latched
+        let mut tpu = run(1);
+        assert!(tpu.tpu_state.digital_pins[DigitalPin::Digital1 as usize]);
+        tpu.tick();
+        assert!(tpu.tpu_state.digital_pins[DigitalPin::Digital1 as usize]);
+
+        // Peripherals aren't part of a snapshot
+        let restored = TPU::new_from_state(tpu.state().clone());
+        assert!(restored.peripherals.is_empty());
+        assert_eq!(tpu.clone().peripherals.len(), 1);
+    }
+
+    /// Every instruction handler takes the TPU followed by its operands by reference, in instruction order.
+    /// This only has to compile, a handler that drifts from the convention breaks the build here.
+    #[test]
+    fn test_op_handler_signatures() {
+        use crate::shared::ExecuteResult;
+        use crate::tpu::{alu, flow, io_matrix, mmu};
+
+        type NoOperands = fn(&mut TPU) -> ExecuteResult;
+        type Reg = fn(&mut TPU, &Register) -> ExecuteResult;
+        type Any = fn(&mut TPU, &OperandValueType) -> ExecuteResult;
+        type RegReg = fn(&mut TPU, &Register, &Register) -> ExecuteResult;
+        type RegAny = fn(&mut TPU, &Register, &OperandValueType) -> ExecuteResult;
+        type AnyReg = fn(&mut TPU, &OperandValueType, &Register) -> ExecuteResult;
+        type AnyAny = fn(&mut TPU, &OperandValueType, &OperandValueType) -> ExecuteResult;
+        type RegRegAny = fn(&mut TPU, &Register, &Register, &OperandValueType) -> ExecuteResult;
+        type AnyRegAny =
+            fn(&mut TPU, &OperandValueType, &Register, &OperandValueType) -> ExecuteResult;
+        type RegAnyReg = fn(&mut TPU, &Register, &OperandValueType, &Register) -> ExecuteResult;
+        type RegAnyAny =
+            fn(&mut TPU, &Register, &OperandValueType, &OperandValueType) -> ExecuteResult;
+        type AnyAnyReg =
+            fn(&mut TPU, &OperandValueType, &OperandValueType, &Register) -> ExecuteResult;
+        type RegAnyRegAny = fn(
+            &mut TPU,
+            &Register,
+            &OperandValueType,
+            &Register,
+            &OperandValueType,
+        ) -> ExecuteResult;
+        type AnyAnyRegAny = fn(
+            &mut TPU,
+            &OperandValueType,
+            &OperandValueType,
+            &Register,
+            &OperandValueType,
+        ) -> ExecuteResult;
+        // Handlers with a length dependent cost also get the remaining wait cycles
+        type AnyTimed = fn(&mut TPU, &OperandValueType, u16) -> ExecuteResult;
+        type AnyAnyTimed = fn(&mut TPU, &OperandValueType, &OperandValueType, u16) -> ExecuteResult;
+
+        let no_operands: &[NoOperands] = &[
+            mmu::op_scr,
+            mmu::op_leave,
+            io_matrix::op_recv,
+            io_matrix::op_txbs,
+            io_matrix::op_rxbs,
+            flow::op_rts,
+            flow::op_sret,
+            io_matrix::op_pevr,
+            TPU::op_nop,
+            TPU::op_wrx,
+            TPU::op_hlt,
+            TPU::op_rst,
+        ];
+        let reg: &[Reg] = &[
+            mmu::op_pop,
+            mmu::op_rsp,
+            alu::op_inc,
+            alu::op_dec,
+            alu::op_not,
+            flow::op_sez,
+            flow::op_snz,
+            io_matrix::op_dprw,
+            io_matrix::op_aprw,
+            io_matrix::op_pevc,
+            io_matrix::op_txok,
+            io_matrix::op_rxav,
+            io_matrix::op_recvv,
+            io_matrix::op_urx,
+            io_matrix::op_netr,
+        ];
+        let any: &[Any] = &[
+            mmu::op_push,
+            mmu::op_enter,
+            mmu::op_sdb,
+            io_matrix::op_dpww,
+            io_matrix::op_apwa,
+            io_matrix::op_apra,
+            io_matrix::op_pcntc,
+            flow::op_jmp,
+            flow::op_jpr,
+            flow::op_jsr,
+            flow::op_swi,
+            flow::op_sjmp,
+            flow::op_bcs,
+            flow::op_bcc,
+            flow::op_bmi,
+            flow::op_bpl,
+            flow::op_bzs,
+            flow::op_bzc,
+            TPU::op_slp,
+            io_matrix::op_recvm,
+            io_matrix::op_fwd,
+            io_matrix::op_utx,
+            io_matrix::op_neta,
+            io_matrix::op_recvp,
+        ];
+        let reg_reg: &[RegReg] = &[
+            mmu::op_rcy,
+            mmu::op_rmv,
+            alu::op_add,
+            alu::op_sub,
+            alu::op_mul,
+            alu::op_div,
+            alu::op_mod,
+            alu::op_and,
+            alu::op_or,
+            alu::op_xor,
+        ];
+        let reg_any: &[RegAny] = &[
+            mmu::op_peek,
+            mmu::op_peekf,
+            mmu::op_ldr,
+            mmu::op_ldm,
+            mmu::op_ldb,
+            mmu::op_ldrp,
+            mmu::op_nvl,
+            alu::op_cmp,
+            io_matrix::op_dpr,
+            io_matrix::op_apr,
+            io_matrix::op_dcfr,
+            io_matrix::op_pcntr,
+            io_matrix::op_acfr,
+            io_matrix::op_xmit,
+        ];
+        let any_reg: &[AnyReg] = &[
+            flow::op_bez,
+            flow::op_bnz,
+            flow::op_djnz,
+            flow::op_jtb,
+            flow::op_jsrt,
+            flow::op_brez,
+            flow::op_brnz,
+        ];
+        let any_any: &[AnyAny] = &[
+            mmu::op_stm,
+            mmu::op_stb,
+            flow::op_bph,
+            flow::op_bplw,
+            flow::op_loops,
+            mmu::op_strp,
+            mmu::op_nvs,
+            io_matrix::op_dpw,
+            io_matrix::op_pwmc,
+            io_matrix::op_pwmd,
+            io_matrix::op_pwms,
+            io_matrix::op_apw,
+            io_matrix::op_dcfg,
+            io_matrix::op_acfg,
+            io_matrix::op_cmpt,
+        ];
+        let reg_reg_any: &[RegRegAny] = &[
+            alu::op_sll,
+            alu::op_slc,
+            alu::op_slr,
+            alu::op_src,
+            alu::op_rol,
+            alu::op_ror,
+        ];
+        let any_reg_any: &[AnyRegAny] = &[
+            flow::op_beq,
+            flow::op_bne,
+            flow::op_bge,
+            flow::op_ble,
+            flow::op_bgt,
+            flow::op_blt,
+            flow::op_breq,
+            flow::op_brne,
+            flow::op_brge,
+            flow::op_brle,
+            flow::op_brgt,
+            flow::op_brlt,
+            flow::op_jtbn,
+        ];
+        let reg_any_reg: &[RegAnyReg] = &[mmu::op_ldo, mmu::op_ldoi, mmu::op_cmovz, mmu::op_cmovn];
+        let reg_any_any: &[RegAnyAny] = &[io_matrix::op_xmitm, io_matrix::op_xmitp];
+        let any_any_reg: &[AnyAnyReg] = &[mmu::op_stmo, mmu::op_smoi];
+        let reg_any_reg_any: &[RegAnyRegAny] = &[mmu::op_ldos];
+        let any_any_reg_any: &[AnyAnyRegAny] = &[mmu::op_smos];
+        let any_timed: &[AnyTimed] = &[TPU::op_wrxt];
+        let any_any_timed: &[AnyAnyTimed] = &[mmu::op_crc];
+
+        let handlers = no_operands.len()
+            + reg.len()
+            + any.len()
+            + reg_reg.len()
+            + reg_any.len()
+            + any_reg.len()
+            + any_any.len()
+            + reg_reg_any.len()
+            + any_reg_any.len()
+            + reg_any_reg.len()
+            + reg_any_any.len()
+            + any_any_reg.len()
+            + reg_any_reg_any.len()
+            + any_any_reg_any.len()
+            + any_timed.len()
+            + any_any_timed.len();
+        assert!(handlers > 0);
+    }
+
     #[test]
     fn test_tpu_state_display() {
         // Create a TPU with some test values
@@ -80,4 +786,919 @@ mod tests {
         // Basic assertion to ensure the test runs
         assert!(true);
     }
+
+    #[test]
+    fn test_pin_callback() {
+        // Blinks pin 0 four times writing each level twice, then sets analog pin 0 twice
+        let program = rgal::parse_program(
+            r#"LDR R1, 4
+            DPW 0, 1
+            DPW 0, 1
+            DPW 0, 0
+            DPW 0, 0
+            DJNZ 1, R1
+            APW 0, 100
+            APW 0, 100
+            HLT"#,
+        )
+        .expect("parse failure");
+        let mut digital_config = [false; DigitalPin::COUNT];
+        digital_config[DigitalPin::Digital1 as usize] = true;
+        let mut tpu = TPU::new(0x1, [false; AnalogPin::COUNT], digital_config, program);
+
+        let changes = Rc::new(std::cell::RefCell::new(Vec::<PinChange>::new()));
+        let recorded = changes.clone();
+        tpu.set_pin_callback(Box::new(move |change| recorded.borrow_mut().push(change)));
+
+        while !tpu.halted() {
+            tpu.tick();
+            // Inputs driven by the host aren't outputs changing
+            tpu.drive_digital_input(
+                DigitalPin::Digital1,
+                tpu.state().cycle_count.is_multiple_of(2),
+            )
+            .unwrap();
+        }
+
+        let log = changes.borrow();
+        let digital: Vec<&PinChange> = log
+            .iter()
+            .filter(|change| change.pin == Pin::Digital(DigitalPin::Digital0))
+            .collect();
+        assert_eq!(digital.len(), 8);
+        assert!(
+            digital
+                .iter()
+                .step_by(2)
+                .all(|change| change.old == 0 && change.new == 1)
+        );
+        assert!(
+            digital
+                .iter()
+                .skip(1)
+                .step_by(2)
+                .all(|change| change.old == 1 && change.new == 0)
+        );
+        assert!(log.windows(2).all(|pair| pair[0].cycle < pair[1].cycle));
+
+        assert_eq!(log.len(), 9);
+        let analog = log.last().unwrap();
+        assert_eq!(analog.pin, Pin::Analog(AnalogPin::Analog0));
+        assert_eq!((analog.old, analog.new), (0, 100));
+
+        // A reset puts the analog pin back to 0, the digital pin is already low so says nothing
+        drop(log);
+        tpu.reset();
+        assert_eq!(changes.borrow().len(), 10);
+        assert_eq!(
+            changes.borrow()[9],
+            PinChange {
+                cycle: 0,
+                pin: Pin::Analog(AnalogPin::Analog0),
+                old: 100,
+                new: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_trace_callback() {
+        let program = rgal::parse_program(
+            r#"LDR A, 5
+            STM 0x10, A
+            SLP 3
+            DPW 2, 1
+            DIV A, X"#,
+        )
+        .expect("parse failure");
+        let mut tpu = create_basic_tpu_config(program);
+
+        let events = Rc::new(std::cell::RefCell::new(Vec::<TraceEvent>::new()));
+        let recorded = events.clone();
+        tpu.set_trace_callback(Box::new(move |event| {
+            recorded.borrow_mut().push(event.clone())
+        }));
+        while !tpu.halted() {
+            tpu.tick();
+        }
+
+        // One event per instruction, the halting one included, the SLP only once it's been waited out
+        let log = events.borrow();
+        let pcs: Vec<usize> = log.iter().map(|event| event.pc).collect();
+        assert_eq!(pcs, [0, 1, 2, 3, 4]);
+        assert!(log.windows(2).all(|pair| pair[0].cycle < pair[1].cycle));
+
+        assert_eq!(log[0].registers[Register::A as usize], 5);
+        assert_eq!(log[0].ram_write, None);
+        assert_eq!(log[1].ram_write, Some((0x10, 5)));
+        assert_eq!(*log[1].instruction, tpu.read_rom()[1].as_ref().clone());
+        assert_eq!(log[2].ram_write, None);
+        assert_eq!(log[2].digital_pins, 0);
+        assert_eq!(log[3].digital_pins, 0b100);
+        drop(log);
+
+        tpu.clear_trace_callback();
+        tpu.reset();
+        tpu.tick();
+        assert_eq!(events.borrow().len(), 5);
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let program = rgal::parse_program(
+            r#"LDR X, 1
+            ADD A, X
+            SLP 3
+            BLT 1, A, 3
+            HLT"#,
+        )
+        .expect("parse failure");
+        let mut tpu = create_basic_tpu_config(program);
+        assert!(tpu.toggle_breakpoint(1));
+        assert!(tpu.toggle_breakpoint(3));
+
+        // Each pass of the loop stops before the ADD and the branch, after the SLP has been waited out
+        let mut stops = Vec::new();
+        loop {
+            match tpu.run(1000) {
+                RunResult::Breakpoint(address) => {
+                    stops.push((address, tpu.read_register(Register::A)))
+                }
+                RunResult::Halted(reason) => {
+                    assert_eq!(reason, HaltReason::HLTOpcode);
+                    break;
+                }
+                RunResult::OutOfTicks => panic!("never halted"),
+            }
+        }
+        assert_eq!(stops, [(1, 0), (3, 1), (1, 1), (3, 2), (1, 2), (3, 3)]);
+        assert_eq!(tpu.run(10), RunResult::Halted(HaltReason::HLTOpcode));
+
+        // Cleared breakpoints don't stop it, and running out of ticks is reported
+        assert!(!tpu.toggle_breakpoint(1));
+        assert!(!tpu.toggle_breakpoint(3));
+        tpu.load_program(tpu.read_rom().clone());
+        assert_eq!(tpu.run(2), RunResult::OutOfTicks);
+
+        // One tick at a time still stops, and carries on once it has
+        tpu.toggle_breakpoint(3);
+        assert!((0..20).any(|_| tpu.run(1) == RunResult::Breakpoint(3)));
+        assert_eq!(tpu.state().program_counter, 3);
+        assert_eq!(tpu.run(1), RunResult::OutOfTicks);
+    }
+
+    #[test]
+    fn test_builder_defaults() {
+        let program = rgal::parse_program("HLT").expect("parse failure");
+        let built = TpuBuilder::new()
+            .program(program.clone())
+            .build()
+            .expect("build");
+        let basic = TPU::new(
+            0x1,
+            [false; AnalogPin::COUNT],
+            [false; DigitalPin::COUNT],
+            program.clone(),
+        );
+        assert!(built.state().diff(basic.state()).is_empty());
+        assert_eq!(built.state().rom, program);
+        assert_eq!(built.state().network_address, 0x1);
+        assert_eq!(built.state().analog_pin_config, [false; AnalogPin::COUNT]);
+        assert_eq!(built.state().digital_pin_config, [false; DigitalPin::COUNT]);
+        assert_eq!(built.state().nvram.len(), TPU::NVRAM_SIZE);
+        assert_eq!(built.state().config.bounce_seed, 0);
+
+        // The basic config doesn't mind an empty program
+        assert!(create_basic_tpu_config(vec![]).read_rom().is_empty());
+    }
+
+    #[test]
+    fn test_builder_setters() {
+        let program = rgal::parse_program("NOP\nHLT").expect("parse failure");
+        let tpu = TpuBuilder::new()
+            .config(TpuConfig {
+                memory_mapped_io: true,
+                ..TpuConfig::default()
+            })
+            .network_address(0x2)
+            .analog_pin(AnalogPin::Analog3, PinMode::Input)
+            .digital_pin(DigitalPin::Digital0, PinMode::Input)
+            .digital_pin(DigitalPin::Digital5, PinMode::Input)
+            .digital_pin(DigitalPin::Digital5, PinMode::Output)
+            .program(program.clone())
+            .nvram_size(4)
+            .seed(42)
+            .build()
+            .expect("build");
+
+        let state = tpu.state();
+        assert_eq!(state.network_address, 0x2);
+        assert_eq!(state.analog_pin_config, [false, false, false, true]);
+        assert_eq!(
+            state.digital_pin_config,
+            [true, false, false, false, false, false, false, false]
+        );
+        assert_eq!(state.rom, program);
+        assert_eq!(state.nvram.len(), 4);
+        assert_eq!(state.config.bounce_seed, 42);
+        assert_eq!(state.config.noise_seed, 42);
+        assert!(state.config.memory_mapped_io);
+    }
+
+    #[test]
+    fn test_builder_validation() {
+        let program = rgal::parse_program("NOP\nHLT").expect("parse failure");
+        let builder = TpuBuilder::new().program(program);
+
+        assert_eq!(
+            TpuBuilder::new().build().err(),
+            Some(ConfigError::EmptyProgram)
+        );
+        assert_eq!(
+            builder
+                .clone()
+                .config(TpuConfig {
+                    analog_resolution_bits: 17,
+                    ..TpuConfig::default()
+                })
+                .build()
+                .err(),
+            Some(ConfigError::AnalogResolution(17))
+        );
+        assert_eq!(
+            builder
+                .clone()
+                .config(TpuConfig {
+                    trap_vector: Some(2),
+                    ..TpuConfig::default()
+                })
+                .build()
+                .err(),
+            Some(ConfigError::TrapVector(2))
+        );
+
+        let mut network = Network::new();
+        assert_eq!(builder.clone().build_on(&mut network), Ok(0));
+        assert_eq!(
+            builder.clone().build_on(&mut network),
+            Err(ConfigError::AddressInUse(0x1))
+        );
+        assert_eq!(builder.network_address(0x2).build_on(&mut network), Ok(1));
+        assert_eq!(network.len(), 2);
+        assert_eq!(
+            ConfigError::AddressInUse(0x1).to_string(),
+            "a TPU on the network already has address 0x0001"
+        );
+    }
+
+    #[test]
+    fn test_run_until_pc() {
+        let program = rgal::parse_program(
+            r#"LDR A, 3
+            DEC A
+            BNZ 1, A
+            SLP 4
+            HLT"#,
+        )
+        .expect("parse failure");
+        let mut tpu = create_basic_tpu_config(program);
+
+        // Reached, without leaving a breakpoint behind
+        assert_eq!(tpu.run_until_pc(2, 100), RunResult::Breakpoint(2));
+        assert_eq!(tpu.read_register(Register::A), 2);
+        assert!(tpu.breakpoints().is_empty());
+
+        // Already there, so it goes round the loop once more first
+        assert_eq!(tpu.run_until_pc(2, 100), RunResult::Breakpoint(2));
+        assert_eq!(tpu.read_register(Register::A), 1);
+
+        // A breakpoint on the way still stops it
+        tpu.toggle_breakpoint(1);
+        assert_eq!(tpu.run_until_pc(3, 100), RunResult::Breakpoint(1));
+        tpu.toggle_breakpoint(1);
+
+        // Never reached in the cycles given, and the halt when it isn't reached at all
+        assert_eq!(tpu.run_until_pc(4, 3), RunResult::OutOfTicks);
+        assert_ne!(tpu.state().program_counter, 4);
+        assert_eq!(tpu.run_until_pc(4, 100), RunResult::Breakpoint(4));
+        assert_eq!(
+            tpu.run_until_pc(0, 100),
+            RunResult::Halted(HaltReason::HLTOpcode)
+        );
+        assert_eq!(
+            tpu.run_until_pc(0, 100),
+            RunResult::Halted(HaltReason::HLTOpcode)
+        );
+    }
+
+    #[test]
+    fn test_breakpoints_survive_load_program() {
+        let mut tpu = create_basic_tpu_config(
+            rgal::parse_program("NOP\nNOP\nNOP\nNOP").expect("parse failure"),
+        );
+        tpu.toggle_breakpoint(1);
+        tpu.toggle_breakpoint(3);
+        while !tpu.halted() {
+            tpu.tick();
+        }
+
+        tpu.load_program(rgal::parse_program("NOP\nNOP\nHLT").expect("parse failure"));
+        assert!(!tpu.halted());
+        assert_eq!(tpu.state().program_counter, 0);
+        assert_eq!(tpu.read_rom().len(), 3);
+        assert_eq!(tpu.breakpoints().iter().copied().collect::<Vec<_>>(), [1]);
+        assert_eq!(tpu.run(100), RunResult::Breakpoint(1));
+    }
+
+    #[test]
+    fn test_state_diff() {
+        let program = rgal::parse_program(
+            r#"LDR X, 5
+            PUSH X
+            STM 0x10, X
+            CMP X, 5
+            DPW 3, 1
+            POP Y
+            HLT"#,
+        )
+        .expect("parse failure");
+        let mut tpu = create_basic_tpu_config(program);
+
+        // One instruction at a time
+        let mut diffs = Vec::new();
+        while !tpu.halted() {
+            let before = tpu.state().clone();
+            tpu.step();
+            diffs.push(tpu.state().diff(&before));
+        }
+
+        let expected = [
+            StateDiff {
+                registers: vec![Register::X],
+                ..Default::default()
+            },
+            StateDiff {
+                stack: vec![0],
+                ..Default::default()
+            },
+            StateDiff {
+                ram: vec![0x10],
+                ..Default::default()
+            },
+            StateDiff {
+                flags: true,
+                ..Default::default()
+            },
+            StateDiff {
+                digital_pins: vec![DigitalPin::Digital3],
+                ..Default::default()
+            },
+            // A pop isn't a change to the stack
+            StateDiff {
+                registers: vec![Register::Y],
+                ..Default::default()
+            },
+            StateDiff::default(),
+        ];
+        assert_eq!(diffs, expected);
+        assert!(diffs[6].is_empty());
+        assert!(!diffs[0].is_empty());
+    }
+
+    #[test]
+    fn test_deliver_packet() {
+        let program = rgal::parse_program("WRX\nHLT").expect("parse failure");
+        let mut tpu = create_basic_tpu_config(program);
+
+        // WRX waits as long as nothing arrives, and takes the packet itself
+        for _ in 0..20 {
+            tpu.tick();
+        }
+        assert_eq!(tpu.state().program_counter, 0);
+
+        // And wakes on the next tick after something does
+        assert!(tpu.deliver_packet(NetPacket::new(0x2, 0x1, 42)));
+        tpu.tick();
+        assert_eq!(tpu.state().program_counter, 1);
+        while !tpu.halted() {
+            tpu.tick();
+        }
+        assert_eq!(tpu.read_register(Register::X), 0x2);
+        assert_eq!(tpu.read_register(Register::Y), 42);
+
+        // A full buffer drops the packet and says so
+        for data in 0..TPU::NET_BUFFER_SIZE as u16 {
+            assert!(tpu.deliver_packet(NetPacket::new(0x2, 0x1, data)));
+        }
+        assert!(!tpu.deliver_packet(NetPacket::new(0x2, 0x1, 99)));
+        assert_eq!(tpu.state().incoming_packets.len(), TPU::NET_BUFFER_SIZE);
+        assert_eq!(tpu.state().incoming_packets.back().unwrap().data(), 7);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_state_json_round_trip() {
+        let program = rgal::parse_program(
+            r#"PUSH 7
+            PUSH 9
+            LDR A, 0x2
+            XMIT A, 5
+            UTX 65
+            JMP 5"#,
+        )
+        .expect("parse failure");
+        let config = TpuConfig {
+            pin_event_queue_size: 4,
+            ..TpuConfig::default()
+        };
+        let mut tpu = TPU::new_with_config(
+            0x1,
+            [true; AnalogPin::COUNT],
+            [true; DigitalPin::COUNT],
+            program,
+            config,
+        );
+        assert_eq!(tpu.run_until_pc(5, 100), RunResult::Breakpoint(5));
+        assert!(tpu.deliver_packet(NetPacket::new(0x2, 0x1, 3)));
+        tpu.drive_digital_input(DigitalPin::Digital1, true).unwrap();
+        tpu.drive_analog_input(AnalogPin::Analog2, 300).unwrap();
+        tpu.write_ram_slice(0x40, &[1, 2, 3]);
+
+        let state = tpu.state();
+        assert_eq!(state.stack, [7, 9]);
+        assert!(!state.incoming_packets.is_empty());
+        assert!(!state.outgoing_packets.is_empty());
+        assert!(!state.pin_events.is_empty());
+        assert!(!state.uart_tx.is_empty());
+
+        let json = serde_json::to_string(state).expect("serialize");
+        let restored: TpuState = serde_json::from_str(&json).expect("deserialize");
+        assert!(restored.diff(state).is_empty());
+        assert_eq!(restored.rom, state.rom);
+        assert_eq!(restored.incoming_packets, state.incoming_packets);
+        assert_eq!(restored.outgoing_packets, state.outgoing_packets);
+        assert_eq!(restored.uart_tx, state.uart_tx);
+        assert_eq!(restored.ram[0x40..0x43], [1, 2, 3]);
+        // Nothing is lost on the way, not even what `diff` doesn't look at
+        assert_eq!(serde_json::to_string(&restored).expect("serialize"), json);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_snapshot_round_trip() {
+        let program = rgal::parse_program(
+            r#"LDR R0, 5
+            PUSH R0
+            STM 0x70, R0
+            DJNZ 1, R0
+            HLT"#,
+        )
+        .expect("parse failure");
+        let mut tpu = create_basic_tpu_config(program.clone());
+        for _ in 0..7 {
+            tpu.tick();
+        }
+
+        let json = tpu.snapshot().to_json().expect("serialize");
+        let snapshot = TpuSnapshot::from_json(&json).expect("deserialize");
+        let mut restored = create_basic_tpu_config(vec![]);
+        restored.toggle_breakpoint(2);
+        restored.toggle_breakpoint(10);
+        restored.restore(snapshot);
+        assert!(restored.state().diff(tpu.state()).is_empty());
+        assert_eq!(restored.state().rom, program);
+        assert_eq!(restored.breakpoints().iter().collect::<Vec<_>>(), [&2]);
+        restored.toggle_breakpoint(2);
+
+        // Both carry on from the same place, mid instruction included
+        while !tpu.halted() {
+            tpu.tick();
+            restored.tick();
+            assert_eq!(
+                restored.state().program_counter,
+                tpu.state().program_counter
+            );
+        }
+        assert!(restored.halted());
+        assert!(restored.state().diff(tpu.state()).is_empty());
+        assert_eq!(restored.state().cycle_count, tpu.state().cycle_count);
+
+        let older = json.replacen(
+            &format!("\"version\":{}", TpuSnapshot::VERSION),
+            "\"version\":0",
+            1,
+        );
+        assert!(matches!(
+            TpuSnapshot::from_json(&older),
+            Err(crate::Error::Snapshot(SnapshotError::Version(0)))
+        ));
+        assert!(matches!(
+            TpuSnapshot::from_json("{}"),
+            Err(crate::Error::Snapshot(SnapshotError::Json(_)))
+        ));
+    }
+
+    #[test]
+    fn test_execute_now() {
+        let program = rgal::parse_program("LDR A, 1\nLDR A, 2\nHLT").expect("parse failure");
+        let mut tpu = create_basic_tpu_config(program);
+        tpu.step();
+
+        let ldr = rgal::parse_instruction("LDR X, 42").expect("parse failure");
+        assert_eq!(tpu.execute_now(&ldr), Ok(true));
+        assert_eq!(tpu.read_register(Register::X), 42);
+        // No time passes and the program carries on where it was
+        assert_eq!(tpu.state().cycle_count, 1);
+        assert_eq!(tpu.state().program_counter, 1);
+
+        let jump = rgal::parse_instruction("JMP 0").expect("parse failure");
+        assert_eq!(tpu.execute_now(&jump), Ok(true));
+        assert_eq!(tpu.state().program_counter, 0);
+
+        assert_eq!(
+            tpu.execute_now(&Instruction::WRX),
+            Ok(false),
+            "nothing to receive"
+        );
+
+        let div = rgal::parse_instruction("DIV A, R1").expect("parse failure");
+        assert_eq!(tpu.execute_now(&div), Err(HaltReason::Div0));
+        assert!(tpu.halted());
+    }
+
+    fn eval(tpu: &TPU, text: &str) -> Result<i64, EvalError> {
+        Expr::parse(text)
+            .unwrap_or_else(|error| panic!("{text}: {error}"))
+            .eval(tpu)
+    }
+
+    #[test]
+    fn test_expr_eval() {
+        let mut tpu = TPU::new(
+            0x1,
+            [true; AnalogPin::COUNT],
+            [true; DigitalPin::COUNT],
+            rgal::parse_program("PUSH 1\nPUSH 2\nHLT").expect("parse failure"),
+        );
+        tpu.step();
+        tpu.step();
+        tpu.write_register(Register::A, 0x12);
+        tpu.write_register(Register::R6, 3);
+        tpu.write_ram_slice(0x20, &[5]);
+        tpu.drive_digital_input(DigitalPin::Digital0, true).unwrap();
+        tpu.drive_digital_input(DigitalPin::Digital1, true).unwrap();
+        tpu.drive_analog_input(AnalogPin::Analog2, 512).unwrap();
+        tpu.deliver_packet(NetPacket::new(0x2, 0x1, 7));
+
+        for (text, value) in [
+            ("A", 0x12),
+            ("a", 0x12),
+            ("r6", 3),
+            ("ram[0x20]", 5),
+            ("RAM[0x1F + 1]", 5),
+            ("pin(0)", 1),
+            ("pin(2)", 0),
+            ("apin(2)", 512),
+            ("dpins", 0b11),
+            ("dpins & 0b11", 3),
+            ("rxbs", 1),
+            ("txbs", 0),
+            ("depth", 2),
+            ("pc", 2),
+            ("cycles", tpu.state().cycle_count as i64),
+            ("1_000", 1000),
+            // Rust's precedence, comparisons bind looser than bitwise operators
+            ("1 + 2 * 3", 7),
+            ("(1 + 2) * 3", 9),
+            ("dpins & 0b11 == 3", 1),
+            ("1 << 4 | 1", 17),
+            ("10 - 4 - 3", 3),
+            ("7 / 2", 3),
+            ("7 % 2", 1),
+            ("A - 0x13", -1),
+            ("-A", -0x12),
+            ("!A", 0),
+            ("!!A", 1),
+            ("~0", -1),
+            ("0x12 ^ 0xFF", 0xED),
+            ("A == 0x12 && ram[0x20] > 3", 1),
+            ("A != 0x12 || ram[0x20] >= 6", 0),
+            ("1 < 2", 1),
+            ("2 <= 2", 1),
+            ("1 >> 70", 0),
+            // The right side isn't evaluated when the left decides it
+            ("0 && ram[1000]", 0),
+            ("1 || 1 / 0", 1),
+        ] {
+            assert_eq!(eval(&tpu, text), Ok(value), "{text}");
+        }
+        // Watching doesn't count as reading RAM
+        tpu.enable_ram_stats();
+        eval(&tpu, "ram[0x20]").unwrap();
+        assert!(
+            tpu.ram_stats()
+                .unwrap()
+                .reads()
+                .iter()
+                .all(|&reads| reads == 0)
+        );
+    }
+
+    #[test]
+    fn test_expr_eval_errors() {
+        let tpu = create_basic_tpu_config(vec![]);
+        assert_eq!(
+            eval(&tpu, "ram[0x80]"),
+            Err(EvalError::AddressOutOfRange(0x80))
+        );
+        assert_eq!(
+            eval(&tpu, "ram[0 - 1]"),
+            Err(EvalError::AddressOutOfRange(-1))
+        );
+        assert_eq!(eval(&tpu, "pin(8)"), Err(EvalError::NoDigitalPin(8)));
+        assert_eq!(eval(&tpu, "apin(-1)"), Err(EvalError::NoAnalogPin(-1)));
+        assert_eq!(eval(&tpu, "A / 0"), Err(EvalError::DivideByZero));
+        assert_eq!(eval(&tpu, "1 % (A & 0)"), Err(EvalError::DivideByZero));
+        assert_eq!(
+            EvalError::AddressOutOfRange(0x80).to_string(),
+            "0x80 isn't in RAM"
+        );
+    }
+
+    #[test]
+    fn test_expr_parse_errors() {
+        for (text, error) in [
+            ("", ExprError::Empty),
+            ("  ", ExprError::Empty),
+            ("A +", ExprError::UnexpectedEnd),
+            ("(A", ExprError::UnexpectedEnd),
+            ("ram[1", ExprError::UnexpectedEnd),
+            (
+                "ram(1)",
+                ExprError::Unexpected {
+                    position: 3,
+                    found: "(".to_string(),
+                },
+            ),
+            (
+                "A B",
+                ExprError::Unexpected {
+                    position: 2,
+                    found: "B".to_string(),
+                },
+            ),
+            (
+                "A = 1",
+                ExprError::Unexpected {
+                    position: 2,
+                    found: "=".to_string(),
+                },
+            ),
+            (
+                "1 + $",
+                ExprError::Unexpected {
+                    position: 4,
+                    found: "$".to_string(),
+                },
+            ),
+            (
+                "R7 + 1",
+                ExprError::UnknownName {
+                    position: 0,
+                    name: "R7".to_string(),
+                },
+            ),
+            (
+                "A & 0xZZ",
+                ExprError::InvalidNumber {
+                    position: 4,
+                    number: "0xZZ".to_string(),
+                },
+            ),
+            (
+                "99999999999999999999",
+                ExprError::InvalidNumber {
+                    position: 0,
+                    number: "99999999999999999999".to_string(),
+                },
+            ),
+        ] {
+            assert_eq!(Expr::parse(text), Err(error), "{text}");
+        }
+
+        let expr: Expr = " ram[0x20] + 1 ".parse().unwrap();
+        assert_eq!(expr.to_string(), "ram[0x20] + 1");
+    }
+
+    #[test]
+    fn test_step_back() {
+        let program = rgal::parse_program("LDR A, 1\nADD A, A\nSTM 0x10, A\nWRX\nHLT")
+            .expect("parse failure");
+        let mut tpu = create_basic_tpu_config(program);
+        assert_eq!(tpu.history_len(), None);
+        assert!(!tpu.step_back());
+
+        tpu.enable_history(3);
+        assert_eq!(tpu.history_len(), Some(0));
+        tpu.step();
+        tpu.step();
+        let before_store = (tpu.state().cycle_count, tpu.read_register(Register::A));
+        tpu.step();
+        assert_eq!(tpu.read_ram(0x10), 2);
+        assert_eq!(tpu.history_len(), Some(3));
+
+        // Each step back undoes one instruction
+        assert!(tpu.step_back());
+        assert_eq!(tpu.state().program_counter, 2);
+        assert_eq!(tpu.read_ram(0x10), 0);
+        assert_eq!(
+            (tpu.state().cycle_count, tpu.read_register(Register::A)),
+            before_store
+        );
+        assert!(tpu.step_back());
+        assert!(tpu.step_back());
+        assert_eq!(tpu.state().program_counter, 0);
+        assert_eq!(tpu.read_register(Register::A), 0);
+        assert!(!tpu.step_back());
+        assert_eq!(tpu.read_rom().len(), 5);
+
+        // Only the last few are kept
+        for _ in 0..3 {
+            tpu.step();
+        }
+        for _ in 0..5 {
+            tpu.tick();
+        }
+        assert_eq!(tpu.state().program_counter, 3);
+        assert_eq!(tpu.history_len(), Some(3));
+        // All the ticks spent waiting in WRX go in one step back
+        tpu.deliver_packet(NetPacket::new(0x2, 0x1, 7));
+        tpu.step();
+        assert_eq!(tpu.state().program_counter, 4);
+        assert!(tpu.step_back());
+        assert_eq!(tpu.state().program_counter, 3);
+        assert!(tpu.state().incoming_packets.is_empty());
+        assert!(tpu.step_back());
+        assert_eq!(tpu.state().program_counter, 2);
+
+        // A breakpoint stepped back onto is run past like any other
+        tpu.toggle_breakpoint(2);
+        tpu.step_back();
+        tpu.toggle_breakpoint(1);
+        assert_eq!(tpu.state().program_counter, 1);
+        assert_eq!(tpu.run(100), RunResult::Breakpoint(2));
+
+        // Starting again forgets where it's been
+        tpu.load_program(rgal::parse_program("HLT").expect("parse failure"));
+        assert_eq!(tpu.history_len(), Some(0));
+        tpu.disable_history();
+        tpu.tick();
+        assert!(!tpu.step_back());
+    }
+
+    #[test]
+    fn test_step_back_self_loop() {
+        let program = rgal::parse_program("LDR X, 3\nDJNZ 1, X\nHLT").expect("parse failure");
+        let mut tpu = create_basic_tpu_config(program);
+        tpu.enable_history(10);
+        // `step` would run the whole loop, as it waits for the PC to move
+        assert_eq!(tpu.instructions().take(3).count(), 3);
+        assert_eq!(tpu.state().program_counter, 1);
+        assert_eq!(tpu.read_register(Register::X), 1);
+
+        // Each time round the loop is its own step back
+        assert!(tpu.step_back());
+        assert_eq!(tpu.state().program_counter, 1);
+        assert_eq!(tpu.read_register(Register::X), 2);
+        assert!(tpu.step_back());
+        assert_eq!(tpu.state().program_counter, 1);
+        assert_eq!(tpu.read_register(Register::X), 3);
+        assert!(tpu.step_back());
+        assert_eq!(tpu.state().program_counter, 0);
+        assert!(!tpu.step_back());
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_snapshot_without_return_addresses() {
+        let program =
+            rgal::parse_program("PUSH 7\nJSR 3\nHLT\nJSR 5\nRTS\nRTS").expect("parse failure");
+        let mut tpu = create_basic_tpu_config(program.clone());
+        tpu.step();
+        tpu.step();
+
+        // Saved before the stack's return addresses were tagged
+        let mut json: serde_json::Value =
+            serde_json::from_str(&tpu.snapshot().to_json().expect("serialize")).expect("json");
+        json["state"]
+            .as_object_mut()
+            .unwrap()
+            .remove("return_addresses");
+        let snapshot = TpuSnapshot::from_json(&json.to_string()).expect("deserialize");
+        let mut restored = create_basic_tpu_config(program);
+        restored.restore(snapshot);
+        assert_eq!(restored.state().return_addresses, [false, false]);
+
+        // The next call is tagged on its own entry
+        restored.step();
+        assert_eq!(
+            restored.stack_entries(),
+            [
+                StackEntry::ReturnAddress {
+                    address: 4,
+                    instruction: Some(restored.read_rom()[4].clone()),
+                },
+                StackEntry::Value(2),
+                StackEntry::Value(7)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stack_entries() {
+        let program = rgal::parse_program(
+            "PUSH 7\nJSR 4\nHLT\nNOP\nPUSH 9\nJSR 7\nRTS\nPOP R0\nPUSH R0\nRTS",
+        )
+        .expect("parse failure");
+        let mut tpu = create_basic_tpu_config(program);
+        let ret = |tpu: &TPU, address: u16| StackEntry::ReturnAddress {
+            address,
+            instruction: Some(tpu.read_rom()[usize::from(address)].clone()),
+        };
+
+        for _ in 0..4 {
+            tpu.step();
+        }
+        assert_eq!(tpu.state().program_counter, 7);
+        assert_eq!(
+            tpu.stack_entries(),
+            [
+                ret(&tpu, 6),
+                StackEntry::Value(9),
+                ret(&tpu, 2),
+                StackEntry::Value(7)
+            ]
+        );
+        assert_eq!(tpu.stack_entries()[0].to_string(), "ret → 0x0006 (RTS)");
+        assert_eq!(tpu.stack_entries()[1].to_string(), "0x0009");
+
+        // Popping a return address and pushing it back makes it a plain value
+        tpu.step();
+        tpu.step();
+        assert_eq!(tpu.stack_entries()[0], StackEntry::Value(6));
+        assert_eq!(tpu.stack_entries()[1], StackEntry::Value(9));
+        assert_eq!(tpu.stack_entries().len(), 4);
+
+        // A return address outside the program has nothing to show
+        let entry = StackEntry::ReturnAddress {
+            address: 0x40,
+            instruction: None,
+        };
+        assert_eq!(entry.to_string(), "ret → 0x0040");
+
+        // ENTER's frame is plain values, LEAVE and SCR drop their tags with them
+        let program = rgal::parse_program("JSR 2\nHLT\nENTER 2\nLEAVE\nSCR\nJSR 6\nHLT")
+            .expect("parse failure");
+        let mut tpu = create_basic_tpu_config(program);
+        tpu.step();
+        tpu.step();
+        assert_eq!(
+            tpu.stack_entries(),
+            [
+                StackEntry::Value(0),
+                StackEntry::Value(0),
+                StackEntry::Value(0),
+                ret(&tpu, 1)
+            ]
+        );
+        tpu.step();
+        assert_eq!(tpu.stack_entries(), [ret(&tpu, 1)]);
+        tpu.step();
+        assert!(tpu.stack_entries().is_empty());
+        assert!(tpu.state().return_addresses.is_empty());
+        tpu.step();
+        assert_eq!(tpu.stack_entries(), [ret(&tpu, 6)]);
+    }
+
+    #[test]
+    fn test_instructions_retired() {
+        let program = rgal::parse_program("LDR A, 3\nDEC A\nBNZ 1, A\nHLT").expect("parse failure");
+        let mut tpu = create_basic_tpu_config(program.clone());
+        while !tpu.halted() {
+            tpu.tick();
+        }
+
+        // The loop runs three times, the HLT that stopped it isn't finished
+        assert_eq!(tpu.state().instructions_retired, 7);
+        assert!(tpu.state().cycle_count > 7);
+
+        // Nor is an instruction run by the host
+        let ldr = rgal::parse_instruction("LDR X, 1").expect("parse failure");
+        tpu.load_program(program);
+        assert_eq!(tpu.state().instructions_retired, 0);
+        tpu.execute_now(&ldr).unwrap();
+        assert_eq!(tpu.state().instructions_retired, 0);
+        tpu.step();
+        assert_eq!(tpu.state().instructions_retired, 1);
+    }
 }
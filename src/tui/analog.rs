@@ -0,0 +1,72 @@
+use std::collections::VecDeque;
+use strum::EnumCount;
+use tls::shared::AnalogPin;
+use tls::tpu::TpuState;
+
+/// How often `AnalogHistory` samples the pins
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Sampling {
+    /// Once a frame, so a sparkline covers the same time whatever the speed
+    #[default]
+    Frame,
+    /// Every tick while running, so a sparkline shows every value the program saw. Stepping an instruction still
+    /// samples once.
+    Tick,
+}
+
+/// The recent values of each analog pin of the TPU being shown, for the sparklines in the I/O panel
+#[derive(Clone, Debug, Default)]
+pub struct AnalogHistory {
+    pub sampling: Sampling,
+    /// Oldest first, never more than `AnalogHistory::CAPACITY` of them
+    pins: [VecDeque<u16>; AnalogPin::COUNT],
+    /// `TpuState::cycle_count` at the last sample
+    cycle: Option<u64>,
+}
+
+impl AnalogHistory {
+    /// Samples kept for each pin, more than a sparkline is ever wide
+    pub const CAPACITY: usize = 256;
+
+    /// Sample the pins, unless the TPU hasn't moved on since the last sample. A cycle count that's gone back means the
+    /// TPU was reset, loaded with a program, restored or stepped back, so the samples from before are dropped.
+    pub fn sample(&mut self, state: &TpuState) {
+        match self.cycle {
+            Some(cycle) if cycle == state.cycle_count => return,
+            Some(cycle) if cycle > state.cycle_count => self.clear(),
+            _ => {}
+        }
+        self.cycle = Some(state.cycle_count);
+        for (samples, &value) in self.pins.iter_mut().zip(&state.analog_pins) {
+            if samples.len() >= Self::CAPACITY {
+                samples.pop_front();
+            }
+            samples.push_back(value);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.pins.iter_mut().for_each(VecDeque::clear);
+        self.cycle = None;
+    }
+
+    /// The samples of `pin`, oldest first
+    pub fn samples(&self, pin: AnalogPin) -> &VecDeque<u16> {
+        &self.pins[pin as usize]
+    }
+}
+
+/// `samples` squeezed into `width` columns for a sparkline, each column the highest of the samples it covers so a
+/// spike isn't lost. There are never more columns than samples.
+pub fn downsample(samples: &VecDeque<u16>, width: usize) -> Vec<u64> {
+    let len = samples.len();
+    if len <= width {
+        return samples.iter().map(|&value| u64::from(value)).collect();
+    }
+    (0..width)
+        .map(|column| {
+            let range = column * len / width..(column + 1) * len / width;
+            samples.range(range).max().copied().map_or(0, u64::from)
+        })
+        .collect()
+}
@@ -0,0 +1,624 @@
+use crate::tui::analog::{AnalogHistory, Sampling};
+use crate::tui::clock::{Clock, RateMeter, thousands};
+use crate::tui::edit::{AnalogEdit, EditError, PacketEdit, RamEdit, RegisterEdit};
+use crate::tui::highlight::Highlights;
+use crate::tui::log::{LogBuffer, next_level};
+use crate::tui::machines::Machines;
+use crate::tui::mouse::{Panel, SCROLL_LINES, ScreenLayout};
+use crate::tui::ram::RamView;
+use crate::tui::rom::RomView;
+use crate::tui::search::Search;
+use crate::tui::snapshot::{self, LoadError};
+use crate::tui::trace::Trace;
+use crate::tui::watch::Watches;
+use crossterm::event::{KeyCode, MouseButton, MouseEvent, MouseEventKind};
+use std::ops::Range;
+use std::path::Path;
+use std::time::Duration;
+use tls::network::NetworkRunResult;
+use tls::shared::{DigitalPin, Register};
+use tls::tpu::{Expr, TPU, TpuSnapshot};
+
+/// Ticks running to the cursor gives up after, in case the program never gets there
+pub const RUN_TO_CURSOR_TICKS: u64 = 1_000_000;
+
+/// What the main loop should do after a key
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Action {
+    Continue,
+    /// Load the program again from its file, see `TPU::load_program`
+    Reload,
+    Quit,
+}
+
+/// What the edit prompt changes
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PromptKind {
+    Register,
+    Ram,
+    AnalogInput,
+    Packet,
+    /// The path of a snapshot to restore
+    Snapshot,
+    /// An expression to watch, or to stop watching
+    Watch,
+    /// Text to find in the ROM, or a number to find in the ROM and RAM
+    Search,
+    /// When the breakpoint at the cursor stops, see `TPU::set_breakpoint`
+    Condition,
+}
+
+/// The edit prompt and what's been typed at it
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Prompt {
+    pub kind: PromptKind,
+    pub input: String,
+}
+
+impl Prompt {
+    pub fn new(kind: PromptKind) -> Self {
+        Self {
+            kind,
+            input: String::new(),
+        }
+    }
+}
+
+/// The debugger's state between frames, and what the keys do to it, kept apart from the terminal so it can be tested
+#[derive(Clone, Default)]
+pub struct App {
+    pub clock: Clock,
+    pub running: bool,
+    pub rom_view: RomView,
+    pub ram_view: RamView,
+    /// Why running last stopped, or why a key did nothing, shown in the title until the TPU is stepped or run again
+    pub banner: Option<String>,
+    /// The register or RAM edit prompt, `None` when it isn't open
+    pub prompt: Option<Prompt>,
+    /// Why the last edit was refused, shown under the prompt
+    pub edit_error: Option<String>,
+    /// Registers poked since the TPU last moved on
+    pub edited: Vec<Register>,
+    /// RAM poked since the TPU last moved on
+    pub poked: Vec<Range<usize>>,
+    /// What changed in the last few ticks
+    pub highlights: Highlights,
+    /// Lines scrolled down the network panel
+    pub network_scroll: u16,
+    /// Entries scrolled down the stack panel, from the top of the stack
+    pub stack_scroll: u16,
+    /// A snapshot of another program, restored over the loaded one if the next key is Y
+    pub replace: Option<Box<TpuSnapshot>>,
+    /// Expressions evaluated every frame, see `Expr`
+    pub watches: Watches,
+    /// Where the last frame drew each panel, for the mouse
+    pub layout: ScreenLayout,
+    /// How fast the TPUs are running, measured on the one being shown
+    pub rate: RateMeter,
+    /// What the TPUs logged, see `LogLayer`
+    pub log: LogBuffer,
+    /// Whether the log panel is open
+    pub show_log: bool,
+    /// The last search and its matches, until Esc
+    pub search: Option<Search>,
+    /// The trace being recorded, if there is one
+    pub trace: Trace,
+    /// Recent values of the analog pins, for the sparklines
+    pub analog_history: AnalogHistory,
+}
+
+impl App {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stepping and running move every TPU on, the rest of the keys act on the one being shown
+    pub fn handle_key(&mut self, key: KeyCode, machines: &mut Machines) -> Action {
+        // The edit prompt takes every key until it's closed
+        if let Some(prompt) = &self.prompt {
+            if prompt.kind == PromptKind::Search && key == KeyCode::Enter {
+                let query = prompt.input.clone();
+                self.search(machines, &query);
+            } else {
+                self.handle_edit_key(key, machines.focused_mut());
+            }
+            return Action::Continue;
+        }
+        if let Some(snapshot) = self.replace.take() {
+            if key == KeyCode::Char('y') || key == KeyCode::Char('Y') {
+                self.restore(machines.focused_mut(), *snapshot);
+            } else {
+                self.banner = None;
+            }
+            return Action::Continue;
+        }
+
+        let program_counter = machines.focused().state().program_counter;
+        let len = machines.focused().read_rom().len();
+        let tpu = machines.focused_mut();
+
+        match key {
+            KeyCode::Char('q') => return Action::Quit,
+            KeyCode::Char('s') => {
+                machines.step();
+                self.resume();
+            }
+            KeyCode::Char(' ') => {
+                machines.tick();
+                self.resume();
+            }
+            KeyCode::Left if !self.running => self.step_back(machines),
+            KeyCode::Char('r') | KeyCode::Char('R') => {
+                self.running = !self.running && !machines.all_halted();
+                self.resume();
+                // Start counting from now, not from whenever the last frame was
+                self.clock.set_speed(self.clock.speed());
+            }
+            KeyCode::Char('l') => self.show_log = !self.show_log,
+            KeyCode::Char('f') if self.show_log => self.log.set_level(next_level(self.log.level())),
+            KeyCode::Char('L') => {
+                self.running = false;
+                self.resume();
+                return Action::Reload;
+            }
+            KeyCode::Char('+') | KeyCode::Char('=') => {
+                self.clock.set_speed(self.clock.speed().faster())
+            }
+            KeyCode::Char('-') => self.clock.set_speed(self.clock.speed().slower()),
+            KeyCode::Tab => self.focus(machines, |machines| machines.cycle_focus(1)),
+            KeyCode::BackTab => self.focus(machines, |machines| machines.cycle_focus(-1)),
+            KeyCode::F(number) => self.focus(machines, |machines| {
+                machines.focus_on(usize::from(number).saturating_sub(1));
+            }),
+            // The cursor can only be moved while paused, running follows the program counter
+            KeyCode::Up if !self.running => self.rom_view.move_cursor(-1, program_counter, len),
+            KeyCode::Down if !self.running => self.rom_view.move_cursor(1, program_counter, len),
+            KeyCode::PageUp if !self.running => {
+                self.rom_view.move_cursor_pages(-1, program_counter, len)
+            }
+            KeyCode::PageDown if !self.running => {
+                self.rom_view.move_cursor_pages(1, program_counter, len)
+            }
+            KeyCode::Home => self.rom_view.follow(),
+            KeyCode::Char('b') if !self.running && len > 0 => {
+                tpu.toggle_breakpoint(self.rom_view.cursor(program_counter));
+            }
+            KeyCode::Char('c') if !self.running && len > 0 => {
+                self.run_to_cursor(machines, self.rom_view.cursor(program_counter))
+            }
+            KeyCode::Char('B') if !self.running && len > 0 => {
+                self.prompt = Some(Prompt::new(PromptKind::Condition))
+            }
+            KeyCode::Char('e') if !self.running => {
+                self.prompt = Some(Prompt::new(PromptKind::Register))
+            }
+            KeyCode::Char('p') if !self.running => self.prompt = Some(Prompt::new(PromptKind::Ram)),
+            KeyCode::Char('w') => {
+                self.banner = Some(match snapshot::save(tpu, Path::new(".")) {
+                    Ok(path) => format!("saved {}", path.display()),
+                    Err(error) => format!("couldn't save a snapshot: {error}"),
+                })
+            }
+            KeyCode::Char('o') if !self.running => {
+                self.prompt = Some(Prompt::new(PromptKind::Snapshot))
+            }
+            KeyCode::Char('t') => self.toggle_trace(machines),
+            KeyCode::Char('/') => self.prompt = Some(Prompt::new(PromptKind::Search)),
+            // N goes on to the next match while there's a search, otherwise it sends a packet
+            KeyCode::Char('n') if self.search.is_some() => self.next_match(tpu),
+            KeyCode::Esc => self.search = None,
+            KeyCode::Char('n') => self.prompt = Some(Prompt::new(PromptKind::Packet)),
+            KeyCode::Char('i') => self.prompt = Some(Prompt::new(PromptKind::AnalogInput)),
+            KeyCode::Char('v') => self.prompt = Some(Prompt::new(PromptKind::Watch)),
+            key if let Some(pin) = digital_pin_for_key(key) => self.toggle_input(tpu, pin),
+            KeyCode::Char('[') => self.ram_view.page(-1),
+            KeyCode::Char(']') => self.ram_view.page(1),
+            KeyCode::Char('a') => self.ram_view.toggle_ascii(),
+            KeyCode::Char('<') | KeyCode::Char(',') => {
+                self.network_scroll = self.network_scroll.saturating_sub(1)
+            }
+            KeyCode::Char('>') | KeyCode::Char('.') => {
+                self.network_scroll = self.network_scroll.saturating_add(1)
+            }
+            KeyCode::Char('{') => self.stack_scroll = self.stack_scroll.saturating_sub(1),
+            KeyCode::Char('}') => self.stack_scroll = self.stack_scroll.saturating_add(1),
+            _ => {}
+        }
+        self.stop_trace_if_halted(machines);
+        Action::Continue
+    }
+
+    /// Clicking a line of the ROM toggles a breakpoint there, clicking an input pin toggles it, and the wheel scrolls the
+    /// panel under the mouse. Like the keys, the mouse does nothing while the prompt is open or a question is asked.
+    pub fn handle_mouse(&mut self, event: MouseEvent, machines: &mut Machines) {
+        if self.prompt.is_some() || self.replace.is_some() {
+            return;
+        }
+        let (column, row) = (event.column, event.row);
+        let tpu = machines.focused_mut();
+        match event.kind {
+            MouseEventKind::Down(MouseButton::Left) => {
+                if let Some(address) = self.layout.rom_address_at(column, row) {
+                    tpu.toggle_breakpoint(address);
+                } else if let Some(pin) = self.layout.digital_pin_at(column, row) {
+                    self.toggle_input(tpu, pin);
+                }
+            }
+            MouseEventKind::ScrollUp => self.scroll(tpu, column, row, -SCROLL_LINES),
+            MouseEventKind::ScrollDown => self.scroll(tpu, column, row, SCROLL_LINES),
+            _ => {}
+        }
+    }
+
+    /// Scroll the panel at `column` and `row` by `lines`, up when negative
+    fn scroll(&mut self, tpu: &TPU, column: u16, row: u16, lines: isize) {
+        let scroll = |offset: u16| offset.saturating_add_signed(lines as i16);
+        match self.layout.panel_at(column, row) {
+            // Like the arrow keys, the ROM follows the program counter while running
+            Some(Panel::Rom) if !self.running => {
+                let len = tpu.read_rom().len();
+                let program_counter = tpu.state().program_counter;
+                self.rom_view.move_cursor(lines, program_counter, len);
+            }
+            Some(Panel::Ram) => self.ram_view.scroll(lines),
+            Some(Panel::Stack) => self.stack_scroll = scroll(self.stack_scroll),
+            Some(Panel::Network) => self.network_scroll = scroll(self.network_scroll),
+            _ => {}
+        }
+    }
+
+    /// Like pressing or letting go of a button, the program's own outputs are left alone
+    fn toggle_input(&mut self, tpu: &mut TPU, pin: DigitalPin) {
+        let level = !tpu.state().digital_pins[pin as usize];
+        if tpu.drive_digital_input(pin, level).is_err() {
+            self.banner = Some(format!("{pin:?} is an output, only inputs can be toggled"));
+        }
+    }
+
+    /// Start tracing the TPU being shown, or stop the trace there is and say where it went
+    fn toggle_trace(&mut self, machines: &mut Machines) {
+        if self.trace.tpu().is_some() {
+            self.stop_trace(machines);
+            return;
+        }
+        let index = machines.focus();
+        self.banner = Some(
+            match self
+                .trace
+                .start(machines.tpu_mut(index), index, Path::new("."))
+            {
+                Ok(path) => format!("tracing to {}, T to stop", path.display()),
+                Err(error) => format!("couldn't start a trace: {error}"),
+            },
+        );
+    }
+
+    fn stop_trace(&mut self, machines: &mut Machines) {
+        let Some(index) = self.trace.tpu() else {
+            return;
+        };
+        let Some((path, rows)) = self.trace.stop(machines.tpu_mut(index)) else {
+            return;
+        };
+        self.banner = Some(match rows {
+            Ok(rows) => format!("trace of {rows} instructions written to {}", path.display()),
+            Err(error) => format!("couldn't write the trace to {}: {error}", path.display()),
+        });
+    }
+
+    /// A halted TPU has nothing more to trace
+    fn stop_trace_if_halted(&mut self, machines: &mut Machines) {
+        if self
+            .trace
+            .tpu()
+            .is_some_and(|index| machines.tpu(index).halted())
+        {
+            self.stop_trace(machines);
+        }
+    }
+
+    /// Find `query` in the TPU being shown, going to the first match in the ROM and in RAM
+    fn search(&mut self, machines: &Machines, query: &str) {
+        let tpu = machines.focused();
+        let state = tpu.state();
+        let mut search = Search::new(query, &state.rom, machines.focused_symbols(), &state.ram);
+        self.prompt = None;
+        self.edit_error = None;
+        if search.is_empty() {
+            self.banner = Some(format!("nothing matches {}", search.query));
+            self.search = None;
+            return;
+        }
+
+        // Like the arrow keys, the cursor stays on the program counter while running
+        if let Some(&address) = search.rom.first()
+            && !self.running
+        {
+            self.rom_view.set_cursor(address, state.rom.len());
+        }
+        if let Some(address) = search.next_ram() {
+            self.ram_view.show(address);
+        }
+        self.banner = Some(format!(
+            "{} in ROM and {} in RAM match {}, N for the next, Esc to clear",
+            search.rom.len(),
+            search.ram.len(),
+            search.query
+        ));
+        self.search = Some(search);
+    }
+
+    /// Go to the ROM match after the cursor, or the next RAM match if there are none in ROM
+    fn next_match(&mut self, tpu: &TPU) {
+        let Some(search) = &mut self.search else {
+            return;
+        };
+        let state = tpu.state();
+        if !search.rom.is_empty() {
+            if !self.running
+                && let Some(address) = search.next_rom(self.rom_view.cursor(state.program_counter))
+            {
+                self.rom_view.set_cursor(address, state.rom.len());
+            }
+        } else if let Some(address) = search.next_ram() {
+            self.ram_view.show(address);
+        }
+    }
+
+    /// Undo the last instruction, or say why it can't be
+    fn step_back(&mut self, machines: &mut Machines) {
+        // The other TPUs would carry on from where they are, and the packets between them wouldn't add up
+        if machines.len() > 1 {
+            self.banner = Some("can't step back with more than one TPU".to_string());
+            return;
+        }
+        let tpu = machines.focused_mut();
+        match tpu.history_len() {
+            None => {
+                self.banner = Some("history is off, start with --history to step back".to_string())
+            }
+            Some(0) => self.banner = Some("no history to step back through".to_string()),
+            Some(_) => {
+                tpu.step_back();
+                self.resume();
+            }
+        }
+    }
+
+    /// Show another TPU, what was highlighted or poked on the last one means nothing on this one
+    fn focus(&mut self, machines: &mut Machines, change: impl FnOnce(&mut Machines)) {
+        let focus = machines.focus();
+        change(machines);
+        if machines.focus() != focus {
+            self.highlights = Highlights::new(self.highlights.fade_ticks);
+            self.edited.clear();
+            self.poked.clear();
+            self.rom_view.follow();
+            self.watches.forget();
+            self.analog_history.clear();
+        }
+    }
+
+    /// Type at the edit prompt, Enter makes the change and Esc gives up
+    fn handle_edit_key(&mut self, key: KeyCode, tpu: &mut TPU) {
+        let Some(prompt) = &mut self.prompt else {
+            return;
+        };
+
+        match key {
+            KeyCode::Char(c) => prompt.input.push(c),
+            KeyCode::Backspace => {
+                prompt.input.pop();
+            }
+            KeyCode::Esc => {
+                self.prompt = None;
+                self.edit_error = None;
+            }
+            KeyCode::Enter => {
+                let result = match prompt.kind {
+                    PromptKind::Register => RegisterEdit::parse(&prompt.input).map(|edit| {
+                        edit.apply(tpu);
+                        self.edited.push(edit.register);
+                    }),
+                    PromptKind::AnalogInput => AnalogEdit::parse(&prompt.input).and_then(|edit| {
+                        edit.apply(tpu).map_err(|_| EditError::NotAnInput(edit.pin))
+                    }),
+                    PromptKind::Packet => {
+                        PacketEdit::parse(&prompt.input).and_then(|edit| edit.apply(tpu))
+                    }
+                    PromptKind::Watch => {
+                        match Expr::parse(&prompt.input) {
+                            Ok(expr) => {
+                                self.watches.toggle(expr);
+                                self.prompt = None;
+                                self.edit_error = None;
+                            }
+                            Err(error) => self.edit_error = Some(error.to_string()),
+                        }
+                        return;
+                    }
+                    PromptKind::Condition => {
+                        let input = prompt.input.trim();
+                        let condition = if input.is_empty() {
+                            Ok(None)
+                        } else {
+                            Expr::parse(input).map(Some)
+                        };
+                        match condition {
+                            Ok(condition) => {
+                                let address = self.rom_view.cursor(tpu.state().program_counter);
+                                tpu.set_breakpoint(address, condition);
+                                self.prompt = None;
+                                self.edit_error = None;
+                            }
+                            Err(error) => self.edit_error = Some(error.to_string()),
+                        }
+                        return;
+                    }
+                    // Searching needs the labels as well as the TPU, `handle_key` does it
+                    PromptKind::Search => return,
+                    PromptKind::Snapshot => {
+                        let path = prompt.input.trim().to_string();
+                        self.open_snapshot(tpu, &path);
+                        return;
+                    }
+                    PromptKind::Ram => RamEdit::parse(&prompt.input, tpu.ram_size()).map(|edit| {
+                        edit.apply(tpu);
+                        self.poked.push(edit.addresses);
+                    }),
+                };
+                match result {
+                    Ok(()) => {
+                        self.prompt = None;
+                        self.edit_error = None;
+                    }
+                    // Leave what was typed so it can be fixed
+                    Err(error) => self.edit_error = Some(error.to_string()),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Whether a RAM address was poked since the TPU last moved on
+    pub fn was_poked(&self, address: usize) -> bool {
+        self.poked.iter().any(|poked| poked.contains(&address))
+    }
+
+    /// Run the ticks due for `elapsed` time on every TPU, if running, stopping at a breakpoint or once they've all
+    /// halted. The TPU that hit a breakpoint is brought into view.
+    pub fn run_frame(&mut self, machines: &mut Machines, elapsed: Duration) {
+        if !self.running {
+            self.rate.clear();
+            return;
+        }
+
+        // Every TPU keeps the same time, so any of them will do
+        let focus = machines.focus();
+        let cycles = machines.tpu(focus).state().cycle_count;
+        let ticks = self.clock.ticks_due(elapsed);
+        let result = match self.analog_history.sampling {
+            Sampling::Frame => machines.run(ticks),
+            Sampling::Tick => self.run_sampling(machines, ticks),
+        };
+        let ran = machines
+            .tpu(focus)
+            .state()
+            .cycle_count
+            .saturating_sub(cycles);
+        self.rate.record(ran, elapsed);
+
+        match result {
+            NetworkRunResult::OutOfTicks => {}
+            NetworkRunResult::AllHalted => self.running = false,
+            NetworkRunResult::Breakpoint { tpu, address } => {
+                self.running = false;
+                self.breakpoint_hit(machines, tpu, address);
+            }
+        }
+        self.stop_trace_if_halted(machines);
+    }
+
+    /// Run the TPU being shown until it's about to run the instruction at `address`, with every other TPU keeping
+    /// time, see `TPU::run_until_pc`
+    fn run_to_cursor(&mut self, machines: &mut Machines, address: usize) {
+        let focus = machines.focus();
+        let result = machines.run_until(address, RUN_TO_CURSOR_TICKS);
+        self.resume();
+        match result {
+            NetworkRunResult::Breakpoint { tpu, address: stop }
+                if tpu == focus && stop == address =>
+            {
+                self.banner = Some(format!("ran to {address:#06X}"))
+            }
+            NetworkRunResult::Breakpoint { tpu, address } => {
+                self.breakpoint_hit(machines, tpu, address)
+            }
+            // The halt has a banner of its own
+            _ if machines.focused().halted() => {}
+            _ => {
+                self.banner = Some(format!(
+                    "didn't reach {address:#06X} in {} ticks",
+                    thousands(RUN_TO_CURSOR_TICKS)
+                ))
+            }
+        }
+    }
+
+    /// Show the TPU that hit a breakpoint, and say where
+    fn breakpoint_hit(&mut self, machines: &mut Machines, tpu: usize, address: usize) {
+        self.focus(machines, |machines| {
+            machines.focus_on(tpu);
+        });
+        let condition = machines
+            .tpu(tpu)
+            .breakpoint_condition(address)
+            .map_or_else(String::new, |condition| format!(" with {condition}"));
+        self.banner = Some(if machines.len() > 1 {
+            format!(
+                "breakpoint hit at {address:#06X}{condition} on TPU {}",
+                tpu + 1
+            )
+        } else {
+            format!("breakpoint hit at {address:#06X}{condition}")
+        });
+    }
+
+    /// Run like `Machines::run`, sampling the analog pins of the TPU being shown after every tick
+    fn run_sampling(&mut self, machines: &mut Machines, ticks: u64) -> NetworkRunResult {
+        for _ in 0..ticks {
+            let result = machines.run(1);
+            self.analog_history.sample(machines.focused().state());
+            if result != NetworkRunResult::OutOfTicks {
+                return result;
+            }
+        }
+        NetworkRunResult::OutOfTicks
+    }
+
+    /// Restore the snapshot at `path`, or ask first if it's for another program
+    fn open_snapshot(&mut self, tpu: &mut TPU, path: &str) {
+        match snapshot::load(Path::new(path), tpu.read_rom()) {
+            Ok(snapshot) => {
+                self.restore(tpu, snapshot);
+                self.banner = Some(format!("restored {path}"));
+            }
+            Err(LoadError::RomMismatch(snapshot)) => {
+                self.prompt = None;
+                self.edit_error = None;
+                self.replace = Some(snapshot);
+                self.banner = Some(format!(
+                    "{path} is for a different program, Y to replace it as well, any other key to keep it"
+                ));
+            }
+            // Left under the prompt so the path can be fixed
+            Err(error) => self.edit_error = Some(error.to_string()),
+        }
+    }
+
+    /// Put the TPU back to a snapshot, paused
+    fn restore(&mut self, tpu: &mut TPU, snapshot: TpuSnapshot) {
+        tpu.restore(snapshot);
+        self.prompt = None;
+        self.edit_error = None;
+        self.running = false;
+        self.resume();
+    }
+
+    /// The TPU is moving on, so show where it is
+    fn resume(&mut self) {
+        self.rom_view.follow();
+        self.banner = None;
+        self.edited.clear();
+        self.poked.clear();
+    }
+}
+
+/// The digital pin the number keys 1 to 8 toggle
+pub fn digital_pin_for_key(key: KeyCode) -> Option<DigitalPin> {
+    let KeyCode::Char(c) = key else {
+        return None;
+    };
+    let number = c.to_digit(10)?.checked_sub(1)?;
+    DigitalPin::from_repr(number as u16)
+}
@@ -0,0 +1,162 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::Duration;
+
+/// How fast the debugger ticks the TPU while it's running
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum RunSpeed {
+    Hz1,
+    #[default]
+    Hz10,
+    Hz100,
+    KHz1,
+    /// As many ticks as fit in a frame, see `Clock::UNLIMITED_TICKS_PER_FRAME`
+    Unlimited,
+}
+
+impl RunSpeed {
+    const ALL: [RunSpeed; 5] = [
+        RunSpeed::Hz1,
+        RunSpeed::Hz10,
+        RunSpeed::Hz100,
+        RunSpeed::KHz1,
+        RunSpeed::Unlimited,
+    ];
+
+    /// `None` when unlimited
+    pub fn ticks_per_second(self) -> Option<u64> {
+        match self {
+            RunSpeed::Hz1 => Some(1),
+            RunSpeed::Hz10 => Some(10),
+            RunSpeed::Hz100 => Some(100),
+            RunSpeed::KHz1 => Some(1000),
+            RunSpeed::Unlimited => None,
+        }
+    }
+
+    /// The next speed up, unlimited stays unlimited
+    pub fn faster(self) -> Self {
+        let index = Self::ALL.iter().position(|&speed| speed == self).unwrap();
+        Self::ALL[(index + 1).min(Self::ALL.len() - 1)]
+    }
+
+    /// The next speed down, 1 Hz stays 1 Hz
+    pub fn slower(self) -> Self {
+        let index = Self::ALL.iter().position(|&speed| speed == self).unwrap();
+        Self::ALL[index.saturating_sub(1)]
+    }
+}
+
+impl fmt::Display for RunSpeed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RunSpeed::Hz1 => write!(f, "1 Hz"),
+            RunSpeed::Hz10 => write!(f, "10 Hz"),
+            RunSpeed::Hz100 => write!(f, "100 Hz"),
+            RunSpeed::KHz1 => write!(f, "1 kHz"),
+            RunSpeed::Unlimited => write!(f, "unlimited"),
+        }
+    }
+}
+
+/// Works out how many ticks to run between two frames, so the TPU keeps to its speed however often the screen is drawn.
+///
+/// Time left over from a frame is carried into the next one, so a 1 Hz clock still ticks once a second at 20 frames a
+/// second. A long stall, e.g. the terminal being suspended, is not caught up on beyond a second's worth of ticks.
+#[derive(Clone, Debug, Default)]
+pub struct Clock {
+    speed: RunSpeed,
+    /// Ticks owed, in billionths of a tick
+    owed: u128,
+}
+
+impl Clock {
+    /// Ticks run in each frame at `RunSpeed::Unlimited`, small enough to keep the keys responsive
+    pub const UNLIMITED_TICKS_PER_FRAME: u64 = 50_000;
+
+    pub fn speed(&self) -> RunSpeed {
+        self.speed
+    }
+
+    /// Change speed, dropping any part of a tick that was owed
+    pub fn set_speed(&mut self, speed: RunSpeed) {
+        self.speed = speed;
+        self.owed = 0;
+    }
+
+    /// The ticks due for `elapsed` time since the last call
+    pub fn ticks_due(&mut self, elapsed: Duration) -> u64 {
+        let Some(rate) = self.speed.ticks_per_second() else {
+            return Self::UNLIMITED_TICKS_PER_FRAME;
+        };
+
+        const NANOS_PER_SECOND: u128 = 1_000_000_000;
+        self.owed += elapsed.as_nanos() * rate as u128;
+        let ticks = (self.owed / NANOS_PER_SECOND) as u64;
+        self.owed %= NANOS_PER_SECOND;
+        ticks.min(rate)
+    }
+}
+
+/// How fast a running TPU is really going, which can fall short of its speed when the host can't keep up.
+///
+/// The rate is taken over the last few frames rather than the last one, so the readout doesn't flicker as frames come a
+/// little early or late.
+#[derive(Clone, Debug)]
+pub struct RateMeter {
+    /// Ticks run in each frame and how long the frame took, oldest first
+    frames: VecDeque<(u64, Duration)>,
+    window: usize,
+}
+
+impl Default for RateMeter {
+    fn default() -> Self {
+        Self::new(Self::WINDOW)
+    }
+}
+
+impl RateMeter {
+    /// Frames the rate is taken over, a second's worth at the debugger's frame rate
+    pub const WINDOW: usize = 20;
+
+    /// Take the rate over the last `window` frames
+    pub fn new(window: usize) -> Self {
+        Self {
+            frames: VecDeque::with_capacity(window),
+            window: window.max(1),
+        }
+    }
+
+    /// Note a frame that ran `ticks` in `elapsed` time
+    pub fn record(&mut self, ticks: u64, elapsed: Duration) {
+        if self.frames.len() == self.window {
+            self.frames.pop_front();
+        }
+        self.frames.push_back((ticks, elapsed));
+    }
+
+    /// Forget the frames seen, the TPU stopped so the next run starts afresh
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    /// Ticks a second over the frames seen, `None` until some time has passed
+    pub fn hz(&self) -> Option<f64> {
+        let ticks: u64 = self.frames.iter().map(|&(ticks, _)| ticks).sum();
+        let elapsed: Duration = self.frames.iter().map(|&(_, elapsed)| elapsed).sum();
+        (!elapsed.is_zero()).then(|| ticks as f64 / elapsed.as_secs_f64())
+    }
+}
+
+/// `value` with a comma between each group of three digits, e.g. `1,250,000`
+pub fn thousands(value: u64) -> String {
+    let digits = value.to_string();
+    let mut grouped = String::with_capacity(digits.len() + digits.len() / 3);
+    for (index, digit) in digits.chars().enumerate() {
+        if index > 0 && (digits.len() - index).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
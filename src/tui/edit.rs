@@ -0,0 +1,253 @@
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+use tls::cli::parse_number;
+use tls::shared::{AnalogPin, NetPacket, Register};
+use tls::tpu::{PinError, TPU};
+
+/// A register and the value to poke into it, typed as e.g. `A 0x10`, `r3=42` or `X 0b101`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RegisterEdit {
+    pub register: Register,
+    pub value: u16,
+}
+
+/// Words of RAM and the value to poke into them, typed as e.g. `0x10 5` or `0x10..0x18 = 0`.
+/// Like a Rust range the end isn't included.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RamEdit {
+    pub addresses: Range<usize>,
+    pub value: u16,
+}
+
+/// An analog input and the value to drive it to, typed as e.g. `2 512`, `A2=512` or `analog2 0x200`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct AnalogEdit {
+    pub pin: AnalogPin,
+    pub value: u16,
+}
+
+/// A packet to deliver to the TPU as if it came from the network, typed as e.g. `0x2 5` or `0x2:3 5 6 7`, the
+/// sender, optionally its port, and up to `NetPacket::MAX_PAYLOAD` words
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PacketEdit {
+    pub sender: u16,
+    pub port: u8,
+    pub words: Vec<u16>,
+}
+
+/// Why an edit couldn't be understood
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum EditError {
+    MissingRegister,
+    UnknownRegister(String),
+    MissingPin,
+    UnknownPin(String),
+    /// The pin is an output, so it can't be driven
+    NotAnInput(AnalogPin),
+    MissingSender,
+    /// Not an address, or an address and port like 0x2:3
+    InvalidSender(String),
+    /// More words than fit in a packet
+    TooManyWords(usize),
+    /// The TPU's incoming buffer is full, so the packet was dropped
+    BufferFull,
+    MissingAddress,
+    /// Not an address or a range of them
+    InvalidAddress(String),
+    /// Past the end of RAM, or a range with nothing in it
+    AddressOutOfRange(String),
+    MissingValue,
+    /// Not a word in decimal, hex or binary
+    InvalidValue(String),
+}
+
+impl fmt::Display for EditError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EditError::MissingRegister => write!(f, "type a register, then a value"),
+            EditError::UnknownRegister(register) => write!(f, "no register called {register}"),
+            EditError::MissingPin => write!(f, "type an analog pin, then a value"),
+            EditError::UnknownPin(pin) => write!(f, "no analog pin called {pin}"),
+            EditError::NotAnInput(pin) => {
+                write!(f, "{pin:?} is an output, only inputs can be driven")
+            }
+            EditError::MissingSender => write!(f, "type the sender's address, then the data"),
+            EditError::InvalidSender(sender) => {
+                write!(
+                    f,
+                    "{sender} isn't an address or an address and port like 0x2:3"
+                )
+            }
+            EditError::TooManyWords(count) => write!(
+                f,
+                "{count} words won't fit, a packet carries up to {}",
+                NetPacket::MAX_PAYLOAD
+            ),
+            EditError::BufferFull => {
+                write!(f, "the incoming buffer is full, the packet was dropped")
+            }
+            EditError::MissingAddress => write!(f, "type an address or range, then a value"),
+            EditError::InvalidAddress(address) => {
+                write!(f, "{address} isn't an address or a range like 0x10..0x18")
+            }
+            EditError::AddressOutOfRange(address) => write!(f, "{address} isn't in RAM"),
+            EditError::MissingValue => write!(f, "type a value as well"),
+            EditError::InvalidValue(value) => write!(f, "{value} isn't a 16 bit value"),
+        }
+    }
+}
+
+impl std::error::Error for EditError {}
+
+impl RegisterEdit {
+    /// The register name and value, split by spaces or `=`. Register names aren't case sensitive.
+    pub fn parse(input: &str) -> Result<Self, EditError> {
+        let parts = split(input);
+        let (register, value) = parts.split_first().ok_or(EditError::MissingRegister)?;
+        let register = Register::from_str(&register.to_uppercase())
+            .map_err(|_| EditError::UnknownRegister(register.to_string()))?;
+
+        Ok(Self {
+            register,
+            value: parse_value(value)?,
+        })
+    }
+
+    pub fn apply(self, tpu: &mut TPU) {
+        tpu.write_register(self.register, self.value);
+    }
+}
+
+impl RamEdit {
+    /// The address, or a `..` range of them, and the value, split by spaces or `=`.
+    /// Every address has to be in RAM of `ram_size` words.
+    pub fn parse(input: &str, ram_size: usize) -> Result<Self, EditError> {
+        let parts = split(input);
+        let (text, value) = parts.split_first().ok_or(EditError::MissingAddress)?;
+        let address = |address: &str| {
+            parse_number(address)
+                .map(usize::from)
+                .ok_or_else(|| EditError::InvalidAddress(text.to_string()))
+        };
+
+        let addresses = if let Some((start, end)) = text.split_once("..") {
+            address(start)?..address(end)?
+        } else {
+            let address = address(text)?;
+            address..address + 1
+        };
+        if addresses.is_empty() || addresses.end > ram_size {
+            return Err(EditError::AddressOutOfRange(text.to_string()));
+        }
+
+        Ok(Self {
+            addresses,
+            value: parse_value(value)?,
+        })
+    }
+
+    pub fn apply(&self, tpu: &mut TPU) {
+        tpu.write_ram_slice(
+            self.addresses.start,
+            &vec![self.value; self.addresses.len()],
+        );
+    }
+}
+
+impl AnalogEdit {
+    /// The pin's number, as a number or e.g. `A2` or `Analog2`, and the value, split by spaces or `=`
+    pub fn parse(input: &str) -> Result<Self, EditError> {
+        let parts = split(input);
+        let (text, value) = parts.split_first().ok_or(EditError::MissingPin)?;
+        let lower = text.to_lowercase();
+        let number = lower
+            .strip_prefix("analog")
+            .or_else(|| lower.strip_prefix('a'))
+            .unwrap_or(&lower);
+        let pin = number
+            .parse()
+            .ok()
+            .and_then(AnalogPin::from_repr)
+            .ok_or_else(|| EditError::UnknownPin(text.to_string()))?;
+
+        Ok(Self {
+            pin,
+            value: parse_value(value)?,
+        })
+    }
+
+    /// Drive the pin, values past the top of the pin's range are clamped to it
+    pub fn apply(self, tpu: &mut TPU) -> Result<(), PinError> {
+        tpu.drive_analog_input(self.pin, self.value)
+    }
+}
+
+impl PacketEdit {
+    /// The sender, with `:port` if it isn't port 0, then the words of the payload, split by spaces or `=`
+    pub fn parse(input: &str) -> Result<Self, EditError> {
+        let parts = split(input);
+        let (text, words) = parts.split_first().ok_or(EditError::MissingSender)?;
+        let invalid = || EditError::InvalidSender(text.to_string());
+        let (sender, port) = match text.split_once(':') {
+            Some((sender, port)) => (
+                sender,
+                parse_number(port)
+                    .and_then(|port| u8::try_from(port).ok())
+                    .ok_or_else(invalid)?,
+            ),
+            None => (*text, 0),
+        };
+        let sender = parse_number(sender).ok_or_else(invalid)?;
+
+        if words.is_empty() {
+            return Err(EditError::MissingValue);
+        }
+        if words.len() > NetPacket::MAX_PAYLOAD {
+            return Err(EditError::TooManyWords(words.len()));
+        }
+        let words = words
+            .iter()
+            .map(|word| parse_number(word).ok_or_else(|| EditError::InvalidValue(word.to_string())))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            sender,
+            port,
+            words,
+        })
+    }
+
+    /// The packet, addressed to `target`
+    pub fn packet(&self, target: u16) -> NetPacket {
+        NetPacket::with_payload(self.sender, target, &self.words).on_port(self.port)
+    }
+
+    /// Deliver the packet to the TPU's own address
+    pub fn apply(&self, tpu: &mut TPU) -> Result<(), EditError> {
+        let packet = self.packet(tpu.state().network_address);
+        if tpu.deliver_packet(packet) {
+            Ok(())
+        } else {
+            Err(EditError::BufferFull)
+        }
+    }
+}
+
+/// The words of an edit, split by spaces or `=`
+fn split(input: &str) -> Vec<&str> {
+    input
+        .split(|c: char| c.is_whitespace() || c == '=')
+        .filter(|part| !part.is_empty())
+        .collect()
+}
+
+/// The value at the end of an edit
+fn parse_value(parts: &[&str]) -> Result<u16, EditError> {
+    match parts {
+        [] => Err(EditError::MissingValue),
+        [value] => parse_number(value).ok_or_else(|| EditError::InvalidValue(value.to_string())),
+        // Anything after the value is a typo, not something to ignore
+        values => Err(EditError::InvalidValue(values.join(" "))),
+    }
+}
@@ -0,0 +1,24 @@
+use tls::rgal::SymbolResolver;
+use tls::shared::{HaltReason, Instruction};
+
+/// The banner shown across the top while the TPU is halted, e.g. `HALTED: Div0 at 0x0007 (DIVIDE): DIV A, X`.
+/// The program counter is left on the instruction that halted, there's no instruction to show for an empty program.
+/// Lines and branch targets are named by `symbols`, as in the ROM panel.
+pub fn halt_banner(
+    reason: HaltReason,
+    program_counter: usize,
+    instruction: Option<&Instruction>,
+    symbols: &SymbolResolver,
+) -> String {
+    let label = match symbols.labels_at(program_counter).first() {
+        Some(label) => format!(" ({label})"),
+        None => String::new(),
+    };
+    match instruction {
+        Some(instruction) => format!(
+            "HALTED: {reason:?} at {program_counter:#06X}{label}: {}",
+            symbols.format_instruction(instruction)
+        ),
+        None => format!("HALTED: {reason:?} at {program_counter:#06X}{label}"),
+    }
+}
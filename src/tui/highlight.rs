@@ -0,0 +1,149 @@
+use ratatui::style::{Color, Modifier, Style};
+use strum::EnumCount;
+use tls::shared::{AnalogPin, DigitalPin, Register};
+use tls::tpu::{StateDiff, TpuState};
+
+/// How recently something changed, see `heat`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Heat {
+    /// In the first half of the fade
+    Hot,
+    /// In the second half
+    Warm,
+}
+
+/// How a change `ticks_since` ticks ago should look, `None` once it has faded after `fade_ticks`
+pub fn heat(ticks_since: u64, fade_ticks: u64) -> Option<Heat> {
+    if ticks_since >= fade_ticks {
+        None
+    } else if ticks_since < fade_ticks.div_ceil(2) {
+        Some(Heat::Hot)
+    } else {
+        Some(Heat::Warm)
+    }
+}
+
+/// A value that changed, text in a contrasting colour
+pub fn text_style(heat: Option<Heat>) -> Style {
+    match heat {
+        Some(Heat::Hot) => Style::default()
+            .fg(Color::LightYellow)
+            .add_modifier(Modifier::BOLD),
+        Some(Heat::Warm) => Style::default().fg(Color::Yellow),
+        None => Style::default(),
+    }
+}
+
+/// A cell that changed, with a background colour
+pub fn cell_style(heat: Option<Heat>) -> Style {
+    match heat {
+        Some(Heat::Hot) => Style::default().fg(Color::Black).bg(Color::LightYellow),
+        Some(Heat::Warm) => Style::default().fg(Color::Black).bg(Color::Yellow),
+        None => Style::default(),
+    }
+}
+
+/// When each register, RAM word, stack slot and pin last changed, to highlight them until the change fades.
+///
+/// `Highlights::update` is called with the TPU's state before each draw. It diffs it against the state it saw last
+/// time and stamps what changed with the cycle count, so a frame that ran many ticks highlights everything those ticks
+/// changed.
+#[derive(Clone)]
+pub struct Highlights {
+    /// Ticks a change stays highlighted for, 0 turns highlighting off
+    pub fade_ticks: u64,
+    previous: Option<TpuState>,
+    registers: [Option<u64>; Register::COUNT],
+    ram: Vec<Option<u64>>,
+    stack: Vec<Option<u64>>,
+    digital_pins: [Option<u64>; DigitalPin::COUNT],
+    analog_pins: [Option<u64>; AnalogPin::COUNT],
+    cycle: u64,
+}
+
+impl Default for Highlights {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_FADE_TICKS)
+    }
+}
+
+impl Highlights {
+    pub const DEFAULT_FADE_TICKS: u64 = 8;
+
+    pub fn new(fade_ticks: u64) -> Self {
+        Self {
+            fade_ticks,
+            previous: None,
+            registers: [None; Register::COUNT],
+            ram: Vec::new(),
+            stack: Vec::new(),
+            digital_pins: [None; DigitalPin::COUNT],
+            analog_pins: [None; AnalogPin::COUNT],
+            cycle: 0,
+        }
+    }
+
+    pub fn update(&mut self, state: &TpuState) {
+        // A reset starts the cycles again, so older stamps would look like they're from the future
+        if state.cycle_count < self.cycle {
+            *self = Self::new(self.fade_ticks);
+        }
+        self.cycle = state.cycle_count;
+
+        let Some(previous) = self.previous.replace(state.clone()) else {
+            return;
+        };
+        self.record(&state.diff(&previous));
+    }
+
+    /// Stamp everything in `diff` as changed now
+    pub fn record(&mut self, diff: &StateDiff) {
+        let now = Some(self.cycle);
+        for &register in &diff.registers {
+            self.registers[register as usize] = now;
+        }
+        for &address in &diff.ram {
+            stamp(&mut self.ram, address, now);
+        }
+        for &slot in &diff.stack {
+            stamp(&mut self.stack, slot, now);
+        }
+        for &pin in &diff.digital_pins {
+            self.digital_pins[pin as usize] = now;
+        }
+        for &pin in &diff.analog_pins {
+            self.analog_pins[pin as usize] = now;
+        }
+    }
+
+    pub fn register(&self, register: Register) -> Option<Heat> {
+        self.heat(self.registers[register as usize])
+    }
+
+    pub fn ram(&self, address: usize) -> Option<Heat> {
+        self.heat(self.ram.get(address).copied().flatten())
+    }
+
+    pub fn stack(&self, slot: usize) -> Option<Heat> {
+        self.heat(self.stack.get(slot).copied().flatten())
+    }
+
+    pub fn digital_pin(&self, pin: DigitalPin) -> Option<Heat> {
+        self.heat(self.digital_pins[pin as usize])
+    }
+
+    pub fn analog_pin(&self, pin: AnalogPin) -> Option<Heat> {
+        self.heat(self.analog_pins[pin as usize])
+    }
+
+    fn heat(&self, changed: Option<u64>) -> Option<Heat> {
+        heat(self.cycle.saturating_sub(changed?), self.fade_ticks)
+    }
+}
+
+fn stamp(stamps: &mut Vec<Option<u64>>, index: usize, now: Option<u64>) {
+    if stamps.len() <= index {
+        stamps.resize(index + 1, None);
+    }
+    stamps[index] = now;
+}
@@ -0,0 +1,168 @@
+use std::collections::VecDeque;
+use std::fmt::{self, Write};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tracing::field::{Field, Visit};
+use tracing::subscriber::Interest;
+use tracing::{Event, Level, Metadata, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+
+/// An event caught by `LogLayer`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LogEntry {
+    pub level: Level,
+    /// The message, then any other fields as `name=value`
+    pub message: String,
+}
+
+/// The last few events, shared by the layer catching them and the panel showing them.
+///
+/// Once it's full the oldest entry is dropped for each new one, and its message's buffer is reused, so a program
+/// logging every tick doesn't allocate every tick.
+#[derive(Clone, Debug)]
+pub struct LogBuffer {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    /// Oldest first
+    entries: VecDeque<LogEntry>,
+    capacity: usize,
+    /// The least severe level caught
+    level: Level,
+}
+
+impl Default for LogBuffer {
+    fn default() -> Self {
+        Self::new(Self::CAPACITY)
+    }
+}
+
+impl LogBuffer {
+    /// Entries kept by default, more than fit on screen
+    pub const CAPACITY: usize = 256;
+
+    /// Keep up to `capacity` entries, of `Level::INFO` and worse to start with
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                entries: VecDeque::with_capacity(capacity),
+                capacity,
+                level: Level::INFO,
+            })),
+        }
+    }
+
+    /// A panic while logging leaves the entries as they were, they're still worth showing
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// The least severe level caught
+    pub fn level(&self) -> Level {
+        self.lock().level
+    }
+
+    /// Catch events of `level` and worse from now on, those already caught are kept
+    pub fn set_level(&self, level: Level) {
+        self.lock().level = level;
+    }
+
+    /// Whether an event of `level` is caught
+    pub fn accepts(&self, level: Level) -> bool {
+        level <= self.lock().level
+    }
+
+    /// Add an entry written by `write`, dropping the oldest if it's full
+    pub fn push_with(&self, level: Level, write: impl FnOnce(&mut String)) {
+        let mut inner = self.lock();
+        let mut message = if inner.entries.len() >= inner.capacity {
+            inner
+                .entries
+                .pop_front()
+                .map(|entry| entry.message)
+                .unwrap_or_default()
+        } else {
+            String::new()
+        };
+        message.clear();
+        write(&mut message);
+        inner.entries.push_back(LogEntry { level, message });
+    }
+
+    /// The newest `count` entries of `level` and worse, oldest first
+    pub fn recent(&self, count: usize, level: Level) -> Vec<LogEntry> {
+        let inner = self.lock();
+        let mut entries: Vec<_> = inner
+            .entries
+            .iter()
+            .rev()
+            .filter(|entry| entry.level <= level)
+            .take(count)
+            .cloned()
+            .collect();
+        entries.reverse();
+        entries
+    }
+}
+
+/// A tracing layer that keeps events in a `LogBuffer` rather than writing them out, so they can be shown without
+/// spoiling the terminal the debugger is drawn on
+pub struct LogLayer {
+    buffer: LogBuffer,
+}
+
+impl LogLayer {
+    pub fn new(buffer: LogBuffer) -> Self {
+        Self { buffer }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for LogLayer {
+    /// The level caught can change, so every event is asked about rather than the answer being cached
+    fn register_callsite(&self, _metadata: &'static Metadata<'static>) -> Interest {
+        Interest::sometimes()
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>, _ctx: Context<'_, S>) -> bool {
+        self.buffer.accepts(*metadata.level())
+    }
+
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let level = *event.metadata().level();
+        if !self.buffer.accepts(level) {
+            return;
+        }
+        self.buffer
+            .push_with(level, |message| event.record(&mut MessageVisitor(message)));
+    }
+}
+
+/// Writes an event's message, then its other fields
+struct MessageVisitor<'a>(&'a mut String);
+
+impl Visit for MessageVisitor<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        let separator = if self.0.is_empty() { "" } else { " " };
+        // Writing to a String can't fail
+        let _ = if field.name() == "message" {
+            write!(self.0, "{separator}{value:?}")
+        } else {
+            write!(self.0, "{separator}{}={value:?}", field.name())
+        };
+    }
+}
+
+/// The level after `level` for the panel's filter, from only errors through to everything and round again
+pub fn next_level(level: Level) -> Level {
+    match level {
+        Level::ERROR => Level::WARN,
+        Level::WARN => Level::INFO,
+        Level::INFO => Level::DEBUG,
+        Level::DEBUG => Level::TRACE,
+        _ => Level::ERROR,
+    }
+}
@@ -0,0 +1,194 @@
+use tls::network::{Network, NetworkRunResult};
+use tls::rgal::SymbolResolver;
+use tls::tpu::{RunResult, TPU};
+
+/// The TPUs the debugger drives, and the one it shows.
+///
+/// A TPU on its own is ticked by itself, so the packets it sends wait in its outgoing buffer. Several are ticked
+/// together on a `Network`, which delivers their packets between them. Either way stepping and running go through
+/// here so every TPU keeps the same time.
+pub struct Machines {
+    tpus: Tpus,
+    focus: usize,
+    /// The labels in each TPU's program, by index
+    symbols: Vec<SymbolResolver>,
+}
+
+enum Tpus {
+    Single(Box<TPU>),
+    Network(Box<Network>),
+}
+
+impl Machines {
+    pub fn single(tpu: TPU) -> Self {
+        Self {
+            tpus: Tpus::Single(Box::new(tpu)),
+            focus: 0,
+            symbols: vec![SymbolResolver::default()],
+        }
+    }
+
+    /// The TPUs on `network`, which needs at least one
+    pub fn network(network: Network) -> Self {
+        assert!(!network.is_empty(), "the debugger needs a TPU to show");
+        Self {
+            symbols: vec![SymbolResolver::default(); network.len()],
+            tpus: Tpus::Network(Box::new(network)),
+            focus: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.tpus {
+            Tpus::Single(_) => 1,
+            Tpus::Network(network) => network.len(),
+        }
+    }
+
+    pub fn tpu(&self, index: usize) -> &TPU {
+        match &self.tpus {
+            Tpus::Single(tpu) => {
+                assert_eq!(index, 0, "there's only one TPU");
+                tpu
+            }
+            Tpus::Network(network) => network.tpu(index),
+        }
+    }
+
+    pub fn tpu_mut(&mut self, index: usize) -> &mut TPU {
+        match &mut self.tpus {
+            Tpus::Single(tpu) => {
+                assert_eq!(index, 0, "there's only one TPU");
+                tpu
+            }
+            Tpus::Network(network) => network.tpu_mut(index),
+        }
+    }
+
+    /// Index of the TPU being shown
+    pub fn focus(&self) -> usize {
+        self.focus
+    }
+
+    /// Show the TPU at `index`, returns false if there isn't one
+    pub fn focus_on(&mut self, index: usize) -> bool {
+        if index >= self.len() {
+            return false;
+        }
+        self.focus = index;
+        true
+    }
+
+    /// Show the next TPU, or the previous one when `step` is negative, wrapping around
+    pub fn cycle_focus(&mut self, step: isize) {
+        let len = self.len() as isize;
+        self.focus = (self.focus as isize + step).rem_euclid(len) as usize;
+    }
+
+    pub fn focused(&self) -> &TPU {
+        self.tpu(self.focus)
+    }
+
+    pub fn focused_mut(&mut self) -> &mut TPU {
+        self.tpu_mut(self.focus)
+    }
+
+    /// Name the lines of the program of the TPU at `index`, until they're set its lines have no names
+    pub fn set_symbols(&mut self, index: usize, symbols: SymbolResolver) {
+        self.symbols[index] = symbols;
+    }
+
+    /// The labels in the program of the TPU being shown
+    pub fn focused_symbols(&self) -> &SymbolResolver {
+        &self.symbols[self.focus]
+    }
+
+    pub fn all_halted(&self) -> bool {
+        match &self.tpus {
+            Tpus::Single(tpu) => tpu.halted(),
+            Tpus::Network(network) => network.all_halted(),
+        }
+    }
+
+    /// Tick every TPU once
+    pub fn tick(&mut self) {
+        match &mut self.tpus {
+            Tpus::Single(tpu) => tpu.tick(),
+            Tpus::Network(network) => network.tick(),
+        }
+    }
+
+    /// Tick every TPU until the one being shown finishes its instruction, like `TPU::step`
+    pub fn step(&mut self) {
+        let focus = self.focus;
+        match &mut self.tpus {
+            Tpus::Single(tpu) => tpu.step(),
+            Tpus::Network(network) => {
+                let old_pc = network.tpu(focus).state().program_counter;
+                while !network.tpu(focus).halted()
+                    && network.tpu(focus).state().program_counter == old_pc
+                {
+                    network.tick();
+                }
+            }
+        }
+    }
+
+    /// Tick up to `ticks` times, see `Network::run`. A TPU on its own stops when it halts.
+    pub fn run(&mut self, ticks: u64) -> NetworkRunResult {
+        match &mut self.tpus {
+            Tpus::Single(tpu) => single_result(tpu.run(ticks)),
+            Tpus::Network(network) => network.run(ticks),
+        }
+    }
+
+    /// Like `run`, but stopping as well when the one being shown reaches `pc`, reported as a breakpoint there, see
+    /// `TPU::run_until_pc`. On a network it gives up early if the one being shown halts.
+    pub fn run_until(&mut self, pc: usize, ticks: u64) -> NetworkRunResult {
+        let focus = self.focus;
+        match &mut self.tpus {
+            Tpus::Single(tpu) => single_result(tpu.run_until_pc(pc, ticks)),
+            Tpus::Network(network) => {
+                for _ in 0..ticks {
+                    let result = network.run(1);
+                    let tpu = network.tpu(focus);
+                    if result != NetworkRunResult::OutOfTicks {
+                        return result;
+                    }
+                    if tpu.halted() {
+                        break;
+                    }
+                    if tpu.about_to_fetch(pc) {
+                        return NetworkRunResult::Breakpoint {
+                            tpu: focus,
+                            address: pc,
+                        };
+                    }
+                }
+                NetworkRunResult::OutOfTicks
+            }
+        }
+    }
+}
+
+/// What a TPU on its own running means for all of them
+fn single_result(result: RunResult) -> NetworkRunResult {
+    match result {
+        RunResult::Breakpoint(address) => NetworkRunResult::Breakpoint { tpu: 0, address },
+        RunResult::Halted(_) => NetworkRunResult::AllHalted,
+        RunResult::OutOfTicks => NetworkRunResult::OutOfTicks,
+    }
+}
+
+/// One TPU in the strip along the top, its address, PC, whether it's halted and its queued packets
+pub fn summary(tpu: &TPU) -> String {
+    let state = tpu.state();
+    format!(
+        "{:04X} PC {:04X}{} in {} out {}",
+        state.network_address,
+        state.program_counter,
+        if tpu.halted() { " HALTED" } else { "" },
+        state.incoming_packets.len(),
+        state.outgoing_packets.len()
+    )
+}
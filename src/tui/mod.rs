@@ -0,0 +1,19 @@
+pub mod analog;
+pub mod app;
+pub mod clock;
+pub mod edit;
+pub mod halt;
+pub mod highlight;
+pub mod log;
+pub mod machines;
+pub mod mouse;
+pub mod network;
+pub mod ram;
+pub mod rom;
+pub mod search;
+pub mod snapshot;
+pub mod trace;
+#[cfg(test)]
+mod tui_test;
+pub mod ui;
+pub mod watch;
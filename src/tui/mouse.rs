@@ -0,0 +1,90 @@
+use ratatui::layout::{Constraint, Direction, Layout, Margin, Rect};
+use std::rc::Rc;
+use strum::IntoEnumIterator;
+use tls::shared::DigitalPin;
+
+/// Lines a notch of the scroll wheel moves
+pub const SCROLL_LINES: isize = 3;
+
+/// A panel the mouse does something in
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Panel {
+    Rom,
+    Ram,
+    Stack,
+    Network,
+    DigitalPins,
+}
+
+/// Where the panels were drawn last frame, and what's on each line of the ROM panel, so a click can be traced back to
+/// what was under it. Kept by the draw, everything is in screen cells.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ScreenLayout {
+    pub rom: Rect,
+    /// The ROM address on each line of the ROM panel under its header, `None` for a label
+    pub rom_lines: Vec<Option<usize>>,
+    pub ram: Rect,
+    pub stack: Rect,
+    pub network: Rect,
+    /// The digital pins, inside the I/O panel
+    pub digital_pins: Rect,
+}
+
+impl ScreenLayout {
+    /// The panel drawn at `column` and `row`. The pins are inside the I/O panel, so they're looked for first.
+    pub fn panel_at(&self, column: u16, row: u16) -> Option<Panel> {
+        [
+            (self.digital_pins, Panel::DigitalPins),
+            (self.rom, Panel::Rom),
+            (self.ram, Panel::Ram),
+            (self.stack, Panel::Stack),
+            (self.network, Panel::Network),
+        ]
+        .into_iter()
+        .find(|&(area, _)| contains(area, column, row))
+        .map(|(_, panel)| panel)
+    }
+
+    pub fn rom_address_at(&self, column: u16, row: u16) -> Option<usize> {
+        rom_address_at(self.rom, &self.rom_lines, column, row)
+    }
+
+    pub fn digital_pin_at(&self, column: u16, row: u16) -> Option<DigitalPin> {
+        digital_pin_at(self.digital_pins, column, row)
+    }
+}
+
+/// Whether the cell at `column` and `row` is in `area`
+fn contains(area: Rect, column: u16, row: u16) -> bool {
+    (area.left()..area.right()).contains(&column) && (area.top()..area.bottom()).contains(&row)
+}
+
+/// The ROM address on the line at `column` and `row` of a ROM panel drawn in `area` with `lines` under its header.
+/// There's none on the border, the header, a label or past the last line.
+pub fn rom_address_at(area: Rect, lines: &[Option<usize>], column: u16, row: u16) -> Option<usize> {
+    let inner = area.inner(&Margin::new(1, 1));
+    if !contains(inner, column, row) {
+        return None;
+    }
+    let line = (row - inner.y).checked_sub(1)?;
+    lines.get(usize::from(line)).copied().flatten()
+}
+
+/// Where each digital pin is drawn in `area`, in pin order
+pub fn digital_pin_areas(area: Rect) -> Rc<[Rect]> {
+    Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints(DigitalPin::iter().map(|_| Constraint::Fill(1)))
+        .split(area)
+}
+
+/// The digital pin drawn at `column` and `row` when the pins are drawn in `area`
+pub fn digital_pin_at(area: Rect, column: u16, row: u16) -> Option<DigitalPin> {
+    if !contains(area, column, row) {
+        return None;
+    }
+    let index = digital_pin_areas(area)
+        .iter()
+        .position(|&pin| contains(pin, column, row))?;
+    DigitalPin::from_repr(index as u16)
+}
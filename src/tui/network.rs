@@ -0,0 +1,47 @@
+use tls::shared::NetPacket;
+
+/// Packets listed for each queue before the rest are summed up
+pub const PACKETS_SHOWN: usize = 6;
+
+/// One packet as `sender→target :port words`, in hex, flagged if it no longer matches its checksum
+pub fn format_packet(packet: &NetPacket) -> String {
+    let words = if packet.words().is_empty() {
+        "(empty)".to_string()
+    } else {
+        packet
+            .words()
+            .iter()
+            .map(|word| format!("{word:04X}"))
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    let damaged = if packet.verify() {
+        ""
+    } else {
+        " (bad checksum)"
+    };
+    format!(
+        "{:04X}→{:04X} :{:<3} {words}{damaged}",
+        packet.sender, packet.target, packet.port
+    )
+}
+
+/// A heading and the packets in a queue, oldest first so the newest is at the bottom.
+/// Past `cap` packets the rest are counted instead of listed.
+pub fn queue_lines<'p>(
+    heading: &str,
+    packets: impl ExactSizeIterator<Item = &'p NetPacket>,
+    cap: usize,
+) -> Vec<String> {
+    let count = packets.len();
+    let mut lines = vec![format!("{heading} ({count})")];
+    lines.extend(
+        packets
+            .take(cap)
+            .map(|packet| format!("  {}", format_packet(packet))),
+    );
+    if count > cap {
+        lines.push(format!("  … {} more", count - cap));
+    }
+    lines
+}
@@ -0,0 +1,101 @@
+use std::ops::Range;
+
+/// Words shown on each line of the RAM panel
+pub const WORDS_PER_ROW: usize = 16;
+/// The address at the start of each line, `0000:`
+const ADDRESS_WIDTH: usize = 5;
+/// A space and a word in hex
+const CELL_WIDTH: usize = 5;
+
+/// Which page of RAM the RAM panel shows, and how.
+///
+/// A page is as many rows as fit in the panel, the height is kept from the last draw so paging knows how far to go.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct RamView {
+    /// First row shown
+    row: usize,
+    /// Rows that fit in the panel when it was last drawn
+    height: usize,
+    ascii: bool,
+}
+
+impl RamView {
+    /// Whether each row ends with the words as ASCII
+    pub fn ascii(&self) -> bool {
+        self.ascii
+    }
+
+    pub fn toggle_ascii(&mut self) {
+        self.ascii = !self.ascii;
+    }
+
+    /// Move by `pages`, back when negative
+    pub fn page(&mut self, pages: isize) {
+        self.row = self
+            .row
+            .saturating_add_signed(pages * self.height.max(1) as isize);
+    }
+
+    /// Move by `rows`, back when negative
+    pub fn scroll(&mut self, rows: isize) {
+        self.row = self.row.saturating_add_signed(rows);
+    }
+
+    /// Scroll so the row holding `address` is at the top, or as near as the end of RAM allows
+    pub fn show(&mut self, address: usize) {
+        self.row = address / WORDS_PER_ROW;
+    }
+
+    /// The words to draw from RAM of `len` words in a panel `height` rows high, remembered for paging
+    pub fn window(&mut self, len: usize, height: usize) -> Range<usize> {
+        let rows = len.div_ceil(WORDS_PER_ROW);
+        self.row = self.row.min(rows.saturating_sub(height));
+        self.height = height;
+        let start = self.row * WORDS_PER_ROW;
+        start..(start + height * WORDS_PER_ROW).min(len)
+    }
+}
+
+/// One line per row of `words`, which start at `address`.
+///
+/// Each line is the address of its first word, then the words in hex, then with `ascii` the high and low byte of each
+/// word as characters, with `.` for anything that isn't printable.
+pub fn format_page(words: &[u16], address: usize, ascii: bool) -> Vec<String> {
+    words
+        .chunks(WORDS_PER_ROW)
+        .enumerate()
+        .map(|(row, words)| {
+            let mut line = format!("{:04X}:", address + row * WORDS_PER_ROW);
+            for word in words {
+                line.push_str(&format!(" {word:04X}"));
+            }
+            if ascii {
+                // Short last rows keep the ASCII column lined up
+                line.push_str(&" ".repeat((WORDS_PER_ROW - words.len()) * CELL_WIDTH));
+                line.push_str("  |");
+                line.extend(
+                    words
+                        .iter()
+                        .flat_map(|word| word.to_be_bytes())
+                        .map(printable),
+                );
+                line.push('|');
+            }
+            line
+        })
+        .collect()
+}
+
+/// Where the word in `column` of a row is in a line from `format_page`, with the space before it
+pub fn cell_columns(column: usize) -> Range<usize> {
+    let start = ADDRESS_WIDTH + column * CELL_WIDTH;
+    start..start + CELL_WIDTH
+}
+
+fn printable(byte: u8) -> char {
+    if byte.is_ascii_graphic() || byte == b' ' {
+        byte as char
+    } else {
+        '.'
+    }
+}
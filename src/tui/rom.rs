@@ -0,0 +1,82 @@
+use std::ops::Range;
+
+/// Which part of the ROM the ROM panel shows, and the line under the cursor.
+///
+/// It follows the program counter, keeping it in the middle of the panel, until the cursor is moved. After that the
+/// panel scrolls just enough to keep the cursor in view, and stepping or running the TPU goes back to following. The
+/// offset and height are kept from the last draw so the keys know where the panel is and how long a page is.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct RomView {
+    /// First line shown
+    offset: usize,
+    /// Lines that fit in the panel when it was last drawn
+    height: usize,
+    /// `None` while following the program counter
+    cursor: Option<usize>,
+}
+
+impl RomView {
+    pub fn following(&self) -> bool {
+        self.cursor.is_none()
+    }
+
+    /// Go back to keeping the program counter in view
+    pub fn follow(&mut self) {
+        self.cursor = None;
+    }
+
+    /// The line under the cursor, the program counter while following
+    pub fn cursor(&self, program_counter: usize) -> usize {
+        self.cursor.unwrap_or(program_counter)
+    }
+
+    /// Move the cursor by `lines`, up when negative, and stop following the program counter
+    pub fn move_cursor(&mut self, lines: isize, program_counter: usize, len: usize) {
+        let cursor = self.cursor(program_counter).saturating_add_signed(lines);
+        self.cursor = Some(cursor.min(len.saturating_sub(1)));
+    }
+
+    /// Put the cursor on `line`, and stop following the program counter
+    pub fn set_cursor(&mut self, line: usize, len: usize) {
+        self.cursor = Some(line.min(len.saturating_sub(1)));
+    }
+
+    /// Move the cursor by `pages` of the panel's height
+    pub fn move_cursor_pages(&mut self, pages: isize, program_counter: usize, len: usize) {
+        self.move_cursor(pages * self.height.max(1) as isize, program_counter, len);
+    }
+
+    /// The lines to draw for a ROM of `len` instructions in a panel `height` lines high, remembered for the keys
+    pub fn window(&mut self, program_counter: usize, len: usize, height: usize) -> Range<usize> {
+        let offset = self.cursor.map(|cursor| {
+            if cursor < self.offset {
+                cursor
+            } else if cursor >= self.offset + height {
+                (cursor + 1).saturating_sub(height)
+            } else {
+                self.offset
+            }
+        });
+        let window = visible_window(program_counter, offset, len, height);
+        self.offset = window.start;
+        self.height = height;
+        window
+    }
+}
+
+/// The lines of a ROM of `len` instructions that fit in `height` lines.
+///
+/// Without an `offset` the program counter is centred. Either way the window doesn't run past the end of the ROM, so a
+/// short program starts at the top and the last page is full.
+pub fn visible_window(
+    program_counter: usize,
+    offset: Option<usize>,
+    len: usize,
+    height: usize,
+) -> Range<usize> {
+    let last_start = len.saturating_sub(height);
+    let start = offset
+        .unwrap_or_else(|| program_counter.saturating_sub(height / 2))
+        .min(last_start);
+    start..(start + height).min(len)
+}
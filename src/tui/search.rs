@@ -0,0 +1,109 @@
+use std::rc::Rc;
+use tls::cli::parse_number;
+use tls::rgal::SymbolResolver;
+use tls::shared::Instruction;
+
+/// What was searched for and where it was found
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Search {
+    pub query: String,
+    /// ROM addresses whose instruction matched, in order
+    pub rom: Vec<usize>,
+    /// RAM addresses holding the value searched for, in order
+    pub ram: Vec<usize>,
+    /// The RAM match last gone to, RAM has no cursor to go on from
+    ram_at: Option<usize>,
+}
+
+impl Search {
+    /// Search the ROM as the ROM panel shows it, and the RAM too if `query` is a number
+    pub fn new(
+        query: &str,
+        rom: &[Rc<Instruction>],
+        symbols: &SymbolResolver,
+        ram: &[u16],
+    ) -> Self {
+        let query = query.trim();
+        let value = parse_number(query);
+        Self {
+            query: query.to_string(),
+            rom: match value {
+                Some(value) => search_rom_value(rom, symbols, value),
+                None => search_rom(rom, symbols, query),
+            },
+            ram: value.map_or_else(Vec::new, |value| search_ram(ram, value)),
+            ram_at: None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rom.is_empty() && self.ram.is_empty()
+    }
+
+    /// The first ROM match after `address`, round to the first one past the last
+    pub fn next_rom(&self, address: usize) -> Option<usize> {
+        next_after(&self.rom, address)
+    }
+
+    /// The RAM match after the one last gone to, round to the first one past the last
+    pub fn next_ram(&mut self) -> Option<usize> {
+        self.ram_at = match self.ram_at {
+            Some(address) => next_after(&self.ram, address),
+            None => self.ram.first().copied(),
+        };
+        self.ram_at
+    }
+}
+
+fn next_after(matches: &[usize], address: usize) -> Option<usize> {
+    matches
+        .iter()
+        .copied()
+        .find(|&found| found > address)
+        .or_else(|| matches.first().copied())
+}
+
+/// The addresses of the instructions whose text contains `query`, ignoring case, e.g. a mnemonic, a register or a label
+pub fn search_rom(rom: &[Rc<Instruction>], symbols: &SymbolResolver, query: &str) -> Vec<usize> {
+    if query.is_empty() {
+        return Vec::new();
+    }
+    let query = query.to_uppercase();
+    rom.iter()
+        .enumerate()
+        .filter(|(_, instruction)| {
+            symbols
+                .format_instruction(instruction)
+                .to_uppercase()
+                .contains(&query)
+        })
+        .map(|(address, _)| address)
+        .collect()
+}
+
+/// The addresses of the instructions with `value` as an operand, where the ROM panel doesn't show a label instead
+pub fn search_rom_value(
+    rom: &[Rc<Instruction>],
+    symbols: &SymbolResolver,
+    value: u16,
+) -> Vec<usize> {
+    let operand = format!("{value:04X}");
+    rom.iter()
+        .enumerate()
+        .filter(|(_, instruction)| {
+            let text = symbols.format_instruction(instruction);
+            text.split_once(' ')
+                .is_some_and(|(_, operands)| operands.split(", ").any(|found| found == operand))
+        })
+        .map(|(address, _)| address)
+        .collect()
+}
+
+/// The addresses of the words of `ram` holding `value`
+pub fn search_ram(ram: &[u16], value: u16) -> Vec<usize> {
+    ram.iter()
+        .enumerate()
+        .filter(|&(_, &word)| word == value)
+        .map(|(address, _)| address)
+        .collect()
+}
@@ -0,0 +1,49 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tls::shared::Instruction;
+use tls::tpu::{TPU, TpuSnapshot};
+
+/// Why a snapshot couldn't be restored
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    Snapshot(tls::Error),
+    /// The snapshot is for another program, restoring it would replace the one that's loaded
+    RomMismatch(Box<TpuSnapshot>),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(error) => write!(f, "couldn't read the snapshot: {error}"),
+            LoadError::Snapshot(error) => write!(f, "{error}"),
+            LoadError::RomMismatch(_) => write!(f, "the snapshot is for a different program"),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+/// Write the TPU's state to `tpu-<milliseconds since the epoch>.json` in `directory`, returns where it went
+pub fn save(tpu: &TPU, directory: &Path) -> io::Result<PathBuf> {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let path = directory.join(format!("tpu-{millis}.json"));
+    std::fs::write(&path, tpu.snapshot().to_json()?)?;
+    Ok(path)
+}
+
+/// Read a snapshot, refusing one whose ROM isn't `rom` so a checkpoint isn't restored over the wrong program by mistake
+pub fn load(path: &Path, rom: &[Rc<Instruction>]) -> Result<TpuSnapshot, LoadError> {
+    let json = std::fs::read_to_string(path).map_err(LoadError::Io)?;
+    let snapshot = TpuSnapshot::from_json(&json).map_err(LoadError::Snapshot)?;
+    if snapshot.state.rom != rom {
+        return Err(LoadError::RomMismatch(Box::new(snapshot)));
+    }
+    Ok(snapshot)
+}
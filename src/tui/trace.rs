@@ -0,0 +1,177 @@
+use std::cell::RefCell;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use strum::IntoEnumIterator;
+use tls::observer::{Observer, ObserverId};
+use tls::shared::Register;
+use tls::tpu::{TPU, TraceEvent};
+
+/// Bytes of trace held in memory before they're written out, unless `--trace-buffer` says otherwise
+pub const BUFFER: usize = 64 * 1024;
+
+/// The first line of a trace, naming the columns `format_row` writes
+pub fn header() -> String {
+    let registers: Vec<String> = Register::iter()
+        .map(|register| register.to_string())
+        .collect();
+    format!(
+        "cycle,pc,mnemonic,operands,{},ram_address,ram_value,pins",
+        registers.join(",")
+    )
+}
+
+/// A line of a trace for `event`. Operands are separated by spaces so none need quoting, and the RAM columns are
+/// empty if the instruction didn't write to RAM.
+pub fn format_row(event: &TraceEvent) -> String {
+    let text = event.instruction.to_string();
+    let (mnemonic, operands) = text.split_once(' ').unwrap_or((&text, ""));
+    let mut row = format!(
+        "{},{:#06X},{mnemonic},{}",
+        event.cycle,
+        event.pc,
+        operands.replace(", ", " ")
+    );
+    // Writing to a String can't fail
+    for register in event.registers {
+        let _ = write!(row, ",{register:#06X}");
+    }
+    let _ = match event.ram_write {
+        Some((address, value)) => write!(row, ",{address:#06X},{value:#06X}"),
+        None => write!(row, ",,"),
+    };
+    let _ = write!(row, ",{:#010b}", event.digital_pins);
+    row
+}
+
+/// Writes a row for each event it's given to `writer` as they come, so a long run isn't held in memory. Give it a
+/// `BufWriter` to write in blocks rather than a line at a time.
+#[derive(Debug)]
+pub struct TraceRecorder<W: Write> {
+    writer: W,
+    rows: u64,
+    /// The first write that failed, nothing more is written after it
+    error: Option<io::Error>,
+}
+
+impl<W: Write> TraceRecorder<W> {
+    /// Start a trace on `writer` with its header
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writeln!(writer, "{}", header())?;
+        Ok(Self {
+            writer,
+            rows: 0,
+            error: None,
+        })
+    }
+
+    pub fn record(&mut self, event: &TraceEvent) {
+        if self.error.is_some() {
+            return;
+        }
+        match writeln!(self.writer, "{}", format_row(event)) {
+            Ok(()) => self.rows += 1,
+            Err(error) => self.error = Some(error),
+        }
+    }
+
+    /// Write out anything still held, returns the rows written or the first error
+    pub fn finish(&mut self) -> io::Result<u64> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+        self.writer.flush()?;
+        Ok(self.rows)
+    }
+}
+
+/// Records the instructions a TPU retires, see `TPU::add_observer`. A clone shares the recorder, so the host keeps one
+/// to finish the trace once the other has been removed.
+#[derive(Debug)]
+pub struct TraceObserver<W: Write>(pub Rc<RefCell<TraceRecorder<W>>>);
+
+impl<W: Write> Clone for TraceObserver<W> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<W: Write> Observer for TraceObserver<W> {
+    fn on_instruction_retired(&mut self, event: &TraceEvent) {
+        self.0.borrow_mut().record(event);
+    }
+}
+
+/// A trace being written to a file
+#[derive(Clone, Debug)]
+struct Recording {
+    /// The TPU being traced
+    tpu: usize,
+    path: PathBuf,
+    observer: TraceObserver<BufWriter<File>>,
+    /// For removing the observer from the TPU when the trace stops
+    id: ObserverId,
+}
+
+/// Recording a trace of one TPU to a CSV file
+#[derive(Clone, Debug)]
+pub struct Trace {
+    /// Bytes held before they're written out
+    pub buffer: usize,
+    recording: Option<Recording>,
+}
+
+impl Default for Trace {
+    fn default() -> Self {
+        Self {
+            buffer: BUFFER,
+            recording: None,
+        }
+    }
+}
+
+impl Trace {
+    /// The TPU being traced, if there is one
+    pub fn tpu(&self) -> Option<usize> {
+        self.recording.as_ref().map(|recording| recording.tpu)
+    }
+
+    /// Where the trace is going, if there is one
+    pub fn path(&self) -> Option<&Path> {
+        self.recording
+            .as_ref()
+            .map(|recording| recording.path.as_path())
+    }
+
+    /// Trace `tpu`, the `index`th, to `trace-<milliseconds since the epoch>.csv` in `directory`, returns where it's going
+    pub fn start(&mut self, tpu: &mut TPU, index: usize, directory: &Path) -> io::Result<PathBuf> {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let path = directory.join(format!("trace-{millis}.csv"));
+        let file = BufWriter::with_capacity(self.buffer, File::create(&path)?);
+        let observer = TraceObserver(Rc::new(RefCell::new(TraceRecorder::new(file)?)));
+
+        let id = tpu.add_observer(observer.clone());
+        self.recording = Some(Recording {
+            tpu: index,
+            path: path.clone(),
+            observer,
+            id,
+        });
+        Ok(path)
+    }
+
+    /// Stop tracing `tpu` and write out the rest of the trace, returns where it went and how many rows it has.
+    /// `None` if there wasn't a trace.
+    pub fn stop(&mut self, tpu: &mut TPU) -> Option<(PathBuf, io::Result<u64>)> {
+        let recording = self.recording.take()?;
+        tpu.remove_observer(recording.id);
+        let rows = recording.observer.0.borrow_mut().finish();
+        Some((recording.path, rows))
+    }
+}
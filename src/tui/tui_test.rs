@@ -0,0 +1,1700 @@
+use crate::tui::analog::{AnalogHistory, Sampling, downsample};
+use crate::tui::app::{Action, App, Prompt, PromptKind, RUN_TO_CURSOR_TICKS, digital_pin_for_key};
+use crate::tui::clock::{Clock, RateMeter, RunSpeed, thousands};
+use crate::tui::edit::{AnalogEdit, EditError, PacketEdit, RamEdit, RegisterEdit};
+use crate::tui::halt::halt_banner;
+use crate::tui::highlight::{Heat, Highlights, cell_style, heat, text_style};
+use crate::tui::log::{LogBuffer, LogEntry, LogLayer, next_level};
+use crate::tui::machines::{Machines, summary};
+use crate::tui::mouse::{Panel, ScreenLayout};
+use crate::tui::network::{format_packet, queue_lines};
+use crate::tui::ram::{RamView, WORDS_PER_ROW, cell_columns, format_page};
+use crate::tui::rom::{RomView, visible_window};
+use crate::tui::search::{Search, search_ram, search_rom, search_rom_value};
+use crate::tui::snapshot::{self, LoadError};
+use crate::tui::trace::{TraceObserver, TraceRecorder, format_row, header};
+use crate::tui::watch::{Watches, format_watch};
+use crossterm::event::{KeyCode, KeyModifiers, MouseButton, MouseEvent, MouseEventKind};
+use ratatui::layout::Rect;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::time::Duration;
+use tls::network::Network;
+use tls::rgal::{SymbolResolver, parse_program, parse_program_with_symbols};
+use tls::shared::{AnalogPin, DigitalPin, HaltReason, NetPacket, Register};
+use tls::tpu::{
+    EvalError, Expr, PinError, SnapshotError, TPU, TraceEvent, create_basic_tpu_config,
+};
+use tracing::Level;
+use tracing_subscriber::layer::SubscriberExt;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use strum::EnumCount;
+
+    const FRAME: Duration = Duration::from_millis(50);
+
+    /// A clock at `speed` with nothing owed, as the debugger's is after a speed change
+    fn clock_at(speed: RunSpeed) -> Clock {
+        let mut clock = Clock::default();
+        clock.set_speed(speed);
+        clock
+    }
+
+    /// Ticks run over `frames` frames of 50ms
+    fn ticks_over(clock: &mut Clock, frames: usize) -> Vec<u64> {
+        (0..frames).map(|_| clock.ticks_due(FRAME)).collect()
+    }
+
+    #[test]
+    fn test_clock_ticks_per_frame() {
+        // Slow clocks carry the time over until a tick is due
+        let mut clock = clock_at(RunSpeed::Hz1);
+        let ticks = ticks_over(&mut clock, 40);
+        assert_eq!(ticks.iter().sum::<u64>(), 2);
+        assert_eq!(ticks[19], 1);
+        assert_eq!(ticks[39], 1);
+
+        let mut clock = clock_at(RunSpeed::Hz10);
+        assert_eq!(ticks_over(&mut clock, 4), [0, 1, 0, 1]);
+
+        // Fast clocks run a batch each frame
+        let mut clock = clock_at(RunSpeed::Hz100);
+        assert_eq!(ticks_over(&mut clock, 2), [5, 5]);
+        let mut clock = clock_at(RunSpeed::KHz1);
+        assert_eq!(clock.ticks_due(Duration::from_millis(33)), 33);
+        assert_eq!(clock.ticks_due(Duration::from_micros(1500)), 1);
+        assert_eq!(clock.ticks_due(Duration::from_micros(500)), 1);
+
+        let mut clock = clock_at(RunSpeed::Unlimited);
+        assert_eq!(clock.ticks_due(FRAME), Clock::UNLIMITED_TICKS_PER_FRAME);
+        assert_eq!(
+            clock.ticks_due(Duration::ZERO),
+            Clock::UNLIMITED_TICKS_PER_FRAME
+        );
+
+        // A stall only catches up a second's worth
+        let mut clock = clock_at(RunSpeed::Hz100);
+        assert_eq!(clock.ticks_due(Duration::from_secs(30)), 100);
+    }
+
+    #[test]
+    fn test_rate_meter() {
+        let mut meter = RateMeter::new(3);
+        assert_eq!(meter.hz(), None);
+        meter.record(0, Duration::ZERO);
+        assert_eq!(meter.hz(), None);
+
+        // Taken over the frames in the window, not just the last one
+        meter.record(100, Duration::from_millis(50));
+        meter.record(300, Duration::from_millis(50));
+        assert_eq!(meter.hz(), Some(4000.0));
+        meter.record(200, Duration::from_millis(100));
+        meter.record(200, Duration::from_millis(100));
+        assert_eq!(meter.hz(), Some(2800.0));
+
+        meter.clear();
+        assert_eq!(meter.hz(), None);
+
+        // The debugger measures while running, and starts again once paused
+        let mut machines = Machines::single(tpu("NOP\nJMP 0"));
+        let mut app = App::new();
+        app.clock.set_speed(RunSpeed::Hz100);
+        app.handle_key(KeyCode::Char('r'), &mut machines);
+        app.run_frame(&mut machines, Duration::from_millis(100));
+        assert_eq!(app.rate.hz(), Some(100.0));
+        app.handle_key(KeyCode::Char('r'), &mut machines);
+        app.run_frame(&mut machines, Duration::from_millis(100));
+        assert_eq!(app.rate.hz(), None);
+    }
+
+    #[test]
+    fn test_thousands() {
+        assert_eq!(thousands(0), "0");
+        assert_eq!(thousands(999), "999");
+        assert_eq!(thousands(1000), "1,000");
+        assert_eq!(thousands(1_250_000), "1,250,000");
+        assert_eq!(thousands(u64::MAX), "18,446,744,073,709,551,615");
+    }
+
+    #[test]
+    fn test_clock_speed_changes() {
+        assert_eq!(RunSpeed::Hz1.slower(), RunSpeed::Hz1);
+        assert_eq!(RunSpeed::Hz1.faster(), RunSpeed::Hz10);
+        assert_eq!(RunSpeed::KHz1.faster(), RunSpeed::Unlimited);
+        assert_eq!(RunSpeed::Unlimited.faster(), RunSpeed::Unlimited);
+        assert_eq!(RunSpeed::Unlimited.slower(), RunSpeed::KHz1);
+        assert_eq!(RunSpeed::KHz1.to_string(), "1 kHz");
+
+        // What was owed at the old speed is dropped
+        let mut clock = clock_at(RunSpeed::Hz1);
+        ticks_over(&mut clock, 19);
+        clock.set_speed(RunSpeed::Hz10);
+        assert_eq!(clock.speed(), RunSpeed::Hz10);
+        assert_eq!(ticks_over(&mut clock, 2), [0, 1]);
+    }
+
+    #[test]
+    fn test_rom_visible_window() {
+        // The program counter is kept in the middle
+        assert_eq!(visible_window(50, None, 200, 10), 45..55);
+        assert_eq!(visible_window(51, None, 200, 11), 46..57);
+        // Without running off either end
+        assert_eq!(visible_window(2, None, 200, 10), 0..10);
+        assert_eq!(visible_window(198, None, 200, 10), 190..200);
+        // A program shorter than the panel is shown whole
+        assert_eq!(visible_window(3, None, 5, 10), 0..5);
+        assert_eq!(visible_window(0, None, 0, 10), 0..0);
+        assert_eq!(visible_window(0, None, 200, 0), 0..0);
+
+        // A scroll offset wins over the program counter, up to the last page
+        assert_eq!(visible_window(50, Some(0), 200, 10), 0..10);
+        assert_eq!(visible_window(50, Some(120), 200, 10), 120..130);
+        assert_eq!(visible_window(50, Some(500), 200, 10), 190..200);
+    }
+
+    #[test]
+    fn test_rom_view_cursor() {
+        let mut view = RomView::default();
+        assert!(view.following());
+        assert_eq!(view.window(50, 200, 10), 45..55);
+        assert_eq!(view.cursor(50), 50);
+
+        // Moving the cursor starts from the PC and only scrolls once it leaves the panel
+        view.move_cursor(-5, 50, 200);
+        assert!(!view.following());
+        assert_eq!(view.cursor(60), 45);
+        assert_eq!(view.window(60, 200, 10), 45..55);
+        view.move_cursor(-1, 60, 200);
+        assert_eq!(view.window(60, 200, 10), 44..54);
+        view.move_cursor_pages(2, 60, 200);
+        assert_eq!(view.cursor(60), 64);
+        assert_eq!(view.window(60, 200, 10), 55..65);
+        // It stays in the ROM
+        view.move_cursor_pages(-100, 60, 200);
+        assert_eq!(view.cursor(60), 0);
+        assert_eq!(view.window(60, 200, 10), 0..10);
+        view.move_cursor_pages(100, 60, 200);
+        assert_eq!(view.cursor(60), 199);
+        assert_eq!(view.window(60, 200, 10), 190..200);
+
+        view.follow();
+        assert_eq!(view.window(60, 200, 10), 55..65);
+    }
+
+    fn tpu(program: &str) -> TPU {
+        create_basic_tpu_config(parse_program(program).expect("parse failure"))
+    }
+
+    #[test]
+    fn test_app_breakpoint_keys() {
+        let mut machines = Machines::single(tpu("NOP\nNOP\nNOP\nJMP 0"));
+        let mut app = App::new();
+
+        // B toggles the breakpoint under the cursor, which starts on the PC
+        app.handle_key(KeyCode::Down, &mut machines);
+        app.handle_key(KeyCode::Down, &mut machines);
+        assert_eq!(app.rom_view.cursor(0), 2);
+        assert_eq!(
+            app.handle_key(KeyCode::Char('b'), &mut machines),
+            Action::Continue
+        );
+        assert!(machines.focused().breakpoints().contains(&2));
+        app.handle_key(KeyCode::Up, &mut machines);
+        app.handle_key(KeyCode::Char('b'), &mut machines);
+        app.handle_key(KeyCode::Char('b'), &mut machines);
+        assert_eq!(
+            machines
+                .focused()
+                .breakpoints()
+                .iter()
+                .copied()
+                .collect::<Vec<_>>(),
+            [2]
+        );
+
+        // Running stops at it with a banner, and carries on past it
+        app.handle_key(KeyCode::Char('r'), &mut machines);
+        assert!(app.running);
+        assert!(app.rom_view.following());
+        app.clock.set_speed(RunSpeed::Unlimited);
+        app.run_frame(&mut machines, Duration::from_millis(50));
+        assert!(!app.running);
+        assert_eq!(machines.focused().state().program_counter, 2);
+        assert_eq!(app.banner.as_deref(), Some("breakpoint hit at 0x0002"));
+
+        // The cursor and breakpoints can't change while running
+        app.handle_key(KeyCode::Char('r'), &mut machines);
+        assert_eq!(app.banner, None);
+        app.handle_key(KeyCode::Down, &mut machines);
+        app.handle_key(KeyCode::Char('b'), &mut machines);
+        assert!(app.rom_view.following());
+        assert_eq!(machines.focused().breakpoints().len(), 1);
+        app.run_frame(&mut machines, Duration::from_millis(50));
+        assert_eq!(app.banner.as_deref(), Some("breakpoint hit at 0x0002"));
+        assert!(machines.focused().state().cycle_count > 4);
+    }
+
+    #[test]
+    fn test_app_keys() {
+        let mut machines = Machines::single(tpu("NOP\nHLT"));
+        let mut app = App::new();
+
+        assert_eq!(
+            app.handle_key(KeyCode::Char('+'), &mut machines),
+            Action::Continue
+        );
+        assert_eq!(app.clock.speed(), RunSpeed::Hz100);
+        app.handle_key(KeyCode::Char('-'), &mut machines);
+        app.handle_key(KeyCode::Char('-'), &mut machines);
+        assert_eq!(app.clock.speed(), RunSpeed::Hz1);
+
+        app.handle_key(KeyCode::Char('a'), &mut machines);
+        assert!(app.ram_view.ascii());
+        app.handle_key(KeyCode::Char('}'), &mut machines);
+        app.handle_key(KeyCode::Char('}'), &mut machines);
+        app.handle_key(KeyCode::Char('{'), &mut machines);
+        assert_eq!(app.stack_scroll, 1);
+        app.handle_key(KeyCode::Char('s'), &mut machines);
+        assert_eq!(machines.focused().state().program_counter, 1);
+
+        // Running stops when the TPU halts, and a halted TPU can't be run
+        app.handle_key(KeyCode::Char('r'), &mut machines);
+        app.clock.set_speed(RunSpeed::Unlimited);
+        app.run_frame(&mut machines, Duration::from_millis(50));
+        assert!(machines.focused().halted());
+        assert!(!app.running);
+        app.handle_key(KeyCode::Char('r'), &mut machines);
+        assert!(!app.running);
+
+        assert_eq!(
+            app.handle_key(KeyCode::Char('L'), &mut machines),
+            Action::Reload
+        );
+
+        // L opens the log, where F changes how much it catches
+        app.handle_key(KeyCode::Char('f'), &mut machines);
+        assert_eq!(app.log.level(), Level::INFO);
+        app.handle_key(KeyCode::Char('l'), &mut machines);
+        assert!(app.show_log);
+        app.handle_key(KeyCode::Char('f'), &mut machines);
+        assert_eq!(app.log.level(), Level::DEBUG);
+        app.handle_key(KeyCode::Char('l'), &mut machines);
+        assert!(!app.show_log);
+        assert_eq!(
+            app.handle_key(KeyCode::Char('q'), &mut machines),
+            Action::Quit
+        );
+    }
+
+    #[test]
+    fn test_ram_format_page() {
+        let mut ram = [0u16; 40];
+        ram[0] = 0x4869; // "Hi"
+        ram[1] = 0x2100; // "!" and a NUL
+        ram[17] = 0xBEEF;
+
+        assert_eq!(
+            format_page(&ram[..32], 0, false),
+            [
+                "0000: 4869 2100 0000 0000 0000 0000 0000 0000 0000 0000 0000 0000 0000 0000 0000 0000",
+                "0010: 0000 BEEF 0000 0000 0000 0000 0000 0000 0000 0000 0000 0000 0000 0000 0000 0000",
+            ]
+        );
+        assert_eq!(
+            format_page(&ram[..16], 0, true),
+            [
+                "0000: 4869 2100 0000 0000 0000 0000 0000 0000 0000 0000 0000 0000 0000 0000 0000 0000  |Hi!.............................|"
+            ]
+        );
+        // A short last row keeps the columns lined up
+        let page = format_page(&ram[32..], 32, true);
+        assert_eq!(
+            page,
+            [
+                "0020: 0000 0000 0000 0000 0000 0000 0000 0000                                          |................|"
+            ]
+        );
+        assert_eq!(page[0].find('|'), Some(5 + WORDS_PER_ROW * 5 + 2));
+        assert!(format_page(&[], 0, true).is_empty());
+
+        // Cells can be picked out of a line to highlight them
+        let line = &format_page(&ram[16..32], 16, true)[0];
+        assert_eq!(&line[cell_columns(1)], " BEEF");
+        assert_eq!(&line[cell_columns(15)], " 0000");
+    }
+
+    #[test]
+    fn test_ram_view_paging() {
+        let mut view = RamView::default();
+        assert_eq!(view.window(128, 3), 0..48);
+        view.page(1);
+        assert_eq!(view.window(128, 3), 48..96);
+        // The last page is kept full
+        view.page(1);
+        assert_eq!(view.window(128, 3), 80..128);
+        view.page(-10);
+        assert_eq!(view.window(128, 3), 0..48);
+        view.scroll(2);
+        assert_eq!(view.window(128, 3), 32..80);
+        view.scroll(-5);
+        assert_eq!(view.window(128, 3), 0..48);
+        // RAM that fits is shown whole, and a part row is still shown
+        assert_eq!(view.window(40, 8), 0..40);
+
+        assert!(!view.ascii());
+        view.toggle_ascii();
+        assert!(view.ascii());
+    }
+
+    #[test]
+    fn test_register_edit_parse() {
+        let edit = |register, value| Ok(RegisterEdit { register, value });
+        assert_eq!(RegisterEdit::parse("A 0x10"), edit(Register::A, 0x10));
+        assert_eq!(RegisterEdit::parse(" r3=42 "), edit(Register::R3, 42));
+        assert_eq!(RegisterEdit::parse("X = 0b101"), edit(Register::X, 5));
+        assert_eq!(RegisterEdit::parse("y 65535"), edit(Register::Y, 0xFFFF));
+
+        assert_eq!(RegisterEdit::parse(""), Err(EditError::MissingRegister));
+        assert_eq!(RegisterEdit::parse("A"), Err(EditError::MissingValue));
+        assert_eq!(
+            RegisterEdit::parse("Q 1"),
+            Err(EditError::UnknownRegister("Q".into()))
+        );
+        assert_eq!(
+            RegisterEdit::parse("A 65536"),
+            Err(EditError::InvalidValue("65536".into()))
+        );
+        assert_eq!(
+            RegisterEdit::parse("A 0xZZ"),
+            Err(EditError::InvalidValue("0xZZ".into()))
+        );
+        assert_eq!(
+            RegisterEdit::parse("A 1 2"),
+            Err(EditError::InvalidValue("1 2".into()))
+        );
+        assert_eq!(
+            EditError::UnknownRegister("Q".into()).to_string(),
+            "no register called Q"
+        );
+
+        let mut tpu = tpu("HLT");
+        RegisterEdit::parse("R6 0xBEEF").unwrap().apply(&mut tpu);
+        assert_eq!(tpu.read_register(Register::R6), 0xBEEF);
+    }
+
+    #[test]
+    fn test_app_register_edit_prompt() {
+        let mut machines = Machines::single(tpu("LDR X, 1\nADD A, X\nHLT"));
+        let mut app = App::new();
+        let type_in = |app: &mut App, machines: &mut Machines, text: &str| {
+            for c in text.chars() {
+                app.handle_key(KeyCode::Char(c), machines);
+            }
+        };
+
+        app.handle_key(KeyCode::Char('e'), &mut machines);
+        assert_eq!(app.prompt, Some(Prompt::new(PromptKind::Register)));
+        // Keys go to the prompt, so Q doesn't quit
+        assert_eq!(
+            app.handle_key(KeyCode::Char('q'), &mut machines),
+            Action::Continue
+        );
+        app.handle_key(KeyCode::Backspace, &mut machines);
+        type_in(&mut app, &mut machines, "A 0x1G");
+        app.handle_key(KeyCode::Enter, &mut machines);
+        assert_eq!(
+            app.prompt.as_ref().map(|prompt| prompt.input.as_str()),
+            Some("A 0x1G")
+        );
+        assert_eq!(app.edit_error.as_deref(), Some("0x1G isn't a 16 bit value"));
+        assert_eq!(machines.focused().read_register(Register::A), 0);
+
+        app.handle_key(KeyCode::Backspace, &mut machines);
+        type_in(&mut app, &mut machines, "0");
+        app.handle_key(KeyCode::Enter, &mut machines);
+        assert_eq!(app.prompt, None);
+        assert_eq!(app.edit_error, None);
+        assert_eq!(machines.focused().read_register(Register::A), 0x10);
+        assert_eq!(app.edited, [Register::A]);
+
+        // The mark goes once the TPU moves on, and the program sees the new value
+        app.handle_key(KeyCode::Char('s'), &mut machines);
+        app.handle_key(KeyCode::Char('s'), &mut machines);
+        assert!(app.edited.is_empty());
+        assert_eq!(machines.focused().read_register(Register::A), 0x11);
+
+        // Esc gives up without changing anything
+        app.handle_key(KeyCode::Char('e'), &mut machines);
+        type_in(&mut app, &mut machines, "A 5");
+        app.handle_key(KeyCode::Esc, &mut machines);
+        assert_eq!(app.prompt, None);
+        assert_eq!(machines.focused().read_register(Register::A), 0x11);
+    }
+
+    #[test]
+    fn test_ram_edit_parse() {
+        let edit = |addresses, value| Ok(RamEdit { addresses, value });
+        assert_eq!(RamEdit::parse("0x10 5", 128), edit(0x10..0x11, 5));
+        assert_eq!(RamEdit::parse("127=0xFFFF", 128), edit(127..128, 0xFFFF));
+        assert_eq!(RamEdit::parse("0x10..0x18 = 0", 128), edit(0x10..0x18, 0));
+        assert_eq!(RamEdit::parse(" 0..128 0b1", 128), edit(0..128, 1));
+
+        assert_eq!(RamEdit::parse("", 128), Err(EditError::MissingAddress));
+        assert_eq!(RamEdit::parse("0x10", 128), Err(EditError::MissingValue));
+        assert_eq!(
+            RamEdit::parse("A 1", 128),
+            Err(EditError::InvalidAddress("A".into()))
+        );
+        assert_eq!(
+            RamEdit::parse("0x10..x 1", 128),
+            Err(EditError::InvalidAddress("0x10..x".into()))
+        );
+        assert_eq!(
+            RamEdit::parse("128 1", 128),
+            Err(EditError::AddressOutOfRange("128".into()))
+        );
+        assert_eq!(
+            RamEdit::parse("120..130 1", 128),
+            Err(EditError::AddressOutOfRange("120..130".into()))
+        );
+        assert_eq!(
+            RamEdit::parse("0x18..0x10 = 0", 128),
+            Err(EditError::AddressOutOfRange("0x18..0x10".into()))
+        );
+        assert_eq!(
+            RamEdit::parse("0 70000", 128),
+            Err(EditError::InvalidValue("70000".into()))
+        );
+
+        let mut tpu = tpu("HLT");
+        RamEdit::parse("0x10..0x13 = 7", 128)
+            .unwrap()
+            .apply(&mut tpu);
+        let words: Vec<u16> = (0x0F..0x14).map(|address| tpu.read_ram(address)).collect();
+        assert_eq!(words, [0, 7, 7, 7, 0]);
+    }
+
+    #[test]
+    fn test_app_ram_poke_prompt() {
+        let mut machines = Machines::single(tpu("LDR X, 1\nHLT"));
+        let mut app = App::new();
+
+        app.handle_key(KeyCode::Char('p'), &mut machines);
+        assert_eq!(app.prompt, Some(Prompt::new(PromptKind::Ram)));
+        for c in "0x20..0x22=0x41".chars() {
+            app.handle_key(KeyCode::Char(c), &mut machines);
+        }
+        app.handle_key(KeyCode::Enter, &mut machines);
+        assert_eq!(app.prompt, None);
+        assert_eq!(machines.focused().read_ram(0x21), 0x41);
+        assert!(app.was_poked(0x20));
+        assert!(app.was_poked(0x21));
+        assert!(!app.was_poked(0x22));
+
+        // A bad address is refused without writing
+        app.handle_key(KeyCode::Char('p'), &mut machines);
+        for c in "200 1".chars() {
+            app.handle_key(KeyCode::Char(c), &mut machines);
+        }
+        app.handle_key(KeyCode::Enter, &mut machines);
+        assert_eq!(app.edit_error.as_deref(), Some("200 isn't in RAM"));
+        app.handle_key(KeyCode::Esc, &mut machines);
+
+        app.handle_key(KeyCode::Char(' '), &mut machines);
+        assert!(!app.was_poked(0x20));
+        assert_eq!(machines.focused().read_ram(0x20), 0x41);
+    }
+
+    /// A TPU with digital pins 0 and 2 and analog pin 1 as inputs
+    fn tpu_with_inputs() -> TPU {
+        let mut digital = [false; DigitalPin::COUNT];
+        digital[0] = true;
+        digital[2] = true;
+        let mut analog = [false; AnalogPin::COUNT];
+        analog[1] = true;
+        TPU::new(
+            0x1,
+            analog,
+            digital,
+            parse_program("NOP\nJMP 0").expect("parse failure"),
+        )
+    }
+
+    #[test]
+    fn test_digital_pin_keys() {
+        assert_eq!(
+            digital_pin_for_key(KeyCode::Char('1')),
+            Some(DigitalPin::Digital0)
+        );
+        assert_eq!(
+            digital_pin_for_key(KeyCode::Char('8')),
+            Some(DigitalPin::Digital7)
+        );
+        assert_eq!(digital_pin_for_key(KeyCode::Char('0')), None);
+        assert_eq!(digital_pin_for_key(KeyCode::Char('9')), None);
+        assert_eq!(digital_pin_for_key(KeyCode::F(1)), None);
+
+        let mut machines = Machines::single(tpu_with_inputs());
+        let mut app = App::new();
+        app.handle_key(KeyCode::Char('1'), &mut machines);
+        app.handle_key(KeyCode::Char('3'), &mut machines);
+        assert_eq!(machines.focused().get_digital_pins(), 0b101);
+        app.handle_key(KeyCode::Char('1'), &mut machines);
+        assert_eq!(machines.focused().get_digital_pins(), 0b100);
+        assert_eq!(app.banner, None);
+
+        // Outputs are left alone, and say why
+        app.handle_key(KeyCode::Char('2'), &mut machines);
+        assert_eq!(machines.focused().get_digital_pins(), 0b100);
+        assert_eq!(
+            app.banner.as_deref(),
+            Some("Digital1 is an output, only inputs can be toggled")
+        );
+    }
+
+    /// Panels laid out as if drawn: the ROM at the top with a label on its third line, the pins below it, the RAM and
+    /// stack on the left
+    fn screen_layout() -> ScreenLayout {
+        ScreenLayout {
+            rom: Rect::new(40, 0, 40, 10),
+            rom_lines: vec![Some(0), Some(1), None, Some(2), Some(3)],
+            ram: Rect::new(0, 0, 40, 10),
+            stack: Rect::new(0, 10, 40, 10),
+            network: Rect::default(),
+            digital_pins: Rect::new(40, 10, 80, 3),
+        }
+    }
+
+    #[test]
+    fn test_mouse_hit_testing() {
+        let layout = screen_layout();
+        assert_eq!(layout.panel_at(45, 5), Some(Panel::Rom));
+        assert_eq!(layout.panel_at(0, 0), Some(Panel::Ram));
+        assert_eq!(layout.panel_at(39, 19), Some(Panel::Stack));
+        assert_eq!(layout.panel_at(45, 11), Some(Panel::DigitalPins));
+        assert_eq!(layout.panel_at(0, 20), None);
+
+        // The border and header come before the first line
+        assert_eq!(layout.rom_address_at(50, 0), None);
+        assert_eq!(layout.rom_address_at(50, 1), None);
+        assert_eq!(layout.rom_address_at(50, 2), Some(0));
+        assert_eq!(layout.rom_address_at(41, 3), Some(1));
+        // A label has no address, the line under it does
+        assert_eq!(layout.rom_address_at(50, 4), None);
+        assert_eq!(layout.rom_address_at(50, 5), Some(2));
+        // Past the last line and on the side borders
+        assert_eq!(layout.rom_address_at(50, 7), None);
+        assert_eq!(layout.rom_address_at(40, 2), None);
+        assert_eq!(layout.rom_address_at(79, 2), None);
+        assert_eq!(layout.rom_address_at(20, 2), None);
+
+        // Eight pins ten cells wide
+        assert_eq!(layout.digital_pin_at(40, 10), Some(DigitalPin::Digital0));
+        assert_eq!(layout.digital_pin_at(59, 12), Some(DigitalPin::Digital1));
+        assert_eq!(layout.digital_pin_at(119, 11), Some(DigitalPin::Digital7));
+        assert_eq!(layout.digital_pin_at(120, 11), None);
+        assert_eq!(layout.digital_pin_at(50, 13), None);
+    }
+
+    #[test]
+    fn test_app_mouse() {
+        let click = |column, row| MouseEvent {
+            kind: MouseEventKind::Down(MouseButton::Left),
+            column,
+            row,
+            modifiers: KeyModifiers::NONE,
+        };
+        let wheel = |kind, column, row| MouseEvent {
+            kind,
+            column,
+            row,
+            modifiers: KeyModifiers::NONE,
+        };
+        let mut machines = Machines::single(tpu_with_inputs());
+        let mut app = App::new();
+        app.layout = screen_layout();
+
+        // Clicking a line toggles its breakpoint, a label or the header does nothing
+        app.handle_mouse(click(50, 3), &mut machines);
+        app.handle_mouse(click(50, 4), &mut machines);
+        app.handle_mouse(click(50, 1), &mut machines);
+        assert_eq!(
+            machines
+                .focused()
+                .breakpoints()
+                .iter()
+                .copied()
+                .collect::<Vec<_>>(),
+            [1]
+        );
+        app.handle_mouse(click(50, 3), &mut machines);
+        assert!(machines.focused().breakpoints().is_empty());
+
+        // Clicking an input pin toggles it, an output says why it can't be
+        app.handle_mouse(click(40, 11), &mut machines);
+        assert_eq!(machines.focused().get_digital_pins(), 0b1);
+        app.handle_mouse(click(50, 11), &mut machines);
+        assert_eq!(machines.focused().get_digital_pins(), 0b1);
+        assert_eq!(
+            app.banner.as_deref(),
+            Some("Digital1 is an output, only inputs can be toggled")
+        );
+
+        // The wheel scrolls the panel under it
+        app.handle_mouse(wheel(MouseEventKind::ScrollDown, 5, 15), &mut machines);
+        assert_eq!(app.stack_scroll, 3);
+        app.handle_mouse(wheel(MouseEventKind::ScrollUp, 5, 15), &mut machines);
+        app.handle_mouse(wheel(MouseEventKind::ScrollUp, 5, 15), &mut machines);
+        assert_eq!(app.stack_scroll, 0);
+        app.handle_mouse(wheel(MouseEventKind::ScrollDown, 50, 5), &mut machines);
+        assert!(!app.rom_view.following());
+        assert_eq!(app.rom_view.cursor(0), 1);
+
+        // Not while the prompt is open
+        app.handle_key(KeyCode::Char('v'), &mut machines);
+        app.handle_mouse(click(50, 2), &mut machines);
+        assert!(machines.focused().breakpoints().is_empty());
+    }
+
+    #[test]
+    fn test_analog_input_prompt() {
+        let edit = |pin, value| Ok(AnalogEdit { pin, value });
+        assert_eq!(AnalogEdit::parse("2 512"), edit(AnalogPin::Analog2, 512));
+        assert_eq!(AnalogEdit::parse("a1=0x10"), edit(AnalogPin::Analog1, 16));
+        assert_eq!(AnalogEdit::parse("Analog3 0"), edit(AnalogPin::Analog3, 0));
+        assert_eq!(AnalogEdit::parse(""), Err(EditError::MissingPin));
+        assert_eq!(
+            AnalogEdit::parse("4 1"),
+            Err(EditError::UnknownPin("4".into()))
+        );
+        assert_eq!(
+            AnalogEdit::parse("D1 1"),
+            Err(EditError::UnknownPin("D1".into()))
+        );
+        assert_eq!(AnalogEdit::parse("1"), Err(EditError::MissingValue));
+
+        let mut machines = Machines::single(tpu_with_inputs());
+        assert_eq!(
+            AnalogEdit::parse("0 5")
+                .unwrap()
+                .apply(machines.focused_mut()),
+            Err(PinError::NotAnInput)
+        );
+
+        let mut app = App::new();
+        let type_in = |app: &mut App, machines: &mut Machines, text: &str| {
+            app.handle_key(KeyCode::Char('i'), machines);
+            for c in text.chars() {
+                app.handle_key(KeyCode::Char(c), machines);
+            }
+            app.handle_key(KeyCode::Enter, machines);
+        };
+        type_in(&mut app, &mut machines, "1 300");
+        assert_eq!(app.prompt, None);
+        assert_eq!(machines.focused().get_analog_pin(AnalogPin::Analog1), 300);
+
+        type_in(&mut app, &mut machines, "0 300");
+        assert_eq!(
+            app.prompt.as_ref().map(|prompt| prompt.kind),
+            Some(PromptKind::AnalogInput)
+        );
+        assert_eq!(
+            app.edit_error.as_deref(),
+            Some("Analog0 is an output, only inputs can be driven")
+        );
+        assert_eq!(machines.focused().get_analog_pin(AnalogPin::Analog0), 0);
+    }
+
+    #[test]
+    fn test_heat() {
+        assert_eq!(heat(0, 8), Some(Heat::Hot));
+        assert_eq!(heat(3, 8), Some(Heat::Hot));
+        assert_eq!(heat(4, 8), Some(Heat::Warm));
+        assert_eq!(heat(7, 8), Some(Heat::Warm));
+        assert_eq!(heat(8, 8), None);
+        assert_eq!(heat(0, 1), Some(Heat::Hot));
+        assert_eq!(heat(0, 0), None);
+
+        // Registers change colour, cells change background, faded ones look like the rest
+        assert_ne!(text_style(Some(Heat::Hot)), text_style(Some(Heat::Warm)));
+        assert_eq!(text_style(None), ratatui::style::Style::default());
+        assert!(cell_style(Some(Heat::Hot)).bg.is_some());
+        assert!(cell_style(Some(Heat::Warm)).bg.is_some());
+        assert_eq!(cell_style(None), ratatui::style::Style::default());
+    }
+
+    #[test]
+    fn test_highlights() {
+        let mut tpu =
+            tpu("LDR X, 7\nPUSH X\nSTM 0x10, X\nAPW 1, X\nDPW 2, 1\nNOP\nNOP\nNOP\nNOP\nJMP 5");
+        let mut highlights = Highlights::new(4);
+        highlights.update(tpu.state());
+        assert_eq!(highlights.register(Register::X), None);
+
+        tpu.step();
+        highlights.update(tpu.state());
+        assert_eq!(highlights.register(Register::X), Some(Heat::Hot));
+        assert_eq!(highlights.register(Register::A), None);
+
+        for _ in 0..4 {
+            tpu.step();
+        }
+        highlights.update(tpu.state());
+        // Everything from a frame of several ticks is stamped at once
+        assert_eq!(highlights.stack(0), Some(Heat::Hot));
+        assert_eq!(highlights.ram(0x10), Some(Heat::Hot));
+        assert_eq!(highlights.ram(0x11), None);
+        assert_eq!(highlights.analog_pin(AnalogPin::Analog1), Some(Heat::Hot));
+        assert_eq!(
+            highlights.digital_pin(DigitalPin::Digital2),
+            Some(Heat::Hot)
+        );
+        assert_eq!(highlights.digital_pin(DigitalPin::Digital1), None);
+
+        // Then fades over the next ticks
+        let cycle = tpu.state().cycle_count;
+        while tpu.state().cycle_count < cycle + 2 {
+            tpu.tick();
+        }
+        highlights.update(tpu.state());
+        assert_eq!(highlights.ram(0x10), Some(Heat::Warm));
+        while tpu.state().cycle_count < cycle + 4 {
+            tpu.tick();
+        }
+        highlights.update(tpu.state());
+        assert_eq!(highlights.ram(0x10), None);
+        assert_eq!(highlights.register(Register::X), None);
+    }
+
+    #[test]
+    fn test_format_packet() {
+        let packet = NetPacket::with_payload(0x2, 0x1, &[0x10, 0xBEEF]).on_port(3);
+        assert_eq!(format_packet(&packet), "0002→0001 :3   0010 BEEF");
+        let packet = NetPacket::with_payload(0xA, 0xFFFF, &[]).on_port(255);
+        assert_eq!(format_packet(&packet), "000A→FFFF :255 (empty)");
+
+        let mut damaged = NetPacket::new(0x2, 0x1, 5);
+        damaged.payload[0] = 6;
+        assert_eq!(
+            format_packet(&damaged),
+            "0002→0001 :0   0006 (bad checksum)"
+        );
+    }
+
+    #[test]
+    fn test_queue_lines() {
+        let packets: Vec<NetPacket> = (0..5).map(|data| NetPacket::new(0x2, 0x1, data)).collect();
+
+        assert_eq!(queue_lines("Incoming", [].iter(), 3), ["Incoming (0)"]);
+        assert_eq!(
+            queue_lines("Outgoing", packets[..2].iter(), 3),
+            [
+                "Outgoing (2)",
+                "  0002→0001 :0   0000",
+                "  0002→0001 :0   0001",
+            ]
+        );
+        // Oldest first, and the rest counted
+        assert_eq!(
+            queue_lines("Incoming", packets.iter(), 3),
+            [
+                "Incoming (5)",
+                "  0002→0001 :0   0000",
+                "  0002→0001 :0   0001",
+                "  0002→0001 :0   0002",
+                "  … 2 more",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_packet_edit_parse() {
+        let edit = |sender, port, words: &[u16]| {
+            Ok(PacketEdit {
+                sender,
+                port,
+                words: words.to_vec(),
+            })
+        };
+        assert_eq!(PacketEdit::parse("0x2 5"), edit(0x2, 0, &[5]));
+        assert_eq!(PacketEdit::parse("2:3 5 6 0x7"), edit(2, 3, &[5, 6, 7]));
+        assert_eq!(PacketEdit::parse("0x2:0xFF=1"), edit(2, 255, &[1]));
+
+        assert_eq!(PacketEdit::parse(" "), Err(EditError::MissingSender));
+        assert_eq!(PacketEdit::parse("0x2"), Err(EditError::MissingValue));
+        assert_eq!(
+            PacketEdit::parse("0x2:256 1"),
+            Err(EditError::InvalidSender("0x2:256".into()))
+        );
+        assert_eq!(
+            PacketEdit::parse("x 1"),
+            Err(EditError::InvalidSender("x".into()))
+        );
+        assert_eq!(
+            PacketEdit::parse("2 1 2 3 4 5"),
+            Err(EditError::TooManyWords(5))
+        );
+        assert_eq!(
+            PacketEdit::parse("2 1 q"),
+            Err(EditError::InvalidValue("q".into()))
+        );
+
+        let packet = PacketEdit::parse("2:3 5 6").unwrap().packet(0x1);
+        assert_eq!(packet, NetPacket::with_payload(2, 1, &[5, 6]).on_port(3));
+        assert!(packet.verify());
+    }
+
+    #[test]
+    fn test_app_packet_prompt() {
+        let mut machines = Machines::single(tpu("WRX\nHLT"));
+        let mut app = App::new();
+        let deliver = |app: &mut App, machines: &mut Machines, text: &str| {
+            app.handle_key(KeyCode::Char('n'), machines);
+            for c in text.chars() {
+                app.handle_key(KeyCode::Char(c), machines);
+            }
+            app.handle_key(KeyCode::Enter, machines);
+        };
+
+        deliver(&mut app, &mut machines, "0x2 42");
+        assert_eq!(app.prompt, None);
+        assert_eq!(machines.focused().state().incoming_packets.len(), 1);
+        // The first tick fetches the WRX, the second takes the packet
+        app.handle_key(KeyCode::Char(' '), &mut machines);
+        app.handle_key(KeyCode::Char(' '), &mut machines);
+        assert_eq!(machines.focused().read_register(Register::Y), 42);
+
+        // The buffer fills up
+        for _ in 0..TPU::NET_BUFFER_SIZE {
+            deliver(&mut app, &mut machines, "3 1");
+        }
+        assert_eq!(app.edit_error, None);
+        deliver(&mut app, &mut machines, "3 1");
+        assert_eq!(
+            app.edit_error.as_deref(),
+            Some("the incoming buffer is full, the packet was dropped")
+        );
+        assert_eq!(
+            machines.focused().state().incoming_packets.len(),
+            TPU::NET_BUFFER_SIZE
+        );
+    }
+
+    #[test]
+    fn test_log_buffer() {
+        let log = LogBuffer::new(3);
+        assert!(log.recent(10, Level::TRACE).is_empty());
+        for (level, message) in [
+            (Level::ERROR, "one"),
+            (Level::INFO, "two"),
+            (Level::DEBUG, "three"),
+            (Level::WARN, "four"),
+        ] {
+            log.push_with(level, |text| text.push_str(message));
+        }
+
+        // The oldest is dropped once it's full
+        let messages = |entries: Vec<LogEntry>| -> Vec<String> {
+            entries.into_iter().map(|entry| entry.message).collect()
+        };
+        assert_eq!(
+            messages(log.recent(10, Level::TRACE)),
+            ["two", "three", "four"]
+        );
+        assert_eq!(messages(log.recent(2, Level::TRACE)), ["three", "four"]);
+        assert_eq!(messages(log.recent(10, Level::INFO)), ["two", "four"]);
+        assert_eq!(messages(log.recent(10, Level::ERROR)), Vec::<String>::new());
+
+        assert!(log.accepts(Level::INFO));
+        assert!(!log.accepts(Level::DEBUG));
+        log.set_level(Level::ERROR);
+        assert!(!log.accepts(Level::WARN));
+
+        assert_eq!(next_level(Level::ERROR), Level::WARN);
+        assert_eq!(next_level(Level::DEBUG), Level::TRACE);
+        assert_eq!(next_level(Level::TRACE), Level::ERROR);
+    }
+
+    #[test]
+    fn test_log_layer() {
+        let log = LogBuffer::new(64);
+        let subscriber = tracing_subscriber::registry().with(LogLayer::new(log.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("started");
+            tracing::trace!("not caught");
+            tracing::warn!(pin = 3, "bouncing");
+
+            // Caught as the level changes, the TPU's own events included
+            log.set_level(Level::TRACE);
+            let mut tpu = tpu("LDR X, 0\nDIV A, X");
+            while !tpu.halted() {
+                tpu.tick();
+            }
+        });
+
+        let entries = log.recent(3, Level::TRACE);
+        assert_eq!(
+            entries.last(),
+            Some(&LogEntry {
+                level: Level::ERROR,
+                message: "TPU Halted: Div0".to_string()
+            })
+        );
+        let all = log.recent(64, Level::INFO);
+        assert_eq!(all[0].message, "started");
+        assert_eq!(all[1].message, "bouncing pin=3");
+        assert!(
+            log.recent(8, Level::TRACE)
+                .iter()
+                .any(|entry| entry.message == "TICK")
+        );
+    }
+
+    #[test]
+    fn test_search() {
+        let (rom, symbols) = parse_program_with_symbols(
+            "LDR A, 0x5555\nTOP: STM 0x10, A\nldr_x: LDR X, 16\nJMP TOP",
+        )
+        .expect("parse failure");
+        let symbols = SymbolResolver::new(&symbols, rom.len());
+        let none = SymbolResolver::default();
+
+        // Text matches anywhere in the line as it's shown, ignoring case
+        assert_eq!(search_rom(&rom, &symbols, "ldr"), [0, 2]);
+        assert_eq!(search_rom(&rom, &symbols, ", a"), [1]);
+        assert_eq!(search_rom(&rom, &symbols, "top"), [3]);
+        assert_eq!(search_rom(&rom, &none, "top"), Vec::<usize>::new());
+        assert_eq!(search_rom(&rom, &symbols, ""), Vec::<usize>::new());
+
+        // A number matches whole operands, however it's written
+        assert_eq!(search_rom_value(&rom, &symbols, 0x10), [1, 2]);
+        assert_eq!(search_rom_value(&rom, &symbols, 0x5555), [0]);
+        assert_eq!(search_rom_value(&rom, &symbols, 0x55), Vec::<usize>::new());
+        // Not where a label is shown instead
+        assert_eq!(search_rom_value(&rom, &symbols, 1), Vec::<usize>::new());
+        assert_eq!(search_rom_value(&rom, &none, 1), [3]);
+
+        assert_eq!(search_ram(&[0, 0x5555, 7, 0x5555], 0x5555), [1, 3]);
+        assert_eq!(search_ram(&[0, 1], 2), Vec::<usize>::new());
+
+        // Text only searches the ROM, a number both
+        let ram = [0, 16, 0, 16];
+        let text = Search::new(" LDR ", &rom, &symbols, &ram);
+        assert_eq!(text.query, "LDR");
+        assert!(text.ram.is_empty());
+        let mut number = Search::new("0x10", &rom, &symbols, &ram);
+        assert_eq!(number.rom, [1, 2]);
+        assert_eq!(number.ram, [1, 3]);
+        assert!(Search::new("nowhere", &rom, &symbols, &ram).is_empty());
+
+        // Going on from one match to the next wraps round
+        assert_eq!(number.next_rom(0), Some(1));
+        assert_eq!(number.next_rom(1), Some(2));
+        assert_eq!(number.next_rom(2), Some(1));
+        assert_eq!(number.next_ram(), Some(1));
+        assert_eq!(number.next_ram(), Some(3));
+        assert_eq!(number.next_ram(), Some(1));
+    }
+
+    #[test]
+    fn test_app_search() {
+        let mut machines = Machines::single(tpu("LDR A, 7\nSTM 0x40, A\nLDR X, 7\nHLT"));
+        for _ in 0..2 {
+            machines.step();
+        }
+        let mut app = App::new();
+        let search = |app: &mut App, machines: &mut Machines, query: &str| {
+            app.handle_key(KeyCode::Char('/'), machines);
+            assert_eq!(app.prompt.as_ref().unwrap().kind, PromptKind::Search);
+            for c in query.chars() {
+                app.handle_key(KeyCode::Char(c), machines);
+            }
+            app.handle_key(KeyCode::Enter, machines);
+            assert_eq!(app.prompt, None);
+        };
+
+        // Goes to the first match in each, and N on through the ROM's
+        search(&mut app, &mut machines, "7");
+        let found = app.search.clone().unwrap();
+        assert_eq!(found.rom, [0, 2]);
+        assert_eq!(found.ram, [0x40]);
+        assert_eq!(app.rom_view.cursor(2), 0);
+        assert_eq!(app.ram_view.window(256, 2), 0x40..0x60);
+        assert_eq!(
+            app.banner.as_deref(),
+            Some("2 in ROM and 1 in RAM match 7, N for the next, Esc to clear")
+        );
+        app.handle_key(KeyCode::Char('n'), &mut machines);
+        assert_eq!(app.rom_view.cursor(2), 2);
+        app.handle_key(KeyCode::Char('n'), &mut machines);
+        assert_eq!(app.rom_view.cursor(2), 0);
+
+        // Without a search N sends a packet again
+        app.handle_key(KeyCode::Esc, &mut machines);
+        assert_eq!(app.search, None);
+        app.handle_key(KeyCode::Char('n'), &mut machines);
+        assert_eq!(app.prompt.as_ref().unwrap().kind, PromptKind::Packet);
+        app.handle_key(KeyCode::Esc, &mut machines);
+
+        search(&mut app, &mut machines, "nop");
+        assert_eq!(app.search, None);
+        assert_eq!(app.banner.as_deref(), Some("nothing matches nop"));
+    }
+
+    #[test]
+    fn test_halt_banner() {
+        let program = parse_program("LDR A, 1\nLDR X, 0\nDIV A, X\nHLT").expect("parse failure");
+        let mut tpu = create_basic_tpu_config(program);
+        while !tpu.halted() {
+            tpu.tick();
+        }
+        let state = tpu.state();
+        let reason = state.halt_reason.expect("halted without a reason");
+        let pc = state.program_counter;
+        let none = SymbolResolver::default();
+        assert_eq!(
+            halt_banner(reason, pc, state.rom.get(pc).map(|i| i.as_ref()), &none),
+            "HALTED: Div0 at 0x0002: DIV A, X"
+        );
+
+        assert_eq!(
+            halt_banner(HaltReason::EndOfProgram, 0, None, &none),
+            "HALTED: EndOfProgram at 0x0000"
+        );
+
+        // Named as in the ROM panel
+        let (program, symbols) =
+            parse_program_with_symbols("LDR A, 0\nSTOP: BEZ STOP, A").expect("parse failure");
+        let symbols = SymbolResolver::new(&symbols, program.len());
+        assert_eq!(
+            halt_banner(HaltReason::InvalidPC, 1, Some(&program[1]), &symbols),
+            "HALTED: InvalidPC at 0x0001 (STOP): BEZ STOP, A"
+        );
+    }
+
+    /// A directory of its own under the temp directory, so tests running at the same time don't share files
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("tls_tui_test_{name}_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_snapshot_save_load() {
+        let dir = temp_dir("snapshot");
+        let program = parse_program("LDR A, 7\nLDR X, 9\nHLT").expect("parse failure");
+        let mut tpu = create_basic_tpu_config(program.clone());
+        tpu.tick();
+        let path = snapshot::save(&tpu, &dir).unwrap();
+        assert!(path.starts_with(&dir));
+        assert!(
+            path.extension()
+                .is_some_and(|extension| extension == "json")
+        );
+
+        let snapshot = snapshot::load(&path, &program).unwrap();
+        assert_eq!(snapshot.state.registers, tpu.state().registers);
+
+        // Another program is refused, but the snapshot comes back with the error to replace it anyway
+        let other = parse_program("HLT").expect("parse failure");
+        match snapshot::load(&path, &other) {
+            Err(LoadError::RomMismatch(snapshot)) => assert_eq!(snapshot.state.rom, program),
+            result => panic!("expected a ROM mismatch, got {result:?}"),
+        }
+
+        assert!(matches!(
+            snapshot::load(&dir.join("missing.json"), &program),
+            Err(LoadError::Io(_))
+        ));
+        let garbage = dir.join("garbage.json");
+        std::fs::write(&garbage, "not json").unwrap();
+        assert!(matches!(
+            snapshot::load(&garbage, &program),
+            Err(LoadError::Snapshot(tls::Error::Snapshot(
+                SnapshotError::Json(_)
+            )))
+        ));
+        let future = dir.join("future.json");
+        std::fs::write(&future, r#"{"version": 999}"#).unwrap();
+        let error = snapshot::load(&future, &program).unwrap_err();
+        assert!(matches!(
+            error,
+            LoadError::Snapshot(tls::Error::Snapshot(SnapshotError::Version(999)))
+        ));
+        assert!(error.to_string().contains("version 999"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_app_open_snapshot() {
+        let dir = temp_dir("open_snapshot");
+        let program = parse_program("LDR A, 7\nLDR X, 9\nHLT").expect("parse failure");
+        let mut machines = Machines::single(create_basic_tpu_config(program.clone()));
+        let mut app = App::new();
+        let type_path = |app: &mut App, machines: &mut Machines, path: &std::path::Path| {
+            app.handle_key(KeyCode::Char('o'), machines);
+            for c in path.to_str().unwrap().chars() {
+                app.handle_key(KeyCode::Char(c), machines);
+            }
+            app.handle_key(KeyCode::Enter, machines);
+        };
+
+        machines.focused_mut().step();
+        let path = snapshot::save(machines.focused(), &dir).unwrap();
+        machines.focused_mut().step();
+        assert_eq!(machines.focused().read_register(Register::X), 9);
+
+        type_path(&mut app, &mut machines, &path);
+        assert_eq!(app.prompt, None);
+        assert_eq!(machines.focused().read_register(Register::A), 7);
+        assert_eq!(machines.focused().read_register(Register::X), 0);
+        assert!(
+            app.banner
+                .as_ref()
+                .is_some_and(|banner| banner.starts_with("restored"))
+        );
+
+        // A bad path stays in the prompt with the reason
+        type_path(&mut app, &mut machines, &dir.join("missing.json"));
+        assert!(app.prompt.is_some());
+        assert!(app.edit_error.is_some());
+        app.handle_key(KeyCode::Esc, &mut machines);
+
+        // Another program's snapshot only replaces this one on Y
+        machines
+            .focused_mut()
+            .load_program(parse_program("HLT").expect("parse failure"));
+        type_path(&mut app, &mut machines, &path);
+        assert!(app.replace.is_some());
+        app.handle_key(KeyCode::Char('s'), &mut machines);
+        assert!(app.replace.is_none());
+        assert_eq!(machines.focused().read_rom().len(), 1);
+        assert_eq!(machines.focused().read_register(Register::A), 0);
+
+        type_path(&mut app, &mut machines, &path);
+        app.handle_key(KeyCode::Char('y'), &mut machines);
+        assert_eq!(machines.focused().read_rom(), &program);
+        assert_eq!(machines.focused().read_register(Register::A), 7);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn network_machines() -> Machines {
+        let mut network = Network::new();
+        let mut sender = tpu("LDR A, 0x2\nXMIT A, 42\nHLT");
+        sender.set_network_address(0x1);
+        let mut receiver = tpu("WRX\nNOP\nNOP\nHLT");
+        receiver.set_network_address(0x2);
+        network.add_tpu(sender);
+        network.add_tpu(receiver);
+        Machines::network(network)
+    }
+
+    #[test]
+    fn test_machines_focus() {
+        let mut machines = network_machines();
+        assert_eq!(machines.len(), 2);
+        assert_eq!(machines.focused().state().network_address, 0x1);
+        machines.cycle_focus(1);
+        assert_eq!(machines.focus(), 1);
+        machines.cycle_focus(1);
+        assert_eq!(machines.focus(), 0);
+        machines.cycle_focus(-1);
+        assert_eq!(machines.focus(), 1);
+        assert!(!machines.focus_on(2));
+        assert_eq!(machines.focus(), 1);
+
+        // Stepping finishes the focused TPU's instruction, the packet it waits for has to be sent first
+        machines.step();
+        assert_eq!(machines.focused().state().program_counter, 1);
+        assert_eq!(machines.focused().read_register(Register::Y), 42);
+        assert!(machines.tpu(0).halted());
+        assert_eq!(summary(machines.tpu(0)), "0001 PC 0002 HALTED in 0 out 0");
+
+        let mut single = Machines::single(tpu("HLT"));
+        single.cycle_focus(1);
+        assert_eq!(single.focus(), 0);
+    }
+
+    #[test]
+    fn test_app_network_focus() {
+        let mut machines = network_machines();
+        let mut app = App::new();
+
+        app.handle_key(KeyCode::Tab, &mut machines);
+        assert_eq!(machines.focus(), 1);
+        app.handle_key(KeyCode::BackTab, &mut machines);
+        assert_eq!(machines.focus(), 0);
+        app.handle_key(KeyCode::F(9), &mut machines);
+        assert_eq!(machines.focus(), 0);
+
+        // Keys act on the focused TPU
+        app.handle_key(KeyCode::F(2), &mut machines);
+        assert_eq!(machines.focus(), 1);
+        app.handle_key(KeyCode::Down, &mut machines);
+        app.handle_key(KeyCode::Down, &mut machines);
+        app.handle_key(KeyCode::Char('b'), &mut machines);
+        assert!(machines.tpu(1).breakpoints().contains(&2));
+        assert!(machines.tpu(0).breakpoints().is_empty());
+
+        // A breakpoint brings its TPU into view
+        app.handle_key(KeyCode::F(1), &mut machines);
+        assert!(app.rom_view.following());
+        app.handle_key(KeyCode::Char('r'), &mut machines);
+        app.clock.set_speed(RunSpeed::Unlimited);
+        app.run_frame(&mut machines, Duration::from_millis(50));
+        assert!(!app.running);
+        assert_eq!(machines.focus(), 1);
+        assert_eq!(
+            app.banner.as_deref(),
+            Some("breakpoint hit at 0x0002 on TPU 2")
+        );
+
+        app.handle_key(KeyCode::Char('r'), &mut machines);
+        app.run_frame(&mut machines, Duration::from_millis(50));
+        assert!(!app.running);
+        assert!(machines.all_halted());
+        app.handle_key(KeyCode::Char('r'), &mut machines);
+        assert!(!app.running);
+    }
+
+    #[test]
+    fn test_watches() {
+        let mut tpu = tpu("LDR A, 1\nLDR A, 2\nNOP\nNOP\nHLT");
+        let mut watches = Watches::default();
+        assert!(watches.toggle(Expr::parse("A").unwrap()));
+        assert!(watches.toggle(Expr::parse("ram[A * 0x100]").unwrap()));
+        assert!(watches.toggle(Expr::parse("-1").unwrap()));
+
+        watches.update(&tpu);
+        let lines: Vec<_> = watches.iter().map(format_watch).collect();
+        assert_eq!(
+            lines,
+            ["A = 0x0000 (0)", "ram[A * 0x100] = 0x0000 (0)", "-1 = -1"]
+        );
+        // The first value seen isn't a change
+        assert!(watches.iter().all(|watch| watches.heat(watch, 4).is_none()));
+
+        tpu.step();
+        watches.update(&tpu);
+        let a = watches.iter().next().unwrap();
+        assert_eq!(a.value, Some(Ok(1)));
+        assert_eq!(watches.heat(a, 4), Some(Heat::Hot));
+        let ram = watches.iter().nth(1).unwrap();
+        assert_eq!(ram.value, Some(Err(EvalError::AddressOutOfRange(0x100))));
+        assert_eq!(format_watch(ram), "ram[A * 0x100]: 0x100 isn't in RAM");
+
+        // Fades like the other highlights, and is forgotten on a reset
+        tpu.step();
+        watches.update(&tpu);
+        let a = watches.iter().next().unwrap();
+        assert_eq!(a.value, Some(Ok(2)));
+        tpu.tick();
+        tpu.tick();
+        watches.update(&tpu);
+        assert_eq!(
+            watches.heat(watches.iter().next().unwrap(), 4),
+            Some(Heat::Warm)
+        );
+        tpu.tick();
+        tpu.tick();
+        watches.update(&tpu);
+        assert_eq!(watches.heat(watches.iter().next().unwrap(), 4), None);
+        tpu.load_program(parse_program("HLT").expect("parse failure"));
+        watches.update(&tpu);
+        assert!(watches.iter().all(|watch| watches.heat(watch, 4).is_none()));
+
+        // Watching it again, however it's typed, stops watching it
+        assert!(!watches.toggle(Expr::parse(" a").unwrap()));
+        assert!(!watches.toggle(Expr::parse("RAM[a*256]").unwrap()));
+        assert!(!watches.toggle(Expr::parse("-1").unwrap()));
+        assert!(watches.is_empty());
+    }
+
+    #[test]
+    fn test_app_conditional_breakpoint() {
+        let mut machines = Machines::single(tpu("LDR A, 5\nDEC A\nBNZ 1, A\nHLT"));
+        let mut app = App::new();
+        let type_in = |app: &mut App, machines: &mut Machines, text: &str| {
+            app.handle_key(KeyCode::Char('B'), machines);
+            for c in text.chars() {
+                app.handle_key(KeyCode::Char(c), machines);
+            }
+            app.handle_key(KeyCode::Enter, machines);
+        };
+
+        app.handle_key(KeyCode::Down, &mut machines);
+        app.handle_key(KeyCode::Down, &mut machines);
+        type_in(&mut app, &mut machines, "A ==");
+        assert_eq!(app.prompt.as_ref().unwrap().kind, PromptKind::Condition);
+        assert_eq!(
+            app.edit_error.as_deref(),
+            Some("the expression ends too soon")
+        );
+        assert!(machines.focused().breakpoints().is_empty());
+        app.handle_key(KeyCode::Esc, &mut machines);
+
+        // Only the pass with A at 2 stops
+        type_in(&mut app, &mut machines, "A == 2");
+        assert_eq!(app.prompt, None);
+        assert_eq!(
+            machines
+                .focused()
+                .breakpoint_condition(2)
+                .map(ToString::to_string),
+            Some("A == 2".to_string())
+        );
+        app.handle_key(KeyCode::Char('r'), &mut machines);
+        app.clock.set_speed(RunSpeed::Unlimited);
+        app.run_frame(&mut machines, FRAME);
+        assert!(!app.running);
+        assert_eq!(machines.focused().read_register(Register::A), 2);
+        assert_eq!(
+            app.banner.as_deref(),
+            Some("breakpoint hit at 0x0002 with A == 2")
+        );
+
+        // Nothing typed makes it stop every time
+        type_in(&mut app, &mut machines, "");
+        assert!(machines.focused().breakpoints().contains(&2));
+        assert_eq!(machines.focused().breakpoint_condition(2), None);
+        app.handle_key(KeyCode::Char('r'), &mut machines);
+        app.run_frame(&mut machines, FRAME);
+        assert_eq!(machines.focused().read_register(Register::A), 1);
+    }
+
+    #[test]
+    fn test_app_run_to_cursor() {
+        let mut machines = Machines::single(tpu("LDR A, 3\nDEC A\nBNZ 1, A\nHLT"));
+        let mut app = App::new();
+        app.handle_key(KeyCode::Down, &mut machines);
+        app.handle_key(KeyCode::Down, &mut machines);
+        app.handle_key(KeyCode::Char('c'), &mut machines);
+        assert_eq!(machines.focused().state().program_counter, 2);
+        assert_eq!(machines.focused().read_register(Register::A), 2);
+        assert!(machines.focused().breakpoints().is_empty());
+        assert_eq!(app.banner.as_deref(), Some("ran to 0x0002"));
+        assert!(app.rom_view.following());
+
+        // The cursor follows the PC, so it's there already and goes round once more
+        app.handle_key(KeyCode::Char('c'), &mut machines);
+        assert_eq!(machines.focused().read_register(Register::A), 1);
+
+        // Never reached, the program halts first
+        app.handle_key(KeyCode::Up, &mut machines);
+        app.handle_key(KeyCode::Up, &mut machines);
+        app.handle_key(KeyCode::Char('c'), &mut machines);
+        assert!(machines.focused().halted());
+        assert_eq!(app.banner, None);
+
+        let mut machines = Machines::single(tpu("JMP 0\nHLT"));
+        app.handle_key(KeyCode::Down, &mut machines);
+        app.handle_key(KeyCode::Char('c'), &mut machines);
+        assert_eq!(
+            app.banner.as_deref(),
+            Some("didn't reach 0x0001 in 1,000,000 ticks")
+        );
+        assert_eq!(machines.focused().state().cycle_count, RUN_TO_CURSOR_TICKS);
+
+        // On a network the others keep time
+        let mut machines = network_machines();
+        app.handle_key(KeyCode::Home, &mut machines);
+        app.handle_key(KeyCode::Down, &mut machines);
+        app.handle_key(KeyCode::Char('c'), &mut machines);
+        assert_eq!(app.banner.as_deref(), Some("ran to 0x0001"));
+        assert_eq!(
+            machines.tpu(1).state().cycle_count,
+            machines.focused().state().cycle_count
+        );
+    }
+
+    #[test]
+    fn test_app_watch_prompt() {
+        let mut machines = network_machines();
+        let mut app = App::new();
+        let type_in = |app: &mut App, machines: &mut Machines, text: &str| {
+            app.handle_key(KeyCode::Char('v'), machines);
+            for c in text.chars() {
+                app.handle_key(KeyCode::Char(c), machines);
+            }
+            app.handle_key(KeyCode::Enter, machines);
+        };
+
+        type_in(&mut app, &mut machines, "A +");
+        assert_eq!(app.prompt.as_ref().unwrap().kind, PromptKind::Watch);
+        assert_eq!(
+            app.edit_error.as_deref(),
+            Some("the expression ends too soon")
+        );
+        app.handle_key(KeyCode::Esc, &mut machines);
+
+        type_in(&mut app, &mut machines, "A");
+        assert_eq!(app.prompt, None);
+        app.watches.update(machines.focused());
+        app.handle_key(KeyCode::Char('s'), &mut machines);
+        app.watches.update(machines.focused());
+        let watch = app.watches.iter().next().unwrap();
+        assert_eq!(watch.value, Some(Ok(2)));
+        assert!(app.watches.heat(watch, 8).is_some());
+
+        // Another TPU's values aren't changes
+        app.handle_key(KeyCode::Tab, &mut machines);
+        app.watches.update(machines.focused());
+        let watch = app.watches.iter().next().unwrap();
+        assert_eq!(watch.value, Some(Ok(0)));
+        assert!(app.watches.heat(watch, 8).is_none());
+
+        type_in(&mut app, &mut machines, "a");
+        assert!(app.watches.is_empty());
+    }
+
+    #[test]
+    fn test_app_step_back() {
+        let mut tpu = tpu("LDR A, 1\nADD A, A\nADD A, A\nHLT");
+        tpu.enable_history(10);
+        let mut machines = Machines::single(tpu);
+        let mut app = App::new();
+
+        app.handle_key(KeyCode::Left, &mut machines);
+        assert_eq!(
+            app.banner.as_deref(),
+            Some("no history to step back through")
+        );
+        for _ in 0..3 {
+            app.handle_key(KeyCode::Char('s'), &mut machines);
+        }
+        assert_eq!(machines.focused().read_register(Register::A), 4);
+        assert_eq!(machines.focused().history_len(), Some(3));
+
+        app.handle_key(KeyCode::Left, &mut machines);
+        assert_eq!(app.banner, None);
+        assert_eq!(machines.focused().state().program_counter, 2);
+        assert_eq!(machines.focused().read_register(Register::A), 2);
+        assert_eq!(machines.focused().history_len(), Some(2));
+
+        // Back onto a breakpoint, running carries on from it
+        app.handle_key(KeyCode::Char('b'), &mut machines);
+        app.handle_key(KeyCode::Left, &mut machines);
+        app.handle_key(KeyCode::Char('b'), &mut machines);
+        assert_eq!(machines.focused().breakpoints().len(), 2);
+        app.handle_key(KeyCode::Char('r'), &mut machines);
+        // Not while running
+        app.handle_key(KeyCode::Left, &mut machines);
+        assert_eq!(machines.focused().state().program_counter, 1);
+        app.clock.set_speed(RunSpeed::Unlimited);
+        app.run_frame(&mut machines, Duration::from_millis(50));
+        assert_eq!(app.banner.as_deref(), Some("breakpoint hit at 0x0002"));
+
+        machines.focused_mut().disable_history();
+        app.handle_key(KeyCode::Left, &mut machines);
+        assert_eq!(
+            app.banner.as_deref(),
+            Some("history is off, start with --history to step back")
+        );
+        assert_eq!(machines.focused().state().program_counter, 2);
+
+        let mut machines = network_machines();
+        machines.focused_mut().enable_history(10);
+        app.handle_key(KeyCode::Char('s'), &mut machines);
+        app.handle_key(KeyCode::Left, &mut machines);
+        assert_eq!(
+            app.banner.as_deref(),
+            Some("can't step back with more than one TPU")
+        );
+    }
+
+    #[test]
+    fn test_trace_rows() {
+        let program = parse_program("STM 0x10, A\nBEQ 7, X, 0x20\nHLT").expect("parse failure");
+        assert_eq!(
+            header(),
+            "cycle,pc,mnemonic,operands,A,X,Y,R0,R1,R2,R3,R4,R5,R6,ram_address,ram_value,pins"
+        );
+
+        let mut event = TraceEvent {
+            cycle: 12,
+            pc: 3,
+            instruction: program[0].clone(),
+            registers: [5, 0, 0, 0, 0, 0, 0, 0, 0, 0xFFFF],
+            ram_write: Some((0x10, 5)),
+            digital_pins: 0b101,
+        };
+        assert_eq!(
+            format_row(&event),
+            "12,0x0003,STM,0010 A,0x0005,0x0000,0x0000,0x0000,0x0000,0x0000,0x0000,0x0000,0x0000,0xFFFF,0x0010,0x0005,0b00000101"
+        );
+
+        // Nothing written to RAM leaves its columns empty
+        event.instruction = program[1].clone();
+        event.ram_write = None;
+        assert_eq!(
+            format_row(&event),
+            "12,0x0003,BEQ,0007 X 0020,0x0005,0x0000,0x0000,0x0000,0x0000,0x0000,0x0000,0x0000,0x0000,0xFFFF,,,0b00000101"
+        );
+        event.instruction = program[2].clone();
+        event.registers = [0; Register::COUNT];
+        event.digital_pins = 0;
+        assert_eq!(
+            format_row(&event),
+            "12,0x0003,HLT,,0x0000,0x0000,0x0000,0x0000,0x0000,0x0000,0x0000,0x0000,0x0000,0x0000,,,0b00000000"
+        );
+    }
+
+    #[test]
+    fn test_trace_recorder() {
+        let program = parse_program("LDR A, 2\nSTM 0x01, A\nHLT").expect("parse failure");
+        let mut tpu = create_basic_tpu_config(program);
+        let dir = temp_dir("trace_recorder");
+        let path = dir.join("trace.csv");
+        let observer = TraceObserver(Rc::new(std::cell::RefCell::new(
+            TraceRecorder::new(std::fs::File::create(&path).unwrap()).unwrap(),
+        )));
+        let id = tpu.add_observer(observer.clone());
+        while !tpu.halted() {
+            tpu.tick();
+        }
+        assert!(tpu.remove_observer(id));
+
+        assert_eq!(observer.0.borrow_mut().finish().unwrap(), 3);
+        let csv = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], header());
+        assert!(lines[1].starts_with("1,0x0000,LDR,A 0002,0x0002,"));
+        assert!(lines[2].ends_with(",0x0001,0x0002,0b00000000"));
+        assert!(lines[3].contains(",0x0002,HLT,,"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_trace_file() {
+        let dir = temp_dir("trace");
+        let program = parse_program("LDR A, 2\nINC A\nHLT").expect("parse failure");
+        let mut machines = Machines::single(create_basic_tpu_config(program.clone()));
+        let mut app = App::new();
+        // A tiny buffer still writes every row
+        app.trace.buffer = 8;
+
+        let path = app
+            .trace
+            .start(machines.focused_mut(), 0, &dir)
+            .expect("couldn't start the trace");
+        assert_eq!(app.trace.tpu(), Some(0));
+        assert_eq!(app.trace.path(), Some(path.as_path()));
+        app.handle_key(KeyCode::Char('s'), &mut machines);
+        assert_eq!(app.trace.tpu(), Some(0));
+
+        // The trace ends once the TPU halts
+        app.handle_key(KeyCode::Char('r'), &mut machines);
+        app.run_frame(&mut machines, Duration::from_secs(10));
+        assert!(machines.focused().halted());
+        assert_eq!(app.trace.tpu(), None);
+        assert_eq!(
+            app.banner,
+            Some(format!(
+                "trace of 3 instructions written to {}",
+                path.display()
+            ))
+        );
+        let csv = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(csv.lines().count(), 4);
+        assert!(csv.lines().nth(2).unwrap().contains(",INC,A,0x0003,"));
+
+        // Stopping by hand
+        machines.focused_mut().load_program(program);
+        app.trace.start(machines.focused_mut(), 0, &dir).unwrap();
+        app.handle_key(KeyCode::Char('t'), &mut machines);
+        assert_eq!(app.trace.tpu(), None);
+        assert!(
+            app.banner
+                .as_deref()
+                .is_some_and(|banner| banner.starts_with("trace of 0 instructions written to"))
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_downsample() {
+        let samples: VecDeque<u16> = (1..=8).collect();
+        // Fewer samples than columns are left as they are
+        assert_eq!(downsample(&samples, 10), [1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(downsample(&samples, 8), [1, 2, 3, 4, 5, 6, 7, 8]);
+        // Otherwise each column is the highest of the samples it covers
+        assert_eq!(downsample(&samples, 4), [2, 4, 6, 8]);
+        assert_eq!(downsample(&samples, 3), [2, 5, 8]);
+        assert_eq!(downsample(&samples, 1), [8]);
+        assert!(downsample(&samples, 0).is_empty());
+
+        // A spike is kept however narrow the sparkline
+        let mut spike = VecDeque::from(vec![0u16; 100]);
+        spike[41] = 1000;
+        let columns = downsample(&spike, 7);
+        assert_eq!(columns.len(), 7);
+        assert_eq!(columns.iter().filter(|&&value| value == 1000).count(), 1);
+        assert!(downsample(&VecDeque::new(), 5).is_empty());
+    }
+
+    #[test]
+    fn test_analog_history() {
+        let program =
+            parse_program("APW 0, 10\nAPW 0, 20\nAPW 1, 7\nJMP 0").expect("parse failure");
+        let mut tpu = create_basic_tpu_config(program.clone());
+        let mut history = AnalogHistory::default();
+        assert_eq!(history.sampling, Sampling::Frame);
+
+        history.sample(tpu.state());
+        // Nothing's moved on, so there's nothing new to sample
+        history.sample(tpu.state());
+        assert_eq!(history.samples(AnalogPin::Analog0).len(), 1);
+        for _ in 0..3 {
+            tpu.step();
+            history.sample(tpu.state());
+        }
+        assert_eq!(history.samples(AnalogPin::Analog0), &[0, 10, 20, 20]);
+        assert_eq!(history.samples(AnalogPin::Analog1), &[0, 0, 0, 7]);
+
+        // Capped, the oldest samples going first
+        for _ in 0..AnalogHistory::CAPACITY {
+            tpu.step();
+            history.sample(tpu.state());
+        }
+        assert_eq!(
+            history.samples(AnalogPin::Analog0).len(),
+            AnalogHistory::CAPACITY
+        );
+        assert_ne!(history.samples(AnalogPin::Analog0)[0], 0);
+
+        // Loading a program puts the cycle count back, so the samples from before go
+        tpu.load_program(program);
+        history.sample(tpu.state());
+        assert_eq!(history.samples(AnalogPin::Analog0), &[0]);
+    }
+
+    #[test]
+    fn test_app_samples_every_tick() {
+        let program = parse_program("APW 0, 1\nAPW 0, 2\nAPW 0, 3\nHLT").expect("parse failure");
+        let mut machines = Machines::single(create_basic_tpu_config(program));
+        let mut app = App::new();
+        app.analog_history.sampling = Sampling::Tick;
+        app.clock.set_speed(RunSpeed::KHz1);
+        app.handle_key(KeyCode::Char('r'), &mut machines);
+        app.run_frame(&mut machines, Duration::from_secs(1));
+        assert!(!app.running);
+
+        // A sample for each tick up to the halt, each APW taking a few
+        let samples = app.analog_history.samples(AnalogPin::Analog0);
+        assert_eq!(samples.len() as u64, machines.focused().state().cycle_count);
+        let mut values: Vec<u16> = samples.iter().copied().collect();
+        values.dedup();
+        assert_eq!(values, [0, 1, 2, 3]);
+
+        // Another TPU's pins have nothing to do with these
+        let mut machines = network_machines();
+        app.analog_history.sample(machines.focused().state());
+        app.handle_key(KeyCode::Tab, &mut machines);
+        assert!(app.analog_history.samples(AnalogPin::Analog0).is_empty());
+    }
+}
@@ -0,0 +1,722 @@
+use crate::tui::analog::downsample;
+use crate::tui::app::{App, PromptKind};
+use crate::tui::clock::thousands;
+use crate::tui::halt::halt_banner;
+use crate::tui::highlight::{Heat, cell_style, text_style};
+use crate::tui::machines::{self, Machines};
+use crate::tui::mouse::digital_pin_areas;
+use crate::tui::network::{PACKETS_SHOWN, queue_lines};
+use crate::tui::ram::{WORDS_PER_ROW, cell_columns, format_page};
+use crate::tui::watch::format_watch;
+use ratatui::{
+    Frame,
+    layout::{Alignment, Constraint, Direction, Layout},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{
+        Block, Borders, Cell, Paragraph, Row, Sparkline, Table,
+        block::{Position, Title},
+    },
+};
+use strum::{EnumCount, IntoEnumIterator};
+use tls::rgal::SymbolResolver;
+use tls::shared::{AnalogPin, DigitalPin, Register};
+use tls::tpu::{self, peripheral::seven_segment::SevenSegmentDisplay};
+
+/// Draw the debugger: the machines, the TPU in focus and its panels, and the prompt or banner if there is one
+pub fn ui(f: &mut Frame, machines: &Machines, displays: &[SevenSegmentDisplay], app: &mut App) {
+    let tpu_vm = machines.focused();
+    let display = &displays[machines.focus()];
+    let tpu = tpu_vm.state();
+    let speed = app.clock.speed();
+
+    // Create main layout with title and content areas
+    let main_chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .margin(1)
+        .constraints(
+            [
+                Constraint::Length(3),                                         // Title
+                Constraint::Length(u16::from(machines.len() > 1)),             // Every TPU
+                Constraint::Length(u16::from(tpu.halted)),                     // Halt banner
+                Constraint::Min(0),                                            // Content
+                Constraint::Length(if app.show_log { LOG_HEIGHT } else { 0 }), // Log
+            ]
+            .as_ref(),
+        )
+        .split(f.size());
+
+    // Title with mode indicator
+    let mode_text = if let (Some(banner), Some(_)) = (&app.banner, &app.replace) {
+        format!("TPU Simulator - {banner}")
+    } else if app.running {
+        format!("TPU Simulator - RUNNING at {speed} - R to pause, +/- to change speed, Q to quit")
+    } else if let Some(banner) = &app.banner {
+        format!("TPU Simulator - {banner} - R to run on, S to step, Q to quit")
+    } else if tpu.halted {
+        "TPU Simulator - HALTED - Shift+L to reload, L for the log, Q to quit".to_string()
+    } else {
+        format!(
+            "TPU Simulator - Press Space to tick, S to Step, Left to step back, R to run at {speed}, +/- to change speed, arrows/PgUp/PgDn to move the cursor, B or click a line for a breakpoint, Shift+B for one with a condition, C to run to the cursor, E to edit a register, P to poke RAM, 1-8 or click to toggle inputs, the wheel to scroll, I to drive an analog input, N to deliver a packet, V to watch an expression, / to search, T to record a trace, Tab/F1-F12 to switch TPU, W to save a snapshot, O to open one, </> to scroll the network, {{/}} the stack, Home to find the PC, Shift+L to reload, L for the log, [/] to page RAM, A for ASCII, Q to quit"
+        )
+    };
+
+    let title = Paragraph::new(mode_text)
+        .style(Style::default().fg(if tpu.halted {
+            Color::Red
+        } else if app.banner.is_some() {
+            Color::Yellow
+        } else {
+            Color::Cyan
+        }))
+        .block(Block::default().borders(Borders::ALL));
+    f.render_widget(title, main_chunks[0]);
+
+    if machines.len() > 1 {
+        render_machines(f, machines, main_chunks[1]);
+    }
+
+    // Stays up until the TPU is reset or the program reloaded, both of which clear the reason
+    if let Some(reason) = tpu.halt_reason {
+        let pc = tpu.program_counter;
+        let instruction = tpu.rom.get(pc).map(|i| i.as_ref());
+        let banner = Paragraph::new(halt_banner(
+            reason,
+            pc,
+            instruction,
+            machines.focused_symbols(),
+        ))
+        .style(
+            Style::default()
+                .fg(Color::Red)
+                .add_modifier(Modifier::REVERSED | Modifier::BOLD),
+        );
+        f.render_widget(banner, main_chunks[2]);
+    }
+
+    if app.show_log {
+        render_log(f, app, main_chunks[4]);
+    }
+
+    // Split content area into left and right columns
+    let content_chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints(
+            [
+                Constraint::Percentage(50), // Left column
+                Constraint::Percentage(50), // Right column
+            ]
+            .as_ref(),
+        )
+        .split(main_chunks[3]);
+
+    // Split left column into sections
+    let left_chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(
+            [
+                Constraint::Percentage(25), // CPU Status
+                Constraint::Percentage(25), // Registers
+                Constraint::Percentage(25), // Network
+                Constraint::Percentage(25), // Stack
+            ]
+            .as_ref(),
+        )
+        .split(content_chunks[0]);
+
+    // Split right column into sections
+    let right_chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(
+            [
+                Constraint::Percentage(33), // RAM
+                Constraint::Percentage(33), // ROM
+                Constraint::Percentage(34), // I/O Pins
+            ]
+            .as_ref(),
+        )
+        .split(content_chunks[1]);
+
+    app.layout.ram = right_chunks[0];
+    app.layout.rom = right_chunks[1];
+    app.layout.network = left_chunks[2];
+
+    // Render each component
+    render_cpu_status(f, tpu, tpu_vm.history_len(), app, left_chunks[0]);
+    render_registers(f, tpu, app, left_chunks[1]);
+    render_network(f, tpu, app, left_chunks[2]);
+    // Watches share the stack's space once there are any
+    let watching = !app.watches.is_empty()
+        || app
+            .prompt
+            .as_ref()
+            .is_some_and(|prompt| prompt.kind == PromptKind::Watch);
+    if watching {
+        let stack_chunks = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+            .split(left_chunks[3]);
+        app.layout.stack = stack_chunks[0];
+        render_stack(f, tpu_vm, app, stack_chunks[0]);
+        render_watches(f, app, stack_chunks[1]);
+    } else {
+        app.layout.stack = left_chunks[3];
+        render_stack(f, tpu_vm, app, left_chunks[3]);
+    }
+    render_ram(f, tpu, app, right_chunks[0]);
+    render_rom(f, tpu_vm, machines.focused_symbols(), app, right_chunks[1]);
+    render_io_pins(f, tpu, display, app, right_chunks[2]);
+}
+
+/// Lines the log panel takes when it's open, with its border
+const LOG_HEIGHT: u16 = 8;
+
+/// The newest entries the log caught, coloured by level
+fn render_log(f: &mut Frame, app: &App, area: ratatui::layout::Rect) {
+    let level = app.log.level();
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(format!("Log, {level} and worse - F to change, L to hide"));
+    let entries = app.log.recent(block.inner(area).height as usize, level);
+    let lines: Vec<_> = entries
+        .into_iter()
+        .map(|entry| {
+            let color = match entry.level {
+                tracing::Level::ERROR => Color::Red,
+                tracing::Level::WARN => Color::Yellow,
+                tracing::Level::INFO => Color::Green,
+                tracing::Level::DEBUG => Color::Cyan,
+                _ => Color::DarkGray,
+            };
+            Line::from(vec![
+                Span::styled(format!("{:5} ", entry.level), Style::default().fg(color)),
+                Span::raw(entry.message),
+            ])
+        })
+        .collect();
+    f.render_widget(Paragraph::new(lines).block(block), area);
+}
+
+/// Every TPU on one line, with the one being shown highlighted
+fn render_machines(f: &mut Frame, machines: &Machines, area: ratatui::layout::Rect) {
+    let mut spans = Vec::new();
+    for index in 0..machines.len() {
+        if index > 0 {
+            spans.push(Span::raw(" │ "));
+        }
+        let tpu = machines.tpu(index);
+        let text = format!("F{} {}", index + 1, machines::summary(tpu));
+        let style = if index == machines.focus() {
+            Style::default().add_modifier(Modifier::REVERSED)
+        } else if tpu.halted() {
+            Style::default().fg(Color::Red)
+        } else {
+            Style::default()
+        };
+        spans.push(Span::styled(text, style));
+    }
+    f.render_widget(Paragraph::new(Line::from(spans)), area);
+}
+
+fn render_cpu_status(
+    f: &mut Frame,
+    tpu: &tpu::TpuState,
+    history: Option<usize>,
+    app: &App,
+    area: ratatui::layout::Rect,
+) {
+    let halted = tpu.halted;
+    let program_counter = tpu.program_counter;
+    let wait_cycles = tpu.execution_state.wait_cycles;
+    let history = match history {
+        Some(steps) => format!("{steps} steps, Left to step back"),
+        None => "off".to_string(),
+    };
+    let mut text = format!(
+        "Program Counter: {:04X}\nWait Cycles: {:04X}\nHalted: {}\nHistory: {}\nCycles: {}\nInstructions Retired: {}",
+        program_counter,
+        wait_cycles,
+        halted,
+        history,
+        thousands(tpu.cycle_count),
+        thousands(tpu.instructions_retired)
+    );
+    // Only running has a speed to measure
+    if app.running
+        && let Some(hz) = app.rate.hz()
+    {
+        text.push_str(&format!(
+            "\nSimulated Speed: {} Hz",
+            thousands(hz.round() as u64)
+        ));
+    }
+    if let Some(path) = app.trace.path() {
+        text.push_str(&format!("\nTracing to {}", path.display()));
+    }
+    let mut lines = prompt_lines(app, PromptKind::Snapshot);
+    lines.extend(text.lines().map(|line| Line::raw(line.to_string())));
+    let widget =
+        Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title("TPU Status"));
+    f.render_widget(widget, area);
+}
+
+fn render_registers(f: &mut Frame, tpu: &tpu::TpuState, app: &App, area: ratatui::layout::Rect) {
+    let mut lines = prompt_lines(app, PromptKind::Register);
+
+    for register in Register::iter() {
+        let value = tpu.registers[register as usize];
+        let text = format!("{:2}: {:04X}", format!("{:?}", register), value);
+        // Poked registers stand out until the TPU moves on
+        lines.push(if app.edited.contains(&register) {
+            Line::styled(format!("{text} *"), Style::default().fg(Color::Yellow))
+        } else {
+            Line::styled(text, text_style(app.highlights.register(register)))
+        });
+    }
+
+    let title = if lines.len() > Register::COUNT {
+        "Registers - e.g. A 0x10, Enter to set, Esc to cancel"
+    } else {
+        "Registers"
+    };
+    let widget = Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(title));
+    f.render_widget(widget, area);
+}
+
+/// The edit prompt and any error, if it's open for `kind`.
+/// It goes at the top of its panel so it's never cut off in a short one.
+fn prompt_lines(app: &App, kind: PromptKind) -> Vec<Line<'static>> {
+    let mut lines = Vec::new();
+    if let Some(prompt) = app.prompt.as_ref().filter(|prompt| prompt.kind == kind) {
+        let verb = match kind {
+            PromptKind::Snapshot => "Open",
+            PromptKind::Watch => "Watch",
+            PromptKind::Search => "Search",
+            PromptKind::Condition => "Break if",
+            _ => "Set",
+        };
+        lines.push(Line::styled(
+            format!("{verb}: {}_", prompt.input),
+            Style::default().add_modifier(Modifier::REVERSED),
+        ));
+        if let Some(error) = &app.edit_error {
+            lines.push(Line::styled(error.clone(), Style::default().fg(Color::Red)));
+        }
+    }
+    lines
+}
+
+fn render_network(f: &mut Frame, tpu: &tpu::TpuState, app: &App, area: ratatui::layout::Rect) {
+    let mut lines = prompt_lines(app, PromptKind::Packet);
+    let title = if lines.is_empty() {
+        "Network"
+    } else {
+        "Network - sender[:port] and data, e.g. 0x2:3 5 6, Enter to deliver, Esc to cancel"
+    };
+    lines.push(Line::raw(format!(
+        "Network Address: {:04X}",
+        tpu.network_address
+    )));
+    let queues = queue_lines("Incoming", tpu.incoming_packets.iter(), PACKETS_SHOWN)
+        .into_iter()
+        .chain(queue_lines(
+            "Outgoing",
+            tpu.outgoing_packets.iter(),
+            PACKETS_SHOWN,
+        ));
+    lines.extend(queues.map(Line::raw));
+
+    // Don't scroll past the last line, or the prompt out of view
+    let scroll = if app.prompt.is_some() {
+        0
+    } else {
+        app.network_scroll
+            .min((lines.len() as u16).saturating_sub(1))
+    };
+    let widget = Paragraph::new(lines)
+        .scroll((scroll, 0))
+        .block(Block::default().borders(Borders::ALL).title(title));
+    f.render_widget(widget, area);
+}
+
+fn render_stack(f: &mut Frame, tpu_vm: &tpu::TPU, app: &App, area: ratatui::layout::Rect) {
+    let tpu = tpu_vm.state();
+    let entries = tpu_vm.stack_entries();
+
+    let heading = Line::raw(format!(
+        "Stack Size: {} (Max: {})",
+        entries.len(),
+        tpu.stack_high_water
+    ));
+
+    // Top of the stack first, numbered from the bottom like PEEK does
+    let mut lines = Vec::new();
+    if entries.is_empty() {
+        lines.push(Line::raw("<empty>"));
+    } else {
+        for (slot, entry) in (0..entries.len()).rev().zip(&entries) {
+            lines.push(Line::styled(
+                format!("{slot:2}: {entry}"),
+                text_style(app.highlights.stack(slot)),
+            ));
+        }
+    }
+    // The heading stays put, {/} scroll down from the top of the stack
+    let scroll = (app.stack_scroll as usize).min(lines.len().saturating_sub(1));
+    lines.drain(..scroll);
+    lines.insert(0, heading);
+
+    let title = if scroll > 0 {
+        format!("Stack, {scroll} hidden above")
+    } else {
+        "Stack".to_string()
+    };
+    let widget = Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(title));
+    f.render_widget(widget, area);
+}
+
+fn render_watches(f: &mut Frame, app: &App, area: ratatui::layout::Rect) {
+    let mut lines = prompt_lines(app, PromptKind::Watch);
+    let title = if lines.is_empty() {
+        "Watches"
+    } else {
+        "Watches - e.g. ram[0x20] + A, one already watched to remove it, Enter to add, Esc to cancel"
+    };
+    for watch in app.watches.iter() {
+        let style = match &watch.value {
+            Some(Err(_)) => Style::default().fg(Color::Red),
+            _ => text_style(app.watches.heat(watch, app.highlights.fade_ticks)),
+        };
+        lines.push(Line::styled(format_watch(watch), style));
+    }
+
+    let widget = Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(title));
+    f.render_widget(widget, area);
+}
+
+fn render_ram(f: &mut Frame, tpu: &tpu::TpuState, app: &mut App, area: ratatui::layout::Rect) {
+    let ram = &tpu.ram;
+    let mut lines = prompt_lines(app, PromptKind::Ram);
+    let block = Block::default().borders(Borders::ALL);
+    let height = (block.inner(area).height as usize).saturating_sub(lines.len());
+    let ram_view = &mut app.ram_view;
+    let window = ram_view.window(ram.len(), height);
+
+    let title = if lines.is_empty() {
+        format!(
+            "RAM, {} words, {:04X}-{:04X}{}",
+            ram.len(),
+            window.start,
+            window.end.saturating_sub(1),
+            if ram_view.ascii() { ", ASCII" } else { "" }
+        )
+    } else {
+        "RAM - e.g. 0x10 5 or 0x10..0x18 = 0, Enter to set, Esc to cancel".into()
+    };
+
+    let page = format_page(&ram[window.clone()], window.start, ram_view.ascii());
+    let found = |address: usize| {
+        app.search
+            .as_ref()
+            .is_some_and(|search| search.ram.binary_search(&address).is_ok())
+    };
+    for (row, line) in page.into_iter().enumerate() {
+        let address = window.start + row * WORDS_PER_ROW;
+        // Pokes stand out until the TPU moves on, other changes until they fade
+        let styles: Vec<(usize, Style)> = (0..WORDS_PER_ROW)
+            .filter(|&column| address + column < window.end)
+            .filter_map(|column| {
+                let style = if app.was_poked(address + column) {
+                    Style::default().fg(Color::Yellow)
+                } else if found(address + column) {
+                    Style::default().fg(Color::Black).bg(Color::Magenta)
+                } else {
+                    cell_style(app.highlights.ram(address + column))
+                };
+                (style != Style::default()).then_some((column, style))
+            })
+            .collect();
+
+        // Split the line around the words to be picked out
+        let mut spans = Vec::new();
+        let mut at = 0;
+        for (column, style) in styles {
+            let cell = cell_columns(column);
+            // The space before the word is left plain, so neighbouring words don't run together
+            spans.push(Span::raw(line[at..=cell.start].to_string()));
+            spans.push(Span::styled(
+                line[cell.start + 1..cell.end].to_string(),
+                style,
+            ));
+            at = cell.end;
+        }
+        spans.push(Span::raw(line[at..].to_string()));
+        lines.push(Line::from(spans));
+    }
+
+    let widget = Paragraph::new(lines).block(block.title(title));
+    f.render_widget(widget, area);
+}
+
+fn render_rom(
+    f: &mut Frame,
+    tpu_vm: &tpu::TPU,
+    symbols: &SymbolResolver,
+    app: &mut App,
+    area: ratatui::layout::Rect,
+) {
+    let tpu = tpu_vm.state();
+    let rom = &tpu.rom;
+    let program_counter = tpu.program_counter;
+    let cursor = (!app.running).then(|| app.rom_view.cursor(program_counter));
+    // The search and breakpoint prompts go in the title, the lines below are all the ROM's
+    let search = prompt_lines(app, PromptKind::Search).into_iter().next();
+    let condition = prompt_lines(app, PromptKind::Condition);
+    let title = match (search, condition.as_slice()) {
+        (Some(prompt), _) => Line::from(vec![
+            prompt.spans[0].clone(),
+            Span::raw(
+                " - text, or a number to find in RAM as well, Enter to search, Esc to cancel",
+            ),
+        ]),
+        (None, [prompt, error @ ..]) => {
+            let mut spans = vec![prompt.spans[0].clone()];
+            match error.first() {
+                Some(error) => spans.extend(error.spans.iter().map(|span| {
+                    Span::styled(format!(" - {}", span.content), span.style)
+                })),
+                None => spans.push(Span::raw(
+                    " - e.g. A == 0x12 && ram[0x20] > 3, empty to always stop, Enter to set, Esc to cancel",
+                )),
+            }
+            Line::from(spans)
+        }
+        (None, []) => Line::raw(format!(
+            "ROM, {} instructions, PC {:04X}{}",
+            rom.len(),
+            program_counter,
+            match cursor {
+                Some(cursor) if !app.rom_view.following() => format!(", cursor {cursor:04X}"),
+                _ => String::new(),
+            }
+        )),
+    };
+    let block = Block::default().borders(Borders::ALL).title(title);
+    let found = |address: usize| {
+        app.search
+            .as_ref()
+            .is_some_and(|search| search.rom.binary_search(&address).is_ok())
+    };
+
+    // Only the lines that fit are built, less the header, so a long program costs no more than a short one. Labels
+    // take a line of their own above the instruction they name, so fewer instructions fit when there are some.
+    let height = block.inner(area).height.saturating_sub(1) as usize;
+    let mut window = app.rom_view.window(program_counter, rom.len(), height);
+    let labels: usize = window.clone().map(|i| symbols.labels_at(i).len()).sum();
+    if labels > 0 {
+        let height = height.saturating_sub(labels).max(1);
+        window = app.rom_view.window(program_counter, rom.len(), height);
+    }
+
+    // What's on each line, so a click can find the address under it
+    app.layout.rom_lines = window
+        .clone()
+        .flat_map(|i| {
+            let labels = symbols.labels_at(i).iter().map(|_| None);
+            labels.chain(std::iter::once(Some(i)))
+        })
+        .take(height)
+        .collect();
+
+    let rows = window.flat_map(|i| {
+        let labels = symbols.labels_at(i).iter().map(|label| {
+            Row::new(vec![
+                Cell::from(""),
+                Cell::from(""),
+                Cell::from(""),
+                Cell::from(format!("{label}:")),
+            ])
+            .style(Style::default().fg(Color::Yellow))
+        });
+        let row = Row::new(vec![
+            // A breakpoint with a condition is a diamond, it might not stop
+            Cell::from(if tpu_vm.breakpoint_condition(i).is_some() {
+                Span::styled("◆", Style::default().fg(Color::Red))
+            } else if tpu_vm.breakpoints().contains(&i) {
+                Span::styled("●", Style::default().fg(Color::Red))
+            } else {
+                Span::raw(" ")
+            }),
+            Cell::from(if i == program_counter { ">" } else { " " }),
+            Cell::from(format!("{:04X}", i)),
+            Cell::from(symbols.format_instruction(&rom[i])),
+        ]);
+        let row = if i == program_counter {
+            row.style(
+                Style::default()
+                    .fg(Color::Black)
+                    .bg(Color::Cyan)
+                    .add_modifier(Modifier::BOLD),
+            )
+        } else if Some(i) == cursor {
+            row.style(Style::default().add_modifier(Modifier::REVERSED))
+        } else if found(i) {
+            row.style(Style::default().fg(Color::Magenta))
+        } else {
+            row
+        };
+        labels.chain(std::iter::once(row))
+    });
+
+    let widget = Table::new(
+        rows,
+        [
+            Constraint::Length(1),
+            Constraint::Length(1),
+            Constraint::Length(4),
+            Constraint::Fill(1),
+        ],
+    )
+    .header(Row::new(vec!["", "", "ADDR", "INSTRUCTION"]).style(Style::default().fg(Color::Yellow)))
+    .block(block);
+    f.render_widget(widget, area);
+}
+
+fn render_io_pins(
+    f: &mut Frame,
+    tpu: &tpu::TpuState,
+    display: &SevenSegmentDisplay,
+    app: &mut App,
+    area: ratatui::layout::Rect,
+) {
+    let prompt = prompt_lines(app, PromptKind::AnalogInput);
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(
+            [
+                Constraint::Length(if prompt.is_empty() {
+                    0
+                } else {
+                    prompt.len() as u16 + 2
+                }), // Prompt
+                Constraint::Percentage(40), // Analog
+                Constraint::Percentage(40), // Digital
+                Constraint::Length(5),      // Seven segment display
+            ]
+            .as_ref(),
+        )
+        .split(area);
+
+    if !prompt.is_empty() {
+        let widget = Paragraph::new(prompt).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Analog input - e.g. 2 512, Enter to drive, Esc to cancel"),
+        );
+        f.render_widget(widget, chunks[0]);
+    }
+    app.layout.digital_pins = chunks[1];
+    render_digital_io_block(f, tpu, app, chunks[1]);
+    render_analog_io_block(f, tpu, app, chunks[2]);
+    render_seven_segment(f, display, chunks[3]);
+    // // For now, just display a placeholder
+    // let widget = Paragraph::new("I/O Pin states will be displayed here")
+    //     .block(Block::default().borders(Borders::ALL).title("I/O Pins"));
+    //    f.render_widget(widget, area);
+}
+
+fn render_digital_io_block(
+    f: &mut Frame,
+    tpu: &tpu::TpuState,
+    app: &App,
+    area: ratatui::layout::Rect,
+) {
+    // The mouse finds the pins where they're drawn
+    let chunks = digital_pin_areas(area);
+
+    for pin in DigitalPin::iter() {
+        let state = tpu.digital_pins[pin as usize];
+        let widget = Paragraph::new("")
+            .style(Style::default().fg(Color::White).bg(if state {
+                Color::Green
+            } else {
+                Color::Black
+            }))
+            .block(pin_block(
+                format!("{pin:?} [{}]", pin as usize + 1),
+                format!("{pin:?}"),
+                tpu.digital_pin_config[pin as usize],
+                app.highlights.digital_pin(pin),
+            ));
+        f.render_widget(widget, chunks[pin as usize]);
+    }
+}
+
+/// The border of a pin, inputs can be driven from the keyboard so they stand out.
+/// An input's title says how.
+/// A pin that changed recently has its title highlighted.
+fn pin_block(
+    input_title: String,
+    output_title: String,
+    input: bool,
+    heat: Option<Heat>,
+) -> Block<'static> {
+    let block = Block::default().borders(Borders::ALL);
+    if input {
+        block
+            .title(Span::styled(input_title, cell_style(heat)))
+            .border_style(Style::default().fg(Color::Yellow))
+    } else {
+        block.title(Span::styled(output_title, cell_style(heat)))
+    }
+}
+
+/// Each analog pin as a sparkline of its recent values, with the value it has now under it. A sparkline is scaled to
+/// its highest value so a trend shows whatever the resolution.
+fn render_analog_io_block(
+    f: &mut Frame,
+    tpu: &tpu::TpuState,
+    app: &App,
+    area: ratatui::layout::Rect,
+) {
+    let constraints = AnalogPin::iter().map(|_| Constraint::Fill(1));
+
+    let chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints(constraints)
+        .split(area);
+
+    for pin in AnalogPin::iter() {
+        let state = tpu.analog_pins[pin as usize];
+        let block = pin_block(
+            format!("{pin:?} [I]"),
+            format!("{pin:?}"),
+            tpu.analog_pin_config[pin as usize],
+            app.highlights.analog_pin(pin),
+        )
+        .title(
+            Title::from(state.to_string())
+                .position(Position::Bottom)
+                .alignment(Alignment::Center),
+        );
+        let area = chunks[pin as usize];
+        let data = downsample(
+            app.analog_history.samples(pin),
+            usize::from(block.inner(area).width),
+        );
+        let widget = Sparkline::default()
+            .data(&data)
+            .style(Style::default().fg(Color::Cyan).bg(Color::Black))
+            .block(block);
+        f.render_widget(widget, area);
+    }
+}
+
+fn render_seven_segment(f: &mut Frame, display: &SevenSegmentDisplay, area: ratatui::layout::Rect) {
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title("Seven Segment");
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+    f.render_widget(display, inner);
+}
@@ -0,0 +1,86 @@
+use crate::tui::highlight::{Heat, heat};
+use tls::tpu::{EvalError, Expr, TPU};
+
+/// An expression shown in the watch panel, with its value when the TPU was last drawn
+#[derive(Clone, Debug)]
+pub struct Watch {
+    pub expr: Expr,
+    pub value: Option<Result<i64, EvalError>>,
+    /// The cycle the value last changed on
+    changed: Option<u64>,
+}
+
+/// The expressions being watched, in the order they were added
+#[derive(Clone, Debug, Default)]
+pub struct Watches {
+    watches: Vec<Watch>,
+    cycle: u64,
+}
+
+impl Watches {
+    /// Watch `expr`, or stop watching it if it's already watched. Returns whether it's now watched.
+    pub fn toggle(&mut self, expr: Expr) -> bool {
+        if let Some(index) = self.watches.iter().position(|watch| watch.expr == expr) {
+            self.watches.remove(index);
+            return false;
+        }
+        self.watches.push(Watch {
+            expr,
+            value: None,
+            changed: None,
+        });
+        true
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.watches.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Watch> {
+        self.watches.iter()
+    }
+
+    /// Evaluate every watch on the TPU, stamping those whose value changed with its cycle count
+    pub fn update(&mut self, tpu: &TPU) {
+        let cycle = tpu.state().cycle_count;
+        // A reset starts the cycles again, so older stamps would look like they're from the future
+        if cycle < self.cycle {
+            self.forget();
+        }
+        self.cycle = cycle;
+
+        for watch in &mut self.watches {
+            let value = Some(watch.expr.eval(tpu));
+            // The first value isn't a change, nor is one seen before any tick
+            if watch.value.is_some() && watch.value != value {
+                watch.changed = Some(cycle);
+            }
+            watch.value = value;
+        }
+    }
+
+    /// Forget the values seen, so the next TPU shown doesn't look like it changed all of them
+    pub fn forget(&mut self) {
+        for watch in &mut self.watches {
+            watch.value = None;
+            watch.changed = None;
+        }
+    }
+
+    /// How recently the watch's value changed, see `heat`
+    pub fn heat(&self, watch: &Watch, fade_ticks: u64) -> Option<Heat> {
+        heat(self.cycle.saturating_sub(watch.changed?), fade_ticks)
+    }
+}
+
+/// A watch as `expr = value`, in hex as well if it fits in a word, or why it couldn't be evaluated
+pub fn format_watch(watch: &Watch) -> String {
+    match watch.value {
+        None => format!("{} = ?", watch.expr),
+        Some(Ok(value)) if (0..=0xFFFF).contains(&value) => {
+            format!("{} = {value:#06X} ({value})", watch.expr)
+        }
+        Some(Ok(value)) => format!("{} = {value}", watch.expr),
+        Some(Err(error)) => format!("{}: {error}", watch.expr),
+    }
+}
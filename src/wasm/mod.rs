@@ -0,0 +1,75 @@
+//! The TPU for JavaScript, `wasm-pack build tls-wasm` builds it.
+//!
+//! The core of the crate, the assembler and the TPU, builds for `wasm32-unknown-unknown` without the `tui` feature,
+//! `scripts/check-wasm.sh` checks it does.
+
+#[cfg(test)]
+mod wasm_test;
+
+use crate::rgal::parse_program;
+use crate::shared::{DigitalPin, NetPacket};
+use crate::tpu::{TPU, create_basic_tpu_config};
+use wasm_bindgen::prelude::*;
+
+/// A TPU as JavaScript sees it, its state is read as JSON
+#[wasm_bindgen]
+pub struct WasmTpu {
+    tpu: TPU,
+}
+
+#[wasm_bindgen]
+impl WasmTpu {
+    /// Assemble `source` and make a TPU to run it, throws if it isn't valid RGAL
+    #[wasm_bindgen(constructor)]
+    pub fn new(source: &str) -> Result<WasmTpu, JsError> {
+        Self::from_source(source).map_err(|error| JsError::new(&error.to_string()))
+    }
+
+    /// Run up to `n` ticks, stopping early if the TPU halts
+    pub fn tick(&mut self, n: u32) {
+        self.tpu.run(n.into());
+    }
+
+    /// The whole of the TPU's state, as `TpuState` serializes it
+    pub fn state_json(&self) -> Result<String, JsError> {
+        serde_json::to_string(self.tpu.state()).map_err(|error| JsError::new(&error.to_string()))
+    }
+
+    /// Drive a digital input, returns false if `pin` doesn't exist or the program drives it
+    pub fn drive_digital(&mut self, pin: u16, level: bool) -> bool {
+        DigitalPin::from_repr(pin)
+            .is_some_and(|pin| self.tpu.drive_digital_input(pin, level).is_ok())
+    }
+
+    /// Deliver a packet of one word from `sender`, returns false if the TPU's buffer is full
+    pub fn inject_packet(&mut self, sender: u16, data: u16) -> bool {
+        let target = self.tpu.state().network_address;
+        self.tpu
+            .deliver_packet(NetPacket::new(sender, target, data))
+    }
+
+    pub fn halted(&self) -> bool {
+        self.tpu.halted()
+    }
+
+    /// Why the TPU halted, named as in `state_json`, undefined while it's running
+    pub fn halt_reason(&self) -> Option<String> {
+        self.tpu
+            .state()
+            .halt_reason
+            .map(|reason| format!("{reason:?}"))
+    }
+}
+
+impl WasmTpu {
+    /// As `new`, for Rust, where there's no JavaScript to throw to
+    pub fn from_source(source: &str) -> crate::Result<Self> {
+        Ok(Self {
+            tpu: create_basic_tpu_config(parse_program(source)?),
+        })
+    }
+
+    pub fn tpu(&self) -> &TPU {
+        &self.tpu
+    }
+}
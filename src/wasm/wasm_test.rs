@@ -0,0 +1,54 @@
+use super::*;
+use crate::shared::{HaltReason, Register};
+use serde_json::Value;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(tpu: &WasmTpu) -> Value {
+        serde_json::from_str(&tpu.state_json().unwrap()).expect("valid JSON")
+    }
+
+    #[test]
+    fn test_wasm_tpu() {
+        assert!(WasmTpu::from_source("NOT AN INSTRUCTION").is_err());
+
+        let mut tpu = WasmTpu::from_source("WRX\nDPWW 0x80\nHLT").unwrap();
+        tpu.tick(20);
+        assert!(!tpu.halted());
+        assert_eq!(tpu.halt_reason(), None);
+
+        // Every pin starts as an output
+        assert!(!tpu.drive_digital(0, true));
+        assert!(!tpu.drive_digital(99, true));
+
+        assert!(tpu.inject_packet(0x2, 42));
+        tpu.tick(100);
+        assert!(tpu.halted());
+        assert_eq!(tpu.halt_reason().as_deref(), Some("HLTOpcode"));
+        assert_eq!(tpu.tpu().read_register(Register::X), 0x2);
+        assert_eq!(tpu.tpu().read_register(Register::Y), 42);
+    }
+
+    #[test]
+    fn test_wasm_state_json() {
+        let mut tpu = WasmTpu::from_source("LDR A, 7\nHLT").unwrap();
+        tpu.tick(100);
+        let state = state(&tpu);
+
+        // The fields a page reads
+        assert_eq!(state["halted"], Value::Bool(true));
+        assert_eq!(
+            state["halt_reason"],
+            serde_json::to_value(HaltReason::HLTOpcode).unwrap()
+        );
+        assert_eq!(state["halt_reason"], tpu.halt_reason().unwrap().as_str());
+        let registers = state["registers"].as_array().expect("registers");
+        assert_eq!(registers.len(), 10);
+        assert_eq!(registers[Register::A as usize], 7);
+        assert!(state["program_counter"].is_u64());
+        assert_eq!(state["ram"].as_array().expect("ram").len(), TPU::RAM_SIZE);
+        assert_eq!(state["digital_pins"].as_array().expect("pins").len(), 8);
+    }
+}
@@ -0,0 +1,179 @@
+#[cfg(test)]
+mod wave_test;
+
+use crate::shared::{AnalogPin, DigitalPin, Register};
+use crate::tpu::TPU;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use strum::{EnumCount, IntoEnumIterator};
+
+/// Records a TPU's pins, program counter and chosen registers as a VCD waveform, for viewers like GTKWave.
+///
+/// The host calls `WaveRecorder::sample` after each tick. Each tick is one unit of the timescale, and the time is
+/// the TPU's cycle count. Digital pins are 1 bit wires, analog pins, the program counter and registers are 16 bit
+/// vectors. Only the last value of each signal is kept, the changes go straight to the writer so a long run doesn't
+/// build up in memory.
+pub struct WaveRecorder<W: Write> {
+    out: W,
+    registers: Vec<Register>,
+    timescale: String,
+    /// Values written last, in the order the signals are declared, empty until the header is written
+    last: Vec<u16>,
+}
+
+/// A signal in the waveform
+struct Signal {
+    name: String,
+    width: u8,
+}
+
+impl WaveRecorder<BufWriter<File>> {
+    /// Record to a new file at `path`
+    pub fn create(path: impl AsRef<Path>, registers: &[Register]) -> io::Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?), registers))
+    }
+}
+
+impl<W: Write> WaveRecorder<W> {
+    pub const DEFAULT_TIMESCALE: &'static str = "1 us";
+
+    pub fn new(out: W, registers: &[Register]) -> Self {
+        Self {
+            out,
+            registers: registers.to_vec(),
+            timescale: Self::DEFAULT_TIMESCALE.into(),
+            last: Vec::new(),
+        }
+    }
+
+    /// How long a tick is, e.g. `10 ns`
+    pub fn with_timescale(mut self, timescale: &str) -> Self {
+        self.timescale = timescale.into();
+        self
+    }
+
+    /// Write the signals that changed since the last sample, the first sample writes the header and every value
+    pub fn sample(&mut self, tpu: &TPU) -> io::Result<()> {
+        let values = self.values(tpu);
+        let cycle = tpu.state().cycle_count;
+
+        if self.last.is_empty() {
+            self.write_header()?;
+            writeln!(self.out, "#{cycle}")?;
+            writeln!(self.out, "$dumpvars")?;
+            for (index, &value) in values.iter().enumerate() {
+                self.write_value(index, value)?;
+            }
+            writeln!(self.out, "$end")?;
+            self.last = values;
+            return Ok(());
+        }
+
+        let changed: Vec<(usize, u16)> = values
+            .iter()
+            .zip(&self.last)
+            .enumerate()
+            .filter(|(_, (value, last))| value != last)
+            .map(|(index, (&value, _))| (index, value))
+            .collect();
+        if !changed.is_empty() {
+            writeln!(self.out, "#{cycle}")?;
+            for (index, value) in changed {
+                self.write_value(index, value)?;
+            }
+        }
+        self.last = values;
+        Ok(())
+    }
+
+    /// Flush the writer and hand it back
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    /// Every signal, in the order they're declared
+    fn signals(&self) -> Vec<Signal> {
+        let digital = DigitalPin::iter().map(|pin| Signal {
+            name: format!("D{}", pin as u16),
+            width: 1,
+        });
+        let analog = AnalogPin::iter().map(|pin| Signal {
+            name: format!("A{}", pin as u16),
+            width: 16,
+        });
+        let program_counter = std::iter::once(Signal {
+            name: "PC".into(),
+            width: 16,
+        });
+        let registers = self.registers.iter().map(|register| Signal {
+            name: format!("{register}"),
+            width: 16,
+        });
+        digital
+            .chain(analog)
+            .chain(program_counter)
+            .chain(registers)
+            .collect()
+    }
+
+    /// The values of the signals now
+    fn values(&self, tpu: &TPU) -> Vec<u16> {
+        let state = tpu.state();
+        let digital = DigitalPin::iter().map(|pin| state.digital_pins[pin as usize] as u16);
+        let analog = AnalogPin::iter().map(|pin| tpu.get_analog_pin(pin));
+        let registers = self
+            .registers
+            .iter()
+            .map(|&register| tpu.read_register(register));
+        digital
+            .chain(analog)
+            .chain(std::iter::once(state.program_counter as u16))
+            .chain(registers)
+            .collect()
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        writeln!(self.out, "$version tls {} $end", env!("CARGO_PKG_VERSION"))?;
+        writeln!(self.out, "$timescale {} $end", self.timescale)?;
+        writeln!(self.out, "$scope module tpu $end")?;
+        for (index, signal) in self.signals().iter().enumerate() {
+            let kind = if signal.width == 1 { "wire" } else { "reg" };
+            writeln!(
+                self.out,
+                "$var {kind} {} {} {} $end",
+                signal.width,
+                identifier(index),
+                signal.name
+            )?;
+        }
+        writeln!(self.out, "$upscope $end")?;
+        writeln!(self.out, "$enddefinitions $end")
+    }
+
+    fn write_value(&mut self, index: usize, value: u16) -> io::Result<()> {
+        // Only digital pins are 1 bit wide, they come first
+        if index < DigitalPin::COUNT {
+            writeln!(self.out, "{value}{}", identifier(index))
+        } else {
+            writeln!(self.out, "b{value:b} {}", identifier(index))
+        }
+    }
+}
+
+/// The short VCD identifier of the signal at `index`, made from the printable characters `!` to `~`
+fn identifier(mut index: usize) -> String {
+    const FIRST: u8 = b'!';
+    const COUNT: usize = (b'~' - b'!' + 1) as usize;
+
+    let mut identifier = String::new();
+    loop {
+        identifier.push((FIRST + (index % COUNT) as u8) as char);
+        index /= COUNT;
+        if index == 0 {
+            return identifier;
+        }
+        index -= 1;
+    }
+}
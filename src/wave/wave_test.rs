@@ -0,0 +1,119 @@
+use crate::rgal::parse_program;
+use crate::shared::{AnalogPin, DigitalPin, Register};
+use crate::tpu::TPU;
+use crate::wave::{WaveRecorder, identifier};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use strum::EnumCount;
+
+    /// Records every tick of `program` until it halts
+    fn record(program: &str) -> (String, TPU) {
+        let mut tpu = TPU::new(
+            0x1,
+            [false; AnalogPin::COUNT],
+            [false; DigitalPin::COUNT],
+            parse_program(program).expect("parse failure"),
+        );
+        let mut recorder =
+            WaveRecorder::new(Vec::new(), &[Register::A, Register::R0]).with_timescale("10 ns");
+        recorder.sample(&tpu).expect("write failure");
+        while !tpu.halted() {
+            tpu.tick();
+            recorder.sample(&tpu).expect("write failure");
+        }
+
+        let vcd = String::from_utf8(recorder.finish().expect("write failure")).expect("not UTF-8");
+        (vcd, tpu)
+    }
+
+    #[test]
+    fn test_wave_header() {
+        let (vcd, _) = record("HLT");
+        let header: Vec<&str> = vcd
+            .lines()
+            .take_while(|line| *line != "$enddefinitions $end")
+            .collect();
+
+        assert!(header[0].starts_with("$version tls "));
+        assert_eq!(header[1], "$timescale 10 ns $end");
+        assert_eq!(header[2], "$scope module tpu $end");
+        let vars: Vec<&str> = header
+            .iter()
+            .copied()
+            .filter(|line| line.starts_with("$var"))
+            .collect();
+        assert_eq!(vars.len(), DigitalPin::COUNT + AnalogPin::COUNT + 1 + 2);
+        assert_eq!(vars[0], "$var wire 1 ! D0 $end");
+        assert_eq!(vars[7], "$var wire 1 ( D7 $end");
+        assert_eq!(vars[8], "$var reg 16 ) A0 $end");
+        assert_eq!(vars[12], "$var reg 16 - PC $end");
+        assert_eq!(vars[13], "$var reg 16 . A $end");
+        assert_eq!(vars[14], "$var reg 16 / R0 $end");
+        assert_eq!(header.last(), Some(&"$upscope $end"));
+
+        // Every signal starts from 0 in the initial dump
+        let dump: Vec<&str> = vcd
+            .lines()
+            .skip_while(|line| *line != "$dumpvars")
+            .skip(1)
+            .take_while(|line| *line != "$end")
+            .collect();
+        assert_eq!(dump.len(), vars.len());
+        assert_eq!(dump[0], "0!");
+        assert_eq!(dump[8], "b0 )");
+    }
+
+    #[test]
+    fn test_wave_value_changes() {
+        let (vcd, tpu) = record(
+            r#"DPW 0, 1
+            LDR R0, 5
+            APW 1, R0
+            DPW 0, 0
+            HLT"#,
+        );
+
+        // The changes after the initial dump, grouped under the time they happened
+        let mut changes: Vec<(u64, Vec<&str>)> = Vec::new();
+        for line in vcd.lines().skip_while(|line| *line != "$end").skip(1) {
+            match line.strip_prefix('#') {
+                Some(time) => changes.push((time.parse().expect("bad time"), Vec::new())),
+                None => changes
+                    .last_mut()
+                    .expect("change before a time")
+                    .1
+                    .push(line),
+            }
+        }
+
+        let time_of = |change: &str| {
+            changes
+                .iter()
+                .find(|(_, lines)| lines.contains(&change))
+                .map(|&(time, _)| time)
+                .unwrap_or_else(|| panic!("{change} never happened"))
+        };
+        // D0 goes high, then R0 and A1 are 5, then D0 goes low
+        let d0_high = time_of("1!");
+        assert!(d0_high < time_of("b101 /"));
+        assert!(time_of("b101 /") < time_of("b101 *"));
+        assert!(time_of("b101 *") < time_of("0!"));
+
+        // Times only go forwards, up to where the TPU halted
+        assert!(changes.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert!(changes.last().unwrap().0 <= tpu.state().cycle_count);
+        // Each time has the PC moving or a value changing, nothing is repeated
+        assert!(changes.iter().all(|(_, lines)| !lines.is_empty()));
+    }
+
+    #[test]
+    fn test_wave_identifiers() {
+        assert_eq!(identifier(0), "!");
+        assert_eq!(identifier(93), "~");
+        assert_eq!(identifier(94), "!!");
+        assert_eq!(identifier(95), "\"!");
+        assert_eq!(identifier(94 + 94 * 94), "!!!");
+    }
+}
@@ -0,0 +1,43 @@
+//! Runs `scripts/check-wasm.sh`, skipped when the wasm32 target isn't installed
+#![cfg(unix)]
+
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+const TARGET: &str = "wasm32-unknown-unknown";
+
+/// Whether rustup has the standard library for `TARGET`
+fn target_installed() -> bool {
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    Command::new(rustc)
+        .args(["--print", "sysroot"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .is_some_and(|output| {
+            let sysroot = String::from_utf8_lossy(&output.stdout);
+            Path::new(sysroot.trim())
+                .join("lib/rustlib")
+                .join(TARGET)
+                .exists()
+        })
+}
+
+#[test]
+fn test_wasm_build() {
+    if !target_installed() {
+        eprintln!("skipped, {TARGET} isn't installed");
+        return;
+    }
+
+    // A target directory of its own, the one running this test is locked
+    let status = Command::new(Path::new(env!("CARGO_MANIFEST_DIR")).join("scripts/check-wasm.sh"))
+        .env(
+            "CARGO_TARGET_DIR",
+            Path::new(env!("CARGO_TARGET_TMPDIR")).join("wasm"),
+        )
+        .status()
+        .expect("run scripts/check-wasm.sh");
+    assert!(status.success(), "the crate doesn't build for {TARGET}");
+}
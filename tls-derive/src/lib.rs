@@ -1,6 +1,7 @@
 use proc_macro::TokenStream;
 use quote::{quote};
-use syn::{parse_macro_input, DeriveInput, Data, Fields};
+use std::collections::BTreeMap;
+use syn::{parse_macro_input, DeriveInput, Data, Fields, LitInt, Type};
 
 #[proc_macro_derive(DisplayInstruction)]
 pub fn derive_display(input: TokenStream) -> TokenStream {
@@ -56,7 +57,12 @@ pub fn derive_display(input: TokenStream) -> TokenStream {
                             #name::#variant_name(a, b, c) => write!(f, "{} {}, {}, {}", stringify!(#variant_name), a, b, c),
                         }
                     },
-                    _ => panic!("More than 3 fields are not supported"),
+                    4 => {
+                        quote! {
+                            #name::#variant_name(a, b, c, d) => write!(f, "{} {}, {}, {}, {}", stringify!(#variant_name), a, b, c, d),
+                        }
+                    },
+                    _ => panic!("More than 4 fields are not supported"),
                 }
             },
         }
@@ -64,8 +70,8 @@ pub fn derive_display(input: TokenStream) -> TokenStream {
     
     // Generate the implementation
     let expanded = quote! {
-        impl std::fmt::Display for #name {
-            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        impl ::core::fmt::Display for #name {
+            fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
                 match self {
                     #(#match_arms)*
                 }
@@ -75,4 +81,162 @@ pub fn derive_display(input: TokenStream) -> TokenStream {
     
     // Return the generated code
     TokenStream::from(expanded)
-}
\ No newline at end of file
+}
+
+/// What an operand of an instruction holds
+#[derive(Clone, Copy, PartialEq)]
+enum OperandKind {
+    Register,
+    Value,
+}
+
+/// The kind of an operand, from the type of the field holding it
+fn operand_kind(ty: &Type) -> syn::Result<OperandKind> {
+    let ident = match ty {
+        Type::Path(path) => path.path.segments.last().map(|segment| segment.ident.to_string()),
+        _ => None,
+    };
+    match ident.as_deref() {
+        Some("Register") => Ok(OperandKind::Register),
+        Some("OperandValueType") => Ok(OperandKind::Value),
+        _ => Err(syn::Error::new_spanned(ty, "operands must be a Register or an OperandValueType")),
+    }
+}
+
+/// `Instruction::opcode`, `Instruction::operands`, `Instruction::from_opcode` and `Instruction::OPCODES` from an
+/// `#[opcode(..)]` on every variant. A variant without one, or two variants with the same one, don't compile.
+#[proc_macro_derive(InstructionOpcode, attributes(opcode))]
+pub fn derive_opcode(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand_opcode(&input) {
+        Ok(expanded) => TokenStream::from(expanded),
+        Err(error) => TokenStream::from(error.to_compile_error()),
+    }
+}
+
+fn expand_opcode(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let name = &input.ident;
+    let Data::Enum(data_enum) = &input.data else {
+        return Err(syn::Error::new_spanned(input, "InstructionOpcode can only be derived for enums"));
+    };
+
+    let mut used = BTreeMap::new();
+    let mut table = Vec::new();
+    let mut opcode_arms = Vec::new();
+    let mut operands_arms = Vec::new();
+    let mut decode_arms = Vec::new();
+
+    for variant in &data_enum.variants {
+        let variant_name = &variant.ident;
+        let mnemonic = variant_name.to_string();
+
+        // The opcode, which must be given and not already taken
+        let attribute = variant
+            .attrs
+            .iter()
+            .find(|attribute| attribute.path().is_ident("opcode"))
+            .ok_or_else(|| syn::Error::new_spanned(variant, "every variant needs an #[opcode(..)]"))?;
+        let literal: LitInt = attribute.parse_args()?;
+        let opcode: u16 = literal.base10_parse()?;
+        if let Some(other) = used.insert(opcode, mnemonic.clone()) {
+            return Err(syn::Error::new_spanned(
+                literal,
+                format!("opcode {opcode:#06X} is already used by {other}"),
+            ));
+        }
+
+        let fields: Vec<&Type> = match &variant.fields {
+            Fields::Unit => Vec::new(),
+            Fields::Unnamed(fields) => fields.unnamed.iter().map(|field| &field.ty).collect(),
+            Fields::Named(_) => {
+                return Err(syn::Error::new_spanned(variant, "named fields are not supported"));
+            }
+        };
+        let kinds = fields.iter().map(|ty| operand_kind(ty)).collect::<syn::Result<Vec<_>>>()?;
+        let count = kinds.len();
+        let bindings: Vec<_> = (0..count).map(|index| quote::format_ident!("operand{}", index)).collect();
+
+        let kind_names = kinds.iter().map(|kind| match kind {
+            OperandKind::Register => quote! { crate::opcode::OperandKind::Register },
+            OperandKind::Value => quote! { crate::opcode::OperandKind::Value },
+        });
+        table.push(quote! {
+            crate::opcode::OpcodeInfo { opcode: #opcode, mnemonic: #mnemonic, operands: &[#(#kind_names),*] },
+        });
+
+        // Taking an instruction apart
+        let pattern = if count == 0 {
+            quote! { #name::#variant_name }
+        } else {
+            quote! { #name::#variant_name(#(#bindings),*) }
+        };
+        opcode_arms.push(quote! { #pattern => #opcode, });
+        let values = kinds.iter().zip(&bindings).map(|(kind, binding)| match kind {
+            OperandKind::Register => quote! { crate::shared::OperandValueType::Register(*#binding) },
+            OperandKind::Value => quote! { *#binding },
+        });
+        operands_arms.push(quote! { #pattern => ::alloc::vec![#(#values),*], });
+
+        // And putting it back together
+        let decoded = kinds.iter().enumerate().map(|(index, kind)| match kind {
+            OperandKind::Register => quote! {
+                match operands[#index] {
+                    crate::shared::OperandValueType::Register(register) => register,
+                    crate::shared::OperandValueType::Immediate(_) => {
+                        return Err(crate::opcode::OpcodeError::ExpectedRegister { opcode, index: #index });
+                    }
+                }
+            },
+            OperandKind::Value => quote! { operands[#index] },
+        });
+        let built = if count == 0 {
+            quote! { #name::#variant_name }
+        } else {
+            quote! { #name::#variant_name(#(#decoded),*) }
+        };
+        decode_arms.push(quote! {
+            #opcode => {
+                if operands.len() != #count {
+                    return Err(crate::opcode::OpcodeError::OperandCount {
+                        opcode,
+                        expected: #count,
+                        found: operands.len(),
+                    });
+                }
+                Ok(#built)
+            }
+        });
+    }
+
+    Ok(quote! {
+        impl #name {
+            /// Every instruction's opcode, mnemonic and the kinds of its operands, in the order they're declared
+            pub const OPCODES: &'static [crate::opcode::OpcodeInfo] = &[#(#table)*];
+
+            /// The instruction's opcode, see `crate::opcode`
+            pub const fn opcode(&self) -> u16 {
+                match self {
+                    #(#opcode_arms)*
+                }
+            }
+
+            /// The instruction's operands in order, a register operand as `OperandValueType::Register`
+            pub fn operands(&self) -> ::alloc::vec::Vec<crate::shared::OperandValueType> {
+                match self {
+                    #(#operands_arms)*
+                }
+            }
+
+            /// The instruction with `opcode` and `operands`, as `opcode` and `operands` give them
+            pub fn from_opcode(
+                opcode: u16,
+                operands: &[crate::shared::OperandValueType],
+            ) -> Result<Self, crate::opcode::OpcodeError> {
+                match opcode {
+                    #(#decode_arms)*
+                    _ => Err(crate::opcode::OpcodeError::UnknownOpcode(opcode)),
+                }
+            }
+        }
+    })
+}
@@ -0,0 +1,2 @@
+//! `tls::ffi` as a shared library, kept out of `tls` itself so it can still be built without std
+pub use tls::ffi::*;
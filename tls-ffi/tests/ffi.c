@@ -0,0 +1,109 @@
+/* Drives a TPU through include/tls.h the way a C host would, built and run by tests/ffi.rs */
+
+#include <stdio.h>
+#include <string.h>
+
+#include "tls.h"
+
+#define CHECK(condition)                                                      \
+  do {                                                                        \
+    if (!(condition)) {                                                       \
+      fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__,        \
+              #condition);                                                    \
+      return 1;                                                               \
+    }                                                                         \
+  } while (0)
+
+/* Echo a packet back to its sender, then set digital pin 7 */
+static const char *PROGRAM = "WRX\nXMIT X, Y\nDPWW 0x80\nHLT";
+
+/* LDR X, 7 / ADD X, X / HLT by opcode, leaving 14 in A */
+static const TlsInstruction OPCODES[] = {
+    {.opcode = 0x002F, .operand_count = 2, .operands = {{.is_register = true, .value = 1}, {.value = 7}}},
+    {.opcode = 0x0019,
+     .operand_count = 2,
+     .operands = {{.is_register = true, .value = 1}, {.is_register = true, .value = 1}}},
+    {.opcode = 0x0059, .operand_count = 0},
+};
+
+int main(void) {
+  TpuHandle *tpu = NULL;
+  char message[256];
+
+  /* Bad source is reported, not a crash */
+  CHECK(tls_tpu_create("NOT AN INSTRUCTION", 0x1, 0, 0, &tpu) == TLS_STATUS_PARSE_ERROR);
+  CHECK(tpu == NULL);
+  CHECK(tls_last_error(message, sizeof message) > 0);
+  CHECK(strlen(message) > 0);
+  CHECK(tls_tpu_create(NULL, 0x1, 0, 0, &tpu) == TLS_STATUS_NULL_POINTER);
+
+  /* A program given by opcode */
+  uint16_t value = 0;
+  CHECK(tls_tpu_create_from_opcodes(OPCODES, 3, 0x1, 0, 0, &tpu) == TLS_STATUS_OK);
+  CHECK(tls_tpu_run(tpu, 100) == TLS_STATUS_OK);
+  CHECK(tls_tpu_read_register(tpu, 0, &value) == TLS_STATUS_OK);
+  CHECK(value == 14);
+  tls_tpu_destroy(tpu);
+  tpu = NULL;
+  TlsInstruction unknown = {.opcode = 0};
+  CHECK(tls_tpu_create_from_opcodes(&unknown, 1, 0x1, 0, 0, &tpu) == TLS_STATUS_INVALID_INSTRUCTION);
+  CHECK(tls_last_error(message, sizeof message) > 0);
+  TlsInstruction missing_operand = {.opcode = 0x002F, .operand_count = 1, .operands = {{.is_register = true}}};
+  CHECK(tls_tpu_create_from_opcodes(&missing_operand, 1, 0x1, 0, 0, &tpu) == TLS_STATUS_INVALID_INSTRUCTION);
+  CHECK(tls_tpu_create_from_opcodes(OPCODES, 0, 0x1, 0, 0, &tpu) == TLS_STATUS_CONFIG_ERROR);
+  CHECK(tpu == NULL);
+
+  /* Analog 0 and digital 0 are inputs */
+  CHECK(tls_tpu_create(PROGRAM, 0x1, 0x01, 0x01, &tpu) == TLS_STATUS_OK);
+  CHECK(tpu != NULL);
+
+  /* Registers and RAM */
+  CHECK(tls_tpu_write_register(tpu, 3, 1234) == TLS_STATUS_OK);
+  CHECK(tls_tpu_read_register(tpu, 3, &value) == TLS_STATUS_OK);
+  CHECK(value == 1234);
+  CHECK(tls_tpu_read_register(tpu, 10, &value) == TLS_STATUS_OUT_OF_RANGE);
+  CHECK(tls_tpu_read_register(tpu, 0, NULL) == TLS_STATUS_NULL_POINTER);
+  CHECK(tls_tpu_write_ram(tpu, 0x10, 0xBEEF) == TLS_STATUS_OK);
+  CHECK(tls_tpu_read_ram(tpu, 0x10, &value) == TLS_STATUS_OK);
+  CHECK(value == 0xBEEF);
+  CHECK(tls_tpu_read_ram(tpu, 0xFFFF, &value) == TLS_STATUS_OUT_OF_RANGE);
+
+  /* Inputs */
+  CHECK(tls_tpu_drive_analog(tpu, 0, 300) == TLS_STATUS_OK);
+  CHECK(tls_tpu_read_analog(tpu, 0, &value) == TLS_STATUS_OK);
+  CHECK(value == 300);
+  CHECK(tls_tpu_drive_analog(tpu, 1, 300) == TLS_STATUS_NOT_AN_INPUT);
+  CHECK(tls_tpu_drive_digital(tpu, 0, true) == TLS_STATUS_OK);
+  CHECK(tls_tpu_drive_digital(tpu, 7, true) == TLS_STATUS_NOT_AN_INPUT);
+  CHECK(tls_tpu_drive_digital(tpu, 8, true) == TLS_STATUS_OUT_OF_RANGE);
+
+  /* Still waiting for a packet */
+  TlsHaltReason reason;
+  CHECK(tls_tpu_run(tpu, 20) == TLS_STATUS_OK);
+  CHECK(tls_tpu_halt_reason(tpu, &reason) == TLS_STATUS_EMPTY);
+
+  TlsPacket packet = {.sender = 0x2, .target = 0x1, .port = 0, .length = 1, .payload = {42}};
+  CHECK(tls_tpu_inject_packet(tpu, &packet) == TLS_STATUS_OK);
+  packet.length = TLS_MAX_PAYLOAD + 1;
+  CHECK(tls_tpu_inject_packet(tpu, &packet) == TLS_STATUS_OUT_OF_RANGE);
+  CHECK(tls_tpu_tick(tpu) == TLS_STATUS_OK);
+  CHECK(tls_tpu_run(tpu, 100) == TLS_STATUS_OK);
+
+  /* Halted, with the reply sent and pin 7 set */
+  CHECK(tls_tpu_halt_reason(tpu, &reason) == TLS_STATUS_OK);
+  CHECK(reason == TLS_HALT_REASON_HLT_OPCODE);
+  TlsPacket reply;
+  CHECK(tls_tpu_drain_packet(tpu, &reply) == TLS_STATUS_OK);
+  CHECK(reply.sender == 0x1 && reply.target == 0x2);
+  CHECK(reply.length == 1 && reply.payload[0] == 42);
+  CHECK(tls_tpu_drain_packet(tpu, &reply) == TLS_STATUS_EMPTY);
+  CHECK(tls_tpu_read_digital(tpu, &value) == TLS_STATUS_OK);
+  CHECK(value == 0x81);
+
+  tls_tpu_destroy(tpu);
+  tls_tpu_destroy(NULL);
+  CHECK(tls_tpu_tick(NULL) == TLS_STATUS_NULL_POINTER);
+
+  printf("ok\n");
+  return 0;
+}
@@ -0,0 +1,49 @@
+//! Builds `tests/ffi.c` against `include/tls.h` and `libtls_ffi`, and runs it
+#![cfg(unix)]
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Where cargo put `libtls_ffi` for this test, the `deps` directory it runs from
+fn library_dir() -> PathBuf {
+    let exe = env::current_exe().expect("test executable");
+    exe.parent().expect("deps directory").to_path_buf()
+}
+
+#[test]
+fn test_c_host() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let libraries = library_dir();
+    let program = Path::new(env!("CARGO_TARGET_TMPDIR")).join("ffi_test");
+
+    let compiler = env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let status = Command::new(&compiler)
+        .arg("-std=c99")
+        .arg("-Wall")
+        .arg("-Werror")
+        .arg("-I")
+        .arg(root.join("../include"))
+        .arg(root.join("tests/ffi.c"))
+        .arg("-o")
+        .arg(&program)
+        .arg("-L")
+        .arg(&libraries)
+        .arg(format!("-Wl,-rpath,{}", libraries.display()))
+        .arg("-ltls_ffi")
+        .status()
+        .unwrap_or_else(|error| panic!("couldn't run {compiler}: {error}"));
+    assert!(status.success(), "tests/ffi.c didn't compile");
+
+    // Cargo's LD_LIBRARY_PATH comes before the rpath and can hold an older build of the library
+    let output = Command::new(&program)
+        .env("LD_LIBRARY_PATH", &libraries)
+        .output()
+        .expect("run the C test");
+    assert!(
+        output.status.success(),
+        "the C test failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "ok\n");
+}
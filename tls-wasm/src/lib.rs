@@ -0,0 +1,2 @@
+//! `tls::wasm` for wasm-pack, kept out of `tls` itself so it can still be built without std
+pub use tls::wasm::WasmTpu;